@@ -0,0 +1,158 @@
+use clap::{value_t_or_exit, App, Arg};
+use noria::channel::{self, ChannelCoordinator, Sender, Tls, TlsConfig};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+
+/// Accept `connections` connections in turn, and drain `bytes` bytes from each.
+async fn receive(
+    mut listener: tokio::net::TcpListener,
+    tls: Option<Tls>,
+    connections: usize,
+    bytes: usize,
+) -> Duration {
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut elapsed = Duration::default();
+    for _ in 0..connections {
+        let (stream, _) = listener.accept().await.unwrap();
+        let start = Instant::now();
+        stream.set_nodelay(true).unwrap();
+        let mut stream = channel::tls::maybe_accept(tls.as_ref(), stream)
+            .await
+            .unwrap();
+        let mut left = bytes;
+        while left != 0 {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_ne!(n, 0, "sender went away early");
+            left -= n;
+        }
+        elapsed += start.elapsed();
+    }
+    elapsed
+}
+
+/// Send `messages` messages of `size` bytes over each of `connections` domain connections, and
+/// return how long the receiver took to get them all.
+async fn run(
+    server: Option<Tls>,
+    client: Option<Tls>,
+    connections: usize,
+    messages: usize,
+    size: usize,
+) -> Duration {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+
+    // the connection type byte, and then a length prefix, the bincode length of the payload, and
+    // the payload for each message
+    let bytes = 1 + messages * (4 + 8 + size);
+    let expects_tls = server.is_some();
+    let receiver = tokio::spawn(receive(listener, server, connections, bytes));
+
+    // connect the same way one domain connects to another, so that reconnections reuse sessions
+    let coord = ChannelCoordinator::<usize, Vec<u8>>::with_tls(client);
+    coord.insert_remote(0, addr, expects_tls);
+    tokio::task::spawn_blocking(move || {
+        let payload = vec![0u8; size];
+        for _ in 0..connections {
+            let mut tx = coord.builder_for(&0).unwrap().build_sync().unwrap();
+            for _ in 0..messages {
+                tx.send(payload.clone()).unwrap();
+            }
+        }
+    })
+    .await
+    .unwrap();
+
+    receiver.await.unwrap()
+}
+
+fn report(name: &str, took: Duration, connections: usize, messages: usize, size: usize) {
+    let total = connections * messages;
+    let secs = took.as_secs_f64();
+    println!(
+        "{:<9} {:>12.0} msgs/s {:>10.2} MB/s",
+        name,
+        total as f64 / secs,
+        (total * size) as f64 / secs / 1_000_000.0,
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let args = App::new("channel-throughput")
+        .about("Compares the throughput of plaintext and TLS domain-to-domain connections")
+        .arg(
+            Arg::with_name("cert")
+                .long("cert")
+                .takes_value(true)
+                .required(true)
+                .help("PEM-encoded certificate for the receiving end."),
+        )
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .takes_value(true)
+                .required(true)
+                .help("PEM-encoded private key for the certificate."),
+        )
+        .arg(
+            Arg::with_name("ca")
+                .long("ca")
+                .takes_value(true)
+                .required(true)
+                .help("PEM-encoded CA bundle the sending end verifies the certificate against."),
+        )
+        .arg(
+            Arg::with_name("server-name")
+                .long("server-name")
+                .takes_value(true)
+                .default_value("noria")
+                .help("Name the certificate is issued for."),
+        )
+        .arg(
+            Arg::with_name("connections")
+                .long("connections")
+                .takes_value(true)
+                .default_value("8")
+                .help("Number of consecutive connections to send over."),
+        )
+        .arg(
+            Arg::with_name("messages")
+                .long("messages")
+                .short("n")
+                .takes_value(true)
+                .default_value("100000")
+                .help("Number of messages to send per connection."),
+        )
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .takes_value(true)
+                .default_value("256")
+                .help("Size of each message, in bytes."),
+        )
+        .get_matches();
+
+    let connections = value_t_or_exit!(args, "connections", usize);
+    let messages = value_t_or_exit!(args, "messages", usize);
+    let size = value_t_or_exit!(args, "size", usize);
+    let name = args.value_of("server-name").unwrap();
+    let server = TlsConfig::new(
+        args.value_of("cert").unwrap(),
+        args.value_of("key").unwrap(),
+        args.value_of("ca").unwrap(),
+    )
+    .with_server_name(name)
+    .load()
+    .unwrap();
+    let client = TlsConfig::client(args.value_of("ca").unwrap())
+        .with_server_name(name)
+        .load()
+        .unwrap();
+
+    let took = run(None, None, connections, messages, size).await;
+    report("plaintext", took, connections, messages, size);
+    let took = run(Some(server), Some(client), connections, messages, size).await;
+    report("tls", took, connections, messages, size);
+}
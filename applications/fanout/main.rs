@@ -0,0 +1,125 @@
+use clap::{value_t_or_exit, App, Arg};
+use noria::{Builder, DurabilityMode, PersistenceParameters};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Counts the allocations the whole process makes.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// every update to Post goes through the filter, and from there to each of the four queries
+const RECIPE: &str =
+    "CREATE TABLE Post (id int, author int, topic int, body text, PRIMARY KEY(id));
+
+CREATE VIEW Visible AS SELECT id, author, topic, body FROM Post WHERE topic > 0;
+
+QUERY ByAuthor: SELECT id, body FROM Visible WHERE author = ?;
+QUERY ByTopic: SELECT id, body FROM Visible WHERE topic = ?;
+QUERY ById: SELECT author, topic, body FROM Visible WHERE id = ?;
+QUERY Bodies: SELECT id, author, body FROM Visible WHERE body = ?;";
+
+#[tokio::main]
+async fn main() {
+    let args = App::new("fanout")
+        .about("Measures the allocations made when updates fan out to several children")
+        .arg(
+            Arg::with_name("rows")
+                .long("rows")
+                .takes_value(true)
+                .default_value("100000")
+                .help("Number of rows to write."),
+        )
+        .arg(
+            Arg::with_name("batch")
+                .long("batch")
+                .takes_value(true)
+                .default_value("100")
+                .help("Number of rows written at a time."),
+        )
+        .arg(Arg::with_name("verbose").long("verbose").short("v"))
+        .get_matches();
+
+    let rows = value_t_or_exit!(args, "rows", usize);
+    let batch = value_t_or_exit!(args, "batch", usize);
+
+    let mut builder = Builder::default();
+    if args.is_present("verbose") {
+        builder.log_with(noria::logger_pls());
+    }
+    builder.disable_partial();
+    builder.set_sharding(None);
+    builder.set_persistence(PersistenceParameters::new(
+        DurabilityMode::MemoryOnly,
+        Duration::from_millis(1),
+        Some(String::from("fanout")),
+        1,
+    ));
+
+    let (mut g, _done) = builder.start_local().await.unwrap();
+    g.install_recipe(RECIPE).await.unwrap();
+    let mut post = g.table("Post").await.unwrap();
+    let mut by_id = g.view("ById").await.unwrap();
+
+    let before = (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        ALLOCATED.load(Ordering::Relaxed),
+    );
+    let start = Instant::now();
+    let mut id = 0;
+    while id < rows {
+        let n = batch.min(rows - id);
+        post.perform_all((id..id + n).map(|i| {
+            let i = i as i32;
+            vec![
+                i.into(),
+                (i % 100).into(),
+                (1 + i % 10).into(),
+                format!("post number {}", i).into(),
+            ]
+        }))
+        .await
+        .unwrap();
+        id += n;
+    }
+
+    // wait for the last row to make it through
+    let last = (rows - 1) as i32;
+    while by_id.lookup(&[last.into()], true).await.unwrap().is_empty() {
+        tokio::time::delay_for(Duration::from_millis(1)).await;
+    }
+    let took = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before.0;
+    let allocated = ALLOCATED.load(Ordering::Relaxed) - before.1;
+
+    println!("# rows written: {}", rows);
+    println!(
+        "# throughput: {:.0} rows/s",
+        rows as f64 / took.as_secs_f64()
+    );
+    println!(
+        "# allocations per row: {:.1}",
+        allocations as f64 / rows as f64
+    );
+    println!(
+        "# bytes allocated per row: {:.1}",
+        allocated as f64 / rows as f64
+    );
+}
@@ -0,0 +1,569 @@
+//! Token authentication for table and view handles.
+//!
+//! An instance that is given a set of [`Principals`] only hands out [`Table`](crate::Table) and
+//! [`View`](crate::View) handles to clients that present the token of a principal, and only for the
+//! tables and views on that principal's allowlists. A principal can also be bound to a
+//! [namespace](crate::namespace), in which case the allowlists name tables and views in that
+//! namespace, and it gets no handles for any other namespace. The controller checks the allowlists when a
+//! handle is established, and again whenever a view asks where its shards have moved to.
+//!
+//! Every connection a handle opens to a worker starts with the handle's token and the [`Scope`] of
+//! the handle, that is, the table or view it is for. The worker checks the principal's allowlists
+//! for that table or view itself, and drops the connection if they do not include it. Over an
+//! accepted connection, the worker only serves writes to the table, or reads from the view, that
+//! the connection was opened for. A view that the recipe made an alias of an identical query is
+//! served under the name of that query, so principals must be allowed to read that name too.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use byteorder::{BigEndian, ReadBytesExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::namespace;
+
+/// Grants every table, or every view, when it appears in an allowlist.
+pub const ANY: &str = "*";
+
+const ACCEPTED: u8 = 0;
+const REJECTED: u8 = 1;
+
+/// A client identity, and what it is allowed to do.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Principal {
+    /// The name of the principal, used in errors and logs.
+    pub name: String,
+    /// The secret the principal's clients present.
+    pub token: String,
+    /// The tables the principal may write to.
+    #[serde(default)]
+    pub tables: Vec<String>,
+    /// The views the principal may read from.
+    #[serde(default)]
+    pub views: Vec<String>,
+    /// The namespace the principal is bound to. Principals that are not bound to one may use
+    /// every namespace.
+    #[serde(default)]
+    pub namespace: Option<String>,
+}
+
+impl Principal {
+    /// Whether the principal may use tables and views in `namespace`.
+    pub fn may_use(&self, namespace: Option<&str>) -> bool {
+        self.namespace.is_none() || self.namespace.as_deref() == namespace
+    }
+
+    /// Whether the principal may write to the table `name`.
+    pub fn may_write(&self, name: &str) -> bool {
+        self.tables.iter().any(|t| t == ANY || t == name)
+    }
+
+    /// Whether the principal may read from the view `name`.
+    pub fn may_read(&self, name: &str) -> bool {
+        self.views.iter().any(|v| v == ANY || v == name)
+    }
+}
+
+/// What a connection to a worker is for.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Scope {
+    /// Writes to a table.
+    Table {
+        /// The namespace the table is in.
+        namespace: Option<String>,
+        /// The name of the table within its namespace.
+        name: String,
+    },
+    /// Reads from a view.
+    View {
+        /// The namespace the view is in.
+        namespace: Option<String>,
+        /// The name of the view within its namespace.
+        name: String,
+    },
+    /// Reads from the change feed of a table.
+    Changes {
+        /// The namespace the table is in.
+        namespace: Option<String>,
+        /// The name of the table within its namespace.
+        name: String,
+    },
+}
+
+impl Scope {
+    /// The name of the dataflow node that connections for this scope may use.
+    pub fn node_name(&self) -> String {
+        match *self {
+            Scope::Table {
+                ref namespace,
+                ref name,
+            }
+            | Scope::View {
+                ref namespace,
+                ref name,
+            } => namespace::qualify(namespace.as_deref(), name),
+            // the controller names the reader behind a table's change feed this way
+            Scope::Changes {
+                ref namespace,
+                ref name,
+            } => format!("{}$changes", namespace::qualify(namespace.as_deref(), name)),
+        }
+    }
+}
+
+/// The principals an instance accepts.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Principals(Vec<Principal>);
+
+impl Principals {
+    /// Accept the given principals.
+    pub fn new(principals: Vec<Principal>) -> Self {
+        Principals(principals)
+    }
+
+    /// Read the principals from a JSON file that holds a list of [`Principal`]s.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let f = File::open(path)?;
+        serde_json::from_reader(f).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Find the principal whose token was presented.
+    pub fn authenticate(&self, token: Option<&str>) -> Result<&Principal, AuthError> {
+        let token = token.ok_or(AuthError::MissingToken)?;
+        // look at every principal, so that how long this takes says nothing about the token
+        self.0
+            .iter()
+            .fold(None, |found, p| {
+                if constant_time_eq(p.token.as_bytes(), token.as_bytes()) {
+                    Some(p)
+                } else {
+                    found
+                }
+            })
+            .ok_or(AuthError::InvalidToken)
+    }
+
+    /// Check that the principal whose token was presented may use `namespace`.
+    pub fn authorize_namespace(
+        &self,
+        token: Option<&str>,
+        namespace: Option<&str>,
+    ) -> Result<&Principal, AuthError> {
+        let p = self.authenticate(token)?;
+        if p.may_use(namespace) {
+            Ok(p)
+        } else {
+            Err(AuthError::NamespaceDenied {
+                principal: p.name.clone(),
+                namespace: namespace.map(String::from),
+            })
+        }
+    }
+
+    /// Check that the principal whose token was presented may write to the table `name` in
+    /// `namespace`.
+    pub fn authorize_table(
+        &self,
+        token: Option<&str>,
+        namespace: Option<&str>,
+        name: &str,
+    ) -> Result<(), AuthError> {
+        let p = self.authorize_namespace(token, namespace)?;
+        if p.may_write(name) {
+            Ok(())
+        } else {
+            Err(AuthError::TableDenied {
+                principal: p.name.clone(),
+                table: name.to_owned(),
+            })
+        }
+    }
+
+    /// Check that the principal whose token was presented may read from the view `name` in
+    /// `namespace`.
+    pub fn authorize_view(
+        &self,
+        token: Option<&str>,
+        namespace: Option<&str>,
+        name: &str,
+    ) -> Result<(), AuthError> {
+        let p = self.authorize_namespace(token, namespace)?;
+        if p.may_read(name) {
+            Ok(())
+        } else {
+            Err(AuthError::ViewDenied {
+                principal: p.name.clone(),
+                view: name.to_owned(),
+            })
+        }
+    }
+
+    /// Check that the principal whose token was presented may use what `scope` names. The change
+    /// feed of a table may be read by those who may read from a view of the same name.
+    pub fn authorize(&self, token: Option<&str>, scope: &Scope) -> Result<(), AuthError> {
+        match *scope {
+            Scope::Table {
+                ref namespace,
+                ref name,
+            } => self.authorize_table(token, namespace.as_deref(), name),
+            Scope::View {
+                ref namespace,
+                ref name,
+            }
+            | Scope::Changes {
+                ref namespace,
+                ref name,
+            } => self.authorize_view(token, namespace.as_deref(), name),
+        }
+    }
+
+    /// Check the token and scope that a connection presented, and return the scope if the
+    /// connection may be accepted.
+    fn admit(&self, token: &[u8], scope: Option<Scope>) -> Result<Scope, AuthError> {
+        let token = if token.is_empty() {
+            None
+        } else {
+            Some(std::str::from_utf8(token).map_err(|_| AuthError::InvalidToken)?)
+        };
+        match scope {
+            Some(scope) => {
+                self.authorize(token, &scope)?;
+                Ok(scope)
+            }
+            None => Err(AuthError::Unscoped {
+                principal: self.authenticate(token)?.name.clone(),
+            }),
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// A client was not allowed to establish a handle.
+#[derive(Clone, Debug, PartialEq, Eq, Fail, Serialize, Deserialize)]
+pub enum AuthError {
+    /// The instance requires a token, but the client did not give one.
+    #[fail(display = "no token was given, but one is required")]
+    MissingToken,
+    /// The token does not belong to any principal.
+    #[fail(display = "the given token does not belong to any principal")]
+    InvalidToken,
+    /// The principal may not write to the table.
+    #[fail(display = "{} may not write to table {}", principal, table)]
+    TableDenied {
+        /// The principal whose token was given.
+        principal: String,
+        /// The table that was asked for.
+        table: String,
+    },
+    /// The principal may not read from the view.
+    #[fail(display = "{} may not read from view {}", principal, view)]
+    ViewDenied {
+        /// The principal whose token was given.
+        principal: String,
+        /// The view that was asked for.
+        view: String,
+    },
+    /// The principal is bound to a different namespace.
+    #[fail(
+        display = "{} may not use tables and views outside its namespace",
+        principal
+    )]
+    NamespaceDenied {
+        /// The principal whose token was given.
+        principal: String,
+        /// The namespace that was asked for.
+        namespace: Option<String>,
+    },
+    /// A connection to a worker did not say which table or view it is for.
+    #[fail(
+        display = "{} did not say which table or view the connection is for",
+        principal
+    )]
+    Unscoped {
+        /// The principal whose token was given.
+        principal: String,
+    },
+}
+
+impl AuthError {
+    fn into_io(self) -> io::Error {
+        io::Error::new(io::ErrorKind::PermissionDenied, self.to_string())
+    }
+}
+
+fn invalid_input<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
+
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Append `part` to `frame`, prefixed by its length.
+fn push_part(frame: &mut Vec<u8>, part: &[u8], what: &str) -> io::Result<()> {
+    let len =
+        u16::try_from(part.len()).map_err(|_| invalid_input(format!("{} is too long", what)))?;
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(part);
+    Ok(())
+}
+
+fn encode(token: Option<&str>, scope: Option<&Scope>) -> io::Result<Vec<u8>> {
+    let token = token.unwrap_or("").as_bytes();
+    let scope = bincode::serialize(&scope).map_err(invalid_input)?;
+    let mut frame = Vec::with_capacity(4 + token.len() + scope.len());
+    push_part(&mut frame, token, "token")?;
+    push_part(&mut frame, &scope, "scope")?;
+    Ok(frame)
+}
+
+fn rejected(reason: &[u8]) -> io::Error {
+    match bincode::deserialize::<AuthError>(reason) {
+        Ok(e) => e.into_io(),
+        Err(e) => invalid_data(e),
+    }
+}
+
+fn unexpected(reply: u8) -> io::Error {
+    invalid_data(format!("unexpected reply {} to token", reply))
+}
+
+/// Present `token` on a new connection to a worker that is for `scope`, and wait for the worker
+/// to accept it.
+pub async fn present<S>(stream: &mut S, token: Option<&str>, scope: &Scope) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(&encode(token, Some(scope))?).await?;
+    stream.flush().await?;
+    match stream.read_u8().await? {
+        ACCEPTED => Ok(()),
+        REJECTED => {
+            let mut reason = vec![0; usize::from(stream.read_u16().await?)];
+            stream.read_exact(&mut reason).await?;
+            Err(rejected(&reason))
+        }
+        r => Err(unexpected(r)),
+    }
+}
+
+/// Like [`present`], but for a blocking connection that is not for any table or view in
+/// particular. Workers that require a token do not accept such connections.
+pub fn present_blocking<S: Read + Write>(stream: &mut S, token: Option<&str>) -> io::Result<()> {
+    stream.write_all(&encode(token, None)?)?;
+    stream.flush()?;
+    match ReadBytesExt::read_u8(stream)? {
+        ACCEPTED => Ok(()),
+        REJECTED => {
+            let mut reason = vec![0; usize::from(ReadBytesExt::read_u16::<BigEndian>(stream)?)];
+            stream.read_exact(&mut reason)?;
+            Err(rejected(&reason))
+        }
+        r => Err(unexpected(r)),
+    }
+}
+
+/// Read the token and scope presented on a new connection, and tell the client whether it was
+/// accepted.
+///
+/// Any connection is accepted if there are no `principals`, and `None` is returned, since the
+/// connection may then be used for anything. Otherwise the connection is only accepted if its
+/// principal may use what its scope names, and that scope is returned. A rejected connection is
+/// returned as an error of kind `PermissionDenied`.
+pub async fn check<S>(stream: &mut S, principals: Option<&Principals>) -> io::Result<Option<Scope>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut token = vec![0; usize::from(stream.read_u16().await?)];
+    stream.read_exact(&mut token).await?;
+    let mut scope = vec![0; usize::from(stream.read_u16().await?)];
+    stream.read_exact(&mut scope).await?;
+    let scope: Option<Scope> = bincode::deserialize(&scope).map_err(invalid_data)?;
+
+    let result = match principals {
+        None => Ok(None),
+        Some(principals) => principals.admit(&token, scope).map(Some),
+    };
+
+    match result {
+        Ok(scope) => {
+            stream.write_u8(ACCEPTED).await?;
+            stream.flush().await?;
+            Ok(scope)
+        }
+        Err(e) => {
+            let mut reply = vec![REJECTED];
+            push_part(
+                &mut reply,
+                &bincode::serialize(&e).map_err(invalid_input)?,
+                "rejection",
+            )?;
+            stream.write_all(&reply).await?;
+            stream.flush().await?;
+            Err(e.into_io())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principals() -> Principals {
+        Principals::new(vec![
+            Principal {
+                name: "app".to_owned(),
+                token: "secret".to_owned(),
+                tables: vec!["Article".to_owned()],
+                views: vec![ANY.to_owned()],
+                namespace: None,
+            },
+            Principal {
+                name: "blog".to_owned(),
+                token: "hunter2".to_owned(),
+                tables: vec![ANY.to_owned()],
+                views: vec![ANY.to_owned()],
+                namespace: Some("blog".to_owned()),
+            },
+        ])
+    }
+
+    fn table(name: &str) -> Scope {
+        Scope::Table {
+            namespace: None,
+            name: name.to_owned(),
+        }
+    }
+
+    async fn handshake(
+        principals: Option<Principals>,
+        token: Option<&str>,
+        scope: Scope,
+    ) -> io::Result<Option<Scope>> {
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut s, _) = listener.accept().await.unwrap();
+            check(&mut s, principals.as_ref()).await
+        });
+        let mut c = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let presented = present(&mut c, token, &scope).await;
+        let checked = server.await.unwrap();
+        assert_eq!(presented.is_ok(), checked.is_ok());
+        presented?;
+        checked
+    }
+
+    #[test]
+    fn allowlists() {
+        let p = principals();
+        assert_eq!(p.authorize_table(Some("secret"), None, "Article"), Ok(()));
+        assert_eq!(p.authorize_view(Some("secret"), None, "Anything"), Ok(()));
+        assert_eq!(
+            p.authorize_table(Some("secret"), None, "Vote"),
+            Err(AuthError::TableDenied {
+                principal: "app".to_owned(),
+                table: "Vote".to_owned(),
+            })
+        );
+        assert_eq!(
+            p.authorize_table(Some("secreT"), None, "Article"),
+            Err(AuthError::InvalidToken)
+        );
+        assert_eq!(
+            p.authorize_view(None, None, "Anything"),
+            Err(AuthError::MissingToken)
+        );
+    }
+
+    #[test]
+    fn namespaces() {
+        let p = principals();
+        assert_eq!(
+            p.authorize_view(Some("secret"), Some("blog"), "Posts"),
+            Ok(())
+        );
+        assert_eq!(
+            p.authorize_view(Some("hunter2"), Some("blog"), "Posts"),
+            Ok(())
+        );
+        assert_eq!(
+            p.authorize_view(Some("hunter2"), Some("shop"), "Posts"),
+            Err(AuthError::NamespaceDenied {
+                principal: "blog".to_owned(),
+                namespace: Some("shop".to_owned()),
+            })
+        );
+        assert_eq!(
+            p.authorize_table(Some("hunter2"), None, "Article"),
+            Err(AuthError::NamespaceDenied {
+                principal: "blog".to_owned(),
+                namespace: None,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn channel_handshake() {
+        assert_eq!(handshake(None, None, table("Vote")).await.unwrap(), None);
+        assert_eq!(
+            handshake(Some(principals()), Some("secret"), table("Article"))
+                .await
+                .unwrap(),
+            Some(table("Article"))
+        );
+
+        let e = handshake(Some(principals()), Some("guess"), table("Article"))
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        let e = handshake(Some(principals()), None, table("Article"))
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+
+        // the worker checks the allowlists itself
+        let e = handshake(Some(principals()), Some("secret"), table("Vote"))
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(
+            e.to_string(),
+            AuthError::TableDenied {
+                principal: "app".to_owned(),
+                table: "Vote".to_owned(),
+            }
+            .to_string()
+        );
+        let feed = Scope::Changes {
+            namespace: Some("shop".to_owned()),
+            name: "Article".to_owned(),
+        };
+        let e = handshake(Some(principals()), Some("hunter2"), feed)
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn node_names() {
+        assert_eq!(table("Article").node_name(), "Article");
+        let feed = Scope::Changes {
+            namespace: Some("blog".to_owned()),
+            name: "Post".to_owned(),
+        };
+        assert_eq!(feed.node_name(), "blog:Post$changes");
+    }
+}
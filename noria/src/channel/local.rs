@@ -0,0 +1,76 @@
+//! In-memory connections between the parts of a deployment that run in the same process.
+//!
+//! An endpoint that can be reached in memory registers the address it listens on over TCP along
+//! with the sending half of a queue. Whoever connects to that address from within the process
+//! then sends on the queue rather than over a socket, so that what is sent is never serialized.
+//! Queues deliver in the order things were sent, as TCP connections do, and endpoints that should
+//! push back on their senders register bounded queues.
+//!
+//! Endpoints in other processes are never registered, so connections to them use TCP as before.
+
+use lazy_static::lazy_static;
+use std::any::Any;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref ENDPOINTS: Mutex<HashMap<SocketAddr, (usize, Box<dyn Any + Send>)>> =
+        Default::default();
+}
+
+static NEXT_REGISTRATION: AtomicUsize = AtomicUsize::new(0);
+
+/// An endpoint that can be reached in memory until this is dropped.
+#[derive(Debug)]
+pub struct Registration {
+    addr: SocketAddr,
+    id: usize,
+}
+
+impl Registration {
+    /// The address that the endpoint is reached at.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut endpoints = ENDPOINTS.lock().unwrap();
+        // the address may already have been given to an endpoint that replaced this one
+        if endpoints
+            .get(&self.addr)
+            .map_or(false, |&(id, _)| id == self.id)
+        {
+            endpoints.remove(&self.addr);
+        }
+    }
+}
+
+/// Let connections from within the process to the endpoint at `addr` use `tx`.
+pub fn register<T: Clone + Send + 'static>(addr: SocketAddr, tx: T) -> Registration {
+    let id = NEXT_REGISTRATION.fetch_add(1, Ordering::Relaxed);
+    ENDPOINTS
+        .lock()
+        .unwrap()
+        .insert(addr, (id, Box::new(tx) as Box<_>));
+    Registration { addr, id }
+}
+
+/// The sender for an in-memory connection to the endpoint at `addr`, if it is in this process
+/// and takes `T`s.
+pub fn connect<T: Clone + Send + 'static>(addr: &SocketAddr) -> Option<T> {
+    ENDPOINTS
+        .lock()
+        .unwrap()
+        .get(addr)
+        .and_then(|(_, tx)| tx.downcast_ref::<T>())
+        .cloned()
+}
+
+/// Whether the endpoint at `addr` is in this process.
+pub fn is_local(addr: &SocketAddr) -> bool {
+    ENDPOINTS.lock().unwrap().contains_key(addr)
+}
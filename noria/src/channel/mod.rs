@@ -13,25 +13,83 @@ use std::{
     task::{Context, Poll},
 };
 
+use crate::consensus::Epoch;
 use async_bincode::{AsyncBincodeWriter, AsyncDestination};
 use futures_util::sink::{Sink, SinkExt};
 use tokio::io::BufWriter;
 
+pub mod local;
 pub mod tcp;
+pub mod tls;
 
 pub use self::tcp::{DualTcpStream, TcpSender};
+pub use self::tls::{MaybeTls, Tls, TlsConfig};
 
 pub const CONNECTION_FROM_BASE: u8 = 1;
 pub const CONNECTION_FROM_DOMAIN: u8 = 2;
+/// A connection on a domain's priority lane.
+///
+/// Replay requests, evictions, and control messages go over a lane of their own, which a domain
+/// keeps reading even while it holds back regular updates because its outputs are backed up. This
+/// way, backpressure on writes cannot stall the replays that would let it drain.
+pub const CONNECTION_PRIORITY: u8 = 3;
+
+/// How many bytes follow the first byte of a connection between domains to fence it.
+///
+/// The first is 1 if the sender knows the epoch of the controller whose dataflow it belongs to,
+/// and the rest are that epoch. Domains refuse connections from another controller's dataflow, so
+/// that a sender that was cut off from a failed-over controller cannot interleave its packets
+/// with those of the dataflow that replaced it.
+pub const FENCE_LEN: usize = 9;
+
+/// Parse the bytes that fence a connection between domains into the sender's epoch, if it has one.
+pub fn read_fence(fence: [u8; FENCE_LEN]) -> Option<Epoch> {
+    if fence[0] == 0 {
+        return None;
+    }
+    let mut epoch = [0; 8];
+    epoch.copy_from_slice(&fence[1..]);
+    Some(Epoch::from_be_bytes(epoch))
+}
+
+fn write_fence(epoch: Option<Epoch>) -> [u8; FENCE_LEN] {
+    let mut fence = [0; FENCE_LEN];
+    if let Some(epoch) = epoch {
+        fence[0] = 1;
+        fence[1..].copy_from_slice(&epoch.to_be_bytes());
+    }
+    fence
+}
 
 pub struct Remote;
 pub struct MaybeLocal;
 
+/// The channels over which a domain receives packets from other domains on the same worker.
+pub struct LocalChannels<T> {
+    /// Regular updates, bounded so that a domain that falls behind pushes back on its inputs.
+    pub updates: tokio::sync::mpsc::Sender<T>,
+    /// The priority lane.
+    pub priority: tokio::sync::mpsc::UnboundedSender<T>,
+}
+
+impl<T> Clone for LocalChannels<T> {
+    fn clone(&self) -> Self {
+        LocalChannels {
+            updates: self.updates.clone(),
+            priority: self.priority.clone(),
+        }
+    }
+}
+
 pub struct DomainConnectionBuilder<D, T> {
     sport: Option<u16>,
     addr: SocketAddr,
-    chan: Option<tokio::sync::mpsc::UnboundedSender<T>>,
+    chan: Option<LocalChannels<T>>,
     is_for_base: bool,
+    priority: bool,
+    tls: Option<Tls>,
+    expects_tls: bool,
+    epoch: Option<Epoch>,
     _marker: D,
 }
 
@@ -57,6 +115,30 @@ impl<T> Sink<T> for ImplSinkForSender<T> {
     }
 }
 
+struct ImplSinkForBoundedSender<T>(tokio::sync::mpsc::Sender<T>);
+
+impl<T> Sink<T> for ImplSinkForBoundedSender<T> {
+    // the only error is that the receiving domain went away
+    type Error = ();
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().0.poll_ready(cx).map_err(|_| ())
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        // poll_ready reserved a slot for us, so this can only fail if the receiver is gone
+        self.get_mut().0.try_send(item).map_err(|_| ())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
 impl<T> DomainConnectionBuilder<Remote, T> {
     pub fn for_base(addr: SocketAddr) -> Self {
         DomainConnectionBuilder {
@@ -64,12 +146,24 @@ impl<T> DomainConnectionBuilder<Remote, T> {
             chan: None,
             addr,
             is_for_base: true,
+            priority: false,
+            tls: None,
+            expects_tls: false,
+            epoch: None,
             _marker: Remote,
         }
     }
 }
 
 impl<D, T> DomainConnectionBuilder<D, T> {
+    /// Connect to the domain's priority lane rather than the lane for regular updates.
+    ///
+    /// Synchronous connections to a local domain always use the priority lane.
+    pub fn prioritized(mut self) -> Self {
+        self.priority = true;
+        self
+    }
+
     pub fn maybe_on_port(mut self, sport: Option<u16>) -> Self {
         self.sport = sport;
         self
@@ -79,6 +173,13 @@ impl<D, T> DomainConnectionBuilder<D, T> {
         self.sport = Some(sport);
         self
     }
+
+    /// Connect over TLS, since the domain expects it.
+    pub fn over_tls(mut self, tls: Option<Tls>) -> Self {
+        self.tls = tls;
+        self.expects_tls = true;
+        self
+    }
 }
 
 impl<T> DomainConnectionBuilder<Remote, T>
@@ -87,28 +188,39 @@ where
 {
     pub fn build_async(
         self,
-    ) -> io::Result<AsyncBincodeWriter<BufWriter<tokio::net::TcpStream>, T, AsyncDestination>> {
+    ) -> io::Result<
+        AsyncBincodeWriter<BufWriter<MaybeTls<tokio::net::TcpStream>>, T, AsyncDestination>,
+    > {
         // TODO: async
         // we must currently write and call flush, because the remote end (currently) does a
         // synchronous read upon accepting a connection.
         let s = self.build_sync()?.into_inner().into_inner()?;
 
-        tokio::net::TcpStream::from_std(s)
+        s.into_async()
             .map(BufWriter::new)
             .map(AsyncBincodeWriter::from)
             .map(AsyncBincodeWriter::for_async)
     }
 
     pub fn build_sync(self) -> io::Result<TcpSender<T>> {
-        let mut s = TcpSender::connect_from(self.sport, &self.addr)?;
+        let tls = tls::for_endpoint(self.tls.as_ref(), self.addr, self.expects_tls)?;
+        let mut s = TcpSender::connect_from(self.sport, &self.addr, tls)?;
         {
             let s = s.get_mut();
             s.write_all(&[if self.is_for_base {
                 CONNECTION_FROM_BASE
+            } else if self.priority {
+                CONNECTION_PRIORITY
             } else {
                 CONNECTION_FROM_DOMAIN
             }])?;
-            s.flush()?;
+            if self.is_for_base {
+                // bases connected to this way have no principal to present the token of
+                crate::auth::present_blocking(s, None)?;
+            } else {
+                s.write_all(&write_fence(self.epoch))?;
+                s.flush()?;
+            }
         }
 
         Ok(s)
@@ -134,6 +246,57 @@ impl<T> Sender for tokio::sync::mpsc::UnboundedSender<T> {
     }
 }
 
+/// A connection to an endpoint that is either in this process, and then sent to in memory, or
+/// elsewhere, and then sent to over TCP.
+pub enum MaybeLocalSender<T> {
+    Local {
+        tx: tokio::sync::mpsc::UnboundedSender<T>,
+        peer: SocketAddr,
+    },
+    Remote(TcpSender<T>),
+}
+
+impl<T: serde::Serialize + Send + 'static> MaybeLocalSender<T> {
+    /// Connect to `addr`, in memory if the endpoint there is in this process, and otherwise over
+    /// TCP, with TLS if `tls` is given.
+    pub fn connect(addr: &SocketAddr, tls: Option<&Tls>) -> io::Result<Self> {
+        match local::connect(addr) {
+            Some(tx) => Ok(MaybeLocalSender::Local { tx, peer: *addr }),
+            None => TcpSender::connect_with_tls(addr, tls).map(MaybeLocalSender::Remote),
+        }
+    }
+
+    /// Send a message on this connection.
+    pub fn send(&mut self, t: T) -> Result<(), tcp::SendError> {
+        match *self {
+            MaybeLocalSender::Local { ref mut tx, .. } => Sender::send(tx, t),
+            MaybeLocalSender::Remote(ref mut s) => s.send(t),
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match *self {
+            MaybeLocalSender::Local { peer, .. } => Ok(peer),
+            MaybeLocalSender::Remote(ref s) => s.peer_addr(),
+        }
+    }
+
+    pub fn is_local(&self) -> bool {
+        match *self {
+            MaybeLocalSender::Local { .. } => true,
+            MaybeLocalSender::Remote(_) => false,
+        }
+    }
+}
+
+impl<T: serde::Serialize + Send + 'static> Sender for MaybeLocalSender<T> {
+    type Item = T;
+
+    fn send(&mut self, t: T) -> Result<(), tcp::SendError> {
+        MaybeLocalSender::send(self, t)
+    }
+}
+
 impl<T> DomainConnectionBuilder<MaybeLocal, T>
 where
     T: serde::Serialize + 'static + Send,
@@ -141,33 +304,45 @@ where
     pub fn build_async(
         self,
     ) -> io::Result<Box<dyn Sink<T, Error = bincode::Error> + Send + Unpin>> {
-        if let Some(chan) = self.chan {
-            Ok(Box::new(
-                ImplSinkForSender(chan)
+        match self.chan {
+            Some(chan) if self.priority => Ok(Box::new(
+                ImplSinkForSender(chan.priority)
                     .sink_map_err(|_| serde::de::Error::custom("failed to do local send")),
-            ) as Box<_>)
-        } else {
-            DomainConnectionBuilder {
+            ) as Box<_>),
+            Some(chan) => Ok(Box::new(
+                ImplSinkForBoundedSender(chan.updates)
+                    .sink_map_err(|_| serde::de::Error::custom("failed to do local send")),
+            ) as Box<_>),
+            None => DomainConnectionBuilder {
                 sport: self.sport,
                 chan: None,
                 addr: self.addr,
                 is_for_base: false,
+                priority: self.priority,
+                tls: self.tls,
+                expects_tls: self.expects_tls,
+                epoch: self.epoch,
                 _marker: Remote,
             }
             .build_async()
-            .map(|c| Box::new(c) as Box<_>)
+            .map(|c| Box::new(c) as Box<_>),
         }
     }
 
     pub fn build_sync(self) -> io::Result<Box<dyn Sender<Item = T> + Send>> {
         if let Some(chan) = self.chan {
-            Ok(Box::new(chan))
+            // a blocking send on the bounded lane could deadlock the runtime
+            Ok(Box::new(chan.priority))
         } else {
             DomainConnectionBuilder {
                 sport: self.sport,
                 chan: None,
                 addr: self.addr,
                 is_for_base: false,
+                priority: self.priority,
+                tls: self.tls,
+                expects_tls: self.expects_tls,
+                epoch: self.epoch,
                 _marker: Remote,
             }
             .build_sync()
@@ -177,14 +352,17 @@ where
 }
 
 struct ChannelCoordinatorInner<K: Eq + Hash + Clone, T> {
-    /// Map from key to remote address.
-    addrs: HashMap<K, SocketAddr>,
+    /// Map from key to remote address, and whether that address expects TLS.
+    addrs: HashMap<K, (SocketAddr, bool)>,
     /// Map from key to channel sender for local connections.
-    locals: HashMap<K, tokio::sync::mpsc::UnboundedSender<T>>,
+    locals: HashMap<K, LocalChannels<T>>,
+    /// The epoch of the controller whose dataflow connections are made for, if it is known.
+    epoch: Option<Epoch>,
 }
 
 pub struct ChannelCoordinator<K: Eq + Hash + Clone, T> {
     inner: RwLock<ChannelCoordinatorInner<K, T>>,
+    tls: Option<Tls>,
 }
 
 impl<K: Eq + Hash + Clone, T> Default for ChannelCoordinator<K, T> {
@@ -195,24 +373,59 @@ impl<K: Eq + Hash + Clone, T> Default for ChannelCoordinator<K, T> {
 
 impl<K: Eq + Hash + Clone, T> ChannelCoordinator<K, T> {
     pub fn new() -> Self {
+        Self::with_tls(None)
+    }
+
+    /// Make a coordinator whose connections use `tls` for the endpoints that expect TLS.
+    pub fn with_tls(tls: Option<Tls>) -> Self {
         Self {
             inner: RwLock::new(ChannelCoordinatorInner {
                 addrs: Default::default(),
                 locals: Default::default(),
+                epoch: None,
             }),
+            tls,
         }
     }
 
-    pub fn insert_remote(&self, key: K, addr: SocketAddr) {
+    pub fn tls(&self) -> Option<&Tls> {
+        self.tls.as_ref()
+    }
+
+    /// Fence the connections made from now on with the epoch of the controller whose dataflow
+    /// they belong to.
+    pub fn set_epoch(&self, epoch: Epoch) {
+        self.inner.write().unwrap().epoch = Some(epoch);
+    }
+
+    pub fn insert_remote(&self, key: K, addr: SocketAddr, expects_tls: bool) {
         let mut inner = self.inner.write().unwrap();
-        inner.addrs.insert(key, addr);
+        inner.addrs.insert(key, (addr, expects_tls));
     }
 
-    pub fn insert_local(&self, key: K, chan: tokio::sync::mpsc::UnboundedSender<T>) {
+    pub fn insert_local(&self, key: K, chan: LocalChannels<T>) {
         let mut inner = self.inner.write().unwrap();
         inner.locals.insert(key, chan);
     }
 
+    /// Stop sending to the domain at `key` in memory, since it is no longer in this process.
+    pub fn remove_local<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.write().unwrap().locals.remove(key);
+    }
+
+    /// The channels that the domain at `key` receives packets on, if it is in this process.
+    pub fn local_channels<Q>(&self, key: &Q) -> Option<LocalChannels<T>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.read().unwrap().locals.get(key).cloned()
+    }
+
     pub fn has<Q>(&self, key: &Q) -> bool
     where
         K: Borrow<Q>,
@@ -226,7 +439,21 @@ impl<K: Eq + Hash + Clone, T> ChannelCoordinator<K, T> {
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        self.inner.read().unwrap().addrs.get(key).cloned()
+        self.inner
+            .read()
+            .unwrap()
+            .addrs
+            .get(key)
+            .map(|&(addr, _)| addr)
+    }
+
+    pub fn expects_tls<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let inner = self.inner.read().unwrap();
+        inner.addrs.get(key).map_or(false, |&(_, tls)| tls)
     }
 
     pub fn is_local<Q>(&self, key: &Q) -> Option<bool>
@@ -243,11 +470,16 @@ impl<K: Eq + Hash + Clone, T> ChannelCoordinator<K, T> {
         Q: Hash + Eq + ?Sized,
     {
         let inner = self.inner.read().unwrap();
+        let &(addr, expects_tls) = inner.addrs.get(key)?;
         Some(DomainConnectionBuilder {
             sport: None,
-            addr: *inner.addrs.get(key)?,
+            addr,
             chan: inner.locals.get(key).cloned(),
             is_for_base: false,
+            priority: false,
+            tls: self.tls.clone(),
+            expects_tls,
+            epoch: inner.epoch,
             _marker: MaybeLocal,
         })
     }
@@ -3,7 +3,8 @@ use std::io::{self, Write};
 use std::marker::PhantomData;
 use std::net::{Ipv4Addr, SocketAddr};
 
-use crate::Tagged;
+use super::tls::{BlockingStream, Tls};
+use crate::{Tagged, WriteAck};
 use async_bincode::{AsyncBincodeStream, AsyncDestination};
 use bufstream::BufStream;
 use byteorder::{NetworkEndian, WriteBytesExt};
@@ -52,7 +53,7 @@ macro_rules! poisoning_try {
 }
 
 pub struct TcpSender<T> {
-    stream: BufStream<std::net::TcpStream>,
+    stream: BufStream<BlockingStream>,
     poisoned: bool,
 
     phantom: PhantomData<T>,
@@ -61,21 +62,33 @@ pub struct TcpSender<T> {
 impl<T: Serialize> TcpSender<T> {
     pub fn new(stream: std::net::TcpStream) -> Result<Self, io::Error> {
         stream.set_nodelay(true).unwrap();
-        Ok(Self {
+        Ok(Self::from_stream(BlockingStream::Plain(stream)))
+    }
+
+    fn from_stream(stream: BlockingStream) -> Self {
+        Self {
             stream: BufStream::new(stream),
             poisoned: false,
             phantom: PhantomData,
-        })
+        }
     }
 
-    pub(crate) fn connect_from(sport: Option<u16>, addr: &SocketAddr) -> Result<Self, io::Error> {
+    pub(crate) fn connect_from(
+        sport: Option<u16>,
+        addr: &SocketAddr,
+        tls: Option<&Tls>,
+    ) -> Result<Self, io::Error> {
         let f = move || {
             let s = net2::TcpBuilder::new_v4()?
                 .reuse_address(true)?
                 .bind((Ipv4Addr::UNSPECIFIED, sport.unwrap_or(0)))?
                 .connect(addr)?;
             s.set_nodelay(true)?;
-            Self::new(s)
+            let s = match tls {
+                Some(tls) => BlockingStream::Tls(Box::new(tls.connect_blocking(s, *addr)?)),
+                None => BlockingStream::Plain(s),
+            };
+            Ok(Self::from_stream(s))
         };
 
         if tokio::runtime::Handle::try_current().is_ok() {
@@ -86,23 +99,28 @@ impl<T: Serialize> TcpSender<T> {
     }
 
     pub fn connect(addr: &SocketAddr) -> Result<Self, io::Error> {
-        Self::connect_from(None, addr)
+        Self::connect_from(None, addr, None)
+    }
+
+    /// Connect to `addr`, over TLS if `tls` is given.
+    pub fn connect_with_tls(addr: &SocketAddr, tls: Option<&Tls>) -> Result<Self, io::Error> {
+        Self::connect_from(None, addr, tls)
     }
 
-    pub fn get_mut(&mut self) -> &mut BufStream<std::net::TcpStream> {
+    pub fn get_mut(&mut self) -> &mut BufStream<BlockingStream> {
         &mut self.stream
     }
 
-    pub(crate) fn into_inner(self) -> BufStream<std::net::TcpStream> {
+    pub(crate) fn into_inner(self) -> BufStream<BlockingStream> {
         self.stream
     }
 
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
-        self.stream.get_ref().local_addr()
+        self.stream.get_ref().get_ref().local_addr()
     }
 
     pub fn peer_addr(&self) -> io::Result<SocketAddr> {
-        self.stream.get_ref().peer_addr()
+        self.stream.get_ref().get_ref().peer_addr()
     }
 
     /// Send a message on this channel. Ownership isn't actually required, but is taken anyway to
@@ -158,9 +176,9 @@ pub enum RecvError {
 
 #[pin_project(project = DualTcpStreamProj)]
 pub enum DualTcpStream<S, T, T2, D> {
-    Passthrough(#[pin] AsyncBincodeStream<S, T, Tagged<()>, D>),
+    Passthrough(#[pin] AsyncBincodeStream<S, T, Tagged<WriteAck>, D>),
     Upgrade(
-        #[pin] AsyncBincodeStream<S, T2, Tagged<()>, D>,
+        #[pin] AsyncBincodeStream<S, T2, Tagged<WriteAck>, D>,
         Box<dyn FnMut(T2) -> T + Send + Sync>,
     ),
 }
@@ -173,7 +191,7 @@ impl<S, T, T2> From<S> for DualTcpStream<S, T, T2, AsyncDestination> {
 
 impl<S, T, T2> DualTcpStream<S, T, T2, AsyncDestination> {
     pub fn upgrade<F: 'static + FnMut(T2) -> T + Send + Sync>(stream: S, f: F) -> Self {
-        let s: AsyncBincodeStream<S, T2, Tagged<()>, AsyncDestination> =
+        let s: AsyncBincodeStream<S, T2, Tagged<WriteAck>, AsyncDestination> =
             AsyncBincodeStream::from(stream).for_async();
         DualTcpStream::Upgrade(s, Box::new(f))
     }
@@ -186,11 +204,11 @@ impl<S, T, T2> DualTcpStream<S, T, T2, AsyncDestination> {
     }
 }
 
-impl<S, T, T2, D> Sink<Tagged<()>> for DualTcpStream<S, T, T2, D>
+impl<S, T, T2, D> Sink<Tagged<WriteAck>> for DualTcpStream<S, T, T2, D>
 where
     S: AsyncWrite,
-    AsyncBincodeStream<S, T, Tagged<()>, D>: Sink<Tagged<()>, Error = bincode::Error>,
-    AsyncBincodeStream<S, T2, Tagged<()>, D>: Sink<Tagged<()>, Error = bincode::Error>,
+    AsyncBincodeStream<S, T, Tagged<WriteAck>, D>: Sink<Tagged<WriteAck>, Error = bincode::Error>,
+    AsyncBincodeStream<S, T2, Tagged<WriteAck>, D>: Sink<Tagged<WriteAck>, Error = bincode::Error>,
 {
     type Error = bincode::Error;
 
@@ -201,7 +219,7 @@ where
         }
     }
 
-    fn start_send(self: Pin<&mut Self>, item: Tagged<()>) -> Result<(), Self::Error> {
+    fn start_send(self: Pin<&mut Self>, item: Tagged<WriteAck>) -> Result<(), Self::Error> {
         match self.project() {
             DualTcpStreamProj::Passthrough(abs) => abs.start_send(item),
             DualTcpStreamProj::Upgrade(abs, _) => abs.start_send(item),
@@ -228,8 +246,8 @@ where
     for<'a> T: Deserialize<'a>,
     for<'a> T2: Deserialize<'a>,
     S: AsyncRead,
-    AsyncBincodeStream<S, T, Tagged<()>, D>: Stream<Item = Result<T, bincode::Error>>,
-    AsyncBincodeStream<S, T2, Tagged<()>, D>: Stream<Item = Result<T2, bincode::Error>>,
+    AsyncBincodeStream<S, T, Tagged<WriteAck>, D>: Stream<Item = Result<T, bincode::Error>>,
+    AsyncBincodeStream<S, T2, Tagged<WriteAck>, D>: Stream<Item = Result<T2, bincode::Error>>,
 {
     type Item = Result<T, bincode::Error>;
 
@@ -0,0 +1,590 @@
+//! Optional TLS for the connections between clients, workers, and the controller.
+//!
+//! A worker that is given a certificate and key only accepts connections over TLS, and says so
+//! when it registers with the controller. The controller hands that along with every address it
+//! gives out, so that whoever connects knows up front whether to start a handshake. This is what
+//! lets a deployment move to TLS one worker at a time: a peer that has TLS configured still talks
+//! in plain text to workers that do not expect TLS yet, and a peer that has no TLS configured
+//! fails with an error that names the endpoint, rather than with a garbled handshake.
+//!
+//! Peers verify each other's certificates against a CA bundle. Since peers find each other by IP
+//! address, all worker certificates must be issued for a single shared name, which is
+//! [`TlsConfig::server_name`].
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures_util::{future, ready};
+use rustls::{ClientConfig, ClientSession, ServerConfig, ServerSession, Session, StreamOwned};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// How many sessions a worker remembers so that returning peers can resume them.
+const SERVER_SESSIONS: usize = 1024;
+
+/// How many sessions with each endpoint a peer remembers for resumption.
+const CLIENT_SESSIONS: usize = 8;
+
+/// Where to find the certificates and keys used for TLS.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// The PEM certificate chain to present to peers.
+    ///
+    /// Only needed to accept connections over TLS, so clients can leave it unset.
+    pub cert: Option<PathBuf>,
+    /// The PEM private key for `cert`, in PKCS#8 or PKCS#1 form.
+    pub key: Option<PathBuf>,
+    /// The PEM bundle of CAs that the certificates of peers are verified against.
+    pub ca: PathBuf,
+    /// The name that worker certificates are issued for.
+    pub server_name: String,
+}
+
+impl TlsConfig {
+    /// TLS for a worker, which both accepts and makes connections.
+    pub fn new<P: Into<PathBuf>>(cert: P, key: P, ca: P) -> Self {
+        TlsConfig {
+            cert: Some(cert.into()),
+            key: Some(key.into()),
+            ..TlsConfig::client(ca)
+        }
+    }
+
+    /// TLS for a client, which only connects to workers.
+    pub fn client<P: Into<PathBuf>>(ca: P) -> Self {
+        TlsConfig {
+            cert: None,
+            key: None,
+            ca: ca.into(),
+            server_name: String::from("noria"),
+        }
+    }
+
+    /// Set the name that worker certificates are issued for. Defaults to `noria`.
+    pub fn with_server_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.server_name = name.into();
+        self
+    }
+
+    /// Read the certificates and keys.
+    pub fn load(&self) -> io::Result<Tls> {
+        let server_name = webpki::DNSNameRef::try_from_ascii_str(&self.server_name)
+            .map_err(|_| invalid(format!("{:?} is not a valid server name", self.server_name)))?
+            .to_owned();
+
+        let mut client = ClientConfig::new();
+        let (added, _) = client
+            .root_store
+            .add_pem_file(&mut open(&self.ca)?)
+            .map_err(|_| invalid(format!("could not parse CAs in {}", self.ca.display())))?;
+        if added == 0 {
+            return Err(invalid(format!("no CAs found in {}", self.ca.display())));
+        }
+
+        let server = match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => {
+                let certs = rustls::internal::pemfile::certs(&mut open(cert)?)
+                    .map_err(|_| invalid(format!("could not parse {}", cert.display())))?;
+                let key = read_key(key)?;
+                let mut server = ServerConfig::new(rustls::NoClientAuth::new());
+                server
+                    .set_single_cert(certs, key)
+                    .map_err(|e| invalid(e.to_string()))?;
+                // peers resume by ticket under TLS 1.3, and by session id before it
+                server.ticketer = rustls::Ticketer::new();
+                server.set_persistence(rustls::ServerSessionMemoryCache::new(SERVER_SESSIONS));
+                Some(Arc::new(server))
+            }
+            (None, None) => None,
+            _ => {
+                return Err(invalid(
+                    "a certificate needs a key, and a key needs a certificate",
+                ))
+            }
+        };
+
+        Ok(Tls {
+            inner: Arc::new(Inner {
+                server,
+                client,
+                server_name,
+                endpoints: Default::default(),
+            }),
+        })
+    }
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
+
+fn open(path: &Path) -> io::Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
+
+fn read_key(path: &Path) -> io::Result<rustls::PrivateKey> {
+    let unparseable = || invalid(format!("could not parse {}", path.display()));
+    let mut keys = rustls::internal::pemfile::pkcs8_private_keys(&mut open(path)?)
+        .map_err(|_| unparseable())?;
+    if keys.is_empty() {
+        keys = rustls::internal::pemfile::rsa_private_keys(&mut open(path)?)
+            .map_err(|_| unparseable())?;
+    }
+    keys.pop()
+        .ok_or_else(|| invalid(format!("no private key found in {}", path.display())))
+}
+
+/// Loaded TLS certificates and keys, shared by all the connections of a process.
+#[derive(Clone)]
+pub struct Tls {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    server: Option<Arc<ServerConfig>>,
+    client: ClientConfig,
+    server_name: webpki::DNSName,
+    /// Since every worker goes by the same name, sessions are cached per endpoint, with one client
+    /// configuration for each. Otherwise, connecting to one worker would push out the session with
+    /// another, and domains that connect to several others would never get to resume.
+    endpoints: Mutex<HashMap<SocketAddr, Arc<ClientConfig>>>,
+}
+
+impl fmt::Debug for Tls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tls")
+            .field("accepts", &self.accepts())
+            .finish()
+    }
+}
+
+impl Tls {
+    /// Whether this accepts connections over TLS, which it does if it was given a certificate.
+    pub fn accepts(&self) -> bool {
+        self.inner.server.is_some()
+    }
+
+    fn client_session(&self, addr: SocketAddr) -> ClientSession {
+        let config = self
+            .inner
+            .endpoints
+            .lock()
+            .unwrap()
+            .entry(addr)
+            .or_insert_with(|| {
+                let mut config = self.inner.client.clone();
+                config.set_persistence(rustls::ClientSessionMemoryCache::new(CLIENT_SESSIONS));
+                Arc::new(config)
+            })
+            .clone();
+        ClientSession::new(&config, self.inner.server_name.as_ref())
+    }
+
+    /// Start TLS on a blocking connection to `addr`, and wait for the handshake to complete.
+    pub fn connect_blocking(
+        &self,
+        mut stream: std::net::TcpStream,
+        addr: SocketAddr,
+    ) -> io::Result<StreamOwned<ClientSession, std::net::TcpStream>> {
+        let mut session = self.client_session(addr);
+        while session.is_handshaking() {
+            session.complete_io(&mut stream)?;
+        }
+        Ok(StreamOwned::new(session, stream))
+    }
+
+    /// Start TLS on a connection to `addr`, and wait for the handshake to complete.
+    pub async fn connect<S>(&self, stream: S, addr: SocketAddr) -> io::Result<MaybeTls<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut stream = TlsStream::new(stream, self.client_session(addr));
+        stream.handshake().await?;
+        Ok(MaybeTls::Client(stream))
+    }
+
+    /// Start TLS on a connection a peer made to us, and wait for the handshake to complete.
+    pub async fn accept<S>(&self, stream: S) -> io::Result<MaybeTls<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let server = self
+            .inner
+            .server
+            .as_ref()
+            .ok_or_else(|| invalid("cannot accept TLS connections without a certificate"))?;
+        let mut stream = TlsStream::new(stream, ServerSession::new(server));
+        stream.handshake().await?;
+        Ok(MaybeTls::Server(stream))
+    }
+}
+
+/// Decide how to connect to the endpoint at `addr`, given whether it expects TLS.
+///
+/// Endpoints that do not expect TLS are connected to in plain text even if `tls` is given, which
+/// is what lets a deployment switch to TLS gradually.
+pub fn for_endpoint(
+    tls: Option<&Tls>,
+    addr: SocketAddr,
+    expects_tls: bool,
+) -> io::Result<Option<&Tls>> {
+    match tls {
+        _ if !expects_tls => Ok(None),
+        Some(tls) => Ok(Some(tls)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} expects TLS, but no TLS configuration was given", addr),
+        )),
+    }
+}
+
+/// Connect over TLS if `tls` is given, and in plain text otherwise.
+pub async fn maybe_connect<S>(
+    tls: Option<&Tls>,
+    stream: S,
+    addr: SocketAddr,
+) -> io::Result<MaybeTls<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match tls {
+        Some(tls) => tls.connect(stream, addr).await,
+        None => Ok(MaybeTls::Plain(stream)),
+    }
+}
+
+/// Accept a connection over TLS if `tls` is given and has a certificate, and in plain text
+/// otherwise.
+pub async fn maybe_accept<S>(tls: Option<&Tls>, stream: S) -> io::Result<MaybeTls<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match tls {
+        Some(tls) if tls.accepts() => tls.accept(stream).await,
+        _ => Ok(MaybeTls::Plain(stream)),
+    }
+}
+
+/// A blocking connection that may or may not use TLS.
+pub enum BlockingStream {
+    /// A plain text connection.
+    Plain(std::net::TcpStream),
+    /// A connection that we started TLS on.
+    Tls(Box<StreamOwned<ClientSession, std::net::TcpStream>>),
+}
+
+impl BlockingStream {
+    /// The underlying TCP connection.
+    pub fn get_ref(&self) -> &std::net::TcpStream {
+        match *self {
+            BlockingStream::Plain(ref s) => s,
+            BlockingStream::Tls(ref s) => &s.sock,
+        }
+    }
+
+    /// Move the connection onto the current tokio runtime, keeping any TLS session it has.
+    pub fn into_async(self) -> io::Result<MaybeTls<tokio::net::TcpStream>> {
+        match self {
+            BlockingStream::Plain(s) => tokio::net::TcpStream::from_std(s).map(MaybeTls::Plain),
+            BlockingStream::Tls(s) => {
+                let StreamOwned { sess, sock } = *s;
+                let sock = tokio::net::TcpStream::from_std(sock)?;
+                Ok(MaybeTls::Client(TlsStream::new(sock, sess)))
+            }
+        }
+    }
+}
+
+impl Read for BlockingStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            BlockingStream::Plain(ref mut s) => s.read(buf),
+            BlockingStream::Tls(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for BlockingStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            BlockingStream::Plain(ref mut s) => s.write(buf),
+            BlockingStream::Tls(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            BlockingStream::Plain(ref mut s) => s.flush(),
+            BlockingStream::Tls(ref mut s) => s.flush(),
+        }
+    }
+}
+
+/// An asynchronous connection that may or may not use TLS.
+pub enum MaybeTls<S> {
+    /// A plain text connection.
+    Plain(S),
+    /// A connection that we started TLS on.
+    Client(TlsStream<S, ClientSession>),
+    /// A connection that a peer started TLS on.
+    Server(TlsStream<S, ServerSession>),
+}
+
+impl<S> MaybeTls<S> {
+    /// The underlying connection.
+    pub fn get_ref(&self) -> &S {
+        match *self {
+            MaybeTls::Plain(ref s) => s,
+            MaybeTls::Client(ref s) => &s.io,
+            MaybeTls::Server(ref s) => &s.io,
+        }
+    }
+
+    /// Whether the connection uses TLS.
+    pub fn is_tls(&self) -> bool {
+        match *self {
+            MaybeTls::Plain(_) => false,
+            MaybeTls::Client(_) | MaybeTls::Server(_) => true,
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for MaybeTls<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTls::Plain(s) => Pin::new(s).poll_read(cx, buf),
+            MaybeTls::Client(s) => Pin::new(s).poll_read(cx, buf),
+            MaybeTls::Server(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for MaybeTls<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTls::Plain(s) => Pin::new(s).poll_write(cx, buf),
+            MaybeTls::Client(s) => Pin::new(s).poll_write(cx, buf),
+            MaybeTls::Server(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTls::Plain(s) => Pin::new(s).poll_flush(cx),
+            MaybeTls::Client(s) => Pin::new(s).poll_flush(cx),
+            MaybeTls::Server(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTls::Plain(s) => Pin::new(s).poll_shutdown(cx),
+            MaybeTls::Client(s) => Pin::new(s).poll_shutdown(cx),
+            MaybeTls::Server(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+/// A TLS session over an asynchronous connection.
+pub struct TlsStream<S, C> {
+    io: S,
+    session: C,
+    eof: bool,
+    closing: bool,
+}
+
+/// Lets a session do blocking-style IO on an asynchronous connection, with `WouldBlock` standing
+/// in for `Poll::Pending`.
+struct SyncIo<'a, 'b, S> {
+    io: &'a mut S,
+    cx: &'a mut Context<'b>,
+}
+
+impl<S: AsyncRead + Unpin> Read for SyncIo<'_, '_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match Pin::new(&mut *self.io).poll_read(self.cx, buf) {
+            Poll::Ready(r) => r,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Write for SyncIo<'_, '_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match Pin::new(&mut *self.io).poll_write(self.cx, buf) {
+            Poll::Ready(r) => r,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match Pin::new(&mut *self.io).poll_flush(self.cx) {
+            Poll::Ready(r) => r,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl<S, C> TlsStream<S, C>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    C: Session,
+{
+    fn new(io: S, session: C) -> Self {
+        TlsStream {
+            io,
+            session,
+            eof: false,
+            closing: false,
+        }
+    }
+
+    /// Read TLS records off the connection, and process them.
+    ///
+    /// Returns the number of bytes read, which is zero once the peer has closed the connection.
+    fn read_io(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let n = match self.session.read_tls(&mut SyncIo {
+            io: &mut self.io,
+            cx,
+        }) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Poll::Pending,
+            Err(e) => return Poll::Ready(Err(e)),
+        };
+
+        if let Err(e) = self.session.process_new_packets() {
+            // give the peer a chance to learn why we are giving up
+            let _ = self.session.write_tls(&mut SyncIo {
+                io: &mut self.io,
+                cx,
+            });
+            return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, e)));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    /// Write all the TLS records the session has ready to the connection.
+    fn write_io(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.session.wants_write() {
+            match self.session.write_tls(&mut SyncIo {
+                io: &mut self.io,
+                cx,
+            }) {
+                Ok(0) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Poll::Pending,
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_handshake(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            ready!(self.write_io(cx))?;
+            if !self.session.is_handshaking() {
+                return Pin::new(&mut self.io).poll_flush(cx);
+            }
+            if ready!(self.read_io(cx))? == 0 {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "peer closed the connection during the TLS handshake",
+                )));
+            }
+        }
+    }
+
+    async fn handshake(&mut self) -> io::Result<()> {
+        future::poll_fn(|cx| self.poll_handshake(cx)).await
+    }
+}
+
+impl<S, C> AsyncRead for TlsStream<S, C>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    C: Session + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        loop {
+            match this.session.read(buf) {
+                Ok(0) if !this.eof => {}
+                r => return Poll::Ready(r),
+            }
+
+            // the session may owe the peer a response before the peer sends anything more
+            ready!(this.write_io(cx))?;
+            if ready!(this.read_io(cx))? == 0 {
+                this.eof = true;
+            }
+        }
+    }
+}
+
+impl<S, C> AsyncWrite for TlsStream<S, C>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    C: Session + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let n = this.session.write(buf)?;
+            if n != 0 || buf.is_empty() {
+                // send what we can right away, and leave the rest to poll_flush
+                if let Poll::Ready(Err(e)) = this.write_io(cx) {
+                    return Poll::Ready(Err(e));
+                }
+                return Poll::Ready(Ok(n));
+            }
+
+            // the session will not buffer any more until some of it is sent
+            ready!(this.write_io(cx))?;
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.session.flush()?;
+        ready!(this.write_io(cx))?;
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.closing {
+            this.session.send_close_notify();
+            this.closing = true;
+        }
+        ready!(this.write_io(cx))?;
+        Pin::new(&mut this.io).poll_shutdown(cx)
+    }
+}
@@ -16,6 +16,18 @@ pub const STATE_KEY: &str = "/state";
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Epoch(i64);
 
+impl Epoch {
+    /// The epoch in the form that connections between domains are fenced with.
+    pub fn to_be_bytes(self) -> [u8; 8] {
+        self.0.to_be_bytes()
+    }
+
+    /// The epoch that a connection between domains was fenced with.
+    pub fn from_be_bytes(bytes: [u8; 8]) -> Self {
+        Epoch(i64::from_be_bytes(bytes))
+    }
+}
+
 pub enum ElectionResult {
     Won(Epoch),
     Lost { epoch: Epoch, payload: Vec<u8> },
@@ -1,8 +1,19 @@
+use crate::auth::{AuthError, Scope};
+use crate::channel::Tls;
 use crate::consensus::{self, Authority};
-use crate::debug::stats;
-use crate::table::{Table, TableBuilder, TableRpc};
-use crate::view::{View, ViewBuilder, ViewRpc};
-use crate::ActivationResult;
+use crate::debug::{bookkeeping, events, stats, trace};
+use crate::internal::DomainIndex;
+use crate::namespace;
+use crate::subscription::{ChangeFeed, ResumeToken};
+use crate::table::{
+    BeginTransaction, Committed, Table, TableBuilder, TableRpc, Transaction, TransactionBegun,
+    TransactionDecided, TransactionError, WriteToken, TRANSACTION_PROTOCOL,
+};
+use crate::view::{View, ViewBuilder, ViewRpc, ViewSource};
+use crate::{
+    ActivationResult, BackupSummary, DataType, DecommissionStatus, DomainMove, MigrationPlan,
+    MigrationProgress, Rebalance, RecipeDiff, RecipeVersion, RecoveryStatus, ReplayRate,
+};
 use failure::{self, ResultExt};
 use futures_util::future;
 use petgraph::graph::NodeIndex;
@@ -29,6 +40,9 @@ pub struct ControllerDescriptor {
     pub worker_addr: SocketAddr,
     pub domain_addr: SocketAddr,
     pub nonce: u64,
+    /// Whether the controller expects workers and domains to connect to it over TLS.
+    #[serde(default)]
+    pub tls: bool,
 }
 
 struct Controller<A> {
@@ -145,8 +159,11 @@ where
     A: 'static + Authority,
 {
     handle: Buffer<Controller<A>, ControllerRequest>,
-    domains: Arc<Mutex<HashMap<(SocketAddr, usize), TableRpc>>>,
-    views: Arc<Mutex<HashMap<(SocketAddr, usize), ViewRpc>>>,
+    domains: Arc<Mutex<HashMap<(SocketAddr, usize, Option<Scope>), TableRpc>>>,
+    views: Arc<Mutex<HashMap<(SocketAddr, usize, Option<Scope>), ViewRpc>>>,
+    tls: Option<Tls>,
+    token: Option<String>,
+    namespace: Option<String>,
     tracer: tracing::Dispatch,
 }
 
@@ -159,6 +176,9 @@ where
             handle: self.handle.clone(),
             domains: self.domains.clone(),
             views: self.views.clone(),
+            tls: self.tls.clone(),
+            token: self.token.clone(),
+            namespace: self.namespace.clone(),
             tracer: self.tracer.clone(),
         }
     }
@@ -173,6 +193,9 @@ impl ControllerHandle<consensus::ZookeeperAuthority> {
     }
 }
 
+/// How many rows `ControllerHandle::bulk_load` sends to the controller in each frame.
+const BULK_LOAD_FRAME: usize = 10_000;
+
 // this alias is needed to work around -> impl Trait capturing _all_ lifetimes by default
 // the A parameter is needed so it gets captured into the impl Trait
 #[cfg(not(doc))]
@@ -205,6 +228,9 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         Ok(ControllerHandle {
             views: Default::default(),
             domains: Default::default(),
+            tls: None,
+            token: None,
+            namespace: None,
             handle: Buffer::new(
                 Controller {
                     authority,
@@ -245,9 +271,44 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         Self::make(Arc::new(authority)).await
     }
 
+    /// Connect to the workers that expect it over TLS.
+    ///
+    /// Without this, `View`s and `Table`s on workers that expect TLS cannot be obtained.
+    pub fn with_tls(mut self, tls: Tls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Present `token` when obtaining `View`s and `Table`s, and when connecting to workers.
+    ///
+    /// On an instance that authenticates its clients, the token decides which tables this handle
+    /// may write to, which views it may read from, and which namespaces' recipes it may change or
+    /// place. Handles that were denied fail with an
+    /// [`AuthError`](crate::error::AuthError). Each table and view opens its own connections to
+    /// the workers, since the workers check the token against the table or view a connection is
+    /// for, and connections are not shared with handles that present a different token.
+    pub fn with_token<S: Into<String>>(mut self, token: S) -> Self {
+        self.token = Some(token.into());
+        self.domains = Default::default();
+        self.views = Default::default();
+        self
+    }
+
+    /// Work in `namespace` rather than in the default namespace.
+    ///
+    /// Recipes are then installed into and extended in that namespace, `View`s and `Table`s are
+    /// looked up by their names within it, and the inputs, outputs, graph and statistics only
+    /// cover it. See the [`namespace`](crate::namespace) module for what namespaces are.
+    pub fn in_namespace<S: Into<String>>(mut self, namespace: S) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
     /// Enumerate all known base tables.
     ///
-    /// These have all been created in response to a `CREATE TABLE` statement in a recipe.
+    /// These have all been created in response to a `CREATE TABLE` statement in a recipe. A handle
+    /// in a namespace only sees the tables in that namespace; otherwise, tables in namespaces are
+    /// listed by their qualified names.
     ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
     pub fn inputs(
@@ -255,7 +316,7 @@ impl<A: Authority + 'static> ControllerHandle<A> {
     ) -> impl Future<Output = Result<BTreeMap<String, NodeIndex>, failure::Error>> {
         let fut = self
             .handle
-            .call(ControllerRequest::new("inputs", &()).unwrap());
+            .call(ControllerRequest::new("inputs", &self.namespace).unwrap());
 
         async move {
             let body: hyper::body::Bytes = fut
@@ -271,7 +332,8 @@ impl<A: Authority + 'static> ControllerHandle<A> {
 
     /// Enumerate all known external views.
     ///
-    /// These have all been created in response to a `CREATE EXT VIEW` statement in a recipe.
+    /// These have all been created in response to a `CREATE EXT VIEW` statement in a recipe. Like
+    /// [`ControllerHandle::inputs`], this only covers the handle's namespace if it is in one.
     ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
     pub fn outputs(
@@ -279,7 +341,7 @@ impl<A: Authority + 'static> ControllerHandle<A> {
     ) -> impl Future<Output = Result<BTreeMap<String, NodeIndex>, failure::Error>> {
         let fut = self
             .handle
-            .call(ControllerRequest::new("outputs", &()).unwrap());
+            .call(ControllerRequest::new("outputs", &self.namespace).unwrap());
 
         async move {
             let body: hyper::body::Bytes = fut
@@ -303,26 +365,95 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         #[cfg(debug_assertions)]
         assert_infrequent::at_most(200);
 
+        self.view_from("view_builder", name)
+    }
+
+    /// Obtain a `View` from the `ViewBuilder` that the controller gives out at `path` for `name`.
+    fn view_from(
+        &mut self,
+        path: &'static str,
+        name: &str,
+    ) -> impl Future<Output = Result<View, failure::Error>> {
         let views = self.views.clone();
+        let tls = self.tls.clone();
+        let token = self.token.clone();
+        let namespace = self.namespace.clone();
         let name = name.to_string();
         let fut = self
             .handle
-            .call(ControllerRequest::new("view_builder", &name).unwrap());
+            .call(ControllerRequest::new(path, (&name, &token, &namespace)).unwrap());
+        let scope = if path == "change_feed" {
+            Scope::Changes {
+                namespace: namespace.clone(),
+                name: name.clone(),
+            }
+        } else {
+            Scope::View {
+                namespace: namespace.clone(),
+                name: name.clone(),
+            }
+        };
+
+        // once the view's shards move, the view asks the controller where they went
+        let handle = Mutex::new(self.handle.clone());
+        let source_name = name.clone();
+        let source_token = token.clone();
+        let source: ViewSource = Arc::new(move || {
+            let mut handle = handle.lock().unwrap().clone();
+            let name = source_name.clone();
+            let token = source_token.clone();
+            let namespace = namespace.clone();
+            Box::pin(async move {
+                future::poll_fn(|cx| handle.poll_ready(cx))
+                    .await
+                    .map_err(failure::Error::from_boxed_compat)?;
+                let body: hyper::body::Bytes = handle
+                    .call(ControllerRequest::new(path, (&name, &token, &namespace)).unwrap())
+                    .await
+                    .map_err(failure::Context::new)
+                    .context("failed to fetch view builder")?;
+                Ok(serde_json::from_slice::<
+                    Result<Option<ViewBuilder>, AuthError>,
+                >(&body)??)
+            })
+        });
+
         async move {
             let body: hyper::body::Bytes = fut
                 .await
                 .map_err(failure::Context::new)
                 .context("failed to fetch view builder")?;
 
-            match serde_json::from_slice::<Option<ViewBuilder>>(&body) {
-                Ok(Some(vb)) => Ok(vb.build(views)?),
-                Ok(None) => Err(failure::err_msg("view does not exist")),
+            match serde_json::from_slice::<Result<Option<ViewBuilder>, AuthError>>(&body) {
+                Ok(Ok(Some(vb))) => Ok(vb.build(views, tls, token, scope)?.with_source(source)),
+                Ok(Ok(None)) => Err(failure::err_msg("view does not exist")),
+                Ok(Err(e)) => Err(failure::Error::from(e)),
                 Err(e) => Err(failure::Error::from(e)),
             }
             .map_err(move |e| e.context(format!("building view for {}", name)).into())
         }
     }
 
+    /// Follow the changes made to the base table `name`.
+    ///
+    /// Without `from`, the feed delivers the changes made from now on. With `from`, it picks up
+    /// after the point in the table's changes that the token identifies, as long as those changes
+    /// are still retained; if they are not, the feed starts with
+    /// [`FeedUpdate::ResyncRequired`](crate::subscription::FeedUpdate::ResyncRequired).
+    ///
+    /// The first feed for a table sets up a fully materialized view of it that retains its recent
+    /// changes, and so holds a copy of every row of the table.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn change_feed(
+        &mut self,
+        name: &str,
+        from: Option<ResumeToken>,
+    ) -> impl Future<Output = Result<ChangeFeed, failure::Error>> {
+        let view = self.view_from("change_feed", name);
+        async move { Ok(view.await?.change_feed(from).await?) }
+    }
+
     /// Obtain a `Table` that allows you to perform writes, deletes, and other operations on the
     /// given base table.
     ///
@@ -334,10 +465,12 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         assert_infrequent::at_most(200);
 
         let domains = self.domains.clone();
+        let tls = self.tls.clone();
+        let token = self.token.clone();
         let name = name.to_string();
-        let fut = self
-            .handle
-            .call(ControllerRequest::new("table_builder", &name).unwrap());
+        let fut = self.handle.call(
+            ControllerRequest::new("table_builder", (&name, &token, &self.namespace)).unwrap(),
+        );
 
         async move {
             let body: hyper::body::Bytes = fut
@@ -345,9 +478,10 @@ impl<A: Authority + 'static> ControllerHandle<A> {
                 .map_err(failure::Context::new)
                 .context("failed to fetch table builder")?;
 
-            match serde_json::from_slice::<Option<TableBuilder>>(&body) {
-                Ok(Some(tb)) => Ok(tb.build(domains)?),
-                Ok(None) => Err(failure::err_msg("view table not exist")),
+            match serde_json::from_slice::<Result<Option<TableBuilder>, AuthError>>(&body) {
+                Ok(Ok(Some(tb))) => Ok(tb.build(domains, tls.as_ref(), token.as_deref())?),
+                Ok(Ok(None)) => Err(failure::err_msg("view table not exist")),
+                Ok(Err(e)) => Err(failure::Error::from(e)),
                 Err(e) => Err(failure::Error::from(e)),
             }
             .map_err(move |e| e.context(format!("building table for {}", name)).into())
@@ -372,11 +506,156 @@ impl<A: Authority + 'static> ControllerHandle<A> {
 
     /// Get statistics about the time spent processing different parts of the graph.
     ///
+    /// A handle in a namespace only gets the statistics of the domains and views in that namespace.
+    ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
     pub fn statistics(
         &mut self,
     ) -> impl Future<Output = Result<stats::GraphStats, failure::Error>> {
-        self.rpc("get_statistics", (), "failed to get stats")
+        let namespace = self.namespace.clone();
+        self.rpc("get_statistics", namespace, "failed to get stats")
+    }
+
+    /// Fetch the events recorded so far for the write traced under `tag`.
+    ///
+    /// The tag is the one returned by [`Table::perform_all_traced`]. Each worker only remembers
+    /// the most recent traces, so a trace that is fetched long after its write may be empty.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn trace(
+        &mut self,
+        tag: u64,
+    ) -> impl Future<Output = Result<trace::Trace, failure::Error>> {
+        self.rpc("trace", tag, "failed to fetch trace")
+    }
+
+    /// Fetch the trace of the write traced under `tag` once it is complete, or once `timeout` has
+    /// passed, whichever comes first.
+    ///
+    /// Check [`Trace::complete`](trace::Trace::complete) to tell the two apart.
+    pub async fn wait_for_trace(
+        &mut self,
+        tag: u64,
+        timeout: Duration,
+    ) -> Result<trace::Trace, failure::Error> {
+        let deadline = std::time::Instant::now() + timeout;
+        loop {
+            self.ready().await?;
+            let trace = self.trace(tag).await?;
+            if trace.complete || std::time::Instant::now() >= deadline {
+                return Ok(trace);
+            }
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Turn logging of every packet that the shards of `domain` handle on or off.
+    ///
+    /// The packets are logged at the info level by the workers running the domain, so this works
+    /// without rebuilding with trace-level logging compiled in. Domain indices can be found in
+    /// [`ControllerHandle::statistics`] or [`ControllerHandle::annotated_graphviz`].
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn set_packet_logging(
+        &mut self,
+        domain: DomainIndex,
+        enabled: bool,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc(
+            "set_packet_logging",
+            (domain, enabled),
+            "failed to set packet logging",
+        )
+    }
+
+    /// Fetch at most `limit` entries of the controller's event log, starting with the one numbered
+    /// `cursor`.
+    ///
+    /// The log records recipe activations, domain placements, workers joining and failing,
+    /// recoveries, and resharding steps. To tail it, start with a cursor of 0 and keep passing
+    /// in the returned [`EventPage::next`](crate::debug::events::EventPage::next). The log is
+    /// kept in memory, and only its most recent entries are kept.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn events(
+        &mut self,
+        cursor: u64,
+        limit: usize,
+    ) -> impl Future<Output = Result<events::EventPage, failure::Error>> {
+        self.rpc("events", (cursor, limit), "failed to get events")
+    }
+
+    /// Get what each shard of `domain` keeps track of about the updates flowing through its
+    /// nodes, such as the latest batch of writes each base has sent and each reader reflects, and
+    /// the updates that are held back.
+    ///
+    /// The domain answers in between packets, so this works even while it is busy. Domain
+    /// indices can be found in [`ControllerHandle::statistics`].
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn domain_bookkeeping(
+        &mut self,
+        domain: DomainIndex,
+    ) -> impl Future<Output = Result<Vec<bookkeeping::DomainBookkeeping>, failure::Error>> {
+        self.rpc(
+            "domain_bookkeeping",
+            domain,
+            "failed to get domain bookkeeping",
+        )
+    }
+
+    /// Have every domain log a warning whenever one of its nodes takes longer than `packet` to
+    /// process a single packet, or a partial replay takes longer than `replay` from the miss that
+    /// caused it until the missing state is filled. `None` turns the respective warning off.
+    ///
+    /// The warnings name the node by its index, as shown by [`ControllerHandle::graphviz`], and
+    /// are logged by the worker running the domain.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn set_slow_thresholds(
+        &mut self,
+        packet: Option<Duration>,
+        replay: Option<Duration>,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc(
+            "set_slow_thresholds",
+            (packet, replay),
+            "failed to set slow thresholds",
+        )
+    }
+
+    /// Change the share of writes whose propagation latency is measured, from 0 (none, the
+    /// default) to 1 (all).
+    ///
+    /// Sampled writes are stamped when they arrive at their base table, and every reader they
+    /// reach records how long they took to get there. The latencies show up in
+    /// [`ViewStats::propagation`](crate::debug::stats::ViewStats::propagation) and in the
+    /// workers' metrics. Stamping costs little, but keep the rate low for write-heavy workloads.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn set_propagation_sampling(
+        &mut self,
+        rate: f64,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc(
+            "set_propagation_sampling",
+            rate,
+            "failed to set propagation sampling",
+        )
+    }
+
+    /// Change how fast domains replay the state of a node in full, such as when a new view is
+    /// filled from a table, or a restarted deployment is restored.
+    ///
+    /// The new rate also applies to replays that are underway, so a slow replay can be sped up
+    /// once the traffic it would interfere with has died down. A rate of zero is refused.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn set_replay_rate(
+        &mut self,
+        rate: ReplayRate,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("set_replay_rate", rate, "failed to set replay rate")
     }
 
     /// Flush all partial state, evicting all rows present.
@@ -393,37 +672,535 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         &mut self,
         recipe_addition: &str,
     ) -> impl Future<Output = Result<ActivationResult, failure::Error>> {
-        self.rpc("extend_recipe", recipe_addition, "failed to extend recipe")
+        let namespace = self.namespace.clone();
+        let token = self.token.clone();
+        self.rpc(
+            "extend_recipe",
+            (recipe_addition, namespace, token),
+            "failed to extend recipe",
+        )
     }
 
     /// Replace the existing recipe with this one.
     ///
+    /// Only the tables and views in the handle's namespace are replaced; those in other namespaces
+    /// stay as they are.
+    ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
     pub fn install_recipe(
         &mut self,
         new_recipe: &str,
     ) -> impl Future<Output = Result<ActivationResult, failure::Error>> {
-        self.rpc("install_recipe", new_recipe, "failed to install recipe")
+        let namespace = self.namespace.clone();
+        let token = self.token.clone();
+        self.rpc(
+            "install_recipe",
+            (new_recipe, namespace, token),
+            "failed to install recipe",
+        )
+    }
+
+    /// Remove the view `name` from the recipe, and tear down the parts of the dataflow that no
+    /// other view or table needs.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn remove_view(
+        &mut self,
+        name: &str,
+    ) -> impl Future<Output = Result<ActivationResult, failure::Error>> {
+        let namespace = self.namespace.clone();
+        let token = self.token.clone();
+        self.rpc(
+            "remove_view",
+            (name, namespace, token),
+            "failed to remove view",
+        )
     }
 
-    /// Fetch a graphviz description of the dataflow graph.
+    /// Fetch every change that has been made to the recipe, oldest first.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn recipe_history(
+        &mut self,
+    ) -> impl Future<Output = Result<Vec<RecipeVersion>, failure::Error>> {
+        self.rpc("recipe_history", (), "failed to get recipe history")
+    }
+
+    /// Compute which tables and views installing the given recipe would add, remove, and leave
+    /// as they are, without installing it.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn recipe_diff(
+        &mut self,
+        candidate: &str,
+    ) -> impl Future<Output = Result<RecipeDiff, failure::Error>> {
+        let namespace = self.namespace.clone();
+        self.rpc(
+            "recipe_diff",
+            (candidate, namespace),
+            "failed to compute recipe diff",
+        )
+    }
+
+    /// Work out what installing the given recipe would do to the dataflow, without installing
+    /// it or changing the dataflow in any way.
+    ///
+    /// The plan lists the nodes that would be added, the domains and shards they would be placed
+    /// in, how they would be materialized, and roughly how many rows would have to be replayed.
+    /// Use `MigrationPlan::matches` to check an installation against the plan.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn plan_recipe(
+        &mut self,
+        candidate: &str,
+    ) -> impl Future<Output = Result<MigrationPlan, failure::Error>> {
+        let namespace = self.namespace.clone();
+        let token = self.token.clone();
+        self.rpc(
+            "plan_recipe",
+            (candidate, namespace, token),
+            "failed to plan recipe",
+        )
+    }
+
+    /// Fetch how far along the migration that the controller is running, or last ran, is, if it
+    /// has run one.
+    ///
+    /// This is answered even while the controller is busy migrating, so it can be polled from a
+    /// clone of this handle while a call that migrates, such as `Self::install_recipe`, is
+    /// outstanding. A migration that ended with an error is reported as failed.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn migration_progress(
+        &mut self,
+    ) -> impl Future<Output = Result<Option<MigrationProgress>, failure::Error>> {
+        self.rpc("migration_progress", (), "failed to get migration progress")
+    }
+
+    /// Fetch how far the controller is with restoring the dataflow over the base tables of a
+    /// deployment that it took over, if it had any to restore.
+    ///
+    /// Until recovery is complete, the controller answers no other requests, so that no client
+    /// reads or writes before every view has caught up with the restored tables. Like
+    /// `Self::migration_progress`, this is answered all the same.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn recovery_status(
+        &mut self,
+    ) -> impl Future<Output = Result<Option<RecoveryStatus>, failure::Error>> {
+        self.rpc("recovery_status", (), "failed to get recovery status")
+    }
+
+    /// Only place the domains of the table or view `name` on workers that were started with
+    /// `label`, or let them go on any worker again if `label` is `None`.
+    ///
+    /// The constraint covers the base domains of a table and the reader domains of a view, and is
+    /// kept across controller restarts. A migration that adds a constrained table or view while no
+    /// healthy worker has its label fails. Domains that are already running are not moved; the
+    /// constraint applies the next time they are placed, such as after their worker is lost.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn set_placement(
+        &mut self,
+        name: &str,
+        label: Option<&str>,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        let namespace = self.namespace.clone();
+        let token = self.token.clone();
+        self.rpc(
+            "set_placement",
+            (name, label, namespace, token),
+            "failed to set placement",
+        )
+    }
+
+    /// Fetch the label that the domains of each constrained table and view must be placed on.
+    ///
+    /// A handle in a namespace only gets the tables and views in that namespace.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn placements(
+        &mut self,
+    ) -> impl Future<Output = Result<HashMap<String, String>, failure::Error>> {
+        let namespace = self.namespace.clone();
+        self.rpc("placements", namespace, "failed to get placements")
+    }
+
+    /// Roll the tables and views in the handle's namespace back to what they were at version `to`
+    /// of the recipe. Those in other namespaces stay as they are.
+    ///
+    /// The rollback only happens if the recipe is still at version `from`, so that it does not
+    /// undo a change that someone else has made in the meantime. If it is not, this fails with a
+    /// conflict.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn rollback_recipe(
+        &mut self,
+        from: usize,
+        to: usize,
+    ) -> impl Future<Output = Result<ActivationResult, failure::Error>> {
+        let namespace = self.namespace.clone();
+        let token = self.token.clone();
+        self.rpc(
+            "rollback_recipe",
+            (from, to, namespace, token),
+            "failed to roll back recipe",
+        )
+    }
+
+    /// Fetch a graphviz description of the dataflow graph, or of the part of it in the handle's
+    /// namespace if it is in one.
     ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
     pub fn graphviz(&mut self) -> impl Future<Output = Result<String, failure::Error>> {
-        self.rpc("graphviz", (), "failed to fetch graphviz output")
+        let namespace = self.namespace.clone();
+        self.rpc("graphviz", namespace, "failed to fetch graphviz output")
     }
 
-    /// Fetch a simplified graphviz description of the dataflow graph.
+    /// Fetch a simplified graphviz description of the dataflow graph, or of the part of it in the
+    /// handle's namespace if it is in one.
     ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
     pub fn simple_graphviz(&mut self) -> impl Future<Output = Result<String, failure::Error>> {
+        let namespace = self.namespace.clone();
         self.rpc(
             "simple_graphviz",
-            (),
+            namespace,
             "failed to fetch simple graphviz output",
         )
     }
 
+    /// Fetch a graphviz description of the dataflow graph that shows how it is laid out.
+    ///
+    /// The nodes of each domain are drawn in a cluster, once for each of the domain's shards, and
+    /// are labeled with their operator, fields, state keys, and whether their state is full or
+    /// partial. Edges between domains are labeled with how the receiving side is sharded. If
+    /// `stats` is true, the labels also include the size of each node's state on each shard,
+    /// which takes a round-trip to every domain.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn annotated_graphviz(
+        &mut self,
+        stats: bool,
+    ) -> impl Future<Output = Result<String, failure::Error>> {
+        self.rpc(
+            "annotated_graphviz",
+            stats,
+            "failed to fetch annotated graphviz output",
+        )
+    }
+
+    /// Load the given rows into the base table `table` in bulk, and return how many were loaded.
+    ///
+    /// The rows are streamed into the table's state in large frames, without going through the
+    /// normal write path. Views over the table only see the loaded rows once all of them have been
+    /// loaded, and normal writes to the table are held back until then.
+    ///
+    /// If a frame is rejected, for example because one of its rows does not have as many columns
+    /// as the table, the load is finished with the rows loaded before it, and the error returned.
+    pub async fn bulk_load<I>(&mut self, table: &str, rows: I) -> Result<usize, failure::Error>
+    where
+        I: IntoIterator<Item = Vec<DataType>>,
+    {
+        let table = &namespace::qualify(self.namespace.as_deref(), table);
+        self.ready().await?;
+        self.rpc::<_, ()>("start_bulk_load", table, "failed to start bulk load")
+            .await?;
+
+        let mut rows = rows.into_iter().peekable();
+        while rows.peek().is_some() {
+            let frame: Vec<_> = rows.by_ref().take(BULK_LOAD_FRAME).collect();
+            self.ready().await?;
+            if let Err(e) = self
+                .rpc::<_, ()>("bulk_load", (table, frame), "failed to bulk load rows")
+                .await
+            {
+                // don't leave writes to the table held back forever
+                self.rpc::<_, usize>("finish_bulk_load", table, "failed to finish bulk load")
+                    .await?;
+                return Err(e);
+            }
+        }
+
+        self.ready().await?;
+        self.rpc("finish_bulk_load", table, "failed to finish bulk load")
+            .await
+    }
+
+    /// Back up every base table, along with the recipe, into the directory `dir` on the
+    /// controller's host.
+    ///
+    /// The backup is consistent: writes to every table are held back while the tables are
+    /// snapshotted, so that it holds each write either for every table it went to or for none,
+    /// and no write without the writes that were acknowledged before it was made. Writes resume
+    /// whether or not the backup succeeds. The directory must not exist, or be empty. Tables
+    /// cannot be backed up while a transaction is open, a table is being bulk loaded, or the
+    /// dataflow is being resharded.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn backup(
+        &mut self,
+        dir: &str,
+    ) -> impl Future<Output = Result<BackupSummary, failure::Error>> {
+        self.rpc("backup", dir, "failed to back up")
+    }
+
+    /// Restore the backup in the directory `dir` on the controller's host into this deployment,
+    /// which must not have any tables yet.
+    ///
+    /// The backed up recipe is installed, and the rows of each table are bulk loaded into it.
+    /// Views fill from the restored tables as they do after any bulk load. A restore that fails
+    /// part of the way leaves the deployment partially restored.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn restore(
+        &mut self,
+        dir: &str,
+    ) -> impl Future<Output = Result<BackupSummary, failure::Error>> {
+        self.rpc("restore", dir, "failed to restore backup")
+    }
+
+    /// Get how many rows an ongoing bulk load into the base table `table` has loaded so far, or
+    /// `None` if the table is not being bulk loaded.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn bulk_load_progress(
+        &mut self,
+        table: &str,
+    ) -> impl Future<Output = Result<Option<usize>, failure::Error>> {
+        let table = namespace::qualify(self.namespace.as_deref(), table);
+        self.rpc(
+            "bulk_load_progress",
+            table,
+            "failed to get bulk load progress",
+        )
+    }
+
+    /// Move the boundaries between the shards of the range-sharded base table `table`.
+    ///
+    /// Use this when the table's shards have become skewed; `noria::range_boundaries` picks new
+    /// boundaries from a sample of the sharding column's values. Rows that now belong to a
+    /// different shard are moved there, and are briefly missing from views over the table while
+    /// that happens. Writes to the table are held back meanwhile. `Table` and `View` handles
+    /// obtained before the re-split still route by the old boundaries, and must be obtained again.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn resplit(
+        &mut self,
+        table: &str,
+        boundaries: Vec<DataType>,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        let table = namespace::qualify(self.namespace.as_deref(), table);
+        self.rpc("resplit", (table, boundaries), "failed to re-split table")
+    }
+
+    /// Start changing the number of shards that the views of the dataflow are split into.
+    ///
+    /// Every view is copied under the new number of shards, and the copies are filled in the
+    /// background while the existing views keep serving reads, so no key goes missing during the
+    /// change. Writes reach both. The recipe cannot be changed until the resharding is finished
+    /// with [`ControllerHandle::finish_reshard`], or undone with
+    /// [`ControllerHandle::abort_reshard`].
+    ///
+    /// Only the views, and the state kept to maintain them, are resharded. Existing base tables,
+    /// and the rows they hold, stay on the shards they were created with, so `Table` handles stay
+    /// valid throughout, but resharding does not spread the writes to or the storage of an
+    /// existing table over more shards. Tables created after the change get the new number of
+    /// shards.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn start_reshard(
+        &mut self,
+        shards: usize,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("start_reshard", shards, "failed to start resharding")
+    }
+
+    /// Switch the views over to the copies made by [`ControllerHandle::start_reshard`].
+    ///
+    /// The views that were copied are removed. `View` handles obtained before this are redirected
+    /// to the copies the next time they read.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn finish_reshard(&mut self) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("finish_reshard", (), "failed to finish resharding")
+    }
+
+    /// Undo [`ControllerHandle::start_reshard`], removing the copies it made.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn abort_reshard(&mut self) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("abort_reshard", (), "failed to abort resharding")
+    }
+
+    /// Change the number of shards that the dataflow is split into, as
+    /// [`ControllerHandle::start_reshard`] followed by [`ControllerHandle::finish_reshard`].
+    pub async fn reshard(&mut self, shards: usize) -> Result<(), failure::Error> {
+        self.ready().await?;
+        self.start_reshard(shards).await?;
+        self.ready().await?;
+        self.finish_reshard().await
+    }
+
+    /// Apply the writes collected in `transaction`, so that views observe all of them at once.
+    ///
+    /// Every part of the transaction is checked by its client before any of it is written, and
+    /// then checked and set aside by its table. Only once every table has accepted its part does
+    /// the controller commit the transaction, and the tables apply their parts. A write that a
+    /// table rejects, such as an insert of a key that already exists, aborts the whole
+    /// transaction. Views that read from more than one of the tables involved hold back the
+    /// transaction's writes until all of them have arrived.
+    ///
+    /// Each table holds back its other writes while it has a part of the transaction set aside.
+    ///
+    /// [`TransactionError::is_retryable`] tells whether a failed transaction can safely be
+    /// committed again.
+    pub async fn commit(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<Committed, TransactionError> {
+        let mut parts = Vec::with_capacity(transaction.writes.len());
+        for (table, ops) in transaction.writes {
+            let input = table
+                .prepare_transaction(ops)
+                .map_err(TransactionError::ConstraintViolation)?;
+            parts.push((table, input));
+        }
+        let begin = BeginTransaction {
+            version: TRANSACTION_PROTOCOL,
+            bases: parts.iter().map(|&(ref t, _)| t.node()).collect(),
+        };
+
+        self.ready()
+            .await
+            .map_err(TransactionError::CoordinatorUnavailable)?;
+        let begun = self
+            .rpc("begin_transaction", begin, "failed to begin transaction")
+            .await
+            .map_err(TransactionError::CoordinatorUnavailable)?;
+        let timestamp = match begun {
+            TransactionBegun::Begun(timestamp) => timestamp,
+            TransactionBegun::Conflict(e) => return Err(TransactionError::Conflict(e)),
+            TransactionBegun::Rejected(e) => return Err(TransactionError::Rejected(e)),
+        };
+
+        let writes = future::join_all(parts.iter_mut().map(|&mut (ref mut table, ref input)| {
+            table.perform_transaction(input.clone(), timestamp)
+        }))
+        .await;
+        let mut token = WriteToken::default();
+        let mut failed = None;
+        for w in writes {
+            match w {
+                Ok(t) => token.merge(t),
+                Err(e) => failed = failed.or(Some(e)),
+            }
+        }
+
+        if let Some(e) = failed {
+            // the controller aborts transactions that are never finished, so failing to tell it
+            // only keeps the tables that accepted their parts waiting for longer
+            if self.ready().await.is_ok() {
+                let _ = self
+                    .rpc::<_, ()>(
+                        "abort_transaction",
+                        timestamp,
+                        "failed to abort transaction",
+                    )
+                    .await;
+            }
+            return Err(e.into());
+        }
+
+        self.ready()
+            .await
+            .map_err(TransactionError::CoordinatorUnavailable)?;
+        let decided = self
+            .rpc(
+                "commit_transaction",
+                timestamp,
+                "failed to commit transaction",
+            )
+            .await
+            .map_err(TransactionError::InDoubt)?;
+        match decided {
+            TransactionDecided::Committed => Ok(Committed { timestamp, token }),
+            TransactionDecided::Aborted(e) => Err(TransactionError::Aborted(failure::err_msg(e))),
+        }
+    }
+
+    /// Fetch the workers that the controller knows of, by the address they are identified by in
+    /// events and when decommissioning them.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn workers(&mut self) -> impl Future<Output = Result<Vec<SocketAddr>, failure::Error>> {
+        self.rpc("workers", (), "failed to get workers")
+    }
+
+    /// Start emptying `worker` so that it can be taken out of service.
+    ///
+    /// No new domains are placed on the worker from now on. In the background, the controller
+    /// moves the domains the worker runs to other workers one at a time: each is copied, along
+    /// with everything downstream of it, and the copies are filled while the existing domains keep
+    /// serving, before reads switch over to them. Use
+    /// [`ControllerHandle::decommission_status`] to find out when the worker is empty. A
+    /// controller that takes over keeps decommissioning the worker once it has registered again.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn decommission(
+        &mut self,
+        worker: SocketAddr,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("decommission", worker, "failed to decommission worker")
+    }
+
+    /// Stop decommissioning `worker`, and let new domains be placed on it again.
+    ///
+    /// Domains that have already been moved stay where they are now.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn abort_decommission(
+        &mut self,
+        worker: SocketAddr,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc(
+            "abort_decommission",
+            worker,
+            "failed to abort decommissioning worker",
+        )
+    }
+
+    /// Fetch how far along decommissioning `worker` is.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn decommission_status(
+        &mut self,
+        worker: SocketAddr,
+    ) -> impl Future<Output = Result<DecommissionStatus, failure::Error>> {
+        self.rpc(
+            "decommission_status",
+            worker,
+            "failed to get decommission status",
+        )
+    }
+
+    /// Move domains from the workers with the most load onto `worker`, as `rebalance` says.
+    ///
+    /// Returns the domains that were moved, or that would have been for a dry run.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn rebalance(
+        &mut self,
+        worker: SocketAddr,
+        rebalance: Rebalance,
+    ) -> impl Future<Output = Result<Vec<DomainMove>, failure::Error>> {
+        self.rpc(
+            "rebalance",
+            (worker, rebalance),
+            "failed to rebalance domains",
+        )
+    }
+
     /// Remove the given external view from the graph.
     ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
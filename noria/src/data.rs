@@ -2,16 +2,21 @@ use arccstr::ArcCStr;
 
 use chrono::{self, NaiveDate, NaiveDateTime};
 
-use nom_sql::Literal;
+use nom_sql::{ColumnConstraint, ColumnSpecification, Literal, SqlType};
 
+use std::borrow::Cow;
 use std::convert::TryFrom;
 use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::ops::{Add, Div, Mul, Sub};
+use std::ops::{Add, Bound, Div, Mul, Sub};
+use std::sync::Arc;
 
 const FLOAT_PRECISION: f64 = 1_000_000_000.0;
 const TINYTEXT_WIDTH: usize = 15;
 
+/// The most fractional digits a `DataType::Decimal` can have.
+pub const MAX_DECIMAL_SCALE: u8 = 18;
+
 /// The main type used for user data throughout the codebase.
 ///
 /// Having this be an enum allows for our code to be agnostic about the types of user data except
@@ -25,6 +30,9 @@ const TINYTEXT_WIDTH: usize = 15;
 pub enum DataType {
     /// An empty value.
     None,
+    /// A boolean. Booleans compare equal to the integers 0 and 1, which is how they used to be
+    /// represented.
+    Bool(bool),
     /// A signed 32-bit numeric value.
     Int(i32),
     /// An unsigned 32-bit numeric value.
@@ -33,6 +41,9 @@ pub enum DataType {
     BigInt(i64),
     /// An unsigned signed 64-bit numeric value.
     UnsignedBigInt(u64),
+    /// A signed 128-bit numeric value, for results of integer arithmetic and sums that do not fit
+    /// in 64 bits. It is kept out of line so that `DataType` stays small.
+    HugeInt(Box<i128>),
     /// A fixed point real value. The first field is the integer part, while the second is the
     /// fractional and must be between -999999999 and 999999999.
     Real(i64, i32),
@@ -40,15 +51,35 @@ pub enum DataType {
     Text(ArcCStr),
     /// A tiny string that fits in a pointer
     TinyText([u8; TINYTEXT_WIDTH]),
+    /// A string with a case-insensitive collation. It keeps its original form, but compares equal
+    /// to any string with the same Unicode case folding.
+    CiText(ArcCStr),
     /// A timestamp for date/time types.
     Timestamp(NaiveDateTime),
+    /// A calendar date without a time of day.
+    Date(NaiveDate),
+    /// A UUID. The 16 bytes are kept out of line so that `DataType` stays small.
+    Uuid(Box<[u8; 16]>),
+    /// A JSON document, kept as its canonical text: no whitespace, and object keys in order.
+    Json(ArcCStr),
+    /// A binary blob. The bytes are behind an extra pointer, since an `Arc<[u8]>` would double
+    /// the size of every `DataType`.
+    Bytes(#[serde(with = "bytes_serde")] Arc<Vec<u8>>),
+    /// An exact fixed point value. The first field is the mantissa, and the second is the number
+    /// of fractional digits (the scale), which is at most `MAX_DECIMAL_SCALE`. `Decimal(1234, 2)`
+    /// is `12.34`.
+    ///
+    /// Addition, subtraction, and multiplication of decimals and integers are exact. Results that
+    /// do not fit in the mantissa saturate at the largest or smallest representable value.
+    Decimal(i64, u8),
 }
 
 impl fmt::Display for DataType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
             DataType::None => write!(f, "NULL"),
-            DataType::Text(..) | DataType::TinyText(..) => {
+            DataType::Bool(b) => write!(f, "{}", if b { "TRUE" } else { "FALSE" }),
+            DataType::Text(..) | DataType::TinyText(..) | DataType::CiText(..) => {
                 let text: &str = self.into();
                 // TODO: do we really want to produce quoted strings?
                 write!(f, "\"{}\"", text)
@@ -57,6 +88,7 @@ impl fmt::Display for DataType {
             DataType::UnsignedInt(n) => write!(f, "{}", n),
             DataType::BigInt(n) => write!(f, "{}", n),
             DataType::UnsignedBigInt(n) => write!(f, "{}", n),
+            DataType::HugeInt(ref n) => write!(f, "{}", n),
             DataType::Real(i, frac) => {
                 if i == 0 && frac < 0 {
                     // We have to insert the negative sign ourselves.
@@ -66,6 +98,42 @@ impl fmt::Display for DataType {
                 }
             }
             DataType::Timestamp(ts) => write!(f, "{}", ts.format("%c")),
+            DataType::Date(d) => write!(f, "{}", d.format("%Y-%m-%d")),
+            DataType::Json(..) => {
+                let text: &str = self.into();
+                write!(f, "{}", text)
+            }
+            DataType::Bytes(ref b) => {
+                write!(f, "x'")?;
+                for byte in b.iter() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, "'")
+            }
+            DataType::Uuid(ref b) => {
+                for (i, byte) in b.iter().enumerate() {
+                    if i == 4 || i == 6 || i == 8 || i == 10 {
+                        write!(f, "-")?;
+                    }
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            DataType::Decimal(m, scale) => {
+                if scale == 0 {
+                    return write!(f, "{}", m);
+                }
+                let m = i128::from(m);
+                let p = pow10(scale);
+                write!(
+                    f,
+                    "{}{}.{:0width$}",
+                    if m < 0 { "-" } else { "" },
+                    m.abs() / p,
+                    m.abs() % p,
+                    width = scale as usize
+                )
+            }
         }
     }
 }
@@ -74,6 +142,7 @@ impl fmt::Debug for DataType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
             DataType::None => write!(f, "None"),
+            DataType::Bool(b) => write!(f, "Bool({})", b),
             DataType::Text(..) => {
                 let text: &str = self.into();
                 write!(f, "Text({:?})", text)
@@ -82,12 +151,22 @@ impl fmt::Debug for DataType {
                 let text: &str = self.into();
                 write!(f, "TinyText({:?})", text)
             }
+            DataType::CiText(..) => {
+                let text: &str = self.into();
+                write!(f, "CiText({:?})", text)
+            }
             DataType::Timestamp(ts) => write!(f, "Timestamp({:?})", ts),
+            DataType::Date(d) => write!(f, "Date({:?})", d),
+            DataType::Uuid(..) => write!(f, "Uuid({})", self),
+            DataType::Json(..) => write!(f, "Json({})", self),
+            DataType::Bytes(..) => write!(f, "Bytes({})", self),
             DataType::Real(..) => write!(f, "Real({})", self),
+            DataType::Decimal(..) => write!(f, "Decimal({})", self),
             DataType::Int(n) => write!(f, "Int({})", n),
             DataType::UnsignedInt(n) => write!(f, "UnsignedInt({})", n),
             DataType::BigInt(n) => write!(f, "BigInt({})", n),
             DataType::UnsignedBigInt(n) => write!(f, "UnsignedBigInt({})", n),
+            DataType::HugeInt(ref n) => write!(f, "HugeInt({})", n),
         }
     }
 }
@@ -100,6 +179,9 @@ impl DataType {
     pub fn deep_clone(&self) -> Self {
         match *self {
             DataType::Text(ref cstr) => DataType::Text(ArcCStr::from(&**cstr)),
+            DataType::Json(ref cstr) => DataType::Json(ArcCStr::from(&**cstr)),
+            DataType::CiText(ref cstr) => DataType::CiText(ArcCStr::from(&**cstr)),
+            DataType::Bytes(ref b) => DataType::Bytes(Arc::new(Vec::clone(b))),
             ref dt => dt.clone(),
         }
     }
@@ -112,6 +194,31 @@ impl DataType {
         }
     }
 
+    /// Checks if this value is a boolean.
+    pub fn is_bool(&self) -> bool {
+        match *self {
+            DataType::Bool(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this value satisfies a predicate, as a bare boolean column in a `WHERE` clause
+    /// would. Non-zero integers are true, as they are in MySQL; `NULL` and all non-numeric values
+    /// are not.
+    pub fn is_truthy(&self) -> bool {
+        match *self {
+            DataType::Bool(b) => b,
+            DataType::Int(..)
+            | DataType::UnsignedInt(..)
+            | DataType::BigInt(..)
+            | DataType::UnsignedBigInt(..)
+            | DataType::HugeInt(..) => i128::from(self) != 0,
+            DataType::Real(i, f) => i != 0 || f != 0,
+            DataType::Decimal(m, _) => m != 0,
+            _ => false,
+        }
+    }
+
     /// Checks if this value is of an integral data type (i.e., can be converted into integral types).
     pub fn is_integer(&self) -> bool {
         match *self {
@@ -128,11 +235,253 @@ impl DataType {
         }
     }
 
+    /// Checks if this value is of the date data type.
+    pub fn is_date(&self) -> bool {
+        match *self {
+            DataType::Date(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Parse a timestamp from one of the standard SQL literal forms: `YYYY-MM-DD HH:MM:SS`, with
+    /// optional fractional seconds, an optional `T` instead of the space, or just `YYYY-MM-DD` for
+    /// midnight of that day.
+    pub fn parse_datetime(s: &str) -> Result<Self, &'static str> {
+        let s = s.trim();
+        for format in &[
+            "%Y-%m-%d %H:%M:%S%.f",
+            "%Y-%m-%dT%H:%M:%S%.f",
+            "%Y-%m-%d %H:%M",
+            "%Y-%m-%dT%H:%M",
+        ] {
+            if let Ok(ts) = NaiveDateTime::parse_from_str(s, format) {
+                return Ok(DataType::Timestamp(ts));
+            }
+        }
+        NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .map(|d| DataType::Timestamp(d.and_hms(0, 0, 0)))
+            .map_err(|_| "invalid datetime literal")
+    }
+
+    /// Parse a date from the standard SQL literal form `YYYY-MM-DD`. A timestamp literal is also
+    /// accepted, and its time of day dropped.
+    pub fn parse_date(s: &str) -> Result<Self, &'static str> {
+        match NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d") {
+            Ok(d) => Ok(DataType::Date(d)),
+            Err(_) => match DataType::parse_datetime(s)? {
+                DataType::Timestamp(ts) => Ok(DataType::Date(ts.date())),
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    /// Checks if this value is of the UUID data type.
+    pub fn is_uuid(&self) -> bool {
+        match *self {
+            DataType::Uuid(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Parse a UUID from its canonical text form, such as
+    /// `67e55044-10b1-426f-9247-bb680e5fe0c8`. Upper case hex digits are also accepted.
+    pub fn parse_uuid(s: &str) -> Result<Self, &'static str> {
+        let s = s.as_bytes();
+        if s.len() != 36 {
+            return Err("invalid UUID length");
+        }
+
+        let mut bytes = [0u8; 16];
+        let mut digits = Vec::with_capacity(32);
+        for (i, &c) in s.iter().enumerate() {
+            if i == 8 || i == 13 || i == 18 || i == 23 {
+                if c != b'-' {
+                    return Err("invalid UUID separator");
+                }
+                continue;
+            }
+            let d = match c {
+                b'0'..=b'9' => c - b'0',
+                b'a'..=b'f' => c - b'a' + 10,
+                b'A'..=b'F' => c - b'A' + 10,
+                _ => return Err("invalid UUID digit"),
+            };
+            digits.push(d);
+        }
+        for (i, pair) in digits.chunks(2).enumerate() {
+            bytes[i] = pair[0] << 4 | pair[1];
+        }
+        Ok(DataType::Uuid(Box::new(bytes)))
+    }
+
+    /// Convert a value written to, or looked up in, the column with the given specification into
+    /// the form that the column holds its values in.
+    ///
+    /// Values for UUID columns, which are declared as `BINARY(16)`, may be given in the canonical
+    /// text form or as 16 raw bytes, and are turned into [`DataType::Uuid`]. Any other value fails
+    /// to convert for such a column, and is left as it was. Strings for columns with a
+    /// case-insensitive collation are collated, so that they shard like the column's values.
+    pub fn coerce_to_column(&mut self, spec: &ColumnSpecification) -> Result<(), &'static str> {
+        if is_case_insensitive_column(spec) {
+            if self.is_string() && !self.is_case_insensitive() {
+                *self = DataType::case_insensitive((&*self).into());
+            }
+            return Ok(());
+        }
+        if is_uuid_column(spec) {
+            let uuid = match *self {
+                DataType::Uuid(..) | DataType::None => return Ok(()),
+                DataType::Text(..) | DataType::TinyText(..) | DataType::CiText(..) => {
+                    DataType::parse_uuid((&*self).into())?
+                }
+                DataType::Bytes(ref b) => match <[u8; 16]>::try_from(&b[..]) {
+                    Ok(b) => DataType::from(b),
+                    Err(_) => return Err("invalid UUID length"),
+                },
+                _ => return Err("not a UUID"),
+            };
+            *self = uuid;
+        }
+        Ok(())
+    }
+
+    /// Checks if this value is a binary blob.
+    pub fn is_bytes(&self) -> bool {
+        match *self {
+            DataType::Bytes(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Checks if this value is a JSON document.
+    pub fn is_json(&self) -> bool {
+        match *self {
+            DataType::Json(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Make a string with a case-insensitive collation.
+    pub fn case_insensitive(s: &str) -> Self {
+        DataType::CiText(ArcCStr::from(s))
+    }
+
+    /// Checks if this value is a string with a case-insensitive collation.
+    pub fn is_case_insensitive(&self) -> bool {
+        match *self {
+            DataType::CiText(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Parse a JSON document, and keep it in its canonical form so that equal documents compare
+    /// and hash the same.
+    pub fn parse_json(s: &str) -> Result<Self, serde_json::Error> {
+        let value: serde_json::Value = serde_json::from_str(s)?;
+        Ok(DataType::Json(ArcCStr::from(&*value.to_string())))
+    }
+
+    /// Checks if this value is of the exact decimal data type.
+    pub fn is_decimal(&self) -> bool {
+        match *self {
+            DataType::Decimal(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Construct the decimal value `mantissa * 10^-scale`.
+    pub fn decimal(mantissa: i64, scale: u8) -> Self {
+        assert!(
+            scale <= MAX_DECIMAL_SCALE,
+            "decimal scale {} is larger than {}",
+            scale,
+            MAX_DECIMAL_SCALE
+        );
+        DataType::Decimal(mantissa, scale)
+    }
+
+    /// Construct the decimal value `mantissa * 10^-scale`, saturating if the mantissa does not
+    /// fit.
+    pub fn decimal_saturating(mantissa: i128, scale: u8) -> Self {
+        let m = mantissa
+            .max(i128::from(std::i64::MIN))
+            .min(i128::from(std::i64::MAX));
+        DataType::decimal(m as i64, scale)
+    }
+
+    /// Parse a decimal from its text form, such as `-12.340`. The decimal keeps every fractional
+    /// digit that is given.
+    pub fn parse_decimal(s: &str) -> Result<Self, &'static str> {
+        let s = s.trim();
+        let (negative, digits) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (int, frac) = match digits.find('.') {
+            Some(i) => (&digits[..i], &digits[i + 1..]),
+            None => (digits, ""),
+        };
+        if int.is_empty() && frac.is_empty() {
+            return Err("empty decimal");
+        }
+        if !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+            return Err("invalid decimal");
+        }
+        if frac.len() > MAX_DECIMAL_SCALE as usize {
+            return Err("decimal has too many fractional digits");
+        }
+
+        let mut m: i128 = 0;
+        for b in int.bytes().chain(frac.bytes()) {
+            m = m * 10 + i128::from(b - b'0');
+            if m > i128::from(std::i64::MAX) {
+                return Err("decimal is out of range");
+            }
+        }
+        let m = if negative { -m } else { m };
+        Ok(DataType::Decimal(m as i64, frac.len() as u8))
+    }
+
+    /// Convert a numeric value into a decimal with the given scale, rounding half away from zero.
+    ///
+    /// This is how literals meet decimal columns: integers and reals are converted exactly where
+    /// possible, and strings are parsed as decimals.
+    pub fn to_decimal(&self, scale: u8) -> Result<Self, &'static str> {
+        if scale > MAX_DECIMAL_SCALE {
+            return Err("decimal scale is too large");
+        }
+        let (m, from) = match *self {
+            DataType::None => return Ok(DataType::None),
+            DataType::Text(..) | DataType::TinyText(..) => {
+                return DataType::parse_decimal(self.into())?.to_decimal(scale)
+            }
+            DataType::Real(i, f) => (i128::from(i) * 1_000_000_000 + i128::from(f), 9),
+            ref d => exact(d).ok_or("value cannot be converted to a decimal")?,
+        };
+
+        let m = if scale >= from {
+            m * pow10(scale - from)
+        } else {
+            let p = pow10(from - scale);
+            let (q, r) = (m / p, m % p);
+            if r.abs() * 2 >= p {
+                q + m.signum()
+            } else {
+                q
+            }
+        };
+        if m < i128::from(std::i64::MIN) || m > i128::from(std::i64::MAX) {
+            return Err("decimal is out of range");
+        }
+        Ok(DataType::Decimal(m as i64, scale))
+    }
+
     /// Checks if this value is of a string data type (i.e., can be converted into `String` and
     /// `&str`).
     pub fn is_string(&self) -> bool {
         match *self {
-            DataType::Text(_) | DataType::TinyText(_) => true,
+            DataType::Text(_) | DataType::TinyText(_) | DataType::CiText(_) => true,
             _ => false,
         }
     }
@@ -146,6 +495,165 @@ impl DataType {
     }
 }
 
+/// The Unicode case folding of a string, which is what case-insensitive strings are compared by.
+///
+/// All strings hash by their folding, so that a case-insensitive string hashes the same as the
+/// strings it is equal to. ASCII strings without upper case letters are their own folding, which
+/// keeps the common case free of allocations.
+pub(crate) fn case_fold(s: &str) -> Cow<'_, str> {
+    if s.is_ascii() {
+        if s.bytes().any(|b| b.is_ascii_uppercase()) {
+            Cow::Owned(s.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(s)
+        }
+    } else {
+        Cow::Owned(caseless::default_case_fold_str(s))
+    }
+}
+
+fn pow10(n: u8) -> i128 {
+    10i128.pow(u32::from(n))
+}
+
+/// The exact value of a decimal or integer, as a mantissa and a scale.
+fn exact(d: &DataType) -> Option<(i128, u8)> {
+    match *d {
+        DataType::Decimal(m, scale) => Some((i128::from(m), scale)),
+        DataType::Bool(b) => Some((i128::from(b), 0)),
+        DataType::Int(..)
+        | DataType::UnsignedInt(..)
+        | DataType::BigInt(..)
+        | DataType::UnsignedBigInt(..)
+        | DataType::HugeInt(..) => Some((d.into(), 0)),
+        _ => None,
+    }
+}
+
+/// The mantissas of two values at the same scale, if at least one is a decimal and the other is a
+/// decimal or an integer.
+fn exact_operands(a: &DataType, b: &DataType) -> Option<(i128, i128, u8)> {
+    if !a.is_decimal() && !b.is_decimal() {
+        return None;
+    }
+    let (am, ascale) = exact(a)?;
+    let (bm, bscale) = exact(b)?;
+    let scale = std::cmp::max(ascale, bscale);
+    Some((
+        am * pow10(scale - ascale),
+        bm * pow10(scale - bscale),
+        scale,
+    ))
+}
+
+/// Compare a decimal with an integer, a real, or another decimal by value.
+///
+/// Integers are compared exactly. So are reals, since they have a fixed nine fractional digits,
+/// but a real is never equal to a decimal: when the values are the same, the decimal orders after
+/// the real, just as decimals and reals never compare equal.
+fn decimal_cmp(a: &DataType, b: &DataType) -> Option<Ordering> {
+    if let Some((a, b, _)) = exact_operands(a, b) {
+        return Some(a.cmp(&b));
+    }
+    let as_real = |d: &DataType| match *d {
+        DataType::Real(i, f) => Some(i128::from(i) * 1_000_000_000 + i128::from(f)),
+        _ => None,
+    };
+    match (a, b) {
+        (&DataType::Decimal(m, scale), r @ &DataType::Real(..)) => {
+            let r = as_real(r)?;
+            let (m, r) = if scale >= 9 {
+                (i128::from(m), r * pow10(scale - 9))
+            } else {
+                (i128::from(m) * pow10(9 - scale), r)
+            };
+            Some(m.cmp(&r).then(Ordering::Greater))
+        }
+        (&DataType::Real(..), &DataType::Decimal(..)) => decimal_cmp(b, a).map(Ordering::reverse),
+        _ => None,
+    }
+}
+
+/// Blobs are sent as raw bytes on channels, and as base64 text in human-readable formats like JSON.
+mod bytes_serde {
+    use serde::de::{self, Deserializer, SeqAccess, Visitor};
+    use serde::Serializer;
+    use std::fmt;
+    use std::sync::Arc;
+
+    pub(super) fn serialize<S: Serializer>(b: &Arc<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+        if s.is_human_readable() {
+            s.serialize_str(&base64::encode(&***b))
+        } else {
+            s.serialize_bytes(&***b)
+        }
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "a byte array or a base64 string")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            base64::decode(v).map_err(E::custom)
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+            Ok(v)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut v = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(b) = seq.next_element()? {
+                v.push(b);
+            }
+            Ok(v)
+        }
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Arc<Vec<u8>>, D::Error> {
+        if d.is_human_readable() {
+            d.deserialize_str(BytesVisitor).map(Arc::new)
+        } else {
+            d.deserialize_byte_buf(BytesVisitor).map(Arc::new)
+        }
+    }
+}
+
+/// Whether a value is one of the integer types, which booleans compare equal to.
+fn is_integral(d: &DataType) -> bool {
+    match *d {
+        DataType::Int(..)
+        | DataType::UnsignedInt(..)
+        | DataType::BigInt(..)
+        | DataType::UnsignedBigInt(..)
+        | DataType::HugeInt(..) => true,
+        _ => false,
+    }
+}
+
+/// Whether the column with the given specification holds UUIDs.
+pub fn is_uuid_column(spec: &ColumnSpecification) -> bool {
+    spec.sql_type == SqlType::Binary(16)
+}
+
+/// Whether the column with the given specification has a case-insensitive collation.
+pub fn is_case_insensitive_column(spec: &ColumnSpecification) -> bool {
+    // MySQL's case-insensitive collations all end in `_ci`, as in `utf8mb4_general_ci`
+    spec.constraints.iter().any(|c| match *c {
+        ColumnConstraint::Collation(ref collation) => collation.to_lowercase().ends_with("_ci"),
+        _ => false,
+    })
+}
+
 impl PartialEq for DataType {
     fn eq(&self, other: &DataType) -> bool {
         unsafe {
@@ -169,6 +677,11 @@ impl PartialEq for DataType {
                 let b: &str = other.into();
                 a == b
             }
+            (&DataType::CiText(..), b) | (b, &DataType::CiText(..)) if b.is_string() => {
+                let a: &str = self.into();
+                let b: &str = other.into();
+                case_fold(a) == case_fold(b)
+            }
             (&DataType::BigInt(a), &DataType::BigInt(b)) => a == b,
             (&DataType::UnsignedBigInt(a), &DataType::UnsignedBigInt(b)) => a == b,
             (&DataType::Int(a), &DataType::Int(b)) => a == b,
@@ -189,9 +702,29 @@ impl PartialEq for DataType {
                 let b: i128 = other.into();
                 a == b
             }
+            (&DataType::HugeInt(..), b) | (b, &DataType::HugeInt(..)) if is_integral(b) => {
+                let a: i128 = self.into();
+                let b: i128 = other.into();
+                a == b
+            }
+            (&DataType::Bool(a), &DataType::Bool(b)) => a == b,
+            (&DataType::Bool(a), b) | (b, &DataType::Bool(a)) if is_integral(b) => {
+                let b: i128 = b.into();
+                i128::from(a) == b
+            }
             (&DataType::Real(ai, af), &DataType::Real(bi, bf)) => ai == bi && af == bf,
             (&DataType::Timestamp(tsa), &DataType::Timestamp(tsb)) => tsa == tsb,
+            (&DataType::Date(a), &DataType::Date(b)) => a == b,
+            (&DataType::Uuid(ref a), &DataType::Uuid(ref b)) => a == b,
+            (&DataType::Json(ref a), &DataType::Json(ref b)) => a == b,
+            (&DataType::Bytes(ref a), &DataType::Bytes(ref b)) => a == b,
             (&DataType::None, &DataType::None) => true,
+            (&DataType::Decimal(..), _) | (_, &DataType::Decimal(..)) => {
+                match exact_operands(self, other) {
+                    Some((a, b, _)) => a == b,
+                    None => false,
+                }
+            }
 
             _ => false,
         }
@@ -207,6 +740,10 @@ impl PartialOrd for DataType {
 
 impl Ord for DataType {
     fn cmp(&self, other: &DataType) -> Ordering {
+        if let Some(o) = decimal_cmp(self, other) {
+            return o;
+        }
+
         match (self, other) {
             (&DataType::Text(ref a), &DataType::Text(ref b)) => a.cmp(b),
             (&DataType::TinyText(ref a), &DataType::TinyText(ref b)) => a.cmp(b),
@@ -216,6 +753,11 @@ impl Ord for DataType {
                 let b: &str = other.into();
                 a.cmp(&b)
             }
+            (&DataType::CiText(..), b) | (b, &DataType::CiText(..)) if b.is_string() => {
+                let a: &str = self.into();
+                let b: &str = other.into();
+                case_fold(a).cmp(&case_fold(b))
+            }
             (&DataType::BigInt(a), &DataType::BigInt(ref b)) => a.cmp(b),
             (&DataType::UnsignedBigInt(a), &DataType::UnsignedBigInt(ref b)) => a.cmp(b),
             (&DataType::Int(a), &DataType::Int(b)) => a.cmp(&b),
@@ -236,20 +778,53 @@ impl Ord for DataType {
                 let b: i128 = other.into();
                 a.cmp(&b)
             }
+            (&DataType::HugeInt(..), b) | (b, &DataType::HugeInt(..)) if is_integral(b) => {
+                let a: i128 = self.into();
+                let b: i128 = other.into();
+                a.cmp(&b)
+            }
+            (&DataType::Bool(a), &DataType::Bool(ref b)) => a.cmp(b),
+            (&DataType::Bool(a), b) if is_integral(b) => i128::from(a).cmp(&b.into()),
+            (a, &DataType::Bool(b)) if is_integral(a) => i128::from(a).cmp(&b.into()),
             (&DataType::Real(ai, af), &DataType::Real(ref bi, ref bf)) => {
                 ai.cmp(bi).then_with(|| af.cmp(bf))
             }
             (&DataType::Timestamp(tsa), &DataType::Timestamp(ref tsb)) => tsa.cmp(tsb),
+            (&DataType::Date(a), &DataType::Date(ref b)) => a.cmp(b),
+            (&DataType::Uuid(ref a), &DataType::Uuid(ref b)) => a.cmp(b),
+            (&DataType::Json(ref a), &DataType::Json(ref b)) => a.cmp(b),
+            (&DataType::Bytes(ref a), &DataType::Bytes(ref b)) => a.cmp(b),
+            // a UUID is never equal to a string, since clients and the planner turn strings given
+            // for UUID columns into UUIDs, and orders after all of them
+            (&DataType::Uuid(..), b) if b.is_string() => Ordering::Greater,
+            (a, &DataType::Uuid(..)) if a.is_string() => Ordering::Less,
+            // a date orders as midnight of that day, but before a timestamp at midnight, since
+            // the two are never equal.
+            (&DataType::Date(a), &DataType::Timestamp(ref ts)) => {
+                a.and_hms(0, 0, 0).cmp(ts).then(Ordering::Less)
+            }
+            (&DataType::Timestamp(ts), &DataType::Date(b)) => {
+                ts.cmp(&b.and_hms(0, 0, 0)).then(Ordering::Greater)
+            }
             (&DataType::None, &DataType::None) => Ordering::Equal,
 
             // order Ints, Reals, Text, Timestamps, None
-            (&DataType::Int(..), _)
+            (&DataType::Bool(..), _)
+            | (&DataType::Int(..), _)
             | (&DataType::UnsignedInt(..), _)
             | (&DataType::BigInt(..), _)
-            | (&DataType::UnsignedBigInt(..), _) => Ordering::Greater,
+            | (&DataType::UnsignedBigInt(..), _)
+            | (&DataType::HugeInt(..), _) => Ordering::Greater,
             (&DataType::Real(..), _) => Ordering::Greater,
-            (&DataType::Text(..), _) | (&DataType::TinyText(..), _) => Ordering::Greater,
+            (&DataType::Decimal(..), _) => Ordering::Greater,
+            (&DataType::Text(..), _)
+            | (&DataType::TinyText(..), _)
+            | (&DataType::CiText(..), _) => Ordering::Greater,
             (&DataType::Timestamp(..), _) => Ordering::Greater,
+            (&DataType::Date(..), _) => Ordering::Greater,
+            (&DataType::Uuid(..), _) => Ordering::Greater,
+            (&DataType::Json(..), _) => Ordering::Greater,
+            (&DataType::Bytes(..), _) => Ordering::Greater,
             (&DataType::None, _) => Ordering::Greater,
         }
     }
@@ -262,6 +837,8 @@ impl Hash for DataType {
         // collisions, but the decreased overhead is worth it.
         match *self {
             DataType::None => {}
+            // booleans hash like the integers they are equal to
+            DataType::Bool(b) => i64::from(b).hash(state),
             DataType::Int(..) | DataType::BigInt(..) => {
                 let n: i64 = self.into();
                 n.hash(state)
@@ -270,15 +847,44 @@ impl Hash for DataType {
                 let n: u64 = self.into();
                 n.hash(state)
             }
+            // hash like the 64-bit integers it may be equal to
+            DataType::HugeInt(ref n) => {
+                if let Ok(n) = i64::try_from(**n) {
+                    n.hash(state)
+                } else if let Ok(n) = u64::try_from(**n) {
+                    n.hash(state)
+                } else {
+                    n.hash(state)
+                }
+            }
             DataType::Real(i, f) => {
                 i.hash(state);
                 f.hash(state);
             }
-            DataType::Text(..) | DataType::TinyText(..) => {
+            DataType::Text(..) | DataType::TinyText(..) | DataType::CiText(..) => {
+                let t: &str = self.into();
+                case_fold(t).hash(state)
+            }
+            DataType::Json(..) => {
                 let t: &str = self.into();
                 t.hash(state)
             }
             DataType::Timestamp(ts) => ts.hash(state),
+            DataType::Date(d) => d.hash(state),
+            DataType::Uuid(ref b) => b.hash(state),
+            DataType::Bytes(ref b) => b.hash(state),
+            DataType::Decimal(mut m, mut scale) => {
+                // equal decimals hash the same regardless of scale, and integral ones hash like
+                // the integers they are equal to.
+                while scale > 0 && m % 10 == 0 {
+                    m /= 10;
+                    scale -= 1;
+                }
+                m.hash(state);
+                if scale > 0 {
+                    scale.hash(state);
+                }
+            }
         }
     }
 }
@@ -295,12 +901,18 @@ where
     }
 }
 
+impl From<bool> for DataType {
+    fn from(b: bool) -> Self {
+        DataType::Bool(b)
+    }
+}
+
 impl From<i128> for DataType {
     fn from(s: i128) -> Self {
         if s >= std::i64::MIN.into() && s <= std::i64::MAX.into() {
             DataType::BigInt(s as i64)
         } else {
-            panic!("can't fit {} in a DataType::BigInt", s)
+            DataType::HugeInt(Box::new(s))
         }
     }
 }
@@ -397,6 +1009,24 @@ impl From<NaiveDateTime> for DataType {
     }
 }
 
+impl From<Vec<u8>> for DataType {
+    fn from(b: Vec<u8>) -> Self {
+        DataType::Bytes(Arc::new(b))
+    }
+}
+
+impl From<[u8; 16]> for DataType {
+    fn from(b: [u8; 16]) -> Self {
+        DataType::Uuid(Box::new(b))
+    }
+}
+
+impl From<NaiveDate> for DataType {
+    fn from(d: NaiveDate) -> Self {
+        DataType::Date(d)
+    }
+}
+
 // This conversion has many unwraps, but all of them are expected to be safe,
 // because DataType variants (i.e. `Text` and `TinyText`) constructors are all
 // generated from valid UTF-8 strings, or the constructor fails (e.g. TryFrom &[u8]).
@@ -404,7 +1034,9 @@ impl From<NaiveDateTime> for DataType {
 impl<'a> From<&'a DataType> for &'a str {
     fn from(data: &'a DataType) -> Self {
         match *data {
-            DataType::Text(ref s) => s.to_str().unwrap(),
+            DataType::Text(ref s) | DataType::Json(ref s) | DataType::CiText(ref s) => {
+                s.to_str().unwrap()
+            }
             DataType::TinyText(ref bts) => {
                 if bts[TINYTEXT_WIDTH - 1] == 0 {
                     // NULL terminated CStr
@@ -443,6 +1075,8 @@ impl From<&'_ DataType> for i128 {
             DataType::UnsignedBigInt(s) => i128::from(s),
             DataType::Int(s) => i128::from(s),
             DataType::UnsignedInt(s) => i128::from(s),
+            DataType::HugeInt(ref s) => **s,
+            DataType::Bool(b) => i128::from(b),
             _ => panic!("attempted to convert a {:?} to an i128", data),
         }
     }
@@ -454,6 +1088,7 @@ impl From<&'_ DataType> for i64 {
             DataType::BigInt(s) => s,
             DataType::Int(s) => i64::from(s),
             DataType::UnsignedInt(s) => i64::from(s),
+            DataType::Bool(b) => i64::from(b),
             _ => panic!("attempted to convert a {:?} to an i64", data),
         }
     }
@@ -470,6 +1105,7 @@ impl From<&'_ DataType> for u64 {
         match *data {
             DataType::UnsignedBigInt(s) => s,
             DataType::UnsignedInt(s) => u64::from(s),
+            DataType::Bool(b) => u64::from(b),
             _ => panic!("attempted to convert a {:?} to a u64", data),
         }
     }
@@ -519,6 +1155,8 @@ impl From<&'_ DataType> for f64 {
             DataType::Real(i, f) => i as f64 + f64::from(f) / FLOAT_PRECISION,
             DataType::Int(i) => f64::from(i),
             DataType::BigInt(i) => i as f64,
+            DataType::HugeInt(ref i) => **i as f64,
+            DataType::Decimal(m, scale) => m as f64 / 10f64.powi(i32::from(scale)),
             _ => panic!("attempted to convert a {:?} to an f64", data),
         }
     }
@@ -589,22 +1227,20 @@ impl TryFrom<mysql_common::value::Value> for DataType {
 // Performs an arithmetic operation on two numeric DataTypes,
 // returning a new DataType as the result.
 macro_rules! arithmetic_operation (
-    ($op:tt, $first:ident, $second:ident) => (
+    ($op:tt, $checked:ident, $first:ident, $second:ident) => (
         match ($first, $second) {
             (&DataType::None, _) | (_, &DataType::None) => DataType::None,
-            (&DataType::Int(a), &DataType::Int(b)) => (a $op b).into(),
-            (&DataType::UnsignedInt(a), &DataType::UnsignedInt(b)) => (a $op b).into(),
-            (&DataType::BigInt(a), &DataType::BigInt(b)) => (a $op b).into(),
-            (&DataType::UnsignedBigInt(a), &DataType::UnsignedBigInt(b)) => (a $op b).into(),
-
-            (&DataType::Int(a), &DataType::BigInt(b)) => (i64::from(a) $op b).into(),
-            (&DataType::BigInt(a), &DataType::Int(b)) => (a $op i64::from(b)).into(),
-            (&DataType::Int(a), &DataType::UnsignedBigInt(b)) => (i128::from(a) $op i128::from(b)).into(),
-            (&DataType::UnsignedBigInt(a), &DataType::Int(b)) => (i128::from(a) $op i128::from(b)).into(),
-            (&DataType::BigInt(a), &DataType::UnsignedBigInt(b)) => (i128::from(a) $op i128::from(b)).into(),
-            (&DataType::UnsignedBigInt(a), &DataType::BigInt(b)) => (i128::from(a) $op i128::from(b)).into(),
-            (&DataType::UnsignedBigInt(a), &DataType::UnsignedInt(b)) => (a $op u64::from(b)).into(),
-            (&DataType::UnsignedInt(a), &DataType::UnsignedBigInt(b)) => (u64::from(a) $op b).into(),
+            // booleans take part in arithmetic as 0 or 1
+            (&DataType::Bool(a), second) => &DataType::Int(i32::from(a)) $op second,
+            (first, &DataType::Bool(b)) => first $op &DataType::Int(i32::from(b)),
+            // integers are computed with 128 bits, so that results that do not fit in 64 bits
+            // widen to a `HugeInt` rather than overflow. Results that do not even fit in 128
+            // bits, and division by zero, are NULL.
+            (first, second) if is_integral(first) && is_integral(second) => {
+                let a: i128 = first.into();
+                let b: i128 = second.into();
+                a.$checked(b).map(DataType::from).unwrap_or(DataType::None)
+            }
 
             (first @ &DataType::Int(..), second @ &DataType::Real(..)) |
             (first @ &DataType::BigInt(..), second @ &DataType::Real(..)) |
@@ -614,7 +1250,22 @@ macro_rules! arithmetic_operation (
             (first @ &DataType::Real(..), second @ &DataType::BigInt(..)) |
             (first @ &DataType::Real(..), second @ &DataType::UnsignedInt(..)) |
             (first @ &DataType::Real(..), second @ &DataType::UnsignedBigInt(..)) |
-            (first @ &DataType::Real(..), second @ &DataType::Real(..)) => {
+            (first @ &DataType::Real(..), second @ &DataType::Real(..)) |
+            (first @ &DataType::Decimal(..), second @ &DataType::Real(..)) |
+            (first @ &DataType::Real(..), second @ &DataType::Decimal(..)) |
+            (first @ &DataType::Decimal(..), second @ &DataType::Int(..)) |
+            (first @ &DataType::Decimal(..), second @ &DataType::BigInt(..)) |
+            (first @ &DataType::Decimal(..), second @ &DataType::UnsignedInt(..)) |
+            (first @ &DataType::Decimal(..), second @ &DataType::UnsignedBigInt(..)) |
+            (first @ &DataType::Int(..), second @ &DataType::Decimal(..)) |
+            (first @ &DataType::BigInt(..), second @ &DataType::Decimal(..)) |
+            (first @ &DataType::UnsignedInt(..), second @ &DataType::Decimal(..)) |
+            (first @ &DataType::UnsignedBigInt(..), second @ &DataType::Decimal(..)) |
+            (first @ &DataType::HugeInt(..), second @ &DataType::Real(..)) |
+            (first @ &DataType::Real(..), second @ &DataType::HugeInt(..)) |
+            (first @ &DataType::HugeInt(..), second @ &DataType::Decimal(..)) |
+            (first @ &DataType::Decimal(..), second @ &DataType::HugeInt(..)) |
+            (first @ &DataType::Decimal(..), second @ &DataType::Decimal(..)) => {
                 let a: f64 = first.into();
                 let b: f64 = second.into();
                 (a $op b).into()
@@ -635,7 +1286,10 @@ impl<'a, 'b> Add<&'b DataType> for &'a DataType {
     type Output = DataType;
 
     fn add(self, other: &'b DataType) -> DataType {
-        arithmetic_operation!(+, self, other)
+        if let Some((a, b, scale)) = exact_operands(self, other) {
+            return DataType::decimal_saturating(a + b, scale);
+        }
+        arithmetic_operation!(+, checked_add, self, other)
     }
 }
 
@@ -643,7 +1297,10 @@ impl<'a, 'b> Sub<&'b DataType> for &'a DataType {
     type Output = DataType;
 
     fn sub(self, other: &'b DataType) -> DataType {
-        arithmetic_operation!(-, self, other)
+        if let Some((a, b, scale)) = exact_operands(self, other) {
+            return DataType::decimal_saturating(a - b, scale);
+        }
+        arithmetic_operation!(-, checked_sub, self, other)
     }
 }
 
@@ -651,7 +1308,14 @@ impl<'a, 'b> Mul<&'b DataType> for &'a DataType {
     type Output = DataType;
 
     fn mul(self, other: &'b DataType) -> DataType {
-        arithmetic_operation!(*, self, other)
+        if self.is_decimal() || other.is_decimal() {
+            if let (Some((a, ascale)), Some((b, bscale))) = (exact(self), exact(other)) {
+                if ascale + bscale <= MAX_DECIMAL_SCALE {
+                    return DataType::decimal_saturating(a.saturating_mul(b), ascale + bscale);
+                }
+            }
+        }
+        arithmetic_operation!(*, checked_mul, self, other)
     }
 }
 
@@ -659,7 +1323,7 @@ impl<'a, 'b> Div<&'b DataType> for &'a DataType {
     type Output = DataType;
 
     fn div(self, other: &'b DataType) -> DataType {
-        arithmetic_operation!(/, self, other)
+        arithmetic_operation!(/, checked_div, self, other)
     }
 }
 
@@ -710,6 +1374,8 @@ pub enum TableOperation {
         /// These modifications will be applied to the columns of an existing row.
         update: Vec<Modification>,
     },
+    /// Insert the contained row, replacing any existing row with the same key.
+    Upsert(Vec<DataType>),
     /// Update an existing row with the given `key`.
     Update {
         /// The modifications to make to each column of the existing row.
@@ -717,6 +1383,43 @@ pub enum TableOperation {
         /// The key used to identify the row to update.
         key: Vec<DataType>,
     },
+    /// Update an existing row with the given `key`, but only if the row holds the expected value
+    /// in each of the given columns.
+    ConditionalUpdate {
+        /// The key used to identify the row to update.
+        key: Vec<DataType>,
+        /// The columns to check, and the value each must hold for the update to apply.
+        expected: Vec<(usize, DataType)>,
+        /// The modifications to make to each column of the existing row.
+        set: Vec<Modification>,
+    },
+    /// Delete every row that meets all of the given conditions.
+    DeleteWhere {
+        /// The columns to check, and the condition each must meet for the row to be deleted.
+        conditions: Vec<(usize, ColumnCondition)>,
+        /// The most rows to delete from each shard in this operation.
+        limit: usize,
+    },
+}
+
+/// A condition on one column of a row, as used by [`TableOperation::DeleteWhere`].
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum ColumnCondition {
+    /// The column holds the given value.
+    Equal(DataType),
+    /// The column holds a value in the given range.
+    InRange(KeyRange),
+}
+
+impl ColumnCondition {
+    /// Whether the given value meets this condition.
+    pub fn matches(&self, v: &DataType) -> bool {
+        use std::ops::RangeBounds;
+        match *self {
+            ColumnCondition::Equal(ref e) => v == e,
+            ColumnCondition::InRange(ref range) => range.bounds().contains(v),
+        }
+    }
 }
 
 impl TableOperation {
@@ -725,6 +1428,7 @@ impl TableOperation {
         match *self {
             TableOperation::Insert(ref r) => Some(r),
             TableOperation::InsertOrUpdate { ref row, .. } => Some(row),
+            TableOperation::Upsert(ref r) => Some(r),
             _ => None,
         }
     }
@@ -736,10 +1440,80 @@ impl From<Vec<DataType>> for TableOperation {
     }
 }
 
+/// A range of values to look up in a view that is parameterized by a range placeholder (e.g.,
+/// `WHERE created_at > ?`).
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum KeyRange {
+    /// All values strictly greater than the given one.
+    Greater(DataType),
+    /// All values greater than or equal to the given one.
+    GreaterOrEqual(DataType),
+    /// All values strictly less than the given one.
+    Less(DataType),
+    /// All values less than or equal to the given one.
+    LessOrEqual(DataType),
+    /// All values between the two given ones, inclusive.
+    Between(DataType, DataType),
+}
+
+impl KeyRange {
+    /// The lower and upper bounds of this range.
+    pub fn bounds(&self) -> (Bound<&DataType>, Bound<&DataType>) {
+        match *self {
+            KeyRange::Greater(ref v) => (Bound::Excluded(v), Bound::Unbounded),
+            KeyRange::GreaterOrEqual(ref v) => (Bound::Included(v), Bound::Unbounded),
+            KeyRange::Less(ref v) => (Bound::Unbounded, Bound::Excluded(v)),
+            KeyRange::LessOrEqual(ref v) => (Bound::Unbounded, Bound::Included(v)),
+            KeyRange::Between(ref lo, ref hi) => (Bound::Included(lo), Bound::Included(hi)),
+        }
+    }
+
+    /// Whether `v` falls within this range.
+    pub fn contains(&self, v: &DataType) -> bool {
+        match *self {
+            KeyRange::Greater(ref lo) => v > lo,
+            KeyRange::GreaterOrEqual(ref lo) => v >= lo,
+            KeyRange::Less(ref hi) => v < hi,
+            KeyRange::LessOrEqual(ref hi) => v <= hi,
+            KeyRange::Between(ref lo, ref hi) => v >= lo && v <= hi,
+        }
+    }
+
+    /// The values that bound this range.
+    pub(crate) fn values_mut(&mut self) -> Vec<&mut DataType> {
+        match *self {
+            KeyRange::Greater(ref mut v)
+            | KeyRange::GreaterOrEqual(ref mut v)
+            | KeyRange::Less(ref mut v)
+            | KeyRange::LessOrEqual(ref mut v) => vec![v],
+            KeyRange::Between(ref mut lo, ref mut hi) => vec![lo, hi],
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn key_range_contains() {
+        let one = DataType::from(1);
+        let two = DataType::from(2);
+        let three = DataType::from(3);
+
+        assert!(!KeyRange::Greater(two.clone()).contains(&two));
+        assert!(KeyRange::Greater(two.clone()).contains(&three));
+        assert!(KeyRange::GreaterOrEqual(two.clone()).contains(&two));
+        assert!(!KeyRange::GreaterOrEqual(two.clone()).contains(&one));
+        assert!(!KeyRange::Less(two.clone()).contains(&two));
+        assert!(KeyRange::Less(two.clone()).contains(&one));
+        assert!(KeyRange::LessOrEqual(two.clone()).contains(&two));
+        assert!(!KeyRange::LessOrEqual(two.clone()).contains(&three));
+        assert!(KeyRange::Between(one.clone(), two.clone()).contains(&one));
+        assert!(KeyRange::Between(one.clone(), two.clone()).contains(&two));
+        assert!(!KeyRange::Between(one, two).contains(&three));
+    }
+
     #[test]
     fn mysql_value_to_datatype() {
         use assert_approx_eq::assert_approx_eq;
@@ -868,6 +1642,475 @@ mod tests {
         assert_eq!(&DataType::BigInt(4) / &DataType::from(2), 2.into());
     }
 
+    #[test]
+    fn dates_and_datetimes() {
+        use std::cmp::Ordering;
+
+        let d = DataType::parse_date("2020-02-29").unwrap();
+        assert_eq!(d, DataType::Date(NaiveDate::from_ymd(2020, 2, 29)));
+        assert_eq!(d.to_string(), "2020-02-29");
+        assert_eq!(format!("{:?}", d), "Date(2020-02-29)");
+        assert_eq!(DataType::parse_date("2020-02-29 12:00:00"), Ok(d.clone()));
+        assert!(DataType::parse_date("2019-02-29").is_err());
+
+        let noon = NaiveDate::from_ymd(2020, 2, 29).and_hms(12, 0, 0);
+        for lit in &[
+            "2020-02-29 12:00:00",
+            "2020-02-29T12:00:00",
+            "2020-02-29 12:00",
+        ] {
+            assert_eq!(DataType::parse_datetime(lit), Ok(DataType::Timestamp(noon)));
+        }
+        assert_eq!(
+            DataType::parse_datetime("2020-02-29 12:00:00.25"),
+            Ok(DataType::Timestamp(
+                NaiveDate::from_ymd(2020, 2, 29).and_hms_milli(12, 0, 0, 250)
+            ))
+        );
+        assert_eq!(
+            DataType::parse_datetime("2020-02-29"),
+            Ok(DataType::Timestamp(
+                NaiveDate::from_ymd(2020, 2, 29).and_hms(0, 0, 0)
+            ))
+        );
+        assert!(DataType::parse_datetime("02/29/2020").is_err());
+
+        // dates order by day, and against timestamps as midnight of that day
+        let next = DataType::parse_date("2020-03-01").unwrap();
+        assert_eq!(d.cmp(&next), Ordering::Less);
+        assert_eq!(d.cmp(&DataType::Timestamp(noon)), Ordering::Less);
+        assert_eq!(next.cmp(&DataType::Timestamp(noon)), Ordering::Greater);
+        assert_eq!(DataType::Timestamp(noon).cmp(&next), Ordering::Less);
+        assert_ne!(d, DataType::Timestamp(noon.date().and_hms(0, 0, 0)));
+    }
+
+    #[test]
+    fn dates_round_trip_serde() {
+        let values = vec![
+            DataType::parse_date("2020-02-29").unwrap(),
+            DataType::parse_datetime("2020-02-29 12:34:56.789").unwrap(),
+        ];
+        let json = serde_json::to_string(&values).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<DataType>>(&json).unwrap(),
+            values
+        );
+        let bytes = bincode::serialize(&values).unwrap();
+        assert_eq!(
+            bincode::deserialize::<Vec<DataType>>(&bytes).unwrap(),
+            values
+        );
+    }
+
+    #[test]
+    fn json_is_canonical() {
+        let a = DataType::parse_json(r#"{ "b": [1, 2.5, null], "a": "x" }"#).unwrap();
+        let b = DataType::parse_json(r#"{"a":"x","b":[1,2.5,null]}"#).unwrap();
+        assert!(a.is_json());
+        assert_eq!(a.to_string(), r#"{"a":"x","b":[1,2.5,null]}"#);
+        assert_eq!(a, b);
+
+        use std::collections::hash_map::DefaultHasher;
+        let hash = |d: &DataType| {
+            let mut h = DefaultHasher::new();
+            d.hash(&mut h);
+            h.finish()
+        };
+        assert_eq!(hash(&a), hash(&b));
+
+        assert_ne!(
+            a,
+            DataType::parse_json(r#"{"a":"y","b":[1,2.5,null]}"#).unwrap()
+        );
+        assert!(DataType::parse_json(r#"{"a":"#).is_err());
+
+        let json = serde_json::to_string(&a).unwrap();
+        assert_eq!(serde_json::from_str::<DataType>(&json).unwrap(), a);
+    }
+
+    #[test]
+    fn bools() {
+        let hash = |dt: &DataType| {
+            use std::collections::hash_map::DefaultHasher;
+            use std::hash::{Hash, Hasher};
+            let mut s = DefaultHasher::new();
+            dt.hash(&mut s);
+            s.finish()
+        };
+        let t = DataType::from(true);
+        let f = DataType::from(false);
+        assert!(t.is_bool());
+        assert_eq!(t.to_string(), "TRUE");
+        assert!(f < t);
+
+        // legacy 0/1 integers compare, hash, and compute like booleans
+        assert_eq!(t, DataType::from(1));
+        assert_eq!(DataType::BigInt(0), f);
+        assert_ne!(t, DataType::from(2));
+        assert!(DataType::from(-1) < f);
+        assert_eq!(hash(&t), hash(&DataType::from(1)));
+        assert_eq!(&t + &t, DataType::from(2));
+        assert_eq!(i64::from(&t), 1);
+
+        assert!(t.is_truthy());
+        assert!(!f.is_truthy());
+        assert!(DataType::from(2).is_truthy());
+        assert!(!DataType::None.is_truthy());
+    }
+
+    #[test]
+    fn case_insensitive_text() {
+        let hash = |dt: &DataType| {
+            use std::collections::hash_map::DefaultHasher;
+            use std::hash::{Hash, Hasher};
+            let mut s = DefaultHasher::new();
+            dt.hash(&mut s);
+            s.finish()
+        };
+        let ci = DataType::case_insensitive("Grüße, Jürgen");
+        assert_eq!(ci.to_string(), "\"Grüße, Jürgen\"");
+
+        // equal to any casing, and hashes like it
+        let h = crate::ShardHasher::default();
+        for other in &["GRÜSSE, JÜRGEN", "grüsse, jürgen"] {
+            assert_eq!(
+                crate::shard_by(&h, &ci, 8),
+                crate::shard_by(&h, &DataType::case_insensitive(other), 8)
+            );
+            let other = DataType::from(*other);
+            assert_eq!(ci, other);
+            assert_eq!(other, ci);
+            assert_eq!(ci.cmp(&other), Ordering::Equal);
+            assert_eq!(hash(&ci), hash(&other));
+        }
+        assert_ne!(ci, DataType::from("Grüße, Jörg"));
+
+        // without the collation, casing still matters, and text shards by its raw bytes
+        assert_ne!(DataType::from("Bob"), DataType::from("bob"));
+        assert_eq!(
+            crate::shard_by(&h, &DataType::from("Bob"), 1 << 16),
+            (crate::ShardHash::hash_bytes(&h, b"Bob") % (1 << 16)) as usize
+        );
+        assert_eq!(
+            DataType::case_insensitive("Bob"),
+            DataType::case_insensitive("BOB")
+        );
+    }
+
+    #[test]
+    fn bytes() {
+        let b = DataType::from(vec![0u8, 1, 0xfe, 0xff]);
+        assert!(b.is_bytes());
+        assert_eq!(b.to_string(), "x'0001feff'");
+        assert_eq!(b, b.deep_clone());
+        assert_ne!(b, DataType::from(vec![0u8, 1, 0xfe]));
+        assert!(DataType::from(vec![0u8, 1]) < b);
+
+        // raw on channels, base64 in JSON
+        let raw = bincode::serialize(&b).unwrap();
+        assert!(raw.len() <= 4 + 8 + 4);
+        assert_eq!(bincode::deserialize::<DataType>(&raw).unwrap(), b);
+        let json = serde_json::to_string(&b).unwrap();
+        assert!(json.contains("AAH+/w=="), "{}", json);
+        assert_eq!(serde_json::from_str::<DataType>(&json).unwrap(), b);
+    }
+
+    #[test]
+    fn uuids() {
+        let text = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        let u = DataType::parse_uuid(text).unwrap();
+        assert_eq!(u.to_string(), text);
+        assert_eq!(format!("{:?}", u), format!("Uuid({})", text));
+        assert_eq!(DataType::parse_uuid(&text.to_uppercase()).unwrap(), u);
+        assert!(DataType::parse_uuid("67e55044-10b1-426f-9247-bb680e5fe0c").is_err());
+        assert!(DataType::parse_uuid("67e55044x10b1-426f-9247-bb680e5fe0c8").is_err());
+        assert!(DataType::parse_uuid("67e55044-10b1-426f-9247-bb680e5fe0cg").is_err());
+
+        let v = DataType::parse_uuid("67e55044-10b1-426f-9247-bb680e5fe0c9").unwrap();
+        assert_ne!(u, v);
+        assert!(u < v);
+
+        // the channel representation is just the 16 bytes
+        let bytes = bincode::serialize(&u).unwrap();
+        assert!(bytes.len() <= 4 + 16);
+        assert_eq!(bincode::deserialize::<DataType>(&bytes).unwrap(), u);
+        let json = serde_json::to_string(&u).unwrap();
+        assert_eq!(serde_json::from_str::<DataType>(&json).unwrap(), u);
+    }
+
+    #[test]
+    fn uuids_shard_evenly() {
+        // sequential, time-ordered ids must still spread over all shards
+        let shards = 8;
+        let h = crate::ShardHasher::default();
+        let mut counts = vec![0; shards];
+        for i in 0u64..8000 {
+            let mut b = [0u8; 16];
+            b[..8].copy_from_slice(&(i << 16).to_be_bytes());
+            counts[crate::shard_by(&h, &DataType::from(b), shards)] += 1;
+        }
+        assert!(counts.iter().all(|&c| c > 800 && c < 1200), "{:?}", counts);
+    }
+
+    #[test]
+    fn compound_keys_shard_by_all_columns() {
+        let shards = 8;
+        let h = crate::ShardHasher::default();
+        // a one-column key shards like its value does
+        let v = DataType::from(42);
+        assert_eq!(
+            crate::shard_by_key(&h, &[v.clone()], shards),
+            crate::shard_by(&h, &v, shards)
+        );
+
+        // equal keys go to the same shard, even if their values have different types
+        let key = vec![DataType::from(1), DataType::from("Foo")];
+        let same = vec![DataType::Bool(true), DataType::case_insensitive("foo")];
+        assert_eq!(
+            crate::shard_by_key(&h, &key, shards),
+            crate::shard_by_key(&h, &same, shards)
+        );
+
+        // keys that share their first column still spread over all shards
+        let mut counts = vec![0; shards];
+        for i in 0..8000 {
+            let key = [DataType::from(0), DataType::from(i)];
+            counts[crate::shard_by_key(&h, &key, shards)] += 1;
+        }
+        assert!(counts.iter().all(|&c| c > 800 && c < 1200), "{:?}", counts);
+    }
+
+    #[test]
+    fn default_shard_hash_is_stable() {
+        use crate::ShardHash;
+
+        // rows stay where they were written, so these must never change
+        let h = crate::ShardHasher::default();
+        assert_eq!(h.hash_bytes(b"noria"), 0x6bdf_4930_870a_c98e);
+        assert_eq!(crate::shard_by(&h, &"Noria".into(), 8), 6);
+        assert_eq!(crate::shard_by(&h, &DataType::from(-1), 16), 15);
+        assert_eq!(
+            h.hash_compound(&[1, 0x6bdf_4930_870a_c98e]),
+            0xf315_2567_1b7a_0604
+        );
+        let key = [DataType::from(1), DataType::from("Noria")];
+        assert_eq!(crate::shard_by_key(&h, &key, 1 << 16), 0x0604);
+    }
+
+    #[test]
+    fn range_sharding_routes_by_boundaries() {
+        let boundaries: Vec<DataType> = vec![10.into(), 20.into(), 30.into()];
+        let shard = |v: i32| crate::shard_by_range(&v.into(), &boundaries);
+        assert_eq!(shard(-5), 0);
+        assert_eq!(shard(9), 0);
+        // values on a boundary always go to the shard above it
+        assert_eq!(shard(10), 1);
+        assert_eq!(shard(19), 1);
+        assert_eq!(shard(30), 3);
+        assert_eq!(shard(1000), 3);
+
+        let shards = |r: KeyRange| crate::shards_in_range(&r, &boundaries);
+        assert_eq!(shards(KeyRange::Between(12.into(), 18.into())), 1..2);
+        assert_eq!(shards(KeyRange::Between(5.into(), 20.into())), 0..3);
+        assert_eq!(shards(KeyRange::Less(20.into())), 0..2);
+        assert_eq!(shards(KeyRange::LessOrEqual(20.into())), 0..3);
+        assert_eq!(shards(KeyRange::Greater(25.into())), 2..4);
+        assert_eq!(shards(KeyRange::GreaterOrEqual(30.into())), 3..4);
+    }
+
+    #[test]
+    fn range_boundaries_split_sample_evenly() {
+        let sample: Vec<DataType> = (0..100).rev().map(DataType::from).collect();
+        let boundaries: Vec<DataType> = vec![25.into(), 50.into(), 75.into()];
+        assert_eq!(crate::range_boundaries(sample, 4), boundaries);
+
+        // a sample with few distinct values yields fewer boundaries
+        let sample: Vec<DataType> = (0..100).map(|i| DataType::from(i / 60)).collect();
+        assert_eq!(crate::range_boundaries(sample, 4), vec![DataType::from(1)]);
+        assert!(crate::range_boundaries(Vec::new(), 4).is_empty());
+    }
+
+    #[test]
+    fn uuids_never_equal_strings() {
+        let text = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        let u = DataType::parse_uuid(text).unwrap();
+        for s in &[
+            DataType::from(text),
+            DataType::case_insensitive(text),
+            "a".into(),
+        ] {
+            assert_ne!(&u, s);
+            assert_eq!(u.cmp(s), Ordering::Greater);
+            assert_eq!(s.cmp(&u), Ordering::Less);
+        }
+    }
+
+    #[test]
+    fn uuid_columns_coerce_values() {
+        use nom_sql::Column;
+
+        let text = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        let u = DataType::parse_uuid(text).unwrap();
+        let spec = ColumnSpecification::new(Column::from("t.id"), SqlType::Binary(16));
+
+        let mut v = DataType::from(text);
+        v.coerce_to_column(&spec).unwrap();
+        assert_eq!(v, u);
+        let mut v = DataType::from(match u {
+            DataType::Uuid(ref b) => b.to_vec(),
+            _ => unreachable!(),
+        });
+        v.coerce_to_column(&spec).unwrap();
+        assert_eq!(v, u);
+
+        let mut v = DataType::from("not a uuid");
+        assert!(v.coerce_to_column(&spec).is_err());
+        assert_eq!(v, "not a uuid".into());
+        assert!(DataType::from(1).coerce_to_column(&spec).is_err());
+
+        // strings for case-insensitive columns are collated
+        let mut spec = ColumnSpecification::new(Column::from("t.name"), SqlType::Varchar(36));
+        spec.constraints
+            .push(ColumnConstraint::Collation("utf8mb4_general_ci".into()));
+        let mut v = DataType::from("Bob");
+        v.coerce_to_column(&spec).unwrap();
+        assert!(v.is_case_insensitive());
+        assert_eq!(v, "BOB".into());
+
+        // other columns keep their values as they are
+        let spec = ColumnSpecification::new(Column::from("t.name"), SqlType::Char(36));
+        let mut v = DataType::from(text);
+        v.coerce_to_column(&spec).unwrap();
+        assert!(v.is_string() && !v.is_case_insensitive());
+    }
+
+    #[test]
+    fn decimal_text_forms() {
+        let d = DataType::parse_decimal("-12.340").unwrap();
+        assert_eq!(d, DataType::Decimal(-12340, 3));
+        assert_eq!(d.to_string(), "-12.340");
+        assert_eq!(format!("{:?}", d), "Decimal(-12.340)");
+        assert_eq!(DataType::decimal(5, 3).to_string(), "0.005");
+        assert_eq!(DataType::decimal(-5, 3).to_string(), "-0.005");
+        assert_eq!(DataType::decimal(7, 0).to_string(), "7");
+        assert_eq!(DataType::parse_decimal(".5"), Ok(DataType::decimal(5, 1)));
+        assert!(DataType::parse_decimal("1.2.3").is_err());
+        assert!(DataType::parse_decimal("-").is_err());
+        assert!(DataType::parse_decimal("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn decimal_from_literals() {
+        assert_eq!(
+            DataType::from(3).to_decimal(2),
+            Ok(DataType::Decimal(300, 2))
+        );
+        assert_eq!(
+            DataType::from(2.345).to_decimal(2),
+            Ok(DataType::Decimal(235, 2))
+        );
+        assert_eq!(
+            DataType::from(-2.345).to_decimal(2),
+            Ok(DataType::Decimal(-235, 2))
+        );
+        assert_eq!(
+            DataType::from("10.5").to_decimal(2),
+            Ok(DataType::Decimal(1050, 2))
+        );
+        assert_eq!(DataType::None.to_decimal(2), Ok(DataType::None));
+        assert!(DataType::from(std::i64::MAX).to_decimal(2).is_err());
+    }
+
+    #[test]
+    fn decimal_arithmetic_is_exact() {
+        let tenth = DataType::decimal(1, 1);
+        let mut sum = DataType::decimal(0, 0);
+        for _ in 0..10 {
+            sum = &sum + &tenth;
+        }
+        assert_eq!(sum, DataType::decimal(10, 1));
+        assert_eq!(sum, 1.into());
+
+        assert_eq!(
+            &DataType::decimal(150, 2) - &DataType::from(2),
+            DataType::decimal(-50, 2)
+        );
+        assert_eq!(
+            &DataType::decimal(15, 1) * &DataType::decimal(15, 1),
+            DataType::decimal(225, 2)
+        );
+        // mixing in reals is not exact, and produces a real
+        assert_eq!(
+            &DataType::decimal(15, 1) + &DataType::from(1.0),
+            (2.5).into()
+        );
+
+        // overflow saturates
+        let max = DataType::decimal(std::i64::MAX, 1);
+        assert_eq!(&max + &tenth, max);
+        assert_eq!(
+            &DataType::decimal(std::i64::MIN, 1) - &tenth,
+            DataType::decimal(std::i64::MIN, 1)
+        );
+    }
+
+    #[test]
+    fn decimal_coercion() {
+        use std::cmp::Ordering;
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+        let hash = |dt: &DataType| {
+            let mut s = DefaultHasher::new();
+            dt.hash(&mut s);
+            s.finish()
+        };
+
+        // decimals are equal to decimals and integers of the same value
+        let a = DataType::decimal(500, 2);
+        assert_eq!(a, DataType::decimal(5, 0));
+        assert_eq!(a, DataType::Int(5));
+        assert_eq!(DataType::UnsignedBigInt(5), a);
+        assert_eq!(hash(&a), hash(&DataType::decimal(5, 0)));
+        assert_eq!(hash(&a), hash(&DataType::Int(5)));
+        assert_eq!(
+            hash(&DataType::decimal(10, 1)),
+            hash(&DataType::decimal(100, 2))
+        );
+        assert_eq!(a.cmp(&DataType::BigInt(6)), Ordering::Less);
+        assert_eq!(DataType::BigInt(4).cmp(&a), Ordering::Less);
+
+        // and ordered by value against reals, but never equal to them
+        let r: DataType = (5.0).into();
+        assert_ne!(a, r);
+        assert_eq!(a.cmp(&r), Ordering::Greater);
+        assert_eq!(r.cmp(&a), Ordering::Less);
+        assert_eq!(a.cmp(&(5.5).into()), Ordering::Less);
+        assert_eq!(DataType::decimal(51, 1).cmp(&r), Ordering::Greater);
+
+        // and unequal to anything else
+        assert_ne!(a, "5".into());
+        assert_ne!(a, DataType::None);
+    }
+
+    #[test]
+    fn integer_arithmetic_widens() {
+        let max = DataType::from(std::i64::MAX);
+        let one = DataType::from(1);
+        let sum = &max + &one;
+        assert_eq!(format!("{:?}", sum), format!("HugeInt({})", 1u64 << 63));
+        assert!(sum > max);
+        assert_eq!(&sum - &one, max);
+        assert_eq!(DataType::UnsignedBigInt(1 << 63), sum);
+        assert_eq!(
+            &DataType::from(std::i32::MAX) * &DataType::from(2),
+            DataType::from(i64::from(std::i32::MAX) * 2)
+        );
+        assert_eq!(&DataType::from(3u32) - &DataType::from(5u32), (-2).into());
+
+        // division by zero is NULL rather than a panic
+        assert_eq!(&one / &DataType::from(0), DataType::None);
+    }
+
     #[test]
     #[should_panic(expected = "can't + a TinyText(\"hi\") and Int(5)")]
     fn add_invalid_types() {
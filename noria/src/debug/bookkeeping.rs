@@ -0,0 +1,67 @@
+use crate::internal::DomainIndex;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+
+/// A batch of writes from one base table shard.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LabelEntry {
+    /// The base table.
+    pub base: NodeIndex,
+    /// The shard of the base table.
+    pub shard: usize,
+    /// The sequence number of the batch.
+    pub seq: u64,
+}
+
+/// What a node in a domain shard keeps track of about the updates flowing through it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeBookkeeping {
+    /// The name of the node.
+    pub name: String,
+    /// The global address of the node.
+    pub node: NodeIndex,
+    /// The latest batch of writes the node has labeled and sent on, if it is a base.
+    pub sent: Vec<LabelEntry>,
+    /// The latest batch from each base shard that the node reflects, if it is a reader.
+    pub received: Vec<LabelEntry>,
+    /// The number of updates the node is holding back before sending them on.
+    pub buffered: usize,
+    /// Roughly how many bytes of records those updates carry.
+    pub buffered_bytes: usize,
+    /// Whether the node is waiting for a full replay to finish. Updates for the node are held
+    /// back until then, and are counted in `buffered`.
+    pub awaiting_replay: bool,
+}
+
+/// What a domain shard keeps track of about the updates flowing through it.
+///
+/// This is returned by
+/// [`ControllerHandle::domain_bookkeeping`](crate::ControllerHandle::domain_bookkeeping).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DomainBookkeeping {
+    /// The domain.
+    pub domain: DomainIndex,
+    /// The shard of the domain.
+    pub shard: usize,
+    /// Every node in the domain shard, ordered by local address.
+    pub nodes: Vec<NodeBookkeeping>,
+}
+
+/// The latest batch from each base table shard that a domain shard sent along one of the edges
+/// that leave it.
+///
+/// Domain shards report these when they are drained before their worker shuts down, so that
+/// whatever replaces them knows which writes the domain shards below them have already seen.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EdgeFrontier {
+    /// The egress node the edge leaves the domain shard from.
+    pub egress: NodeIndex,
+    /// The ingress node the edge leads to.
+    pub ingress: NodeIndex,
+    /// The domain the ingress node is in.
+    pub domain: DomainIndex,
+    /// The shard of that domain the edge leads to.
+    pub shard: usize,
+    /// The latest batch from each base table shard that was sent along the edge.
+    pub sent: Vec<LabelEntry>,
+}
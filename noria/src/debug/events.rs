@@ -0,0 +1,158 @@
+use crate::debug::bookkeeping::EdgeFrontier;
+use crate::internal::DomainIndex;
+use crate::DomainMove;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Something the controller did, or that happened to the workers it manages.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum EventKind {
+    /// A recipe was activated.
+    RecipeActivated {
+        /// The version of the recipe.
+        version: usize,
+        /// The names of the queries and tables the recipe added, and their nodes.
+        added: Vec<(String, NodeIndex)>,
+        /// The leaf nodes of the queries and tables the recipe removed.
+        removed: Vec<NodeIndex>,
+    },
+    /// Activating a recipe failed, and the previous recipe was restored.
+    RecipeFailed {
+        /// Why the recipe could not be activated.
+        error: String,
+    },
+    /// A domain was placed on workers.
+    DomainPlaced {
+        /// The domain.
+        domain: DomainIndex,
+        /// The worker each shard of the domain was placed on, in shard order.
+        workers: Vec<SocketAddr>,
+        /// The nodes in the domain.
+        nodes: Vec<NodeIndex>,
+    },
+    /// A worker registered with the controller.
+    WorkerJoined {
+        /// The worker.
+        worker: SocketAddr,
+    },
+    /// A worker stopped sending heartbeats and was declared failed.
+    WorkerLost {
+        /// The worker.
+        worker: SocketAddr,
+    },
+    /// The queries affected by failed workers were removed and are about to be added again.
+    RecoveryStarted {
+        /// The failed workers.
+        workers: Vec<SocketAddr>,
+        /// The names of the queries that had nodes on those workers.
+        queries: Vec<String>,
+    },
+    /// The queries affected by failed workers were added again.
+    RecoveryFinished {
+        /// The failed workers.
+        workers: Vec<SocketAddr>,
+    },
+    /// The rows of a range-sharded base were moved to match new range boundaries.
+    BaseResplit {
+        /// The base.
+        base: NodeIndex,
+        /// The number of rows that changed shards.
+        moved: usize,
+    },
+    /// A change of the number of shards was started.
+    ReshardStarted {
+        /// The number of shards things are sharded across after the change.
+        shards: usize,
+    },
+    /// A change of the number of shards was finished.
+    ReshardFinished {
+        /// The number of shards things are now sharded across.
+        shards: usize,
+    },
+    /// A change of the number of shards was aborted.
+    ReshardAborted,
+    /// The controller started moving the domains of a worker elsewhere so that it can be stopped.
+    DecommissionStarted {
+        /// The worker.
+        worker: SocketAddr,
+    },
+    /// A domain, and everything downstream of it, was moved off a worker that is being
+    /// decommissioned.
+    DomainMoved {
+        /// The domain that was moved. Its nodes were replaced by copies in new domains.
+        domain: DomainIndex,
+        /// The worker it was moved off.
+        worker: SocketAddr,
+    },
+    /// Decommissioning a worker was called off. Domains already moved stay where they are.
+    DecommissionAborted {
+        /// The worker.
+        worker: SocketAddr,
+    },
+    /// Domains were spread onto a worker that had less load than the others.
+    Rebalanced {
+        /// The worker that the domains were moved to.
+        worker: SocketAddr,
+        /// The domains that were moved, or would have been had this not been a dry run.
+        moves: Vec<DomainMove>,
+        /// Whether the domains were left where they were.
+        dry_run: bool,
+    },
+    /// A transaction that its client never finished was given up on, and the views waiting for
+    /// the rest of its writes were released.
+    TransactionExpired {
+        /// The transaction's commit timestamp.
+        timestamp: u64,
+        /// How long the transaction had been open.
+        open_for: Duration,
+    },
+    /// A domain shard finished the work it had been given before its worker shut down.
+    DomainDrained {
+        /// The domain.
+        domain: DomainIndex,
+        /// The shard of the domain.
+        shard: usize,
+        /// The latest batch from each base table shard that the domain shard sent along each of
+        /// the edges that leave it.
+        frontiers: Vec<EdgeFrontier>,
+    },
+    /// A domain shard refused a connection from a sender in the dataflow of an earlier
+    /// controller, such as a domain on a worker that was cut off while the controller failed over.
+    SenderFenced {
+        /// The domain.
+        domain: DomainIndex,
+        /// The shard of the domain.
+        shard: usize,
+        /// Where the connection came from.
+        peer: SocketAddr,
+    },
+}
+
+/// An entry in the controller's event log.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    /// The position of the event in the log. Positions start at 0 and have no gaps, but a
+    /// restarted controller starts over.
+    pub seq: u64,
+    /// When the event happened, in nanoseconds since the Unix epoch on the controller's clock.
+    pub time: u64,
+    /// What happened.
+    pub kind: EventKind,
+}
+
+/// A page of the controller's event log.
+///
+/// Pages are returned by [`ControllerHandle::events`](crate::ControllerHandle::events).
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct EventPage {
+    /// The events on the page, oldest first.
+    pub events: Vec<Event>,
+    /// The cursor to fetch the next page with. This is also what to poll with once the page is
+    /// empty, to wait for new events.
+    pub next: u64,
+    /// The number of events that were requested but have already been dropped from the log
+    /// because it only keeps so many.
+    pub dropped: u64,
+}
@@ -0,0 +1,38 @@
+use crate::internal::DomainIndex;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Whether the domains on a worker are processing what is sent to them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HealthReport {
+    /// How the worker is doing overall.
+    pub status: HealthStatus,
+    /// How long the worker waited for each domain shard to answer.
+    pub deadline: Duration,
+    /// How each of the worker's domain shards is doing, in domain and shard order.
+    pub domains: Vec<DomainHealth>,
+}
+
+/// How a worker is doing overall.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// Every domain shard on the worker answered in time, or there are none.
+    Healthy,
+    /// Some domain shards on the worker did not answer in time.
+    Degraded,
+    /// No domain shard on the worker answered in time.
+    Unhealthy,
+}
+
+/// How one domain shard is doing.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DomainHealth {
+    /// The domain.
+    pub domain: DomainIndex,
+    /// The shard of the domain.
+    pub shard: usize,
+    /// How long the domain shard took to answer after it was asked, or `None` if it did not
+    /// answer in time.
+    pub round_trip: Option<Duration>,
+}
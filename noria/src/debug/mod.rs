@@ -1,2 +1,10 @@
+/// Types related to the bookkeeping of domains.
+pub mod bookkeeping;
+/// Types related to the controller's event log.
+pub mod events;
+/// Types related to checking on the domains of a worker.
+pub mod health;
 /// Types related to graph statistics.
 pub mod stats;
+/// Types related to tracing writes through the dataflow.
+pub mod trace;
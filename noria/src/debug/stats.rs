@@ -1,5 +1,5 @@
 use crate::internal::*;
-use crate::MaterializationStatus;
+use crate::{DataType, MaterializationStatus};
 use petgraph::graph::NodeIndex;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
@@ -21,6 +21,128 @@ pub struct DomainStats {
     pub total_forward_time: u64,
     /// Total wall-clock time spent waiting for work in this domain.
     pub wait_time: u64,
+    /// Total memory size of the state of this domain's nodes.
+    #[serde(default)]
+    pub mem_size: u64,
+    /// Total number of rows in the state of this domain's nodes.
+    #[serde(default)]
+    pub rows: u64,
+    /// The keys that made up the largest shares of the records that this domain's sharders have
+    /// seen recently, hottest first.
+    #[serde(default)]
+    pub hot_keys: Vec<HotKey>,
+    /// How many packets this domain's sharders have waiting to be sent to each of their shards.
+    #[serde(default)]
+    pub shard_queues: Vec<ShardQueue>,
+    /// The cores that the thread running this domain is pinned to, if it is pinned.
+    #[serde(default)]
+    pub cores: Option<Vec<usize>>,
+    /// How many batches of partial replay requests this domain has answered as the source of a
+    /// replay path.
+    #[serde(default)]
+    pub replay_batches: u64,
+    /// How many keys those batches were for in total. Divided by `replay_batches`, this gives the
+    /// average number of keys replayed together.
+    #[serde(default)]
+    pub replayed_keys: u64,
+    /// How many of this domain's partial replay requests had to wait because it already had as
+    /// many replays in flight as it may.
+    #[serde(default)]
+    pub queued_replays: u64,
+    /// Total time those requests spent waiting. This is included in the time the reads that
+    /// missed took to complete, so a large share of it means the replay cap is the bottleneck.
+    #[serde(default)]
+    pub replay_queue_time: u64,
+    /// How many partial replay requests are waiting right now.
+    #[serde(default)]
+    pub replay_queue_len: usize,
+    /// What this domain's memory is spent on.
+    #[serde(default)]
+    pub memory: MemoryBreakdown,
+}
+
+/// Some number of rows, and roughly how many bytes they take up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryUsage {
+    /// The number of rows.
+    pub rows: u64,
+    /// Roughly how many bytes the rows take up.
+    pub bytes: u64,
+}
+
+impl MemoryUsage {
+    /// Add `other` to this.
+    pub fn merge(&mut self, other: &MemoryUsage) {
+        self.rows += other.rows;
+        self.bytes += other.bytes;
+    }
+}
+
+/// What the memory of a domain, or of all the domains on a worker, is spent on.
+///
+/// The byte counts are estimates of the memory taken up by the rows, and leave out the overhead
+/// of the data structures holding them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryBreakdown {
+    /// The state of readers.
+    pub reader_state: MemoryUsage,
+    /// The state of base tables and of the other operators that keep state.
+    pub operator_state: MemoryUsage,
+    /// Updates that egress nodes are holding back to send together.
+    pub egress_buffers: MemoryUsage,
+    /// Writes waiting to be committed together as a group. Here, rows are table operations.
+    pub group_commit: MemoryUsage,
+    /// Packets waiting to be sent on to other domains.
+    pub output_queues: MemoryUsage,
+}
+
+impl MemoryBreakdown {
+    /// The memory taken up by all of it together.
+    pub fn total(&self) -> MemoryUsage {
+        let mut total = MemoryUsage::default();
+        total.merge(&self.reader_state);
+        total.merge(&self.operator_state);
+        total.merge(&self.egress_buffers);
+        total.merge(&self.group_commit);
+        total.merge(&self.output_queues);
+        total
+    }
+
+    /// Add `other` to this.
+    pub fn merge(&mut self, other: &MemoryBreakdown) {
+        self.reader_state.merge(&other.reader_state);
+        self.operator_state.merge(&other.operator_state);
+        self.egress_buffers.merge(&other.egress_buffers);
+        self.group_commit.merge(&other.group_commit);
+        self.output_queues.merge(&other.output_queues);
+    }
+}
+
+/// A key that made up a large share of the records that a sharder has seen recently.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HotKey {
+    /// The sharder that saw the key.
+    pub node: NodeIndex,
+    /// The key.
+    pub key: Vec<DataType>,
+    /// The number of recent records with this key. Older records count for less: the count is
+    /// halved every time the sharder has seen another 16384 records.
+    pub records: u64,
+    /// The share of the sharder's recent records that had this key.
+    pub share: f64,
+    /// Whether the key's records are spread over several shards.
+    pub salted: bool,
+}
+
+/// The packets that a sharder has waiting to be sent to one of its shards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShardQueue {
+    /// The sharder.
+    pub node: NodeIndex,
+    /// The shard the packets are for.
+    pub shard: usize,
+    /// The number of packets waiting.
+    pub queued: usize,
 }
 
 /// Statistics about a node.
@@ -36,10 +158,135 @@ pub struct NodeStats {
     pub process_ptime: u64,
     /// Total memory size of this node's state.
     pub mem_size: u64,
+    /// The number of rows in this node's state.
+    #[serde(default)]
+    pub rows: u64,
     /// The materialization type of this node's state.
     pub materialized: MaterializationStatus,
     /// The value returned from Ingredient::probe.
     pub probe_result: HashMap<String, String>,
+    /// How long sampled writes took to reach this node, if it is a reader.
+    #[serde(default)]
+    pub propagation: Option<LatencyHistogram>,
+    /// How much work this node's operator has done, if it is an internal node.
+    #[serde(default)]
+    pub operator: Option<OperatorStats>,
+    /// If this node is a reader, the time up to which it reflects every write applied by the
+    /// base table shards it has heard from, in nanoseconds since the Unix epoch.
+    #[serde(default)]
+    pub applied: Option<u64>,
+}
+
+/// How much work an operator has done since its domain shard started.
+///
+/// Both regular updates and replays are counted. Dividing the counters by the time between two
+/// statistics requests gives the operator's rates over that time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperatorStats {
+    /// The number of updates the operator has processed.
+    pub invocations: u64,
+    /// The number of records in those updates.
+    pub records_in: u64,
+    /// The number of records the operator emitted in response.
+    pub records_out: u64,
+    /// Total wall-clock time spent processing those updates, in nanoseconds.
+    pub process_time: u64,
+}
+
+/// The number of buckets in a [`LatencyHistogram`].
+const LATENCY_BUCKETS: usize = 32;
+
+/// A histogram of latencies, with exponentially growing buckets.
+///
+/// Bucket `i` counts the latencies of at most 2^`i` microseconds that did not fit in bucket
+/// `i - 1`, and the last bucket also counts all longer latencies.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyHistogram {
+    /// The number of latencies that fell into each bucket.
+    pub buckets: Vec<u64>,
+    /// The number of latencies recorded.
+    pub count: u64,
+    /// The sum of all latencies recorded, in nanoseconds.
+    pub sum: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: vec![0; LATENCY_BUCKETS],
+            count: 0,
+            sum: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// The upper bound of bucket `i`, in nanoseconds.
+    pub fn bound(i: usize) -> u64 {
+        1_000 << i
+    }
+
+    /// Record a latency.
+    pub fn record(&mut self, latency: std::time::Duration) {
+        let ns = latency.as_nanos() as u64;
+        let bucket = (0..self.buckets.len())
+            .find(|&i| ns <= Self::bound(i))
+            .unwrap_or(self.buckets.len() - 1);
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(ns);
+    }
+
+    /// Add the latencies recorded in `other` to this histogram.
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        if self.buckets.len() < other.buckets.len() {
+            self.buckets.resize(other.buckets.len(), 0);
+        }
+        for (b, &n) in self.buckets.iter_mut().zip(&other.buckets) {
+            *b += n;
+        }
+        self.count += other.count;
+        self.sum = self.sum.saturating_add(other.sum);
+    }
+
+    /// An upper bound on the `q`th quantile of the recorded latencies, in nanoseconds, or `None`
+    /// if no latencies have been recorded.
+    pub fn quantile(&self, q: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((q * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Some(Self::bound(i));
+            }
+        }
+        Some(Self::bound(self.buckets.len() - 1))
+    }
+}
+
+/// The size of a view's state, summed over all of its shards.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewStats {
+    /// Total memory size of the view's state.
+    pub mem_size: u64,
+    /// The number of rows in the view's state.
+    pub rows: u64,
+    /// How long sampled writes took from their base table to the view, over all of its shards.
+    ///
+    /// Only writes sampled for this are recorded; see
+    /// [`ControllerHandle::set_propagation_sampling`](crate::ControllerHandle::set_propagation_sampling).
+    #[serde(default)]
+    pub propagation: LatencyHistogram,
+    /// The time up to which every shard of the view reflects every write applied by the base
+    /// tables it reads from, in nanoseconds since the Unix epoch.
+    ///
+    /// How far this lags behind the current time is how stale reads from the view may be. See
+    /// [`View::lookup_fresh`](crate::View::lookup_fresh).
+    #[serde(default)]
+    pub applied: Option<u64>,
 }
 
 /// Statistics about the Soup data-flow.
@@ -49,6 +296,12 @@ pub struct GraphStats {
     #[serde(deserialize_with = "deserialize_domainmap")]
     #[doc(hidden)]
     pub domains: DomainMap,
+    /// The size of each view's state, keyed by view name.
+    #[serde(default)]
+    pub views: HashMap<String, ViewStats>,
+    /// What the memory of the domains on each worker is spent on, keyed by the worker's address.
+    #[serde(default)]
+    pub workers: HashMap<String, MemoryBreakdown>,
 }
 
 use std::ops::Deref;
@@ -0,0 +1,132 @@
+use crate::internal::DomainIndex;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+
+/// Something that happened to a traced write on its way through the dataflow.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PacketEvent {
+    /// The write was committed to a base shard in a group of `writes` client writes.
+    ///
+    /// The rest of the trace follows the update the group became, which is recorded under the
+    /// trace tag `into`. That is the write's own tag unless another traced write was in the group.
+    Merged {
+        /// The trace tag the group's update carries.
+        into: u64,
+        /// The number of client writes in the group.
+        writes: usize,
+    },
+    /// The node processed the update.
+    Processed,
+    /// The reader applied the update, so reads from it reflect the write.
+    Reached,
+}
+
+/// A single event in the trace of a write.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TraceEvent {
+    /// The node the event happened at.
+    pub node: NodeIndex,
+    /// The domain the node is in.
+    pub domain: DomainIndex,
+    /// The shard of the domain.
+    pub shard: usize,
+    /// What happened.
+    pub event: PacketEvent,
+    /// When it happened, in nanoseconds since the Unix epoch on the worker's clock.
+    pub time: u64,
+}
+
+/// The path a traced write took through the dataflow.
+///
+/// Traces are returned by [`ControllerHandle::trace`](crate::ControllerHandle::trace).
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Trace {
+    /// Everything that has happened to the write so far, ordered by time.
+    ///
+    /// Events from different workers are ordered by their clocks, which may disagree slightly.
+    pub events: Vec<TraceEvent>,
+    /// The write has reached every shard of every reader downstream of the tables it was made
+    /// to, so no more events will be added.
+    pub complete: bool,
+}
+
+/// A span of a distributed trace that a Noria read or write is part of, as propagated by whoever
+/// made it.
+///
+/// Workers that export spans over OTLP record the spans of the operation's way through Noria as
+/// descendants of this one. Only propagate the context of a span that was sampled: an operation
+/// without a context causes no spans, and costs next to nothing extra.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TraceContext {
+    /// The id of the trace.
+    pub trace_id: u128,
+    /// The id of the span within the trace.
+    pub span_id: u64,
+}
+
+impl TraceContext {
+    /// Parse the context out of a W3C `traceparent` header.
+    ///
+    /// Returns `None` if the header is malformed, or if the span was not sampled.
+    pub fn from_traceparent(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let flags = parts.next()?;
+        // later versions may add fields, but must keep these ones where they are
+        if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+        if trace_id.len() != 32 || span_id.len() != 16 || flags.len() != 2 {
+            return None;
+        }
+
+        let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+        let span_id = u64::from_str_radix(span_id, 16).ok()?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        if trace_id == 0 || span_id == 0 || flags & 0x01 == 0 {
+            return None;
+        }
+        Some(TraceContext { trace_id, span_id })
+    }
+
+    /// The W3C `traceparent` header that propagates this context.
+    pub fn to_traceparent(&self) -> String {
+        format!("00-{:032x}-{:016x}-01", self.trace_id, self.span_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traceparent_roundtrip() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let cx = TraceContext::from_traceparent(header).unwrap();
+        assert_eq!(cx.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(cx.span_id, 0x00f067aa0ba902b7);
+        assert_eq!(cx.to_traceparent(), header);
+    }
+
+    #[test]
+    fn traceparent_unsampled_or_malformed() {
+        for header in &[
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(TraceContext::from_traceparent(header), None, "{}", header);
+        }
+        // later versions may carry more fields
+        assert!(TraceContext::from_traceparent(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra"
+        )
+        .is_some());
+    }
+}
@@ -201,14 +201,17 @@ pub(crate) const VIEW_POOL_SIZE: usize = 16;
 pub(crate) const PENDING_LIMIT: usize = 8192;
 
 use petgraph::graph::NodeIndex;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
 use tokio_tower::multiplex;
 
 mod controller;
 mod data;
+mod shard_hash;
 mod table;
 mod view;
 
+pub mod auth;
 #[doc(hidden)]
 pub mod channel;
 #[doc(hidden)]
@@ -219,6 +222,7 @@ pub mod doc_mock;
 #[doc(hidden)]
 #[allow(unreachable_pub)] // https://github.com/rust-lang/rust/issues/57411
 pub mod internal;
+pub mod namespace;
 
 // for the row! macro
 #[doc(hidden)]
@@ -243,9 +247,18 @@ pub mod results {
     pub use super::view::results::{ResultRow, Results, Row};
 }
 
+/// Types used to subscribe to the changes made to a view or a base table.
+pub mod subscription {
+    pub use super::view::subscription::{
+        Backpressure, Change, ChangeEvent, ChangeFeed, ChangeLabel, ChangeSink, FeedUpdate,
+        ResumeToken, Subscription, SubscriptionOptions, Update,
+    };
+}
+
 /// Noria errors.
 pub mod error {
-    pub use crate::table::TableError;
+    pub use crate::auth::AuthError;
+    pub use crate::table::{TableError, TransactionError};
     pub use crate::view::ViewError;
 }
 
@@ -296,15 +309,23 @@ impl<T> From<T> for Tagged<T> {
 }
 
 pub use crate::controller::{ControllerDescriptor, ControllerHandle};
-pub use crate::data::{DataType, Modification, Operation, TableOperation};
-pub use crate::table::Table;
-pub use crate::view::View;
+pub use crate::data::{
+    ColumnCondition, DataType, KeyRange, Modification, Operation, TableOperation, MAX_DECIMAL_SCALE,
+};
+pub use crate::shard_hash::{ShardHash, ShardHasher};
+pub use crate::table::{Committed, Table, Transaction, WriteToken};
+pub use crate::view::{PageToken, View};
 
 #[doc(hidden)]
-pub use crate::table::Input;
+pub use crate::table::{
+    BeginTransaction, Input, TransactionBegun, TransactionDecided, WriteAck, TRANSACTION_PROTOCOL,
+};
 
 #[doc(hidden)]
-pub use crate::view::{ReadQuery, ReadReply, ReadReplyBatch};
+pub use crate::view::{ChangeCursor, ChangeSet, ReadQuery, ReadReply, ReadReplyBatch};
+
+#[doc(hidden)]
+pub use crate::data::is_case_insensitive_column;
 
 #[doc(hidden)]
 pub mod builders {
@@ -322,27 +343,294 @@ pub struct ActivationResult {
     pub new_nodes: HashMap<String, NodeIndex>,
     /// List of leaf nodes that were removed.
     pub removed_leaves: Vec<NodeIndex>,
+    /// Map of names of added queries to the existing nodes, other than base tables, that they
+    /// share with earlier queries rather than having their own copies of.
+    #[serde(default)]
+    pub reused_nodes: HashMap<String, Vec<NodeIndex>>,
     /// Number of expressions the recipe added compared to the prior recipe.
     pub expressions_added: usize,
     /// Number of expressions the recipe removed compared to the prior recipe.
     pub expressions_removed: usize,
 }
 
-#[doc(hidden)]
+/// A change that was made to the recipe.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RecipeChange {
+    /// The tables and views in the change's namespace were replaced by the given recipe.
+    Install(String),
+    /// The given queries were added to the recipe.
+    Extend(String),
+    /// The view with the given name was removed.
+    RemoveView(String),
+    /// The recipe was rolled back to what it was at the given version.
+    Rollback(usize),
+}
+
+/// A version of the recipe, as kept in the controller's recipe history.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RecipeVersion {
+    /// The version of the recipe after the change.
+    pub version: usize,
+    /// The change that led to this version.
+    pub change: RecipeChange,
+    /// The namespace the change was made in, if any.
+    #[serde(default)]
+    pub namespace: Option<String>,
+}
+
+/// What installing a recipe would change, by the names of tables and views.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RecipeDiff {
+    /// The version of the recipe that the candidate was compared against.
+    pub version: usize,
+    /// Tables and views that would be added, or whose definition would change.
+    pub added: Vec<String>,
+    /// Tables and views that would be removed, or whose definition would change.
+    pub removed: Vec<String>,
+    /// Tables and views that would stay as they are, along with their dataflow.
+    pub reused: Vec<String>,
+}
+
+/// What activating a recipe would do to the dataflow, as worked out without changing it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MigrationPlan {
+    /// The version of the recipe that the plan was made against.
+    pub version: usize,
+    /// Map of query names to the nodes that would handle reads and writes for them.
+    pub new_nodes: HashMap<String, NodeIndex>,
+    /// The nodes that would be added, in topological order.
+    pub added: Vec<PlannedNode>,
+    /// Existing nodes that new nodes would be added below.
+    pub reused: Vec<NodeIndex>,
+    /// Map of query names to the existing nodes, other than base tables, that the queries would
+    /// share with earlier queries.
+    #[serde(default)]
+    pub shared: HashMap<String, Vec<NodeIndex>>,
+    /// Existing materializations that would gain indices or replay paths, by the columns of
+    /// those indices.
+    pub reindexed: Vec<(NodeIndex, Vec<Vec<usize>>)>,
+    /// Leaf nodes that would be removed.
+    pub removed_leaves: Vec<NodeIndex>,
+    /// How many rows would be replayed to fill the new full materializations.
+    pub replay_rows: u64,
+}
+
+impl MigrationPlan {
+    /// Whether the given result of activating the recipe is what the plan said it would be.
+    ///
+    /// This only holds if nothing else changed the recipe between planning and activating.
+    pub fn matches(&self, result: &ActivationResult) -> bool {
+        self.new_nodes == result.new_nodes
+            && self.removed_leaves == result.removed_leaves
+            && self.shared == result.reused_nodes
+    }
+}
+
+/// A node that a migration would add.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PlannedNode {
+    /// The index that the node would have in the graph.
+    pub node: NodeIndex,
+    /// The node's name.
+    pub name: String,
+    /// The operator the node would run.
+    pub operator: String,
+    /// The domain that the node would be assigned to.
+    pub domain: usize,
+    /// How many shards the node would be split into, if any.
+    pub shards: Option<usize>,
+    /// The columns the node would be sharded by, if any.
+    pub shard_key: Option<Vec<usize>>,
+    /// How the node's state would be materialized.
+    pub materialization: PlannedMaterialization,
+    /// The columns the node's state would be indexed by.
+    pub keys: Vec<Vec<usize>>,
+    /// How many rows would be replayed to fill the node's state, going by how many rows there
+    /// are in the tables it is computed from.
+    pub replay_rows: u64,
+}
+
+/// How a node that a migration would add would keep its state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PlannedMaterialization {
+    /// The node would keep no state.
+    Not,
+    /// The node would keep all of its state.
+    Full,
+    /// The node would only keep state for keys that have been asked for.
+    Partial,
+}
+
+/// How far along the migration that the controller is running, or last ran, is.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MigrationProgress {
+    /// Tells migrations apart; every migration gets a higher one than the one before it.
+    pub id: u64,
+    /// Whether the migration is still running, and if so, in which phase.
+    pub status: MigrationStatus,
+    /// How long the migration has been running, or ran for if it has ended.
+    pub elapsed: std::time::Duration,
+    /// The phases the migration has gone through so far, in order, ending with the current one.
+    pub phases: Vec<PhaseProgress>,
+    /// How much longer the current phase is expected to take, if there is enough to go by.
+    pub eta: Option<std::time::Duration>,
+}
+
+/// Whether a migration is still running.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MigrationStatus {
+    /// The migration is in the given phase.
+    Running(MigrationPhase),
+    /// The migration completed.
+    Done,
+    /// The migration gave up with the given error.
+    Failed(String),
+}
+
+/// A phase of a migration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum MigrationPhase {
+    /// Adding the new nodes to the controller's copy of the graph.
+    GraphConstruction,
+    /// Sharding the new nodes and assigning them to domains.
+    DomainAssignment,
+    /// Starting new domains and adding the new nodes to existing ones.
+    DomainBoot,
+    /// Filling the state of new materializations.
+    StateReplay,
+    /// Making the new views available to clients.
+    ReaderPublication,
+}
+
+/// What happened in one phase of a migration.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PhaseProgress {
+    /// The phase.
+    pub phase: MigrationPhase,
+    /// How long the phase has taken so far.
+    pub elapsed: std::time::Duration,
+    /// How many units of work the phase has done: nodes added, domains assigned or booted, rows
+    /// replayed, or views published.
+    ///
+    /// Rows replayed are estimated from the number of rows in the tables that each new
+    /// materialization is computed from, and counted once that materialization is filled.
+    pub done: u64,
+    /// How many units of work the phase is expected to do in total.
+    pub total: u64,
+}
+
+/// How fast a domain replays the state of a node in full, such as to fill a new materialization
+/// from a base table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ReplayRate {
+    /// Replay as fast as the target domain takes the replayed records in.
+    Unlimited,
+    /// Replay at most this many records per second.
+    RecordsPerSecond(u64),
+    /// Replay at most this many packets of records per second.
+    PacketsPerSecond(u64),
+}
+
+impl Default for ReplayRate {
+    fn default() -> Self {
+        ReplayRate::Unlimited
+    }
+}
+
+/// How far a controller that took over a deployment with existing base tables is with restoring
+/// the dataflow over them.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RecoveryStatus {
+    /// Whether the dataflow has been restored, and clients can read and write again.
+    pub complete: bool,
+    /// How long recovery has been running, or ran for if it is complete.
+    pub elapsed: std::time::Duration,
+    /// The number of rows each base table held once it was restored, or `None` for the tables
+    /// that have not been restored yet.
+    pub bases: std::collections::BTreeMap<String, Option<u64>>,
+}
+
+/// How far along emptying a worker so that it can be stopped is.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DecommissionStatus {
+    /// Whether the worker is being decommissioned.
+    pub decommissioning: bool,
+    /// The domains on the worker that are still to be moved to other workers.
+    pub remaining: Vec<DomainIndex>,
+    /// The tables whose base domains are on the worker. Bases hold the only copy of their rows,
+    /// and are not moved, so the worker cannot be stopped without losing them.
+    pub tables: Vec<String>,
+    /// Whether the worker runs no domains, and can be stopped without anything becoming
+    /// unavailable.
+    pub empty: bool,
+}
+
+/// How to spread domains onto a worker that has less load than the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Rebalance {
+    /// The most domains to move.
+    pub max_moves: usize,
+    /// Only work out which domains would be moved, without moving them.
+    pub dry_run: bool,
+}
+
+/// A domain that rebalancing moved, or would move, from one worker to another.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DomainMove {
+    /// The domain.
+    pub domain: DomainIndex,
+    /// The worker that runs the domain.
+    pub from: SocketAddr,
+    /// The worker that the domain is moved to.
+    pub to: SocketAddr,
+}
+
+/// What a backup of a deployment holds.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BackupSummary {
+    /// The version of the recipe that was backed up.
+    pub recipe_version: usize,
+    /// The number of rows backed up of each base table.
+    pub tables: BTreeMap<String, usize>,
+}
+
+/// The hash of a single value, before it is reduced to a shard.
 #[inline]
-pub fn shard_by(dt: &DataType, shards: usize) -> usize {
+fn value_hash<H: ShardHash + ?Sized>(hash: &H, dt: &DataType) -> u64 {
     match *dt {
-        DataType::Int(n) => n as usize % shards,
-        DataType::UnsignedInt(n) => n as usize % shards,
-        DataType::BigInt(n) => n as usize % shards,
-        DataType::UnsignedBigInt(n) => n as usize % shards,
-        DataType::Text(..) | DataType::TinyText(..) => {
-            use std::hash::Hasher;
-            let mut hasher = ahash::AHasher::new_with_keys(0x3306, 0x6033);
+        // booleans must go to the same shard as the integers they are equal to
+        DataType::Bool(b) => hash.hash_int(i128::from(b)),
+        DataType::Int(n) => hash.hash_int(i128::from(n)),
+        DataType::UnsignedInt(n) => hash.hash_int(i128::from(n)),
+        DataType::BigInt(n) => hash.hash_int(i128::from(n)),
+        DataType::UnsignedBigInt(n) => hash.hash_int(i128::from(n)),
+        DataType::HugeInt(ref n) => hash.hash_int(**n),
+        DataType::CiText(..) => {
+            // strings that are equal under a case-insensitive collation must go to the same shard
+            let s: &str = dt.into();
+            hash.hash_bytes(data::case_fold(s).as_bytes())
+        }
+        DataType::Text(..) | DataType::TinyText(..) | DataType::Json(..) => {
             let s: &str = dt.into();
-            hasher.write(s.as_bytes());
-            hasher.finish() as usize % shards
+            hash.hash_bytes(s.as_bytes())
+        }
+        DataType::Decimal(mut m, mut scale) => {
+            // decimals that are equal to an integer must go to the same shard as it
+            while scale > 0 && m % 10 == 0 {
+                m /= 10;
+                scale -= 1;
+            }
+            if scale == 0 {
+                hash.hash_int(i128::from(m))
+            } else {
+                let mut bytes = m.to_le_bytes().to_vec();
+                bytes.push(scale);
+                hash.hash_bytes(&bytes)
+            }
         }
+        DataType::Bytes(ref b) => hash.hash_bytes(&b[..]),
+        // UUIDs are often time-based, so their raw bytes don't spread evenly over shards
+        DataType::Uuid(ref b) => hash.hash_bytes(&b[..]),
         // a bit hacky: send all NULL values to the first shard
         DataType::None => 0,
         ref x => {
@@ -350,3 +638,84 @@ pub fn shard_by(dt: &DataType, shards: usize) -> usize {
         }
     }
 }
+
+#[doc(hidden)]
+#[inline]
+pub fn shard_by<H: ShardHash + ?Sized>(hash: &H, dt: &DataType, shards: usize) -> usize {
+    (value_hash(hash, dt) % shards as u64) as usize
+}
+
+/// Compute the shard that a (possibly compound) key belongs to, out of `shards` shards.
+///
+/// With the shard hash and number of shards that a base table is sharded with, this is the shard
+/// that writes with the given key go to, so writes can be partitioned the same way outside of
+/// Noria. A single-column key goes by the hash of its value; for a key over several columns, the
+/// hashes of its values are combined with `ShardHash::hash_compound`, so values that compare
+/// equal (e.g., `1` and `TRUE`) still land on the same shard.
+#[inline]
+pub fn shard_by_key<H: ShardHash + ?Sized>(hash: &H, key: &[DataType], shards: usize) -> usize {
+    if key.len() == 1 {
+        return shard_by(hash, &key[0], shards);
+    }
+
+    let hashes: Vec<u64> = key.iter().map(|dt| value_hash(hash, dt)).collect();
+    (hash.hash_compound(&hashes) % shards as u64) as usize
+}
+
+/// Pick the shard for a value of a range-sharded column.
+///
+/// Shard `i` holds the values from `boundaries[i - 1]` up to, but not including, `boundaries[i]`,
+/// so a value that is exactly on a boundary always goes to the shard above it.
+#[doc(hidden)]
+#[inline]
+pub fn shard_by_range(dt: &DataType, boundaries: &[DataType]) -> usize {
+    match boundaries.binary_search(dt) {
+        Ok(i) => i + 1,
+        Err(i) => i,
+    }
+}
+
+/// The shards of a range-sharded column that may hold values in `range`, in key order.
+#[doc(hidden)]
+pub fn shards_in_range(range: &KeyRange, boundaries: &[DataType]) -> std::ops::Range<usize> {
+    use std::ops::Bound;
+    let (lo, hi) = range.bounds();
+    let first = match lo {
+        Bound::Included(v) | Bound::Excluded(v) => shard_by_range(v, boundaries),
+        Bound::Unbounded => 0,
+    };
+    let last = match hi {
+        Bound::Included(v) => shard_by_range(v, boundaries),
+        // if `v` is a boundary, the shard it starts holds nothing below it
+        Bound::Excluded(v) => match boundaries.binary_search(v) {
+            Ok(i) | Err(i) => i,
+        },
+        Bound::Unbounded => boundaries.len(),
+    };
+    first..(last + 1).max(first)
+}
+
+/// Choose boundaries that split a sample of a column's values into `shards` ranges that each hold
+/// about the same number of the sampled values.
+///
+/// The result can be used to range shard a base table, or to re-split one whose shards have
+/// become skewed. Fewer boundaries are returned if the sample has too few distinct values to split
+/// it that many ways.
+pub fn range_boundaries(mut sample: Vec<DataType>, shards: usize) -> Vec<DataType> {
+    sample.sort();
+    let mut boundaries: Vec<DataType> = Vec::with_capacity(shards.saturating_sub(1));
+    if sample.is_empty() {
+        return boundaries;
+    }
+    for i in 1..shards {
+        let b = &sample[i * sample.len() / shards];
+        if boundaries
+            .last()
+            .map(|last| last < b)
+            .unwrap_or(b > &sample[0])
+        {
+            boundaries.push(b.clone());
+        }
+    }
+    boundaries
+}
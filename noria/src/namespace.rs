@@ -0,0 +1,79 @@
+//! Namespaces that keep the tables and views of several applications apart.
+//!
+//! A recipe can be installed into a namespace, and a [`ControllerHandle`](crate::ControllerHandle)
+//! that is put [`in_namespace`](crate::ControllerHandle::in_namespace) only sees the tables and
+//! views of that namespace. Two namespaces can therefore both have, say, an `Article` table,
+//! and nothing from one namespace can refer to anything in another.
+//!
+//! Internally, the controller qualifies the name of every table and view in a namespace as
+//! `namespace:name`. Names without a namespace are in the default namespace, which is what
+//! handles see unless they are put in another one.
+
+/// Separates the namespace from the name in a qualified name.
+///
+/// SQL identifiers cannot contain it, so qualified names never collide with unqualified ones.
+pub const SEPARATOR: char = ':';
+
+/// Check that `namespace` can be used as a namespace.
+///
+/// Like SQL identifiers, namespaces may only contain ASCII letters, digits and underscores.
+pub fn validate(namespace: &str) -> Result<(), String> {
+    if namespace.is_empty() {
+        return Err("namespaces cannot be empty".to_owned());
+    }
+    if !namespace
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(format!(
+            "namespace {} may only contain letters, digits and underscores",
+            namespace
+        ));
+    }
+    Ok(())
+}
+
+/// The name that `name` in `namespace` goes by internally.
+pub fn qualify(namespace: Option<&str>, name: &str) -> String {
+    match namespace {
+        None => name.to_owned(),
+        Some(ns) => format!("{}{}{}", ns, SEPARATOR, name),
+    }
+}
+
+/// Split a qualified name into its namespace and the name within that namespace.
+pub fn split(name: &str) -> (Option<&str>, &str) {
+    match name.find(SEPARATOR) {
+        None => (None, name),
+        Some(i) => (Some(&name[..i]), &name[i + 1..]),
+    }
+}
+
+/// The name within `namespace` of the qualified name `name`, if it is in that namespace.
+pub fn strip<'a>(namespace: Option<&str>, name: &'a str) -> Option<&'a str> {
+    match split(name) {
+        (ns, name) if ns == namespace => Some(name),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qualified_names() {
+        assert_eq!(qualify(None, "Article"), "Article");
+        assert_eq!(qualify(Some("blog"), "Article"), "blog:Article");
+        assert_eq!(split("blog:Article"), (Some("blog"), "Article"));
+        assert_eq!(split("Article"), (None, "Article"));
+        assert_eq!(strip(Some("blog"), "blog:Article"), Some("Article"));
+        assert_eq!(strip(None, "blog:Article"), None);
+        assert_eq!(strip(Some("shop"), "blog:Article"), None);
+        assert_eq!(strip(None, "Article"), Some("Article"));
+
+        assert!(validate("blog_2").is_ok());
+        assert!(validate("").is_err());
+        assert!(validate("blog:posts").is_err());
+    }
+}
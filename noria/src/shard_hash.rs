@@ -0,0 +1,149 @@
+use std::hash::Hasher;
+
+/// A hash function that picks the shard a key belongs to.
+///
+/// An integer key `n`, and any other value that compares equal to one (a boolean, or a decimal
+/// with no fractional part), goes to shard `hash_int(n) % shards`. Text and JSON go by
+/// `hash_bytes` of their UTF-8 bytes, except that text with a case-insensitive collation goes by
+/// the bytes of its Unicode case folding. Byte strings and UUIDs go by their raw bytes, and other
+/// decimals by the little-endian bytes of their mantissa followed by their scale, all with
+/// trailing zeroes removed from the mantissa. `NULL` always hashes to 0. The
+/// values of a compound key are hashed one by one, and their hashes combined with
+/// `hash_compound`.
+///
+/// Rows stay on the shard they were first written to, so an implementation must give the same
+/// hashes in every process, on every platform, and in every release.
+pub trait ShardHash {
+    /// Hash an integer.
+    ///
+    /// The default is the integer's low 64 bits, so that consecutive keys go to consecutive
+    /// shards.
+    fn hash_int(&self, n: i128) -> u64 {
+        n as u64
+    }
+
+    /// Hash the bytes of a value that is not an integer.
+    fn hash_bytes(&self, bytes: &[u8]) -> u64;
+
+    /// Combine the hashes of the values in a compound key.
+    ///
+    /// The default hashes the little-endian bytes of the hashes with `hash_bytes`.
+    fn hash_compound(&self, hashes: &[u64]) -> u64 {
+        self.hash_bytes(&le_bytes(hashes))
+    }
+}
+
+fn le_bytes(hashes: &[u64]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(hashes.len() * 8);
+    for h in hashes {
+        bytes.extend_from_slice(&h.to_le_bytes());
+    }
+    bytes
+}
+
+/// The shard hashes that a Noria deployment can be configured with.
+///
+/// The hash is chosen when a deployment is first started, and cannot be changed afterwards, since
+/// that would move keys to other shards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShardHasher {
+    /// SipHash-1-3 keyed with `k0` and `k1`.
+    ///
+    /// This is the default, keyed with `0x3306` and `0x6033`.
+    SipHash13 {
+        /// The first half of the key.
+        k0: u64,
+        /// The second half of the key.
+        k1: u64,
+    },
+    /// The `ahash` hash that deployments used before the shard hash could be configured.
+    ///
+    /// Its output depends on the version of the `ahash` crate, so it is only stable for as long as
+    /// that version is. It exists so that such deployments keep their shard assignments, which it
+    /// reproduces for every key they could be sharded by: integers, text, and `NULL`.
+    AHash,
+}
+
+impl Default for ShardHasher {
+    fn default() -> Self {
+        ShardHasher::SipHash13 {
+            k0: 0x3306,
+            k1: 0x6033,
+        }
+    }
+}
+
+impl ShardHasher {
+    /// The hash of deployments whose persisted configuration predates configurable shard hashes.
+    #[doc(hidden)]
+    pub fn unconfigured() -> Self {
+        ShardHasher::AHash
+    }
+}
+
+impl ShardHash for ShardHasher {
+    fn hash_bytes(&self, bytes: &[u8]) -> u64 {
+        match *self {
+            ShardHasher::SipHash13 { k0, k1 } => {
+                let mut hasher = siphasher::sip::SipHasher13::new_with_keys(k0, k1);
+                hasher.write(bytes);
+                hasher.finish()
+            }
+            ShardHasher::AHash => {
+                let mut hasher = ahash::AHasher::new_with_keys(0x3306, 0x6033);
+                hasher.write(bytes);
+                hasher.finish()
+            }
+        }
+    }
+
+    fn hash_compound(&self, hashes: &[u64]) -> u64 {
+        match *self {
+            ShardHasher::AHash => {
+                let mut hasher = ahash::AHasher::new_with_keys(0x3306, 0x6033);
+                for &h in hashes {
+                    hasher.write_usize(h as usize);
+                }
+                hasher.finish()
+            }
+            _ => self.hash_bytes(&le_bytes(hashes)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+
+    #[test]
+    fn ahash_keeps_legacy_placement() {
+        let legacy = |s: &str, shards: usize| {
+            let mut hasher = ahash::AHasher::new_with_keys(0x3306, 0x6033);
+            hasher.write(s.as_bytes());
+            hasher.finish() as usize % shards
+        };
+
+        let h = ShardHasher::AHash;
+        for shards in 1..8 {
+            for n in 0..100 {
+                assert_eq!(
+                    crate::shard_by(&h, &DataType::from(n), shards),
+                    n as usize % shards
+                );
+            }
+            for s in &[
+                "alice",
+                "Alice",
+                "a long string that does not fit inline",
+                "Straße",
+            ] {
+                assert_eq!(
+                    crate::shard_by(&h, &DataType::from(*s), shards),
+                    legacy(s, shards)
+                );
+            }
+            assert_eq!(crate::shard_by(&h, &DataType::None, shards), 0);
+        }
+    }
+}
@@ -1,8 +1,11 @@
-use crate::channel::CONNECTION_FROM_BASE;
+use crate::auth::{self, Scope};
+use crate::channel::tls::{self, MaybeTls, Tls};
+use crate::channel::{local, CONNECTION_FROM_BASE};
 use crate::data::*;
+use crate::debug::trace::TraceContext;
 use crate::internal::*;
 use crate::LocalOrNot;
-use crate::{Tagged, Tagger};
+use crate::{ShardHasher, Tagged, Tagger};
 use async_bincode::{AsyncBincodeStream, AsyncDestination};
 use futures_util::{
     future, future::TryFutureExt, ready, stream::futures_unordered::FuturesUnordered,
@@ -26,8 +29,8 @@ use tower_service::Service;
 use vec_map::VecMap;
 
 type Transport = AsyncBincodeStream<
-    tokio::net::TcpStream,
-    Tagged<()>,
+    MaybeTls<tokio::net::TcpStream>,
+    Tagged<WriteAck>,
     Tagged<LocalOrNot<Input>>,
     AsyncDestination,
 >;
@@ -104,6 +107,7 @@ macro_rules! row {
 
                     // Maybe we have a default value?
                     let mut allow_null = true;
+                    let mut auto_increment = false;
                     let spec = &schema.fields[coli];
                     for c in &spec.constraints {
                         use $crate::ColumnConstraint;
@@ -115,13 +119,14 @@ macro_rules! row {
                                 row[coli] = Into::<$crate::DataType>::into(literal);
                             }
                             ColumnConstraint::AutoIncrement => {
-                                // TODO
+                                // left as None so that the base table assigns a key
+                                auto_increment = true;
                             }
                             _ => {}
                         }
                     }
 
-                    if !allow_null && row[coli].is_none() {
+                    if !allow_null && !auto_increment && row[coli].is_none() {
                         panic!("Column {} is declared NOT NULL, has no default, and was not provided", cname);
                     }
                 }
@@ -202,7 +207,12 @@ async fn update_user(users: &mut Table) -> Result<(), TableError> {
 }
 
 #[derive(Debug)]
-struct Endpoint(SocketAddr);
+struct Endpoint {
+    addr: SocketAddr,
+    tls: Option<Tls>,
+    token: Option<String>,
+    scope: Scope,
+}
 
 type InnerService = multiplex::Client<
     multiplex::MultiplexTransport<Transport, Tagger>,
@@ -224,12 +234,17 @@ impl Service<()> for Endpoint {
     }
 
     fn call(&mut self, _: ()) -> Self::Future {
-        let f = tokio::net::TcpStream::connect(self.0);
+        let f = tokio::net::TcpStream::connect(self.addr);
+        let addr = self.addr;
+        let tls = self.tls.clone();
+        let token = self.token.clone();
+        let scope = self.scope.clone();
         async move {
-            let mut s = f.await?;
+            let s = f.await?;
             s.set_nodelay(true)?;
+            let mut s = tls::maybe_connect(tls.as_ref(), s, addr).await?;
             s.write_all(&[CONNECTION_FROM_BASE]).await.unwrap();
-            s.flush().await.unwrap();
+            auth::present(&mut s, token.as_deref(), &scope).await?;
             let s = AsyncBincodeStream::from(s).for_async();
             let t = multiplex::MultiplexTransport::new(s, Tagger::default());
             Ok(multiplex::Client::with_error_handler(t, |e| {
@@ -241,6 +256,9 @@ impl Service<()> for Endpoint {
 
 fn make_table_stream(
     addr: SocketAddr,
+    tls: Option<Tls>,
+    token: Option<String>,
+    scope: Scope,
 ) -> impl futures_util::stream::TryStream<
     Ok = tower_discover::Change<usize, InnerService>,
     Error = tokio::io::Error,
@@ -248,15 +266,28 @@ fn make_table_stream(
     // TODO: use whatever comes out of https://github.com/tower-rs/tower/issues/456 instead of
     // creating _all_ the connections every time.
     (0..crate::TABLE_POOL_SIZE)
-        .map(|i| async move {
-            let svc = Endpoint(addr).call(()).await?;
-            Ok(tower_discover::Change::Insert(i, svc))
+        .map(|i| {
+            let mut endpoint = Endpoint {
+                addr,
+                tls: tls.clone(),
+                token: token.clone(),
+                scope: scope.clone(),
+            };
+            async move {
+                let svc = endpoint.call(()).await?;
+                Ok(tower_discover::Change::Insert(i, svc))
+            }
         })
         .collect::<futures_util::stream::FuturesUnordered<_>>()
 }
 
-fn make_table_discover(addr: SocketAddr) -> Discover {
-    ServiceStream::new(make_table_stream(addr))
+fn make_table_discover(
+    addr: SocketAddr,
+    tls: Option<Tls>,
+    token: Option<String>,
+    scope: Scope,
+) -> Discover {
+    ServiceStream::new(make_table_stream(addr, tls, token, scope))
 }
 
 // Unpin + Send bounds are needed due to https://github.com/rust-lang/rust/issues/55997
@@ -289,11 +320,150 @@ pub enum TableError {
     )]
     WrongKeyColumnCount(usize, usize),
 
+    /// Inserts whose key the table already held were rejected.
+    ///
+    /// Holds the positions of the rejected inserts among the operations that were written. All
+    /// other operations were applied.
+    #[fail(
+        display = "inserts at positions {:?} have a key that already exists",
+        _0
+    )]
+    DuplicateKey(Vec<usize>),
+
+    /// Writes with a value that their column does not accept, such as malformed JSON, were
+    /// rejected.
+    ///
+    /// Holds the positions of the rejected writes among the operations that were written. All
+    /// other operations were applied. Operations that were sent to a base shard along with one
+    /// that carries a blob larger than the base allows are all rejected with it.
+    #[fail(
+        display = "writes at positions {:?} have a value their column does not accept",
+        _0
+    )]
+    InvalidValue(Vec<usize>),
+
+    /// A value given for a UUID column is neither a UUID nor the text form of one.
+    ///
+    /// Holds the name of the column and the value. Nothing was written.
+    #[fail(display = "column {} holds UUIDs, but was given {:?}", _0, _1)]
+    InvalidUuid(String, DataType),
+
+    /// Writes were turned away because Noria is not keeping up with the writes it has already
+    /// accepted. They can be retried once it catches up.
+    ///
+    /// Holds the positions of the writes that were not applied among the operations that were
+    /// written. All other operations were applied.
+    #[fail(
+        display = "writes at positions {:?} were not applied because Noria is overloaded; retry later",
+        _0
+    )]
+    Overloaded(Vec<usize>),
+
     /// The underlying connection to Noria produced an error.
     #[fail(display = "{}", _0)]
     TransportError(#[cause] failure::Error),
 }
 
+/// A failed [`ControllerHandle::commit`](crate::ControllerHandle::commit).
+#[derive(Debug, Fail)]
+pub enum TransactionError {
+    /// The transaction could not begin because a table it writes to was being changed, such as by
+    /// a bulk load. Nothing was written, and the transaction can be retried once the change is
+    /// done.
+    #[fail(display = "transaction conflicts with a change in progress: {}", _0)]
+    Conflict(String),
+
+    /// The controller could not be reached to begin or to commit the transaction. Nothing was
+    /// written, and the transaction can be retried.
+    #[fail(display = "transaction coordinator unavailable: {}", _0)]
+    CoordinatorUnavailable(#[cause] failure::Error),
+
+    /// The controller refused the transaction, for example because a table it writes to has been
+    /// dropped, or because the client and the controller speak different versions of the
+    /// transaction protocol. Nothing was written.
+    #[fail(display = "transaction rejected: {}", _0)]
+    Rejected(String),
+
+    /// A table rejected some of the writes made to it, because they do not fit the table or
+    /// break one of its constraints. Nothing was written.
+    #[fail(display = "{}", _0)]
+    ConstraintViolation(#[cause] TableError),
+
+    /// The transaction was called off before it was committed, for example because the writes to
+    /// a table could not be delivered, or because the table was busy with another transaction.
+    /// Nothing was written, and the transaction can be retried.
+    #[fail(display = "transaction aborted: {}", _0)]
+    Aborted(#[cause] failure::Error),
+
+    /// The controller could not be heard back from while committing the transaction, so it may or
+    /// may not have been committed. If it was, all of it is applied.
+    #[fail(display = "transaction outcome unknown: {}", _0)]
+    InDoubt(#[cause] failure::Error),
+}
+
+impl TransactionError {
+    /// Returns true if nothing the transaction wrote was applied, so that it is safe to commit it
+    /// again, and doing so later may succeed.
+    pub fn is_retryable(&self) -> bool {
+        match *self {
+            TransactionError::Conflict(_)
+            | TransactionError::CoordinatorUnavailable(_)
+            | TransactionError::Aborted(_) => true,
+            TransactionError::Rejected(_)
+            | TransactionError::ConstraintViolation(_)
+            | TransactionError::InDoubt(_) => false,
+        }
+    }
+}
+
+impl From<TableError> for TransactionError {
+    fn from(e: TableError) -> Self {
+        match e {
+            TableError::Overloaded(_) | TableError::TransportError(_) => {
+                TransactionError::Aborted(e.into())
+            }
+            e => TransactionError::ConstraintViolation(e),
+        }
+    }
+}
+
+/// The version of the messages that clients and the controller exchange to begin a transaction.
+///
+/// Bumped whenever they change in a way that older clients or controllers cannot understand.
+#[doc(hidden)]
+pub const TRANSACTION_PROTOCOL: u32 = 2;
+
+/// A request to begin a transaction that writes to the given bases.
+#[doc(hidden)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BeginTransaction {
+    /// The [`TRANSACTION_PROTOCOL`] that the client speaks.
+    pub version: u32,
+    pub bases: Vec<NodeIndex>,
+}
+
+/// The controller's answer to a [`BeginTransaction`].
+#[doc(hidden)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TransactionBegun {
+    /// The transaction has begun, with the given commit timestamp.
+    Begun(u64),
+    /// A table the transaction writes to is being changed.
+    Conflict(String),
+    /// The transaction cannot be run.
+    Rejected(String),
+}
+
+/// The controller's answer to a request to commit a transaction.
+#[doc(hidden)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TransactionDecided {
+    /// Every table applies its part of the transaction.
+    Committed,
+    /// No table applies its part of the transaction.
+    Aborted(String),
+}
+
 impl From<Box<dyn std::error::Error + Send + Sync>> for TableError {
     fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
         TableError::TransportError(failure::Error::from_boxed_compat(e))
@@ -305,6 +475,91 @@ impl From<Box<dyn std::error::Error + Send + Sync>> for TableError {
 pub struct Input {
     pub dst: LocalNodeIndex,
     pub data: Vec<TableOperation>,
+    /// The writer wants a [`WriteToken`] for this write, so every reader downstream of the base
+    /// must learn when the write has been applied, even if none of its rows reach that reader.
+    pub tracked: bool,
+    /// The tag that the events this write causes in the dataflow are recorded under, if the
+    /// writer asked for the write to be traced.
+    #[serde(default)]
+    pub trace: Option<u64>,
+    /// When the write arrived at its base table's domain, in nanoseconds since the Unix epoch,
+    /// if it was sampled for measuring how long writes take to reach readers.
+    #[serde(default)]
+    pub origin: Option<u64>,
+    /// The transaction this write is one part of, if it was made through
+    /// [`ControllerHandle::commit`](crate::ControllerHandle::commit).
+    #[serde(default)]
+    pub txn: Option<u64>,
+    /// The id the writer gave this write so that the base applies it only once, if it was made
+    /// through [`Table::perform_all_once`].
+    #[serde(default)]
+    pub op: Option<u64>,
+    /// The span of a distributed trace that this write is part of, if the writer set one with
+    /// [`Table::set_trace_context`].
+    #[serde(default)]
+    pub context: Option<TraceContext>,
+    /// When the writer sent this write, in nanoseconds since the Unix epoch on the writer's clock,
+    /// if it is part of a distributed trace.
+    #[serde(default)]
+    pub sent: u64,
+}
+
+/// The acknowledgement a base table shard sends once a batch of writes has been applied.
+#[doc(hidden)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WriteAck {
+    /// The label of the batch the writes were processed in.
+    pub label: u64,
+    /// The keys assigned to auto-increment inserts, in the order the inserts were sent.
+    pub ids: Vec<DataType>,
+    /// The positions of the inserts that were rejected because their key already existed.
+    pub rejected: Vec<usize>,
+    /// The positions of the conditional updates that did not apply.
+    pub unapplied: Vec<usize>,
+    /// The number of rows removed by filtered deletes.
+    pub deleted: usize,
+    /// The positions of the writes that were rejected because of a value their column does not
+    /// accept.
+    pub invalid: Vec<usize>,
+    /// The positions of the writes that were turned away without being applied because the base
+    /// was overloaded, or was not accepting the part of a transaction they belong to.
+    #[serde(default)]
+    pub overloaded: Vec<usize>,
+}
+
+/// The outcome of a write, gathered from every shard it was sent to.
+struct WriteResult {
+    token: WriteToken,
+    /// The keys assigned to auto-increment inserts, in the order the inserts were issued.
+    ids: Vec<DataType>,
+    /// The positions of the conditional updates that did not apply, in the original write.
+    unapplied: Vec<usize>,
+    /// The number of rows removed by filtered deletes, across all shards.
+    deleted: usize,
+}
+
+/// The most rows a filtered delete removes from each shard in a single write.
+const DELETE_WHERE_CHUNK: usize = 1024;
+
+/// The value a filtered delete requires the given column to be equal to, if any.
+fn required_value(conditions: &[(usize, ColumnCondition)], col: usize) -> Option<&DataType> {
+    conditions.iter().find_map(|&(c, ref cond)| match *cond {
+        ColumnCondition::Equal(ref v) if c == col => Some(v),
+        _ => None,
+    })
+}
+
+/// A trace tag that is very unlikely to be in use by another client.
+fn new_trace_tag() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hash, Hasher};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let mut h = RandomState::new().build_hasher();
+    NEXT.fetch_add(1, Ordering::Relaxed).hash(&mut h);
+    std::time::SystemTime::now().hash(&mut h);
+    h.finish()
 }
 
 impl fmt::Debug for Input {
@@ -312,19 +567,125 @@ impl fmt::Debug for Input {
         fmt.debug_struct("Input")
             .field("dst", &self.dst)
             .field("data", &self.data)
+            .field("tracked", &self.tracked)
+            .field("trace", &self.trace)
+            .field("origin", &self.origin)
+            .field("txn", &self.txn)
+            .field("op", &self.op)
+            .field("context", &self.context)
+            .field("sent", &self.sent)
             .finish()
     }
 }
 
+/// Identifies a set of writes made through [`Table`]s, so that later reads can be made to observe
+/// them.
+///
+/// Tokens are returned by [`Table::perform_all_with_token`], and can be passed to
+/// [`View::lookup_after`](crate::View::lookup_after) to read from a view only once the writes
+/// have been applied to it. Tokens for writes to different tables can be combined with
+/// [`WriteToken::merge`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteToken(pub(crate) Vec<(NodeIndex, usize, u64)>);
+
+impl WriteToken {
+    /// Extend this token so that it also covers the writes covered by `other`.
+    pub fn merge(&mut self, other: WriteToken) {
+        for (base, shard, label) in other.0 {
+            match self
+                .0
+                .iter_mut()
+                .find(|&&mut (b, s, _)| b == base && s == shard)
+            {
+                Some(&mut (_, _, ref mut l)) => *l = std::cmp::max(*l, label),
+                None => self.0.push((base, shard, label)),
+            }
+        }
+    }
+
+    /// Returns true if this token does not cover any writes.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// A set of writes to one or more base tables that views observe all at once.
+///
+/// Writes are collected with [`Transaction::perform`], and applied with
+/// [`ControllerHandle::commit`](crate::ControllerHandle::commit). Either every table applies its
+/// writes or none does, and no view that reads from more than one of the tables involved ever
+/// shows some of the writes without the others.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    pub(crate) writes: Vec<(Table, Vec<TableOperation>)>,
+}
+
+impl Transaction {
+    /// Start a transaction that does not write anything yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add operations on the base table behind `table` to this transaction.
+    pub fn perform<I, V>(&mut self, table: &Table, ops: I)
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        let ops = ops.into_iter().map(Into::into);
+        match self
+            .writes
+            .iter_mut()
+            .find(|&&mut (ref t, _)| t.ni == table.ni)
+        {
+            Some(&mut (_, ref mut existing)) => existing.extend(ops),
+            None => self.writes.push((table.clone(), ops.collect())),
+        }
+    }
+
+    /// Add an insert of a single row into the base table behind `table` to this transaction.
+    pub fn insert<V>(&mut self, table: &Table, row: V)
+    where
+        V: Into<Vec<DataType>>,
+    {
+        self.perform(table, vec![TableOperation::Insert(row.into())]);
+    }
+
+    /// Returns true if this transaction does not write anything.
+    pub fn is_empty(&self) -> bool {
+        self.writes.iter().all(|&(_, ref ops)| ops.is_empty())
+    }
+}
+
+/// The outcome of a committed [`Transaction`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Committed {
+    /// When the transaction was committed, in nanoseconds since the Unix epoch.
+    ///
+    /// Timestamps are unique, and increase in the order transactions are committed in.
+    pub timestamp: u64,
+    /// Identifies the writes the transaction made, for use with
+    /// [`View::lookup_after`](crate::View::lookup_after).
+    pub token: WriteToken,
+}
+
 #[doc(hidden)]
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TableBuilder {
     pub txs: Vec<SocketAddr>,
+    /// Whether each of `txs` expects clients to connect over TLS.
+    #[serde(default)]
+    pub txs_tls: Vec<bool>,
     pub ni: NodeIndex,
     pub addr: LocalNodeIndex,
     pub key_is_primary: bool,
     pub key: Vec<usize>,
     pub dropped: VecMap<DataType>,
+    pub auto_increment: bool,
+    /// The boundaries between the base's shards, if it is sharded by ranges of `key[0]`.
+    pub shard_ranges: Option<Vec<DataType>>,
+    /// The hash that picks the base's shard for a key, if it is not sharded by ranges.
+    pub shard_hasher: ShardHasher,
 
     pub table_name: String,
     pub columns: Vec<String>,
@@ -334,25 +695,43 @@ pub struct TableBuilder {
 impl TableBuilder {
     pub(crate) fn build(
         self,
-        rpcs: Arc<Mutex<HashMap<(SocketAddr, usize), TableRpc>>>,
+        rpcs: Arc<Mutex<HashMap<(SocketAddr, usize, Option<Scope>), TableRpc>>>,
+        tls: Option<&Tls>,
+        token: Option<&str>,
     ) -> Result<Table, io::Error> {
+        let (namespace, name) = crate::namespace::split(&self.table_name);
+        let scope = Scope::Table {
+            namespace: namespace.map(String::from),
+            name: name.to_owned(),
+        };
+        // workers only serve a table over connections that were opened for it once they check
+        // tokens, so only handles without a token can share connections between tables
+        let shared_as = token.map(|_| scope.clone());
+
         let mut addrs = Vec::with_capacity(self.txs.len());
         let mut conns = Vec::with_capacity(self.txs.len());
         for (shardi, &addr) in self.txs.iter().enumerate() {
             use std::collections::hash_map::Entry;
 
             addrs.push(addr);
+            let expects_tls = self.txs_tls.get(shardi).cloned().unwrap_or(false);
+            let shard_tls = tls::for_endpoint(tls, addr, expects_tls)?.cloned();
 
             // one entry per shard so that we can send sharded requests in parallel even if
             // they happen to be targeting the same machine.
             let mut rpcs = rpcs.lock().unwrap();
-            let s = match rpcs.entry((addr, shardi)) {
+            let s = match rpcs.entry((addr, shardi, shared_as.clone())) {
                 Entry::Occupied(e) => e.get().clone(),
                 Entry::Vacant(h) => {
                     // TODO: maybe always use the same local port?
                     let (c, w) = Buffer::pair(
                         ConcurrencyLimit::new(
-                            Balance::from_entropy(make_table_discover(addr)),
+                            Balance::from_entropy(make_table_discover(
+                                addr,
+                                shard_tls,
+                                token.map(String::from),
+                                scope.clone(),
+                            )),
                             crate::PENDING_LIMIT,
                         ),
                         crate::BUFFER_TO_POOL,
@@ -370,17 +749,24 @@ impl TableBuilder {
             conns.push(s);
         }
 
+        // writes to a base whose shards are all in this process need not be serialized
+        let dst_is_local = self.txs.iter().all(local::is_local);
         let dispatch = tracing::dispatcher::get_default(|d| d.clone());
         Ok(Table {
             ni: self.ni,
             node: self.addr,
             key: self.key,
             key_is_primary: self.key_is_primary,
+            auto_increment: self.auto_increment,
+            next_auto_shard: 0,
+            shard_ranges: self.shard_ranges,
+            shard_hasher: self.shard_hasher,
             columns: self.columns,
             dropped: self.dropped,
             table_name: self.table_name,
             schema: self.schema,
-            dst_is_local: false,
+            dst_is_local,
+            context: None,
 
             shard_addrs: addrs,
             shards: conns,
@@ -402,11 +788,16 @@ pub struct Table {
     node: LocalNodeIndex,
     key_is_primary: bool,
     key: Vec<usize>,
+    auto_increment: bool,
+    next_auto_shard: usize,
+    shard_ranges: Option<Vec<DataType>>,
+    shard_hasher: ShardHasher,
     columns: Vec<String>,
     dropped: VecMap<DataType>,
     table_name: String,
     schema: Option<CreateTableStatement>,
     dst_is_local: bool,
+    context: Option<TraceContext>,
 
     shards: Vec<TableRpc>,
     shard_addrs: Vec<SocketAddr>,
@@ -421,22 +812,162 @@ impl fmt::Debug for Table {
             .field("node", &self.node)
             .field("key_is_primary", &self.key_is_primary)
             .field("key", &self.key)
+            .field("auto_increment", &self.auto_increment)
+            .field("shard_ranges", &self.shard_ranges)
+            .field("shard_hasher", &self.shard_hasher)
             .field("columns", &self.columns)
             .field("dropped", &self.dropped)
             .field("table_name", &self.table_name)
             .field("schema", &self.schema)
             .field("dst_is_local", &self.dst_is_local)
+            .field("context", &self.context)
             .field("shard_addrs", &self.shard_addrs)
             .finish()
     }
 }
 
 impl Table {
+    /// Check that the given operations fit this table's columns and key.
+    fn validate(&self, ops: &[TableOperation]) -> Result<(), TableError> {
+        let ncols = self.columns.len() + self.dropped.len();
+        for op in ops {
+            match op {
+                TableOperation::Insert(ref row) | TableOperation::Upsert(ref row) => {
+                    if row.len() != ncols {
+                        return Err(TableError::WrongColumnCount(ncols, row.len()));
+                    }
+                }
+                TableOperation::Delete { ref key } => {
+                    if key.len() != self.key.len() {
+                        return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
+                    }
+                }
+                TableOperation::InsertOrUpdate {
+                    ref row,
+                    ref update,
+                } => {
+                    if row.len() != ncols {
+                        return Err(TableError::WrongColumnCount(ncols, row.len()));
+                    }
+                    if update.len() > self.columns.len() {
+                        // NOTE: < is okay to allow dropping tailing no-ops
+                        return Err(TableError::WrongColumnCount(
+                            self.columns.len(),
+                            update.len(),
+                        ));
+                    }
+                }
+                TableOperation::DeleteWhere { ref conditions, .. } => {
+                    if let Some(&(coli, _)) = conditions
+                        .iter()
+                        .find(|&&(coli, _)| coli >= self.columns.len())
+                    {
+                        return Err(TableError::WrongColumnCount(self.columns.len(), coli + 1));
+                    }
+                }
+                TableOperation::Update { ref set, ref key }
+                | TableOperation::ConditionalUpdate {
+                    ref set, ref key, ..
+                } => {
+                    if key.len() != self.key.len() {
+                        return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
+                    }
+                    if set.len() > self.columns.len() {
+                        // NOTE: < is okay to allow dropping tailing no-ops
+                        return Err(TableError::WrongColumnCount(self.columns.len(), set.len()));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Turn the values that the given operations give for columns of this table into the form
+    /// the columns hold their values in, such as UUIDs given in their text form.
+    fn coerce(&self, ops: &mut [TableOperation]) -> Result<(), TableError> {
+        let schema = match self.schema {
+            Some(ref schema) => schema,
+            None => return Ok(()),
+        };
+        let coerce = |coli: usize, v: &mut DataType| match schema.fields.get(coli) {
+            Some(spec) => v
+                .coerce_to_column(spec)
+                .map_err(|_| TableError::InvalidUuid(spec.column.name.clone(), v.clone())),
+            None => Ok(()),
+        };
+        let coerce_key = |key: &mut [DataType]| {
+            key.iter_mut()
+                .zip(&self.key)
+                .try_for_each(|(v, &coli)| coerce(coli, v))
+        };
+        let coerce_set = |set: &mut [Modification]| {
+            set.iter_mut()
+                .enumerate()
+                .try_for_each(|(coli, m)| match *m {
+                    Modification::Set(ref mut v) => coerce(coli, v),
+                    Modification::Apply(..) | Modification::None => Ok(()),
+                })
+        };
+
+        for op in ops {
+            match *op {
+                TableOperation::Insert(ref mut row) | TableOperation::Upsert(ref mut row) => {
+                    row.iter_mut()
+                        .enumerate()
+                        .try_for_each(|(coli, v)| coerce(coli, v))?;
+                }
+                TableOperation::InsertOrUpdate {
+                    ref mut row,
+                    ref mut update,
+                } => {
+                    row.iter_mut()
+                        .enumerate()
+                        .try_for_each(|(coli, v)| coerce(coli, v))?;
+                    coerce_set(update)?;
+                }
+                TableOperation::Delete { ref mut key } => coerce_key(key)?,
+                TableOperation::Update {
+                    ref mut set,
+                    ref mut key,
+                } => {
+                    coerce_set(set)?;
+                    coerce_key(key)?;
+                }
+                TableOperation::ConditionalUpdate {
+                    ref mut key,
+                    ref mut expected,
+                    ref mut set,
+                } => {
+                    coerce_key(key)?;
+                    for (coli, v) in expected.iter_mut() {
+                        coerce(*coli, v)?;
+                    }
+                    coerce_set(set)?;
+                }
+                TableOperation::DeleteWhere {
+                    ref mut conditions, ..
+                } => {
+                    for (coli, condition) in conditions.iter_mut() {
+                        match *condition {
+                            ColumnCondition::Equal(ref mut v) => coerce(*coli, v)?,
+                            ColumnCondition::InRange(ref mut range) => {
+                                for v in range.values_mut() {
+                                    coerce(*coli, v)?;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     #[allow(clippy::cognitive_complexity)]
     fn input(
         &mut self,
         mut i: Input,
-    ) -> impl Future<Output = Result<Tagged<()>, TableError>> + Send {
+    ) -> impl Future<Output = Result<WriteResult, TableError>> + Send {
         let span = if crate::trace_next_op() {
             Some(tracing::trace_span!(
                 "table-request",
@@ -446,56 +977,18 @@ impl Table {
             None
         };
 
-        // NOTE: this is really just a try block
-        let immediate_err = || {
-            let ncols = self.columns.len() + self.dropped.len();
-            for op in &i.data {
-                match op {
-                    TableOperation::Insert(ref row) => {
-                        if row.len() != ncols {
-                            return Err(TableError::WrongColumnCount(ncols, row.len()));
-                        }
-                    }
-                    TableOperation::Delete { ref key } => {
-                        if key.len() != self.key.len() {
-                            return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
-                        }
-                    }
-                    TableOperation::InsertOrUpdate {
-                        ref row,
-                        ref update,
-                    } => {
-                        if row.len() != ncols {
-                            return Err(TableError::WrongColumnCount(ncols, row.len()));
-                        }
-                        if update.len() > self.columns.len() {
-                            // NOTE: < is okay to allow dropping tailing no-ops
-                            return Err(TableError::WrongColumnCount(
-                                self.columns.len(),
-                                update.len(),
-                            ));
-                        }
-                    }
-                    TableOperation::Update { ref set, ref key } => {
-                        if key.len() != self.key.len() {
-                            return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
-                        }
-                        if set.len() > self.columns.len() {
-                            // NOTE: < is okay to allow dropping tailing no-ops
-                            return Err(TableError::WrongColumnCount(
-                                self.columns.len(),
-                                set.len(),
-                            ));
-                        }
-                    }
-                }
-            }
-            Ok(())
-        };
-
-        if let Err(e) = immediate_err() {
+        if let Err(e) = self
+            .validate(&i.data)
+            .and_then(|()| self.coerce(&mut i.data))
+        {
             return future::Either::Left(async move { Err(e) });
         }
+        if let Some(context) = self.context {
+            i.context = Some(context);
+            i.sent = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64);
+        }
 
         if self.shards.len() == 1 {
             let request = Tagged::from(if self.dst_is_local {
@@ -506,8 +999,27 @@ impl Table {
 
             let _guard = span.as_ref().map(tracing::Span::enter);
             tracing::trace!("submit request");
+            let base = self.ni;
             future::Either::Right(future::Either::Left(
-                self.shards[0].call(request).map_err(TableError::from),
+                self.shards[0]
+                    .call(request)
+                    .map_err(TableError::from)
+                    .and_then(move |ack| {
+                        future::ready(if !ack.v.overloaded.is_empty() {
+                            Err(TableError::Overloaded(ack.v.overloaded))
+                        } else if !ack.v.invalid.is_empty() {
+                            Err(TableError::InvalidValue(ack.v.invalid))
+                        } else if ack.v.rejected.is_empty() {
+                            Ok(WriteResult {
+                                token: WriteToken(vec![(base, 0, ack.v.label)]),
+                                ids: ack.v.ids,
+                                unapplied: ack.v.unapplied,
+                                deleted: ack.v.deleted,
+                            })
+                        } else {
+                            Err(TableError::DuplicateKey(ack.v.rejected))
+                        })
+                    }),
             ))
         } else {
             if self.key.is_empty() {
@@ -522,33 +1034,89 @@ impl Table {
             let _guard = span.as_ref().map(tracing::Span::enter);
             tracing::trace!("shard request");
             let mut shard_writes = vec![Vec::new(); self.shards.len()];
-            for r in i.data.drain(..) {
-                let shard = {
-                    let key = match r {
-                        TableOperation::Insert(ref r) => &r[key_col],
-                        TableOperation::Delete { ref key } => &key[0],
-                        TableOperation::Update { ref key, .. } => &key[0],
-                        TableOperation::InsertOrUpdate { ref row, .. } => &row[key_col],
-                    };
-                    crate::shard_by(key, self.shards.len())
+            // the position in the original write of each operation sent to a shard
+            let mut shard_positions = vec![Vec::new(); self.shards.len()];
+            // the shard each auto-increment insert was sent to, in insertion order
+            let mut auto_shards = Vec::new();
+            let op = i.op;
+            for (pos, r) in i.data.drain(..).enumerate() {
+                if let TableOperation::DeleteWhere { ref conditions, .. } = r {
+                    if required_value(conditions, key_col).is_none() {
+                        // the matching rows may live on any shard
+                        for (writes, positions) in
+                            shard_writes.iter_mut().zip(shard_positions.iter_mut())
+                        {
+                            writes.push(r.clone());
+                            positions.push(pos);
+                        }
+                        continue;
+                    }
+                }
+
+                let shard = match r {
+                    TableOperation::Insert(ref r)
+                        if self.auto_increment && r[key_col].is_none() =>
+                    {
+                        // the key is assigned by whichever shard receives the insert, so spread
+                        // those inserts evenly across the shards. a write that may be retried has
+                        // to reach the same shard every time, so that it is only applied once.
+                        let shard = match op {
+                            Some(op) => (op % self.shards.len() as u64) as usize,
+                            None => self.next_auto_shard,
+                        };
+                        self.next_auto_shard = (shard + 1) % self.shards.len();
+                        auto_shards.push(shard);
+                        shard
+                    }
+                    _ => {
+                        let key = match r {
+                            TableOperation::Insert(ref r) => &r[key_col],
+                            TableOperation::Delete { ref key } => &key[0],
+                            TableOperation::Update { ref key, .. } => &key[0],
+                            TableOperation::ConditionalUpdate { ref key, .. } => &key[0],
+                            TableOperation::InsertOrUpdate { ref row, .. } => &row[key_col],
+                            TableOperation::Upsert(ref r) => &r[key_col],
+                            TableOperation::DeleteWhere { ref conditions, .. } => {
+                                required_value(conditions, key_col).unwrap()
+                            }
+                        };
+                        self.shard_of(key)
+                    }
                 };
                 shard_writes[shard].push(r);
+                shard_positions[shard].push(pos);
             }
 
             let wait_for = FuturesUnordered::new();
             for (s, rs) in shard_writes.drain(..).enumerate() {
-                if !rs.is_empty() {
+                // every shard takes part in a transaction, so that readers waiting for it hear
+                // from all of them
+                if !rs.is_empty() || i.txn.is_some() {
                     let p = if self.dst_is_local {
                         unsafe {
                             LocalOrNot::for_local_transfer(Input {
                                 dst: i.dst,
                                 data: rs,
+                                tracked: i.tracked,
+                                trace: i.trace,
+                                origin: i.origin,
+                                txn: i.txn,
+                                op: i.op,
+                                context: i.context,
+                                sent: i.sent,
                             })
                         }
                     } else {
                         LocalOrNot::new(Input {
                             dst: i.dst,
                             data: rs,
+                            tracked: i.tracked,
+                            trace: i.trace,
+                            origin: i.origin,
+                            txn: i.txn,
+                            op: i.op,
+                            context: i.context,
+                            sent: i.sent,
                         })
                     };
                     let request = Tagged::from(p);
@@ -562,7 +1130,7 @@ impl Table {
                     let _guard = span.as_ref().map(tracing::Span::enter);
                     tracing::trace!("submit request shard");
 
-                    wait_for.push(self.shards[s].call(request));
+                    wait_for.push(self.shards[s].call(request).map_ok(move |ack| (s, ack.v)));
                 } else {
                     // poll_ready reserves a sender slot which we have to release
                     // we do that by dropping the old handle and replacing it with a clone
@@ -571,11 +1139,82 @@ impl Table {
                 }
             }
 
+            let base = self.ni;
             future::Either::Right(future::Either::Right(
                 wait_for
-                    .try_for_each(|_| async { Ok(()) })
+                    .try_collect::<Vec<_>>()
                     .map_err(TableError::from)
-                    .map_ok(Tagged::from),
+                    .and_then(move |acks| {
+                        let positions = &shard_positions;
+                        let mut overloaded: Vec<_> = acks
+                            .iter()
+                            .flat_map(|&(shard, ref ack)| {
+                                ack.overloaded.iter().map(move |&i| positions[shard][i])
+                            })
+                            .collect();
+                        if !overloaded.is_empty() {
+                            overloaded.sort_unstable();
+                            return future::ready(Err(TableError::Overloaded(overloaded)));
+                        }
+
+                        let mut invalid: Vec<_> = acks
+                            .iter()
+                            .flat_map(|&(shard, ref ack)| {
+                                ack.invalid.iter().map(move |&i| positions[shard][i])
+                            })
+                            .collect();
+                        if !invalid.is_empty() {
+                            invalid.sort_unstable();
+                            return future::ready(Err(TableError::InvalidValue(invalid)));
+                        }
+
+                        let mut rejected: Vec<_> = acks
+                            .iter()
+                            .flat_map(|&(shard, ref ack)| {
+                                ack.rejected.iter().map(move |&i| positions[shard][i])
+                            })
+                            .collect();
+                        if !rejected.is_empty() {
+                            rejected.sort_unstable();
+                            return future::ready(Err(TableError::DuplicateKey(rejected)));
+                        }
+
+                        let mut unapplied: Vec<_> = acks
+                            .iter()
+                            .flat_map(|&(shard, ref ack)| {
+                                ack.unapplied.iter().map(move |&i| positions[shard][i])
+                            })
+                            .collect();
+                        unapplied.sort_unstable();
+                        let deleted = acks.iter().map(|&(_, ref ack)| ack.deleted).sum();
+
+                        let token = WriteToken(
+                            acks.iter()
+                                .map(|&(shard, ref ack)| (base, shard, ack.label))
+                                .collect(),
+                        );
+
+                        // each shard reports its ids in the order it received the inserts, so
+                        // interleave them back into the order they were issued in.
+                        let mut ids: VecMap<_> = acks
+                            .into_iter()
+                            .map(|(shard, ack)| (shard, ack.ids.into_iter()))
+                            .collect();
+                        let ids = auto_shards
+                            .into_iter()
+                            .map(|shard| {
+                                ids.get_mut(shard)
+                                    .and_then(Iterator::next)
+                                    .expect("shard did not report an id for every insert")
+                            })
+                            .collect();
+                        future::ready(Ok(WriteResult {
+                            token,
+                            ids,
+                            unapplied,
+                            deleted,
+                        }))
+                    }),
             ))
         }
     }
@@ -583,7 +1222,7 @@ impl Table {
 
 impl Service<Vec<TableOperation>> for Table {
     type Error = TableError;
-    type Response = <TableRpc as Service<Tagged<LocalOrNot<Input>>>>::Response;
+    type Response = Tagged<()>;
 
     #[cfg(not(doc))]
     type Future = impl Future<Output = Result<Tagged<()>, TableError>> + Send;
@@ -599,7 +1238,7 @@ impl Service<Vec<TableOperation>> for Table {
 
     fn call(&mut self, ops: Vec<TableOperation>) -> Self::Future {
         let i = self.prep_records(ops);
-        self.input(i)
+        self.input(i).map_ok(|_| Tagged::from(()))
     }
 }
 
@@ -609,6 +1248,29 @@ impl Table {
         &self.table_name
     }
 
+    /// Get the shard that writes to rows with the given value in the table's key column go to.
+    ///
+    /// This lets rows be partitioned outside of Noria the same way Noria partitions them. It is
+    /// always 0 for a table that is not sharded.
+    pub fn shard_of(&self, key: &DataType) -> usize {
+        if self.shards.len() == 1 {
+            return 0;
+        }
+        match self.shard_ranges {
+            Some(ref boundaries) => crate::shard_by_range(key, boundaries),
+            None => crate::shard_by(&self.shard_hasher, key, self.shards.len()),
+        }
+    }
+
+    /// Make the writes made through this handle from now on part of the given span of a
+    /// distributed trace, or of none.
+    ///
+    /// Workers that export spans record each write's way through Noria as descendants of the
+    /// span. Clone the handle to make writes in different spans at the same time.
+    pub fn set_trace_context(&mut self, context: Option<TraceContext>) {
+        self.context = context;
+    }
+
     #[doc(hidden)]
     pub fn i_promise_dst_is_same_process(&mut self) {
         self.dst_is_local = true;
@@ -630,6 +1292,11 @@ impl Table {
         self.schema.as_ref()
     }
 
+    /// Whether the rows of this table are identified by a primary key, as upserts require.
+    pub fn has_primary_key(&self) -> bool {
+        !self.key.is_empty() && self.key_is_primary
+    }
+
     fn inject_dropped_cols(&self, r: &mut TableOperation) {
         use std::mem;
         let ndropped = self.dropped.len();
@@ -640,6 +1307,7 @@ impl Table {
             // get a handle to the underlying data vector
             let r = match *r {
                 TableOperation::Insert(ref mut row)
+                | TableOperation::Upsert(ref mut row)
                 | TableOperation::InsertOrUpdate { ref mut row, .. } => row,
                 _ => unimplemented!("we need to shift the update/delete cols!"),
             };
@@ -716,6 +1384,36 @@ impl Table {
         }
     }
 
+    /// Prepare the given operations to be written as this table's part of a transaction, checking
+    /// them before the transaction begins.
+    pub(crate) fn prepare_transaction(
+        &self,
+        ops: Vec<TableOperation>,
+    ) -> Result<Input, TableError> {
+        let mut i = self.prep_records(ops);
+        self.validate(&i.data)?;
+        self.coerce(&mut i.data)?;
+        i.tracked = true;
+        Ok(i)
+    }
+
+    /// Write this table's part of transaction `txn`, as prepared by
+    /// [`Table::prepare_transaction`].
+    pub(crate) async fn perform_transaction(
+        &mut self,
+        mut i: Input,
+        txn: u64,
+    ) -> Result<WriteToken, TableError> {
+        i.txn = Some(txn);
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        self.input(i).await.map(|r| r.token)
+    }
+
+    /// The index of the base table in the graph.
+    pub(crate) fn node(&self) -> NodeIndex {
+        self.ni
+    }
+
     fn prep_records(&self, mut ops: Vec<TableOperation>) -> Input {
         for r in &mut ops {
             self.inject_dropped_cols(r);
@@ -724,6 +1422,13 @@ impl Table {
         Input {
             dst: self.node,
             data: ops,
+            tracked: false,
+            trace: None,
+            origin: None,
+            txn: None,
+            op: None,
+            context: None,
+            sent: 0,
         }
     }
 
@@ -749,6 +1454,10 @@ impl Table {
     }
 
     /// Perform multiple operation on this base table.
+    ///
+    /// This resolves once the table has applied the operations. A table whose writes are
+    /// persisted has also synced them to disk by then, so acknowledged writes survive a crash.
+    /// Writes wait for up to the flush timeout to be persisted together with other writes.
     pub async fn perform_all<I, V>(&mut self, i: I) -> Result<(), TableError>
     where
         I: IntoIterator<Item = V>,
@@ -758,6 +1467,102 @@ impl Table {
             .await
     }
 
+    /// Perform multiple operation on this base table, and return a token that identifies them.
+    ///
+    /// Passing the token to [`View::lookup_after`](crate::View::lookup_after) makes the read wait
+    /// until the operations have been applied to the view. Tracking the writes has a small cost
+    /// at every operator downstream of this table, so only ask for a token if you need one.
+    pub async fn perform_all_with_token<I, V>(&mut self, i: I) -> Result<WriteToken, TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        let mut i = self.prep_records(i.into_iter().map(Into::into).collect());
+        i.tracked = true;
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        self.input(i).await.map(|r| r.token)
+    }
+
+    /// Perform multiple operation on this base table, and record the path they take through the
+    /// dataflow.
+    ///
+    /// The returned tag can be passed to [`ControllerHandle::trace`](crate::ControllerHandle::trace)
+    /// to fetch the events recorded so far, or to
+    /// [`ControllerHandle::wait_for_trace`](crate::ControllerHandle::wait_for_trace) to fetch them
+    /// once the operations have reached every view downstream of this table. Like
+    /// [`Table::perform_all_with_token`], this makes every operator downstream of this table
+    /// forward the update, so only trace writes that you are investigating.
+    pub async fn perform_all_traced<I, V>(&mut self, i: I) -> Result<u64, TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        let tag = new_trace_tag();
+        let mut i = self.prep_records(i.into_iter().map(Into::into).collect());
+        i.tracked = true;
+        i.trace = Some(tag);
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        self.input(i).await.map(|_| tag)
+    }
+
+    /// Perform multiple operations on this base table at most once.
+    ///
+    /// `op` identifies the operations, and is chosen by the caller. If operations with the same
+    /// `op` were applied to the table recently, these are dropped, and the call succeeds as though
+    /// they had been applied. This makes it safe to retry a write after an error that leaves it
+    /// unclear whether the write took effect, as long as the retry uses the same `op` and the
+    /// same operations. How many ids a table remembers, and for how long, is configured on the
+    /// server.
+    ///
+    /// A write that carries an id is not batched with other writes to the table.
+    pub async fn perform_all_once<I, V>(&mut self, op: u64, i: I) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        let mut i = self.prep_records(i.into_iter().map(Into::into).collect());
+        i.op = Some(op);
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        self.input(i).await.map(|_| ())
+    }
+
+    /// Insert a single row into this base table, and return the key the table assigned to it.
+    ///
+    /// The table must have been created with an auto-increment key, and the key column of `row`
+    /// must be `DataType::None`.
+    pub async fn insert_with_id<V>(&mut self, row: V) -> Result<DataType, TableError>
+    where
+        V: Into<Vec<DataType>>,
+    {
+        let mut ids = self.insert_all_with_ids(vec![row]).await?;
+        assert_eq!(ids.len(), 1);
+        Ok(ids.swap_remove(0))
+    }
+
+    /// Insert multiple rows into this base table, and return the keys the table assigned to them
+    /// in the order the rows were given.
+    ///
+    /// Rows whose key column is not `DataType::None` keep their key, and do not appear in the
+    /// returned list.
+    pub async fn insert_all_with_ids<I, V>(&mut self, rows: I) -> Result<Vec<DataType>, TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Vec<DataType>>,
+    {
+        assert!(
+            self.auto_increment,
+            "table {} does not have an auto-increment key",
+            self.table_name
+        );
+        let i = self.prep_records(
+            rows.into_iter()
+                .map(|r| TableOperation::Insert(r.into()))
+                .collect(),
+        );
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        self.input(i).await.map(|r| r.ids)
+    }
+
     /// Delete the row with the given key from this base table.
     pub async fn delete<I>(&mut self, key: I) -> Result<(), TableError>
     where
@@ -767,6 +1572,38 @@ impl Table {
             .await
     }
 
+    /// Delete every row of this base table that meets all of the given conditions, and return
+    /// how many rows were deleted.
+    ///
+    /// Each condition is given as a column index and the condition that column must meet. The
+    /// rows are deleted in bounded chunks, so that no single update carries all of them. If the
+    /// conditions require the table's key column to hold a particular value, only the shard that
+    /// holds that key is involved.
+    pub async fn delete_where<I>(&mut self, conditions: I) -> Result<usize, TableError>
+    where
+        I: IntoIterator<Item = (usize, ColumnCondition)>,
+    {
+        assert!(
+            !self.key.is_empty() && self.key_is_primary,
+            "filtered deletes can only be applied to base nodes with key columns"
+        );
+
+        let conditions: Vec<_> = conditions.into_iter().collect();
+        let mut deleted = 0;
+        loop {
+            let i = self.prep_records(vec![TableOperation::DeleteWhere {
+                conditions: conditions.clone(),
+                limit: DELETE_WHERE_CHUNK,
+            }]);
+            future::poll_fn(|cx| self.poll_ready(cx)).await?;
+            let n = self.input(i).await?.deleted;
+            if n == 0 {
+                return Ok(deleted);
+            }
+            deleted += n;
+        }
+    }
+
     /// Update the row with the given key in this base table.
     ///
     /// `u` is a set of column-modification pairs, where for each pair `(i, m)`, the modification
@@ -823,4 +1660,80 @@ impl Table {
         }])
         .await
     }
+
+    /// Update the row with the given key in this base table, but only if it currently holds the
+    /// value given in `expected` for each of the listed columns.
+    ///
+    /// `u` is as documented in `Table::update`. Returns whether the update was applied; it is not
+    /// applied if the values do not match or if there is no row with the given key.
+    pub async fn compare_and_swap<E, V>(
+        &mut self,
+        key: Vec<DataType>,
+        expected: E,
+        u: V,
+    ) -> Result<bool, TableError>
+    where
+        E: IntoIterator<Item = (usize, DataType)>,
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        assert!(
+            !self.key.is_empty() && self.key_is_primary,
+            "update operations can only be applied to base nodes with key columns"
+        );
+
+        let expected: Vec<_> = expected.into_iter().collect();
+        if let Some(&(coli, _)) = expected
+            .iter()
+            .find(|&&(coli, _)| coli >= self.columns.len())
+        {
+            return Err(TableError::WrongColumnCount(self.columns.len(), coli + 1));
+        }
+        let mut set = vec![Modification::None; self.columns.len()];
+        for (coli, m) in u {
+            if coli >= self.columns.len() {
+                return Err(TableError::WrongColumnCount(self.columns.len(), coli + 1));
+            }
+            set[coli] = m;
+        }
+
+        let i = self.prep_records(vec![TableOperation::ConditionalUpdate {
+            key,
+            expected,
+            set,
+        }]);
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        let r = self.input(i).await?;
+        Ok(r.unapplied.is_empty())
+    }
+
+    /// Insert a single row into this base table, replacing any existing row with the same key.
+    pub async fn upsert<V>(&mut self, row: V) -> Result<(), TableError>
+    where
+        V: Into<Vec<DataType>>,
+    {
+        self.upsert_many(vec![row]).await
+    }
+
+    /// Insert multiple rows into this base table, each replacing any existing row with the same
+    /// key.
+    ///
+    /// The rows are applied in the order they are given, so a later row replaces an earlier one
+    /// with the same key.
+    pub async fn upsert_many<I, V>(&mut self, rows: I) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Vec<DataType>>,
+    {
+        assert!(
+            !self.key.is_empty() && self.key_is_primary,
+            "upserts can only be applied to base nodes with key columns"
+        );
+
+        self.quick_n_dirty(
+            rows.into_iter()
+                .map(|r| TableOperation::Upsert(r.into()))
+                .collect::<Vec<_>>(),
+        )
+        .await
+    }
 }
@@ -1,9 +1,12 @@
+use crate::auth::{self, AuthError, Scope};
+use crate::channel::tls::{self, MaybeTls, Tls};
 use crate::data::*;
-use crate::{Tagged, Tagger};
+use crate::debug::trace::TraceContext;
+use crate::{ShardHasher, Tagged, Tagger, WriteToken};
 use async_bincode::{AsyncBincodeStream, AsyncDestination};
 use futures_util::{
     future, future::TryFutureExt, ready, stream::futures_unordered::FuturesUnordered,
-    stream::StreamExt, stream::TryStreamExt,
+    stream::FuturesOrdered, stream::StreamExt, stream::TryStreamExt,
 };
 use nom_sql::ColumnSpecification;
 use petgraph::graph::NodeIndex;
@@ -12,8 +15,10 @@ use std::fmt;
 use std::future::Future;
 use std::io;
 use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::Duration;
 use tokio_tower::multiplex;
 use tower_balance::p2c::Balance;
 use tower_buffer::Buffer;
@@ -22,14 +27,19 @@ use tower_limit::concurrency::ConcurrencyLimit;
 use tower_service::Service;
 
 type Transport = AsyncBincodeStream<
-    tokio::net::TcpStream,
+    MaybeTls<tokio::net::TcpStream>,
     Tagged<ReadReply>,
     Tagged<ReadQuery>,
     AsyncDestination,
 >;
 
 #[derive(Debug)]
-struct Endpoint(SocketAddr);
+struct Endpoint {
+    addr: SocketAddr,
+    tls: Option<Tls>,
+    token: Option<String>,
+    scope: Scope,
+}
 
 type InnerService = multiplex::Client<
     multiplex::MultiplexTransport<Transport, Tagger>,
@@ -51,10 +61,16 @@ impl Service<()> for Endpoint {
     }
 
     fn call(&mut self, _: ()) -> Self::Future {
-        let f = tokio::net::TcpStream::connect(self.0);
+        let f = tokio::net::TcpStream::connect(self.addr);
+        let addr = self.addr;
+        let tls = self.tls.clone();
+        let token = self.token.clone();
+        let scope = self.scope.clone();
         async move {
             let s = f.await?;
             s.set_nodelay(true)?;
+            let mut s = tls::maybe_connect(tls.as_ref(), s, addr).await?;
+            auth::present(&mut s, token.as_deref(), &scope).await?;
             let s = AsyncBincodeStream::from(s).for_async();
             let t = multiplex::MultiplexTransport::new(s, Tagger::default());
             Ok(multiplex::Client::with_error_handler(t, |e| {
@@ -66,6 +82,9 @@ impl Service<()> for Endpoint {
 
 fn make_views_stream(
     addr: SocketAddr,
+    tls: Option<Tls>,
+    token: Option<String>,
+    scope: Scope,
 ) -> impl futures_util::stream::TryStream<
     Ok = tower_discover::Change<usize, InnerService>,
     Error = tokio::io::Error,
@@ -73,15 +92,28 @@ fn make_views_stream(
     // TODO: use whatever comes out of https://github.com/tower-rs/tower/issues/456 instead of
     // creating _all_ the connections every time.
     (0..crate::VIEW_POOL_SIZE)
-        .map(|i| async move {
-            let svc = Endpoint(addr).call(()).await?;
-            Ok(tower_discover::Change::Insert(i, svc))
+        .map(|i| {
+            let mut endpoint = Endpoint {
+                addr,
+                tls: tls.clone(),
+                token: token.clone(),
+                scope: scope.clone(),
+            };
+            async move {
+                let svc = endpoint.call(()).await?;
+                Ok(tower_discover::Change::Insert(i, svc))
+            }
         })
         .collect::<futures_util::stream::FuturesUnordered<_>>()
 }
 
-fn make_views_discover(addr: SocketAddr) -> Discover {
-    ServiceStream::new(make_views_stream(addr))
+fn make_views_discover(
+    addr: SocketAddr,
+    tls: Option<Tls>,
+    token: Option<String>,
+    scope: Scope,
+) -> Discover {
+    ServiceStream::new(make_views_stream(addr, tls, token, scope))
 }
 
 // Unpin + Send bounds are needed due to https://github.com/rust-lang/rust/issues/55997
@@ -95,12 +127,68 @@ type Discover = crate::doc_mock::Discover<InnerService>;
 pub(crate) type ViewRpc =
     Buffer<ConcurrencyLimit<Balance<Discover, Tagged<ReadQuery>>>, Tagged<ReadQuery>>;
 
+/// Fetches a view's current `ViewBuilder` from the controller, or `None` if the view no longer
+/// exists.
+pub(crate) type ViewSource = Arc<
+    dyn Fn() -> Pin<Box<dyn Future<Output = Result<Option<ViewBuilder>, failure::Error>> + Send>>
+        + Send
+        + Sync,
+>;
+
 /// A failed [`View`] operation.
 #[derive(Debug, Fail)]
 pub enum ViewError {
     /// The given view is not yet available.
     #[fail(display = "the view is not yet available")]
     NotYetAvailable,
+    /// A subscription fell too far behind the view's changes, and was disconnected.
+    #[fail(display = "the subscription fell behind and was disconnected")]
+    SubscriptionLagged,
+    /// The writes a read had to observe did not reach the view in time.
+    #[fail(display = "timed out waiting for writes to reach the view")]
+    WriteTimeout,
+    /// The view did not catch up with its base tables closely enough for a read bounded in
+    /// staleness in time.
+    #[fail(display = "timed out waiting for the view to catch up")]
+    StaleTimeout,
+    /// A key was still being backfilled when the read's deadline passed.
+    #[fail(display = "the view is still warming up the requested key")]
+    StillWarming {
+        /// How long the replay that fills the key had been outstanding, if known.
+        outstanding: Option<Duration>,
+    },
+    /// A value given for a UUID column of the view's key is neither a UUID nor the text form of
+    /// one.
+    #[fail(display = "column {} holds UUIDs, but was given {:?}", _0, _1)]
+    InvalidUuid(String, DataType),
+    /// The view cannot serve prefix lookups, since its reader does not keep its keys in order.
+    #[fail(display = "the view does not support prefix lookups")]
+    PrefixNotSupported,
+    /// The view cannot serve range lookups, since its reader does not keep its keys in order.
+    #[fail(display = "the view does not support range lookups")]
+    RangeNotSupported,
+    /// A paginated lookup asked for pages that hold no rows.
+    #[fail(display = "pages must hold at least one row")]
+    EmptyPage,
+    /// A lookup gave values for a number of the view's key columns that it does not allow.
+    ///
+    /// Holds the number of values given, and the number of columns the view is keyed on.
+    #[fail(
+        display = "lookup gives {} values for a view keyed on {} columns",
+        _0, _1
+    )]
+    WrongKeyColumnCount(usize, usize),
+    /// The view's reader turned the read down because it does not fit the reader, such as after
+    /// the view was replaced with one that is keyed differently.
+    #[fail(display = "the read was rejected: {}", _0)]
+    Rejected(String),
+    /// A read was sent to a shard that has moved, or that does not hold the requested keys, and
+    /// the view's shards could not be found again.
+    #[fail(display = "the view's shards have moved")]
+    Redirected,
+    /// The view's shards moved, and the controller would no longer let this client read the view.
+    #[fail(display = "{}", _0)]
+    Unauthorized(#[cause] AuthError),
     /// A lower-level error occurred while communicating with Soup.
     #[fail(display = "{}", _0)]
     TransportError(#[cause] failure::Error),
@@ -124,11 +212,140 @@ pub enum ReadQuery {
         /// Whether to block if a partial replay is triggered
         block: bool,
     },
+    /// Read a range of keys from a leaf view with an ordered index
+    Range {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Exact values for all but the last key column
+        prefix: Vec<DataType>,
+        /// Range of values to read for the last key column
+        range: KeyRange,
+    },
+    /// Read all rows whose key starts with the given values from a leaf view with an ordered index
+    Prefix {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Exact values for the leading key columns
+        prefix: Vec<DataType>,
+    },
+    /// Read one page of the rows for a single key from a leaf view
+    Page {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Key to read with
+        key: Vec<DataType>,
+        /// Number of rows to skip
+        offset: usize,
+        /// Maximum number of rows to return
+        limit: usize,
+        /// Whether to block if a partial replay is triggered
+        block: bool,
+    },
+    /// Read from a leaf view once it reflects the writes identified by a write token
+    After {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Keys to read with
+        keys: Vec<Vec<DataType>>,
+        /// The label of the last write to each base table shard that the read must observe
+        token: Vec<(NodeIndex, usize, u64)>,
+        /// How long to wait for the writes to reach the view
+        timeout: Duration,
+    },
+    /// Read from a leaf view once it reflects every write applied to its base tables longer than
+    /// the given time ago
+    Fresh {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Keys to read with
+        keys: Vec<Vec<DataType>>,
+        /// How stale the read may be
+        staleness: Duration,
+        /// How long to wait for the view to catch up
+        timeout: Duration,
+    },
+    /// Read from a leaf view, blocking on partial replays for at most the given time
+    Deadline {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Keys to read with
+        keys: Vec<Vec<DataType>>,
+        /// How long to wait for missing keys to be backfilled
+        timeout: Duration,
+    },
+    /// Read the changes made to a leaf view since the given point in its change feed, waiting
+    /// for there to be some
+    Changes {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Where to read from in the feed, or `None` to subscribe to the feed from now on
+        from: Option<ChangeCursor>,
+        /// Only read changes to rows with this key
+        key: Option<Vec<DataType>>,
+        /// The most changes to read
+        limit: usize,
+        /// How long to wait for changes
+        wait: Duration,
+    },
     /// Read the size of a leaf view
     Size {
         /// Where to read from
         target: (NodeIndex, usize),
     },
+    /// Make a read that is part of a span of a distributed trace
+    Traced {
+        /// The span the read is part of
+        context: TraceContext,
+        /// When the client sent the read, in nanoseconds since the Unix epoch on its clock
+        sent: u64,
+        /// The read to make
+        query: Box<ReadQuery>,
+    },
+}
+
+impl ReadQuery {
+    /// The reader shard that the query reads from.
+    pub fn target(&self) -> (NodeIndex, usize) {
+        match *self {
+            ReadQuery::Normal { target, .. }
+            | ReadQuery::Range { target, .. }
+            | ReadQuery::Prefix { target, .. }
+            | ReadQuery::Page { target, .. }
+            | ReadQuery::After { target, .. }
+            | ReadQuery::Fresh { target, .. }
+            | ReadQuery::Deadline { target, .. }
+            | ReadQuery::Changes { target, .. }
+            | ReadQuery::Size { target } => target,
+            ReadQuery::Traced { ref query, .. } => query.target(),
+        }
+    }
+
+    /// The keys that the query looks up, if it looks up whole keys.
+    pub fn keys(&self) -> &[Vec<DataType>] {
+        match *self {
+            ReadQuery::Normal { ref keys, .. }
+            | ReadQuery::After { ref keys, .. }
+            | ReadQuery::Fresh { ref keys, .. }
+            | ReadQuery::Deadline { ref keys, .. } => keys,
+            ReadQuery::Page { ref key, .. } => std::slice::from_ref(key),
+            ReadQuery::Traced { ref query, .. } => query.keys(),
+            _ => &[],
+        }
+    }
+
+    /// Make the query part of the given span of a distributed trace, if any.
+    pub fn traced(self, context: Option<TraceContext>) -> Self {
+        match context {
+            Some(context) => ReadQuery::Traced {
+                context,
+                sent: std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_nanos() as u64),
+                query: Box::new(self),
+            },
+            None => self,
+        }
+    }
 }
 
 #[doc(hidden)]
@@ -136,30 +353,119 @@ pub enum ReadQuery {
 pub enum ReadReply<D = ReadReplyBatch> {
     /// Errors if view isn't ready yet.
     Normal(Result<Vec<D>, ()>),
+    /// The results of a non-blocking read that missed on some keys, along with the indices of the
+    /// keys that missed. Their entries are empty, and are being backfilled.
+    Partial(Vec<D>, Vec<usize>),
+    /// A page of rows, and the offset of the next page if there are more rows.
+    ///
+    /// Errors if view isn't ready yet.
+    Page(Result<(D, Option<usize>), ()>),
+    /// Changes to a view.
+    ///
+    /// Errors if view isn't ready yet.
+    Changes(Result<ChangeSet, ()>),
+    /// The view did not reflect the writes a read had to observe before its timeout expired.
+    TimedOut,
+    /// Some keys were still being backfilled when the read's deadline passed, along with how long
+    /// the oldest replay for them had been outstanding, if known.
+    Warming(Option<Duration>),
     /// Read size of view
     Size(usize),
+    /// The read was sent to a reader shard that no longer exists, or that does not hold the
+    /// requested keys, so the client should find out where the view's shards are now and retry.
+    Redirect,
+    /// The read does not fit the reader, such as a prefix lookup on a reader that does not keep
+    /// its keys in order, along with why.
+    Invalid(String),
 }
 
+/// The changes made to one shard of a view since some point in its change feed.
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChangeSet {
+    /// The changes, in the order they were applied.
+    pub changes: Vec<Change>,
+    /// Where to read the next changes from.
+    pub next: ChangeCursor,
+    /// Whether some of the requested changes are no longer retained.
+    pub dropped: bool,
+    /// The batch of writes each change stems from, if any, as the position of the first change
+    /// from each batch. Changes before the first position do not stem from a write.
+    #[serde(default)]
+    pub labels: Vec<(usize, Option<ChangeLabel>)>,
+}
+
+/// Where a reader of one shard of a view is in its change feed.
+#[doc(hidden)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ChangeCursor {
+    /// Identifies the change log the cursor is into. A reader that is recreated, such as after
+    /// its worker restarts, starts a new log.
+    pub log: u64,
+    /// The batch of changes in the log to read next.
+    pub batch: u64,
+    /// How many of that batch's changes have been read already.
+    pub offset: usize,
+    /// The last batch of writes from each base table shard that the changes read so far reflect,
+    /// as `(base, shard, seq)`. Picking up in another log skips the changes these stem from.
+    pub labels: Vec<(usize, usize, u64)>,
+}
+
+/// An opaque token identifying where the next page of a paginated lookup starts.
+///
+/// See [`View::lookup_paginated`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PageToken(usize);
+
 #[doc(hidden)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ViewBuilder {
     pub node: NodeIndex,
     pub columns: Vec<String>,
     pub schema: Option<Vec<ColumnSpecification>>,
+    /// The columns the view's reader is keyed by.
+    #[serde(default)]
+    pub key: Vec<usize>,
     pub shards: Vec<SocketAddr>,
+    /// Whether each of `shards` expects clients to connect over TLS.
+    #[serde(default)]
+    pub shards_tls: Vec<bool>,
+    /// The base tables whose writes flow into this view.
+    pub bases: Vec<NodeIndex>,
+    /// Whether the view's reader keeps its keys in order, and so can serve range and prefix
+    /// lookups.
+    pub ordered: bool,
+    /// The boundaries between the reader's shards, if it is sharded by ranges of its first key
+    /// column.
+    pub shard_ranges: Option<Vec<DataType>>,
+    /// The hash that picks the reader's shard for a key, if it is not sharded by ranges.
+    pub shard_hasher: ShardHasher,
 }
 
 impl ViewBuilder {
-    /// Build a `View` out of a `ViewBuilder`
+    /// Build a `View` out of a `ViewBuilder`, whose connections present `token` and are for
+    /// `scope`.
     #[doc(hidden)]
     pub fn build(
         &self,
-        rpcs: Arc<Mutex<HashMap<(SocketAddr, usize), ViewRpc>>>,
+        rpcs: Arc<Mutex<HashMap<(SocketAddr, usize, Option<Scope>), ViewRpc>>>,
+        tls: Option<Tls>,
+        token: Option<String>,
+        scope: Scope,
     ) -> Result<View, io::Error> {
         let node = self.node;
         let columns = self.columns.clone();
         let shards = self.shards.clone();
         let schema = self.schema.clone();
+        let key = self.key.clone();
+        let bases = Arc::from(&self.bases[..]);
+        let ordered = self.ordered;
+        let shard_ranges = self.shard_ranges.clone().map(Arc::from);
+        let shard_hasher = self.shard_hasher;
+
+        // workers only serve a view over connections that were opened for it once they check
+        // tokens, so only handles without a token can share connections between views
+        let shared_as = token.as_ref().map(|_| scope.clone());
 
         let mut addrs = Vec::with_capacity(shards.len());
         let mut conns = Vec::with_capacity(shards.len());
@@ -168,17 +474,24 @@ impl ViewBuilder {
             use std::collections::hash_map::Entry;
 
             addrs.push(addr);
+            let expects_tls = self.shards_tls.get(shardi).cloned().unwrap_or(false);
+            let shard_tls = tls::for_endpoint(tls.as_ref(), addr, expects_tls)?.cloned();
 
             // one entry per shard so that we can send sharded requests in parallel even if
             // they happen to be targeting the same machine.
             let mut rpcs = rpcs.lock().unwrap();
-            let s = match rpcs.entry((addr, shardi)) {
+            let s = match rpcs.entry((addr, shardi, shared_as.clone())) {
                 Entry::Occupied(e) => e.get().clone(),
                 Entry::Vacant(h) => {
                     // TODO: maybe always use the same local port?
                     let (c, w) = Buffer::pair(
                         ConcurrencyLimit::new(
-                            Balance::from_entropy(make_views_discover(addr)),
+                            Balance::from_entropy(make_views_discover(
+                                addr,
+                                shard_tls,
+                                token.clone(),
+                                scope.clone(),
+                            )),
                             crate::PENDING_LIMIT,
                         ),
                         crate::BUFFER_TO_POOL,
@@ -200,9 +513,20 @@ impl ViewBuilder {
         Ok(View {
             node,
             schema,
+            key,
             columns,
+            bases,
+            ordered,
+            shard_ranges,
+            shard_hasher,
             shard_addrs: addrs,
             shards: conns,
+            rpcs,
+            tls,
+            token,
+            scope,
+            source: None,
+            context: None,
             tracer,
         })
     }
@@ -217,9 +541,23 @@ pub struct View {
     node: NodeIndex,
     columns: Vec<String>,
     schema: Option<Vec<ColumnSpecification>>,
+    key: Vec<usize>,
+    bases: Arc<[NodeIndex]>,
+    ordered: bool,
+    shard_ranges: Option<Arc<[DataType]>>,
+    shard_hasher: ShardHasher,
 
     shards: Vec<ViewRpc>,
     shard_addrs: Vec<SocketAddr>,
+    rpcs: Arc<Mutex<HashMap<(SocketAddr, usize, Option<Scope>), ViewRpc>>>,
+    tls: Option<Tls>,
+    token: Option<String>,
+    /// What the view's connections are for.
+    scope: Scope,
+    /// Where to find out where the view's shards are once they move, if anywhere.
+    source: Option<ViewSource>,
+    /// The span of a distributed trace that reads are part of, if any.
+    context: Option<TraceContext>,
 
     tracer: tracing::Dispatch,
 }
@@ -237,6 +575,12 @@ impl fmt::Debug for View {
 pub(crate) mod results;
 use self::results::{Results, Row};
 
+pub(crate) mod subscription;
+use self::subscription::{
+    Change, ChangeEvent, ChangeFeed, ChangeLabel, ResumeToken, Subscription, SubscriptionOptions,
+    CHANGES_WAIT,
+};
+
 impl Service<(Vec<Vec<DataType>>, bool)> for View {
     type Response = Vec<Results>;
     type Error = ViewError;
@@ -265,12 +609,16 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
         };
 
         let columns = Arc::from(&self.columns[..]);
+        let context = self.context;
         if self.shards.len() == 1 {
-            let request = Tagged::from(ReadQuery::Normal {
-                target: (self.node, 0),
-                keys,
-                block,
-            });
+            let request = Tagged::from(
+                ReadQuery::Normal {
+                    target: (self.node, 0),
+                    keys,
+                    block,
+                }
+                .traced(context),
+            );
 
             let _guard = span.as_ref().map(tracing::Span::enter);
             tracing::trace!("submit request");
@@ -279,27 +627,20 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
                 self.shards[0]
                     .call(request)
                     .map_err(ViewError::from)
-                    .and_then(move |reply| async move {
-                        match reply.v {
-                            ReadReply::Normal(Ok(rows)) => Ok(rows
-                                .into_iter()
-                                .map(|rows| Results::new(rows.into(), Arc::clone(&columns)))
-                                .collect()),
-                            ReadReply::Normal(Err(())) => Err(ViewError::NotYetAvailable),
-                            _ => unreachable!(),
-                        }
-                    }),
+                    .and_then(move |reply| async move { into_results(reply.v, &columns) }),
             );
         }
 
         if let Some(ref span) = span {
             span.in_scope(|| tracing::trace!("shard request"));
         }
-        assert!(keys.iter().all(|k| k.len() == 1));
         let mut shard_queries = vec![Vec::new(); self.shards.len()];
-        for key in keys {
-            let shard = crate::shard_by(&key[0], self.shards.len());
+        // remember where each key was in the request, so results can be returned in that order
+        let mut shard_indices = vec![Vec::new(); self.shards.len()];
+        for (i, key) in keys.into_iter().enumerate() {
+            let shard = self.shard_for(&key);
             shard_queries[shard].push(key);
+            shard_indices[shard].push(i);
         }
 
         let node = self.node;
@@ -307,8 +648,8 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
             self.shards
                 .iter_mut()
                 .enumerate()
-                .zip(shard_queries.into_iter())
-                .filter_map(|((shardi, shard), shard_queries)| {
+                .zip(shard_queries.into_iter().zip(shard_indices.into_iter()))
+                .filter_map(|((shardi, shard), (shard_queries, shard_indices))| {
                     if shard_queries.is_empty() {
                         // poll_ready reserves a sender slot which we have to release
                         // we do that by dropping the old handle and replacing it with a clone
@@ -316,15 +657,18 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
                         *shard = shard.clone();
                         None
                     } else {
-                        Some(((shardi, shard), shard_queries))
+                        Some(((shardi, shard), (shard_queries, shard_indices)))
                     }
                 })
-                .map(move |((shardi, shard), shard_queries)| {
-                    let request = Tagged::from(ReadQuery::Normal {
-                        target: (node, shardi),
-                        keys: shard_queries,
-                        block,
-                    });
+                .map(move |((shardi, shard), (shard_queries, shard_indices))| {
+                    let request = Tagged::from(
+                        ReadQuery::Normal {
+                            target: (node, shardi),
+                            keys: shard_queries,
+                            block,
+                        }
+                        .traced(context),
+                    );
 
                     let _guard = span.as_ref().map(tracing::Span::enter);
                     // make a span per shard
@@ -336,28 +680,48 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
                     let _guard = span.as_ref().map(tracing::Span::enter);
                     tracing::trace!("submit request shard");
 
+                    let columns = Arc::clone(&columns);
                     shard
                         .call(request)
                         .map_err(ViewError::from)
-                        .and_then(|reply| async move {
-                            match reply.v {
-                                ReadReply::Normal(Ok(rows)) => Ok(rows),
-                                ReadReply::Normal(Err(())) => Err(ViewError::NotYetAvailable),
-                                _ => unreachable!(),
-                            }
+                        .and_then(move |reply| async move {
+                            let rs = into_results(reply.v, &columns)?;
+                            Ok(shard_indices.into_iter().zip(rs).collect::<Vec<_>>())
                         })
                 })
                 .collect::<FuturesUnordered<_>>()
                 .try_concat()
-                .map_ok(move |rows| {
-                    rows.into_iter()
-                        .map(|rows| Results::new(rows.into(), Arc::clone(&columns)))
-                        .collect()
+                .map_ok(|mut rs| {
+                    rs.sort_unstable_by_key(|&(i, _)| i);
+                    rs.into_iter().map(|(_, rs)| rs).collect()
                 }),
         )
     }
 }
 
+/// Turn the reply to a normal read into one set of results per requested key.
+fn into_results(reply: ReadReply, columns: &Arc<[String]>) -> Result<Vec<Results>, ViewError> {
+    match reply {
+        ReadReply::Normal(Ok(rows)) => Ok(rows
+            .into_iter()
+            .map(|rows| Results::new(rows.into(), Arc::clone(columns)))
+            .collect()),
+        ReadReply::Partial(rows, pending) => {
+            let mut rs: Vec<_> = rows
+                .into_iter()
+                .map(|rows| Results::new(rows.into(), Arc::clone(columns)))
+                .collect();
+            for i in pending {
+                rs[i] = Results::pending(Arc::clone(columns));
+            }
+            Ok(rs)
+        }
+        ReadReply::Normal(Err(())) => Err(ViewError::NotYetAvailable),
+        ReadReply::Redirect => Err(ViewError::Redirected),
+        _ => unreachable!(),
+    }
+}
+
 #[allow(clippy::len_without_is_empty)]
 impl View {
     /// Get the list of columns in this view.
@@ -365,35 +729,77 @@ impl View {
         &*self.columns
     }
 
+    /// Make the reads made through this handle from now on part of the given span of a
+    /// distributed trace, or of none.
+    ///
+    /// Workers that export spans record each read as a descendant of the span. Subscriptions are
+    /// not traced. Clone the handle to read in different spans at the same time.
+    pub fn set_trace_context(&mut self, context: Option<TraceContext>) {
+        self.context = context;
+    }
+
     /// Get the schema definition of this view.
     pub fn schema(&self) -> Option<&[ColumnSpecification]> {
         self.schema.as_deref()
     }
 
+    /// Get the columns that this view is keyed by, in the order a lookup gives their values.
+    ///
+    /// This is empty for views obtained from controllers that do not report it.
+    pub fn key_columns(&self) -> &[usize] {
+        &self.key
+    }
+
+    /// Turn the values given for the view's key columns, in order, into the form those columns
+    /// hold their values in, such as UUIDs given in their text form.
+    fn coerce_key(&self, mut key: Vec<DataType>) -> Result<Vec<DataType>, ViewError> {
+        if let Some(ref schema) = self.schema {
+            for (v, &coli) in key.iter_mut().zip(&self.key) {
+                if let Some(spec) = schema.get(coli) {
+                    v.coerce_to_column(spec)
+                        .map_err(|_| ViewError::InvalidUuid(spec.column.name.clone(), v.clone()))?;
+                }
+            }
+        }
+        Ok(key)
+    }
+
     /// Get the current size of this view.
     ///
     /// Note that you must also continue to poll this `View` for the returned future to resolve.
     pub async fn len(&mut self) -> Result<usize, ViewError> {
+        let r = self.try_len().await;
+        if self.rerouted(&r).await {
+            return self.try_len().await;
+        }
+        r
+    }
+
+    async fn try_len(&mut self) -> Result<usize, ViewError> {
         future::poll_fn(|cx| self.poll_ready(cx)).await?;
 
         let node = self.node;
+        let context = self.context;
         let mut rsps = self
             .shards
             .iter_mut()
             .enumerate()
             .map(|(shardi, shard)| {
-                shard.call(Tagged::from(ReadQuery::Size {
-                    target: (node, shardi),
-                }))
+                shard.call(Tagged::from(
+                    ReadQuery::Size {
+                        target: (node, shardi),
+                    }
+                    .traced(context),
+                ))
             })
             .collect::<FuturesUnordered<_>>();
 
         let mut nrows = 0;
         while let Some(reply) = rsps.next().await.transpose()? {
-            if let ReadReply::Size(rows) = reply.v {
-                nrows += rows;
-            } else {
-                unreachable!();
+            match reply.v {
+                ReadReply::Size(rows) => nrows += rows,
+                ReadReply::Redirect => return Err(ViewError::Redirected),
+                _ => unreachable!(),
             }
         }
 
@@ -402,16 +808,686 @@ impl View {
 
     /// Retrieve the query results for the given parameter values.
     ///
+    /// All keys are looked up with a single request to each shard, and results are returned in the
+    /// order of `keys`. Any requested keys that have missing state will be backfilled together.
+    ///
     /// The method will block if the results are not yet available only when `block` is `true`.
-    /// If `block` is false, misses will be returned as empty results. Any requested keys that have
-    /// missing state will be backfilled (asynchronously if `block` is `false`).
+    /// If `block` is false, keys that hit are returned immediately, and misses are returned as
+    /// empty results for which [`Results::is_pending`] is true; they are backfilled
+    /// asynchronously.
     pub async fn multi_lookup(
         &mut self,
         keys: Vec<Vec<DataType>>,
         block: bool,
     ) -> Result<Vec<Results>, ViewError> {
+        let keys = keys
+            .into_iter()
+            .map(|key| self.coerce_key(key))
+            .collect::<Result<Vec<_>, _>>()?;
         future::poll_fn(|cx| self.poll_ready(cx)).await?;
-        self.call((keys, block)).await
+        let r = self.call((keys.clone(), block)).await;
+        if self.rerouted(&r).await {
+            future::poll_fn(|cx| self.poll_ready(cx)).await?;
+            return self.call((keys, block)).await;
+        }
+        r
+    }
+
+    /// Retrieve the query results for all parameter values that fall within the given range.
+    ///
+    /// This is only supported for views whose only parameter is a range placeholder (e.g., `WHERE
+    /// created_at > ?`). Within each shard, rows are returned in key order. If the view is sharded
+    /// by ranges of its parameter, only the shards whose ranges overlap `range` are queried, and
+    /// rows are returned in key order overall. Views that cannot serve range lookups return
+    /// [`ViewError::RangeNotSupported`].
+    pub async fn lookup_range(&mut self, range: KeyRange) -> Result<Results, ViewError> {
+        self.lookup_prefix_range(&[], range).await
+    }
+
+    /// Retrieve the query results for the given values of the view's equality parameters, and
+    /// all values of its range parameter that fall within the given range.
+    ///
+    /// This is for views such as `WHERE author = ? AND created_at > ?`, where `prefix` holds the
+    /// values for all parameters but the last. Within each shard, rows are returned in key order.
+    /// A `prefix` of any other length fails with [`ViewError::WrongKeyColumnCount`].
+    pub async fn lookup_prefix_range(
+        &mut self,
+        prefix: &[DataType],
+        mut range: KeyRange,
+    ) -> Result<Results, ViewError> {
+        let prefix = self.coerce_key(prefix.to_vec())?;
+        if let Some(&coli) = self.key.get(prefix.len()) {
+            let spec = self.schema.as_ref().and_then(|schema| schema.get(coli));
+            if let Some(spec) = spec {
+                for v in range.values_mut() {
+                    v.coerce_to_column(spec)
+                        .map_err(|_| ViewError::InvalidUuid(spec.column.name.clone(), v.clone()))?;
+                }
+            }
+        }
+
+        let r = self.try_lookup_prefix_range(&prefix, range.clone()).await;
+        if self.rerouted(&r).await {
+            return self.try_lookup_prefix_range(&prefix, range).await;
+        }
+        r
+    }
+
+    async fn try_lookup_prefix_range(
+        &mut self,
+        prefix: &[DataType],
+        range: KeyRange,
+    ) -> Result<Results, ViewError> {
+        if !self.ordered {
+            return Err(ViewError::RangeNotSupported);
+        }
+        if !self.key.is_empty() && prefix.len() + 1 != self.key.len() {
+            return Err(ViewError::WrongKeyColumnCount(
+                prefix.len() + 1,
+                self.key.len(),
+            ));
+        }
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let targets = match (&self.shard_ranges, prefix.first()) {
+            (_, Some(k)) if self.shards.len() > 1 => {
+                let shard = self.shard_for(&[k.clone()]);
+                shard..shard + 1
+            }
+            (Some(boundaries), None) => crate::shards_in_range(&range, boundaries),
+            _ => 0..self.shards.len(),
+        };
+
+        let node = self.node;
+        let context = self.context;
+        // replies are kept in shard order so that range-sharded results come out in key order
+        let mut rsps = self
+            .shards
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(shardi, shard)| {
+                if !targets.contains(&shardi) {
+                    // poll_ready reserved a slot on every shard, but we don't use this one
+                    *shard = shard.clone();
+                    return None;
+                }
+                Some(
+                    shard.call(Tagged::from(
+                        ReadQuery::Range {
+                            target: (node, shardi),
+                            prefix: Vec::from(prefix),
+                            range: range.clone(),
+                        }
+                        .traced(context),
+                    )),
+                )
+            })
+            .collect::<FuturesOrdered<_>>();
+
+        let mut rows = Vec::new();
+        while let Some(reply) = rsps.next().await.transpose()? {
+            match reply.v {
+                ReadReply::Normal(Ok(batches)) => rows.extend(batches.into_iter().flatten()),
+                ReadReply::Normal(Err(())) => return Err(ViewError::NotYetAvailable),
+                ReadReply::Redirect => return Err(ViewError::Redirected),
+                ReadReply::Invalid(e) => return Err(ViewError::Rejected(e)),
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(Results::new(rows, Arc::from(&self.columns[..])))
+    }
+
+    /// The shard that holds the state for `key`.
+    fn shard_for(&self, key: &[DataType]) -> usize {
+        match self.shard_ranges {
+            Some(ref boundaries) => crate::shard_by_range(&key[0], boundaries),
+            None => crate::shard_by_key(&self.shard_hasher, key, self.shards.len()),
+        }
+    }
+
+    /// Find out where the view's shards are from `source` whenever they have moved.
+    pub(crate) fn with_source(mut self, source: ViewSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Whether a read that failed with `r` should be retried, because the view's shards turned
+    /// out to have moved, been resharded, or failed over, and the view now knows where they are.
+    async fn rerouted<T>(&mut self, r: &Result<T, ViewError>) -> bool {
+        match *r {
+            Err(ViewError::Redirected) | Err(ViewError::TransportError(_)) => {
+                self.refresh().await.unwrap_or(false)
+            }
+            _ => false,
+        }
+    }
+
+    /// Fetch where the view's shards are now from the controller.
+    ///
+    /// Returns false if the view was not obtained from a controller, or if its shards are still
+    /// where the view thought they were.
+    async fn refresh(&mut self) -> Result<bool, ViewError> {
+        let source = match self.source {
+            Some(ref source) => Arc::clone(source),
+            None => return Ok(false),
+        };
+        let vb = source()
+            .await
+            .map_err(|e| match e.downcast::<AuthError>() {
+                Ok(e) => ViewError::Unauthorized(e),
+                Err(e) => ViewError::TransportError(e),
+            })?
+            .ok_or_else(|| ViewError::TransportError(failure::err_msg("the view was removed")))?;
+        if vb.node == self.node
+            && vb.shards == self.shard_addrs
+            && vb.shard_ranges.as_deref() == self.shard_ranges.as_deref()
+        {
+            return Ok(false);
+        }
+
+        let view = vb
+            .build(
+                Arc::clone(&self.rpcs),
+                self.tls.clone(),
+                self.token.clone(),
+                self.scope.clone(),
+            )
+            .map_err(|e| ViewError::TransportError(e.into()))?;
+        *self = view.with_source(source);
+        Ok(true)
+    }
+
+    /// Retrieve the query results for all parameter values whose leading columns equal `prefix`.
+    ///
+    /// For a view keyed on `(user_id, category)`, this returns the rows for a user across all
+    /// categories. Rows are returned in key order within each shard. `prefix` must leave out at
+    /// least one of the view's parameters. If it is empty, or the view is sharded and `prefix`
+    /// does not fix the first parameter, all shards are queried.
+    ///
+    /// Only views whose reader keeps its keys in order support this; such readers are always
+    /// fully materialized, so a prefix lookup never misses. Other views return
+    /// [`ViewError::PrefixNotSupported`], and a `prefix` that gives all of the view's parameters
+    /// fails with [`ViewError::WrongKeyColumnCount`].
+    pub async fn lookup_prefix(&mut self, prefix: &[DataType]) -> Result<Results, ViewError> {
+        let prefix = self.coerce_key(prefix.to_vec())?;
+        let r = self.try_lookup_prefix(&prefix).await;
+        if self.rerouted(&r).await {
+            return self.try_lookup_prefix(&prefix).await;
+        }
+        r
+    }
+
+    async fn try_lookup_prefix(&mut self, prefix: &[DataType]) -> Result<Results, ViewError> {
+        if !self.ordered {
+            return Err(ViewError::PrefixNotSupported);
+        }
+        if !self.key.is_empty() && prefix.len() >= self.key.len() {
+            return Err(ViewError::WrongKeyColumnCount(prefix.len(), self.key.len()));
+        }
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let target = if self.shards.len() == 1 {
+            Some(0)
+        } else {
+            prefix.first().map(|k| self.shard_for(&[k.clone()]))
+        };
+
+        let node = self.node;
+        let context = self.context;
+        let mut rsps = self
+            .shards
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(shardi, shard)| {
+                if target.is_some() && target != Some(shardi) {
+                    // poll_ready reserved a slot on every shard, but we don't use this one
+                    *shard = shard.clone();
+                    return None;
+                }
+                Some(
+                    shard.call(Tagged::from(
+                        ReadQuery::Prefix {
+                            target: (node, shardi),
+                            prefix: Vec::from(prefix),
+                        }
+                        .traced(context),
+                    )),
+                )
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut rows = Vec::new();
+        while let Some(reply) = rsps.next().await.transpose()? {
+            match reply.v {
+                ReadReply::Normal(Ok(batches)) => rows.extend(batches.into_iter().flatten()),
+                ReadReply::Normal(Err(())) => return Err(ViewError::NotYetAvailable),
+                ReadReply::Redirect => return Err(ViewError::Redirected),
+                ReadReply::Invalid(e) => return Err(ViewError::Rejected(e)),
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(Results::new(rows, Arc::from(&self.columns[..])))
+    }
+
+    /// Retrieve at most `limit` query results for the given parameter value, starting where the
+    /// page identified by `token` left off (or at the beginning if `token` is `None`).
+    ///
+    /// Returns the rows of the page along with a token for the next page, or `None` if this was
+    /// the last one. Pages are consistent with each other as long as no write to the view touches
+    /// `key` between them. If one does, later pages reflect the new state, so rows may be skipped
+    /// or returned twice across the page boundary; callers that need an exact answer in that case
+    /// should start over from the first page.
+    ///
+    /// The method will block if the results are not yet available only when `block` is `true`.
+    /// If `block` is false, a miss is returned as an empty page whose token refers to the same
+    /// page, which can be retried once the key has been backfilled.
+    ///
+    /// A `limit` of zero fails with [`ViewError::EmptyPage`].
+    pub async fn lookup_paginated(
+        &mut self,
+        key: &[DataType],
+        limit: usize,
+        token: Option<PageToken>,
+        block: bool,
+    ) -> Result<(Results, Option<PageToken>), ViewError> {
+        let key = self.coerce_key(key.to_vec())?;
+        let r = self.try_lookup_paginated(&key, limit, token, block).await;
+        if self.rerouted(&r).await {
+            return self.try_lookup_paginated(&key, limit, token, block).await;
+        }
+        r
+    }
+
+    async fn try_lookup_paginated(
+        &mut self,
+        key: &[DataType],
+        limit: usize,
+        token: Option<PageToken>,
+        block: bool,
+    ) -> Result<(Results, Option<PageToken>), ViewError> {
+        if limit == 0 {
+            return Err(ViewError::EmptyPage);
+        }
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let shardi = if self.shards.len() == 1 {
+            0
+        } else {
+            self.shard_for(key)
+        };
+
+        // poll_ready reserved a slot on every shard, but we only use one of them
+        let node = self.node;
+        let context = self.context;
+        for (i, shard) in self.shards.iter_mut().enumerate() {
+            if i != shardi {
+                *shard = shard.clone();
+            }
+        }
+
+        let reply = self.shards[shardi]
+            .call(Tagged::from(
+                ReadQuery::Page {
+                    target: (node, shardi),
+                    key: Vec::from(key),
+                    offset: token.map(|t| t.0).unwrap_or(0),
+                    limit,
+                    block,
+                }
+                .traced(context),
+            ))
+            .await?;
+
+        match reply.v {
+            ReadReply::Page(Ok((rows, next))) => Ok((
+                Results::new(rows.into(), Arc::from(&self.columns[..])),
+                next.map(PageToken),
+            )),
+            ReadReply::Page(Err(())) => Err(ViewError::NotYetAvailable),
+            ReadReply::Redirect => Err(ViewError::Redirected),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Retrieve the query results for the given parameter value once they reflect the writes
+    /// identified by `token`.
+    ///
+    /// Entries in `token` for tables this view does not read from are ignored. The read blocks
+    /// until the writes have been applied to the view, and then until the results are available.
+    /// If the writes do not reach the view within `timeout`, the read fails with
+    /// [`ViewError::WriteTimeout`].
+    pub async fn lookup_after(
+        &mut self,
+        key: &[DataType],
+        token: &WriteToken,
+        timeout: Duration,
+    ) -> Result<Results, ViewError> {
+        let key = self.coerce_key(key.to_vec())?;
+        let r = self.try_lookup_after(&key, token, timeout).await;
+        if self.rerouted(&r).await {
+            return self.try_lookup_after(&key, token, timeout).await;
+        }
+        r
+    }
+
+    async fn try_lookup_after(
+        &mut self,
+        key: &[DataType],
+        token: &WriteToken,
+        timeout: Duration,
+    ) -> Result<Results, ViewError> {
+        let bases = &self.bases;
+        let token = token
+            .0
+            .iter()
+            .filter(|&&(base, _, _)| bases.contains(&base))
+            .cloned()
+            .collect();
+        let reply = self
+            .call_one(key, |target, keys| ReadQuery::After {
+                target,
+                keys,
+                token,
+                timeout,
+            })
+            .await?;
+
+        match reply {
+            ReadReply::TimedOut => Err(ViewError::WriteTimeout),
+            reply => Ok(into_results(reply, &Arc::from(&self.columns[..]))?
+                .into_iter()
+                .next()
+                .unwrap()),
+        }
+    }
+
+    /// Retrieve the query results for the given parameter value once they reflect every write
+    /// applied to the view's base tables more than `staleness` ago.
+    ///
+    /// This bounds how stale the results may be without waiting for any particular write. A
+    /// base table only lets the view know that it is up to date when it is written to, so reads
+    /// from views over idle tables wait for the next write unless Noria was started with a
+    /// freshness interval. If the view does not catch up within `timeout`, the read fails with
+    /// [`ViewError::StaleTimeout`].
+    pub async fn lookup_fresh(
+        &mut self,
+        key: &[DataType],
+        staleness: Duration,
+        timeout: Duration,
+    ) -> Result<Results, ViewError> {
+        let key = self.coerce_key(key.to_vec())?;
+        let r = self.try_lookup_fresh(&key, staleness, timeout).await;
+        if self.rerouted(&r).await {
+            return self.try_lookup_fresh(&key, staleness, timeout).await;
+        }
+        r
+    }
+
+    async fn try_lookup_fresh(
+        &mut self,
+        key: &[DataType],
+        staleness: Duration,
+        timeout: Duration,
+    ) -> Result<Results, ViewError> {
+        let reply = self
+            .call_one(key, |target, keys| ReadQuery::Fresh {
+                target,
+                keys,
+                staleness,
+                timeout,
+            })
+            .await?;
+
+        match reply {
+            ReadReply::TimedOut => Err(ViewError::StaleTimeout),
+            reply => Ok(into_results(reply, &Arc::from(&self.columns[..]))?
+                .into_iter()
+                .next()
+                .unwrap()),
+        }
+    }
+
+    /// Send the query made by `query` for the single key `key` to the shard that holds the key.
+    async fn call_one<F>(&mut self, key: &[DataType], query: F) -> Result<ReadReply, ViewError>
+    where
+        F: FnOnce((NodeIndex, usize), Vec<Vec<DataType>>) -> ReadQuery,
+    {
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let shardi = if self.shards.len() == 1 {
+            0
+        } else {
+            self.shard_for(key)
+        };
+
+        // poll_ready reserved a slot on every shard, but we only use one of them
+        let node = self.node;
+        let context = self.context;
+        for (i, shard) in self.shards.iter_mut().enumerate() {
+            if i != shardi {
+                *shard = shard.clone();
+            }
+        }
+
+        let reply = self.shards[shardi]
+            .call(Tagged::from(
+                query((node, shardi), vec![Vec::from(key)]).traced(context),
+            ))
+            .await?;
+        Ok(reply.v)
+    }
+
+    /// Retrieve the query results for the given parameter value, waiting at most `timeout` for
+    /// the key to be backfilled if it is missing.
+    ///
+    /// If the key is still missing when the deadline passes, the read fails with
+    /// [`ViewError::StillWarming`] rather than returning an empty result. The backfill continues
+    /// in the background, and retrying the lookup waits for that same backfill instead of
+    /// starting another one.
+    pub async fn lookup_with_deadline(
+        &mut self,
+        key: &[DataType],
+        timeout: Duration,
+    ) -> Result<Results, ViewError> {
+        let key = self.coerce_key(key.to_vec())?;
+        let r = self.try_lookup_with_deadline(&key, timeout).await;
+        if self.rerouted(&r).await {
+            return self.try_lookup_with_deadline(&key, timeout).await;
+        }
+        r
+    }
+
+    async fn try_lookup_with_deadline(
+        &mut self,
+        key: &[DataType],
+        timeout: Duration,
+    ) -> Result<Results, ViewError> {
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let shardi = if self.shards.len() == 1 {
+            0
+        } else {
+            self.shard_for(key)
+        };
+
+        // poll_ready reserved a slot on every shard, but we only use one of them
+        let node = self.node;
+        let context = self.context;
+        for (i, shard) in self.shards.iter_mut().enumerate() {
+            if i != shardi {
+                *shard = shard.clone();
+            }
+        }
+
+        let reply = self.shards[shardi]
+            .call(Tagged::from(
+                ReadQuery::Deadline {
+                    target: (node, shardi),
+                    keys: vec![Vec::from(key)],
+                    timeout,
+                }
+                .traced(context),
+            ))
+            .await?;
+
+        match reply.v {
+            ReadReply::Warming(outstanding) => Err(ViewError::StillWarming { outstanding }),
+            reply => Ok(into_results(reply, &Arc::from(&self.columns[..]))?
+                .into_iter()
+                .next()
+                .unwrap()),
+        }
+    }
+
+    /// Subscribe to the changes made to this view from now on.
+    ///
+    /// If `key` is given, only changes to rows with that key are delivered. Otherwise, the
+    /// subscription covers the whole view. Note that in a partially materialized view, only
+    /// changes to keys that are present in the view are observed; subscribing to a key makes it
+    /// present, but it may later be evicted.
+    pub async fn subscribe(
+        &mut self,
+        key: Option<Vec<DataType>>,
+        options: SubscriptionOptions,
+    ) -> Result<Subscription, ViewError> {
+        let key = key.map(|key| self.coerce_key(key)).transpose()?;
+        if let Some(ref key) = key {
+            // make sure the key is materialized, or we won't hear about changes to it
+            self.lookup(key, true).await?;
+        }
+
+        // register with the shards before returning, so no changes made after this are missed
+        let cursor = self.start_changes(key.as_deref()).await?;
+
+        Ok(subscription::spawn(
+            self.clone(),
+            key,
+            ResumeToken(cursor),
+            options,
+        ))
+    }
+
+    /// Subscribe to the changes made to this view after the point identified by `token`.
+    ///
+    /// If the view no longer retains some of those changes, the subscription starts with
+    /// [`subscription::Update::Lagged`](crate::subscription::Update::Lagged). See
+    /// [`View::subscribe`] for the meaning of `key`, which should be the same as for the
+    /// subscription that `token` came from.
+    pub async fn subscribe_from(
+        &mut self,
+        token: ResumeToken,
+        key: Option<Vec<DataType>>,
+        options: SubscriptionOptions,
+    ) -> Result<Subscription, ViewError> {
+        if token.0.len() != self.shards.len() {
+            // the view was resharded, so the token doesn't say anything about the new shards
+            return self.subscribe(key, options).await;
+        }
+
+        let key = key.map(|key| self.coerce_key(key)).transpose()?;
+        if let Some(ref key) = key {
+            self.lookup(key, true).await?;
+        }
+
+        Ok(subscription::spawn(self.clone(), key, token, options))
+    }
+
+    /// Follow the changes to the base table that this view mirrors, after `from` or from now on.
+    pub(crate) async fn change_feed(
+        mut self,
+        from: Option<ResumeToken>,
+    ) -> Result<ChangeFeed, ViewError> {
+        let (token, resync) = match from {
+            Some(token) if token.0.len() == self.shards.len() => (token, false),
+            // the table was resharded, so the token doesn't say anything about the new shards
+            Some(_) => (ResumeToken(vec![None; self.shards.len()]), true),
+            None => (ResumeToken(vec![None; self.shards.len()]), false),
+        };
+
+        let mut cursor = token;
+        if cursor.0.iter().all(Option::is_none) {
+            // register with the shards before returning, so no changes made after this are missed
+            cursor.0 = self.start_changes(None).await?;
+        }
+        Ok(subscription::spawn_feed(self, cursor, resync))
+    }
+
+    /// The shards that hold the changes to rows with `key`, or to any row if there is no key.
+    fn change_shards(&self, key: Option<&[DataType]>) -> Vec<usize> {
+        match key {
+            Some(key) if self.shards.len() > 1 => vec![self.shard_for(key)],
+            _ => (0..self.shards.len()).collect(),
+        }
+    }
+
+    /// Subscribe to the changes to rows with `key`, or to any row, on the shards that hold them,
+    /// and return where each shard's changes from now on start.
+    async fn start_changes(
+        &self,
+        key: Option<&[DataType]>,
+    ) -> Result<Vec<Option<ChangeCursor>>, ViewError> {
+        let started = self.change_shards(key).into_iter().map(|shardi| {
+            self.shard_changes(shardi, None, None, 0, CHANGES_WAIT)
+                .map_ok(move |(_, next, _)| (shardi, next))
+        });
+        let mut cursor = vec![None; self.shards.len()];
+        for (shardi, next) in future::try_join_all(started).await? {
+            cursor[shardi] = Some(next);
+        }
+        Ok(cursor)
+    }
+
+    /// Read up to `limit` of the changes to rows with `key`, or to any row, that shard `shardi`
+    /// of the view made after `from`, waiting up to `wait` for there to be some. Along with the
+    /// changes, returns where to read from next, and whether some changes were dropped because
+    /// the shard no longer retained them.
+    ///
+    /// The read doesn't borrow the view, so that the reads from all of its shards can wait at
+    /// once.
+    fn shard_changes(
+        &self,
+        shardi: usize,
+        key: Option<Vec<DataType>>,
+        from: Option<ChangeCursor>,
+        limit: usize,
+        wait: Duration,
+    ) -> impl Future<Output = Result<(Vec<ChangeEvent>, ChangeCursor, bool), ViewError>> + Send
+    {
+        let mut shard = self.shards[shardi].clone();
+        let request = Tagged::from(ReadQuery::Changes {
+            target: (self.node, shardi),
+            from,
+            key,
+            limit,
+            wait,
+        });
+        async move {
+            future::poll_fn(|cx| shard.poll_ready(cx)).await?;
+            let reply = shard.call(request).await?;
+            match reply.v {
+                ReadReply::Changes(Ok(cs)) => {
+                    let mut labels = cs.labels.into_iter().peekable();
+                    let mut label = None;
+                    let mut changes = Vec::with_capacity(cs.changes.len());
+                    for (i, change) in cs.changes.into_iter().enumerate() {
+                        while let Some(&(start, next)) = labels.peek() {
+                            if start > i {
+                                break;
+                            }
+                            label = next;
+                            labels.next();
+                        }
+                        changes.push(ChangeEvent { change, label });
+                    }
+                    Ok((changes, cs.next, cs.dropped))
+                }
+                ReadReply::Changes(Err(())) => Err(ViewError::NotYetAvailable),
+                ReadReply::Redirect => Err(ViewError::Redirected),
+                _ => unreachable!(),
+            }
+        }
     }
 
     /// Retrieve the query results for the given parameter value.
@@ -8,6 +8,7 @@ use std::sync::Arc;
 pub struct Results {
     results: Vec<Vec<DataType>>,
     columns: Arc<[String]>,
+    pending: bool,
 }
 
 impl Results {
@@ -15,7 +16,30 @@ impl Results {
     // https://github.com/rust-lang/rust/issues/69785
     #[doc(hidden)]
     pub fn new(results: Vec<Vec<DataType>>, columns: Arc<[String]>) -> Self {
-        Self { results, columns }
+        Self {
+            results,
+            columns,
+            pending: false,
+        }
+    }
+
+    // NOTE: should be pub(crate), see above.
+    #[doc(hidden)]
+    pub fn pending(columns: Arc<[String]>) -> Self {
+        Self {
+            results: Vec::new(),
+            columns,
+            pending: true,
+        }
+    }
+
+    /// Whether the lookup for these results missed in partially materialized state.
+    ///
+    /// Pending results are always empty. They are only returned by non-blocking lookups, and the
+    /// missing state is backfilled in the background, so a later lookup for the same key will
+    /// eventually see the real results.
+    pub fn is_pending(&self) -> bool {
+        self.pending
     }
 
     /// Iterate over references to the returned rows.
@@ -0,0 +1,436 @@
+use super::{ChangeCursor, View, ViewError};
+use crate::data::*;
+use futures_util::future::{self, Either, FutureExt};
+use futures_util::stream::{FuturesUnordered, Stream, StreamExt};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+/// A single change to the contents of a view.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Change {
+    /// The row was added to the view.
+    Positive(Vec<DataType>),
+    /// The row was removed from the view.
+    Negative(Vec<DataType>),
+}
+
+/// Identifies the batch of writes to a base table shard that a change stems from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ChangeLabel {
+    /// The shard of the base table that applied the batch.
+    pub shard: usize,
+    /// The number of the batch. Each base table shard numbers its batches from 1, in the order it
+    /// applied them.
+    pub seq: u64,
+    /// When the base table shard applied the batch, in nanoseconds since the Unix epoch.
+    pub committed: u64,
+    /// The commit timestamp of the transaction the batch is part of, if it is part of one.
+    pub timestamp: Option<u64>,
+}
+
+/// A change to a base table, delivered by a [`ChangeFeed`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangeEvent {
+    /// The row that was inserted ([`Change::Positive`]) or deleted ([`Change::Negative`]), in
+    /// full. An update is a delete of the old row followed by an insert of the new one.
+    pub change: Change,
+    /// The batch of writes the change stems from. Changes that do not stem from a write, such as
+    /// those made when a column's default changes, have none.
+    pub label: Option<ChangeLabel>,
+}
+
+/// Identifies how far into a view's change feed a subscriber has read.
+///
+/// Tokens can be persisted, and passed to [`View::subscribe_from`] to pick up where a previous
+/// subscription left off, for example after reconnecting to Noria. Since a token also records the
+/// labels of the changes read so far, it can pick up where it left off even if the view's readers
+/// have been recreated since, as long as they logged the changes after those.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ResumeToken(pub(super) Vec<Option<ChangeCursor>>);
+
+/// An update delivered by a [`Subscription`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Update {
+    /// A batch of changes to the view, along with the token to resume from after this batch.
+    Batch {
+        /// The changes, in the order they were applied to the view.
+        changes: Vec<Change>,
+        /// Resuming from this token yields the changes after this batch.
+        token: ResumeToken,
+    },
+    /// Some changes were dropped, either because the subscriber fell behind and the subscription
+    /// uses [`Backpressure::Drop`], or because the view no longer retains them. Subscribers that
+    /// need every change should re-read the view, or resubscribe from their last token.
+    Lagged,
+}
+
+/// What a [`Subscription`] does when its subscriber does not keep up with the view's changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Drop updates that don't fit in the buffer, and deliver [`Update::Lagged`] once there is
+    /// room again.
+    Drop,
+    /// End the subscription with [`ViewError::SubscriptionLagged`].
+    Disconnect,
+}
+
+/// Configuration for a [`Subscription`].
+#[derive(Clone, Debug)]
+pub struct SubscriptionOptions {
+    /// The number of updates buffered for the subscriber.
+    pub buffer: usize,
+    /// What to do when the buffer is full.
+    pub backpressure: Backpressure,
+    /// The most changes delivered in a single update from each shard of the view.
+    pub batch: usize,
+}
+
+impl Default for SubscriptionOptions {
+    fn default() -> Self {
+        SubscriptionOptions {
+            buffer: 1024,
+            backpressure: Backpressure::Drop,
+            batch: 1024,
+        }
+    }
+}
+
+/// How long a read of a view's changes waits for there to be some before it is re-issued.
+pub(super) const CHANGES_WAIT: Duration = Duration::from_secs(1);
+
+/// The changes a shard of a view made after some cursor, where to read from next, and whether
+/// some changes were dropped.
+type ShardChanges = (Vec<ChangeEvent>, ChangeCursor, bool);
+
+/// Read the next changes from shard `shardi` of `view`, once there are some.
+fn read_shard(
+    view: &View,
+    shardi: usize,
+    key: Option<&[DataType]>,
+    from: Option<ChangeCursor>,
+    limit: usize,
+) -> impl Future<Output = (usize, Result<ShardChanges, ViewError>)> {
+    view.shard_changes(shardi, key.map(Vec::from), from, limit, CHANGES_WAIT)
+        .map(move |r| (shardi, r))
+}
+
+/// A stream of changes to a view, created with [`View::subscribe`].
+///
+/// The stream ends with an error if the connection to Noria fails; use
+/// [`Subscription::resume_token`] to pick up where it left off.
+pub struct Subscription {
+    updates: mpsc::Receiver<Result<Update, ViewError>>,
+    token: ResumeToken,
+    overflowed: Arc<AtomicBool>,
+    done: bool,
+    // dropped along with the subscription, which tells the background task to stop
+    _alive: oneshot::Sender<()>,
+}
+
+impl Subscription {
+    /// The token to resume from to receive the changes after the last delivered batch.
+    pub fn resume_token(&self) -> &ResumeToken {
+        &self.token
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Result<Update, ViewError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+
+        match self.updates.poll_recv(cx) {
+            Poll::Ready(Some(Ok(update))) => {
+                if let Update::Batch { ref token, .. } = update {
+                    self.token = token.clone();
+                }
+                Poll::Ready(Some(Ok(update)))
+            }
+            Poll::Ready(Some(Err(e))) => {
+                self.done = true;
+                Poll::Ready(Some(Err(e)))
+            }
+            Poll::Ready(None) => {
+                self.done = true;
+                if self.overflowed.load(Ordering::Acquire) {
+                    Poll::Ready(Some(Err(ViewError::SubscriptionLagged)))
+                } else {
+                    Poll::Ready(None)
+                }
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+pub(super) fn spawn(
+    view: View,
+    key: Option<Vec<DataType>>,
+    token: ResumeToken,
+    options: SubscriptionOptions,
+) -> Subscription {
+    let (mut tx, rx) = mpsc::channel(options.buffer);
+    let (alive, mut subscribed) = oneshot::channel();
+    let overflowed = Arc::new(AtomicBool::new(false));
+
+    let mut cursor = token.clone();
+    let overflow = Arc::clone(&overflowed);
+    tokio::spawn(async move {
+        // the shards are read from all at once, and each pushes its changes as soon as it has some
+        let mut reads: FuturesUnordered<_> = view
+            .change_shards(key.as_deref())
+            .into_iter()
+            .map(|shardi| {
+                let from = cursor.0[shardi].clone();
+                read_shard(&view, shardi, key.as_deref(), from, options.batch)
+            })
+            .collect();
+        let mut lagged = false;
+        loop {
+            let (shardi, read) = match future::select(reads.next(), &mut subscribed).await {
+                Either::Left((Some(read), _)) => read,
+                // the subscription was dropped
+                _ => return,
+            };
+            let (events, next, dropped) = match read {
+                Ok(read) => read,
+                Err(e) => {
+                    // the subscriber may already be gone, in which case there's no one to tell
+                    let _ = tx.send(Err(e)).await;
+                    return;
+                }
+            };
+            cursor.0[shardi] = Some(next.clone());
+            reads.push(read_shard(
+                &view,
+                shardi,
+                key.as_deref(),
+                Some(next),
+                options.batch,
+            ));
+            lagged |= dropped;
+
+            let mut updates = Vec::with_capacity(2);
+            if lagged {
+                updates.push(Update::Lagged);
+            }
+            if !events.is_empty() {
+                updates.push(Update::Batch {
+                    changes: events.into_iter().map(|e| e.change).collect(),
+                    token: cursor.clone(),
+                });
+            }
+
+            for update in updates {
+                let is_lagged = update == Update::Lagged;
+                match tx.try_send(Ok(update)) {
+                    Ok(()) => {
+                        if is_lagged {
+                            lagged = false;
+                        }
+                    }
+                    Err(mpsc::error::TrySendError::Full(_)) => match options.backpressure {
+                        Backpressure::Drop => lagged = true,
+                        Backpressure::Disconnect => {
+                            overflow.store(true, Ordering::Release);
+                            return;
+                        }
+                    },
+                    Err(mpsc::error::TrySendError::Closed(_)) => {
+                        // the subscription was dropped
+                        return;
+                    }
+                }
+            }
+        }
+    });
+
+    Subscription {
+        updates: rx,
+        token,
+        overflowed,
+        done: false,
+        _alive: alive,
+    }
+}
+
+/// An update delivered by a [`ChangeFeed`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FeedUpdate {
+    /// Changes to the table, along with the token to resume from after them.
+    ///
+    /// The changes made by each shard of the table are in the order the shard applied them, but
+    /// changes made by different shards may be interleaved in any order.
+    Changes {
+        /// The changes.
+        events: Vec<ChangeEvent>,
+        /// Resuming from this token yields the changes after these.
+        token: ResumeToken,
+    },
+    /// Changes were lost, because the table no longer retains them, or because its shards changed
+    /// since the token the feed was resumed from. The consumer has to rebuild what it derives from
+    /// the table by reading the table anew, and continue from `token`. Since that read may already
+    /// reflect some of the changes after `token`, they should be applied idempotently.
+    ResyncRequired {
+        /// The token to continue from.
+        token: ResumeToken,
+    },
+}
+
+/// A consumer of the changes a [`ChangeFeed`] delivers, for [`ChangeFeed::drive`].
+///
+/// A sink that persists the tokens it is given along with the changes can resume after a restart
+/// by passing the last of them to
+/// [`ControllerHandle::change_feed`](crate::ControllerHandle::change_feed).
+pub trait ChangeSink {
+    /// Apply changes to the table, after which the feed continues from `token`.
+    fn apply<'a>(
+        &'a mut self,
+        events: Vec<ChangeEvent>,
+        token: &'a ResumeToken,
+    ) -> Pin<Box<dyn Future<Output = Result<(), failure::Error>> + Send + 'a>>;
+
+    /// Rebuild from the contents of the table, since changes were lost. See
+    /// [`FeedUpdate::ResyncRequired`].
+    fn resync<'a>(
+        &'a mut self,
+        token: &'a ResumeToken,
+    ) -> Pin<Box<dyn Future<Output = Result<(), failure::Error>> + Send + 'a>>;
+}
+
+/// The number of updates a [`ChangeFeed`] buffers for its consumer. The table retains changes
+/// that have not been read yet, so a consumer that falls behind slows the feed down instead.
+const FEED_BUFFER: usize = 16;
+
+/// The most changes a [`ChangeFeed`] delivers in a single update.
+const FEED_BATCH: usize = 1024;
+
+/// A stream of the changes made to a base table, created with
+/// [`ControllerHandle::change_feed`](crate::ControllerHandle::change_feed).
+///
+/// Unlike a [`Subscription`], a feed never drops changes silently: changes it can no longer
+/// deliver are reported as [`FeedUpdate::ResyncRequired`]. The stream ends with an error if the
+/// connection to Noria fails; use [`ChangeFeed::resume_token`] to pick up where it left off.
+pub struct ChangeFeed {
+    updates: mpsc::Receiver<Result<FeedUpdate, ViewError>>,
+    token: ResumeToken,
+    done: bool,
+    // dropped along with the feed, which tells the background task to stop
+    _alive: oneshot::Sender<()>,
+}
+
+impl ChangeFeed {
+    /// The token to resume from to receive the changes after the last delivered update.
+    pub fn resume_token(&self) -> &ResumeToken {
+        &self.token
+    }
+
+    /// Deliver every update to `sink`, until the feed fails or the sink does.
+    pub async fn drive<S: ChangeSink>(mut self, sink: &mut S) -> Result<(), failure::Error> {
+        while let Some(update) = self.next().await {
+            match update? {
+                FeedUpdate::Changes { events, token } => sink.apply(events, &token).await?,
+                FeedUpdate::ResyncRequired { token } => sink.resync(&token).await?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Stream for ChangeFeed {
+    type Item = Result<FeedUpdate, ViewError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+
+        match self.updates.poll_recv(cx) {
+            Poll::Ready(Some(Ok(update))) => {
+                match update {
+                    FeedUpdate::Changes { ref token, .. }
+                    | FeedUpdate::ResyncRequired { ref token } => self.token = token.clone(),
+                }
+                Poll::Ready(Some(Ok(update)))
+            }
+            Poll::Ready(Some(Err(e))) => {
+                self.done = true;
+                Poll::Ready(Some(Err(e)))
+            }
+            Poll::Ready(None) => {
+                self.done = true;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+pub(super) fn spawn_feed(view: View, token: ResumeToken, mut resync: bool) -> ChangeFeed {
+    let (mut tx, rx) = mpsc::channel(FEED_BUFFER);
+    let (alive, mut subscribed) = oneshot::channel();
+
+    let mut cursor = token.clone();
+    tokio::spawn(async move {
+        let mut reads: FuturesUnordered<_> = view
+            .change_shards(None)
+            .into_iter()
+            .map(|shardi| read_shard(&view, shardi, None, cursor.0[shardi].clone(), FEED_BATCH))
+            .collect();
+        loop {
+            let (shardi, read) = match future::select(reads.next(), &mut subscribed).await {
+                Either::Left((Some(read), _)) => read,
+                // the feed was dropped
+                _ => return,
+            };
+            let (events, next, dropped) = match read {
+                Ok(read) => read,
+                Err(e) => {
+                    // the consumer may already be gone, in which case there's no one to tell
+                    let _ = tx.send(Err(e)).await;
+                    return;
+                }
+            };
+            cursor.0[shardi] = Some(next.clone());
+            reads.push(read_shard(&view, shardi, None, Some(next), FEED_BATCH));
+
+            // the changes after a gap are covered by the resync
+            let update = if resync || dropped {
+                resync = false;
+                Some(FeedUpdate::ResyncRequired {
+                    token: cursor.clone(),
+                })
+            } else if !events.is_empty() {
+                Some(FeedUpdate::Changes {
+                    events,
+                    token: cursor.clone(),
+                })
+            } else {
+                None
+            };
+
+            if let Some(update) = update {
+                // wait for the consumer rather than drop anything
+                if tx.send(Ok(update)).await.is_err() {
+                    // the feed was dropped
+                    return;
+                }
+            }
+        }
+    });
+
+    ChangeFeed {
+        updates: rx,
+        token,
+        done: false,
+        _alive: alive,
+    }
+}
@@ -21,7 +21,12 @@ impl SizeOf for DataType {
         use std::mem::size_of_val;
 
         let inner = match *self {
-            DataType::Text(ref t) => size_of_val(t) as u64 + t.to_bytes().len() as u64,
+            DataType::Text(ref t) | DataType::Json(ref t) | DataType::CiText(ref t) => {
+                size_of_val(t) as u64 + t.to_bytes().len() as u64
+            }
+            DataType::Uuid(ref b) => size_of_val(&**b) as u64,
+            DataType::HugeInt(ref n) => size_of_val(&**n) as u64,
+            DataType::Bytes(ref b) => size_of_val(&**b) as u64 + b.len() as u64,
             _ => 0u64,
         };
 
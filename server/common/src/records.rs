@@ -1,6 +1,7 @@
 use noria::DataType;
 use std::borrow::Borrow;
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 
 /// A record is a single positive or negative data record with an associated time stamp.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
@@ -68,7 +69,7 @@ impl From<(Vec<DataType>, bool)> for Record {
 
 impl Into<Vec<Record>> for Records {
     fn into(self) -> Vec<Record> {
-        self.0
+        self.unwrap_or_clone()
     }
 }
 
@@ -78,7 +79,7 @@ impl FromIterator<Record> for Records {
     where
         I: IntoIterator<Item = Record>,
     {
-        Records(iter.into_iter().collect())
+        Records(Arc::new(iter.into_iter().collect()))
     }
 }
 impl FromIterator<Vec<DataType>> for Records {
@@ -86,7 +87,7 @@ impl FromIterator<Vec<DataType>> for Records {
     where
         I: IntoIterator<Item = Vec<DataType>>,
     {
-        Records(iter.into_iter().map(Record::Positive).collect())
+        Records(Arc::new(iter.into_iter().map(Record::Positive).collect()))
     }
 }
 
@@ -94,7 +95,7 @@ impl IntoIterator for Records {
     type Item = Record;
     type IntoIter = ::std::vec::IntoIter<Record>;
     fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+        self.unwrap_or_clone().into_iter()
     }
 }
 impl<'a> IntoIterator for &'a Records {
@@ -105,10 +106,20 @@ impl<'a> IntoIterator for &'a Records {
     }
 }
 
+/// A batch of records.
+///
+/// Clones share the records, so a batch can be sent to several children without copying it. The
+/// records are only copied when one of the clones is modified, or taken apart, while it is still
+/// shared.
 #[derive(Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
-pub struct Records(Vec<Record>);
+pub struct Records(Arc<Vec<Record>>);
 
 impl Records {
+    /// Take the records out of the batch, copying them if it is still shared.
+    fn unwrap_or_clone(self) -> Vec<Record> {
+        Arc::try_unwrap(self.0).unwrap_or_else(|rs| (*rs).clone())
+    }
+
     pub fn has<Q: ?Sized>(&self, q: &Q, positive: bool) -> bool
     where
         Vec<DataType>: Borrow<Q>,
@@ -147,30 +158,52 @@ impl Deref for Records {
 
 impl DerefMut for Records {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        Arc::make_mut(&mut self.0)
     }
 }
 
 impl Into<Records> for Record {
     fn into(self) -> Records {
-        Records(vec![self])
+        Records(Arc::new(vec![self]))
     }
 }
 
 impl Into<Records> for Vec<Record> {
     fn into(self) -> Records {
-        Records(self)
+        Records(Arc::new(self))
     }
 }
 
 impl Into<Records> for Vec<Vec<DataType>> {
     fn into(self) -> Records {
-        Records(self.into_iter().map(Into::into).collect())
+        Records(Arc::new(self.into_iter().map(Into::into).collect()))
     }
 }
 
 impl Into<Records> for Vec<(Vec<DataType>, bool)> {
     fn into(self) -> Records {
-        Records(self.into_iter().map(Into::into).collect())
+        Records(Arc::new(self.into_iter().map(Into::into).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_until_modified() {
+        let rs: Records = vec![vec![DataType::from(1)], vec![DataType::from(2)]].into();
+        let mut copy = rs.clone();
+        assert!(Arc::ptr_eq(&rs.0, &copy.0));
+
+        copy.push(Record::Negative(vec![DataType::from(1)]));
+        assert!(!Arc::ptr_eq(&rs.0, &copy.0));
+        assert_eq!(rs.len(), 2);
+        assert_eq!(copy.len(), 3);
+
+        // the last holder takes the records without copying them
+        let ptr = copy.as_ptr();
+        let taken: Vec<Record> = copy.into();
+        assert_eq!(taken.as_ptr(), ptr);
     }
 }
@@ -0,0 +1,284 @@
+use crate::prelude::*;
+use rand::prelude::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The number of slots that key statistics are kept in.
+const SLOTS: usize = 1 << 14;
+
+/// The number of candidate keys sampled for each key evicted by recency or frequency.
+const SAMPLES_PER_KEY: usize = 5;
+
+/// How a partially materialized reader chooses which keys to evict when its domain runs low on
+/// memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EvictionPolicy {
+    /// Evict keys chosen uniformly at random.
+    Random,
+    /// Evict the keys that were read least recently.
+    Lru,
+    /// Evict the keys that were read least frequently. Read counts decay with every round of
+    /// evictions, so keys that are no longer read are eventually evicted.
+    Lfu,
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> Self {
+        EvictionPolicy::Random
+    }
+}
+
+fn slot_of(key: &[DataType]) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() as usize % SLOTS
+}
+
+/// Statistics about reads of a reader's keys, which guide what it evicts.
+///
+/// Read handles record reads, and the write handle consults the statistics when it evicts. To keep
+/// reads cheap, they only touch a few relaxed atomics: keys are hashed into a fixed number of
+/// slots, and keys that share a slot share their statistics.
+pub(super) struct AccessStats {
+    policy: EvictionPolicy,
+    /// For `Lru`, the clock value at the last read of a key in each slot. For `Lfu`, the decayed
+    /// number of reads of keys in each slot. Unused for `Random`.
+    slots: Box<[AtomicU64]>,
+    /// Advanced by the writer every time it makes new writes visible.
+    clock: AtomicU64,
+    /// One bit per slot, set when a key in the slot is evicted, and cleared when one misses.
+    evicted: Box<[AtomicU64]>,
+    evictions: AtomicU64,
+    re_misses: AtomicU64,
+}
+
+impl AccessStats {
+    pub(super) fn new(policy: EvictionPolicy) -> Self {
+        let slots = match policy {
+            EvictionPolicy::Random => 0,
+            EvictionPolicy::Lru | EvictionPolicy::Lfu => SLOTS,
+        };
+        AccessStats {
+            policy,
+            slots: (0..slots).map(|_| AtomicU64::new(0)).collect(),
+            clock: AtomicU64::new(0),
+            evicted: (0..SLOTS / 64).map(|_| AtomicU64::new(0)).collect(),
+            evictions: AtomicU64::new(0),
+            re_misses: AtomicU64::new(0),
+        }
+    }
+
+    pub(super) fn policy(&self) -> EvictionPolicy {
+        self.policy
+    }
+
+    /// Record a read that hit `key`.
+    pub(super) fn hit(&self, key: &[DataType]) {
+        match self.policy {
+            EvictionPolicy::Random => {}
+            EvictionPolicy::Lru => {
+                let now = self.clock.load(Ordering::Relaxed);
+                self.slots[slot_of(key)].store(now, Ordering::Relaxed);
+            }
+            EvictionPolicy::Lfu => {
+                self.slots[slot_of(key)].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Record a read that missed on `key`, and so triggered a replay.
+    pub(super) fn miss(&self, key: &[DataType]) {
+        let slot = slot_of(key);
+        let bit = 1 << (slot % 64);
+        if self.evicted[slot / 64].fetch_and(!bit, Ordering::Relaxed) & bit != 0 {
+            self.re_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record that `key` was evicted.
+    pub(super) fn evicted(&self, key: &[DataType]) {
+        let slot = slot_of(key);
+        self.evicted[slot / 64].fetch_or(1 << (slot % 64), Ordering::Relaxed);
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Advance the clock that reads are timestamped with.
+    pub(super) fn tick(&self) {
+        if let EvictionPolicy::Lru = self.policy {
+            self.clock.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Decay read counts, so that keys that were once read often can be evicted eventually.
+    pub(super) fn decay(&self) {
+        if let EvictionPolicy::Lfu = self.policy {
+            for slot in self.slots.iter() {
+                let reads = slot.load(Ordering::Relaxed);
+                slot.store(reads / 2, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// How much we'd like to keep `key`; keys with lower scores are evicted first.
+    pub(super) fn score(&self, key: &[DataType]) -> u64 {
+        self.slots[slot_of(key)].load(Ordering::Relaxed)
+    }
+
+    /// The number of keys evicted, and the number of misses on keys after they were evicted.
+    pub(super) fn counts(&self) -> (u64, u64) {
+        (
+            self.evictions.load(Ordering::Relaxed),
+            self.re_misses.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// The keys present in a reader, in a form that can be sampled from at random.
+#[derive(Default)]
+pub(super) struct KeySet {
+    keys: Vec<Vec<DataType>>,
+    index: HashMap<Vec<DataType>, usize>,
+}
+
+impl KeySet {
+    pub(super) fn insert(&mut self, key: &[DataType]) {
+        if !self.index.contains_key(key) {
+            self.index.insert(key.to_vec(), self.keys.len());
+            self.keys.push(key.to_vec());
+        }
+    }
+
+    pub(super) fn remove(&mut self, key: &[DataType]) {
+        if let Some(i) = self.index.remove(key) {
+            self.keys.swap_remove(i);
+            if let Some(moved) = self.keys.get(i) {
+                *self.index.get_mut(moved).unwrap() = i;
+            }
+        }
+    }
+
+    /// Pick up to `n` keys to evict, preferring those with the lowest scores among a random
+    /// sample of the keys. Keys for which `skip` returns true are never picked, and no keys are
+    /// picked only if every key is skipped.
+    pub(super) fn victims<F>(
+        &self,
+        rng: &mut ThreadRng,
+        n: usize,
+        stats: &AccessStats,
+        skip: F,
+    ) -> Vec<Vec<DataType>>
+    where
+        F: Fn(&[DataType]) -> bool,
+    {
+        if self.keys.is_empty() {
+            return Vec::new();
+        }
+
+        let mut sample: Vec<_> = (0..n * SAMPLES_PER_KEY)
+            .map(|_| rng.gen_range(0, self.keys.len()))
+            .collect();
+        sample.sort_unstable();
+        sample.dedup();
+
+        let mut candidates: Vec<_> = sample
+            .into_iter()
+            .map(|i| &self.keys[i])
+            .filter(|key| !skip(key))
+            .map(|key| (stats.score(key), key))
+            .collect();
+        if candidates.is_empty() {
+            // the sample may just have missed the keys that may be evicted
+            candidates = self
+                .keys
+                .iter()
+                .filter(|key| !skip(key))
+                .map(|key| (stats.score(key), key))
+                .collect();
+        }
+        candidates.sort_unstable_by_key(|&(score, _)| score);
+        candidates
+            .into_iter()
+            .take(n)
+            .map(|(_, key)| key.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyset_insert_remove() {
+        let mut keys = KeySet::default();
+        keys.insert(&[1.into()]);
+        keys.insert(&[2.into()]);
+        keys.insert(&[1.into()]);
+        assert_eq!(keys.keys.len(), 2);
+
+        keys.remove(&[1.into()]);
+        keys.remove(&[3.into()]);
+        assert_eq!(keys.keys, vec![vec![DataType::from(2)]]);
+        assert_eq!(keys.index[&vec![DataType::from(2)]], 0);
+    }
+
+    #[test]
+    fn lfu_prefers_cold_keys() {
+        let stats = AccessStats::new(EvictionPolicy::Lfu);
+        let mut keys = KeySet::default();
+        for k in 0..4 {
+            keys.insert(&[k.into()]);
+        }
+        for _ in 0..10 {
+            for k in 1..4 {
+                stats.hit(&[k.into()]);
+            }
+        }
+
+        let mut rng = rand::thread_rng();
+        // with enough samples, the cold key is almost certainly among them
+        let mut victims = Vec::new();
+        for _ in 0..10 {
+            victims = keys.victims(&mut rng, 1, &stats, |_| false);
+            if victims == vec![vec![DataType::from(0)]] {
+                break;
+            }
+        }
+        assert_eq!(victims, vec![vec![DataType::from(0)]]);
+
+        // keys with in-flight replays are left alone
+        for _ in 0..10 {
+            let victims = keys.victims(&mut rng, 1, &stats, |k| k[0] == 0.into());
+            assert_ne!(victims, vec![vec![DataType::from(0)]]);
+        }
+    }
+
+    #[test]
+    fn victims_despite_skipped_sample() {
+        let stats = AccessStats::new(EvictionPolicy::Lru);
+        let mut keys = KeySet::default();
+        for k in 0..1000 {
+            keys.insert(&[k.into()]);
+        }
+
+        // a sample of a few keys almost never includes the one that may be evicted
+        let mut rng = rand::thread_rng();
+        let victims = keys.victims(&mut rng, 1, &stats, |k| k[0] != 999.into());
+        assert_eq!(victims, vec![vec![DataType::from(999)]]);
+
+        assert!(keys.victims(&mut rng, 1, &stats, |_| true).is_empty());
+    }
+
+    #[test]
+    fn re_misses() {
+        let stats = AccessStats::new(EvictionPolicy::Random);
+        stats.miss(&[1.into()]);
+        assert_eq!(stats.counts(), (0, 0));
+        stats.evicted(&[1.into()]);
+        stats.miss(&[1.into()]);
+        stats.miss(&[1.into()]);
+        assert_eq!(stats.counts(), (1, 1));
+    }
+}
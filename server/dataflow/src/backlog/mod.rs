@@ -1,34 +1,278 @@
+use crate::payload::Label;
 use crate::prelude::*;
 use ahash::RandomState;
 use common::SizeOf;
+use nom_sql::OrderType;
+use noria::subscription::{Change, ChangeLabel};
+use noria::{ChangeCursor, ChangeSet, KeyRange};
 use rand::prelude::*;
 use std::borrow::Cow;
-use std::sync::Arc;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::ops::Bound;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time;
+use tokio::sync::watch;
+
+mod eviction;
+pub use self::eviction::EvictionPolicy;
+use self::eviction::{AccessStats, KeySet};
+mod negative;
+pub(crate) use self::negative::NegativeCache;
+pub use self::negative::NegativeCaching;
+mod spill;
+pub(crate) use self::spill::Spill;
 
 /// Allocate a new end-user facing result table.
 pub(crate) fn new(cols: usize, key: &[usize]) -> (SingleReadHandle, WriteHandle) {
-    new_inner(cols, key, None)
+    new_inner(cols, key, None, false, EvictionPolicy::Random)
+}
+
+/// Allocate a new end-user facing result table that also keeps its keys in order, so that it can
+/// serve range lookups.
+pub(crate) fn new_ordered(cols: usize, key: &[usize]) -> (SingleReadHandle, WriteHandle) {
+    new_inner(cols, key, None, true, EvictionPolicy::Random)
 }
 
 /// Allocate a new partially materialized end-user facing result table.
 ///
 /// Misses in this table will call `trigger` to populate the entry, and retry until successful.
+/// When the table has to shrink, keys are evicted according to `policy`.
 pub(crate) fn new_partial<F>(
     cols: usize,
     key: &[usize],
+    policy: EvictionPolicy,
     trigger: F,
 ) -> (SingleReadHandle, WriteHandle)
 where
     F: Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + 'static + Send + Sync,
 {
-    new_inner(cols, key, Some(Arc::new(trigger)))
+    new_inner(cols, key, Some(Arc::new(trigger)), false, policy)
+}
+
+/// An ordered index over the keys present in a reader, along with the number of rows for each.
+///
+/// The `evmap` holding the rows is only ever looked up by exact key, so range lookups first find
+/// the matching keys here.
+type KeyIndex = Arc<RwLock<BTreeMap<Vec<DataType>, usize>>>;
+
+/// The number of batches of changes a reader retains for clients subscribed to its changes.
+const CHANGE_LOG_CAPACITY: usize = 4096;
+
+/// Recent changes to a reader, for clients subscribed to its change feed.
+///
+/// Nothing is logged until the first client subscribes.
+struct ChangeLog {
+    /// Distinguishes this log from that of any other reader, including earlier incarnations of
+    /// this one.
+    id: u64,
+    subscribed: AtomicBool,
+    /// Whether the writer has left changes out of the log since the last swap.
+    dirty: AtomicBool,
+    batches: RwLock<ChangeBatches>,
+    /// Ticks whenever a swap adds to the log, or makes it complete from its end onwards.
+    swaps: watch::Sender<u64>,
+    swapped: watch::Receiver<u64>,
+}
+
+impl ChangeLog {
+    fn new() -> Self {
+        let (swaps, swapped) = watch::channel(0);
+        ChangeLog {
+            id: rand::random(),
+            subscribed: AtomicBool::new(false),
+            dirty: AtomicBool::new(false),
+            batches: RwLock::default(),
+            swaps,
+            swapped,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ChangeBatches {
+    /// The sequence number of the next batch.
+    next: u64,
+    /// The most recent batches, the last of which has sequence number `next - 1`.
+    batches: VecDeque<LoggedBatch>,
+    /// Whether the writer logs every change, in which case every batch from `start` on is
+    /// complete.
+    recording: bool,
+    start: u64,
+    /// The label of the last batch of writes from each base table shard that the log reflects up
+    /// to `start`, and up to `next`.
+    base: HashMap<(NodeIndex, usize), u64>,
+    seen: HashMap<(NodeIndex, usize), u64>,
+}
+
+#[derive(Default)]
+struct LoggedBatch {
+    records: Vec<Record>,
+    /// The label of the base table batch each record stems from, if any, along with the base
+    /// table, as the position of the first record from each.
+    labels: Vec<(usize, Option<(NodeIndex, ChangeLabel)>)>,
+    /// The labels of the batches of writes applied along with the batch.
+    applied: Vec<((NodeIndex, usize), u64)>,
+}
+
+/// The label of the last batch of writes from each base table shard that a reader reflects, and
+/// when the base shard applied that batch.
+///
+/// A batch reaches the reader once along each path through the graph from its base, in the order
+/// the base processed it, so the reader reflects a batch once it has arrived along every path.
+type Frontier = Arc<RwLock<HashMap<(NodeIndex, usize), (u64, u64)>>>;
+
+/// The replay outstanding for each missing key of a partial reader.
+///
+/// Entries are removed when their key is filled, or evicted after having been filled by some other
+/// replay.
+type Replays = Arc<Mutex<HashMap<Vec<DataType>, Replay>>>;
+
+/// How long a replay may go without being requested again before a miss on its key assumes it was
+/// lost and requests it anew.
+pub(crate) const REPLAY_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug)]
+struct Replay {
+    /// When the replay was first requested.
+    started: time::Instant,
+    /// When the replay was last requested.
+    requested: time::Instant,
+}
+
+/// The order in which a reader returns the rows for each key.
+///
+/// Rows that compare equal on the order columns are ordered by their remaining values, which makes
+/// the order deterministic, and paginated reads neither skip nor repeat rows.
+#[derive(Clone, Debug)]
+struct RowOrder(Vec<(usize, OrderType)>);
+
+impl RowOrder {
+    fn cmp(&self, a: &[DataType], b: &[DataType]) -> Ordering {
+        for &(c, ref order_type) in &self.0 {
+            let result = match *order_type {
+                OrderType::OrderAscending => a[c].cmp(&b[c]),
+                OrderType::OrderDescending => b[c].cmp(&a[c]),
+            };
+            if result != Ordering::Equal {
+                return result;
+            }
+        }
+        a.cmp(b)
+    }
+}
+
+/// The rows for each key of a reader with a row order, kept in that order.
+///
+/// `evmap` keeps the rows for a key in an unordered bag, so readers with a row order also keep a
+/// sorted copy of each key's rows here, which the writer updates in place as rows come and go.
+/// Whether a key is present, or a hole, is still up to the `evmap`.
+type SortedRows = Arc<RwLock<HashMap<Vec<DataType>, Vec<Vec<DataType>>>>>;
+
+/// The rows of a reader with a row order, along with the changes to them since the last swap.
+struct Sorted {
+    order: Arc<RowOrder>,
+    rows: SortedRows,
+    pending: Vec<SortedChange>,
+}
+
+enum SortedChange {
+    Add(Record),
+    /// All the rows for the key were evicted, or the key was marked as a hole.
+    Empty(Vec<DataType>),
+}
+
+/// The rows for one key of a reader, in the reader's row order if it has one.
+#[derive(Clone, Copy)]
+pub enum KeyRows<'a> {
+    Unordered(&'a evmap::Values<Vec<DataType>, RandomState>),
+    Sorted(&'a [Vec<DataType>]),
+}
+
+impl<'a> KeyRows<'a> {
+    pub fn len(&self) -> usize {
+        match *self {
+            KeyRows::Unordered(rs) => rs.len(),
+            KeyRows::Sorted(rs) => rs.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> KeyRowsIter<'a> {
+        (*self).into_iter()
+    }
+}
+
+impl<'a> IntoIterator for KeyRows<'a> {
+    type Item = &'a Vec<DataType>;
+    type IntoIter = KeyRowsIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        match self {
+            KeyRows::Unordered(rs) => KeyRowsIter::Unordered(rs.into_iter()),
+            KeyRows::Sorted(rs) => KeyRowsIter::Sorted(rs.iter()),
+        }
+    }
+}
+
+pub enum KeyRowsIter<'a> {
+    Unordered(<&'a evmap::Values<Vec<DataType>, RandomState> as IntoIterator>::IntoIter),
+    Sorted(std::slice::Iter<'a, Vec<DataType>>),
+}
+
+impl<'a> Iterator for KeyRowsIter<'a> {
+    type Item = &'a Vec<DataType>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            KeyRowsIter::Unordered(iter) => iter.next(),
+            KeyRowsIter::Sorted(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            KeyRowsIter::Unordered(iter) => iter.size_hint(),
+            KeyRowsIter::Sorted(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl<'a> ExactSizeIterator for KeyRowsIter<'a> {}
+
+/// Keep the rows for each key of the new reader behind `r` and `w` sorted by the given columns, so
+/// that reads return them in that order.
+pub(crate) fn set_row_order(
+    r: &mut SingleReadHandle,
+    w: &mut WriteHandle,
+    order: &[(usize, OrderType)],
+) {
+    assert_eq!(
+        w.rows, 0,
+        "row order must be set before the reader has rows"
+    );
+    let rows = SortedRows::default();
+    r.sorted = Some(Arc::clone(&rows));
+    w.sorted = Some(Sorted {
+        order: Arc::new(RowOrder(Vec::from(order))),
+        rows,
+        pending: Vec::new(),
+    });
 }
 
 fn new_inner(
     cols: usize,
     key: &[usize],
     trigger: Option<Arc<dyn Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + Send + Sync>>,
+    ordered: bool,
+    policy: EvictionPolicy,
 ) -> (SingleReadHandle, WriteHandle) {
+    // ranges can't be replayed, so there's no such thing as a partial ordered reader
+    assert!(!ordered || trigger.is_none());
+
     let contiguous = {
         let mut contiguous = true;
         let mut last = None;
@@ -63,6 +307,23 @@ fn new_inner(
         _ => make!(Many),
     };
 
+    let index = if ordered {
+        Some(KeyIndex::default())
+    } else {
+        None
+    };
+
+    let changes = Arc::new(ChangeLog::new());
+    let frontier = Frontier::default();
+    let access = Arc::new(AccessStats::new(policy));
+    let replays = Replays::default();
+    // random evictions are left to evmap, but other policies need to sample the keys themselves
+    let keys = if trigger.is_some() && policy != EvictionPolicy::Random {
+        Some(KeySet::default())
+    } else {
+        None
+    };
+
     let w = WriteHandle {
         partial: trigger.is_some(),
         handle: w,
@@ -70,11 +331,37 @@ fn new_inner(
         cols,
         contiguous,
         mem_size: 0,
+        rows: 0,
+        index: index.clone(),
+        index_delta: HashMap::new(),
+        changes: Arc::clone(&changes),
+        pending_changes: LoggedBatch::default(),
+        recording: false,
+        unlogged: false,
+        frontier: Arc::clone(&frontier),
+        paths: HashMap::new(),
+        path_labels: HashMap::new(),
+        pending_labels: HashMap::new(),
+        transactions: HashMap::new(),
+        held: VecDeque::new(),
+        access: Arc::clone(&access),
+        keys,
+        replays: Arc::clone(&replays),
+        sorted: None,
     };
     let r = SingleReadHandle {
+        name: Arc::from(""),
         handle: r,
         trigger,
         key: Vec::from(key),
+        index,
+        sorted: None,
+        placement: None,
+        removed: Default::default(),
+        changes,
+        frontier,
+        access,
+        replays,
     };
 
     (r, w)
@@ -111,6 +398,57 @@ pub(crate) struct WriteHandle {
     key: Vec<usize>,
     contiguous: bool,
     mem_size: usize,
+    /// The number of rows in the reader, including those not yet made visible.
+    rows: usize,
+    index: Option<KeyIndex>,
+    /// Changes to the row count of each key since the last swap.
+    index_delta: HashMap<Vec<DataType>, isize>,
+    changes: Arc<ChangeLog>,
+    /// Changes to log for subscribers on the next swap.
+    pending_changes: LoggedBatch,
+    /// Whether changes are being logged, and whether some were left out since the last swap.
+    recording: bool,
+    unlogged: bool,
+    frontier: Frontier,
+    /// The number of paths through the graph from each base table to the reader, if more than
+    /// one.
+    paths: HashMap<NodeIndex, usize>,
+    /// The label of the last batch from each base shard applied along each path, and when the
+    /// base shard applied it.
+    path_labels: HashMap<(NodeIndex, usize), HashMap<u64, (u64, u64)>>,
+    /// Labels of the batches applied since the last swap, and when their base shards applied
+    /// them.
+    pending_labels: HashMap<(NodeIndex, usize), (u64, u64)>,
+    /// The transactions that the reader exposes all at once, by commit timestamp.
+    transactions: HashMap<u64, PendingTransaction>,
+    /// Batches held back from the reader, in the order they arrived in: those of transactions
+    /// that have not fully arrived, and any later ones from the same base shards.
+    held: VecDeque<(Records, Label)>,
+    access: Arc<AccessStats>,
+    /// The keys present in the reader, if its eviction policy needs them.
+    keys: Option<KeySet>,
+    replays: Replays,
+    /// The rows of each key in the reader's row order, if it has one.
+    sorted: Option<Sorted>,
+}
+
+/// A transaction whose batches a reader holds back until it has received them all.
+struct PendingTransaction {
+    /// How many batches from distinct base shards the reader receives for the transaction.
+    parts: usize,
+    /// How many of the transaction's batches the reader has received from each base shard.
+    seen: HashMap<(NodeIndex, usize), usize>,
+}
+
+impl PendingTransaction {
+    /// Whether the transaction's batches have arrived along every path from every base shard.
+    fn complete(&self, paths: &HashMap<NodeIndex, usize>) -> bool {
+        self.seen.len() >= self.parts
+            && self
+                .seen
+                .iter()
+                .all(|(&(base, _), &n)| n >= paths.get(&base).cloned().unwrap_or(1))
+    }
 }
 
 type Key<'a> = Cow<'a, [DataType]>;
@@ -130,6 +468,10 @@ impl<'a> MutWriteHandleEntry<'a> {
             .handle
             .meta_get_and(Cow::Borrowed(&*self.key), |rs| rs.is_empty())
         {
+            if let Some(ref mut keys) = self.handle.keys {
+                keys.insert(&self.key);
+            }
+            self.handle.replay_done(&self.key);
             self.handle.handle.clear(self.key)
         } else {
             unreachable!("attempted to fill already-filled key");
@@ -137,15 +479,23 @@ impl<'a> MutWriteHandleEntry<'a> {
     }
 
     pub(crate) fn mark_hole(self) {
-        let size = self
+        let (size, rows) = self
             .handle
             .handle
             .meta_get_and(Cow::Borrowed(&*self.key), |rs| {
-                rs.iter().map(SizeOf::deep_size_of).sum()
+                (rs.iter().map(SizeOf::deep_size_of).sum(), rs.len())
             })
-            .map(|r| r.0.unwrap_or(0))
-            .unwrap_or(0);
+            .and_then(|r| r.0)
+            .unwrap_or((0, 0));
         self.handle.mem_size = self.handle.mem_size.checked_sub(size as usize).unwrap();
+        self.handle.rows = self.handle.rows.checked_sub(rows).unwrap();
+        if let Some(ref mut keys) = self.handle.keys {
+            keys.remove(&self.key);
+        }
+        if let Some(ref mut sorted) = self.handle.sorted {
+            sorted.pending.push(SortedChange::Empty(self.key.to_vec()));
+        }
+        self.handle.replay_done(&self.key);
         self.handle.handle.empty(self.key)
     }
 }
@@ -220,6 +570,11 @@ impl WriteHandle {
         self.mut_with_key(key)
     }
 
+    /// The key that `record` is stored under.
+    pub(crate) fn key_of<'a>(&self, record: &'a [DataType]) -> Key<'a> {
+        key_from_record(&self.key[..], self.contiguous, record)
+    }
+
     pub(crate) fn entry_from_record<'a, R>(&'a self, record: R) -> WriteHandleEntry<'a>
     where
         R: Into<Cow<'a, [DataType]>>,
@@ -229,7 +584,273 @@ impl WriteHandle {
     }
 
     pub(crate) fn swap(&mut self) {
+        if let Some(ref index) = self.index {
+            if !self.index_delta.is_empty() {
+                let mut index = index.write().unwrap();
+                for (key, delta) in self.index_delta.drain() {
+                    let n = index.get(&key).cloned().unwrap_or(0);
+                    // a key can't have fewer than no rows, however the negatives add up
+                    let n = if delta >= 0 {
+                        n.checked_add(delta as usize)
+                    } else {
+                        n.checked_sub(delta.abs() as usize)
+                    }
+                    .unwrap_or(0);
+                    if n == 0 {
+                        index.remove(&key);
+                    } else {
+                        index.insert(key, n);
+                    }
+                }
+            }
+        }
+        if let Some(ref mut sorted) = self.sorted {
+            if !sorted.pending.is_empty() {
+                // the lock is released before the refresh below, which waits for readers that
+                // may be waiting for it
+                let mut rows = sorted.rows.write().unwrap();
+                for change in sorted.pending.drain(..) {
+                    let (r, positive) = match change {
+                        SortedChange::Empty(key) => {
+                            rows.remove(&key);
+                            continue;
+                        }
+                        SortedChange::Add(r) => r.extract(),
+                    };
+                    let key = key_from_record(&self.key[..], self.contiguous, &r[..]);
+                    let order = &sorted.order;
+                    if positive {
+                        let rs = rows.entry(key.into_owned()).or_insert_with(Vec::new);
+                        let i = match rs.binary_search_by(|x| order.cmp(x, &r)) {
+                            Ok(i) | Err(i) => i,
+                        };
+                        rs.insert(i, r);
+                    } else if let Some(rs) = rows.get_mut(&key[..]) {
+                        if let Ok(i) = rs.binary_search_by(|x| order.cmp(x, &r)) {
+                            rs.remove(i);
+                        }
+                        if rs.is_empty() {
+                            rows.remove(&key[..]);
+                        }
+                    }
+                }
+            }
+        }
+        let logged = !self.pending_changes.records.is_empty() || !self.pending_labels.is_empty();
+        if self.unlogged || (self.recording && logged) {
+            self.log_swap();
+        }
         self.handle.refresh();
+        self.access.tick();
+        if !self.pending_labels.is_empty() {
+            // only once the writes are visible to readers
+            let mut frontier = self.frontier.write().unwrap();
+            for (source, (seq, committed)) in self.pending_labels.drain() {
+                let last = frontier.entry(source).or_insert((0, 0));
+                last.0 = std::cmp::max(last.0, seq);
+                last.1 = std::cmp::max(last.1, committed);
+            }
+        }
+    }
+
+    /// Append the changes logged since the last swap to the change log, and let subscribers
+    /// waiting for changes know.
+    fn log_swap(&mut self) {
+        let mut log = self.changes.batches.write().unwrap();
+        if !log.recording {
+            let frontier = self.frontier.read().unwrap();
+            log.seen = frontier.iter().map(|(&k, &(seq, _))| (k, seq)).collect();
+            if !self.unlogged {
+                // nothing was left out, so the log is complete from this swap's batch on
+                log.start = log.next;
+                log.base = log.seen.clone();
+            }
+        }
+
+        let mut batch = std::mem::take(&mut self.pending_changes);
+        for (&source, &(seq, _)) in &self.pending_labels {
+            let last = log.seen.entry(source).or_insert(0);
+            *last = std::cmp::max(*last, seq);
+            batch.applied.push((source, seq));
+        }
+        if !batch.records.is_empty() {
+            log.next += 1;
+            log.batches.push_back(batch);
+            if log.batches.len() > CHANGE_LOG_CAPACITY {
+                log.batches.pop_front();
+            }
+        } else if let Some(last) = log.batches.back_mut() {
+            // the labels still count towards where subscribers are
+            last.applied.extend(batch.applied);
+        }
+
+        if self.unlogged {
+            // subscribers can only pick up from after the batch that left out changes
+            log.start = log.next;
+            log.base = log.seen.clone();
+            self.unlogged = false;
+        }
+        log.recording = self.recording;
+        self.changes.dirty.store(false, atomic::Ordering::SeqCst);
+        let next = log.next;
+        drop(log);
+        let _ = self.changes.swaps.broadcast(next);
+    }
+
+    /// The latest batch of writes from each base shard that readers can see has been applied.
+    pub(crate) fn frontier(&self) -> Vec<((NodeIndex, usize), u64)> {
+        let frontier = self.frontier.read().unwrap();
+        frontier.iter().map(|(&k, &(seq, _))| (k, seq)).collect()
+    }
+
+    /// The time, in nanoseconds since the Unix epoch, up to which readers can see every batch of
+    /// writes applied by the base shards the reader has heard from, if it has heard from any.
+    pub(crate) fn applied(&self) -> Option<u64> {
+        let frontier = self.frontier.read().unwrap();
+        frontier.values().map(|&(_, committed)| committed).min()
+    }
+
+    /// The reader hears of each batch of writes to `base` along `paths` paths through the graph.
+    pub(crate) fn set_paths(&mut self, base: NodeIndex, paths: usize) {
+        self.paths.insert(base, paths);
+    }
+
+    /// Record that the batch of writes with the given label has been applied to the reader.
+    ///
+    /// Readers will see the batch as applied after the next call to `swap()`.
+    pub(crate) fn observe(&mut self, label: Label) {
+        let source = (label.base, label.shard);
+        let paths = self.path_labels.entry(source).or_insert_with(HashMap::new);
+        let last = paths.entry(label.path).or_insert((0, 0));
+        last.0 = std::cmp::max(last.0, label.seq);
+        last.1 = std::cmp::max(last.1, label.committed);
+
+        // a batch is only applied once it has arrived along every path from its base
+        let expected = self.paths.get(&label.base).cloned().unwrap_or(1);
+        if paths.len() < expected {
+            return;
+        }
+        let seq = paths.values().map(|&(seq, _)| seq).min().unwrap_or(0);
+        let committed = paths.values().map(|&(_, c)| c).min().unwrap_or(0);
+        let pending = self.pending_labels.entry(source).or_insert((0, 0));
+        pending.0 = std::cmp::max(pending.0, seq);
+        pending.1 = std::cmp::max(pending.1, committed);
+    }
+
+    /// Hold back the batches of the transaction with commit timestamp `txn` until `parts` of
+    /// them, from distinct base shards, have arrived, so that they can all be applied at once.
+    ///
+    /// With no `parts`, stop waiting for the transaction. Either way, `release()` hands back
+    /// whatever no longer needs to be held.
+    pub(crate) fn expect_transaction(&mut self, txn: u64, parts: usize) {
+        if parts == 0 {
+            self.transactions.remove(&txn);
+        } else {
+            self.transactions.insert(
+                txn,
+                PendingTransaction {
+                    parts,
+                    seen: HashMap::new(),
+                },
+            );
+        }
+    }
+
+    /// Hold back a batch that has arrived with the given label if it belongs to a transaction
+    /// the reader is waiting for the rest of, or if an earlier batch from its base shard is being
+    /// held back. Otherwise, hand it back to be applied right away.
+    pub(crate) fn hold(&mut self, rs: Records, label: Label) -> Option<(Records, Label)> {
+        let source = (label.base, label.shard);
+        let pending = match label.txn.and_then(|txn| self.transactions.get_mut(&txn)) {
+            Some(t) => {
+                *t.seen.entry(source).or_insert(0) += 1;
+                true
+            }
+            None => false,
+        };
+        if pending || self.held.iter().any(|(_, l)| (l.base, l.shard) == source) {
+            self.held.push_back((rs, label));
+            None
+        } else {
+            Some((rs, label))
+        }
+    }
+
+    /// Take the held back batches that can now be applied, in the order they arrived in.
+    ///
+    /// A batch stays held back while its transaction has not fully arrived, or while an earlier
+    /// batch from its base shard does.
+    pub(crate) fn release(&mut self) -> Vec<(Records, Label)> {
+        if self.held.is_empty() {
+            return Vec::new();
+        }
+        let paths = &self.paths;
+        self.transactions.retain(|_, t| !t.complete(paths));
+
+        let mut released = Vec::new();
+        let mut blocked = HashSet::new();
+        let mut still_held = VecDeque::new();
+        for (rs, label) in self.held.drain(..) {
+            let source = (label.base, label.shard);
+            let pending = label
+                .txn
+                .map(|txn| self.transactions.contains_key(&txn))
+                .unwrap_or(false);
+            if pending || blocked.contains(&source) {
+                blocked.insert(source);
+                still_held.push_back((rs, label));
+            } else {
+                released.push((rs, label));
+            }
+        }
+        self.held = still_held;
+        released
+    }
+
+    /// Drop the held back records for `key`, which a replay has just filled with state that
+    /// already reflects them.
+    pub(crate) fn forget_held(&mut self, key: &[DataType]) {
+        let cols = &self.key[..];
+        let contiguous = self.contiguous;
+        for (rs, _) in self.held.iter_mut() {
+            rs.retain(|r| &key_from_record(cols, contiguous, &r[..])[..] != key);
+        }
+    }
+
+    /// Record changes to the contents of the reader for any subscribers to its change feed, along
+    /// with the label of the batch of writes they stem from, if any.
+    ///
+    /// These will be made visible to subscribers after the next call to `swap()`.
+    pub(crate) fn log_changes(&mut self, rs: &[Record], label: Option<&Label>) {
+        if rs.is_empty() {
+            return;
+        }
+        if !self.recording {
+            // a subscriber that sees the log as clean before this store can rely on us seeing
+            // the subscription below, and so on logging everything from here on
+            self.changes.dirty.store(true, atomic::Ordering::SeqCst);
+            if !self.changes.subscribed.load(atomic::Ordering::SeqCst) {
+                self.unlogged = true;
+                return;
+            }
+            self.recording = true;
+        }
+
+        let pending = &mut self.pending_changes;
+        let label = label.map(|label| {
+            let change = ChangeLabel {
+                shard: label.shard,
+                seq: label.seq,
+                committed: label.committed,
+                timestamp: label.txn,
+            };
+            (label.base, change)
+        });
+        let last = pending.labels.last().and_then(|&(_, label)| label);
+        if label != last {
+            pending.labels.push((pending.records.len(), label));
+        }
+        pending.records.extend(rs.iter().cloned());
     }
 
     /// Add a new set of records to the backlog.
@@ -239,7 +860,41 @@ impl WriteHandle {
     where
         I: IntoIterator<Item = Record>,
     {
-        let mem_delta = self.handle.add(&self.key[..], self.cols, rs);
+        let mut rows_delta = 0isize;
+        let rs = rs.into_iter().inspect(|r| {
+            if r.is_positive() {
+                rows_delta += 1;
+            } else {
+                rows_delta -= 1;
+            }
+        });
+        let mem_delta = if self.index.is_some() || self.keys.is_some() || self.sorted.is_some() {
+            let rs: Vec<_> = rs.collect();
+            if let Some(ref mut sorted) = self.sorted {
+                sorted
+                    .pending
+                    .extend(rs.iter().cloned().map(SortedChange::Add));
+            }
+            for r in &rs {
+                let key = key_from_record(&self.key[..], self.contiguous, &r[..]);
+                if let Some(ref mut keys) = self.keys {
+                    if r.is_positive() {
+                        keys.insert(&key);
+                    }
+                }
+                if self.index.is_some() {
+                    let delta = self.index_delta.entry(key.into_owned()).or_insert(0);
+                    if r.is_positive() {
+                        *delta += 1;
+                    } else {
+                        *delta -= 1;
+                    }
+                }
+            }
+            self.handle.add(&self.key[..], self.cols, rs)
+        } else {
+            self.handle.add(&self.key[..], self.cols, rs)
+        };
         if mem_delta > 0 {
             self.mem_size += mem_delta as usize;
         } else if mem_delta < 0 {
@@ -248,33 +903,155 @@ impl WriteHandle {
                 .checked_sub(mem_delta.checked_abs().unwrap() as usize)
                 .unwrap();
         }
+        if rows_delta > 0 {
+            self.rows += rows_delta as usize;
+        } else if rows_delta < 0 {
+            self.rows = self.rows.checked_sub(rows_delta.abs() as usize).unwrap();
+        }
+    }
+
+    /// Give every row that predates the addition of column `column` to the upstream base the
+    /// column's default value.
+    ///
+    /// The padded rows are made visible to readers immediately.
+    pub(crate) fn add_column(&mut self, column: usize, default: &DataType) {
+        self.rewrite_rows(|r| r.len() == column, |r| r.push(default.clone()));
+    }
+
+    /// Replace the value of column `column` in every row with `default`.
+    ///
+    /// The rewritten rows are made visible to readers immediately.
+    pub(crate) fn hide_column(&mut self, column: usize, default: &DataType) {
+        self.rewrite_rows(
+            |r| r.get(column).map(|v| v != default).unwrap_or(false),
+            |r| r[column] = default.clone(),
+        );
+    }
+
+    /// Apply `rewrite` to every row that matches `filter`.
+    fn rewrite_rows<F, R>(&mut self, filter: F, mut rewrite: R)
+    where
+        F: FnMut(&[DataType]) -> bool,
+        R: FnMut(&mut Vec<DataType>),
+    {
+        // make sure we also see writes that have not yet been published
+        self.swap();
+        let old = self.handle.filter_rows(filter);
+        if old.is_empty() {
+            return;
+        }
+
+        let new: Vec<_> = old
+            .iter()
+            .cloned()
+            .map(|mut r| {
+                rewrite(&mut r);
+                Record::Positive(r)
+            })
+            .collect();
+        let changes: Vec<_> = old.into_iter().map(Record::Negative).chain(new).collect();
+        self.log_changes(&changes, None);
+        self.add(changes);
+        self.swap();
+    }
+
+    /// The number of rows in the reader, including those that are not yet visible to readers.
+    pub(crate) fn rows(&self) -> usize {
+        self.rows
     }
 
     pub(crate) fn is_partial(&self) -> bool {
         self.partial
     }
 
-    /// Evict `count` randomly selected keys from state and return them along with the number of
-    /// bytes that will be freed once the underlying `evmap` applies the operation.
-    pub(crate) fn evict_random_keys(&mut self, rng: &mut ThreadRng, mut n: usize) -> u64 {
+    /// Forget the replay outstanding for `key`, if any.
+    fn replay_done(&self, key: &[DataType]) {
+        if self.partial {
+            self.replays.lock().unwrap().remove(key);
+        }
+    }
+
+    /// The reader's eviction policy, the number of keys it has evicted, and the number of misses
+    /// on keys after they were evicted.
+    pub(crate) fn eviction_stats(&self) -> (EvictionPolicy, u64, u64) {
+        let (evictions, re_misses) = self.access.counts();
+        (self.access.policy(), evictions, re_misses)
+    }
+
+    /// Evict up to `n` keys from state, chosen according to the reader's eviction policy, and
+    /// return the number of bytes that will be freed once the underlying `evmap` applies the
+    /// operation, along with the number of keys evicted. No keys are evicted only if there are
+    /// none left that may be.
+    ///
+    /// Keys for which `skip` returns true are not evicted, unless the policy is random. `evicted`
+    /// is shown the key and rows of every key that is evicted.
+    pub(crate) fn evict_keys<F, E>(
+        &mut self,
+        rng: &mut ThreadRng,
+        mut n: usize,
+        skip: F,
+        mut evicted: E,
+    ) -> (u64, usize)
+    where
+        F: Fn(&[DataType]) -> bool,
+        E: FnMut(&[DataType], &mut dyn Iterator<Item = &Vec<DataType>>),
+    {
         let mut bytes_to_be_freed = 0;
+        let mut rows_freed = 0;
+        let mut keys_evicted = 0;
         if self.mem_size > 0 {
             if self.handle.is_empty() {
                 unreachable!("mem size is {}, but map is empty", self.mem_size);
             }
 
-            self.handle.empty_random_for_each(rng, n, |vs| {
-                let size: u64 = vs.iter().map(|r| r.deep_size_of() as u64).sum();
-                bytes_to_be_freed += size;
-                n -= 1;
-            });
+            let access = &self.access;
+            let mut replays = self.replays.lock().unwrap();
+            let mut sorted = self.sorted.as_mut().map(|s| &mut s.pending);
+            if let Some(ref mut keys) = self.keys {
+                for key in keys.victims(rng, n, access, skip) {
+                    let (size, rows) = self
+                        .handle
+                        .meta_get_and(Cow::Borrowed(&key[..]), |rs| {
+                            evicted(&key[..], &mut rs.iter());
+                            (rs.iter().map(|r| r.deep_size_of() as u64).sum(), rs.len())
+                        })
+                        .and_then(|r| r.0)
+                        .unwrap_or((0, 0));
+                    bytes_to_be_freed += size;
+                    rows_freed += rows;
+                    keys_evicted += 1;
+                    keys.remove(&key);
+                    access.evicted(&key);
+                    replays.remove(&key);
+                    if let Some(ref mut sorted) = sorted {
+                        sorted.push(SortedChange::Empty(key.clone()));
+                    }
+                    self.handle.empty(Cow::Owned(key));
+                }
+                access.decay();
+            } else {
+                self.handle.empty_random_for_each(rng, n, |key, vs| {
+                    let size: u64 = vs.iter().map(|r| r.deep_size_of() as u64).sum();
+                    bytes_to_be_freed += size;
+                    rows_freed += vs.len();
+                    keys_evicted += 1;
+                    evicted(key, &mut vs.iter());
+                    access.evicted(key);
+                    replays.remove(key);
+                    if let Some(ref mut sorted) = sorted {
+                        sorted.push(SortedChange::Empty(key.to_vec()));
+                    }
+                    n -= 1;
+                });
+            }
         }
 
         self.mem_size = self
             .mem_size
             .checked_sub(bytes_to_be_freed as usize)
             .unwrap();
-        bytes_to_be_freed
+        self.rows = self.rows.checked_sub(rows_freed).unwrap();
+        (bytes_to_be_freed, keys_evicted)
     }
 }
 
@@ -297,22 +1074,105 @@ impl SizeOf for WriteHandle {
 /// Handle to get the state of a single shard of a reader.
 #[derive(Clone)]
 pub struct SingleReadHandle {
+    name: Arc<str>,
     handle: multir::Handle,
     trigger: Option<Arc<dyn Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + Send + Sync>>,
     key: Vec<usize>,
+    index: Option<KeyIndex>,
+    sorted: Option<SortedRows>,
+    placement: Option<Arc<ShardPlacement>>,
+    removed: Arc<AtomicBool>,
+    changes: Arc<ChangeLog>,
+    frontier: Frontier,
+    access: Arc<AccessStats>,
+    replays: Replays,
+}
+
+/// Which keys one shard of a reader that is sharded by the hash of its key holds.
+#[derive(Debug)]
+struct ShardPlacement {
+    shard: usize,
+    shards: usize,
+    hasher: noria::ShardHasher,
 }
 
 impl std::fmt::Debug for SingleReadHandle {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SingleReadHandle")
+            .field("name", &self.name)
             .field("handle", &self.handle)
             .field("has_trigger", &self.trigger.is_some())
             .field("key", &self.key)
+            .field("ordered", &self.index.is_some())
+            .field("row_order", &self.sorted.is_some())
+            .field("placement", &self.placement)
             .finish()
     }
 }
 
 impl SingleReadHandle {
+    /// Name this handle after the reader node it reads from.
+    pub(crate) fn set_name(&mut self, name: &str) {
+        self.name = Arc::from(name);
+    }
+
+    /// The name of the reader node this handle reads from.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Mark this handle as reading shard `shard` of the `shards` shards of a reader whose keys
+    /// are assigned to shards by `hasher`.
+    pub(crate) fn set_shard(&mut self, shard: usize, shards: usize, hasher: noria::ShardHasher) {
+        self.placement = Some(Arc::new(ShardPlacement {
+            shard,
+            shards,
+            hasher,
+        }));
+    }
+
+    /// Mark the reader as removed, so that clients still reading through any copy of this handle
+    /// can be told to look for the view elsewhere.
+    pub(crate) fn mark_removed(&self) {
+        self.removed.store(true, atomic::Ordering::Release);
+    }
+
+    /// Whether the reader has been removed from its domain.
+    pub fn is_removed(&self) -> bool {
+        self.removed.load(atomic::Ordering::Acquire)
+    }
+
+    /// Whether `key` belongs to the shard of the reader that this handle reads.
+    ///
+    /// A client that asks the wrong shard for a key has out-of-date information about how the
+    /// reader is sharded. Readers sharded by ranges always claim to hold the key, since their
+    /// ranges can be re-split without them knowing.
+    pub fn holds(&self, key: &[DataType]) -> bool {
+        match self.placement {
+            Some(ref p) => crate::shard_by_key(&p.hasher, key, p.shards) == p.shard,
+            None => true,
+        }
+    }
+
+    /// Returns true if reads reflect all the batches of writes identified by `token`, which holds
+    /// the label of the last batch that must be reflected for each base table shard.
+    pub fn has_observed(&self, token: &[(NodeIndex, usize, u64)]) -> bool {
+        let frontier = self.frontier.read().unwrap();
+        token.iter().all(|&(base, shard, seq)| {
+            frontier
+                .get(&(base, shard))
+                .map(|&(last, _)| last >= seq)
+                .unwrap_or(false)
+        })
+    }
+
+    /// Returns true if reads reflect every batch of writes that the base shards the reader has
+    /// heard from applied up to `since`, in nanoseconds since the Unix epoch.
+    pub fn is_fresh(&self, since: u64) -> bool {
+        let frontier = self.frontier.read().unwrap();
+        frontier.values().all(|&(_, committed)| committed >= since)
+    }
+
     /// Trigger a replay of a missing key from a partially materialized view.
     pub fn trigger<'a, I>(&self, keys: I) -> bool
     where
@@ -323,12 +1183,62 @@ impl SingleReadHandle {
             "tried to trigger a replay for a fully materialized view"
         );
 
-        let mut it = keys;
+        let access = &self.access;
+        let mut replays = self.replays.lock().unwrap();
+        let now = time::Instant::now();
+        let mut it = keys.inspect(|key| {
+            access.miss(key);
+            replays
+                .entry(key.to_vec())
+                .or_insert(Replay {
+                    started: now,
+                    requested: now,
+                })
+                .requested = now;
+        });
 
         // trigger a replay to populate
         (*self.trigger.as_ref().unwrap())(&mut it)
     }
 
+    /// Trigger replays of those of the given missing keys that do not already have one
+    /// outstanding, or whose replay has not been requested for `REPLAY_TIMEOUT` and so may have
+    /// been lost.
+    ///
+    /// This way, many reads that miss on the same key at once wait for a single replay.
+    pub fn trigger_new<'a, I>(&self, keys: I) -> bool
+    where
+        I: Iterator<Item = &'a [DataType]>,
+    {
+        let new: Vec<_> = {
+            let replays = self.replays.lock().unwrap();
+            keys.filter(|key| {
+                replays
+                    .get(*key)
+                    .map(|r| r.requested.elapsed() >= REPLAY_TIMEOUT)
+                    .unwrap_or(true)
+            })
+            .collect()
+        };
+        if new.is_empty() {
+            return true;
+        }
+        self.trigger(new.into_iter())
+    }
+
+    /// How long the oldest replay outstanding for any of the given keys has been running, if any
+    /// of them has one.
+    pub fn replay_outstanding<'a, I>(&self, keys: I) -> Option<time::Duration>
+    where
+        I: Iterator<Item = &'a [DataType]>,
+    {
+        let replays = self.replays.lock().unwrap();
+        keys.filter_map(|key| replays.get(key))
+            .map(|r| r.started)
+            .min()
+            .map(time::Instant::elapsed)
+    }
+
     /// Find all entries that matched the given conditions.
     ///
     /// Returned records are passed to `then` before being returned.
@@ -339,19 +1249,355 @@ impl SingleReadHandle {
     /// Holes in partially materialized state are returned as `Ok((None, _))`.
     pub fn try_find_and<F, T>(&self, key: &[DataType], mut then: F) -> Result<(Option<T>, i64), ()>
     where
-        F: FnMut(&evmap::Values<Vec<DataType>, RandomState>) -> T,
+        F: FnMut(KeyRows<'_>) -> T,
     {
         self.handle
-            .meta_get_and(key, &mut then)
+            .meta_get_and(key, |rs| self.rows_and(key, rs, &mut then))
             .ok_or(())
             .map(|(mut records, meta)| {
                 if records.is_none() && self.trigger.is_none() {
-                    records = Some(then(&evmap::Values::default()));
+                    records = Some(then(KeyRows::Sorted(&[])));
+                } else if records.is_some() {
+                    self.access.hit(key);
                 }
                 (records, meta)
             })
     }
 
+    /// Check that this reader can serve a lookup that fixes the first `fixed` key columns, and
+    /// either looks up a range of the next one, which must then be the last, or leaves all the
+    /// rest open. Returns why not if it can't.
+    pub fn check_ordered_lookup(&self, fixed: usize, range: bool) -> Result<(), String> {
+        if self.index.is_none() {
+            return Err(format!(
+                "reader {} does not keep its keys in order",
+                self.name
+            ));
+        }
+        if range && fixed + 1 != self.key.len() {
+            return Err(format!(
+                "range lookups on reader {} must fix {} key columns, not {}",
+                self.name,
+                self.key.len().saturating_sub(1),
+                fixed
+            ));
+        }
+        if !range && fixed >= self.key.len() {
+            return Err(format!(
+                "prefix lookups on reader {} must fix fewer than {} key columns, not {}",
+                self.name,
+                self.key.len(),
+                fixed
+            ));
+        }
+        Ok(())
+    }
+
+    /// Find all entries whose key starts with `prefix`, and whose last key column falls within
+    /// `range`, in key order.
+    ///
+    /// Returned records are passed to `then` once per matching key before being returned.
+    ///
+    /// Only supported on ordered readers, which are never partial, so a range is never served
+    /// half-evicted. Returns `Err(())` if the reader is not yet ready.
+    ///
+    /// Panics unless [`SingleReadHandle::check_ordered_lookup`] allows the lookup.
+    pub fn try_find_range_and<F, T>(
+        &self,
+        prefix: &[DataType],
+        range: &KeyRange,
+        then: F,
+    ) -> Result<Vec<T>, ()>
+    where
+        F: FnMut(KeyRows<'_>) -> T,
+    {
+        assert_eq!(
+            prefix.len() + 1,
+            self.key.len(),
+            "range lookups must fix all but the last key column"
+        );
+        let index = self
+            .index
+            .as_ref()
+            .expect("tried to do a range lookup on an unordered reader");
+
+        if !self.handle.is_ready() {
+            return Err(());
+        }
+
+        let with_prefix = |v: &DataType| {
+            let mut k = Vec::with_capacity(prefix.len() + 1);
+            k.extend_from_slice(prefix);
+            k.push(v.clone());
+            k
+        };
+        let (lo, hi) = range.bounds();
+        let lo = match lo {
+            Bound::Included(v) => Bound::Included(with_prefix(v)),
+            Bound::Excluded(v) => Bound::Excluded(with_prefix(v)),
+            // every key with this prefix sorts after the prefix itself
+            Bound::Unbounded => Bound::Included(prefix.to_vec()),
+        };
+        let below_hi = |v: &DataType| match hi {
+            Bound::Included(hi) => v <= hi,
+            Bound::Excluded(hi) => v < hi,
+            Bound::Unbounded => true,
+        };
+
+        let index = index.read().unwrap();
+        let keys = index
+            .range((lo, Bound::Unbounded))
+            .map(|(k, _)| k)
+            .take_while(|k| k.starts_with(prefix) && below_hi(&k[prefix.len()]));
+        self.find_keys_and(keys, then)
+    }
+
+    /// Find all entries whose key starts with `prefix`, in key order.
+    ///
+    /// Returned records are passed to `then` once per matching key before being returned.
+    ///
+    /// Like range lookups, this is only supported on ordered readers, which are never partial, so
+    /// a prefix is never served half-evicted. Returns `Err(())` if the reader is not yet ready.
+    ///
+    /// Panics unless [`SingleReadHandle::check_ordered_lookup`] allows the lookup.
+    pub fn try_find_prefix_and<F, T>(&self, prefix: &[DataType], then: F) -> Result<Vec<T>, ()>
+    where
+        F: FnMut(KeyRows<'_>) -> T,
+    {
+        assert!(
+            prefix.len() < self.key.len(),
+            "prefix lookups must leave out at least one key column"
+        );
+        let index = self
+            .index
+            .as_ref()
+            .expect("tried to do a prefix lookup on an unordered reader");
+
+        if !self.handle.is_ready() {
+            return Err(());
+        }
+
+        // every key with this prefix sorts after the prefix itself
+        let index = index.read().unwrap();
+        let keys = index
+            .range(prefix.to_vec()..)
+            .map(|(k, _)| k)
+            .take_while(|k| k.starts_with(prefix));
+        self.find_keys_and(keys, then)
+    }
+
+    fn find_keys_and<'a, I, F, T>(&self, keys: I, mut then: F) -> Result<Vec<T>, ()>
+    where
+        I: Iterator<Item = &'a Vec<DataType>>,
+        F: FnMut(KeyRows<'_>) -> T,
+    {
+        let mut results = Vec::new();
+        for key in keys {
+            match self
+                .handle
+                .meta_get_and(&key[..], |rs| self.rows_and(key, rs, &mut then))
+            {
+                None => return Err(()),
+                Some((Some(rs), _)) => results.push(rs),
+                // the key has been indexed but its rows have not been swapped in yet
+                Some((None, _)) => {}
+            }
+        }
+        Ok(results)
+    }
+
+    /// Pass the rows `rs` for `key` to `then`, in the reader's row order if it has one.
+    fn rows_and<F, T>(
+        &self,
+        key: &[DataType],
+        rs: &evmap::Values<Vec<DataType>, RandomState>,
+        then: F,
+    ) -> T
+    where
+        F: FnOnce(KeyRows<'_>) -> T,
+    {
+        match self.sorted {
+            Some(ref sorted) => {
+                let sorted = sorted.read().unwrap();
+                then(KeyRows::Sorted(
+                    sorted.get(key).map_or(&[][..], |rs| &rs[..]),
+                ))
+            }
+            None => then(KeyRows::Unordered(rs)),
+        }
+    }
+
+    /// A receiver that is notified whenever the reader's change log grows, or starts recording
+    /// every change.
+    pub fn change_swaps(&self) -> watch::Receiver<u64> {
+        self.changes.swapped.clone()
+    }
+
+    /// Read up to `limit` of the changes made to this reader after `from`, keeping only those to
+    /// rows with the given key, if any.
+    ///
+    /// With no `from`, this subscribes to the reader's changes, and returns no changes but the
+    /// cursor to read the changes made from now on with. A `from` into the log of another reader,
+    /// such as one this reader replaced, resumes from the changes after the batches of writes
+    /// the cursor's labels say the subscriber has seen the changes of.
+    ///
+    /// Changes are only logged once the first subscriber has called this, and the log is only
+    /// complete once the writer has noticed. Until then, this returns `Ok(None)`, and should be
+    /// called again once [`SingleReadHandle::change_swaps`] ticks. Returns `Err(())` if the reader
+    /// is not yet ready.
+    pub fn changes_since(
+        &self,
+        from: Option<&ChangeCursor>,
+        key: Option<&[DataType]>,
+        limit: usize,
+    ) -> Result<Option<ChangeSet>, ()> {
+        if !self.handle.is_ready() {
+            return Err(());
+        }
+        // subscribe before checking whether the writer has left anything out, see log_changes
+        self.changes
+            .subscribed
+            .store(true, atomic::Ordering::SeqCst);
+
+        let log = self.changes.batches.read().unwrap();
+        let (start, base, seen) = if log.recording {
+            (
+                log.start,
+                Cow::Borrowed(&log.base),
+                Cow::Borrowed(&log.seen),
+            )
+        } else if self.changes.dirty.load(atomic::Ordering::SeqCst) {
+            return Ok(None);
+        } else {
+            // nothing was left out since the last swap, and the writer logs every change from
+            // here on
+            let frontier = self.frontier.read().unwrap();
+            let seen: HashMap<_, _> = frontier.iter().map(|(&k, &(seq, _))| (k, seq)).collect();
+            (log.next, Cow::Owned(seen.clone()), Cow::Owned(seen))
+        };
+
+        let first = log.next - log.batches.len() as u64;
+        let mut reflected: HashMap<(NodeIndex, usize), u64> = from
+            .map(|c| {
+                c.labels
+                    .iter()
+                    .map(|&(base, shard, seq)| ((NodeIndex::new(base), shard), seq))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let (mut batch, mut offset, skip, dropped) = match from {
+            None => {
+                reflected = (*seen).clone();
+                (log.next, 0, None, false)
+            }
+            Some(c) if c.log == self.changes.id => {
+                if c.batch < first {
+                    (first, 0, None, true)
+                } else {
+                    (c.batch, c.offset, None, false)
+                }
+            }
+            Some(_) => {
+                // all we know is which batches of writes the subscriber has seen the changes of
+                let skip = reflected.clone();
+                let missed = start < first
+                    || base
+                        .iter()
+                        .any(|(source, &seq)| skip.get(source).map_or(true, |&s| s < seq));
+                for (&source, &seq) in base.iter() {
+                    let last = reflected.entry(source).or_insert(0);
+                    *last = std::cmp::max(*last, seq);
+                }
+                (std::cmp::max(start, first), 0, Some(skip), missed)
+            }
+        };
+
+        let mut changes = Vec::new();
+        let mut labels = Vec::new();
+        let mut done = true;
+        'batches: for logged in log.batches.iter().skip((batch - first) as usize) {
+            let mut runs = logged.labels.iter().peekable();
+            let mut label = None;
+            for (i, r) in logged.records.iter().enumerate().skip(offset) {
+                let mut run_start = i == offset;
+                while let Some(&&(start, next)) = runs.peek() {
+                    if start > i {
+                        break;
+                    }
+                    label = next;
+                    run_start = true;
+                    runs.next();
+                }
+
+                let source = label.map(|(base, l)| ((base, l.shard), l.seq));
+                if run_start && changes.len() >= limit {
+                    // only stop between the changes of different batches of writes, which
+                    // resuming by label tells apart
+                    let resumed = source.map_or(false, |(source, seq)| {
+                        reflected.get(&source).map_or(false, |&s| seq <= s)
+                    });
+                    if !resumed {
+                        offset = i;
+                        done = false;
+                        break 'batches;
+                    }
+                }
+                if let Some((source, seq)) = source {
+                    if let Some(ref skip) = skip {
+                        if skip.get(&source).map_or(false, |&s| seq <= s) {
+                            continue;
+                        }
+                    }
+                    let last = reflected.entry(source).or_insert(0);
+                    *last = std::cmp::max(*last, seq);
+                }
+
+                if let Some(key) = key {
+                    if !self.key.iter().zip(key).all(|(&c, k)| r[c] == *k) {
+                        continue;
+                    }
+                }
+                let label = label.map(|(_, l)| l);
+                if labels.last().and_then(|&(_, l)| l) != label {
+                    labels.push((changes.len(), label));
+                }
+                changes.push(match *r {
+                    Record::Positive(ref r) => Change::Positive(r.clone()),
+                    Record::Negative(ref r) => Change::Negative(r.clone()),
+                });
+            }
+            for &(source, seq) in &logged.applied {
+                let last = reflected.entry(source).or_insert(0);
+                *last = std::cmp::max(*last, seq);
+            }
+            batch += 1;
+            offset = 0;
+        }
+        if done {
+            for (&source, &seq) in seen.iter() {
+                let last = reflected.entry(source).or_insert(0);
+                *last = std::cmp::max(*last, seq);
+            }
+        }
+
+        let mut reflected: Vec<_> = reflected
+            .into_iter()
+            .map(|((base, shard), seq)| (base.index(), shard, seq))
+            .collect();
+        reflected.sort();
+        Ok(Some(ChangeSet {
+            changes,
+            next: ChangeCursor {
+                log: self.changes.id,
+                batch,
+                offset,
+                labels: reflected,
+            },
+            dropped,
+            labels,
+        }))
+    }
+
     pub fn len(&self) -> usize {
         self.handle.len()
     }
@@ -359,6 +1605,11 @@ impl SingleReadHandle {
     pub fn is_empty(&self) -> bool {
         self.handle.len() == 0
     }
+
+    /// All the rows that readers can see, in no particular order.
+    pub fn cloned_records(&self) -> Vec<Vec<DataType>> {
+        self.handle.cloned_records()
+    }
 }
 
 #[cfg(test)]
@@ -564,4 +1815,695 @@ mod tests {
             .0
             .unwrap());
     }
+
+    #[test]
+    fn range_lookups() {
+        let (r, mut w) = new_ordered(2, &[0]);
+        w.add((1..=5).map(|i: i32| Record::Positive(vec![i.into(), "a".into()])));
+        w.add(vec![Record::Positive(vec![3.into(), "b".into()])]);
+        w.swap();
+
+        let find = |range: KeyRange| -> Vec<i32> {
+            let mut rows: Vec<i32> = r
+                .try_find_range_and(&[], &range, |rs| {
+                    rs.iter().map(|r| i32::from(&r[0])).collect::<Vec<_>>()
+                })
+                .unwrap()
+                .into_iter()
+                .flatten()
+                .collect();
+            rows.dedup();
+            rows
+        };
+
+        assert_eq!(find(KeyRange::Greater(3.into())), vec![4, 5]);
+        assert_eq!(find(KeyRange::GreaterOrEqual(3.into())), vec![3, 4, 5]);
+        assert_eq!(find(KeyRange::Less(3.into())), vec![1, 2]);
+        assert_eq!(find(KeyRange::LessOrEqual(3.into())), vec![1, 2, 3]);
+        assert_eq!(find(KeyRange::Between(2.into(), 4.into())), vec![2, 3, 4]);
+        assert_eq!(
+            find(KeyRange::Between(4.into(), 2.into())),
+            Vec::<i32>::new()
+        );
+
+        // both rows for key 3 are returned
+        assert_eq!(
+            r.try_find_range_and(&[], &KeyRange::Between(3.into(), 3.into()), |rs| rs.len()),
+            Ok(vec![2])
+        );
+    }
+
+    #[test]
+    fn range_lookups_ignore_stray_negatives() {
+        let (r, mut w) = new_ordered(2, &[0]);
+        w.add(vec![Record::Positive(vec![1.into(), "a".into()])]);
+        w.swap();
+
+        // negatives for rows the reader does not have leave no keys behind
+        w.add(vec![
+            Record::Negative(vec![1.into(), "a".into()]),
+            Record::Negative(vec![1.into(), "b".into()]),
+            Record::Negative(vec![2.into(), "a".into()]),
+        ]);
+        w.swap();
+        assert!(r.index.as_ref().unwrap().read().unwrap().is_empty());
+
+        w.add(vec![Record::Positive(vec![1.into(), "c".into()])]);
+        w.swap();
+        assert_eq!(
+            r.try_find_range_and(&[], &KeyRange::LessOrEqual(2.into()), |rs| rs.len()),
+            Ok(vec![1])
+        );
+    }
+
+    #[test]
+    fn range_lookups_follow_updates() {
+        let (r, mut w) = new_ordered(2, &[0]);
+
+        // not yet ready
+        assert_eq!(
+            r.try_find_range_and(&[], &KeyRange::Less(10.into()), |rs| rs.len()),
+            Err(())
+        );
+
+        w.add(vec![
+            Record::Positive(vec![1.into(), "a".into()]),
+            Record::Positive(vec![5.into(), "b".into()]),
+        ]);
+        w.swap();
+        let range = KeyRange::Less(3.into());
+        assert_eq!(
+            r.try_find_range_and(&[], &range, |rs| rs.len()),
+            Ok(vec![1])
+        );
+
+        // move "b" into the range, and "a" out of it
+        w.add(vec![
+            Record::Negative(vec![5.into(), "b".into()]),
+            Record::Positive(vec![2.into(), "b".into()]),
+            Record::Negative(vec![1.into(), "a".into()]),
+            Record::Positive(vec![4.into(), "a".into()]),
+        ]);
+
+        // not visible until the swap
+        assert_eq!(
+            r.try_find_range_and(&[], &range, |rs| rs.iter().cloned().collect::<Vec<_>>()),
+            Ok(vec![vec![vec![1.into(), "a".into()]]])
+        );
+
+        w.swap();
+        assert_eq!(
+            r.try_find_range_and(&[], &range, |rs| rs.iter().cloned().collect::<Vec<_>>()),
+            Ok(vec![vec![vec![2.into(), "b".into()]]])
+        );
+    }
+
+    #[test]
+    fn prefixed_range_lookups() {
+        let (r, mut w) = new_ordered(3, &[0, 1]);
+        w.add(vec![
+            Record::Positive(vec!["a".into(), 1.into(), "x".into()]),
+            Record::Positive(vec!["a".into(), 2.into(), "y".into()]),
+            Record::Positive(vec!["a".into(), 3.into(), "z".into()]),
+            Record::Positive(vec!["b".into(), 2.into(), "w".into()]),
+            Record::Positive(vec!["ab".into(), 2.into(), "v".into()]),
+        ]);
+        w.swap();
+
+        let find = |prefix: &str, range: KeyRange| -> Vec<DataType> {
+            r.try_find_range_and(&[prefix.into()], &range, |rs| {
+                rs.iter().next().unwrap()[2].clone()
+            })
+            .unwrap()
+        };
+
+        // in order, and never crossing into another prefix
+        assert_eq!(
+            find("a", KeyRange::GreaterOrEqual(1.into())),
+            vec!["x".into(), "y".into(), "z".into()]
+        );
+        assert_eq!(
+            find("a", KeyRange::LessOrEqual(2.into())),
+            vec!["x".into(), "y".into()]
+        );
+        assert_eq!(
+            find("a", KeyRange::Between(2.into(), 3.into())),
+            vec!["y".into(), "z".into()]
+        );
+        assert_eq!(find("b", KeyRange::Less(2.into())), Vec::<DataType>::new());
+        assert_eq!(find("b", KeyRange::Greater(1.into())), vec!["w".into()]);
+        assert_eq!(
+            find("c", KeyRange::Greater(1.into())),
+            Vec::<DataType>::new()
+        );
+    }
+
+    #[test]
+    fn prefix_lookups() {
+        let (r, mut w) = new_ordered(3, &[0, 1]);
+        w.add(vec![
+            Record::Positive(vec!["a".into(), 2.into(), "y".into()]),
+            Record::Positive(vec!["a".into(), 1.into(), "x".into()]),
+            Record::Positive(vec!["b".into(), 1.into(), "w".into()]),
+            Record::Positive(vec!["ab".into(), 1.into(), "v".into()]),
+        ]);
+        w.swap();
+
+        let find = |prefix: &[DataType]| -> Vec<DataType> {
+            r.try_find_prefix_and(prefix, |rs| rs.iter().next().unwrap()[2].clone())
+                .unwrap()
+        };
+
+        // in key order, and never crossing into another prefix
+        assert_eq!(find(&["a".into()]), vec!["x".into(), "y".into()]);
+        assert_eq!(find(&["b".into()]), vec!["w".into()]);
+        assert_eq!(find(&["c".into()]), Vec::<DataType>::new());
+        assert_eq!(
+            find(&[]),
+            vec!["x".into(), "y".into(), "v".into(), "w".into()]
+        );
+    }
+
+    #[test]
+    fn ordered_lookups_are_checked() {
+        let (r, _w) = new_ordered(3, &[0, 1]);
+        assert!(r.check_ordered_lookup(1, true).is_ok());
+        assert!(r.check_ordered_lookup(0, true).is_err());
+        assert!(r.check_ordered_lookup(2, true).is_err());
+        assert!(r.check_ordered_lookup(0, false).is_ok());
+        assert!(r.check_ordered_lookup(1, false).is_ok());
+        assert!(r.check_ordered_lookup(2, false).is_err());
+
+        // readers that don't keep their keys in order serve neither
+        let (r, _w) = new(3, &[0, 1]);
+        assert!(r.check_ordered_lookup(1, true).is_err());
+        assert!(r.check_ordered_lookup(1, false).is_err());
+    }
+
+    #[test]
+    fn row_order_sorts_rows() {
+        let (mut r, mut w) = new(3, &[0]);
+        set_row_order(&mut r, &mut w, &[(1, OrderType::OrderDescending)]);
+        w.add(vec![
+            Record::Positive(vec![1.into(), 10.into(), "b".into()]),
+            Record::Positive(vec![1.into(), 30.into(), "a".into()]),
+            Record::Positive(vec![1.into(), 20.into(), "c".into()]),
+            Record::Positive(vec![1.into(), 40.into(), "d".into()]),
+        ]);
+        w.swap();
+        w.add(vec![
+            Record::Positive(vec![1.into(), 20.into(), "a".into()]),
+            Record::Negative(vec![1.into(), 40.into(), "d".into()]),
+        ]);
+        w.swap();
+
+        let rows = r
+            .try_find_and(&[1.into()], |rs| rs.iter().cloned().collect::<Vec<_>>())
+            .unwrap()
+            .0
+            .unwrap();
+
+        // ties on the order column are broken by the rest of the row
+        let expected: Vec<Vec<DataType>> = vec![
+            vec![1.into(), 30.into(), "a".into()],
+            vec![1.into(), 20.into(), "a".into()],
+            vec![1.into(), 20.into(), "c".into()],
+            vec![1.into(), 10.into(), "b".into()],
+        ];
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn change_feed() {
+        let (r, mut w) = new(2, &[0]);
+        w.swap();
+
+        // nothing is logged before anyone subscribes
+        w.log_changes(&[Record::Positive(vec![1.into(), "a".into()])], None);
+        w.swap();
+        let cs = r.changes_since(None, None, usize::MAX).unwrap().unwrap();
+        assert!(cs.changes.is_empty());
+        let start = cs.next;
+
+        w.log_changes(&[Record::Positive(vec![1.into(), "b".into()])], None);
+        w.log_changes(&[Record::Positive(vec![2.into(), "c".into()])], None);
+        w.swap();
+        w.log_changes(&[Record::Negative(vec![1.into(), "b".into()])], None);
+        w.swap();
+
+        let cs = r
+            .changes_since(Some(&start), None, usize::MAX)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            cs.changes,
+            vec![
+                Change::Positive(vec![1.into(), "b".into()]),
+                Change::Positive(vec![2.into(), "c".into()]),
+                Change::Negative(vec![1.into(), "b".into()]),
+            ]
+        );
+        assert!(!cs.dropped);
+
+        // reads stop once they have enough changes
+        let cs = r.changes_since(Some(&start), None, 1).unwrap().unwrap();
+        assert_eq!(cs.changes.len(), 2);
+
+        // only changes to the given key, and only those after the given cursor
+        let cs = r
+            .changes_since(Some(&cs.next), Some(&[1.into()]), usize::MAX)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            cs.changes,
+            vec![Change::Negative(vec![1.into(), "b".into()])]
+        );
+
+        // old batches are eventually trimmed
+        for _ in 0..CHANGE_LOG_CAPACITY {
+            w.log_changes(&[Record::Positive(vec![3.into(), "d".into()])], None);
+            w.swap();
+        }
+        let cs = r
+            .changes_since(Some(&start), None, usize::MAX)
+            .unwrap()
+            .unwrap();
+        assert!(cs.dropped);
+        assert_eq!(cs.changes.len(), CHANGE_LOG_CAPACITY);
+    }
+
+    #[test]
+    fn change_feed_waits_for_complete_log() {
+        let (r, mut w) = new(2, &[0]);
+        w.swap();
+
+        // the writer has already left this change out, so subscribing has to wait for the swap
+        w.log_changes(&[Record::Positive(vec![1.into(), "a".into()])], None);
+        assert!(r.changes_since(None, None, usize::MAX).unwrap().is_none());
+        w.log_changes(&[Record::Positive(vec![2.into(), "b".into()])], None);
+        w.swap();
+        let start = r
+            .changes_since(None, None, usize::MAX)
+            .unwrap()
+            .unwrap()
+            .next;
+
+        w.log_changes(&[Record::Positive(vec![3.into(), "c".into()])], None);
+        w.swap();
+        let cs = r
+            .changes_since(Some(&start), None, usize::MAX)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            cs.changes,
+            vec![Change::Positive(vec![3.into(), "c".into()])]
+        );
+
+        // with nothing left out, subscribing needs no swap
+        let (r, mut w) = new(2, &[0]);
+        w.swap();
+        let start = r
+            .changes_since(None, None, usize::MAX)
+            .unwrap()
+            .unwrap()
+            .next;
+        w.log_changes(&[Record::Positive(vec![1.into(), "a".into()])], None);
+        w.swap();
+        let cs = r
+            .changes_since(Some(&start), None, usize::MAX)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cs.changes.len(), 1);
+    }
+
+    #[test]
+    fn change_feed_resumes_by_label() {
+        let label = |seq| Label {
+            base: NodeIndex::new(1),
+            shard: 0,
+            seq,
+            tracked: false,
+            trace: None,
+            origin: None,
+            txn: None,
+            committed: seq,
+            context: None,
+            sent: 0,
+            path: 0,
+        };
+        let row = |k: i32| Record::Positive(vec![k.into(), "a".into()]);
+        let write = |w: &mut WriteHandle, seq: u64| {
+            w.log_changes(&[row(seq as i32)], Some(&label(seq)));
+            w.observe(label(seq));
+            w.swap();
+        };
+
+        let (r, mut w) = new(2, &[0]);
+        w.swap();
+        let start = r
+            .changes_since(None, None, usize::MAX)
+            .unwrap()
+            .unwrap()
+            .next;
+        write(&mut w, 1);
+        write(&mut w, 2);
+        let cursor = r
+            .changes_since(Some(&start), None, usize::MAX)
+            .unwrap()
+            .unwrap()
+            .next;
+        assert_eq!(cursor.labels, vec![(1, 0, 2)]);
+
+        // a new reader that applies the same writes picks up after those the cursor reflects
+        let (r, mut w) = new(2, &[0]);
+        w.swap();
+        r.changes_since(None, None, usize::MAX).unwrap().unwrap();
+        for seq in 1..=3 {
+            write(&mut w, seq);
+        }
+        let cs = r
+            .changes_since(Some(&cursor), None, usize::MAX)
+            .unwrap()
+            .unwrap();
+        assert!(!cs.dropped);
+        assert_eq!(
+            cs.changes,
+            vec![Change::Positive(vec![3.into(), "a".into()])]
+        );
+
+        // but one that applied writes before it started logging can't tell what was missed
+        let (r, mut w) = new(2, &[0]);
+        w.swap();
+        for seq in 1..=3 {
+            write(&mut w, seq);
+        }
+        let cs = r
+            .changes_since(Some(&cursor), None, usize::MAX)
+            .unwrap()
+            .unwrap();
+        assert!(cs.dropped);
+    }
+
+    #[test]
+    fn change_feed_labels() {
+        let (r, mut w) = new(2, &[0]);
+        w.swap();
+        let start = r
+            .changes_since(None, None, usize::MAX)
+            .unwrap()
+            .unwrap()
+            .next;
+
+        let label = |seq| Label {
+            base: NodeIndex::new(1),
+            shard: 0,
+            seq,
+            tracked: false,
+            trace: None,
+            origin: None,
+            txn: Some(seq + 100),
+            committed: seq * 10,
+            context: None,
+            sent: 0,
+            path: 0,
+        };
+        let row = |k: i32| Record::Positive(vec![k.into(), "a".into()]);
+        w.log_changes(&[row(1), row(2)], Some(&label(1)));
+        w.log_changes(&[row(1)], Some(&label(2)));
+        w.swap();
+        w.log_changes(&[row(2)], None);
+        w.log_changes(&[row(1)], Some(&label(2)));
+        w.swap();
+
+        let labeled = |seq| ChangeLabel {
+            shard: 0,
+            seq,
+            committed: seq * 10,
+            timestamp: Some(seq + 100),
+        };
+        let cs = r
+            .changes_since(Some(&start), None, usize::MAX)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cs.changes.len(), 5);
+        assert_eq!(
+            cs.labels,
+            vec![
+                (0, Some(labeled(1))),
+                (2, Some(labeled(2))),
+                (3, None),
+                (4, Some(labeled(2))),
+            ]
+        );
+
+        // positions are of the changes that are returned
+        let cs = r
+            .changes_since(Some(&start), Some(&[1.into()]), usize::MAX)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cs.changes.len(), 3);
+        assert_eq!(
+            cs.labels,
+            vec![(0, Some(labeled(1))), (1, Some(labeled(2)))]
+        );
+    }
+
+    #[test]
+    fn frontier() {
+        let (r, mut w) = new(2, &[0]);
+        w.swap();
+
+        let base = NodeIndex::new(1);
+        let label = |shard, seq| Label {
+            base,
+            shard,
+            seq,
+            tracked: true,
+            trace: None,
+            origin: None,
+            txn: None,
+            committed: seq * 10,
+            context: None,
+            sent: 0,
+            path: 0,
+        };
+        assert!(r.has_observed(&[]));
+        assert!(!r.has_observed(&[(base, 0, 1)]));
+
+        w.observe(label(0, 1));
+        w.observe(label(0, 2));
+        // not until the writes are visible
+        assert!(!r.has_observed(&[(base, 0, 1)]));
+        w.swap();
+        assert!(r.has_observed(&[(base, 0, 1)]));
+        assert!(r.has_observed(&[(base, 0, 2)]));
+        assert!(!r.has_observed(&[(base, 0, 3)]));
+
+        // every shard in the token must have been observed
+        assert!(!r.has_observed(&[(base, 0, 2), (base, 1, 1)]));
+        w.observe(label(1, 1));
+        w.swap();
+        assert!(r.has_observed(&[(base, 0, 2), (base, 1, 1)]));
+
+        // the reader is only as fresh as the base shard it has heard from least recently
+        assert_eq!(w.applied(), Some(10));
+        assert!(r.is_fresh(10));
+        assert!(!r.is_fresh(11));
+        w.observe(label(1, 3));
+        w.swap();
+        assert_eq!(w.applied(), Some(20));
+        assert!(r.is_fresh(20));
+    }
+
+    #[test]
+    fn frontier_diamond() {
+        let (r, mut w) = new(2, &[0]);
+        w.swap();
+
+        // the base's batches reach the reader along two paths that meet again before it
+        let base = NodeIndex::new(1);
+        w.set_paths(base, 2);
+        let label = |path, seq| Label {
+            base,
+            shard: 0,
+            seq,
+            tracked: true,
+            trace: None,
+            origin: None,
+            txn: None,
+            committed: seq * 10,
+            context: None,
+            sent: 0,
+            path,
+        };
+
+        w.observe(label(1, 1));
+        w.observe(label(1, 2));
+        w.swap();
+        assert!(!r.has_observed(&[(base, 0, 1)]));
+        assert_eq!(w.applied(), None);
+
+        // only as far as the path that lags behind
+        w.observe(label(2, 1));
+        w.swap();
+        assert!(r.has_observed(&[(base, 0, 1)]));
+        assert!(!r.has_observed(&[(base, 0, 2)]));
+        assert_eq!(w.applied(), Some(10));
+
+        w.observe(label(2, 2));
+        w.swap();
+        assert!(r.has_observed(&[(base, 0, 2)]));
+    }
+
+    #[test]
+    fn transactions() {
+        let (r, mut w) = new(2, &[0]);
+        w.swap();
+
+        let a = vec![1.into(), "a".into()];
+        let b = vec![2.into(), "b".into()];
+        let label = |base, txn| Label {
+            base: NodeIndex::new(base),
+            shard: 0,
+            seq: 1,
+            tracked: true,
+            trace: None,
+            origin: None,
+            txn,
+            committed: 0,
+            context: None,
+            sent: 0,
+            path: 0,
+        };
+        let apply = |w: &mut WriteHandle, batches: Vec<(Records, Label)>| {
+            for (rs, label) in batches {
+                w.add(rs);
+                w.observe(label);
+            }
+            w.swap();
+        };
+        w.expect_transaction(7, 2);
+
+        // half of the transaction is held back
+        let held = w.hold(vec![Record::Positive(a.clone())].into(), label(1, Some(7)));
+        assert!(held.is_none());
+        assert!(w.release().is_empty());
+        w.swap();
+        assert_eq!(r.try_find_and(&a[0..1], |rs| rs.len()).unwrap().0, Some(0));
+        assert!(!r.has_observed(&[(NodeIndex::new(1), 0, 1)]));
+
+        // along with later batches from the same base shard, but not those from others
+        let c = vec![3.into(), "c".into()];
+        let mut later = label(1, None);
+        later.seq = 2;
+        assert!(w
+            .hold(vec![Record::Positive(c.clone())].into(), later)
+            .is_none());
+        let d = vec![4.into(), "d".into()];
+        let other = w
+            .hold(vec![Record::Positive(d.clone())].into(), label(3, None))
+            .unwrap();
+        apply(&mut w, vec![other]);
+        assert_eq!(r.try_find_and(&d[0..1], |rs| rs.len()).unwrap().0, Some(1));
+        assert_eq!(r.try_find_and(&a[0..1], |rs| rs.len()).unwrap().0, Some(0));
+        assert_eq!(r.try_find_and(&c[0..1], |rs| rs.len()).unwrap().0, Some(0));
+
+        // until the rest of it arrives
+        assert!(w
+            .hold(vec![Record::Positive(b.clone())].into(), label(2, Some(7)))
+            .is_none());
+        let released = w.release();
+        assert_eq!(released.len(), 3);
+        apply(&mut w, released);
+        assert_eq!(r.try_find_and(&a[0..1], |rs| rs.len()).unwrap().0, Some(1));
+        assert_eq!(r.try_find_and(&b[0..1], |rs| rs.len()).unwrap().0, Some(1));
+        assert_eq!(r.try_find_and(&c[0..1], |rs| rs.len()).unwrap().0, Some(1));
+        assert!(r.has_observed(&[(NodeIndex::new(1), 0, 2)]));
+
+        // a transaction that is called off releases what has arrived of it
+        w.expect_transaction(8, 2);
+        assert!(w
+            .hold(vec![Record::Negative(a.clone())].into(), label(1, Some(8)))
+            .is_none());
+        assert!(w.release().is_empty());
+        w.expect_transaction(8, 0);
+        let released = w.release();
+        assert_eq!(released.len(), 1);
+        apply(&mut w, released);
+        assert_eq!(r.try_find_and(&a[0..1], |rs| rs.len()).unwrap().0, Some(0));
+    }
+
+    #[test]
+    fn outstanding_replays() {
+        use std::sync::atomic::AtomicUsize;
+
+        let triggered = Arc::new(AtomicUsize::new(0));
+        let t = Arc::clone(&triggered);
+        let (r, mut w) = new_partial(2, &[0], EvictionPolicy::Random, move |keys| {
+            t.fetch_add(keys.count(), atomic::Ordering::SeqCst);
+            true
+        });
+        w.swap();
+
+        let a: &[DataType] = &[1.into()];
+        let b: &[DataType] = &[2.into()];
+        assert_eq!(r.replay_outstanding(vec![a].into_iter()), None);
+
+        r.trigger(vec![a].into_iter());
+        assert_eq!(triggered.load(atomic::Ordering::SeqCst), 1);
+        assert!(r.replay_outstanding(vec![a, b].into_iter()).is_some());
+
+        // only keys without a replay in flight are requested again
+        r.trigger_new(vec![a, b].into_iter());
+        assert_eq!(triggered.load(atomic::Ordering::SeqCst), 2);
+        r.trigger_new(vec![a, b].into_iter());
+        assert_eq!(triggered.load(atomic::Ordering::SeqCst), 2);
+
+        // filling a key completes its replay
+        w.mut_with_key(a).mark_filled();
+        assert_eq!(r.replay_outstanding(vec![a].into_iter()), None);
+        assert!(r.replay_outstanding(vec![b].into_iter()).is_some());
+    }
+
+    #[test]
+    fn row_counts() {
+        let (_r, mut w) = new_partial(2, &[0], EvictionPolicy::Random, |_| true);
+        w.swap();
+
+        w.mut_with_key(&[1.into()][..]).mark_filled();
+        w.mut_with_key(&[2.into()][..]).mark_filled();
+        w.add(vec![
+            Record::Positive(vec![1.into(), "a".into()]),
+            Record::Positive(vec![1.into(), "b".into()]),
+            Record::Positive(vec![2.into(), "c".into()]),
+        ]);
+        assert_eq!(w.rows(), 3);
+
+        w.add(vec![Record::Negative(vec![1.into(), "b".into()])]);
+        assert_eq!(w.rows(), 2);
+        w.swap();
+
+        w.mut_with_key(&[1.into()][..]).mark_hole();
+        assert_eq!(w.rows(), 1);
+        w.swap();
+
+        let mut rng = rand::thread_rng();
+        w.evict_keys(&mut rng, 1, |_| false, |_, _| {});
+        assert_eq!(w.rows(), 0);
+        assert_eq!(w.deep_size_of(), 0);
+    }
+
+    #[test]
+    fn shard_placement() {
+        let hasher = noria::ShardHasher::default();
+        let (mut r, _w) = new(2, &[0]);
+        assert!(r.holds(&[1.into()]));
+
+        let key = vec![DataType::from(1)];
+        let shard = crate::shard_by_key(&hasher, &key, 4);
+        r.set_shard(shard, 4, hasher);
+        assert!(r.holds(&key));
+        r.set_shard((shard + 1) % 4, 4, hasher);
+        assert!(!r.holds(&key));
+    }
 }
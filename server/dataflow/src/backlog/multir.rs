@@ -18,6 +18,30 @@ impl Handle {
         }
     }
 
+    pub(super) fn cloned_records(&self) -> Vec<Vec<DataType>> {
+        fn rows<K: Eq + std::hash::Hash>(
+            h: &evmap::ReadHandle<K, Vec<DataType>, i64, RandomState>,
+        ) -> Vec<Vec<DataType>> {
+            h.read()
+                .map(|map| map.iter().flat_map(|(_, rs)| rs.iter().cloned()).collect())
+                .unwrap_or_default()
+        }
+
+        match *self {
+            Handle::Single(ref h) => rows(h),
+            Handle::Double(ref h) => rows(h),
+            Handle::Many(ref h) => rows(h),
+        }
+    }
+
+    pub(super) fn is_ready(&self) -> bool {
+        match *self {
+            Handle::Single(ref h) => h.read().is_some(),
+            Handle::Double(ref h) => h.read().is_some(),
+            Handle::Many(ref h) => h.read().is_some(),
+        }
+    }
+
     pub(super) fn meta_get_and<F, T>(&self, key: &[DataType], then: F) -> Option<(Option<T>, i64)>
     where
         F: FnOnce(&evmap::Values<Vec<DataType>, RandomState>) -> T,
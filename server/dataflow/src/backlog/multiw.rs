@@ -52,12 +52,42 @@ impl Handle {
         &mut self,
         rng: &mut impl rand::Rng,
         n: usize,
-        mut f: impl FnMut(&evmap::Values<Vec<DataType>, RandomState>),
+        mut f: impl FnMut(&[DataType], &evmap::Values<Vec<DataType>, RandomState>),
     ) {
         match *self {
-            Handle::Single(ref mut h) => h.empty_random(rng, n).for_each(|r| f(r.1)),
-            Handle::Double(ref mut h) => h.empty_random(rng, n).for_each(|r| f(r.1)),
-            Handle::Many(ref mut h) => h.empty_random(rng, n).for_each(|r| f(r.1)),
+            Handle::Single(ref mut h) => h
+                .empty_random(rng, n)
+                .for_each(|r| f(std::slice::from_ref(r.0), r.1)),
+            Handle::Double(ref mut h) => h
+                .empty_random(rng, n)
+                .for_each(|r| f(&[(r.0).0.clone(), (r.0).1.clone()], r.1)),
+            Handle::Many(ref mut h) => h.empty_random(rng, n).for_each(|r| f(&r.0[..], r.1)),
+        }
+    }
+
+    /// Collect every published row that matches the given predicate.
+    pub fn filter_rows<F>(&self, mut f: F) -> Vec<Vec<DataType>>
+    where
+        F: FnMut(&[DataType]) -> bool,
+    {
+        macro_rules! collect {
+            ($h:expr) => {
+                match $h.read() {
+                    Some(map) => map
+                        .iter()
+                        .flat_map(|(_, rs)| rs.iter())
+                        .filter(|r| f(&r[..]))
+                        .cloned()
+                        .collect(),
+                    None => Vec::new(),
+                }
+            };
+        }
+
+        match *self {
+            Handle::Single(ref h) => collect!(h),
+            Handle::Double(ref h) => collect!(h),
+            Handle::Many(ref h) => collect!(h),
         }
     }
 
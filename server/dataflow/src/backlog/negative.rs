@@ -0,0 +1,116 @@
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::time;
+
+/// How a partial reader remembers the keys it evicted while they held no rows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NegativeCaching {
+    /// The most keys remembered at once. Empty keys that are evicted while this many are
+    /// remembered are forgotten right away.
+    pub capacity: usize,
+    /// How long an empty key is remembered for after it was evicted.
+    pub ttl: time::Duration,
+}
+
+/// Keys that a partial reader evicted while they held no rows, and that have not been written to
+/// since.
+///
+/// A miss on such a key can be filled with nothing rather than with a replay, which would walk the
+/// whole replay path only to find no rows. Any write for a remembered key that reaches the reader,
+/// and any eviction of the key upstream, makes the reader forget it.
+pub(crate) struct NegativeCache {
+    config: NegativeCaching,
+    keys: HashMap<Vec<DataType>, time::Instant>,
+    hits: u64,
+    misses: u64,
+    invalidations: u64,
+}
+
+impl NegativeCache {
+    pub(crate) fn new(config: NegativeCaching) -> Self {
+        NegativeCache {
+            config,
+            keys: HashMap::new(),
+            hits: 0,
+            misses: 0,
+            invalidations: 0,
+        }
+    }
+
+    /// Remember that `key` was evicted while it held no rows.
+    pub(crate) fn insert(&mut self, key: &[DataType]) {
+        if self.keys.len() >= self.config.capacity {
+            let ttl = self.config.ttl;
+            self.keys.retain(|_, at| at.elapsed() < ttl);
+            if self.keys.len() >= self.config.capacity {
+                return;
+            }
+        }
+        self.keys.insert(Vec::from(key), time::Instant::now());
+    }
+
+    /// Check whether `key` is known to hold no rows, and forget it either way, since the caller
+    /// is about to fill it.
+    pub(crate) fn take(&mut self, key: &[DataType]) -> bool {
+        match self.keys.remove(key) {
+            Some(at) if at.elapsed() < self.config.ttl => {
+                self.hits += 1;
+                true
+            }
+            _ => {
+                self.misses += 1;
+                false
+            }
+        }
+    }
+
+    /// Forget `key`, which may no longer be empty.
+    pub(crate) fn invalidate(&mut self, key: &[DataType]) {
+        if self.keys.remove(key).is_some() {
+            self.invalidations += 1;
+        }
+    }
+
+    /// The number of misses filled without a replay, the number that needed one, and the number of
+    /// remembered keys that were forgotten because they may have been written to.
+    pub(crate) fn stats(&self) -> (u64, u64, u64) {
+        (self.hits, self.misses, self.invalidations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remembers_until_invalidated() {
+        let mut cache = NegativeCache::new(NegativeCaching {
+            capacity: 2,
+            ttl: time::Duration::from_secs(60),
+        });
+        cache.insert(&[1.into()]);
+        cache.insert(&[2.into()]);
+        // full, and nothing has expired
+        cache.insert(&[3.into()]);
+        cache.invalidate(&[2.into()]);
+
+        assert!(cache.take(&[1.into()]));
+        assert!(!cache.take(&[1.into()]));
+        assert!(!cache.take(&[2.into()]));
+        assert!(!cache.take(&[3.into()]));
+        assert_eq!(cache.stats(), (1, 3, 1));
+    }
+
+    #[test]
+    fn expires() {
+        let mut cache = NegativeCache::new(NegativeCaching {
+            capacity: 1,
+            ttl: time::Duration::from_millis(0),
+        });
+        cache.insert(&[1.into()]);
+        // the expired key makes room
+        cache.insert(&[2.into()]);
+        assert!(!cache.take(&[2.into()]));
+        assert!(!cache.take(&[1.into()]));
+    }
+}
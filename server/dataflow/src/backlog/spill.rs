@@ -0,0 +1,191 @@
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+/// The fewest bytes of dead entries a spill file holds before it is rewritten.
+const COMPACT_AFTER: u64 = 4 << 20;
+
+fn encoding_error(e: bincode::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Keys evicted from a partial reader, kept on disk so that a later miss on them can be answered
+/// without a replay.
+///
+/// The rows of each key are appended to a log file as the key is evicted, and an in-memory index
+/// maps every spilled key to its latest entry. Taking a key back, or invalidating it because it
+/// was written to, only drops it from the index. Once most of the file is dead, the live entries
+/// are copied to a new file that replaces it.
+pub(crate) struct Spill {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    live: u64,
+    index: HashMap<Vec<DataType>, (u64, u64)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Spill {
+    /// Spill to a new, empty file at `path`, replacing any file that is already there.
+    pub(crate) fn create(path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(Spill {
+            path,
+            file,
+            len: 0,
+            live: 0,
+            index: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        })
+    }
+
+    /// Spill the rows of an evicted key.
+    pub(crate) fn put(&mut self, key: &[DataType], rows: &[Vec<DataType>]) -> io::Result<()> {
+        let bytes = bincode::serialize(rows).map_err(encoding_error)?;
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(&bytes)?;
+
+        let entry = (self.len, bytes.len() as u64);
+        self.len += entry.1;
+        self.live += entry.1;
+        if let Some((_, old)) = self.index.insert(Vec::from(key), entry) {
+            self.live -= old;
+        }
+        self.maybe_compact()
+    }
+
+    /// Take the rows of `key` back out of the spill, if it is there.
+    pub(crate) fn take(&mut self, key: &[DataType]) -> io::Result<Option<Vec<Vec<DataType>>>> {
+        let (offset, len) = match self.index.remove(key) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        self.live -= len;
+        let rows = self.read(offset, len)?;
+        self.hits += 1;
+        Ok(Some(rows))
+    }
+
+    /// Forget the spilled copy of `key`, which no longer matches what upstream holds.
+    pub(crate) fn invalidate(&mut self, key: &[DataType]) {
+        if let Some((_, len)) = self.index.remove(key) {
+            self.live -= len;
+        }
+    }
+
+    /// Forget everything that has been spilled.
+    pub(crate) fn clear(&mut self) -> io::Result<()> {
+        self.index.clear();
+        self.live = 0;
+        self.len = 0;
+        self.file.set_len(0)
+    }
+
+    /// Count misses that had to be answered with a replay.
+    pub(crate) fn missed(&mut self, n: usize) {
+        self.misses += n as u64;
+    }
+
+    /// The number of misses answered from the spill, and the number answered with a replay.
+    pub(crate) fn stats(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+
+    fn read(&mut self, offset: u64, len: u64) -> io::Result<Vec<Vec<DataType>>> {
+        let mut bytes = vec![0; len as usize];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut bytes)?;
+        bincode::deserialize(&bytes).map_err(encoding_error)
+    }
+
+    fn maybe_compact(&mut self) -> io::Result<()> {
+        let dead = self.len - self.live;
+        if dead < COMPACT_AFTER || dead < self.live {
+            return Ok(());
+        }
+
+        let tmp = self.path.with_extension("compact");
+        let mut out = File::create(&tmp)?;
+        let mut entries: Vec<_> = self.index.iter_mut().collect();
+        entries.sort_by_key(|e| (e.1).0);
+
+        let mut len = 0;
+        for (_, entry) in entries {
+            let mut bytes = vec![0; entry.1 as usize];
+            self.file.seek(SeekFrom::Start(entry.0))?;
+            self.file.read_exact(&mut bytes)?;
+            out.write_all(&bytes)?;
+            entry.0 = len;
+            len += entry.1;
+        }
+        drop(out);
+
+        fs::rename(&tmp, &self.path)?;
+        self.file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        self.len = len;
+        self.live = len;
+        Ok(())
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(n: i32) -> Vec<Vec<DataType>> {
+        (0..n).map(|i| vec![n.into(), i.into()]).collect()
+    }
+
+    #[test]
+    fn take_and_invalidate() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spill = Spill::create(dir.path().join("reader.spill")).unwrap();
+
+        spill.put(&[1.into()], &rows(1)).unwrap();
+        spill.put(&[2.into()], &rows(2)).unwrap();
+        spill.put(&[3.into()], &rows(3)).unwrap();
+        spill.invalidate(&[2.into()]);
+
+        assert_eq!(spill.take(&[3.into()]).unwrap(), Some(rows(3)));
+        assert_eq!(spill.take(&[3.into()]).unwrap(), None);
+        assert_eq!(spill.take(&[2.into()]).unwrap(), None);
+        assert_eq!(spill.take(&[1.into()]).unwrap(), Some(rows(1)));
+        assert_eq!(spill.stats(), (2, 0));
+    }
+
+    #[test]
+    fn compacts_dead_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reader.spill");
+        let mut spill = Spill::create(path.clone()).unwrap();
+
+        let big: Vec<Vec<DataType>> = vec![vec!["x".repeat(1 << 16).into()]; 16];
+        spill.put(&[0.into()], &rows(4)).unwrap();
+        for _ in 0..8 {
+            spill.put(&[1.into()], &big).unwrap();
+        }
+        // eight megabytes were spilled, but only one is live
+        assert!(fs::metadata(&path).unwrap().len() < 5 << 20);
+
+        assert_eq!(spill.take(&[0.into()]).unwrap(), Some(rows(4)));
+        assert_eq!(spill.take(&[1.into()]).unwrap(), Some(big));
+
+        drop(spill);
+        assert!(!path.exists());
+    }
+}
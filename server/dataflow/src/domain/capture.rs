@@ -0,0 +1,284 @@
+//! Captures of the events that a domain shard processes, and replays of those captures against a
+//! copy of the domain shard that is not connected to any other.
+
+use super::{DomainBuilder, PollEvent, ProcessResult};
+use crate::prelude::*;
+use crate::{DurabilityMode, Readers, Telemetry};
+use slog::Logger;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::{thread, time};
+use stream_cancel::Valve;
+
+/// An entry in a capture, as it is read back.
+#[derive(Deserialize)]
+enum Entry {
+    /// How the domain shard was set up, which every capture starts with.
+    Domain(DomainBuilder),
+    /// An event that the domain shard processed, and how long after it was set up it did so.
+    Event(time::Duration, PollEvent),
+}
+
+/// An entry in a capture, as it is written. Serialized the same way as `Entry`.
+#[derive(Serialize)]
+enum EntryRef<'a> {
+    Domain(&'a DomainBuilder),
+    Event(time::Duration, &'a PollEvent),
+}
+
+/// Writes every event that a domain shard processes to a file, along with how the domain shard
+/// was set up, so that its processing can be replayed elsewhere.
+pub(super) struct Capture {
+    out: BufWriter<File>,
+    started: time::Instant,
+}
+
+impl Capture {
+    pub(super) fn create(path: &Path, domain: &DomainBuilder) -> io::Result<Self> {
+        let mut capture = Capture {
+            out: BufWriter::new(File::create(path)?),
+            started: time::Instant::now(),
+        };
+        capture.write(&EntryRef::Domain(domain))?;
+        Ok(capture)
+    }
+
+    pub(super) fn record(&mut self, event: &PollEvent) -> io::Result<()> {
+        let at = self.started.elapsed();
+        if let PollEvent::Process(ref m) = *event {
+            if let Packet::Input { ref inner, .. } = **m {
+                if inner.is_local() {
+                    // writes from clients on the same worker are handed over by pointer, which a
+                    // copy owns instead
+                    return self.write(&EntryRef::Event(at, &PollEvent::Process(m.clone())));
+                }
+            }
+        }
+        self.write(&EntryRef::Event(at, event))
+    }
+
+    fn write(&mut self, entry: &EntryRef<'_>) -> io::Result<()> {
+        bincode::serialize_into(&mut self.out, entry).map_err(into_io)?;
+        // a capture is most useful of a domain that is about to fail, so don't hold on to events
+        self.out.flush()
+    }
+}
+
+fn into_io(e: bincode::Error) -> io::Error {
+    match *e {
+        bincode::ErrorKind::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+/// A capture of the events that a domain shard processed, as read back from its file.
+///
+/// Iterating over it yields the events in the order they were processed in, with how long after
+/// the domain shard was set up each was processed.
+pub struct CaptureFile {
+    domain: DomainBuilder,
+    input: BufReader<File>,
+}
+
+impl CaptureFile {
+    /// Open the capture in the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut input = BufReader::new(File::open(path)?);
+        match bincode::deserialize_from(&mut input).map_err(into_io)? {
+            Entry::Domain(domain) => Ok(CaptureFile { domain, input }),
+            Entry::Event(..) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "capture does not start with its domain",
+            )),
+        }
+    }
+
+    /// How the domain shard was set up.
+    pub fn domain(&self) -> &DomainBuilder {
+        &self.domain
+    }
+
+    /// What makes the domain shard process the same events differently from one time to the
+    /// next, and so replays of the capture differ from what happened when it was captured.
+    pub fn nondeterminism(&self) -> Vec<String> {
+        let mut found = Vec::new();
+        let config = &self.domain.config;
+        if config.propagation_sampling > 0.0 {
+            found.push("writes are sampled at random to measure how long they propagate".into());
+        }
+        if config.egress_batching.is_some() {
+            found.push("updates are batched until a time limit passes".into());
+        }
+        for n in self.domain.nodes.values() {
+            let n = n.borrow();
+            if let Some(b) = n.get_base() {
+                if b.ttl().is_some() {
+                    found.push(format!("base {} expires rows by the time", n.name()));
+                }
+                if b.has_auto_timestamps() {
+                    found.push(format!(
+                        "base {} stamps writes with the time they arrive",
+                        n.name()
+                    ));
+                }
+                if config.freshness_interval.is_some() {
+                    found.push(format!(
+                        "base {} tells readers it is idle when a time limit passes",
+                        n.name()
+                    ));
+                }
+            }
+        }
+        found
+    }
+}
+
+impl Iterator for CaptureFile {
+    type Item = io::Result<(time::Duration, PollEvent)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match bincode::deserialize_from(&mut self.input) {
+            Ok(Entry::Event(at, event)) => Some(Ok((at, event))),
+            Ok(Entry::Domain(..)) => Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "capture has more than one domain",
+            ))),
+            Err(e) => match into_io(e) {
+                // a capture ends wherever the domain stopped writing it
+                ref e if e.kind() == io::ErrorKind::UnexpectedEof => None,
+                e => Some(Err(e)),
+            },
+        }
+    }
+}
+
+/// What came of replaying a capture.
+#[derive(Debug, Default)]
+pub struct Replayed {
+    /// The rows that each of the domain's materialized nodes held at the end, by the name of the
+    /// node, sorted.
+    pub state: HashMap<String, Vec<Vec<DataType>>>,
+    /// How many packets the domain sent to each other domain shard.
+    pub sent: HashMap<(DomainIndex, usize), usize>,
+    /// How many writes the domain acknowledged.
+    pub acks: usize,
+    /// Why the replay may have gone differently from what was captured.
+    pub nondeterminism: Vec<String>,
+}
+
+/// Takes the place of the rest of the dataflow for a replayed domain shard.
+struct Recorder<'a>(&'a mut Replayed);
+
+impl<'a> Executor for Recorder<'a> {
+    fn ack(&mut self, _: SourceChannelIdentifier, _: WriteAck) {
+        self.0.acks += 1;
+    }
+
+    fn create_universe(&mut self, _: HashMap<String, DataType>) {}
+
+    fn send(&mut self, dest: ReplicaAddr, _: Box<Packet>) {
+        *self.0.sent.entry(dest).or_default() += 1;
+    }
+
+    fn queued(&self, _: ReplicaAddr) -> usize {
+        0
+    }
+
+    fn queued_records(&self) -> (usize, usize) {
+        (0, 0)
+    }
+}
+
+/// Process the events in `capture` with a copy of the domain shard it was captured from, and
+/// return what the domain ended up with.
+///
+/// The copy keeps the state of its bases in memory, and what it sends other domains goes
+/// nowhere, so state it would have to replay from other domains is only there if the replay was
+/// captured too. If `realtime` is set, each event is processed no sooner after the copy was set up
+/// than it was when it was captured.
+///
+/// Must be called from a thread of a multi-threaded tokio runtime.
+pub fn replay_capture(capture: CaptureFile, realtime: bool, log: Logger) -> io::Result<Replayed> {
+    let mut replayed = Replayed {
+        nondeterminism: capture.nondeterminism(),
+        ..Default::default()
+    };
+
+    let mut builder = capture.domain.clone();
+    builder.persistence_parameters.mode = DurabilityMode::MemoryOnly;
+    builder.config.capture_dir = None;
+
+    // the domain reports to the controller as it goes, which nobody needs to hear
+    let control = TcpListener::bind("127.0.0.1:0")?;
+    let control_addr = control.local_addr()?;
+    thread::spawn(move || {
+        for mut conn in control.incoming().flatten() {
+            thread::spawn(move || io::copy(&mut conn, &mut io::sink()));
+        }
+    });
+
+    let readers = Readers::default();
+    let (_trigger, valve) = Valve::new();
+    let mut domain = builder.build(
+        log,
+        readers,
+        Arc::new(ChannelCoordinator::new()),
+        control_addr,
+        false,
+        &valve,
+        Arc::new(AtomicUsize::new(0)),
+        // spans of the captured writes were already recorded when they were captured
+        Telemetry::default(),
+    );
+
+    let started = time::Instant::now();
+    let mut timers = false;
+    let mut evictions = false;
+    for event in capture {
+        let (at, event) = event?;
+        if realtime {
+            let since = started.elapsed();
+            if at > since {
+                thread::sleep(at - since);
+            }
+        }
+
+        match event {
+            PollEvent::Timeout => timers = true,
+            PollEvent::Process(ref m) => {
+                if let Packet::Evict { .. } = **m {
+                    evictions = true;
+                }
+            }
+            PollEvent::ResumePolling => {}
+        }
+        if let ProcessResult::StopPolling = domain.on_event(&mut Recorder(&mut replayed), event) {
+            break;
+        }
+    }
+
+    if timers {
+        replayed.nondeterminism.push(
+            "timers fire when they did, but what is due when they do depends on the time".into(),
+        );
+    }
+    if evictions {
+        replayed
+            .nondeterminism
+            .push("evictions pick the keys they evict at random".into());
+    }
+
+    replayed.state = domain
+        .cloned_state()
+        .into_iter()
+        .map(|(name, mut rows)| {
+            rows.sort();
+            (name, rows)
+        })
+        .collect();
+    Ok(replayed)
+}
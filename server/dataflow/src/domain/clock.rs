@@ -0,0 +1,68 @@
+use chrono::NaiveDateTime;
+use std::time;
+
+/// The time that a domain expires rows of its bases against.
+///
+/// The wall-clock time is read once, when the clock is started, and moved forward by the
+/// monotonic clock from then on. Setting the system clock back or forward thus does not make rows
+/// expire late, or all at once. Tests can instead stop the clock, and move it forward by hand.
+#[derive(Clone, Debug)]
+pub(crate) struct Clock {
+    wall: NaiveDateTime,
+    /// When the wall-clock time was read, unless the clock is stopped.
+    started: Option<time::Instant>,
+}
+
+impl Clock {
+    pub(crate) fn new() -> Self {
+        Clock {
+            wall: chrono::Local::now().naive_local(),
+            started: Some(time::Instant::now()),
+        }
+    }
+
+    /// A clock that is stopped at `wall`, and only moves when it is advanced.
+    #[cfg(test)]
+    pub(crate) fn stopped_at(wall: NaiveDateTime) -> Self {
+        Clock {
+            wall,
+            started: None,
+        }
+    }
+
+    pub(crate) fn now(&self) -> NaiveDateTime {
+        match self.started {
+            Some(started) => self.wall + chrono::Duration::from_std(started.elapsed()).unwrap(),
+            None => self.wall,
+        }
+    }
+
+    /// Move the clock forward by `by`.
+    #[cfg(test)]
+    pub(crate) fn advance(&mut self, by: time::Duration) {
+        self.wall += chrono::Duration::from_std(by).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn it_never_goes_backwards() {
+        let clock = Clock::new();
+        let before = clock.now();
+        std::thread::sleep(time::Duration::from_millis(10));
+        assert!(clock.now() > before);
+    }
+
+    #[test]
+    fn stopped_clocks_move_when_advanced() {
+        let t0 = NaiveDate::from_ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let mut clock = Clock::stopped_at(t0);
+        assert_eq!(clock.now(), t0);
+        clock.advance(time::Duration::from_secs(60));
+        assert_eq!(clock.now(), t0 + chrono::Duration::minutes(1));
+    }
+}
@@ -5,17 +5,29 @@ use std::cmp;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::mem;
 use std::net::SocketAddr;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time;
 
+use self::capture::Capture;
+use self::clock::Clock;
+use self::throttle::ReplayThrottle;
 use crate::group_commit::GroupCommitQueueSet;
-use crate::payload::{ControlReplyPacket, ReplayPieceContext, SourceSelection};
+use crate::ops::udf::Udf;
+use crate::payload::{ControlReplyPacket, Label, ReplayPieceContext, SourceSelection};
 use crate::prelude::*;
+use crate::trace::TraceCollector;
+use crate::Telemetry;
 use ahash::RandomState;
 use futures_util::{future::FutureExt, stream::StreamExt};
-use noria::channel::{self, TcpSender};
+use noria::channel::{self, MaybeLocalSender};
+use noria::debug::bookkeeping::{DomainBookkeeping, EdgeFrontier, LabelEntry, NodeBookkeeping};
+use noria::debug::stats::{LatencyHistogram, MemoryBreakdown, MemoryUsage};
+use noria::debug::trace::PacketEvent;
 pub use noria::internal::DomainIndex as Index;
+use noria::internal::LocalOrNot;
+use opentelemetry::KeyValue;
 use slog::Logger;
 use stream_cancel::Valve;
 
@@ -23,7 +35,13 @@ use crate::Readers;
 use timekeeper::{RealTime, SimpleTracker, ThreadTime, Timer, TimerSet};
 use tokio;
 
-#[derive(Debug)]
+mod capture;
+mod clock;
+mod throttle;
+
+pub use self::capture::{replay_capture, CaptureFile, Replayed};
+
+#[derive(Debug, Serialize, Deserialize)]
 pub enum PollEvent {
     ResumePolling,
     Process(Box<Packet>),
@@ -41,10 +59,154 @@ pub enum ProcessResult {
 pub struct Config {
     pub concurrent_replays: usize,
     pub replay_batch_timeout: time::Duration,
+    /// The most keys a source of partial replays answers together before the batch times out.
+    #[serde(default = "default_replay_batch_size")]
+    pub replay_batch_size: usize,
+    /// How many threads split up a snapshot of a node's state when it is replayed in full.
+    #[serde(default = "default_replay_parallelism")]
+    pub replay_parallelism: usize,
+    /// The hash that keys are sharded by.
+    ///
+    /// Configurations persisted before the hash could be chosen used `ShardHasher::AHash`.
+    #[serde(default = "noria::ShardHasher::unconfigured")]
+    pub shard_hasher: noria::ShardHasher,
+    /// The most packets that may wait to be sent to any one downstream domain shard before the
+    /// domain stops taking in new work.
+    #[serde(default = "default_output_high_water")]
+    pub output_high_water: usize,
+    /// What a base domain does with client writes while it is not taking in new work.
+    #[serde(default)]
+    pub overload_policy: OverloadPolicy,
+    /// When egresses coalesce the regular updates they send downstream, if they do.
+    #[serde(default)]
+    pub egress_batching: Option<crate::node::special::EgressBatching>,
+    /// Where partial readers spill the keys they evict, if they spill them at all.
+    #[serde(default)]
+    pub spill_dir: Option<PathBuf>,
+    /// Whether partial readers remember the keys they evict while those hold no rows.
+    #[serde(default)]
+    pub negative_caching: Option<crate::backlog::NegativeCaching>,
+    /// The share of client writes that base domains stamp with the time they arrived, so that
+    /// readers can record how long writes take to reach them.
+    #[serde(default)]
+    pub propagation_sampling: f64,
+    /// How long a node may take to process a single packet before the domain logs a warning.
+    #[serde(default)]
+    pub slow_packet_threshold: Option<time::Duration>,
+    /// How long a partial replay may take from the miss that caused it until the missing state
+    /// is filled before the domain logs a warning.
+    #[serde(default)]
+    pub slow_replay_threshold: Option<time::Duration>,
+    /// How long a base may go without writes before the domain tells the readers downstream of
+    /// it that it has applied nothing newer, so that reads bounded in staleness do not wait on
+    /// idle bases. `None` turns this off.
+    #[serde(default)]
+    pub freshness_interval: Option<time::Duration>,
+    /// How many client operation ids each base remembers, and for how long, so that writes that
+    /// clients retry are only applied once.
+    #[serde(default)]
+    pub dedup_window: crate::node::special::DedupWindow,
+    /// How fast the domain replays the state of a node in full.
+    #[serde(default)]
+    pub replay_rate: noria::ReplayRate,
+    /// How many packets may wait to be sent to other domains before replays of a node's full
+    /// state hold off further, so that they do not crowd out regular updates. 0 turns this off.
+    #[serde(default)]
+    pub replay_backoff_depth: usize,
+    /// Where the domain writes a capture of every event it processes, if anywhere.
+    #[serde(default)]
+    pub capture_dir: Option<PathBuf>,
+}
+
+fn default_replay_batch_size() -> usize {
+    1024
+}
+
+fn default_replay_parallelism() -> usize {
+    1
+}
+
+fn default_output_high_water() -> usize {
+    4096
+}
+
+/// What a domain does with client writes while a downstream domain is falling behind.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OverloadPolicy {
+    /// Stop reading writes until the domain takes in new work again, which blocks clients once
+    /// their connections fill up.
+    Block,
+    /// Turn writes away without applying them, so that clients can retry them later.
+    Reject,
+}
+
+impl Default for OverloadPolicy {
+    fn default() -> Self {
+        OverloadPolicy::Block
+    }
+}
+
+/// A snapshot of a domain's counters and gauges, which its worker exports for monitoring.
+#[derive(Clone, Debug, Default)]
+pub struct DomainMetrics {
+    /// Packets the domain has handled.
+    pub packets: u64,
+    /// Groups of client writes that the domain's bases have committed together.
+    pub group_commits: u64,
+    /// Keys that the domain's partial readers have issued replays for.
+    pub reader_replays: u64,
+    /// Partial replay requests that had to wait for a free slot.
+    pub queued_replays: u64,
+    /// Total time those requests waited.
+    pub replay_queue_time: time::Duration,
+    /// Partial replay requests waiting right now.
+    pub replay_queue_len: usize,
+    /// Total time spent handling replays.
+    pub replay_time: time::Duration,
+    /// Packets waiting to be sent to other domains. The domain's worker fills this in.
+    pub output_queue: usize,
+    /// The size in bytes of the state of each of the domain's materialized nodes, by node name.
+    pub state_sizes: Vec<(String, usize)>,
+    /// The latest batch of writes each of the domain's bases has processed.
+    pub base_labels: Vec<LabelMetric>,
+    /// The latest batch from each base shard that each of the domain's readers reflects.
+    pub reader_labels: Vec<LabelMetric>,
+    /// How long sampled writes took to reach each of the domain's readers, by reader name.
+    pub reader_propagation: Vec<(String, LatencyHistogram)>,
+}
+
+/// How far the batches of writes from one base shard have made it to one node.
+#[derive(Clone, Debug)]
+pub struct LabelMetric {
+    /// The name of the node.
+    pub node: String,
+    /// The base the batches come from.
+    pub base: NodeIndex,
+    /// The shard of the base the batches come from.
+    pub base_shard: usize,
+    /// The sequence number of the latest batch.
+    pub seq: u64,
 }
 
 const BATCH_SIZE: usize = 256;
 
+/// The most records sent downstream in a single update when a bulk load finishes.
+const BULK_LOAD_CHUNK: usize = 4096;
+
+/// How many of its sharders' hottest keys a domain reports in its statistics.
+const HOT_KEYS: usize = 10;
+
+/// The most partial replays whose duration a domain keeps track of at any one time.
+const MAX_TIMED_REPLAYS: usize = 1 << 16;
+
+/// The current time, in nanoseconds since the Unix epoch.
+pub(crate) fn unix_nanos() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
 #[derive(Debug)]
 enum DomainMode {
     Forwarding,
@@ -145,8 +307,10 @@ impl DomainBuilder {
         readers: Readers,
         channel_coordinator: Arc<ChannelCoordinator>,
         control_addr: SocketAddr,
+        control_tls: bool,
         shutdown_valve: &Valve,
         state_size: Arc<AtomicUsize>,
+        telemetry: Telemetry,
     ) -> Domain {
         // initially, all nodes are not ready
         let not_ready = self
@@ -155,23 +319,69 @@ impl DomainBuilder {
             .map(|n| n.borrow().local_addr())
             .collect();
 
+        let mut udfs = Vec::new();
+        for n in self.nodes.values() {
+            let mut n = n.borrow_mut();
+            if let Some(b) = n.get_base_mut() {
+                b.set_shard(self.shard.unwrap_or(0), self.nshards);
+                b.set_dedup_window(self.config.dedup_window);
+            }
+            if n.is_egress() {
+                n.with_egress_mut(|e| e.set_batching(self.config.egress_batching));
+            }
+            n.share_udfs(&mut udfs);
+        }
+
+        // bases with a TTL are first scanned for expired rows once their scan interval has passed
+        let now = time::Instant::now();
+        let ttl_scans = self
+            .nodes
+            .values()
+            .filter_map(|n| {
+                let n = n.borrow();
+                let ttl = n.get_base()?.ttl()?;
+                Some((n.local_addr(), now + ttl.scan_every))
+            })
+            .collect();
+
         let log = log.new(o!("domain" => self.index.index(), "shard" => self.shard.unwrap_or(0)));
-        let control_reply_tx = TcpSender::connect(&control_addr).unwrap();
-        let group_commit_queues = GroupCommitQueueSet::new(&self.persistence_parameters);
+        let capture = self.config.capture_dir.as_ref().and_then(|dir| {
+            let path = dir.join(format!(
+                "{}.{}.capture",
+                self.index.index(),
+                self.shard.unwrap_or(0)
+            ));
+            match Capture::create(&path, &self) {
+                Ok(capture) => Some(capture),
+                Err(e) => {
+                    warn!(log, "cannot capture events; processing them anyway"; "err" => %e);
+                    None
+                }
+            }
+        });
+        let tls = channel::tls::for_endpoint(channel_coordinator.tls(), control_addr, control_tls)
+            .unwrap();
+        let control_reply_tx = MaybeLocalSender::connect(&control_addr, tls).unwrap();
+        let group_commit_queues =
+            GroupCommitQueueSet::new(&self.persistence_parameters, telemetry.clone());
+        let traces = TraceCollector::new(self.index, self.shard.unwrap_or(0));
+        let log_packets = Arc::new(AtomicBool::new(false));
 
         Domain {
             index: self.index,
             shard: self.shard,
-            _nshards: self.nshards,
+            nshards: self.nshards,
 
             persistence_parameters: self.persistence_parameters,
             nodes: self.nodes,
             state: StateMap::default(),
             log,
+            udfs,
             not_ready,
             mode: DomainMode::Forwarding,
             waiting: Default::default(),
             reader_triggered: Default::default(),
+            reader_replays: Default::default(),
             replay_paths: Default::default(),
             replay_paths_by_dst: Default::default(),
 
@@ -184,16 +394,53 @@ impl DomainBuilder {
 
             buffered_replay_requests: Default::default(),
             replay_batch_timeout: self.config.replay_batch_timeout,
+            replay_batch_size: self.config.replay_batch_size,
+            replay_parallelism: self.config.replay_parallelism,
+            packets: 0,
+            replay_batches: 0,
+            replayed_keys: 0,
+            shard_hasher: self.config.shard_hasher,
+            output_high_water: self.config.output_high_water,
+            overload_policy: self.config.overload_policy,
+            egress_batching: self.config.egress_batching,
+            spill_dir: self.config.spill_dir,
+            negative_caching: self.config.negative_caching,
+            propagation_sampling: self.config.propagation_sampling,
+            propagation: Default::default(),
+            slow_packet_threshold: self.config.slow_packet_threshold,
+            slow_replay_threshold: self.config.slow_replay_threshold,
+            freshness_interval: self.config.freshness_interval,
+            idle_bases: Default::default(),
+            dedup_window: self.config.dedup_window,
+            replay_throttle: Arc::new(ReplayThrottle::new(
+                self.config.replay_rate,
+                self.config.replay_backoff_depth,
+            )),
+            replays_started: Default::default(),
+            capture,
             timed_purges: Default::default(),
 
             concurrent_replays: 0,
             max_concurrent_replays: self.config.concurrent_replays,
             replay_request_queue: Default::default(),
+            queued_replay_keys: Default::default(),
+            queued_replays: 0,
+            replay_queue_time: time::Duration::from_secs(0),
             delayed_for_self: Default::default(),
 
             group_commit_queues,
+            traces,
+            telemetry,
+            log_packets,
+            bulk_loads: Default::default(),
+            paused: Default::default(),
+            open_transactions: Default::default(),
+            staged: Default::default(),
+            ttl_scans,
+            clock: Clock::new(),
 
             state_size,
+            over_memory_budget: false,
             total_time: Timer::new(),
             total_ptime: Timer::new(),
             wait_time: Timer::new(),
@@ -206,6 +453,25 @@ impl DomainBuilder {
     }
 }
 
+/// A bulk load into one of the domain's bases.
+#[derive(Default)]
+struct BulkLoad {
+    /// Records that have been applied to the base, but not yet sent downstream.
+    loaded: Vec<Record>,
+    /// Writes to the base that arrived while the load was running.
+    held_back: Vec<Box<Packet>>,
+}
+
+/// A base's part of a transaction, checked and persisted, but not yet applied.
+struct StagedTransaction {
+    txn: u64,
+    records: Records,
+    /// The label the part is sent downstream with, unless it was recovered from disk.
+    label: Option<Label>,
+    /// Writes to the base that arrived while the transaction was being decided.
+    held_back: Vec<Box<Packet>>,
+}
+
 #[derive(Clone, Debug)]
 struct TimedPurge {
     time: time::Instant,
@@ -217,11 +483,13 @@ struct TimedPurge {
 pub struct Domain {
     index: Index,
     shard: Option<usize>,
-    _nshards: usize,
+    nshards: usize,
 
     nodes: DomainNodes,
     state: StateMap,
     log: Logger,
+    /// The domain's own copies of the user-defined functions that its nodes call.
+    udfs: Vec<Arc<Udf>>,
 
     not_ready: HashSet<LocalNodeIndex>,
 
@@ -232,27 +500,89 @@ pub struct Domain {
     mode: DomainMode,
     waiting: Map<Waiting>,
     replay_paths: HashMap<Tag, ReplayPath>,
-    reader_triggered: Map<HashSet<Vec<DataType>, RandomState>>,
+    /// The keys each partial reader has requested replays for that are still in flight, and when
+    /// each was requested.
+    reader_triggered: Map<HashMap<Vec<DataType>, time::Instant, RandomState>>,
+    /// How many keys each partial reader has issued replays for.
+    reader_replays: Map<u64>,
     timed_purges: VecDeque<TimedPurge>,
 
     replay_paths_by_dst: Map<HashMap<Vec<usize>, Vec<Tag>>>,
 
     concurrent_replays: usize,
     max_concurrent_replays: usize,
-    replay_request_queue: VecDeque<(Tag, Vec<Vec<DataType>>)>,
+    /// Replay requests waiting for a free slot, with the time each was queued.
+    replay_request_queue: VecDeque<(Tag, Vec<Vec<DataType>>, time::Instant)>,
+    /// The keys in `replay_request_queue`, so that a key is only queued once per path.
+    queued_replay_keys: HashSet<(Tag, Vec<DataType>)>,
+    /// How many replay requests have had to wait for a free slot, and for how long in total.
+    queued_replays: u64,
+    replay_queue_time: time::Duration,
 
     shutdown_valve: Valve,
     readers: Readers,
-    control_reply_tx: TcpSender<ControlReplyPacket>,
+    control_reply_tx: MaybeLocalSender<ControlReplyPacket>,
     channel_coordinator: Arc<ChannelCoordinator>,
 
     buffered_replay_requests: HashMap<(Tag, usize), (time::Instant, HashSet<Vec<DataType>>, bool)>,
     replay_batch_timeout: time::Duration,
+    replay_batch_size: usize,
+    replay_parallelism: usize,
+    /// How many packets we have handled.
+    packets: u64,
+    /// How many batches of partial replay requests we have answered as a replay source, and for
+    /// how many keys in total.
+    replay_batches: u64,
+    replayed_keys: u64,
+    shard_hasher: noria::ShardHasher,
+    output_high_water: usize,
+    overload_policy: OverloadPolicy,
+    egress_batching: Option<crate::node::special::EgressBatching>,
+    spill_dir: Option<PathBuf>,
+    negative_caching: Option<crate::backlog::NegativeCaching>,
+    /// The share of client writes stamped with the time they arrived here.
+    propagation_sampling: f64,
+    /// How long stamped writes took to reach each of our readers.
+    propagation: Map<LatencyHistogram>,
+    slow_packet_threshold: Option<time::Duration>,
+    slow_replay_threshold: Option<time::Duration>,
+    freshness_interval: Option<time::Duration>,
+    /// The latest batch of each of our bases, and when we last saw it change or let readers know
+    /// that it had not.
+    idle_bases: Map<(u64, time::Instant)>,
+    dedup_window: crate::node::special::DedupWindow,
+    /// Paces the threads that replay the full state of our nodes.
+    replay_throttle: Arc<ReplayThrottle>,
+    /// When each key we are waiting on a partial replay for was first missed on, if slow replays
+    /// are being logged.
+    replays_started: HashMap<(Tag, Vec<DataType>), time::Instant>,
+    /// Where we write the events we process, if we capture them.
+    capture: Option<Capture>,
     delayed_for_self: VecDeque<Box<Packet>>,
 
     group_commit_queues: GroupCommitQueueSet,
+    /// Events caused by traced writes.
+    traces: TraceCollector,
+    /// Where the spans of writes that are part of distributed traces go.
+    telemetry: Telemetry,
+    /// Whether every packet is logged as it is handled. This is checked for every packet, and is
+    /// shared with the tasks that forward reader misses.
+    log_packets: Arc<AtomicBool>,
+    bulk_loads: Map<BulkLoad>,
+    /// Writes to bases whose writes are paused, held back until they are resumed.
+    paused: Map<Vec<Box<Packet>>>,
+    /// The transactions that each of our bases accepts a part of.
+    open_transactions: HashSet<(LocalNodeIndex, u64)>,
+    /// The parts of transactions that our bases hold until the transactions are decided.
+    staged: Map<StagedTransaction>,
+    /// When each base with a TTL is next scanned for expired rows.
+    ttl_scans: Map<time::Instant>,
+    /// The time that rows of bases with a TTL expire against.
+    clock: Clock,
 
     state_size: Arc<AtomicUsize>,
+    /// Whether we have warned that state we cannot evict does not fit in the memory budget.
+    over_memory_budget: bool,
     total_time: Timer<SimpleTracker, RealTime>,
     total_ptime: Timer<SimpleTracker, ThreadTime>,
     wait_time: Timer<SimpleTracker, RealTime>,
@@ -281,6 +611,20 @@ impl Domain {
             }
         }
 
+        if self.slow_replay_threshold.is_some() {
+            let now = time::Instant::now();
+            for &tag in &tags {
+                for key in &miss_keys {
+                    if self.replays_started.len() >= MAX_TIMED_REPLAYS {
+                        break;
+                    }
+                    self.replays_started
+                        .entry((tag, key.clone()))
+                        .or_insert(now);
+                }
+            }
+        }
+
         for &tag in &tags {
             // send a message to the source domain(s) responsible
             // for the chosen tag so they'll start replay.
@@ -449,7 +793,8 @@ impl Domain {
             } else if let Some(key_shard_i) = ask_shard_by_key_i {
                 let mut shards = HashMap::new();
                 for key in keys {
-                    let shard = crate::shard_by(&key[key_shard_i], options.len());
+                    let shard =
+                        crate::shard_by(&self.shard_hasher, &key[key_shard_i], options.len());
                     shards.entry(shard).or_insert_with(Vec::new).push(key);
                 }
                 for (shard, keys) in shards {
@@ -474,17 +819,49 @@ impl Domain {
         }
     }
 
-    fn request_partial_replay(&mut self, tag: Tag, keys: Vec<Vec<DataType>>) {
+    fn request_partial_replay(&mut self, tag: Tag, mut keys: Vec<Vec<DataType>>) {
         if self.concurrent_replays < self.max_concurrent_replays {
             assert_eq!(self.replay_request_queue.len(), 0);
             self.send_partial_replay_request(tag, keys);
         } else {
+            // a key that is already queued will be replayed once its request is released
+            let queued = &mut self.queued_replay_keys;
+            keys.retain(|key| queued.insert((tag, key.clone())));
+            if keys.is_empty() {
+                return;
+            }
+
             trace!(self.log, "buffering replay request";
                 "tag" => ?tag,
                 "keys" => ?keys,
                 "buffered" => self.replay_request_queue.len(),
             );
-            self.replay_request_queue.push_back((tag, keys));
+            self.queued_replays += 1;
+            self.replay_request_queue
+                .push_back((tag, keys, time::Instant::now()));
+        }
+    }
+
+    /// Log a warning if the partial replay that just filled `keys` along the path with `tag` took
+    /// longer than it should have.
+    fn time_partial_replay(&mut self, tag: Tag, keys: &HashSet<Vec<DataType>>) {
+        let started = keys
+            .iter()
+            .filter_map(|key| self.replays_started.remove(&(tag, key.clone())))
+            .min();
+        if let (Some(threshold), Some(started)) = (self.slow_replay_threshold, started) {
+            let took = started.elapsed();
+            if took > threshold {
+                let path = &self.replay_paths[&tag].path;
+                let node = self.nodes[path.last().unwrap().node].borrow();
+                warn!(self.log, "slow replay";
+                      "tag" => tag,
+                      "node" => node.global_addr().index(),
+                      "name" => node.name(),
+                      "keys" => keys.len(),
+                      "path" => path.len(),
+                      "ms" => took.as_millis() as u64);
+            }
         }
     }
 
@@ -528,8 +905,13 @@ impl Domain {
                 );
                 debug_assert!(self.concurrent_replays < self.max_concurrent_replays);
                 let mut per_tag = HashMap::new();
+                let now = time::Instant::now();
                 while self.concurrent_replays < self.max_concurrent_replays {
-                    if let Some((tag, mut keys)) = self.replay_request_queue.pop_front() {
+                    if let Some((tag, mut keys, queued)) = self.replay_request_queue.pop_front() {
+                        self.replay_queue_time += now.duration_since(queued);
+                        for key in &keys {
+                            self.queued_replay_keys.remove(&(tag, key.clone()));
+                        }
                         per_tag
                             .entry(tag)
                             .or_insert_with(Vec::new)
@@ -579,8 +961,15 @@ impl Domain {
             return;
         }
 
+        let trace = m.trace();
+        let origin = m.origin();
+        let context = m.context();
+        let records = m.records();
         let (mut m, evictions) = {
             let mut n = self.nodes[me].borrow_mut();
+            let started = self.slow_packet_threshold.map(|_| time::Instant::now());
+            let received = if context.is_some() { unix_nanos() } else { 0 };
+            let operator_before = n.operator_stats();
             self.process_times.start(me);
             self.process_ptimes.start(me);
             let mut m = Some(m);
@@ -599,6 +988,79 @@ impl Domain {
             self.process_ptimes.stop();
             self.process_times.stop();
 
+            if let (Some(threshold), Some(started)) = (self.slow_packet_threshold, started) {
+                let took = started.elapsed();
+                if took > threshold {
+                    // the operator's own share of the time, as opposed to materializing its output
+                    let operator_ms = match (operator_before, n.operator_stats()) {
+                        (Some(before), Some(after)) => {
+                            (after.process_time - before.process_time) / 1_000_000
+                        }
+                        _ => 0,
+                    };
+                    warn!(self.log, "slow packet";
+                          "node" => n.global_addr().index(),
+                          "name" => n.name(),
+                          "records" => records,
+                          "ms" => took.as_millis() as u64,
+                          "operator_ms" => operator_ms);
+                }
+            }
+
+            if self.log_packets.load(Ordering::Relaxed) {
+                info!(self.log, "node processed packet";
+                      "node" => me.id(),
+                      "forwarded" => m.as_ref().map(|m| !m.is_empty()).unwrap_or(false),
+                      "misses" => misses.len());
+            }
+
+            if let Some(tag) = trace {
+                let event = if n.is_reader() {
+                    PacketEvent::Reached
+                } else {
+                    PacketEvent::Processed
+                };
+                self.traces.record(tag, n.global_addr(), event);
+            }
+
+            if let Some((parent, sent)) = context {
+                // the update goes on as part of the span of its way to, or through, this node
+                let span = if n.is_ingress() {
+                    Some(("noria.hop", sent, received))
+                } else if n.is_base() {
+                    Some(("noria.base", received, unix_nanos()))
+                } else if n.is_reader() {
+                    Some(("noria.reader", received, unix_nanos()))
+                } else {
+                    None
+                };
+                if let Some((name, start, end)) = span {
+                    let context = self.telemetry.span(
+                        name,
+                        parent,
+                        start,
+                        end,
+                        &[],
+                        vec![
+                            KeyValue::new("noria.node", n.name().to_owned()),
+                            KeyValue::new("noria.domain", self.index.index() as i64),
+                            KeyValue::new("noria.shard", self.shard.unwrap_or(0) as i64),
+                        ],
+                    );
+                    if let Some(ref mut m) = m {
+                        m.set_context(context);
+                    }
+                }
+            }
+
+            if let (Some(origin), true) = (origin, n.is_reader()) {
+                let latency = time::Duration::from_nanos(unix_nanos().saturating_sub(origin));
+                self.propagation
+                    .entry(me)
+                    .or_insert_with(LatencyHistogram::default)
+                    .record(latency);
+            }
+
             if m.is_none() {
                 // no need to deal with our children if we're not sending them anything
                 return;
@@ -711,7 +1173,7 @@ impl Domain {
         }
 
         match &**m.as_ref().unwrap() {
-            m @ &Packet::Message { .. } if m.is_empty() => {
+            m @ &Packet::Message { .. } if m.is_empty() && !m.is_tracked() => {
                 // no need to deal with our children if we're not sending them anything
                 return;
             }
@@ -756,7 +1218,28 @@ impl Domain {
             self.wait_time.stop();
         }
 
+        if self.log_packets.load(Ordering::Relaxed) {
+            info!(self.log, "handling packet"; "packet" => ?m, "top" => top);
+        }
+
         match *m {
+            Packet::Input { .. } if self.bulk_loads.contains_key(m.dst()) => {
+                // normal writes wait until the bulk load is done
+                let dst = m.dst();
+                self.bulk_loads.get_mut(dst).unwrap().held_back.push(m);
+            }
+            Packet::Input { .. } if self.paused.contains_key(m.dst()) => {
+                let dst = m.dst();
+                self.paused.get_mut(dst).unwrap().push(m);
+            }
+            Packet::Input { .. } if m.transaction().is_some() => {
+                self.stage_transaction(m, executor);
+            }
+            Packet::Input { .. } if self.staged.contains_key(m.dst()) => {
+                // normal writes wait until the transaction the base has a part of is decided
+                let dst = m.dst();
+                self.staged.get_mut(dst).unwrap().held_back.push(m);
+            }
             Packet::Message { .. } | Packet::Input { .. } => {
                 // WO for https://github.com/rust-lang/rfcs/issues/1403
                 self.total_forward_time.start();
@@ -771,11 +1254,30 @@ impl Domain {
             Packet::Evict { .. } | Packet::EvictKeys { .. } => {
                 self.handle_eviction(m, executor);
             }
+            Packet::Batch { packets } => {
+                for m in packets {
+                    self.handle(m, executor, top);
+                }
+            }
             consumed => {
                 match consumed {
                     // workaround #16223
-                    Packet::AddNode { node, parents } => {
+                    Packet::AddNode { mut node, parents } => {
+                        if let Some(b) = node.get_base_mut() {
+                            b.set_shard(self.shard.unwrap_or(0), self.nshards);
+                            b.set_dedup_window(self.dedup_window);
+                        }
+                        if node.is_egress() {
+                            let egress_batching = self.egress_batching;
+                            node.with_egress_mut(|e| e.set_batching(egress_batching));
+                        }
+                        node.share_udfs(&mut self.udfs);
+
                         let addr = node.local_addr();
+                        if let Some(ttl) = node.get_base().and_then(|b| b.ttl()) {
+                            self.ttl_scans
+                                .insert(addr, time::Instant::now() + ttl.scan_every);
+                        }
                         self.not_ready.insert(addr);
 
                         for p in parents {
@@ -790,8 +1292,14 @@ impl Domain {
                     }
                     Packet::RemoveNodes { nodes } => {
                         for &node in &nodes {
+                            let gid = self.nodes[node].borrow().global_addr();
+                            let shard = *self.shard.as_ref().unwrap_or(&0);
+                            if let Some(r) = self.readers.lock().unwrap().remove(&(gid, shard)) {
+                                r.mark_removed();
+                            }
                             self.nodes[node].borrow_mut().remove();
                             self.state.remove(node);
+                            self.ttl_scans.remove(node);
                             trace!(self.log, "node removed"; "local" => node.id());
                         }
 
@@ -809,15 +1317,27 @@ impl Domain {
                         default,
                     } => {
                         let mut n = self.nodes[node].borrow_mut();
+                        let column = n.fields().len();
+
+                        // rows that are already materialized were written before the column
+                        // existed, so they must be made to look like they had the default all
+                        // along. otherwise lookups and replays would produce ragged rows.
+                        if let Some(s) = self.state.get_mut(node) {
+                            s.add_column(column, &default);
+                        }
+
                         n.add_column(&field);
                         if let Some(b) = n.get_base_mut() {
                             b.add_column(default);
                         } else if n.is_ingress() {
                             self.ingress_inject
                                 .entry(node)
-                                .or_insert_with(|| (n.fields().len(), Vec::new()))
+                                .or_insert_with(|| (column, Vec::new()))
                                 .1
                                 .push(default);
+                        } else if n.is_reader() {
+                            n.with_reader_mut(|r| r.add_column(column, &default))
+                                .unwrap();
                         } else {
                             unreachable!("node unrelated to base got AddBaseColumn");
                         }
@@ -834,6 +1354,153 @@ impl Domain {
                             .send(ControlReplyPacket::ack())
                             .unwrap();
                     }
+                    Packet::StartBulkLoad { node } => {
+                        assert!(!self.bulk_loads.contains_key(node));
+                        self.bulk_loads.insert(node, BulkLoad::default());
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::BulkLoad { node, rows } => {
+                        let rs = self.nodes[node]
+                            .borrow_mut()
+                            .bulk_load(rows, &mut self.state);
+                        self.bulk_loads
+                            .get_mut(node)
+                            .expect("bulk load frame for base that is not being loaded")
+                            .loaded
+                            .extend(rs);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::FinishBulkLoad { node } => {
+                        let BulkLoad { loaded, held_back } = self
+                            .bulk_loads
+                            .remove(node)
+                            .expect("finished bulk load that was never started");
+
+                        // populate everything downstream in a single pass
+                        let mut loaded = loaded.into_iter().peekable();
+                        while loaded.peek().is_some() {
+                            let chunk: Vec<_> = loaded.by_ref().take(BULK_LOAD_CHUNK).collect();
+                            let m = self.nodes[node]
+                                .borrow_mut()
+                                .base_update(chunk.into(), self.shard);
+                            self.handle(Box::new(m), executor, true);
+                        }
+                        for m in held_back {
+                            self.handle(m, executor, true);
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::PauseWrites { node } => {
+                        if !self.paused.contains_key(node) {
+                            self.paused.insert(node, Vec::new());
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::SnapshotBase { node } => {
+                        let label = self.nodes[node]
+                            .borrow()
+                            .get_base()
+                            .expect("told to snapshot non-base node")
+                            .last_label();
+                        let rows = self.state[node].cloned_records();
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::BaseSnapshot {
+                                shard: self.shard.unwrap_or(0),
+                                label,
+                                rows,
+                            })
+                            .unwrap();
+                    }
+                    Packet::ResumeWrites { node } => {
+                        for m in self.paused.remove(node).unwrap_or_default() {
+                            self.handle(m, executor, true);
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::Resplit { node, boundaries } => {
+                        let rs = self.nodes[node]
+                            .borrow_mut()
+                            .split_off(&boundaries, &mut self.state);
+                        let moved = rs.iter().map(|r| r.rec().to_vec()).collect();
+                        if !rs.is_empty() {
+                            debug!(self.log, "moving rows off shard";
+                                   "node" => node.id(),
+                                   "rows" => rs.len());
+                            let m = self.nodes[node].borrow_mut().base_update(rs, self.shard);
+                            self.handle(Box::new(m), executor, true);
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::Rows(moved))
+                            .unwrap();
+                    }
+                    Packet::ExpectTransaction { node, txn, parts } => {
+                        self.nodes[node]
+                            .borrow_mut()
+                            .with_reader_mut(|r| r.expect_transaction(txn, parts))
+                            .expect("told to hold back a transaction at a non-reader node");
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::OpenTransaction { node, txn } => {
+                        self.open_transactions.insert((node, txn));
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::DecideTransaction { node, txn, commit } => {
+                        self.open_transactions.remove(&(node, txn));
+                        // the base may never have received its part, or turned it away
+                        let staged = self.staged.remove(node);
+                        match staged {
+                            Some(staged) if staged.txn == txn => {
+                                self.decide_transaction(node, staged, commit, executor);
+                            }
+                            Some(staged) => {
+                                self.staged.insert(node, staged);
+                            }
+                            None => {}
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::RecoverTransaction { node, committed } => {
+                        if let Some(staged) = self.staged.remove(node) {
+                            let commit = committed.contains(&staged.txn);
+                            info!(self.log, "deciding recovered part of transaction";
+                                  "node" => node.id(),
+                                  "timestamp" => staged.txn,
+                                  "commit" => commit);
+                            self.decide_transaction(node, staged, commit, executor);
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::HideReaderColumn {
+                        node,
+                        column,
+                        default,
+                    } => {
+                        self.nodes[node]
+                            .borrow_mut()
+                            .with_reader_mut(|r| r.hide_column(column, default))
+                            .expect("told to hide column of non-reader node");
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
                     Packet::UpdateEgress {
                         node,
                         new_tx,
@@ -849,6 +1516,13 @@ impl Domain {
                             }
                         });
                     }
+                    Packet::RemoveEgressTx { node, ingress } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        n.with_egress_mut(move |e| e.remove_tx(ingress));
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
                     Packet::UpdateSharder { node, new_txs } => {
                         let mut n = self.nodes[node].borrow_mut();
                         n.with_sharder_mut(move |s| {
@@ -896,14 +1570,19 @@ impl Domain {
                             } => {
                                 use crate::backlog;
                                 let k = key.clone(); // ugh
+                                let hasher = self.shard_hasher;
                                 let txs = (0..shards)
                                     .map(|shard| {
                                         let key = key.clone();
+                                        let log = self.log.clone();
+                                        let miss_log = log.clone();
+                                        let log_packets = self.log_packets.clone();
                                         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
                                         let sender = self
                                             .channel_coordinator
                                             .builder_for(&(trigger_domain, shard))
                                             .unwrap()
+                                            .prioritized()
                                             .build_async()
                                             .unwrap();
 
@@ -911,6 +1590,11 @@ impl Domain {
                                             self.shutdown_valve
                                                 .wrap(rx)
                                                 .map(move |misses| {
+                                                    if log_packets.load(Ordering::Relaxed) {
+                                                        info!(miss_log, "forwarding reader misses";
+                                                              "shard" => shard,
+                                                              "keys" => misses.len());
+                                                    }
                                                     Box::new(Packet::RequestReaderReplay {
                                                         keys: misses,
                                                         cols: key.clone(),
@@ -919,22 +1603,25 @@ impl Domain {
                                                 })
                                                 .map(Ok)
                                                 .forward(sender)
-                                                .map(|r| {
+                                                .map(move |r| {
                                                     if let Err(e) = r {
                                                         // domain went away?
-                                                        eprintln!(
-                                                            "replay source went away: {:?}",
-                                                            e
-                                                        );
+                                                        warn!(log, "replay source went away";
+                                                              "shard" => shard, "err" => ?e);
                                                     }
                                                 }),
                                         );
                                         tx
                                     })
                                     .collect::<Vec<_>>();
-                                let (r_part, w_part) = backlog::new_partial(
+                                let policy = self.nodes[node]
+                                    .borrow()
+                                    .with_reader(|r| r.eviction_policy())
+                                    .unwrap();
+                                let (mut r_part, mut w_part) = backlog::new_partial(
                                     cols,
                                     &k[..],
+                                    policy,
                                     move |misses: &mut dyn Iterator<Item = &[DataType]>| {
                                         let n = txs.len();
                                         if n == 1 {
@@ -945,11 +1632,10 @@ impl Domain {
                                             }
                                             txs[0].send(misses).is_ok()
                                         } else {
-                                            // TODO: compound reader
+                                            // the reader is sharded by its (maybe compound) key
                                             let mut per_shard = HashMap::new();
                                             for miss in misses {
-                                                assert_eq!(miss.len(), 1);
-                                                let shard = crate::shard_by(&miss[0], n);
+                                                let shard = crate::shard_by_key(&hasher, miss, n);
                                                 per_shard
                                                     .entry(shard)
                                                     .or_insert_with(Vec::new)
@@ -966,8 +1652,20 @@ impl Domain {
                                 );
 
                                 let mut n = self.nodes[node].borrow_mut();
+                                r_part.set_name(n.name());
+                                if let Some(shard) = self.shard {
+                                    // range-sharded readers can be re-split without hearing of it
+                                    if let Sharding::ByColumn(..) | Sharding::ByColumns(..) =
+                                        n.sharded_by()
+                                    {
+                                        r_part.set_shard(shard, self.nshards, self.shard_hasher);
+                                    }
+                                }
                                 tokio::task::block_in_place(|| {
                                     n.with_reader_mut(|r| {
+                                        if let Some(order) = r.row_order() {
+                                            backlog::set_row_order(&mut r_part, &mut w_part, order);
+                                        }
                                         assert!(self
                                             .readers
                                             .lock()
@@ -979,18 +1677,62 @@ impl Domain {
                                             .is_none());
 
                                         // make sure Reader is actually prepared to receive state
-                                        r.set_write_handle(w_part)
+                                        r.set_write_handle(w_part);
+
+                                        if let Some(ref dir) = self.spill_dir {
+                                            let path = dir.join(format!(
+                                                "{}.{}.{}.spill",
+                                                self.index.index(),
+                                                self.shard.unwrap_or(0),
+                                                node.id()
+                                            ));
+                                            match backlog::Spill::create(path) {
+                                                Ok(spill) => r.set_spill(spill),
+                                                Err(e) => warn!(
+                                                    self.log,
+                                                    "cannot spill evicted keys; dropping them";
+                                                    "node" => node.id(),
+                                                    "err" => %e
+                                                ),
+                                            }
+                                        }
+                                        if let Some(negative) = self.negative_caching {
+                                            r.set_negative_cache(backlog::NegativeCache::new(
+                                                negative,
+                                            ));
+                                        }
                                     })
                                 })
                                 .unwrap();
                             }
-                            InitialState::Global { gid, cols, key } => {
+                            InitialState::Global {
+                                gid,
+                                cols,
+                                key,
+                                ordered,
+                            } => {
                                 use crate::backlog;
-                                let (r_part, w_part) = backlog::new(cols, &key[..]);
+                                let (mut r_part, mut w_part) = if ordered {
+                                    backlog::new_ordered(cols, &key[..])
+                                } else {
+                                    backlog::new(cols, &key[..])
+                                };
 
                                 let mut n = self.nodes[node].borrow_mut();
+                                r_part.set_name(n.name());
+                                if let Some(shard) = self.shard {
+                                    // range-sharded readers can be re-split without hearing of it
+                                    if let Sharding::ByColumn(..) | Sharding::ByColumns(..) =
+                                        n.sharded_by()
+                                    {
+                                        r_part.set_shard(shard, self.nshards, self.shard_hasher);
+                                    }
+                                }
                                 tokio::task::block_in_place(|| {
                                     n.with_reader_mut(|r| {
+                                        if let Some(order) = r.row_order() {
+                                            backlog::set_row_order(&mut r_part, &mut w_part, order);
+                                        }
                                         assert!(self
                                             .readers
                                             .lock()
@@ -1045,6 +1787,7 @@ impl Domain {
                                     self.channel_coordinator
                                         .builder_for(&(domain, shardi))
                                         .unwrap()
+                                        .prioritized()
                                         .build_sync()
                                         .unwrap()
                                 };
@@ -1128,14 +1871,57 @@ impl Domain {
                             })
                             .unwrap();
 
-                        // ensure that we haven't already requested a replay of this key
-                        keys.retain(|key| {
-                            self.reader_triggered
-                                .entry(node)
-                                .or_default()
-                                .insert(key.clone())
+                        // keys that were empty or spilled when they were evicted don't need a
+                        // replay
+                        let reader_triggered = self.reader_triggered.entry(node).or_default();
+                        let log = &self.log;
+                        self.nodes[node]
+                            .borrow_mut()
+                            .with_reader_mut(|r| {
+                                let mut filled = false;
+                                keys.retain(|key| {
+                                    if reader_triggered.contains_key(key) {
+                                        return true;
+                                    }
+                                    if r.fill_if_known_empty(key) {
+                                        filled = true;
+                                        return false;
+                                    }
+                                    match r.unspill(key) {
+                                        Ok(hit) => {
+                                            filled |= hit;
+                                            !hit
+                                        }
+                                        Err(e) => {
+                                            warn!(log, "failed to read spilled key; replaying";
+                                                  "node" => node.id(), "err" => %e);
+                                            true
+                                        }
+                                    }
+                                });
+                                if filled {
+                                    r.writer_mut().unwrap().swap();
+                                }
+                            })
+                            .unwrap();
+
+                        // ensure that we haven't already requested a replay of this key, unless
+                        // that replay has been in flight for so long that it was probably lost
+                        let now = time::Instant::now();
+                        let triggered = self.reader_triggered.entry(node).or_default();
+                        keys.retain(|key| match triggered.get(key) {
+                            Some(at)
+                                if now.duration_since(*at) < crate::backlog::REPLAY_TIMEOUT =>
+                            {
+                                false
+                            }
+                            _ => {
+                                triggered.insert(key.clone(), now);
+                                true
+                            }
                         });
                         if !keys.is_empty() {
+                            *self.reader_replays.entry(node).or_default() += keys.len() as u64;
                             self.find_tags_and_replay(keys, &cols[..], node);
                         }
                         self.total_replay_time.stop();
@@ -1226,7 +2012,9 @@ impl Domain {
                             let fix = move |mut r: Vec<DataType>| -> Vec<DataType> {
                                 if let Some((start, ref added)) = added_cols {
                                     let rlen = r.len();
-                                    r.extend(added.iter().skip(rlen - start).cloned());
+                                    r.extend(
+                                        added.iter().skip(rlen.saturating_sub(start)).cloned(),
+                                    );
                                 } else if let Some(ref defaults) = default {
                                     let rlen = r.len();
                                     r.extend(defaults.iter().skip(rlen).cloned());
@@ -1237,61 +2025,98 @@ impl Domain {
                             let replay_tx_desc = self
                                 .channel_coordinator
                                 .builder_for(&(self.index, self.shard.unwrap_or(0)))
-                                .unwrap();
-
-                            thread::Builder::new()
-                                .name(format!(
-                                    "replay{}.{}",
-                                    self.nodes
-                                        .values()
-                                        .next()
-                                        .unwrap()
-                                        .borrow()
-                                        .domain()
-                                        .index(),
-                                    link.src
-                                ))
-                                .spawn(move || {
-                                    use itertools::Itertools;
+                                .unwrap()
+                                .prioritized();
+
+                            // the chunks may be processed in any order, since they are all part of
+                            // the same snapshot, and the target buffers every update that follows
+                            // it until it has seen the last one. so, we split the snapshot up
+                            // between a number of threads, and have whichever finishes last send
+                            // the piece that ends the replay.
+                            let chunks = (state.len() + BATCH_SIZE - 1) / BATCH_SIZE;
+                            let threads = cmp::max(1, cmp::min(self.replay_parallelism, chunks));
+                            let per_thread = (chunks + threads - 1) / threads * BATCH_SIZE;
+                            let mut state = state;
+                            let mut parts = Vec::with_capacity(threads);
+                            while state.len() > per_thread {
+                                let rest = state.split_off(per_thread);
+                                parts.push(mem::replace(&mut state, rest));
+                            }
+                            parts.push(state);
+
+                            // TODO: make async
+                            let chunked_replay_tx =
+                                Arc::new(Mutex::new((Some(replay_tx_desc), None)));
+                            let running = Arc::new(AtomicUsize::new(parts.len()));
+                            let domain = self.index.index();
+                            for (part, state) in parts.into_iter().enumerate() {
+                                let log = log.new(o!("part" => part));
+                                let fix = fix.clone();
+                                let chunked_replay_tx = chunked_replay_tx.clone();
+                                let running = running.clone();
+                                let throttle = self.replay_throttle.clone();
+                                thread::Builder::new()
+                                    .name(format!("replay{}.{}.{}", domain, link.src, part))
+                                    .spawn(move || {
+                                        use itertools::Itertools;
+
+                                        let send = |p: Box<Packet>| {
+                                            let mut tx = chunked_replay_tx.lock().unwrap();
+                                            let (ref mut desc, ref mut tx) = *tx;
+                                            tx.get_or_insert_with(|| {
+                                                desc.take().unwrap().build_sync().unwrap()
+                                            })
+                                            .send(p)
+                                        };
+
+                                        let start = time::Instant::now();
+                                        debug!(log, "starting state chunker"; "node" => %link.dst);
+
+                                        // process all records in state to completion within
+                                        // domain and then forward on tx (if there is one)
+                                        let iter = state.into_iter().chunks(BATCH_SIZE);
+                                        for (i, chunk) in iter.into_iter().enumerate() {
+                                            use std::iter::FromIterator;
+                                            let chunk = Records::from_iter(chunk.map(&fix));
+                                            let len = chunk.len();
+                                            let p = Box::new(Packet::ReplayPiece {
+                                                tag,
+                                                link, // to is overwritten by receiver
+                                                context: ReplayPieceContext::Regular {
+                                                    last: false,
+                                                },
+                                                data: chunk,
+                                            });
+
+                                            throttle.wait(len);
+                                            trace!(log, "sending batch"; "#" => i, "[]" => len);
+                                            if send(p).is_err() {
+                                                warn!(log, "replayer noticed domain shutdown");
+                                                return;
+                                            }
+                                        }
 
-                                    // TODO: make async
-                                    let mut chunked_replay_tx =
-                                        replay_tx_desc.build_sync().unwrap();
-
-                                    let start = time::Instant::now();
-                                    debug!(log, "starting state chunker"; "node" => %link.dst);
-
-                                    let iter = state.into_iter().chunks(BATCH_SIZE);
-                                    let mut iter = iter.into_iter().enumerate().peekable();
-
-                                    // process all records in state to completion within domain
-                                    // and then forward on tx (if there is one)
-                                    while let Some((i, chunk)) = iter.next() {
-                                        use std::iter::FromIterator;
-                                        let chunk = Records::from_iter(chunk.map(&fix));
-                                        let len = chunk.len();
-                                        let last = iter.peek().is_none();
-                                        let p = Box::new(Packet::ReplayPiece {
-                                            tag,
-                                            link, // to is overwritten by receiver
-                                            context: ReplayPieceContext::Regular { last },
-                                            data: chunk,
-                                        });
-
-                                        trace!(log, "sending batch"; "#" => i, "[]" => len);
-                                        if chunked_replay_tx.send(p).is_err() {
-                                            warn!(log, "replayer noticed domain shutdown");
-                                            break;
+                                        if running.fetch_sub(1, Ordering::AcqRel) == 1 {
+                                            // every other chunker has sent all its chunks
+                                            let p = Box::new(Packet::ReplayPiece {
+                                                tag,
+                                                link,
+                                                context: ReplayPieceContext::Regular { last: true },
+                                                data: Vec::<Record>::new().into(),
+                                            });
+                                            if send(p).is_err() {
+                                                warn!(log, "replayer noticed domain shutdown");
+                                            }
                                         }
-                                    }
 
-                                    debug!(log,
-                                       "state chunker finished";
-                                       "node" => %link.dst,
-                                       "μs" => start.elapsed().as_micros()
-                                    );
-                                })
-                                .unwrap();
+                                        debug!(log,
+                                           "state chunker finished";
+                                           "node" => %link.dst,
+                                           "μs" => start.elapsed().as_micros()
+                                        );
+                                    })
+                                    .unwrap();
+                            }
                         }
                         self.handle_replay(p, executor);
 
@@ -1314,12 +2139,8 @@ impl Domain {
                                 match (n.get_base(), &params.mode) {
                                     (Some(base), &DurabilityMode::DeleteOnExit)
                                     | (Some(base), &DurabilityMode::Permanent) => {
-                                        let base_name = format!(
-                                            "{}-{}-{}",
-                                            params.log_prefix,
-                                            n.name(),
-                                            self.shard.unwrap_or(0),
-                                        );
+                                        let base_name =
+                                            params.log_path(n.name(), self.shard.unwrap_or(0));
 
                                         Box::new(PersistentState::new(
                                             base_name,
@@ -1333,6 +2154,19 @@ impl Domain {
                             for idx in index {
                                 s.add_key(&idx[..], None);
                             }
+                            if let Some((txn, records)) = s.staged_transaction() {
+                                // the controller decides it once it knows how the transaction
+                                // ended
+                                self.staged.insert(
+                                    node,
+                                    StagedTransaction {
+                                        txn,
+                                        records,
+                                        label: None,
+                                        held_back: Vec::new(),
+                                    },
+                                );
+                            }
                             assert!(self.state.insert(node, s).is_none());
                         } else {
                             // NOTE: just because index_on is None does *not* mean we're not
@@ -1363,15 +2197,7 @@ impl Domain {
                             .unwrap();
                     }
                     Packet::GetStatistics => {
-                        let domain_stats = noria::debug::stats::DomainStats {
-                            total_time: self.total_time.num_nanoseconds(),
-                            total_ptime: self.total_ptime.num_nanoseconds(),
-                            total_replay_time: self.total_replay_time.num_nanoseconds(),
-                            total_forward_time: self.total_forward_time.num_nanoseconds(),
-                            wait_time: self.wait_time.num_nanoseconds(),
-                        };
-
-                        let node_stats = self
+                        let node_stats: HashMap<_, _> = self
                             .nodes
                             .values()
                             .filter_map(|nd| {
@@ -1381,16 +2207,16 @@ impl Domain {
 
                                 let time = self.process_times.num_nanoseconds(local_index);
                                 let ptime = self.process_ptimes.num_nanoseconds(local_index);
-                                let mem_size = if n.is_reader() {
-                                    let mut size = 0;
-                                    n.with_reader(|r| size = r.state_size().unwrap_or(0))
-                                        .unwrap();
-                                    size
+                                let (mem_size, rows) = if n.is_reader() {
+                                    n.with_reader(|r| {
+                                        (r.state_size().unwrap_or(0), r.state_rows().unwrap_or(0))
+                                    })
+                                    .unwrap()
                                 } else {
                                     self.state
                                         .get(local_index)
-                                        .map(|s| s.deep_size_of())
-                                        .unwrap_or(0)
+                                        .map(|s| (s.deep_size_of(), s.rows() as u64))
+                                        .unwrap_or((0, 0))
                                 };
 
                                 let mat_state = if !n.is_reader() {
@@ -1421,6 +2247,38 @@ impl Domain {
 
                                 let probe_result = if n.is_internal() {
                                     n.probe()
+                                } else if let Ok(Some((policy, evictions, re_misses))) =
+                                    n.with_reader(|r| r.eviction_stats())
+                                {
+                                    let mut probe = HashMap::new();
+                                    probe.insert("eviction policy".into(), format!("{:?}", policy));
+                                    probe.insert("evictions".into(), evictions.to_string());
+                                    probe.insert("re-misses".into(), re_misses.to_string());
+                                    probe.insert(
+                                        "replays".into(),
+                                        self.reader_replays
+                                            .get(local_index)
+                                            .copied()
+                                            .unwrap_or(0)
+                                            .to_string(),
+                                    );
+                                    if let Ok(Some((hits, misses))) =
+                                        n.with_reader(|r| r.spill_stats())
+                                    {
+                                        probe.insert("spill hits".into(), hits.to_string());
+                                        probe.insert("replay misses".into(), misses.to_string());
+                                    }
+                                    if let Ok(Some((hits, misses, invalidations))) =
+                                        n.with_reader(|r| r.negative_cache_stats())
+                                    {
+                                        probe.insert("negative hits".into(), hits.to_string());
+                                        probe.insert("negative misses".into(), misses.to_string());
+                                        probe.insert(
+                                            "negative invalidations".into(),
+                                            invalidations.to_string(),
+                                        );
+                                    }
+                                    probe
                                 } else {
                                     Default::default()
                                 };
@@ -1433,8 +2291,15 @@ impl Domain {
                                             process_time: time.unwrap(),
                                             process_ptime: ptime.unwrap(),
                                             mem_size,
+                                            rows,
                                             materialized: mat_state,
                                             probe_result,
+                                            propagation: self.propagation.get(local_index).cloned(),
+                                            operator: n.operator_stats(),
+                                            applied: n
+                                                .with_reader(|r| r.applied())
+                                                .ok()
+                                                .and_then(|a| a),
                                         },
                                     ))
                                 } else {
@@ -1443,14 +2308,98 @@ impl Domain {
                             })
                             .collect();
 
+                        let mut hot_keys: Vec<_> = self
+                            .nodes
+                            .values()
+                            .filter_map(|nd| {
+                                let n = &*nd.borrow();
+                                n.with_sharder(|s| s.hot_keys(n.global_addr(), HOT_KEYS))
+                            })
+                            .flatten()
+                            .collect();
+                        hot_keys.sort_by(|a, b| b.records.cmp(&a.records));
+                        hot_keys.truncate(HOT_KEYS);
+
+                        let shard_queues = self
+                            .nodes
+                            .values()
+                            .filter_map(|nd| {
+                                let n = &*nd.borrow();
+                                n.with_sharder(|s| s.queues(n.global_addr(), &*executor))
+                            })
+                            .flatten()
+                            .collect();
+
+                        let domain_stats = noria::debug::stats::DomainStats {
+                            total_time: self.total_time.num_nanoseconds(),
+                            total_ptime: self.total_ptime.num_nanoseconds(),
+                            total_replay_time: self.total_replay_time.num_nanoseconds(),
+                            total_forward_time: self.total_forward_time.num_nanoseconds(),
+                            wait_time: self.wait_time.num_nanoseconds(),
+                            mem_size: node_stats.values().map(|s| s.mem_size).sum(),
+                            rows: node_stats.values().map(|s| s.rows).sum(),
+                            hot_keys,
+                            shard_queues,
+                            cores: None,
+                            replay_batches: self.replay_batches,
+                            replayed_keys: self.replayed_keys,
+                            queued_replays: self.queued_replays,
+                            replay_queue_time: self.replay_queue_time.as_nanos() as u64,
+                            replay_queue_len: self.replay_request_queue.len(),
+                            memory: self.memory(&*executor),
+                        };
+
                         self.control_reply_tx
                             .send(ControlReplyPacket::Statistics(domain_stats, node_stats))
                             .unwrap();
                     }
+                    Packet::GetTrace { tag } => {
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::Trace(self.traces.events(tag)))
+                            .unwrap();
+                    }
+                    Packet::GetBookkeeping => {
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::Bookkeeping(self.bookkeeping()))
+                            .unwrap();
+                    }
                     Packet::UpdateStateSize => {
                         self.update_state_sizes();
                     }
+                    Packet::SetPacketLogging { enabled } => {
+                        info!(self.log, "packet logging"; "enabled" => enabled);
+                        self.log_packets.store(enabled, Ordering::Relaxed);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::SetSlowThresholds { packet, replay } => {
+                        info!(self.log, "slow thresholds"; "packet" => ?packet, "replay" => ?replay);
+                        self.slow_packet_threshold = packet;
+                        self.slow_replay_threshold = replay;
+                        if replay.is_none() {
+                            self.replays_started.clear();
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::SetReplayRate { rate } => {
+                        info!(self.log, "replay rate"; "rate" => ?rate);
+                        self.replay_throttle.set_rate(rate);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::SetPropagationSampling { rate } => {
+                        info!(self.log, "propagation sampling"; "rate" => rate);
+                        self.propagation_sampling = rate;
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
                     Packet::Quit => unreachable!("Quit messages are handled by event loop"),
+                    Packet::Ping(_) => unreachable!("pings are answered by the event loop"),
                     Packet::Spin => {
                         // spinning as instructed
                     }
@@ -1548,9 +2497,15 @@ impl Domain {
 
     fn seed_row<'a>(&self, source: LocalNodeIndex, row: Cow<'a, [DataType]>) -> Record {
         if let Some(&(start, ref defaults)) = self.ingress_inject.get(source) {
+            // rows that were materialized before a column was added have already been padded
             let mut v = Vec::with_capacity(start + defaults.len());
             v.extend(row.iter().cloned());
-            v.extend(defaults.iter().cloned());
+            v.extend(
+                defaults
+                    .iter()
+                    .skip(row.len().saturating_sub(start))
+                    .cloned(),
+            );
             return (v, true).into();
         }
 
@@ -1572,6 +2527,9 @@ impl Domain {
         single_shard: bool,
         ex: &mut dyn Executor,
     ) {
+        self.replay_batches += 1;
+        self.replayed_keys += keys.len() as u64;
+
         let (m, source, is_miss) = match self.replay_paths[&tag] {
             ReplayPath {
                 source: Some(source),
@@ -1685,23 +2643,34 @@ impl Domain {
             ..
         } = self.replay_paths[&tag]
         {
-            // maybe delay this seed request so that we can batch respond later?
-            // TODO
+            // delay this seed request so that we can batch respond later
             use std::collections::hash_map::Entry;
             let key = key.into_owned();
-            match self.buffered_replay_requests.entry((tag, requesting_shard)) {
+            let full = match self.buffered_replay_requests.entry((tag, requesting_shard)) {
                 Entry::Occupied(o) => {
                     assert!(!o.get().1.is_empty());
-                    o.into_mut().1.insert(key);
+                    let keys = &mut o.into_mut().1;
+                    keys.insert(key);
+                    keys.len() >= self.replay_batch_size
                 }
                 Entry::Vacant(v) => {
                     let mut ks = HashSet::new();
                     ks.insert(key);
+                    let full = ks.len() >= self.replay_batch_size;
                     v.insert((time::Instant::now(), ks, single_shard));
+                    full
                 }
-            }
+            };
 
             // TODO: if timer has expired, call seed_all(tag, _, executor) immediately
+            if full {
+                // no point in waiting for more keys
+                let (_, keys, single_shard) = self
+                    .buffered_replay_requests
+                    .remove(&(tag, requesting_shard))
+                    .unwrap();
+                self.seed_all(tag, requesting_shard, keys, single_shard, ex);
+            }
             return;
         }
 
@@ -1792,6 +2761,7 @@ impl Domain {
         let mut finished = None;
         let mut need_replay = Vec::new();
         let mut finished_partial = 0;
+        let mut filled_keys = None;
 
         // this loop is just here so we have a way of giving up the borrow of self.replay_paths
         #[allow(clippy::never_loop)]
@@ -1872,7 +2842,7 @@ impl Domain {
                                 });
                             } else if let Some(ref prev) = self.reader_triggered.get(dst) {
                                 // discard all the keys that we aren't waiting for
-                                for_keys.retain(|k| prev.contains(k));
+                                for_keys.retain(|k| prev.contains_key(k));
                             } else {
                                 // this packet contained no keys that we're waiting for, so it's
                                 // useless to us.
@@ -1980,6 +2950,9 @@ impl Domain {
                                     if let Some(wh) = r.writer_mut() {
                                         for key in backfill_keys.iter() {
                                             wh.mut_with_key(&key[..]).mark_filled();
+                                            // the replay already reflects any writes for the
+                                            // key that are held back for a transaction
+                                            wh.forget_held(&key[..]);
                                         }
                                     }
                                 })
@@ -2403,6 +3376,10 @@ impl Domain {
                             requesting_shard: _,
                         } => {
                             assert!(!ignore);
+                            if (dst_is_reader || dst_is_target) && !self.replays_started.is_empty()
+                            {
+                                filled_keys = Some(for_keys.clone());
+                            }
                             if dst_is_reader {
                                 if self.nodes[dst].borrow().beyond_mat_frontier() {
                                     // make sure we eventually evict these from here
@@ -2435,6 +3412,9 @@ impl Domain {
         if finished_partial != 0 {
             self.finished_partial_replay(tag, finished_partial);
         }
+        if let Some(keys) = filled_keys {
+            self.time_partial_replay(tag, &keys);
+        }
 
         for (node, while_replaying_key, miss_key, miss_cols, single_shard, requesting_shard, tag) in
             need_replay
@@ -2805,9 +3785,20 @@ impl Domain {
                         if n.is_dropped() {
                             break; // Node was dropped. Give up.
                         } else if n.is_reader() {
-                            let freed_now = n.with_reader_mut(|r| r.evict_random_keys(16)).unwrap();
+                            let triggered = self.reader_triggered.get(node);
+                            let (freed_now, evicted) = n
+                                .with_reader_mut(|r| {
+                                    r.evict_keys(16, |key| {
+                                        triggered.map(|t| t.contains_key(key)).unwrap_or(false)
+                                    })
+                                })
+                                .unwrap();
 
                             freed += freed_now;
+                            if evicted == 0 {
+                                // nothing left that we may evict, e.g., keys with replays in flight
+                                break;
+                            }
                             if n.with_reader(|r| r.is_empty()).unwrap() {
                                 trace!(
                                     self.log,
@@ -2906,6 +3897,23 @@ impl Domain {
         (self.index, self.shard.unwrap_or(0))
     }
 
+    /// The most packets that may wait to be sent to any one downstream domain shard before the
+    /// domain should stop taking in new work.
+    pub fn output_high_water(&self) -> usize {
+        self.output_high_water
+    }
+
+    /// What the domain should do with client writes while it is not taking in new work.
+    pub fn overload_policy(&self) -> OverloadPolicy {
+        self.overload_policy
+    }
+
+    /// Tell the domain how many packets are waiting to be sent to other domains, so that its
+    /// replays can hold off while those are backed up.
+    pub fn set_output_queued(&self, queued: usize) {
+        self.replay_throttle.set_queued(queued);
+    }
+
     pub fn booted(&mut self, addr: SocketAddr) {
         info!(self.log, "booted domain"; "nodes" => self.nodes.len());
         self.control_reply_tx
@@ -2913,21 +3921,200 @@ impl Domain {
             .unwrap();
     }
 
-    pub fn update_state_sizes(&mut self) {
-        let total: u64 = self
+    /// The base table in this domain that goes by the (qualified) name `name`, if any.
+    pub fn base_named(&self, name: &str) -> Option<LocalNodeIndex> {
+        self.nodes.values().find_map(|n| {
+            let n = n.borrow();
+            if n.is_base() && n.name() == name {
+                Some(n.local_addr())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The rows that each of the domain's materialized nodes holds, by the name of the node.
+    ///
+    /// Readers are included with the rows that are visible to reads. Rows are in no particular
+    /// order.
+    pub fn cloned_state(&self) -> Vec<(String, Vec<Vec<DataType>>)> {
+        let shard = self.shard.unwrap_or(0);
+        let readers = self.readers.lock().unwrap();
+        self.nodes
+            .values()
+            .filter_map(|n| {
+                let n = n.borrow();
+                let rows = if let Some(s) = self.state.get(n.local_addr()) {
+                    s.cloned_records()
+                } else if n.is_reader() {
+                    readers.get(&(n.global_addr(), shard))?.cloned_records()
+                } else {
+                    return None;
+                };
+                Some((n.name().to_owned(), rows))
+            })
+            .collect()
+    }
+
+    /// The size of each node's materialized state, and whether that state is partial.
+    /// A snapshot of the domain's metrics, for its worker to export.
+    pub fn metrics(&self) -> DomainMetrics {
+        let shard = self.shard.unwrap_or(0);
+        let mut base_labels = Vec::new();
+        let mut reader_labels = Vec::new();
+        for n in self.nodes.values() {
+            let n = n.borrow();
+            if let Some(b) = n.get_base() {
+                base_labels.push(LabelMetric {
+                    node: n.name().to_owned(),
+                    base: n.global_addr(),
+                    base_shard: shard,
+                    seq: b.last_label(),
+                });
+            } else if let Ok(Some(frontier)) = n.with_reader(|r| r.frontier()) {
+                reader_labels.extend(frontier.into_iter().map(|((base, base_shard), seq)| {
+                    LabelMetric {
+                        node: n.name().to_owned(),
+                        base,
+                        base_shard,
+                        seq,
+                    }
+                }));
+            }
+        }
+
+        let state_sizes = self
+            .node_state_sizes()
+            .into_iter()
+            .map(|(node, size, _)| (self.nodes[node].borrow().name().to_owned(), size))
+            .collect();
+
+        let reader_propagation = self
+            .propagation
+            .iter()
+            .map(|(node, h)| (self.nodes[node].borrow().name().to_owned(), h.clone()))
+            .collect();
+
+        DomainMetrics {
+            packets: self.packets,
+            group_commits: self.group_commit_queues.flushes(),
+            reader_replays: self.reader_replays.values().sum(),
+            queued_replays: self.queued_replays,
+            replay_queue_time: self.replay_queue_time,
+            replay_queue_len: self.replay_request_queue.len(),
+            replay_time: time::Duration::from_nanos(self.total_replay_time.num_nanoseconds()),
+            output_queue: 0,
+            state_sizes,
+            base_labels,
+            reader_labels,
+            reader_propagation,
+        }
+    }
+
+    /// What the memory of this domain is spent on.
+    fn memory(&self, executor: &dyn Executor) -> MemoryBreakdown {
+        let usage = |(rows, bytes): (usize, usize)| MemoryUsage {
+            rows: rows as u64,
+            bytes: bytes as u64,
+        };
+
+        let mut memory = MemoryBreakdown::default();
+        for n in self.nodes.values() {
+            let n = n.borrow();
+            if let Ok((rows, bytes)) =
+                n.with_reader(|r| (r.state_rows().unwrap_or(0), r.state_size().unwrap_or(0)))
+            {
+                memory.reader_state.merge(&MemoryUsage { rows, bytes });
+            }
+            if let Some(held) = n.with_egress(|e| e.held_records()) {
+                memory.egress_buffers.merge(&usage(held));
+            }
+        }
+        for s in self.state.values() {
+            memory.operator_state.merge(&MemoryUsage {
+                rows: s.rows() as u64,
+                bytes: s.deep_size_of(),
+            });
+        }
+        memory.group_commit = usage(self.group_commit_queues.pending());
+        memory.output_queues = usage(executor.queued_records());
+        memory
+    }
+
+    /// What each of our nodes keeps track of about the updates flowing through it.
+    fn bookkeeping(&self) -> DomainBookkeeping {
+        let nodes = self
             .nodes
+            .iter()
+            .map(|(local, n)| {
+                let n = n.borrow();
+                let shard = self.shard.unwrap_or(0);
+                let sent = n
+                    .get_base()
+                    .map(|b| LabelEntry {
+                        base: n.global_addr(),
+                        shard,
+                        seq: b.last_label(),
+                    })
+                    .into_iter()
+                    .collect();
+                let received = n
+                    .with_reader(|r| r.frontier())
+                    .ok()
+                    .and_then(|f| f)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|((base, shard), seq)| LabelEntry { base, shard, seq })
+                    .collect();
+                let (mut buffered, mut buffered_bytes) =
+                    n.with_egress(|e| e.held()).unwrap_or((0, 0));
+
+                let awaiting_replay = match self.mode {
+                    DomainMode::Replaying {
+                        to,
+                        buffered: ref waiting,
+                        ..
+                    } if to == local => {
+                        buffered += waiting.len();
+                        buffered_bytes += waiting.iter().map(|m| m.data_size()).sum::<usize>();
+                        true
+                    }
+                    _ => false,
+                };
+
+                NodeBookkeeping {
+                    name: n.name().to_owned(),
+                    node: n.global_addr(),
+                    sent,
+                    received,
+                    buffered,
+                    buffered_bytes,
+                    awaiting_replay,
+                }
+            })
+            .collect();
+
+        DomainBookkeeping {
+            domain: self.index,
+            shard: self.shard.unwrap_or(0),
+            nodes,
+        }
+    }
+
+    fn node_state_sizes(&self) -> Vec<(LocalNodeIndex, usize, bool)> {
+        self.nodes
             .values()
-            .map(|nd| {
+            .filter_map(|nd| {
                 let n = &*nd.borrow();
                 let local_index = n.local_addr();
 
                 if n.is_reader() {
                     // We are a reader, which has its own kind of state
-                    let mut size = 0;
+                    let mut size = None;
                     n.with_reader(|r| {
-                        if r.is_partial() {
-                            size = r.state_size().unwrap_or(0)
-                        }
+                        size = r
+                            .state_size()
+                            .map(|s| (local_index, s as usize, r.is_partial()))
                     })
                     .unwrap();
                     size
@@ -2935,21 +4122,425 @@ impl Domain {
                     // Not a reader, state is with domain
                     self.state
                         .get(local_index)
-                        .filter(|state| state.is_partial())
-                        .map(|s| s.deep_size_of())
-                        .unwrap_or(0)
+                        .map(|s| (local_index, s.deep_size_of() as usize, s.is_partial()))
                 }
             })
+            .collect()
+    }
+
+    pub fn update_state_sizes(&mut self) {
+        let total: usize = self
+            .node_state_sizes()
+            .into_iter()
+            .filter(|&(_, _, partial)| partial)
+            .map(|(_, size, _)| size)
             .sum();
 
-        self.state_size.store(total as usize, Ordering::Release);
+        self.state_size.store(total, Ordering::Release);
         // no response sent, as worker will read the atomic
     }
 
+    /// Evict keys from the largest partial states until the domain's state fits in `budget`
+    /// bytes.
+    ///
+    /// Full state cannot be evicted. If it does not fit in the budget by itself, we log the size
+    /// of each node's full state instead, once each time the domain goes over budget.
+    pub fn enforce_memory_budget(&mut self, budget: usize, ex: &mut dyn Executor) {
+        let sizes = self.node_state_sizes();
+        let total: usize = sizes.iter().map(|&(_, size, _)| size).sum();
+        if total <= budget {
+            self.over_memory_budget = false;
+            return;
+        }
+
+        let partial: usize = sizes
+            .iter()
+            .filter(|&&(_, _, partial)| partial)
+            .map(|&(_, size, _)| size)
+            .sum();
+        if partial > 0 {
+            let evict = cmp::min(total - budget, partial);
+            debug!(self.log, "state exceeds memory budget; evicting";
+                   "size" => total, "budget" => budget, "evict" => evict);
+            self.handle_eviction(
+                Box::new(Packet::Evict {
+                    node: None,
+                    num_bytes: evict,
+                }),
+                ex,
+            );
+        }
+
+        let full = total - partial;
+        if full > budget && !self.over_memory_budget {
+            let breakdown: Vec<_> = sizes
+                .iter()
+                .filter(|&&(_, _, partial)| !partial)
+                .map(|&(node, size, _)| {
+                    let n = self.nodes[node].borrow();
+                    format!("{} ({}): {} bytes", n.name(), n.global_addr().index(), size)
+                })
+                .collect();
+            warn!(self.log, "full state that cannot be evicted exceeds memory budget";
+                  "full" => full, "budget" => budget, "nodes" => breakdown.join(", "));
+        }
+        self.over_memory_budget = full > budget;
+    }
+
+    /// Handle the packet that group commit merged a group of client writes into.
+    fn handle_committed(&mut self, m: Box<Packet>, executor: &mut dyn Executor) {
+        for (tag, base, into, writes) in self.group_commit_queues.take_merged_traces() {
+            let base = self.nodes[base].borrow().global_addr();
+            self.traces
+                .record(tag, base, PacketEvent::Merged { into, writes });
+        }
+        self.handle(m, executor, true);
+    }
+
+    /// Commit the client writes waiting for group commit, and send on all the updates that the
+    /// domain's egresses are holding back, so that nothing is left behind in the domain.
+    ///
+    /// Returns the latest batch from each base table shard that has been sent along each of the
+    /// edges that leave the domain.
+    pub fn drain(&mut self, executor: &mut dyn Executor) -> Vec<EdgeFrontier> {
+        for m in self.group_commit_queues.flush_all() {
+            self.handle_committed(m, executor);
+        }
+
+        let mut frontiers = Vec::new();
+        for n in self.nodes.values() {
+            let mut n = n.borrow_mut();
+            if n.is_egress() {
+                let node = n.global_addr();
+                n.with_egress_mut(|e| {
+                    e.flush_all(executor);
+                    frontiers.extend(e.frontiers(node));
+                });
+            }
+        }
+        frontiers
+    }
+
+    /// Fill in the auto-timestamp columns of the writes in an incoming packet.
+    fn stamp_writes(&self, packet: Box<Packet>) -> Box<Packet> {
+        let stamps = match *packet {
+            Packet::Input { .. } => {
+                let n = self.nodes[packet.dst()].borrow();
+                n.get_base()
+                    .map(|b| b.has_auto_timestamps())
+                    .unwrap_or(false)
+            }
+            _ => false,
+        };
+        if !stamps {
+            return packet;
+        }
+
+        if let Packet::Input {
+            inner,
+            src,
+            senders,
+        } = *packet
+        {
+            let mut input = unsafe { inner.take() };
+            let now = chrono::Local::now().naive_local();
+            self.nodes[input.dst]
+                .borrow()
+                .stamp_writes(&mut input.data, now);
+            Box::new(Packet::Input {
+                inner: LocalOrNot::new(input),
+                src,
+                senders,
+            })
+        } else {
+            unreachable!()
+        }
+    }
+
+    /// Turn away a write to a base that carries a blob larger than the base allows, so that it is
+    /// never queued for group commit.
+    ///
+    /// The write is rejected as a whole, and every one of its operations is reported as invalid.
+    fn reject_oversized(
+        &self,
+        packet: Box<Packet>,
+        executor: &mut dyn Executor,
+    ) -> Option<Box<Packet>> {
+        let oversized = match *packet {
+            Packet::Input { ref inner, .. } => {
+                let input = unsafe { inner.deref() };
+                let n = self.nodes[input.dst].borrow();
+                n.is_base() && !n.oversized_writes(&input.data).is_empty()
+            }
+            _ => false,
+        };
+        if !oversized {
+            return Some(packet);
+        }
+
+        if let Packet::Input { inner, src, .. } = *packet {
+            let input = unsafe { inner.take() };
+            warn!(self.log, "rejecting write with oversized values";
+                  "node" => input.dst.id(),
+                  "writes" => input.data.len());
+            if let Some(src) = src {
+                executor.ack(
+                    src,
+                    WriteAck {
+                        invalid: (0..input.data.len()).collect(),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+        None
+    }
+
+    /// Stamp a share of incoming client writes with the time they arrived, so that the readers
+    /// they reach can record how long they took to get there.
+    fn sample_origin(&self, packet: Box<Packet>) -> Box<Packet> {
+        let is_input = match *packet {
+            Packet::Input { .. } => true,
+            _ => false,
+        };
+        if !is_input
+            || self.propagation_sampling <= 0.0
+            || rand::random::<f64>() >= self.propagation_sampling
+        {
+            return packet;
+        }
+
+        if let Packet::Input {
+            inner,
+            src,
+            senders,
+        } = *packet
+        {
+            let mut input = unsafe { inner.take() };
+            input.origin = Some(unix_nanos());
+            Box::new(Packet::Input {
+                inner: LocalOrNot::new(input),
+                src,
+                senders,
+            })
+        } else {
+            unreachable!()
+        }
+    }
+
+    /// Record the span of a client write that is part of a distributed trace from when the client
+    /// sent it until it arrived, and make the write part of that span from then on.
+    fn trace_receipt(&self, packet: Box<Packet>) -> Box<Packet> {
+        let is_input = match *packet {
+            Packet::Input { .. } => true,
+            _ => false,
+        };
+        let (parent, sent) = match packet.context() {
+            Some(context) if is_input => context,
+            _ => return packet,
+        };
+
+        if let Packet::Input {
+            inner,
+            src,
+            senders,
+        } = *packet
+        {
+            let mut input = unsafe { inner.take() };
+            let table = self.nodes[input.dst].borrow().name().to_owned();
+            input.context = Some(self.telemetry.span(
+                "noria.client_send",
+                parent,
+                sent,
+                unix_nanos(),
+                &[],
+                vec![KeyValue::new("noria.table", table)],
+            ));
+            Box::new(Packet::Input {
+                inner: LocalOrNot::new(input),
+                src,
+                senders,
+            })
+        } else {
+            unreachable!()
+        }
+    }
+
+    /// Check a base's part of a transaction, and set it aside until the transaction is decided.
+    ///
+    /// A part of a transaction the base was not told to accept, or that comes while the base
+    /// already has one set aside, is turned away.
+    fn stage_transaction(&mut self, m: Box<Packet>, executor: &mut dyn Executor) {
+        let node = m.dst();
+        let txn = m.transaction().unwrap();
+        if self.staged.contains_key(node) || !self.open_transactions.contains(&(node, txn)) {
+            warn!(self.log, "turning away part of transaction";
+                  "node" => node.id(),
+                  "timestamp" => txn);
+            if let Packet::Input { inner, src, .. } = *m {
+                let n = unsafe { inner.deref() }.data.len();
+                if let Some(src) = src {
+                    executor.ack(
+                        src,
+                        WriteAck {
+                            overloaded: (0..n).collect(),
+                            ..Default::default()
+                        },
+                    );
+                }
+            }
+            return;
+        }
+
+        let staged = self.nodes[node].borrow_mut().stage_transaction(
+            m,
+            &mut self.state,
+            self.shard,
+            executor,
+        );
+        if let Some((records, label)) = staged {
+            self.staged.insert(
+                node,
+                StagedTransaction {
+                    txn,
+                    records,
+                    label: Some(label),
+                    held_back: Vec::new(),
+                },
+            );
+        }
+    }
+
+    /// Apply or drop a base's staged part of a transaction, and then the writes to the base that
+    /// were held back meanwhile.
+    fn decide_transaction(
+        &mut self,
+        node: LocalNodeIndex,
+        staged: StagedTransaction,
+        commit: bool,
+        executor: &mut dyn Executor,
+    ) {
+        let StagedTransaction {
+            txn,
+            records,
+            label,
+            held_back,
+        } = staged;
+        if commit {
+            let m = self.nodes[node].borrow_mut().commit_transaction(
+                txn,
+                records,
+                label,
+                &mut self.state,
+                self.shard,
+            );
+            self.handle(Box::new(m), executor, true);
+        } else if let Some(s) = self.state.get_mut(node) {
+            s.unstage_transaction();
+            s.process_records(&mut Records::default(), None);
+        }
+        for m in held_back {
+            self.handle(m, executor, true);
+        }
+    }
+
+    /// Send an empty update from every base that has not applied a batch of writes for a while,
+    /// so that the readers downstream of it learn that they are up to date with it.
+    fn tick_idle_bases(&mut self, executor: &mut dyn Executor) {
+        let interval = match self.freshness_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        let now = time::Instant::now();
+        let bases: Vec<_> = self
+            .nodes
+            .iter()
+            .filter_map(|(node, n)| n.borrow().get_base().map(|b| (node, b.last_label())))
+            .collect();
+        for (node, label) in bases {
+            if self.bulk_loads.contains_key(node) || self.staged.contains_key(node) {
+                continue;
+            }
+            match self.idle_bases.get(node) {
+                Some(&(last, _)) if last != label => {
+                    self.idle_bases.insert(node, (label, now));
+                    continue;
+                }
+                Some(&(_, since)) if now.duration_since(since) < interval => continue,
+                _ => {}
+            }
+
+            let mut m = self.nodes[node]
+                .borrow_mut()
+                .base_update(Default::default(), self.shard);
+            if let Packet::Message { ref mut label, .. } = m {
+                // carried all the way to the readers even though it is empty
+                label.tracked = true;
+            }
+            self.handle(Box::new(m), executor, true);
+            let label = self.nodes[node].borrow().get_base().unwrap().last_label();
+            self.idle_bases.insert(node, (label, now));
+        }
+    }
+
+    /// Remove expired rows from every base whose next TTL scan is due.
+    ///
+    /// Each due base has at most one chunk of rows removed. If there may be more, its next scan
+    /// happens right away, so that large expiries are spread over several polls.
+    fn expire_rows(&mut self, executor: &mut dyn Executor) {
+        let now = time::Instant::now();
+        let due: Vec<_> = self
+            .ttl_scans
+            .iter()
+            .filter(|&(node, &next)| {
+                next <= now
+                    && !self.bulk_loads.contains_key(node)
+                    && !self.staged.contains_key(node)
+            })
+            .map(|(node, _)| node)
+            .collect();
+        if due.is_empty() {
+            return;
+        }
+
+        let wall = self.clock.now();
+        for node in due {
+            let (rs, ttl) = {
+                let mut n = self.nodes[node].borrow_mut();
+                let rs = n.expire_rows(wall, &mut self.state);
+                (rs, n.get_base().unwrap().ttl().unwrap().clone())
+            };
+
+            let next = if rs.len() == ttl.chunk {
+                now
+            } else {
+                now + ttl.scan_every
+            };
+            self.ttl_scans.insert(node, next);
+
+            if !rs.is_empty() {
+                debug!(self.log, "expiring rows"; "node" => node.id(), "rows" => rs.len());
+                let m = self.nodes[node].borrow_mut().base_update(rs, self.shard);
+                self.handle(Box::new(m), executor, true);
+            }
+        }
+    }
+
     pub fn on_event(&mut self, executor: &mut dyn Executor, event: PollEvent) -> ProcessResult {
         if self.wait_time.is_running() {
             self.wait_time.stop();
         }
+        if let Some(ref mut capture) = self.capture {
+            let captured = match event {
+                // only asks when we next have something to do, which changes nothing
+                PollEvent::ResumePolling => Ok(()),
+                ref event => capture.record(event),
+            };
+            if let Err(e) = captured {
+                warn!(self.log, "cannot capture events; no longer capturing"; "err" => %e);
+                self.capture = None;
+            }
+        }
         //self.total_time.start();
         //self.total_ptime.start();
         let res = match event {
@@ -2975,39 +4566,98 @@ impl Domain {
                     }
                 });
 
-                let mut timeout = opt1.or(opt2).or(opt3);
+                let opt4 = self
+                    .ttl_scans
+                    .values()
+                    .map(|&next| {
+                        if next > now {
+                            next - now
+                        } else {
+                            time::Duration::from_millis(0)
+                        }
+                    })
+                    .min();
+
+                let opt5 = self
+                    .nodes
+                    .values()
+                    .filter_map(|n| {
+                        let n = n.borrow();
+                        n.with_egress(|e| e.next_flush(now)).flatten()
+                    })
+                    .min();
+
+                let mut timeout = opt1.or(opt2).or(opt3).or(opt4).or(opt5);
                 if let Some(opt2) = opt2 {
                     timeout = Some(std::cmp::min(timeout.unwrap(), opt2));
                 }
                 if let Some(opt3) = opt3 {
                     timeout = Some(std::cmp::min(timeout.unwrap(), opt3));
                 }
+                if let Some(opt4) = opt4 {
+                    timeout = Some(std::cmp::min(timeout.unwrap(), opt4));
+                }
+                if let Some(opt5) = opt5 {
+                    timeout = Some(std::cmp::min(timeout.unwrap(), opt5));
+                }
+                if let Some(interval) = self.freshness_interval {
+                    if self.nodes.values().any(|n| n.borrow().is_base()) {
+                        timeout = Some(timeout.map_or(interval, |t| std::cmp::min(t, interval)));
+                    }
+                }
                 ProcessResult::KeepPolling(timeout)
             }
             PollEvent::Process(packet) => {
                 if let Packet::Quit = *packet {
                     return ProcessResult::StopPolling;
                 }
+                self.packets += 1;
+
+                // writes that are too large never make it into a group commit queue, and
+                // timestamps are assigned before group commit, so that they are part of the writes
+                // from then on.
+                let packet = match self.reject_oversized(packet, executor) {
+                    Some(packet) => self.stamp_writes(packet),
+                    None => return ProcessResult::Processed,
+                };
+                let packet = self.sample_origin(packet);
+                let packet = self.trace_receipt(packet);
 
-                // TODO: Initialize tracer here, and when flushing group commit
-                // queue.
                 if self.group_commit_queues.should_append(&packet, &self.nodes) {
-                    if let Some(packet) = self.group_commit_queues.append(packet) {
-                        self.handle(packet, executor, true);
+                    for m in self.group_commit_queues.append(packet) {
+                        self.handle_committed(m, executor);
                     }
                 } else {
+                    if let Packet::Input { .. } = *packet {
+                        // writes to the base that arrived before this one go first
+                        for m in self.group_commit_queues.flush(packet.dst()) {
+                            self.handle_committed(m, executor);
+                        }
+                    }
                     self.handle(packet, executor, true);
                 }
 
-                while let Some(m) = self.group_commit_queues.flush_if_necessary() {
-                    self.handle(m, executor, true);
+                for m in self.group_commit_queues.flush_if_necessary() {
+                    self.handle_committed(m, executor);
                 }
+                self.expire_rows(executor);
+                self.tick_idle_bases(executor);
 
                 ProcessResult::Processed
             }
             PollEvent::Timeout => {
-                while let Some(m) = self.group_commit_queues.flush_if_necessary() {
-                    self.handle(m, executor, true);
+                for m in self.group_commit_queues.flush_if_necessary() {
+                    self.handle_committed(m, executor);
+                }
+                self.expire_rows(executor);
+                self.tick_idle_bases(executor);
+
+                let now = time::Instant::now();
+                for n in self.nodes.values() {
+                    let mut n = n.borrow_mut();
+                    if n.is_egress() {
+                        n.with_egress_mut(|e| e.flush_expired(now, executor));
+                    }
                 }
 
                 if !self.buffered_replay_requests.is_empty() || !self.timed_purges.is_empty() {
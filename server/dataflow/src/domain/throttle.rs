@@ -0,0 +1,74 @@
+use noria::ReplayRate;
+use std::cmp;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time;
+
+/// The longest a replay waits for a domain's outboxes to drain before it sends its next chunk
+/// anyway, so that a domain that never catches up cannot hold a migration up forever.
+const MAX_BACKOFF: time::Duration = time::Duration::from_secs(1);
+
+/// Paces the threads that replay snapshots of a domain's state, so that a large replay does not
+/// crowd out the regular updates that the domain sends to the same downstream domains.
+///
+/// Replays are held to the domain's replay rate, and additionally wait while more than
+/// `backoff_depth` packets are queued for other domains.
+pub(crate) struct ReplayThrottle {
+    rate: Mutex<ReplayRate>,
+    backoff_depth: usize,
+    queued: AtomicUsize,
+    /// When the next chunk may be sent by any of the domain's replays.
+    next: Mutex<time::Instant>,
+}
+
+impl ReplayThrottle {
+    pub(crate) fn new(rate: ReplayRate, backoff_depth: usize) -> Self {
+        ReplayThrottle {
+            rate: Mutex::new(rate),
+            backoff_depth,
+            queued: AtomicUsize::new(0),
+            next: Mutex::new(time::Instant::now()),
+        }
+    }
+
+    /// Change the replay rate, which also applies to replays that are underway.
+    pub(crate) fn set_rate(&self, rate: ReplayRate) {
+        *self.rate.lock().unwrap() = rate;
+    }
+
+    /// Record how many packets the domain has waiting for other domains.
+    pub(crate) fn set_queued(&self, queued: usize) {
+        self.queued.store(queued, Ordering::Relaxed);
+    }
+
+    /// Block until a chunk of `records` replayed records may be sent.
+    pub(crate) fn wait(&self, records: usize) {
+        let mut backoff = time::Duration::from_millis(1);
+        let mut waited = time::Duration::from_secs(0);
+        while self.backoff_depth != 0
+            && self.queued.load(Ordering::Relaxed) > self.backoff_depth
+            && waited < MAX_BACKOFF
+        {
+            thread::sleep(backoff);
+            waited += backoff;
+            backoff = cmp::min(backoff * 2, MAX_BACKOFF / 10);
+        }
+
+        let cost = match *self.rate.lock().unwrap() {
+            ReplayRate::Unlimited => return,
+            ReplayRate::RecordsPerSecond(r) => records as f64 / r as f64,
+            ReplayRate::PacketsPerSecond(p) => 1.0 / p as f64,
+        };
+        let at = {
+            let mut next = self.next.lock().unwrap();
+            let at = cmp::max(*next, time::Instant::now());
+            *next = at + time::Duration::from_secs_f64(cost);
+            at
+        };
+        let now = time::Instant::now();
+        if at > now {
+            thread::sleep(at - now);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use crate::prelude::*;
+use crate::Telemetry;
 use noria::internal::LocalOrNot;
+use opentelemetry::KeyValue;
 use std::time;
 
 pub struct GroupCommitQueueSet {
@@ -7,52 +9,128 @@ pub struct GroupCommitQueueSet {
     #[allow(clippy::vec_box)]
     pending_packets: Map<(time::Instant, Vec<Box<Packet>>)>,
     params: PersistenceParameters,
+    /// How many groups of packets have been flushed.
+    flushes: u64,
+    /// The traced writes in the groups flushed since they were last taken, along with the base
+    /// they were written to, the trace tag of their group, and the number of writes in the group.
+    merged_traces: Vec<(u64, LocalNodeIndex, u64, usize)>,
+    telemetry: Telemetry,
 }
 
 impl GroupCommitQueueSet {
     /// Create a new `GroupCommitQueue`.
-    pub fn new(params: &PersistenceParameters) -> Self {
+    pub fn new(params: &PersistenceParameters, telemetry: Telemetry) -> Self {
         Self {
             pending_packets: Map::default(),
             params: params.clone(),
+            flushes: 0,
+            merged_traces: Vec::new(),
+            telemetry,
         }
     }
 
     /// Returns whether the given packet should be persisted.
+    ///
+    /// The batch of a transaction is committed on its own rather than merged with other writes,
+    /// so that the readers waiting for it can tell it apart.
     pub fn should_append(&self, p: &Packet, nodes: &DomainNodes) -> bool {
         if let Packet::Input { .. } = *p {
             assert!(nodes[p.dst()].borrow().is_base());
-            true
+            // a write that may be a retry has to be checked against the writes the base has
+            // applied on its own
+            p.transaction().is_none() && p.operation().is_none()
         } else {
             false
         }
     }
 
-    /// Find the first queue that has timed out waiting for more packets, and flush it to disk.
-    pub fn flush_if_necessary(&mut self) -> Option<Box<Packet>> {
+    /// Merge the packets waiting to be persisted for `node`, however long they have waited.
+    #[allow(clippy::vec_box)]
+    pub fn flush(&mut self, node: LocalNodeIndex) -> Vec<Box<Packet>> {
+        if self
+            .pending_packets
+            .get(node)
+            .map_or(true, |(_, ps)| ps.is_empty())
+        {
+            return Vec::new();
+        }
+        self.flush_internal(node)
+    }
+
+    /// Flush every queue that has timed out waiting for more packets.
+    #[allow(clippy::vec_box)]
+    pub fn flush_if_necessary(&mut self) -> Vec<Box<Packet>> {
         let now = time::Instant::now();
         let to = self.params.flush_timeout;
-        let node = self
+        let nodes: Vec<_> = self
             .pending_packets
             .iter()
-            .find(|(_, &(first, ref ps))| now.duration_since(first) >= to && !ps.is_empty())
-            .map(|(n, _)| n);
+            .filter(|(_, &(first, ref ps))| now.duration_since(first) >= to && !ps.is_empty())
+            .map(|(n, _)| n)
+            .collect();
+        nodes
+            .into_iter()
+            .flat_map(|node| self.flush_internal(node))
+            .collect()
+    }
 
-        if let Some(node) = node {
-            self.flush_internal(node)
-        } else {
-            None
-        }
+    /// Flush every queue that has packets waiting, however long they have waited.
+    #[allow(clippy::vec_box)]
+    pub fn flush_all(&mut self) -> Vec<Box<Packet>> {
+        let nodes: Vec<_> = self
+            .pending_packets
+            .iter()
+            .filter(|(_, (_, ps))| !ps.is_empty())
+            .map(|(n, _)| n)
+            .collect();
+        nodes
+            .into_iter()
+            .flat_map(|node| self.flush_internal(node))
+            .collect()
     }
 
     /// Merge any pending packets.
-    fn flush_internal(&mut self, node: LocalNodeIndex) -> Option<Box<Packet>> {
-        Self::merge_packets(&mut self.pending_packets[node].1)
+    #[allow(clippy::vec_box)]
+    fn flush_internal(&mut self, node: LocalNodeIndex) -> Vec<Box<Packet>> {
+        let (since, ref mut packets) = self.pending_packets[node];
+        let merged = Self::merge_packets(
+            packets,
+            since,
+            (self.params.max_group_records, self.params.max_group_bytes),
+            &mut self.merged_traces,
+            &self.telemetry,
+        );
+        self.flushes += merged.len() as u64;
+        merged
+    }
+
+    /// How many groups of packets have been flushed.
+    pub fn flushes(&self) -> u64 {
+        self.flushes
+    }
+
+    /// How many table operations are waiting to be committed, and roughly how many bytes they
+    /// take up.
+    pub fn pending(&self) -> (usize, usize) {
+        self.pending_packets
+            .values()
+            .flat_map(|(_, ps)| ps)
+            .fold((0, 0), |(ops, bytes), p| {
+                (ops + p.records(), bytes + p.data_size())
+            })
+    }
+
+    /// Take the traced writes in the groups flushed since this was last called, along with the
+    /// base they were written to, the trace tag of their group, and the number of writes in the
+    /// group.
+    pub fn take_merged_traces(&mut self) -> Vec<(u64, LocalNodeIndex, u64, usize)> {
+        std::mem::replace(&mut self.merged_traces, Vec::new())
     }
 
-    /// Add a new packet to be persisted, and if this triggered a flush return an iterator over the
-    /// packets that were written.
-    pub fn append(&mut self, p: Box<Packet>) -> Option<Box<Packet>> {
+    /// Add a new packet to be persisted, and if this triggered a flush return the packets that
+    /// were written, in the order they should be handled.
+    #[allow(clippy::vec_box)]
+    pub fn append(&mut self, p: Box<Packet>) -> Vec<Box<Packet>> {
         let node = p.dst();
         let pp = self
             .pending_packets
@@ -67,7 +145,7 @@ impl GroupCommitQueueSet {
         if pp.0.elapsed() >= self.params.flush_timeout {
             self.flush_internal(node)
         } else {
-            None
+            Vec::new()
         }
     }
 
@@ -85,7 +163,12 @@ impl GroupCommitQueueSet {
             .min()
     }
 
-    fn merge_committed_packets<I>(packets: I) -> Option<Box<Packet>>
+    fn merge_committed_packets<I>(
+        packets: I,
+        since: time::Instant,
+        merged_traces: &mut Vec<(u64, LocalNodeIndex, u64, usize)>,
+        telemetry: &Telemetry,
+    ) -> Option<Box<Packet>>
     where
         I: Iterator<Item = Box<Packet>>,
     {
@@ -93,6 +176,11 @@ impl GroupCommitQueueSet {
         let merged_dst = packets.peek().as_mut().unwrap().dst();
 
         let mut all_senders = vec![];
+        let mut merged_tracked = false;
+        let mut traces = vec![];
+        let mut contexts = vec![];
+        let mut merged_origin = None;
+        let mut writes = 0;
         let merged_data = packets.fold(Vec::new(), |mut acc, p| {
             match *p {
                 Packet::Input {
@@ -100,38 +188,212 @@ impl GroupCommitQueueSet {
                     src,
                     senders,
                 } => {
-                    let Input { dst, data } = unsafe { inner.take() };
+                    let Input {
+                        dst,
+                        data,
+                        tracked,
+                        trace,
+                        origin,
+                        txn,
+                        op,
+                        context,
+                        sent: _,
+                    } = unsafe { inner.take() };
 
                     assert_eq!(senders.len(), 0);
+                    assert!(txn.is_none());
+                    assert!(op.is_none());
                     assert_eq!(merged_dst, dst);
-                    acc.extend(data);
-
                     if let Some(src) = src {
-                        all_senders.push(src);
+                        all_senders.push((src, data.len()));
                     }
+                    // operations must stay in arrival order, since the base applies operations on
+                    // the same key (such as a conditional update after a write) in that order.
+                    acc.extend(data);
+                    merged_tracked |= tracked;
+                    traces.extend(trace);
+                    contexts.extend(context);
+                    // the group reaches readers no earlier than its oldest write, so that is
+                    // the one whose latency it measures.
+                    merged_origin = match (merged_origin, origin) {
+                        (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+                        (a, b) => a.or(b),
+                    };
+                    writes += 1;
                 }
                 _ => unreachable!(),
             }
             acc
         });
 
+        // the group can only carry one trace tag, so the events of the other traced writes in it
+        // are recorded under that of the first.
+        let merged_trace = traces.first().cloned();
+        merged_traces.extend(
+            traces
+                .into_iter()
+                .map(|t| (t, merged_dst, merged_trace.unwrap(), writes)),
+        );
+        // likewise, the group's update is part of the distributed trace of the first write in it
+        // that is part of one, in a span that links to those of all the others.
+        let merged_context = contexts.first().map(|&parent| {
+            let now = crate::domain::unix_nanos();
+            telemetry.span(
+                "noria.group_commit",
+                parent,
+                now.saturating_sub(since.elapsed().as_nanos() as u64),
+                now,
+                &contexts,
+                vec![KeyValue::new("noria.writes", writes as i64)],
+            )
+        });
+
         Some(Box::new(Packet::Input {
             inner: LocalOrNot::new(Input {
                 dst: merged_dst,
                 data: merged_data,
+                tracked: merged_tracked,
+                trace: merged_trace,
+                origin: merged_origin,
+                txn: None,
+                op: None,
+                context: merged_context,
+                sent: 0,
             }),
             src: None,
             senders: all_senders,
         }))
     }
 
-    /// Merge the contents of packets into a single packet, emptying packets in the process.
+    /// Merge the contents of packets into as few packets as the limits on the number of
+    /// operations and bytes in each allow, emptying packets in the process.
+    ///
+    /// The merged packets hold the writes in the order they arrived. A write is never split
+    /// across merged packets, so one that exceeds the limits on its own is merged with nothing.
     #[allow(clippy::vec_box)]
-    fn merge_packets(packets: &mut Vec<Box<Packet>>) -> Option<Box<Packet>> {
-        if packets.is_empty() {
-            return None;
+    fn merge_packets(
+        packets: &mut Vec<Box<Packet>>,
+        since: time::Instant,
+        (max_records, max_bytes): (usize, usize),
+        merged_traces: &mut Vec<(u64, LocalNodeIndex, u64, usize)>,
+        telemetry: &Telemetry,
+    ) -> Vec<Box<Packet>> {
+        let mut merged = Vec::new();
+        let mut group = Vec::new();
+        let (mut records, mut bytes) = (0, 0);
+        for p in packets.drain(..) {
+            let (r, b) = (p.records(), p.data_size());
+            if !group.is_empty() && (records + r > max_records || bytes + b > max_bytes) {
+                merged.extend(Self::merge_committed_packets(
+                    group.drain(..),
+                    since,
+                    merged_traces,
+                    telemetry,
+                ));
+                records = 0;
+                bytes = 0;
+            }
+            records += r;
+            bytes += b;
+            group.push(p);
+        }
+        if !group.is_empty() {
+            merged.extend(Self::merge_committed_packets(
+                group.into_iter(),
+                since,
+                merged_traces,
+                telemetry,
+            ));
         }
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noria::TableOperation;
+    use std::ops::Range;
+
+    fn write(ids: Range<i64>) -> Box<Packet> {
+        Box::new(Packet::Input {
+            inner: LocalOrNot::new(Input {
+                dst: unsafe { LocalNodeIndex::make(0 as u32) },
+                data: ids
+                    .map(|id| TableOperation::Insert(vec![id.into()]))
+                    .collect(),
+                tracked: false,
+                trace: None,
+                origin: None,
+                txn: None,
+                op: None,
+                context: None,
+                sent: 0,
+            }),
+            src: None,
+            senders: vec![],
+        })
+    }
+
+    /// Merge `packets` under the given limits, and return the ids written in each merged packet.
+    fn merge(mut packets: Vec<Box<Packet>>, limits: (usize, usize)) -> Vec<Vec<i64>> {
+        GroupCommitQueueSet::merge_packets(
+            &mut packets,
+            time::Instant::now(),
+            limits,
+            &mut Vec::new(),
+            &Telemetry::default(),
+        )
+        .into_iter()
+        .map(|p| match *p {
+            Packet::Input { inner, .. } => unsafe { inner.take() }
+                .data
+                .into_iter()
+                .map(|op| match op {
+                    TableOperation::Insert(row) => i64::from(&row[0]),
+                    _ => unreachable!(),
+                })
+                .collect(),
+            _ => unreachable!(),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn merge_splits_at_max_group_records() {
+        let packets = vec![write(0..2), write(2..4), write(4..7), write(7..8)];
+        assert_eq!(
+            merge(packets, (4, usize::max_value())),
+            vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7]]
+        );
+
+        // a write that is over the limit on its own is not split, and is merged with nothing
+        let packets = vec![write(0..1), write(1..6), write(6..7)];
+        assert_eq!(
+            merge(packets, (4, usize::max_value())),
+            vec![vec![0], vec![1, 2, 3, 4, 5], vec![6]]
+        );
+    }
+
+    #[test]
+    fn merge_splits_at_max_group_bytes() {
+        let row = write(0..1).data_size();
+        let packets = vec![write(0..1), write(1..3), write(3..4), write(4..5)];
+        assert_eq!(
+            merge(packets, (usize::max_value(), 3 * row)),
+            vec![vec![0, 1, 2], vec![3, 4]]
+        );
+    }
 
-        Self::merge_committed_packets(packets.drain(..))
+    #[test]
+    fn merge_keeps_write_order_across_groups() {
+        let packets: Vec<_> = (0..10).map(|i| write(i * 3..i * 3 + 3)).collect();
+        let merged = merge(packets, (7, usize::max_value()));
+        assert_eq!(merged.len(), 5);
+        assert!(merged.iter().all(|g| g.len() == 6));
+        assert_eq!(
+            merged.into_iter().flatten().collect::<Vec<_>>(),
+            (0..30).collect::<Vec<_>>()
+        );
     }
 }
@@ -23,29 +23,65 @@ pub(crate) mod state;
 mod domain;
 mod group_commit;
 mod processing;
+mod telemetry;
+mod trace;
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::time;
 
-pub use crate::backlog::SingleReadHandle;
+pub use crate::backlog::{EvictionPolicy, KeyRows, NegativeCaching, SingleReadHandle};
 pub type Readers =
     Arc<Mutex<HashMap<(petgraph::graph::NodeIndex, usize), backlog::SingleReadHandle>>>;
 pub type DomainConfig = domain::Config;
 
-pub use crate::domain::{Domain, DomainBuilder, Index, PollEvent, ProcessResult};
+pub use crate::domain::{
+    replay_capture, CaptureFile, Domain, DomainBuilder, DomainMetrics, Index, LabelMetric,
+    OverloadPolicy, PollEvent, ProcessResult, Replayed,
+};
 pub use crate::payload::Packet;
+pub use crate::state::LogFile;
+pub use crate::telemetry::Telemetry;
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Sharding {
     None,
     ForcedNone,
     Random(usize),
     ByColumn(usize, usize),
+    /// Sharded by the tuple of values in the given columns, which there are always more than one
+    /// of. Use `Sharding::by_columns` to construct this so that single-column keys use `ByColumn`.
+    ByColumns(Vec<usize>, usize),
+    /// Sharded by which of the ranges between the given boundaries the value in the given column
+    /// falls into. There is one more shard than there are boundaries.
+    ByRange(usize, Vec<noria::DataType>),
+    /// Every one of the given number of shards holds all the records.
+    Broadcast(usize),
 }
 
 impl Sharding {
+    /// Shard by the given key columns, using `ByColumn` if there is only one.
+    pub fn by_columns(mut cols: Vec<usize>, shards: usize) -> Self {
+        assert!(!cols.is_empty());
+        if cols.len() == 1 {
+            Sharding::ByColumn(cols.swap_remove(0), shards)
+        } else {
+            Sharding::ByColumns(cols, shards)
+        }
+    }
+
+    /// The columns whose values determine the shard of a record, if any.
+    pub fn columns(&self) -> Option<&[usize]> {
+        match *self {
+            Sharding::ByColumn(ref c, _) | Sharding::ByRange(ref c, _) => {
+                Some(std::slice::from_ref(c))
+            }
+            Sharding::ByColumns(ref cs, _) => Some(&cs[..]),
+            _ => None,
+        }
+    }
+
     pub fn is_none(&self) -> bool {
         match *self {
             Sharding::None | Sharding::ForcedNone => true,
@@ -56,7 +92,22 @@ impl Sharding {
     pub fn shards(&self) -> Option<usize> {
         match *self {
             Sharding::None | Sharding::ForcedNone => None,
-            Sharding::Random(shards) | Sharding::ByColumn(_, shards) => Some(shards),
+            Sharding::Random(shards)
+            | Sharding::Broadcast(shards)
+            | Sharding::ByColumn(_, shards)
+            | Sharding::ByColumns(_, shards) => Some(shards),
+            Sharding::ByRange(_, ref boundaries) => Some(boundaries.len() + 1),
+        }
+    }
+
+    /// The same sharding, but of records whose sharding columns are instead `cols`.
+    pub fn with_columns(&self, mut cols: Vec<usize>) -> Self {
+        match *self {
+            Sharding::ByRange(_, ref boundaries) => {
+                assert_eq!(cols.len(), 1);
+                Sharding::ByRange(cols.swap_remove(0), boundaries.clone())
+            }
+            _ => Sharding::by_columns(cols, self.shards().unwrap()),
         }
     }
 }
@@ -79,12 +130,27 @@ pub struct PersistenceParameters {
     pub flush_timeout: time::Duration,
     /// Whether the output files should be deleted when the GroupCommitQueue is dropped.
     pub mode: DurabilityMode,
-    /// Filename prefix for persistent log entries.
+    /// Filename prefix for persistent log entries, which identifies the deployment they belong to.
     pub log_prefix: String,
     /// Absolute path where the log will be written. Defaults to the current directory.
     pub log_dir: Option<PathBuf>,
     /// Number of background threads PersistentState can use (shared acrosss all worker threads).
     pub persistence_threads: i32,
+    /// The most table operations that group commit merges into one update. Queued writes that
+    /// hold more are committed as several updates, in order.
+    #[serde(default = "default_max_group_records")]
+    pub max_group_records: usize,
+    /// Roughly the most bytes of table operations that group commit merges into one update.
+    #[serde(default = "default_max_group_bytes")]
+    pub max_group_bytes: usize,
+}
+
+fn default_max_group_records() -> usize {
+    10_000
+}
+
+fn default_max_group_bytes() -> usize {
+    4 * 1024 * 1024
 }
 
 impl Default for PersistenceParameters {
@@ -95,6 +161,8 @@ impl Default for PersistenceParameters {
             log_prefix: String::from("soup"),
             log_dir: None,
             persistence_threads: 1,
+            max_group_records: default_max_group_records(),
+            max_group_bytes: default_max_group_bytes(),
         }
     }
 }
@@ -126,6 +194,38 @@ impl PersistenceParameters {
             ..Default::default()
         }
     }
+
+    /// The directory that durable state is kept in, and the name of the deployment that its
+    /// files are named after.
+    ///
+    /// Both come from the log prefix, which may start with a directory.
+    pub fn log_location(&self) -> (&Path, String) {
+        let prefix = Path::new(&self.log_prefix);
+        let deployment = prefix
+            .file_name()
+            .map_or_else(String::new, |d| d.to_string_lossy().into_owned());
+        (prefix.parent().unwrap_or_else(|| Path::new("")), deployment)
+    }
+
+    /// The path to store the durable state of shard `shard` of the base table `base` under.
+    ///
+    /// If the deployment already has that state under the name it was given before names were
+    /// escaped, that name is kept so the state is found again.
+    pub fn log_path(&self, base: &str, shard: usize) -> String {
+        let (dir, deployment) = self.log_location();
+        let file = LogFile {
+            deployment,
+            base: base.to_owned(),
+            shard,
+        };
+        if self.mode == DurabilityMode::Permanent {
+            let legacy = file.legacy_name();
+            if dir.join(format!("{}.db", legacy)).exists() {
+                return dir.join(legacy).to_string_lossy().into_owned();
+            }
+        }
+        dir.join(file.name()).to_string_lossy().into_owned()
+    }
 }
 
-pub use noria::shard_by;
+pub use noria::{shard_by, shard_by_key, shard_by_range};
@@ -9,7 +9,7 @@ impl fmt::Debug for Node {
             NodeType::Source => write!(f, "source node"),
             NodeType::Ingress => write!(f, "ingress node"),
             NodeType::Egress { .. } => write!(f, "egress node"),
-            NodeType::Sharder(ref s) => write!(f, "sharder [{}] node", s.sharded_by()),
+            NodeType::Sharder(ref s) => write!(f, "sharder {:?} node", s.sharded_by()),
             NodeType::Reader(..) => write!(f, "reader node"),
             NodeType::Base(..) => write!(f, "B"),
             NodeType::Internal(ref i) => write!(f, "internal {} node", i.description(true)),
@@ -26,7 +26,11 @@ impl Node {
     ) -> String {
         let mut s = String::new();
         let border = match self.sharded_by {
-            Sharding::ByColumn(_, _) | Sharding::Random(_) => "filled,dashed",
+            Sharding::ByColumn(_, _)
+            | Sharding::ByColumns(_, _)
+            | Sharding::ByRange(_, _)
+            | Sharding::Random(_)
+            | Sharding::Broadcast(_) => "filled,dashed",
             _ => {
                 if Self::is_security(self.name()) {
                     "filled,rounded"
@@ -50,10 +54,13 @@ impl Node {
                         Self::escape(self.name())
                     ));
                 }
+                NodeType::Sharder(ref sharder) if sharder.is_broadcast() => {
+                    s.push_str("[style=bold, shape=Msquare, label=\"broadcast\"]\n");
+                }
                 NodeType::Sharder(ref sharder) => {
                     s.push_str(&format!(
                         "[style=bold, shape=Msquare, label=\"shard by {}\"]\n",
-                        Self::escape(&self.fields[sharder.sharded_by()]),
+                        Self::escape(&self.column_names(sharder.sharded_by())),
                     ));
                 }
                 NodeType::Reader(_) => {
@@ -135,7 +142,14 @@ impl Node {
 
             let sharding = match self.sharded_by {
                 Sharding::ByColumn(k, w) => format!("shard ⚷: {} / {}-way", self.fields[k], w),
+                Sharding::ByColumns(ref ks, w) => {
+                    format!("shard ⚷: {} / {}-way", self.column_names(ks), w)
+                }
+                Sharding::ByRange(k, ref bs) => {
+                    format!("shard ⚷: {} / {} ranges", self.fields[k], bs.len() + 1)
+                }
                 Sharding::Random(_) => "shard randomly".to_owned(),
+                Sharding::Broadcast(w) => format!("broadcast / {}-way", w),
                 Sharding::None => "unsharded".to_owned(),
                 Sharding::ForcedNone => "desharded to avoid SS".to_owned(),
             };
@@ -172,10 +186,13 @@ impl Node {
                 NodeType::Egress { .. } => {
                     s.push_str(&format!("{{ {} | (egress) | {} }}", addr, sharding))
                 }
+                NodeType::Sharder(ref sharder) if sharder.is_broadcast() => {
+                    s.push_str(&format!("{{ {} | broadcast | {} }}", addr, sharding))
+                }
                 NodeType::Sharder(ref sharder) => s.push_str(&format!(
                     "{{ {} | shard by {} | {} }}",
                     addr,
-                    self.fields[sharder.sharded_by()],
+                    self.column_names(sharder.sharded_by()),
                     sharding
                 )),
                 NodeType::Reader(ref r) => {
@@ -216,6 +233,13 @@ impl Node {
         s
     }
 
+    fn column_names(&self, cols: &[usize]) -> String {
+        cols.iter()
+            .map(|&c| &self.fields[c][..])
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
     fn is_security(name: &str) -> bool {
         name.starts_with("sp_")
     }
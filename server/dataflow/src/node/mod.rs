@@ -1,9 +1,12 @@
 use crate::domain;
 use crate::ops;
+use crate::ops::udf::Udf;
 use crate::prelude::*;
+use noria::debug::stats::OperatorStats;
 use petgraph;
 use std::collections::{HashMap, HashSet};
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 
 mod process;
 #[cfg(test)]
@@ -34,6 +37,10 @@ pub struct Node {
     pub purge: bool,
 
     sharded_by: Sharding,
+
+    /// How much work the operator of an internal node has done in this domain shard.
+    #[serde(skip)]
+    operator_stats: OperatorStats,
 }
 
 // constructors
@@ -59,6 +66,8 @@ impl Node {
             purge: false,
 
             sharded_by: Sharding::None,
+
+            operator_stats: OperatorStats::default(),
         }
     }
 
@@ -69,6 +78,20 @@ impl Node {
     pub fn named_mirror<NT: Into<NodeType>>(&self, n: NT, name: String) -> Node {
         Self::new(name, &self.fields, n)
     }
+
+    /// Make a copy of this internal node that reads from different parents.
+    ///
+    /// `parents` gives the node that the copy reads from in place of each of this node's parents.
+    /// The copy is otherwise a new node, and is committed again once it has been placed.
+    pub fn copy_onto(&self, parents: &HashMap<NodeIndex, IndexPair>) -> Node {
+        let mut remap = parents.clone();
+        remap.insert(self.global_addr(), self.index.unwrap());
+        let mut op = ops::NodeOperator::clone(self);
+        op.on_commit(self.global_addr(), &remap);
+        let mut copy = self.mirror(op);
+        copy.purge = self.purge;
+        copy
+    }
 }
 
 #[must_use]
@@ -111,6 +134,25 @@ impl Node {
         }
     }
 
+    /// Have this node call the domain's copies of the user-defined functions it calls, adding a
+    /// copy to `udfs` for each function the domain does not have one of yet.
+    ///
+    /// Shards of a node may share its functions until they reach their domains, and a copy of
+    /// a function keeps its own instance, so no two domains call into the same instance.
+    pub fn share_udfs(&mut self, udfs: &mut Vec<Arc<Udf>>) {
+        if let NodeType::Internal(ref mut i) = self.inner {
+            for f in i.udfs_mut() {
+                match udfs.iter().find(|u| ***u == **f) {
+                    Some(u) => *f = u.clone(),
+                    None => {
+                        *f = Arc::new(Udf::clone(f));
+                        udfs.push(f.clone());
+                    }
+                }
+            }
+        }
+    }
+
     /// May return a set of nodes such that *one* of the given ancestors *must* be the one to be
     /// replayed if this node's state is to be initialized.
     pub fn must_replay_among(&self) -> Option<HashSet<NodeIndex>> {
@@ -125,6 +167,11 @@ impl Node {
         Ingredient::parent_columns(&**self, column)
     }
 
+    /// The columns of the given parent that this node computes on.
+    pub fn used_parent_columns(&self, parent: NodeIndex) -> Vec<usize> {
+        Ingredient::used_parent_columns(&**self, parent)
+    }
+
     /// Resolve where the given field originates from. If the view is materialized, or the value is
     /// otherwise created by this view, None should be returned.
     pub fn resolve(&self, i: usize) -> Option<Vec<(NodeIndex, usize)>> {
@@ -179,7 +226,7 @@ impl Node {
     }
 
     pub fn sharded_by(&self) -> Sharding {
-        self.sharded_by
+        self.sharded_by.clone()
     }
 
     /// Set this node's sharding property.
@@ -214,7 +261,7 @@ impl Node {
 
 // derefs
 impl Node {
-    pub(crate) fn with_sharder_mut<F>(&mut self, f: F)
+    pub fn with_sharder_mut<F>(&mut self, f: F)
     where
         F: FnOnce(&mut special::Sharder),
     {
@@ -235,6 +282,17 @@ impl Node {
         }
     }
 
+    pub(crate) fn with_egress<'a, F, R>(&'a self, f: F) -> Option<R>
+    where
+        F: FnOnce(&'a special::Egress) -> R,
+        R: 'a,
+    {
+        match self.inner {
+            NodeType::Egress(Some(ref e)) => Some(f(e)),
+            _ => None,
+        }
+    }
+
     pub(crate) fn with_egress_mut<F>(&mut self, f: F)
     where
         F: FnOnce(&mut special::Egress),
@@ -335,6 +393,15 @@ impl Node {
         false
     }
 
+    /// How much work this node's operator has done, if it is an internal node.
+    pub fn operator_stats(&self) -> Option<OperatorStats> {
+        if self.is_internal() {
+            Some(self.operator_stats)
+        } else {
+            None
+        }
+    }
+
     pub fn add_column(&mut self, field: &str) -> usize {
         self.fields.push(field.to_string());
         self.fields.len() - 1
@@ -1,9 +1,11 @@
 use crate::node::NodeType;
 use crate::payload;
 use crate::prelude::*;
+use chrono::NaiveDateTime;
 use slog::Logger;
 use std::collections::HashSet;
 use std::mem;
+use std::time;
 
 impl Node {
     #[allow(clippy::too_many_arguments)]
@@ -33,10 +35,39 @@ impl Node {
                 // NOTE: bases only accept BaseOperations
                 match m.take().map(|p| *p) {
                     Some(Packet::Input {
-                        inner, mut senders, ..
+                        inner,
+                        src,
+                        mut senders,
                     }) => {
-                        let Input { dst, data } = unsafe { inner.take() };
-                        let mut rs = b.process(addr, data, &*state);
+                        let Input {
+                            dst,
+                            mut data,
+                            tracked,
+                            trace,
+                            origin,
+                            txn,
+                            op,
+                            context,
+                            sent: _,
+                        } = unsafe { inner.take() };
+                        if let Some(op) = op {
+                            if !b.apply_op(addr, op, state) {
+                                debug!(log, "base dropped a write it had already applied";
+                                       "node" => gaddr.index(), "op" => op);
+                                data.clear();
+                            }
+                        }
+                        // writes that were not merged by group commit are acknowledged on their own
+                        if let Some(src) = src {
+                            debug_assert!(senders.is_empty());
+                            senders.push((src, data.len()));
+                        }
+                        let ids = b.assign_ids(addr, &mut data, &*state);
+                        let (mut rs, outcomes) = b.process(addr, data, &*state);
+                        if !outcomes.ignored.is_empty() {
+                            debug!(log, "base ignored inserts of keys it already has";
+                                   "node" => gaddr.index(), "inserts" => outcomes.ignored.len());
+                        }
 
                         // When a replay originates at a base node, we replay the data *through* that
                         // same base node because its column set may have changed. However, this replay
@@ -49,15 +80,58 @@ impl Node {
                             materialize(&mut rs, None, state.get_mut(addr));
                         }
 
+                        let label = payload::Label {
+                            base: gaddr,
+                            shard: on_shard.unwrap_or(0),
+                            seq: b.next_label(),
+                            tracked,
+                            trace,
+                            origin,
+                            txn,
+                            committed: crate::domain::unix_nanos(),
+                            context,
+                            sent: 0,
+                            path: 0,
+                        };
+
                         // Send write-ACKs to all the clients with updates that made
-                        // it into this merged packet:
-                        senders.drain(..).for_each(|src| ex.ack(src));
+                        // it into this merged packet, along with the keys assigned to their
+                        // inserts and which of their operations did not take effect:
+                        let mut ids = ids.into_iter();
+                        let mut rejected = outcomes.rejected.into_iter().peekable();
+                        let mut unapplied = outcomes.unapplied.into_iter().peekable();
+                        let mut invalid = outcomes.invalid.into_iter().peekable();
+                        let deleted = outcomes.deleted;
+                        let mut offset = 0;
+                        senders.drain(..).for_each(|(src, n)| {
+                            let ack = WriteAck {
+                                label: label.seq,
+                                ids: ids.by_ref().take(n).flatten().collect(),
+                                rejected: positions_within(&mut rejected, offset, n),
+                                unapplied: positions_within(&mut unapplied, offset, n),
+                                invalid: positions_within(&mut invalid, offset, n),
+                                overloaded: Vec::new(),
+                                deleted: deleted
+                                    .iter()
+                                    .filter(|&&(i, _)| i >= offset && i < offset + n)
+                                    .map(|&(_, count)| count)
+                                    .sum(),
+                            };
+                            offset += n;
+                            ex.ack(src, ack)
+                        });
 
                         *m = Some(Box::new(Packet::Message {
                             link: Link::new(dst, dst),
                             data: rs,
+                            label,
                         }));
                     }
+                    Some(p @ Packet::Message { .. }) => {
+                        // bulk loaded rows have already been applied to the base's state, and are
+                        // just being sent on
+                        *m = Some(Box::new(p));
+                    }
                     Some(ref p) => {
                         // TODO: replays?
                         unreachable!("base received non-input packet {:?}", p);
@@ -122,13 +196,26 @@ impl Node {
                             context: payload::ReplayPieceContext::Regular { last },
                             ..
                         } => (data, ReplayContext::Full { last }),
-                        Packet::Message { ref mut data, .. } => (data, ReplayContext::None),
+                        Packet::Message {
+                            ref mut data,
+                            ref mut label,
+                            ..
+                        } => {
+                            if self.parents.len() > 1 {
+                                // the batch reaches this node once along each parent it has
+                                // from the base
+                                label.via(gaddr, from);
+                            }
+                            (data, ReplayContext::None)
+                        }
                         _ => unreachable!(),
                     };
 
                     let mut set_replay_last = None;
                     // we need to own the data
                     let old_data = mem::take(data);
+                    let records_in = old_data.len();
+                    let start = time::Instant::now();
 
                     match i.on_input_raw(ex, from, old_data, replay, nodes, state, log) {
                         RawProcessingResult::Regular(m) => {
@@ -170,6 +257,12 @@ impl Node {
                         }
                     }
 
+                    let stats = &mut self.operator_stats;
+                    stats.invocations += 1;
+                    stats.records_in += records_in as u64;
+                    stats.records_out += data.len() as u64;
+                    stats.process_time += start.elapsed().as_nanos() as u64;
+
                     if let Some(new_last) = set_replay_last {
                         if let Packet::ReplayPiece {
                             context: payload::ReplayPieceContext::Regular { ref mut last },
@@ -298,6 +391,228 @@ impl Node {
     }
 }
 
+impl Node {
+    /// Apply rows that are being bulk loaded into this base to its state, without sending anything
+    /// downstream.
+    ///
+    /// Returns the records that downstream must eventually be sent.
+    pub(crate) fn bulk_load(&mut self, rows: Vec<Vec<DataType>>, state: &mut StateMap) -> Records {
+        let addr = self.local_addr();
+        let b = match self.inner {
+            NodeType::Base(ref mut b) => b,
+            _ => unreachable!("bulk load into non-base node"),
+        };
+
+        let mut ops: Vec<_> = rows
+            .into_iter()
+            .map(noria::TableOperation::Insert)
+            .collect();
+        b.assign_ids(addr, &mut ops, &*state);
+        let (mut rs, _) = b.process(addr, ops, &*state);
+        materialize(&mut rs, None, state.get_mut(addr));
+        rs
+    }
+
+    /// Fill in the auto-timestamp columns of writes to this base with the time `now`.
+    pub(crate) fn stamp_writes(&self, ops: &mut [noria::TableOperation], now: NaiveDateTime) {
+        match self.inner {
+            NodeType::Base(ref b) => b.stamp(ops, now),
+            _ => unreachable!("stamping writes to non-base node"),
+        }
+    }
+
+    /// The positions of the writes to this base that carry a blob larger than it allows.
+    pub(crate) fn oversized_writes(&self, ops: &[noria::TableOperation]) -> Vec<usize> {
+        match self.inner {
+            NodeType::Base(ref b) => b.oversized(ops),
+            _ => unreachable!("checking writes to non-base node"),
+        }
+    }
+
+    /// Remove rows that have expired as of `now` from this base's state, at most one chunk of them.
+    ///
+    /// Returns the records that downstream must be sent.
+    pub(crate) fn expire_rows(&mut self, now: NaiveDateTime, state: &mut StateMap) -> Records {
+        let addr = self.local_addr();
+        let b = match self.inner {
+            NodeType::Base(ref mut b) => b,
+            _ => unreachable!("expiring rows of non-base node"),
+        };
+
+        let mut rs = b.expire(addr, now, &*state);
+        materialize(&mut rs, None, state.get_mut(addr));
+        rs
+    }
+
+    /// Remove the rows that belong to other shards of this range-sharded base once it is split
+    /// along `boundaries` from its state.
+    ///
+    /// Returns the records that downstream must be sent.
+    pub(crate) fn split_off(&mut self, boundaries: &[DataType], state: &mut StateMap) -> Records {
+        let addr = self.local_addr();
+        let b = match self.inner {
+            NodeType::Base(ref mut b) => b,
+            _ => unreachable!("re-splitting non-base node"),
+        };
+
+        let mut rs = b.split_off(addr, boundaries, &*state);
+        materialize(&mut rs, None, state.get_mut(addr));
+        rs
+    }
+
+    /// Wrap records that were applied to this base outside of the normal write path into an
+    /// update it can send downstream.
+    pub(crate) fn base_update(&mut self, data: Records, on_shard: Option<usize>) -> Packet {
+        let addr = self.local_addr();
+        let base = self.global_addr();
+        let b = match self.inner {
+            NodeType::Base(ref mut b) => b,
+            _ => unreachable!("base update from non-base node"),
+        };
+
+        Packet::Message {
+            link: Link::new(addr, addr),
+            data,
+            label: payload::Label {
+                base,
+                shard: on_shard.unwrap_or(0),
+                seq: b.next_label(),
+                tracked: false,
+                trace: None,
+                origin: None,
+                txn: None,
+                committed: crate::domain::unix_nanos(),
+                context: None,
+                sent: 0,
+                path: 0,
+            },
+        }
+    }
+
+    /// Check this base's part of a transaction against its state, and acknowledge the writes that
+    /// make it up, without applying them.
+    ///
+    /// Returns the records the part applies once it is committed, and the label they are to be
+    /// sent downstream with, or nothing if any of its writes were rejected, in which case the
+    /// transaction must be aborted.
+    pub(crate) fn stage_transaction(
+        &mut self,
+        m: Box<Packet>,
+        state: &mut StateMap,
+        on_shard: Option<usize>,
+        ex: &mut dyn Executor,
+    ) -> Option<(Records, payload::Label)> {
+        let addr = self.local_addr();
+        let base = self.global_addr();
+        let b = match self.inner {
+            NodeType::Base(ref mut b) => b,
+            _ => unreachable!("staging transaction at non-base node"),
+        };
+        let (inner, src) = match *m {
+            Packet::Input { inner, src, .. } => (inner, src),
+            _ => unreachable!("staging transaction from non-input packet"),
+        };
+        let Input {
+            mut data,
+            tracked,
+            trace,
+            origin,
+            txn,
+            context,
+            ..
+        } = unsafe { inner.take() };
+        let txn = txn.unwrap();
+
+        // the base's state is left as it is until the transaction commits
+        let ids = b.assign_ids(addr, &mut data, &*state);
+        let (rs, outcomes) = b.process(addr, data, &*state);
+        let staged = outcomes.rejected.is_empty() && outcomes.invalid.is_empty();
+        let seq = if staged {
+            // a part that has been acknowledged must survive the domain going away, since the
+            // transaction may be committed regardless
+            if let Some(s) = state.get_mut(addr) {
+                s.stage_transaction(txn, &rs);
+            }
+            b.next_label()
+        } else {
+            0
+        };
+
+        if let Some(src) = src {
+            ex.ack(
+                src,
+                WriteAck {
+                    label: seq,
+                    ids: ids.into_iter().flatten().collect(),
+                    rejected: outcomes.rejected,
+                    unapplied: outcomes.unapplied,
+                    invalid: outcomes.invalid,
+                    overloaded: Vec::new(),
+                    deleted: outcomes.deleted.iter().map(|&(_, count)| count).sum(),
+                },
+            );
+        }
+        if !staged {
+            return None;
+        }
+
+        let label = payload::Label {
+            base,
+            shard: on_shard.unwrap_or(0),
+            seq,
+            tracked,
+            trace,
+            origin,
+            txn: Some(txn),
+            committed: 0,
+            context,
+            sent: 0,
+            path: 0,
+        };
+        Some((rs, label))
+    }
+
+    /// Apply this base's staged part of a committed transaction, and wrap it into an update it
+    /// can send downstream.
+    ///
+    /// A part that was recovered from disk has no label, and is given a new one.
+    pub(crate) fn commit_transaction(
+        &mut self,
+        txn: u64,
+        mut rs: Records,
+        label: Option<payload::Label>,
+        state: &mut StateMap,
+        on_shard: Option<usize>,
+    ) -> Packet {
+        let addr = self.local_addr();
+        if let Some(s) = state.get_mut(addr) {
+            s.unstage_transaction();
+        }
+        materialize(&mut rs, None, state.get_mut(addr));
+
+        match label {
+            Some(mut label) => {
+                label.committed = crate::domain::unix_nanos();
+                Packet::Message {
+                    link: Link::new(addr, addr),
+                    data: rs,
+                    label,
+                }
+            }
+            None => {
+                let mut m = self.base_update(rs, on_shard);
+                if let Packet::Message { ref mut label, .. } = m {
+                    // carried all the way to the readers that wait for the transaction, even if
+                    // it is empty
+                    label.txn = Some(txn);
+                    label.tracked = true;
+                }
+                m
+            }
+        }
+    }
+}
+
 // When we miss in can_query_through, that miss is *really* in the can_query_through node's
 // ancestor. We need to ensure that a replay is done to there, not the query_through node itself,
 // by translating the Miss into the right parent.
@@ -351,3 +666,24 @@ pub(crate) fn materialize(
     // yes!
     state.unwrap().process_records(rs, partial);
 }
+
+/// Take the positions in `[offset, offset + n)` from the front of the sorted `positions`, relative
+/// to `offset`.
+fn positions_within<I>(
+    positions: &mut std::iter::Peekable<I>,
+    offset: usize,
+    n: usize,
+) -> Vec<usize>
+where
+    I: Iterator<Item = usize>,
+{
+    let mut within = Vec::new();
+    while let Some(&i) = positions.peek() {
+        if i >= offset + n {
+            break;
+        }
+        within.push(i - offset);
+        positions.next();
+    }
+    within
+}
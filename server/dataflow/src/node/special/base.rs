@@ -1,10 +1,58 @@
+use super::dedup::{AppliedOps, DedupWindow};
 use crate::prelude::*;
-use noria::{Modification, Operation, TableOperation};
+use chrono::NaiveDateTime;
+use noria::{ColumnCondition, Modification, Operation, TableOperation};
 use std::borrow::Cow;
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::rc::Rc;
+use std::time::Duration;
 use vec_map::VecMap;
 
+/// What a base with a primary key does with an insert whose key it already holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DuplicateKeyPolicy {
+    /// Drop the insert without telling the writer.
+    Ignore,
+    /// Drop the insert, and report it to the writer as a constraint violation.
+    Reject,
+    /// Replace the existing row with the inserted one.
+    Update,
+}
+
+impl Default for DuplicateKeyPolicy {
+    fn default() -> Self {
+        DuplicateKeyPolicy::Ignore
+    }
+}
+
+/// The largest blob a write may carry by default. Larger blobs are rejected before they reach the
+/// base's state.
+pub const DEFAULT_MAX_BYTES: usize = 1 << 20;
+
+/// How the rows of a base expire.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ttl {
+    /// The timestamp column that rows expire relative to.
+    pub column: usize,
+    /// How long after its timestamp a row expires.
+    pub after: Duration,
+    /// How often the base is scanned for expired rows.
+    pub scan_every: Duration,
+    /// The most rows removed at a time; a scan that hits the limit is continued right away.
+    pub chunk: usize,
+}
+
+/// How the values of the column that a base is range sharded by are split between its shards.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RangeSplit {
+    /// Split at the given boundaries, which must be strictly increasing. A value that is exactly
+    /// on a boundary belongs to the shard above it.
+    Boundaries(Vec<DataType>),
+    /// Split a sample of the column's values into ranges that each hold about as many of them.
+    Sample(Vec<DataType>),
+}
+
 /// Base is used to represent the root nodes of the Noria data flow graph.
 ///
 /// These nodes perform no computation, and their job is merely to persist all received updates and
@@ -17,6 +65,53 @@ pub struct Base {
     defaults: Vec<DataType>,
     dropped: Vec<usize>,
     unmodified: bool,
+
+    /// The label of the last batch of writes processed by this base.
+    label: u64,
+
+    /// Whether the base assigns keys to inserts whose key column is `DataType::None`.
+    auto_increment: bool,
+    /// The shard this base is running on, and the number of shards of the base. Keys assigned on
+    /// a shard always shard back to that same shard.
+    shard: usize,
+    nshards: usize,
+    /// The next key to assign. This is recovered from the base's state the first time a key is
+    /// needed, so that it always resumes past every key that has been handed out.
+    #[serde(skip)]
+    next_id: Option<i64>,
+
+    /// What to do with inserts whose key is already present.
+    on_duplicate: DuplicateKeyPolicy,
+
+    /// When rows of the base expire, if they do.
+    ttl: Option<Ttl>,
+    /// The primary keys of the base's rows ordered by the rows' timestamps. This is built from the
+    /// base's state the first time the base is scanned for expired rows, and kept up to date from
+    /// then on.
+    #[serde(skip)]
+    expiry: Option<BTreeSet<(NaiveDateTime, Vec<DataType>)>>,
+
+    /// The columns the base fills in with the time a row was inserted and last written.
+    created_at: Option<usize>,
+    updated_at: Option<usize>,
+
+    /// The columns that hold JSON documents.
+    json_columns: Vec<usize>,
+    /// The columns whose strings compare case-insensitively.
+    case_insensitive_columns: Vec<usize>,
+    /// The largest blob a write may carry, in bytes.
+    max_bytes: usize,
+
+    /// The column the base is sharded by ranges of, if any, and how those ranges are chosen.
+    range_sharding: Option<(usize, RangeSplit)>,
+
+    /// How many client operation ids the base remembers, and for how long.
+    #[serde(default)]
+    dedup_window: DedupWindow,
+    /// The client operations the base has applied recently. These are recovered from the base's
+    /// state the first time a write carries an operation id.
+    #[serde(skip)]
+    applied_ops: Option<AppliedOps>,
 }
 
 impl Base {
@@ -33,10 +128,187 @@ impl Base {
         self
     }
 
+    /// Builder that makes the base assign keys to inserts that do not provide one.
+    ///
+    /// The base must have a single-column primary key.
+    pub fn with_auto_increment(mut self) -> Base {
+        assert_eq!(
+            self.primary_key.as_ref().map(Vec::len),
+            Some(1),
+            "auto-increment keys require a single-column primary key"
+        );
+        assert!(
+            self.range_sharding.is_none(),
+            "auto-increment keys are assigned by hash, so cannot be range sharded"
+        );
+        self.auto_increment = true;
+        self
+    }
+
+    /// Builder that sets what the base does with inserts whose key is already present.
+    ///
+    /// The base must have a primary key.
+    pub fn with_duplicate_key_policy(mut self, policy: DuplicateKeyPolicy) -> Base {
+        assert!(
+            self.primary_key.is_some(),
+            "unique keys can only be enforced on bases with a primary key"
+        );
+        self.on_duplicate = policy;
+        self
+    }
+
+    /// Builder that makes rows expire once the timestamp in `column` is older than `after`.
+    ///
+    /// The base must have a primary key. By default, the base is scanned for expired rows once a
+    /// minute, and at most 1024 rows are removed at a time.
+    pub fn with_ttl(mut self, column: usize, after: Duration) -> Base {
+        assert!(
+            self.primary_key.is_some(),
+            "rows can only expire from bases with a primary key"
+        );
+        self.ttl = Some(Ttl {
+            column,
+            after,
+            scan_every: Duration::from_secs(60),
+            chunk: 1024,
+        });
+        self
+    }
+
+    /// Builder that sets how often the base is scanned for expired rows, and how many rows are
+    /// removed at a time.
+    ///
+    /// The base must have a TTL.
+    pub fn with_ttl_scan(mut self, scan_every: Duration, chunk: usize) -> Base {
+        assert!(chunk > 0);
+        let ttl = self
+            .ttl
+            .as_mut()
+            .expect("scan schedule set on a base without a TTL");
+        ttl.scan_every = scan_every;
+        ttl.chunk = chunk;
+        self
+    }
+
+    /// Builder that makes the base fill in `column` with the time each row was inserted.
+    pub fn with_created_at(mut self, column: usize) -> Base {
+        self.created_at = Some(column);
+        self
+    }
+
+    /// Builder that makes the base fill in `column` with the time each row was last written.
+    pub fn with_updated_at(mut self, column: usize) -> Base {
+        self.updated_at = Some(column);
+        self
+    }
+
+    /// Builder that makes the given columns hold JSON documents.
+    ///
+    /// Text written to these columns is parsed and stored as a canonical document, and writes
+    /// with text that is not valid JSON are rejected.
+    pub fn with_json_columns(mut self, columns: Vec<usize>) -> Base {
+        self.json_columns = columns;
+        self
+    }
+
+    /// Builder that gives the given columns a case-insensitive collation.
+    ///
+    /// Strings written to these columns keep their original form, but compare, hash, and shard by
+    /// their Unicode case folding, so that lookups, joins, and groupings on them ignore case.
+    ///
+    /// Clients only shard by the collation if they know of it from the table's SQL schema, so a
+    /// sharded base should only be keyed by such a column if it was created from SQL. A join
+    /// only ignores case if the columns on both of its sides have the collation.
+    pub fn with_case_insensitive_columns(mut self, columns: Vec<usize>) -> Base {
+        self.case_insensitive_columns = columns;
+        self
+    }
+
+    /// Builder that sets the largest blob, in bytes, that a write may carry. Writes with larger
+    /// blobs are rejected as soon as they reach the base's domain, before they are queued for
+    /// group commit.
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Base {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Builder that shards the base by ranges of the values in `column` rather than by their hash.
+    ///
+    /// Rows with nearby values then live on the same shard, so that, say, reads of recent rows of
+    /// a time-ordered table only involve the few shards that hold them. The base must either have
+    /// no primary key, or be keyed on `column` alone.
+    pub fn with_range_sharding(mut self, column: usize, split: RangeSplit) -> Base {
+        assert!(
+            self.primary_key
+                .as_ref()
+                .map_or(true, |k| k[..] == [column]),
+            "a range-sharded base must be keyed on the column it is sharded by, if at all"
+        );
+        assert!(
+            !self.auto_increment,
+            "auto-increment keys are assigned by hash, so cannot be range sharded"
+        );
+        if let RangeSplit::Boundaries(ref boundaries) = split {
+            assert!(
+                boundaries.windows(2).all(|w| w[0] < w[1]),
+                "range boundaries must be strictly increasing"
+            );
+        }
+        self.range_sharding = Some((column, split));
+        self
+    }
+
     pub fn key(&self) -> Option<&[usize]> {
         self.primary_key.as_ref().map(|cols| &cols[..])
     }
 
+    pub fn is_auto_increment(&self) -> bool {
+        self.auto_increment
+    }
+
+    /// Returns true if the base fills in any columns with the time rows are written.
+    pub fn has_auto_timestamps(&self) -> bool {
+        self.created_at.is_some() || self.updated_at.is_some()
+    }
+
+    pub fn ttl(&self) -> Option<&Ttl> {
+        self.ttl.as_ref()
+    }
+
+    /// The column this base is range sharded by, if any, and the boundaries between its shards
+    /// when it is split `shards` ways.
+    ///
+    /// There may be fewer boundaries than that if they are learned from a sample with too few
+    /// distinct values.
+    pub fn range_sharding(&self, shards: usize) -> Option<(usize, Vec<DataType>)> {
+        let (column, ref split) = *self.range_sharding.as_ref()?;
+        let boundaries = match *split {
+            RangeSplit::Boundaries(ref boundaries) => boundaries.clone(),
+            RangeSplit::Sample(ref sample) => noria::range_boundaries(sample.clone(), shards),
+        };
+        Some((column, boundaries))
+    }
+
+    /// Move the boundaries between the shards of this range-sharded base.
+    pub fn resplit(&mut self, boundaries: Vec<DataType>) {
+        let range_sharding = self
+            .range_sharding
+            .as_mut()
+            .expect("re-splitting a base that is not range sharded");
+        range_sharding.1 = RangeSplit::Boundaries(boundaries);
+    }
+
+    /// Tell the base which of the base's shards it is.
+    pub(crate) fn set_shard(&mut self, shard: usize, nshards: usize) {
+        self.shard = shard;
+        self.nshards = nshards;
+    }
+
+    /// Set how many client operation ids the base remembers, and for how long.
+    pub(crate) fn set_dedup_window(&mut self, window: DedupWindow) {
+        self.dedup_window = window;
+    }
+
     /// Add a new column to this base node.
     pub fn add_column(&mut self, default: DataType) -> usize {
         assert!(
@@ -57,14 +329,23 @@ impl Base {
              setting default values for initial columns"
         );
         assert!(column < self.defaults.len());
+        assert!(
+            !self.dropped.contains(&column),
+            "column {} has already been dropped",
+            column
+        );
         self.unmodified = false;
 
-        // note that we don't need to *do* anything for dropped columns when we receive records.
-        // the only thing that matters is that new Mutators remember to inject default values for
-        // dropped columns.
+        // new Mutators inject default values for dropped columns, and `fix` strips whatever value
+        // older Mutators still send for them.
         self.dropped.push(column);
     }
 
+    /// Returns true if the given column has been dropped from this base node.
+    pub fn is_dropped(&self, column: usize) -> bool {
+        self.dropped.contains(&column)
+    }
+
     pub fn get_dropped(&self) -> VecMap<DataType> {
         self.dropped
             .iter()
@@ -81,6 +362,9 @@ impl Base {
             let rlen = row.len();
             row.extend(self.defaults.iter().skip(rlen).cloned());
         }
+        for &column in &self.dropped {
+            row[column] = self.defaults[column].clone();
+        }
     }
 }
 
@@ -95,6 +379,30 @@ impl Clone for Base {
             defaults: self.defaults.clone(),
             dropped: self.dropped.clone(),
             unmodified: self.unmodified,
+
+            label: self.label,
+
+            auto_increment: self.auto_increment,
+            shard: self.shard,
+            nshards: self.nshards,
+            next_id: self.next_id,
+
+            on_duplicate: self.on_duplicate,
+
+            ttl: self.ttl.clone(),
+            expiry: None,
+
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+
+            json_columns: self.json_columns.clone(),
+            case_insensitive_columns: self.case_insensitive_columns.clone(),
+            max_bytes: self.max_bytes,
+
+            range_sharding: self.range_sharding.clone(),
+
+            dedup_window: self.dedup_window,
+            applied_ops: None,
         }
     }
 }
@@ -107,20 +415,74 @@ impl Default for Base {
             defaults: Vec::new(),
             dropped: Vec::new(),
             unmodified: true,
+
+            label: 0,
+
+            auto_increment: false,
+            shard: 0,
+            nshards: 1,
+            next_id: None,
+
+            on_duplicate: DuplicateKeyPolicy::Ignore,
+
+            ttl: None,
+            expiry: None,
+
+            created_at: None,
+            updated_at: None,
+
+            json_columns: Vec::new(),
+            case_insensitive_columns: Vec::new(),
+            max_bytes: DEFAULT_MAX_BYTES,
+
+            range_sharding: None,
+
+            dedup_window: DedupWindow::default(),
+            applied_ops: None,
         }
     }
 }
 
-fn key_val(i: usize, col: usize, r: &TableOperation) -> &DataType {
+/// The operations of a batch that did not take effect, by their position in the batch.
+#[derive(Debug, Default)]
+pub(in crate::node) struct Outcomes {
+    /// Inserts that were rejected because their key was already present.
+    pub(in crate::node) rejected: Vec<usize>,
+    /// Conditional updates whose expected values did not match the row.
+    pub(in crate::node) unapplied: Vec<usize>,
+    /// The number of rows each filtered delete removed.
+    pub(in crate::node) deleted: Vec<(usize, usize)>,
+    /// Writes with a value that their column does not accept.
+    pub(in crate::node) invalid: Vec<usize>,
+    /// Inserts that were dropped because their key was already present and duplicates are
+    /// ignored.
+    pub(in crate::node) ignored: Vec<usize>,
+}
+
+/// An operation on a single key of a keyed base.
+enum KeyedOp {
+    Table(TableOperation),
+    /// Delete the row with the given key if it meets all of the conditions of a filtered delete.
+    DeleteIf(Vec<DataType>, Rc<Vec<(usize, ColumnCondition)>>),
+}
+
+fn key_val(i: usize, col: usize, r: &KeyedOp) -> &DataType {
+    let r = match *r {
+        KeyedOp::Table(ref r) => r,
+        KeyedOp::DeleteIf(ref key, _) => return &key[i],
+    };
     match *r {
         TableOperation::Insert(ref row) => &row[col],
         TableOperation::Delete { ref key } => &key[i],
         TableOperation::Update { ref key, .. } => &key[i],
+        TableOperation::ConditionalUpdate { ref key, .. } => &key[i],
         TableOperation::InsertOrUpdate { ref row, .. } => &row[col],
+        TableOperation::Upsert(ref row) => &row[col],
+        TableOperation::DeleteWhere { .. } => unreachable!("filtered deletes have no key"),
     }
 }
 
-fn key_of<'a>(key_cols: &'a [usize], r: &'a TableOperation) -> impl Iterator<Item = &'a DataType> {
+fn key_of<'a>(key_cols: &'a [usize], r: &'a KeyedOp) -> impl Iterator<Item = &'a DataType> {
     key_cols
         .iter()
         .enumerate()
@@ -132,16 +494,126 @@ impl Base {
         Clone::clone(self)
     }
 
+    /// The label of the latest batch of writes processed by this base, or 0 if there has been
+    /// none.
+    pub fn last_label(&self) -> u64 {
+        self.label
+    }
+
+    /// Assign a label to the next batch of writes processed by this base.
+    pub(in crate::node) fn next_label(&mut self) -> u64 {
+        self.label += 1;
+        self.label
+    }
+
+    /// Remember that the write with client operation id `op` is being applied, unless it has been
+    /// applied already, in which case this returns `false` and the write should be dropped.
+    ///
+    /// The ids are kept in the base's state alongside its rows, so that they are not forgotten
+    /// when the base is recovered.
+    pub(in crate::node) fn apply_op(
+        &mut self,
+        us: LocalNodeIndex,
+        op: u64,
+        state: &mut StateMap,
+    ) -> bool {
+        let state = state.get_mut(us);
+        if self.applied_ops.is_none() {
+            let recovered = state.as_ref().map(|s| s.applied_ops()).unwrap_or_default();
+            self.applied_ops = Some(AppliedOps::recover(recovered));
+        }
+
+        let now = crate::domain::unix_nanos();
+        let window = self.dedup_window;
+        match self.applied_ops.as_mut().unwrap().apply(op, now, window) {
+            Some(evicted) => {
+                if let Some(state) = state {
+                    state.record_applied_op((op, now), &evicted);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Fill in the key of every insert that leaves it empty.
+    ///
+    /// Returns the assigned key for each operation, or `None` for operations that did not need
+    /// one, in the order the operations were given.
+    pub(in crate::node) fn assign_ids(
+        &mut self,
+        us: LocalNodeIndex,
+        ops: &mut [TableOperation],
+        state: &StateMap,
+    ) -> Vec<Option<DataType>> {
+        if !self.auto_increment {
+            return Vec::new();
+        }
+
+        let col = self.primary_key.as_ref().unwrap()[0];
+        let nshards = self.nshards as i64;
+        let shard = self.shard as i64;
+        let next = match self.next_id {
+            Some(next) => next,
+            None => {
+                // resume past the highest key that made it into the base's state
+                let max = state
+                    .get(us)
+                    .expect("auto-increment base must be materialized")
+                    .cloned_records()
+                    .iter()
+                    .filter(|r| !r[col].is_none())
+                    .map(|r| i64::from(&r[col]))
+                    .max()
+                    .unwrap_or(0);
+                (max / nshards + 1) * nshards + shard
+            }
+        };
+
+        let mut next = next;
+        let ids = ops
+            .iter_mut()
+            .map(|op| match *op {
+                TableOperation::Insert(ref mut row) if row[col].is_none() => {
+                    let id = DataType::from(next);
+                    next += nshards;
+                    row[col] = id.clone();
+                    Some(id)
+                }
+                TableOperation::Insert(ref row)
+                | TableOperation::Upsert(ref row)
+                | TableOperation::InsertOrUpdate { ref row, .. }
+                    if !row[col].is_none() =>
+                {
+                    // make sure we never hand out a key that was chosen explicitly
+                    let explicit = i64::from(&row[col]);
+                    if explicit >= next {
+                        next = (explicit / nshards + 1) * nshards + shard;
+                    }
+                    None
+                }
+                _ => None,
+            })
+            .collect();
+        self.next_id = Some(next);
+        ids
+    }
+
+    /// Apply a batch of operations to the base.
+    ///
+    /// Also returns which of the operations did not take effect.
     pub(in crate::node) fn process(
         &mut self,
         us: LocalNodeIndex,
-        mut ops: Vec<TableOperation>,
+        ops: Vec<TableOperation>,
         state: &StateMap,
-    ) -> Records {
+    ) -> (Records, Outcomes) {
+        let mut outcomes = Outcomes::default();
+        let ops = self.check_values(ops, &mut outcomes);
         if self.primary_key.is_none() || ops.is_empty() {
-            return ops
+            let rs = ops
                 .into_iter()
-                .map(|r| {
+                .map(|(_, r)| {
                     if let TableOperation::Insert(mut r) = r {
                         self.fix(&mut r);
                         Record::Positive(r)
@@ -150,19 +622,57 @@ impl Base {
                     }
                 })
                 .collect();
+            return (rs, outcomes);
         }
 
         let key_cols = &self.primary_key.as_ref().unwrap()[..];
-        ops.sort_by(|a, b| key_of(key_cols, a).cmp(key_of(key_cols, b)));
-
-        // starting key
-        let mut this_key: Vec<_> = key_of(key_cols, &ops[0]).cloned().collect();
 
         // starting record state
         let db = state
             .get(us)
             .expect("base with primary key must be materialized");
 
+        // a filtered delete turns into a conditional delete of every key that may match it: rows
+        // in the base's state that match now, and rows written earlier in the same batch.
+        let mut written = HashSet::new();
+        let mut keyed = Vec::with_capacity(ops.len());
+        for (i, op) in ops {
+            match op {
+                TableOperation::DeleteWhere { conditions, limit } => {
+                    outcomes.deleted.push((i, 0));
+                    let matches = |r: &[DataType]| {
+                        conditions.iter().all(|&(c, ref cond)| cond.matches(&r[c]))
+                    };
+                    let matching: HashSet<Vec<DataType>> = db
+                        .find_records(&matches, limit)
+                        .into_iter()
+                        .map(|r| key_cols.iter().map(|&c| r[c].clone()).collect())
+                        .chain(written.iter().cloned())
+                        .collect();
+                    let conditions = Rc::new(conditions);
+                    keyed.extend(
+                        matching
+                            .into_iter()
+                            .map(|key| (i, KeyedOp::DeleteIf(key, conditions.clone()))),
+                    );
+                }
+                op => {
+                    let op = KeyedOp::Table(op);
+                    written.insert(key_of(key_cols, &op).cloned().collect::<Vec<_>>());
+                    keyed.push((i, op));
+                }
+            }
+        }
+        if keyed.is_empty() {
+            return (Records::default(), outcomes);
+        }
+        let mut ops = keyed;
+        // the sort is stable, so operations on the same key apply in the order they were sent
+        ops.sort_by(|a, b| key_of(key_cols, &a.1).cmp(key_of(key_cols, &b.1)));
+
+        // starting key
+        let mut this_key: Vec<_> = key_of(key_cols, &ops[0].1).cloned().collect();
+
         let get_current = |current_key: &'_ _| {
             match db.lookup(key_cols, &KeyType::from(current_key)) {
                 LookupResult::Some(rows) => {
@@ -183,7 +693,7 @@ impl Base {
         let mut was = current.clone();
 
         let mut results = Vec::with_capacity(ops.len());
-        for op in ops {
+        for (i, op) in ops {
             if this_key.iter().cmp(key_of(key_cols, &op)) != Ordering::Equal {
                 if current != was {
                     if let Some(was) = was {
@@ -199,14 +709,57 @@ impl Base {
                 was = current.clone();
             }
 
+            let op = match op {
+                KeyedOp::Table(op) => op,
+                KeyedOp::DeleteIf(_, conditions) => {
+                    let matches = current
+                        .as_ref()
+                        .map(|row| {
+                            conditions
+                                .iter()
+                                .all(|&(c, ref cond)| cond.matches(&row[c]))
+                        })
+                        .unwrap_or(false);
+                    if matches {
+                        current = None;
+                        let count = outcomes.deleted.iter_mut().find(|&&mut (p, _)| p == i);
+                        count.unwrap().1 += 1;
+                    }
+                    continue;
+                }
+            };
+
             let update = match op {
-                TableOperation::Insert(row) => {
-                    if let Some(ref was) = was {
-                        eprintln!("base ignoring {:?} since it already has {:?}", row, was);
-                    } else {
-                        //assert!(was.is_none());
+                TableOperation::Insert(row) => match self.on_duplicate {
+                    DuplicateKeyPolicy::Ignore => {
+                        if was.is_some() {
+                            outcomes.ignored.push(i);
+                        } else {
+                            //assert!(was.is_none());
+                            current = Some(Cow::Owned(row));
+                        }
+                        continue;
+                    }
+                    DuplicateKeyPolicy::Reject => {
+                        // this also catches an earlier insert of the same key in this batch
+                        if current.is_some() {
+                            outcomes.rejected.push(i);
+                        } else {
+                            current = Some(Cow::Owned(row));
+                        }
+                        continue;
+                    }
+                    DuplicateKeyPolicy::Update => {
+                        let mut row = row;
+                        self.keep_created_at(&mut row, &current);
                         current = Some(Cow::Owned(row));
+                        continue;
                     }
+                },
+                TableOperation::Upsert(mut row) => {
+                    // the old row, if any, is retracted once all operations on this key are done
+                    self.keep_created_at(&mut row, &current);
+                    current = Some(Cow::Owned(row));
                     continue;
                 }
                 TableOperation::Delete { .. } => {
@@ -218,7 +771,19 @@ impl Base {
                     }
                     continue;
                 }
+                TableOperation::DeleteWhere { .. } => unreachable!(),
                 TableOperation::Update { set, .. } => set,
+                TableOperation::ConditionalUpdate { expected, set, .. } => {
+                    let matches = current
+                        .as_ref()
+                        .map(|row| expected.iter().all(|&(col, ref v)| row[col] == *v))
+                        .unwrap_or(false);
+                    if !matches {
+                        outcomes.unapplied.push(i);
+                        continue;
+                    }
+                    set
+                }
                 TableOperation::InsertOrUpdate { row, update } => {
                     if current.is_none() {
                         current = Some(Cow::Owned(row));
@@ -267,7 +832,286 @@ impl Base {
             self.fix(r);
         }
 
-        results.into()
+        self.track_expiry(&results);
+
+        outcomes.rejected.sort_unstable();
+        outcomes.unapplied.sort_unstable();
+        outcomes.ignored.sort_unstable();
+        (results.into(), outcomes)
+    }
+
+    /// Give a row that replaces `current` the creation time of the row it replaces.
+    fn keep_created_at(&self, row: &mut Vec<DataType>, current: &Option<Cow<'_, [DataType]>>) {
+        if let (Some(col), Some(current)) = (self.created_at, current) {
+            row[col] = current[col].clone();
+        }
+    }
+
+    /// Fill in the auto-timestamp columns of a batch of operations with the time `now`.
+    ///
+    /// Inserted rows get `now` as both their creation and update time, and updates set the update
+    /// time. This happens before the operations are queued for group commit, so the timestamps
+    /// are part of the operations from then on and are never assigned again.
+    pub(in crate::node) fn stamp(&self, ops: &mut [TableOperation], now: NaiveDateTime) {
+        if !self.has_auto_timestamps() {
+            return;
+        }
+
+        let now = DataType::Timestamp(now);
+        let stamp_row = |row: &mut Vec<DataType>| {
+            for &col in self.created_at.iter().chain(self.updated_at.iter()) {
+                if col < row.len() {
+                    row[col] = now.clone();
+                }
+            }
+        };
+        let stamp_update = |set: &mut Vec<Modification>| {
+            if let Some(col) = self.updated_at {
+                if set.len() <= col {
+                    set.resize(col + 1, Modification::None);
+                }
+                set[col] = Modification::Set(now.clone());
+            }
+        };
+
+        for op in ops {
+            match *op {
+                TableOperation::Insert(ref mut row) | TableOperation::Upsert(ref mut row) => {
+                    stamp_row(row)
+                }
+                TableOperation::InsertOrUpdate {
+                    ref mut row,
+                    ref mut update,
+                } => {
+                    stamp_row(row);
+                    stamp_update(update);
+                }
+                TableOperation::Update { ref mut set, .. }
+                | TableOperation::ConditionalUpdate { ref mut set, .. } => stamp_update(set),
+                TableOperation::Delete { .. } | TableOperation::DeleteWhere { .. } => {}
+            }
+        }
+    }
+
+    /// Whether a value is no larger than the base allows.
+    fn fits(&self, v: &DataType) -> bool {
+        match *v {
+            DataType::Bytes(ref b) => b.len() <= self.max_bytes,
+            _ => true,
+        }
+    }
+
+    /// The positions of the operations in a batch that write a blob larger than the base allows.
+    pub(in crate::node) fn oversized(&self, ops: &[TableOperation]) -> Vec<usize> {
+        let sets_fit = |set: &[Modification]| {
+            set.iter().all(|m| match *m {
+                Modification::Set(ref v) | Modification::Apply(_, ref v) => self.fits(v),
+                Modification::None => true,
+            })
+        };
+        ops.iter()
+            .enumerate()
+            .filter(|&(_, op)| match *op {
+                TableOperation::Insert(ref row) | TableOperation::Upsert(ref row) => {
+                    !row.iter().all(|v| self.fits(v))
+                }
+                TableOperation::InsertOrUpdate {
+                    ref row,
+                    ref update,
+                } => !row.iter().all(|v| self.fits(v)) || !sets_fit(update),
+                TableOperation::Update { ref set, .. }
+                | TableOperation::ConditionalUpdate { ref set, .. } => !sets_fit(set),
+                TableOperation::Delete { .. } | TableOperation::DeleteWhere { .. } => false,
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Check the values written by a batch of operations, turn the text written to JSON columns
+    /// into canonical documents, and collate the text written to case-insensitive columns.
+    ///
+    /// Operations that write anything other than a valid JSON document or `NULL` to a JSON
+    /// column, or that write a blob larger than the base allows, are dropped and reported as
+    /// invalid. The remaining operations are returned along with their positions in the batch.
+    fn check_values(
+        &self,
+        ops: Vec<TableOperation>,
+        outcomes: &mut Outcomes,
+    ) -> Vec<(usize, TableOperation)> {
+        let parse = |v: &mut DataType| -> bool {
+            if v.is_string() {
+                match DataType::parse_json((&*v).into()) {
+                    Ok(doc) => *v = doc,
+                    Err(_) => return false,
+                }
+            }
+            v.is_json() || v.is_none()
+        };
+        // writes from clients have been checked already, but bulk loads have not
+        let fits = |v: &DataType| self.fits(v);
+        let collate = |v: &mut DataType| {
+            if v.is_string() && !v.is_case_insensitive() {
+                *v = DataType::case_insensitive((&*v).into());
+            }
+        };
+        let parse_row = |row: &mut Vec<DataType>| {
+            for &col in &self.case_insensitive_columns {
+                if let Some(v) = row.get_mut(col) {
+                    collate(v);
+                }
+            }
+            row.iter().all(fits)
+                && self
+                    .json_columns
+                    .iter()
+                    .all(|&col| col >= row.len() || parse(&mut row[col]))
+        };
+        let parse_update = |set: &mut Vec<Modification>| {
+            for &col in &self.case_insensitive_columns {
+                if let Some(Modification::Set(ref mut v)) = set.get_mut(col) {
+                    collate(v);
+                }
+            }
+            let sets_fit = set.iter().all(|m| match *m {
+                Modification::Set(ref v) | Modification::Apply(_, ref v) => fits(v),
+                Modification::None => true,
+            });
+            sets_fit
+                && self.json_columns.iter().all(|&col| match set.get_mut(col) {
+                    Some(Modification::Set(ref mut v)) => parse(v),
+                    Some(Modification::Apply(..)) => false,
+                    Some(Modification::None) | None => true,
+                })
+        };
+
+        ops.into_iter()
+            .enumerate()
+            .filter_map(|(i, mut op)| {
+                let valid = match op {
+                    TableOperation::Insert(ref mut row) | TableOperation::Upsert(ref mut row) => {
+                        parse_row(row)
+                    }
+                    TableOperation::InsertOrUpdate {
+                        ref mut row,
+                        ref mut update,
+                    } => parse_row(row) && parse_update(update),
+                    TableOperation::Update { ref mut set, .. }
+                    | TableOperation::ConditionalUpdate { ref mut set, .. } => parse_update(set),
+                    TableOperation::Delete { .. } | TableOperation::DeleteWhere { .. } => true,
+                };
+                if valid {
+                    Some((i, op))
+                } else {
+                    outcomes.invalid.push(i);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Keep the keys ordered by timestamp in sync with records about to be applied to the base.
+    fn track_expiry(&mut self, records: &[Record]) {
+        let (col, expiry) = match (&self.ttl, &mut self.expiry) {
+            (Some(ttl), Some(expiry)) => (ttl.column, expiry),
+            _ => return,
+        };
+        let key_cols = &self.primary_key.as_ref().unwrap()[..];
+
+        for r in records {
+            let ts = match r[col] {
+                DataType::Timestamp(ts) => ts,
+                _ => continue,
+            };
+            let key = key_cols.iter().map(|&c| r[c].clone()).collect();
+            if r.is_positive() {
+                expiry.insert((ts, key));
+            } else {
+                expiry.remove(&(ts, key));
+            }
+        }
+    }
+
+    /// Retract the oldest rows that have expired as of `now`, at most one chunk of them.
+    ///
+    /// Rows whose timestamp column is not a timestamp never expire.
+    pub(in crate::node) fn expire(
+        &mut self,
+        us: LocalNodeIndex,
+        now: NaiveDateTime,
+        state: &StateMap,
+    ) -> Records {
+        let ttl = self
+            .ttl
+            .as_ref()
+            .expect("expiring rows of a base without a TTL");
+        let (col, chunk) = (ttl.column, ttl.chunk);
+        let cutoff = match chrono::Duration::from_std(ttl.after) {
+            Ok(after) => now - after,
+            Err(_) => return Records::default(),
+        };
+
+        let key_cols = &self.primary_key.as_ref().unwrap()[..];
+        let db = state
+            .get(us)
+            .expect("base with primary key must be materialized");
+        let expiry = self.expiry.get_or_insert_with(|| {
+            db.cloned_records()
+                .into_iter()
+                .filter_map(|row| match row[col] {
+                    DataType::Timestamp(ts) => {
+                        Some((ts, key_cols.iter().map(|&c| row[c].clone()).collect()))
+                    }
+                    _ => None,
+                })
+                .collect()
+        });
+
+        let due: Vec<_> = expiry
+            .iter()
+            .take_while(|&&(ts, _)| ts <= cutoff)
+            .take(chunk)
+            .cloned()
+            .collect();
+        let mut expired = Vec::with_capacity(due.len());
+        for entry in due {
+            if let LookupResult::Some(rows) = db.lookup(key_cols, &KeyType::from(&entry.1[..])) {
+                expired.extend(
+                    rows.into_iter()
+                        .filter(|r| r[col] == DataType::Timestamp(entry.0))
+                        .map(|r| Record::Negative(r.into_owned())),
+                );
+            }
+            expiry.remove(&entry);
+        }
+        expired.into()
+    }
+
+    /// Retract the rows that belong to other shards once this range-sharded base is split along
+    /// `boundaries`.
+    pub(in crate::node) fn split_off(
+        &mut self,
+        us: LocalNodeIndex,
+        boundaries: &[DataType],
+        state: &StateMap,
+    ) -> Records {
+        let col = self
+            .range_sharding
+            .as_ref()
+            .expect("re-splitting a base that is not range sharded")
+            .0;
+        // the expiry index is rebuilt from the remaining rows when it is next needed
+        self.expiry = None;
+
+        let shard = self.shard;
+        state
+            .get(us)
+            .expect("base with primary key must be materialized")
+            .cloned_records()
+            .into_iter()
+            .filter(|row| noria::shard_by_range(&row[col], boundaries) != shard)
+            .map(Record::Negative)
+            .collect::<Vec<_>>()
+            .into()
     }
 
     pub(in crate::node) fn suggest_indexes(&self, n: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
@@ -345,7 +1189,7 @@ mod tests {
         let mut n = n.finalize(&graph);
 
         let mut one = move |u: Vec<TableOperation>| {
-            let mut m = n.get_base_mut().unwrap().process(local, u, &states);
+            let (mut m, _) = n.get_base_mut().unwrap().process(local, u, &states);
             node::materialize(&mut m, None, states.get_mut(local));
             m
         };
@@ -401,6 +1245,394 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_assigns_auto_increment_keys() {
+        let mut b = Base::new(vec![]).with_key(vec![0]).with_auto_increment();
+        b.set_shard(1, 2);
+
+        // pretend that a previous incarnation already handed out key 5
+        let local = unsafe { LocalNodeIndex::make(0 as u32) };
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        let mut rs: Records = vec![vec![5.into(), "a".into()]].into();
+        state.process_records(&mut rs, None);
+        let mut states = StateMap::new();
+        states.insert(local, Box::new(state) as Box<dyn State>);
+
+        let mut ops = vec![
+            TableOperation::Insert(vec![DataType::None, "b".into()]),
+            TableOperation::Insert(vec![20.into(), "c".into()]),
+            TableOperation::Delete {
+                key: vec![5.into()],
+            },
+            TableOperation::Insert(vec![DataType::None, "d".into()]),
+        ];
+        let ids = b.assign_ids(local, &mut ops, &states);
+
+        // keys resume past the recovered state, skip explicitly chosen keys, and always shard back
+        // to the shard that assigned them.
+        assert_eq!(ids, vec![Some(7.into()), None, None, Some(23.into())]);
+        assert_eq!(ops[0], TableOperation::Insert(vec![7.into(), "b".into()]));
+        assert_eq!(ops[3], TableOperation::Insert(vec![23.into(), "d".into()]));
+    }
+
+    #[test]
+    fn it_validates_json_columns() {
+        let local = unsafe { LocalNodeIndex::make(0 as u32) };
+        let states = StateMap::new();
+
+        let mut b = Base::new(vec![]).with_json_columns(vec![1]);
+        let (rs, outcomes) = b.process(
+            local,
+            vec![
+                TableOperation::Insert(vec![1.into(), r#"{ "b": 1, "a": [] }"#.into()]),
+                TableOperation::Insert(vec![2.into(), "{ nope".into()]),
+                TableOperation::Insert(vec![3.into(), DataType::None]),
+                TableOperation::Insert(vec![4.into(), 4.into()]),
+            ],
+            &states,
+        );
+        assert_eq!(outcomes.invalid, vec![1, 3]);
+        let expected: Records = vec![
+            vec![1.into(), DataType::parse_json(r#"{"a":[],"b":1}"#).unwrap()],
+            vec![3.into(), DataType::None],
+        ]
+        .into();
+        assert_eq!(rs, expected);
+    }
+
+    #[test]
+    fn it_collates_case_insensitive_columns() {
+        let local = unsafe { LocalNodeIndex::make(0 as u32) };
+        let states = StateMap::new();
+
+        let mut b = Base::new(vec![]).with_case_insensitive_columns(vec![1]);
+        let (rs, _) = b.process(
+            local,
+            vec![TableOperation::Insert(vec![
+                "Straße".into(),
+                "Straße".into(),
+            ])],
+            &states,
+        );
+        let row = rs[0].rec();
+        assert!(!row[0].is_case_insensitive());
+        assert!(row[1].is_case_insensitive());
+        // the original form is kept, but compares equal to other casings
+        assert_eq!(row[1].to_string(), "\"Straße\"");
+        assert_eq!(row[1], "STRASSE".into());
+        assert_ne!(row[0], "STRASSE".into());
+    }
+
+    #[test]
+    fn it_rejects_oversized_blobs() {
+        let local = unsafe { LocalNodeIndex::make(0 as u32) };
+        let states = StateMap::new();
+
+        let mut b = Base::new(vec![]).with_max_bytes(4);
+        let (rs, outcomes) = b.process(
+            local,
+            vec![
+                TableOperation::Insert(vec![1.into(), vec![0u8; 4].into()]),
+                TableOperation::Insert(vec![2.into(), vec![0u8; 5].into()]),
+            ],
+            &states,
+        );
+        assert_eq!(outcomes.invalid, vec![1]);
+        let expected: Records = vec![vec![1.into(), vec![0u8; 4].into()]].into();
+        assert_eq!(rs, expected);
+
+        let ops = vec![
+            TableOperation::Insert(vec![1.into(), vec![0u8; 4].into()]),
+            TableOperation::Update {
+                key: vec![1.into()],
+                set: vec![Modification::None, Modification::Set(vec![0u8; 5].into())],
+            },
+            TableOperation::Delete {
+                key: vec![1.into()],
+            },
+        ];
+        assert_eq!(b.oversized(&ops), vec![1]);
+    }
+
+    #[test]
+    fn it_enforces_unique_keys() {
+        let local = unsafe { LocalNodeIndex::make(0 as u32) };
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        let mut rs: Records = vec![vec![1.into(), "a".into()]].into();
+        state.process_records(&mut rs, None);
+        let mut states = StateMap::new();
+        states.insert(local, Box::new(state) as Box<dyn State>);
+
+        // inserts of a key that exists, either before or earlier in the batch, are rejected
+        let mut b = Base::new(vec![])
+            .with_key(vec![0])
+            .with_duplicate_key_policy(DuplicateKeyPolicy::Reject);
+        let (rs, outcomes) = b.process(
+            local,
+            vec![
+                TableOperation::Insert(vec![2.into(), "b".into()]),
+                TableOperation::Insert(vec![1.into(), "x".into()]),
+                TableOperation::Insert(vec![2.into(), "c".into()]),
+            ],
+            &states,
+        );
+        assert_eq!(outcomes.rejected, vec![1, 2]);
+        let expected: Records = vec![vec![2.into(), "b".into()]].into();
+        assert_eq!(rs, expected);
+
+        // or turned into updates of the existing row
+        let mut b = Base::new(vec![])
+            .with_key(vec![0])
+            .with_duplicate_key_policy(DuplicateKeyPolicy::Update);
+        let (rs, outcomes) = b.process(
+            local,
+            vec![TableOperation::Insert(vec![1.into(), "x".into()])],
+            &states,
+        );
+        assert!(outcomes.rejected.is_empty());
+        let expected: Records = vec![
+            Record::Negative(vec![1.into(), "a".into()]),
+            Record::Positive(vec![1.into(), "x".into()]),
+        ]
+        .into();
+        assert_eq!(rs, expected);
+    }
+
+    #[test]
+    fn it_resolves_upserts() {
+        let local = unsafe { LocalNodeIndex::make(0 as u32) };
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        let mut rs: Records = vec![vec![1.into(), "a".into()]].into();
+        state.process_records(&mut rs, None);
+        let mut states = StateMap::new();
+        states.insert(local, Box::new(state) as Box<dyn State>);
+
+        // the existing row is retracted, and later upserts in the batch win
+        let mut b = Base::new(vec![]).with_key(vec![0]);
+        let (rs, _) = b.process(
+            local,
+            vec![
+                TableOperation::Upsert(vec![1.into(), "b".into()]),
+                TableOperation::Upsert(vec![2.into(), "c".into()]),
+                TableOperation::Upsert(vec![1.into(), "d".into()]),
+            ],
+            &states,
+        );
+        let expected: Records = vec![
+            Record::Negative(vec![1.into(), "a".into()]),
+            Record::Positive(vec![1.into(), "d".into()]),
+            Record::Positive(vec![2.into(), "c".into()]),
+        ]
+        .into();
+        assert_eq!(rs, expected);
+    }
+
+    #[test]
+    fn it_applies_conditional_updates_on_match() {
+        let local = unsafe { LocalNodeIndex::make(0 as u32) };
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        let mut rs: Records = vec![vec![5.into(), 10.into()]].into();
+        state.process_records(&mut rs, None);
+        let mut states = StateMap::new();
+        states.insert(local, Box::new(state) as Box<dyn State>);
+
+        // the second swap expects the value from before the first one, so it does not apply
+        let swap = |from: i32, to: i32| TableOperation::ConditionalUpdate {
+            key: vec![5.into()],
+            expected: vec![(1, from.into())],
+            set: vec![Modification::None, Modification::Set(to.into())],
+        };
+        let mut b = Base::new(vec![]).with_key(vec![0]);
+        let (rs, outcomes) = b.process(local, vec![swap(10, 11), swap(10, 12)], &states);
+        assert_eq!(outcomes.unapplied, vec![1]);
+        let expected: Records = vec![
+            Record::Negative(vec![5.into(), 10.into()]),
+            Record::Positive(vec![5.into(), 11.into()]),
+        ]
+        .into();
+        assert_eq!(rs, expected);
+    }
+
+    #[test]
+    fn it_deletes_by_filter() {
+        let local = unsafe { LocalNodeIndex::make(0 as u32) };
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        let mut rs: Records = vec![
+            vec![1.into(), "u".into()],
+            vec![2.into(), "v".into()],
+            vec![3.into(), "u".into()],
+        ]
+        .into();
+        state.process_records(&mut rs, None);
+        let mut states = StateMap::new();
+        states.insert(local, Box::new(state) as Box<dyn State>);
+
+        // rows written earlier in the batch are deleted too, but not those written after
+        let mut b = Base::new(vec![]).with_key(vec![0]);
+        let (rs, outcomes) = b.process(
+            local,
+            vec![
+                TableOperation::Insert(vec![4.into(), "u".into()]),
+                TableOperation::DeleteWhere {
+                    conditions: vec![(1, ColumnCondition::Equal("u".into()))],
+                    limit: 10,
+                },
+                TableOperation::Insert(vec![5.into(), "u".into()]),
+            ],
+            &states,
+        );
+        assert_eq!(outcomes.deleted, vec![(1, 3)]);
+        let expected: Records = vec![
+            Record::Negative(vec![1.into(), "u".into()]),
+            Record::Negative(vec![3.into(), "u".into()]),
+            Record::Positive(vec![5.into(), "u".into()]),
+        ]
+        .into();
+        assert_eq!(rs, expected);
+    }
+
+    #[test]
+    fn it_stamps_auto_timestamps() {
+        use chrono::NaiveDate;
+
+        let t0 = NaiveDate::from_ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let t1 = t0 + chrono::Duration::hours(1);
+
+        let local = unsafe { LocalNodeIndex::make(0 as u32) };
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        let mut states = StateMap::new();
+        states.insert(local, Box::new(state) as Box<dyn State>);
+
+        let mut b = Base::new(vec![])
+            .with_key(vec![0])
+            .with_created_at(2)
+            .with_updated_at(3);
+
+        // client-provided times are overwritten, and updates only refresh the update time
+        let mut ops = vec![
+            TableOperation::Insert(vec![1.into(), "a".into(), 5.into(), 5.into()]),
+            TableOperation::Update {
+                key: vec![2.into()],
+                set: vec![Modification::None, Modification::Set("b".into())],
+            },
+        ];
+        b.stamp(&mut ops, t0);
+        assert_eq!(
+            ops[0],
+            TableOperation::Insert(vec![
+                1.into(),
+                "a".into(),
+                DataType::Timestamp(t0),
+                DataType::Timestamp(t0),
+            ])
+        );
+        assert_eq!(
+            ops[1],
+            TableOperation::Update {
+                key: vec![2.into()],
+                set: vec![
+                    Modification::None,
+                    Modification::Set("b".into()),
+                    Modification::None,
+                    Modification::Set(DataType::Timestamp(t0)),
+                ],
+            }
+        );
+        let (mut rs, _) = b.process(local, ops, &states);
+        states
+            .get_mut(local)
+            .unwrap()
+            .process_records(&mut rs, None);
+
+        // a row that replaces an existing one keeps its creation time
+        let mut ops = vec![TableOperation::Upsert(vec![
+            1.into(),
+            "c".into(),
+            DataType::None,
+            DataType::None,
+        ])];
+        b.stamp(&mut ops, t1);
+        let (rs, _) = b.process(local, ops, &states);
+        let expected: Records = vec![
+            Record::Negative(vec![
+                1.into(),
+                "a".into(),
+                DataType::Timestamp(t0),
+                DataType::Timestamp(t0),
+            ]),
+            Record::Positive(vec![
+                1.into(),
+                "c".into(),
+                DataType::Timestamp(t0),
+                DataType::Timestamp(t1),
+            ]),
+        ]
+        .into();
+        assert_eq!(rs, expected);
+    }
+
+    #[test]
+    fn it_expires_rows_in_chunks() {
+        use chrono::NaiveDate;
+
+        let t0 = NaiveDate::from_ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let at = |hours| DataType::Timestamp(t0 + chrono::Duration::hours(hours));
+
+        let local = unsafe { LocalNodeIndex::make(0 as u32) };
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        let mut rs: Records = vec![
+            vec![1.into(), at(1)],
+            vec![2.into(), at(48)],
+            vec![3.into(), at(0)],
+            vec![4.into(), DataType::None],
+        ]
+        .into();
+        state.process_records(&mut rs, None);
+        let mut states = StateMap::new();
+        states.insert(local, Box::new(state) as Box<dyn State>);
+
+        let mut b = Base::new(vec![])
+            .with_key(vec![0])
+            .with_ttl(1, Duration::from_secs(24 * 60 * 60))
+            .with_ttl_scan(Duration::from_secs(1), 1);
+        let now = t0 + chrono::Duration::hours(26);
+
+        // the oldest rows go first, one chunk at a time
+        let expected: Records = vec![Record::Negative(vec![3.into(), at(0)])].into();
+        assert_eq!(b.expire(local, now, &states), expected);
+        let expected: Records = vec![Record::Negative(vec![1.into(), at(1)])].into();
+        assert_eq!(b.expire(local, now, &states), expected);
+        assert!(b.expire(local, now, &states).is_empty());
+
+        // rows written after the first scan are tracked too
+        let (mut rs, _) = b.process(
+            local,
+            vec![TableOperation::Insert(vec![5.into(), at(2)])],
+            &states,
+        );
+        states
+            .get_mut(local)
+            .unwrap()
+            .process_records(&mut rs, None);
+        let expected: Records = vec![Record::Negative(vec![5.into(), at(2)])].into();
+        assert_eq!(b.expire(local, now, &states), expected);
+        assert!(b
+            .expire(local, now + chrono::Duration::hours(45), &states)
+            .is_empty());
+        let expected: Records = vec![Record::Negative(vec![2.into(), at(48)])].into();
+        assert_eq!(
+            b.expire(local, now + chrono::Duration::hours(46), &states),
+            expected
+        );
+    }
+
     #[test]
     fn lots_of_changes_in_same_batch() {
         let state = MemoryState::default();
@@ -0,0 +1,114 @@
+use std::collections::{HashSet, VecDeque};
+use std::time;
+
+/// How many client operation ids a base remembers, and for how long, so that a write that a
+/// client retries with the same id is only applied once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DedupWindow {
+    /// The most ids remembered at once. Applying a write while this many are remembered forgets
+    /// the oldest one.
+    pub capacity: usize,
+    /// How long an id is remembered for after its write was applied.
+    pub ttl: time::Duration,
+}
+
+impl Default for DedupWindow {
+    fn default() -> Self {
+        DedupWindow {
+            capacity: 100_000,
+            ttl: time::Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// The ids of the client operations that a base has applied recently, in the order they were
+/// applied.
+///
+/// Ids are forgotten strictly oldest first, once they are older than the window's ttl or to make
+/// room for a newer one, so the same sequence of writes always leaves the same ids behind.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AppliedOps {
+    /// Each remembered id with when its write was applied, in nanoseconds since the Unix epoch.
+    order: VecDeque<(u64, u64)>,
+    ids: HashSet<u64>,
+}
+
+impl AppliedOps {
+    /// Remember the given ids again, such as after recovering them from durable storage.
+    pub(crate) fn recover(mut ops: Vec<(u64, u64)>) -> Self {
+        ops.sort_by_key(|&(id, at)| (at, id));
+        AppliedOps {
+            ids: ops.iter().map(|&(id, _)| id).collect(),
+            order: ops.into(),
+        }
+    }
+
+    /// Remember that the operation `id` was applied at `now`, unless it is remembered already.
+    ///
+    /// Returns `None` if the operation had already been applied, and otherwise the ids that had to
+    /// be forgotten to stay within `window`.
+    pub(crate) fn apply(&mut self, id: u64, now: u64, window: DedupWindow) -> Option<Vec<u64>> {
+        let ttl = window.ttl.as_nanos() as u64;
+        let mut evicted = Vec::new();
+        while let Some(&(_, at)) = self.order.front() {
+            if now.saturating_sub(at) < ttl {
+                break;
+            }
+            self.forget_oldest(&mut evicted);
+        }
+        if self.ids.contains(&id) {
+            return None;
+        }
+
+        if window.capacity == 0 {
+            return Some(evicted);
+        }
+        while self.order.len() >= window.capacity {
+            self.forget_oldest(&mut evicted);
+        }
+        self.ids.insert(id);
+        self.order.push_back((id, now));
+        Some(evicted)
+    }
+
+    fn forget_oldest(&mut self, evicted: &mut Vec<u64>) {
+        if let Some((id, _)) = self.order.pop_front() {
+            self.ids.remove(&id);
+            evicted.push(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_repeats() {
+        let window = DedupWindow {
+            capacity: 2,
+            ttl: time::Duration::from_secs(60),
+        };
+        let mut ops = AppliedOps::default();
+        assert_eq!(ops.apply(1, 0, window), Some(vec![]));
+        assert_eq!(ops.apply(1, 1, window), None);
+        assert_eq!(ops.apply(2, 2, window), Some(vec![]));
+        // full, so the oldest id is forgotten
+        assert_eq!(ops.apply(3, 3, window), Some(vec![1]));
+        assert_eq!(ops.apply(1, 4, window), Some(vec![2]));
+        assert_eq!(ops.apply(3, 5, window), None);
+    }
+
+    #[test]
+    fn expires() {
+        let window = DedupWindow {
+            capacity: 10,
+            ttl: time::Duration::from_nanos(10),
+        };
+        let mut ops = AppliedOps::recover(vec![(2, 5), (1, 0)]);
+        assert_eq!(ops.apply(1, 9, window), None);
+        assert_eq!(ops.apply(3, 12, window), Some(vec![1]));
+        assert_eq!(ops.apply(2, 14, window), None);
+        assert_eq!(ops.apply(2, 15, window), Some(vec![2]));
+    }
+}
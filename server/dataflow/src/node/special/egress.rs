@@ -1,17 +1,65 @@
 use crate::prelude::*;
+use noria::debug::bookkeeping::{EdgeFrontier, LabelEntry};
 use std::collections::HashMap;
+use std::time;
+
+/// When an egress coalesces the regular updates it sends to a downstream domain shard.
+///
+/// Updates are held back until they have waited for `max_delay`, or until those for the same
+/// domain shard add up to `max_records` records or about `max_bytes` bytes, and are then sent
+/// together as a single packet. Replays, evictions, and updates that a client is waiting for are
+/// sent right away, along with whatever was held back before them.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EgressBatching {
+    /// The longest an update is held back.
+    pub max_delay: time::Duration,
+    /// How many records may be held back for one domain shard.
+    pub max_records: usize,
+    /// Roughly how many bytes of records may be held back for one domain shard.
+    pub max_bytes: usize,
+}
+
+/// Updates held back for a downstream domain shard.
+#[derive(Default)]
+struct Held {
+    packets: Vec<Box<Packet>>,
+    records: usize,
+    bytes: usize,
+    since: Option<time::Instant>,
+}
 
 #[derive(Serialize, Deserialize)]
 struct EgressTx {
     node: NodeIndex,
     local: LocalNodeIndex,
     dest: ReplicaAddr,
+    #[serde(skip)]
+    held: Held,
+    /// The latest batch from each base table shard that was sent to `dest`.
+    #[serde(skip)]
+    sent: HashMap<(NodeIndex, usize), u64>,
+}
+
+impl EgressTx {
+    fn flush(&mut self, output: &mut dyn Executor) {
+        self.held.since = None;
+        self.held.records = 0;
+        self.held.bytes = 0;
+        let mut packets = std::mem::replace(&mut self.held.packets, Vec::new());
+        match packets.len() {
+            0 => {}
+            1 => output.send(self.dest, packets.pop().unwrap()),
+            _ => output.send(self.dest, Box::new(Packet::Batch { packets })),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Egress {
     txs: Vec<EgressTx>,
     tags: HashMap<Tag, NodeIndex>,
+    #[serde(skip)]
+    batching: Option<EgressBatching>,
 }
 
 impl Clone for Egress {
@@ -21,6 +69,7 @@ impl Clone for Egress {
         Self {
             txs: Vec::new(),
             tags: self.tags.clone(),
+            batching: self.batching,
         }
     }
 }
@@ -30,6 +79,7 @@ impl Default for Egress {
         Self {
             tags: Default::default(),
             txs: Default::default(),
+            batching: None,
         }
     }
 }
@@ -40,9 +90,84 @@ impl Egress {
             node: dst_g,
             local: dst_l,
             dest: addr,
+            held: Default::default(),
+            sent: Default::default(),
         });
     }
 
+    /// Stop sending to the ingress node `dst_g`, dropping anything held back for it.
+    pub fn remove_tx(&mut self, dst_g: NodeIndex) {
+        self.txs.retain(|tx| tx.node != dst_g);
+        self.tags.retain(|_, &mut dst| dst != dst_g);
+    }
+
+    pub fn set_batching(&mut self, batching: Option<EgressBatching>) {
+        self.batching = batching;
+    }
+
+    /// How long until held back updates are due to be sent, if any are held back.
+    pub fn next_flush(&self, now: time::Instant) -> Option<time::Duration> {
+        let max_delay = self.batching?.max_delay;
+        self.txs
+            .iter()
+            .filter_map(|tx| tx.held.since)
+            .map(|since| max_delay.checked_sub(now - since).unwrap_or_default())
+            .min()
+    }
+
+    /// Send the updates that have been held back for as long as they may be.
+    pub fn flush_expired(&mut self, now: time::Instant, output: &mut dyn Executor) {
+        let max_delay = match self.batching {
+            Some(b) => b.max_delay,
+            None => return,
+        };
+        for tx in &mut self.txs {
+            if tx.held.since.map(|since| now - since >= max_delay) == Some(true) {
+                tx.flush(output);
+            }
+        }
+    }
+
+    /// Send all the updates that are held back, however long they have been.
+    pub fn flush_all(&mut self, output: &mut dyn Executor) {
+        for tx in &mut self.txs {
+            tx.flush(output);
+        }
+    }
+
+    /// The latest batch from each base table shard that this egress, `node`, has sent along each
+    /// of its edges.
+    pub fn frontiers(&self, node: NodeIndex) -> Vec<EdgeFrontier> {
+        self.txs
+            .iter()
+            .map(|tx| EdgeFrontier {
+                egress: node,
+                ingress: tx.node,
+                domain: tx.dest.0,
+                shard: tx.dest.1,
+                sent: tx
+                    .sent
+                    .iter()
+                    .map(|(&(base, shard), &seq)| LabelEntry { base, shard, seq })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// How many updates are held back, and roughly how many bytes of records they carry.
+    pub fn held(&self) -> (usize, usize) {
+        self.txs.iter().fold((0, 0), |(packets, bytes), tx| {
+            (packets + tx.held.packets.len(), bytes + tx.held.bytes)
+        })
+    }
+
+    /// How many records the held back updates carry, and roughly how many bytes they take up.
+    pub fn held_records(&self) -> (usize, usize) {
+        self.txs.iter().fold((0, 0), |(records, bytes), tx| {
+            (records + tx.held.records, bytes + tx.held.bytes)
+        })
+    }
+
     pub fn add_tag(&mut self, tag: Tag, dst: NodeIndex) {
         self.tags.insert(tag, dst);
     }
@@ -56,6 +181,7 @@ impl Egress {
         let &mut Self {
             ref mut txs,
             ref tags,
+            batching,
         } = self;
 
         // send any queued updates to all external children
@@ -95,8 +221,44 @@ impl Egress {
             // in which case it wants to know about the shard
             m.link_mut().src = unsafe { LocalNodeIndex::make(shard as u32) };
             m.link_mut().dst = tx.local;
+            m.mark_sent();
+
+            if let Packet::Message { label, .. } = *m {
+                let seq = tx.sent.entry((label.base, label.shard)).or_insert(0);
+                *seq = std::cmp::max(*seq, label.seq);
+            }
 
-            output.send(tx.dest, m);
+            // regular updates that no client is waiting for may be held back for a little while
+            let size = match *m {
+                Packet::Message {
+                    ref data, label, ..
+                } if batching.is_some() && !label.tracked => Some((
+                    data.len(),
+                    data.iter()
+                        .map(|r| r.deep_size_of() as usize)
+                        .sum::<usize>(),
+                )),
+                _ => None,
+            };
+            match (batching, size) {
+                (Some(batching), Some((records, bytes))) => {
+                    tx.held.records += records;
+                    tx.held.bytes += bytes;
+                    tx.held.since.get_or_insert_with(time::Instant::now);
+                    tx.held.packets.push(m);
+                    if tx.held.records >= batching.max_records
+                        || tx.held.bytes >= batching.max_bytes
+                    {
+                        tx.flush(output);
+                    }
+                }
+                (Some(_), None) => {
+                    // this can't wait, and must not overtake what's been held back
+                    tx.held.packets.push(m);
+                    tx.flush(output);
+                }
+                (None, _) => output.send(tx.dest, m),
+            }
             if take {
                 break;
             }
@@ -1,4 +1,5 @@
 mod base;
+mod dedup;
 mod egress;
 mod reader;
 mod sharder;
@@ -6,7 +7,8 @@ mod sharder;
 pub struct Ingress;
 pub struct Source;
 
-pub use self::base::Base;
-pub use self::egress::Egress;
+pub use self::base::{Base, DuplicateKeyPolicy, RangeSplit, Ttl};
+pub use self::dedup::DedupWindow;
+pub use self::egress::{Egress, EgressBatching};
 pub use self::reader::Reader;
-pub use self::sharder::Sharder;
+pub use self::sharder::{HotKeySalting, Sharder};
@@ -1,13 +1,31 @@
 use crate::backlog;
+use crate::payload::Label;
 use crate::prelude::*;
+use nom_sql::OrderType;
+use std::io;
 
 #[derive(Serialize, Deserialize)]
 pub struct Reader {
     #[serde(skip)]
     writer: Option<backlog::WriteHandle>,
+    /// Where evicted keys go, if they are spilled to disk rather than dropped.
+    #[serde(skip)]
+    spill: Option<backlog::Spill>,
+    /// Keys that were evicted while they held no rows, if the reader remembers them.
+    #[serde(skip)]
+    negative: Option<backlog::NegativeCache>,
 
     for_node: NodeIndex,
     state: Option<Vec<usize>>,
+    ordered: bool,
+    row_order: Option<Vec<(usize, OrderType)>>,
+    eviction: backlog::EvictionPolicy,
+    /// Columns that come from dropped base columns, and the value they are shown with instead.
+    hidden: Vec<(usize, DataType)>,
+    /// The number of paths through the graph from each base table to the reader, where there are
+    /// several.
+    #[serde(default)]
+    paths: Vec<(NodeIndex, usize)>,
 }
 
 impl Clone for Reader {
@@ -15,7 +33,14 @@ impl Clone for Reader {
         assert!(self.writer.is_none());
         Reader {
             writer: None,
+            spill: None,
+            negative: None,
             state: self.state.clone(),
+            ordered: self.ordered,
+            row_order: self.row_order.clone(),
+            eviction: self.eviction,
+            hidden: self.hidden.clone(),
+            paths: self.paths.clone(),
             for_node: self.for_node,
         }
     }
@@ -25,8 +50,23 @@ impl Reader {
     pub fn new(for_node: NodeIndex) -> Self {
         Reader {
             writer: None,
+            spill: None,
+            negative: None,
             state: None,
+            ordered: false,
+            row_order: None,
+            eviction: Default::default(),
+            hidden: Vec::new(),
+            paths: Vec::new(),
+            for_node,
+        }
+    }
+
+    /// A reader configured like this one, but for the node `for_node`.
+    pub fn mirror_for(&self, for_node: NodeIndex) -> Self {
+        Reader {
             for_node,
+            ..self.clone()
         }
     }
 
@@ -48,7 +88,14 @@ impl Reader {
     pub(in crate::node) fn take(&mut self) -> Self {
         Self {
             writer: self.writer.take(),
+            spill: self.spill.take(),
+            negative: self.negative.take(),
             state: self.state.clone(),
+            ordered: self.ordered,
+            row_order: self.row_order.clone(),
+            eviction: self.eviction,
+            hidden: self.hidden.clone(),
+            paths: self.paths.clone(),
             for_node: self.for_node,
         }
     }
@@ -64,11 +111,24 @@ impl Reader {
         }
     }
 
-    pub(crate) fn set_write_handle(&mut self, wh: backlog::WriteHandle) {
+    pub(crate) fn set_write_handle(&mut self, mut wh: backlog::WriteHandle) {
         assert!(self.writer.is_none());
+        for &(base, paths) in &self.paths {
+            wh.set_paths(base, paths);
+        }
         self.writer = Some(wh);
     }
 
+    /// Spill the keys this reader evicts to `spill`, rather than dropping them.
+    pub(crate) fn set_spill(&mut self, spill: backlog::Spill) {
+        self.spill = Some(spill);
+    }
+
+    /// Remember the keys this reader evicts while they hold no rows in `negative`.
+    pub(crate) fn set_negative_cache(&mut self, negative: backlog::NegativeCache) {
+        self.negative = Some(negative);
+    }
+
     pub fn key(&self) -> Option<&[usize]> {
         self.state.as_ref().map(|s| &s[..])
     }
@@ -81,6 +141,136 @@ impl Reader {
         }
     }
 
+    /// Keep this reader's state in key order, so that it can serve range lookups.
+    pub fn set_ordered(&mut self) {
+        self.ordered = true;
+    }
+
+    pub fn is_ordered(&self) -> bool {
+        self.ordered
+    }
+
+    /// Return the rows for each key sorted by the given columns.
+    pub fn set_row_order(&mut self, order: Vec<(usize, OrderType)>) {
+        self.row_order = Some(order);
+    }
+
+    pub fn row_order(&self) -> Option<&[(usize, OrderType)]> {
+        self.row_order.as_deref()
+    }
+
+    /// The reader hears of each batch of writes to a base table once along each of the given
+    /// number of paths from it.
+    pub fn set_paths(&mut self, paths: Vec<(NodeIndex, usize)>) {
+        self.paths = paths;
+    }
+
+    /// Evict keys from this reader according to the given policy if it is partial.
+    pub fn set_eviction_policy(&mut self, policy: backlog::EvictionPolicy) {
+        self.eviction = policy;
+    }
+
+    pub fn eviction_policy(&self) -> backlog::EvictionPolicy {
+        self.eviction
+    }
+
+    /// The reader's eviction policy, the number of keys it has evicted, and the number of misses
+    /// on keys after they were evicted.
+    pub(crate) fn eviction_stats(&self) -> Option<(backlog::EvictionPolicy, u64, u64)> {
+        self.writer
+            .as_ref()
+            .map(backlog::WriteHandle::eviction_stats)
+    }
+
+    /// The latest batch of writes from each base shard that the reader reflects.
+    pub(crate) fn frontier(&self) -> Option<Vec<((NodeIndex, usize), u64)>> {
+        self.writer.as_ref().map(backlog::WriteHandle::frontier)
+    }
+
+    /// The time, in nanoseconds since the Unix epoch, up to which the reader reflects every batch
+    /// of writes from the base shards it has heard from.
+    pub(crate) fn applied(&self) -> Option<u64> {
+        self.writer.as_ref().and_then(backlog::WriteHandle::applied)
+    }
+
+    /// The number of misses answered from the reader's spill, and the number answered with a
+    /// replay, if it spills evicted keys.
+    pub(crate) fn spill_stats(&self) -> Option<(u64, u64)> {
+        self.spill.as_ref().map(backlog::Spill::stats)
+    }
+
+    /// The number of misses filled with nothing because the key was remembered to be empty, the
+    /// number that were not, and the number of remembered keys forgotten because of a write, if
+    /// the reader remembers empty keys.
+    pub(crate) fn negative_cache_stats(&self) -> Option<(u64, u64, u64)> {
+        self.negative.as_ref().map(backlog::NegativeCache::stats)
+    }
+
+    /// Fill the hole for `key` with nothing if the key was evicted while it held no rows and has
+    /// not been written to since, and return whether it was.
+    pub(crate) fn fill_if_known_empty(&mut self, key: &[DataType]) -> bool {
+        match (self.negative.as_mut(), self.writer.as_mut()) {
+            (Some(negative), Some(w)) if negative.take(key) => {
+                w.mut_with_key(key).mark_filled();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Fill the hole for `key` from the reader's spill, and return whether it could be.
+    ///
+    /// If the spill fails, it is dropped, and later misses are all answered with replays.
+    pub(crate) fn unspill(&mut self, key: &[DataType]) -> io::Result<bool> {
+        let (spill, w) = match (self.spill.as_mut(), self.writer.as_mut()) {
+            (Some(spill), Some(w)) => (spill, w),
+            _ => return Ok(false),
+        };
+        match spill.take(key) {
+            Ok(Some(rows)) => {
+                w.mut_with_key(key).mark_filled();
+                w.add(rows.into_iter().map(Record::Positive));
+                Ok(true)
+            }
+            Ok(None) => {
+                spill.missed(1);
+                Ok(false)
+            }
+            Err(e) => {
+                self.spill = None;
+                Err(e)
+            }
+        }
+    }
+
+    /// Never show the current value of the given column, and show `default` in its place.
+    ///
+    /// This is used for columns that come from a base column that has been dropped.
+    pub fn hide_column(&mut self, column: usize, default: DataType) {
+        if let Some(w) = self.writer.as_mut() {
+            w.hide_column(column, &default);
+        }
+        self.clear_spill();
+        self.hidden.push((column, default));
+    }
+
+    /// Pad rows materialized before a column was added to the upstream base with its default.
+    pub(crate) fn add_column(&mut self, column: usize, default: &DataType) {
+        if let Some(w) = self.writer.as_mut() {
+            w.add_column(column, default);
+        }
+        self.clear_spill();
+    }
+
+    /// Forget every spilled key, since their rows no longer have the reader's columns.
+    fn clear_spill(&mut self) {
+        if let Some(ref mut spill) = self.spill {
+            if spill.clear().is_err() {
+                self.spill = None;
+            }
+        }
+    }
+
     pub(crate) fn is_empty(&self) -> bool {
         self.writer.as_ref().map(|w| w.is_empty()).unwrap_or(true)
     }
@@ -89,17 +279,40 @@ impl Reader {
         self.writer.as_ref().map(SizeOf::deep_size_of)
     }
 
-    /// Evict a randomly selected key, returning the number of bytes evicted.
-    /// Note that due to how `evmap` applies the evictions asynchronously, we can only evict a
-    /// single key at a time here.
-    pub(crate) fn evict_random_keys(&mut self, n: usize) -> u64 {
-        let mut bytes_freed = 0;
+    pub(crate) fn state_rows(&self) -> Option<u64> {
+        self.writer.as_ref().map(|w| w.rows() as u64)
+    }
+
+    /// Evict up to `n` keys chosen by the reader's eviction policy, returning the number of bytes
+    /// and keys evicted. Keys for which `skip` returns true, such as those with replays in flight,
+    /// are left alone.
+    pub(crate) fn evict_keys<F>(&mut self, n: usize, skip: F) -> (u64, usize)
+    where
+        F: Fn(&[DataType]) -> bool,
+    {
+        let mut freed = (0, 0);
         if let Some(ref mut handle) = self.writer {
             let mut rng = rand::thread_rng();
-            bytes_freed = handle.evict_random_keys(&mut rng, n);
+            let spill = &mut self.spill;
+            let negative = &mut self.negative;
+            freed = handle.evict_keys(&mut rng, n, skip, |key, rows| {
+                let mut rows = rows.peekable();
+                let empty = rows.peek().is_none();
+                match negative {
+                    Some(negative) if empty => negative.insert(key),
+                    _ => {
+                        if let Some(s) = spill {
+                            let rows: Vec<_> = rows.cloned().collect();
+                            if s.put(key, &rows[..]).is_err() {
+                                *spill = None;
+                            }
+                        }
+                    }
+                }
+            });
             handle.swap();
         }
-        bytes_freed
+        freed
     }
 
     pub(in crate::node) fn on_eviction(&mut self, keys: &[Vec<DataType>]) {
@@ -107,36 +320,80 @@ impl Reader {
         if let Some(w) = self.writer.as_mut() {
             for k in keys {
                 w.mut_with_key(&k[..]).mark_hole();
+                if let Some(ref mut spill) = self.spill {
+                    spill.invalidate(&k[..]);
+                }
+                if let Some(ref mut negative) = self.negative {
+                    negative.invalidate(&k[..]);
+                }
             }
             w.swap();
         }
     }
 
-    pub(in crate::node) fn process(&mut self, m: &mut Option<Box<Packet>>, swap: bool) {
+    /// Wait for `parts` batches of the transaction with commit timestamp `txn` before applying
+    /// any of them, or with no `parts`, apply what has arrived of it.
+    pub(crate) fn expect_transaction(&mut self, txn: u64, parts: usize) {
+        // a reader whose state is not built yet has nothing to hold back
         if let Some(ref mut state) = self.writer {
-            let m = m.as_mut().unwrap();
-            // make sure we don't fill a partial materialization
-            // hole with incomplete (i.e., non-replay) state.
-            if m.is_regular() && state.is_partial() {
-                m.map_data(|data| {
-                    data.retain(|row| {
-                        match state.entry_from_record(&row[..]).try_find_and(|_| ()) {
-                            Ok((None, _)) => {
-                                // row would miss in partial state.
-                                // leave it blank so later lookup triggers replay.
-                                false
+            state.expect_transaction(txn, parts);
+            let released = state.release();
+            if !released.is_empty() {
+                for (data, label) in released {
+                    Self::apply(state, &mut self.spill, &mut self.negative, data, label);
+                }
+                state.swap();
+            }
+        }
+    }
+
+    /// Apply a batch of writes with the given label to the reader.
+    fn apply(
+        state: &mut backlog::WriteHandle,
+        spill: &mut Option<backlog::Spill>,
+        negative: &mut Option<backlog::NegativeCache>,
+        mut data: Records,
+        label: Label,
+    ) {
+        // make sure we don't fill a partial materialization
+        // hole with incomplete (i.e., non-replay) state.
+        if state.is_partial() {
+            data.retain(|row| {
+                match state.entry_from_record(&row[..]).try_find_and(|_| ()) {
+                    Ok((None, _)) => {
+                        // row would miss in partial state.
+                        // leave it blank so later lookup triggers replay.
+                        // any spilled copy of the key is now stale, and the key may
+                        // no longer be empty.
+                        if spill.is_some() || negative.is_some() {
+                            let key = state.key_of(&row[..]);
+                            if let Some(spill) = spill {
+                                spill.invalidate(&key[..]);
                             }
-                            Err(_) => unreachable!(),
-                            _ => {
-                                // state is already present,
-                                // so we can safely keep it up to date.
-                                true
+                            if let Some(negative) = negative {
+                                negative.invalidate(&key[..]);
                             }
                         }
-                    });
-                });
-            }
+                        false
+                    }
+                    Err(_) => unreachable!(),
+                    _ => {
+                        // state is already present,
+                        // so we can safely keep it up to date.
+                        true
+                    }
+                }
+            });
+        }
 
+        state.log_changes(&data[..], Some(&label));
+        state.observe(label);
+        state.add(data);
+    }
+
+    pub(in crate::node) fn process(&mut self, m: &mut Option<Box<Packet>>, swap: bool) {
+        if let Some(ref mut state) = self.writer {
+            let m = m.as_mut().unwrap();
             // it *can* happen that multiple readers miss (and thus request replay for) the
             // same hole at the same time. we need to make sure that we ignore any such
             // duplicated replay.
@@ -164,7 +421,28 @@ impl Reader {
                 });
             }
 
-            state.add(m.take_data());
+            if !self.hidden.is_empty() {
+                let hidden = &self.hidden;
+                m.map_data(|data| {
+                    for r in data.iter_mut() {
+                        for &(col, ref default) in hidden {
+                            r[col] = default.clone();
+                        }
+                    }
+                });
+            }
+
+            let data = m.take_data();
+            if let Packet::Message { label, .. } = **m {
+                // a transaction's batches are only applied once they have all arrived
+                let ready = state.hold(data, label);
+                for (data, label) in ready.into_iter().chain(state.release()) {
+                    Self::apply(state, &mut self.spill, &mut self.negative, data, label);
+                }
+            } else {
+                // replays fill in existing state, and aren't changes to the view
+                state.add(data);
+            }
 
             if swap {
                 // TODO: avoid doing the pointer swap if we didn't modify anything (inc. ts)
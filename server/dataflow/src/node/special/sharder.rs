@@ -1,12 +1,80 @@
 use crate::payload;
 use crate::prelude::*;
+use noria::debug::stats::{HotKey, ShardQueue};
+use std::collections::{HashMap, HashSet};
 use vec_map::VecMap;
 
+/// How many records a sharder sees between halving its per-key traffic counts.
+const DECAY_EVERY: usize = 1 << 14;
+
+/// When the sharder in front of a sharded count or sum spreads a hot key over several shards.
+///
+/// Each of those shards then counts or sums its part of the key's records, and their results are
+/// combined further downstream.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HotKeySalting {
+    /// The share of the sharder's recent records that a key must exceed to be spread out.
+    pub share: f64,
+    /// How many shards a hot key is spread over.
+    pub fanout: usize,
+}
+
+/// The number of recent records with each key, halved every `DECAY_EVERY` records so that old
+/// traffic counts for less.
+#[derive(Default)]
+struct KeyTraffic {
+    counts: HashMap<Vec<DataType>, u64>,
+    total: u64,
+    since_decay: usize,
+}
+
+impl KeyTraffic {
+    /// Count a record with the given key. Returns true once the counts are due to be decayed.
+    fn count(&mut self, key: Vec<DataType>) -> bool {
+        *self.counts.entry(key).or_insert(0) += 1;
+        self.total += 1;
+        self.since_decay += 1;
+        self.since_decay >= DECAY_EVERY
+    }
+
+    fn decay(&mut self) {
+        self.since_decay = 0;
+        self.total /= 2;
+        for n in self.counts.values_mut() {
+            *n /= 2;
+        }
+        self.counts.retain(|_, &mut n| n > 0);
+    }
+
+    fn share(&self, n: u64) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            n as f64 / self.total as f64
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Sharder {
     txs: Vec<(LocalNodeIndex, ReplicaAddr)>,
     sharded: VecMap<Box<Packet>>,
-    shard_by: usize,
+    shard_by: Vec<usize>,
+    /// If set, records are sharded by which range between these boundaries their (single)
+    /// sharding column falls into, rather than by its hash.
+    ranges: Option<Vec<DataType>>,
+    hasher: noria::ShardHasher,
+    /// If set, this sharder feeds a sharded count or sum, and spreads keys that get too hot over
+    /// several shards.
+    salting: Option<HotKeySalting>,
+    /// If set, every record is sent to every shard, so that each holds a full copy.
+    broadcast: bool,
+
+    #[serde(skip)]
+    traffic: KeyTraffic,
+    /// The keys that are spread over several shards. Once salted, a key stays salted.
+    #[serde(skip)]
+    salted: HashSet<Vec<DataType>>,
 }
 
 impl Clone for Sharder {
@@ -16,30 +84,138 @@ impl Clone for Sharder {
         Sharder {
             txs: Vec::new(),
             sharded: Default::default(),
-            shard_by: self.shard_by,
+            shard_by: self.shard_by.clone(),
+            ranges: self.ranges.clone(),
+            hasher: self.hasher,
+            salting: self.salting,
+            broadcast: self.broadcast,
+            traffic: Default::default(),
+            salted: Default::default(),
         }
     }
 }
 
 impl Sharder {
     pub fn new(by: usize) -> Self {
+        Self::by_columns(vec![by])
+    }
+
+    /// Construct a sharder that shards by the tuple of values in the given columns.
+    pub fn by_columns(by: Vec<usize>) -> Self {
+        assert!(!by.is_empty());
         Self {
             txs: Default::default(),
             shard_by: by,
+            ranges: None,
+            hasher: Default::default(),
+            salting: None,
+            broadcast: false,
+            traffic: Default::default(),
+            salted: Default::default(),
             sharded: VecMap::default(),
         }
     }
 
+    /// Construct a sharder that sends every record to every shard.
+    ///
+    /// This is for relations small enough that each shard of a join can keep a full copy of
+    /// them, so that the join's other, larger input need not be reshuffled.
+    pub fn broadcast() -> Self {
+        Self {
+            txs: Default::default(),
+            shard_by: Vec::new(),
+            ranges: None,
+            hasher: Default::default(),
+            salting: None,
+            broadcast: true,
+            traffic: Default::default(),
+            salted: Default::default(),
+            sharded: VecMap::default(),
+        }
+    }
+
+    /// Construct a sharder that shards by the tuple of values in the given columns, but spreads
+    /// hot keys over several shards as `salting` says.
+    ///
+    /// Its children must only count or sum their records, and have their results combined
+    /// further downstream, since a salted key's records no longer all go to the same shard.
+    pub fn salting(by: Vec<usize>, salting: HotKeySalting) -> Self {
+        Self {
+            salting: Some(salting),
+            ..Self::by_columns(by)
+        }
+    }
+
+    /// Construct a sharder that shards by which range between `boundaries` the value in the given
+    /// column falls into.
+    pub fn by_range(by: usize, boundaries: Vec<DataType>) -> Self {
+        Self {
+            ranges: Some(boundaries),
+            ..Self::new(by)
+        }
+    }
+
     pub fn take(&mut self) -> Self {
         use std::mem;
         let txs = mem::replace(&mut self.txs, Vec::new());
         Self {
             txs,
             sharded: VecMap::default(),
-            shard_by: self.shard_by,
+            shard_by: self.shard_by.clone(),
+            ranges: self.ranges.clone(),
+            hasher: self.hasher,
+            salting: self.salting,
+            broadcast: self.broadcast,
+            traffic: mem::replace(&mut self.traffic, Default::default()),
+            salted: mem::replace(&mut self.salted, Default::default()),
         }
     }
 
+    /// Pick shards by the given hash rather than the default one.
+    pub fn set_hasher(&mut self, hasher: noria::ShardHasher) {
+        self.hasher = hasher;
+    }
+
+    /// Whether this sharder may spread hot keys over several shards.
+    pub fn is_salting(&self) -> bool {
+        self.salting.is_some()
+    }
+
+    /// Whether this sharder sends every record to every shard.
+    pub fn is_broadcast(&self) -> bool {
+        self.broadcast
+    }
+
+    /// The `n` keys that made up the largest shares of this sharder's recent records, hottest
+    /// first.
+    pub fn hot_keys(&self, node: NodeIndex, n: usize) -> Vec<HotKey> {
+        let mut hot: Vec<_> = self.traffic.counts.iter().collect();
+        hot.sort_by(|a, b| b.1.cmp(a.1));
+        hot.into_iter()
+            .take(n)
+            .map(|(key, &records)| HotKey {
+                node,
+                key: key.clone(),
+                records,
+                share: self.traffic.share(records),
+                salted: self.salted.contains(key),
+            })
+            .collect()
+    }
+
+    /// How many packets `ex` has waiting to be sent to each of this sharder's shards.
+    pub fn queues(&self, node: NodeIndex, ex: &dyn Executor) -> Vec<ShardQueue> {
+        self.txs
+            .iter()
+            .enumerate()
+            .map(|(shard, &(_, addr))| ShardQueue {
+                node,
+                shard,
+                queued: ex.queued(addr),
+            })
+            .collect()
+    }
+
     pub fn add_sharded_child(&mut self, dst: LocalNodeIndex, txs: Vec<ReplicaAddr>) {
         assert_eq!(self.txs.len(), 0);
         // TODO: add support for "shared" sharder?
@@ -48,18 +224,95 @@ impl Sharder {
         }
     }
 
-    pub fn sharded_by(&self) -> usize {
-        self.shard_by
+    pub fn sharded_by(&self) -> &[usize] {
+        &self.shard_by[..]
+    }
+
+    /// The sharding of this sharder's output when it feeds `shards` shards.
+    ///
+    /// A salting sharder's output is effectively sharded randomly, since the records of a hot key
+    /// may go to any of several shards.
+    pub fn sharding(&self, shards: usize) -> Sharding {
+        match self.ranges {
+            None if self.broadcast => Sharding::Broadcast(shards),
+            Some(ref boundaries) => Sharding::ByRange(self.shard_by[0], boundaries.clone()),
+            None if self.is_salting() => Sharding::Random(shards),
+            None => Sharding::by_columns(self.shard_by.clone(), shards),
+        }
+    }
+
+    /// The boundaries between the shards, if this sharder shards by range.
+    pub fn ranges(&self) -> Option<&[DataType]> {
+        self.ranges.as_ref().map(|b| &b[..])
+    }
+
+    #[inline]
+    fn to_shard(&mut self, r: &Record) -> usize {
+        let key: Vec<_> = self.shard_by.iter().map(|&c| r[c].clone()).collect();
+        let mut shard = self.shard(&key);
+        if self.salted.contains(&key) {
+            // the same record always goes to the same shard, so that its removal reaches the
+            // shard that counted it, whether it arrives as a write or in a replay.
+            shard =
+                (shard + crate::shard_by_key(&self.hasher, &r[..], self.fanout())) % self.txs.len();
+        }
+
+        if self.traffic.count(key) {
+            self.salt_hot_keys();
+            self.traffic.decay();
+        }
+        shard
+    }
+
+    /// How many shards a salted key is spread over.
+    fn fanout(&self) -> usize {
+        self.salting
+            .map(|s| s.fanout)
+            .unwrap_or(1)
+            .min(self.txs.len())
+            .max(1)
+    }
+
+    /// The shards that records with the given key may have been sent to.
+    fn shards_of(&self, key: &[DataType]) -> Vec<usize> {
+        let shard = self.shard(key);
+        if self.salted.contains(key) {
+            (0..self.fanout())
+                .map(|i| (shard + i) % self.txs.len())
+                .collect()
+        } else {
+            vec![shard]
+        }
+    }
+
+    /// Salt the keys that have made up more than their share of recent records.
+    fn salt_hot_keys(&mut self) {
+        let salting = match self.salting {
+            Some(salting) if salting.fanout > 1 => salting,
+            _ => return,
+        };
+        for (key, &n) in &self.traffic.counts {
+            if self.traffic.share(n) > salting.share && !self.salted.contains(key) {
+                self.salted.insert(key.clone());
+            }
+        }
     }
 
     #[inline]
-    fn to_shard(&self, r: &Record) -> usize {
-        self.shard(&r[self.shard_by])
+    fn shard(&self, key: &[DataType]) -> usize {
+        if let [ref v] = *key {
+            self.shard_value(v)
+        } else {
+            crate::shard_by_key(&self.hasher, key, self.txs.len())
+        }
     }
 
     #[inline]
-    fn shard(&self, dt: &DataType) -> usize {
-        crate::shard_by(dt, self.txs.len())
+    fn shard_value(&self, v: &DataType) -> usize {
+        match self.ranges {
+            Some(ref boundaries) => crate::shard_by_range(v, boundaries),
+            None => crate::shard_by(&self.hasher, v, self.txs.len()),
+        }
     }
 
     pub fn process(
@@ -73,6 +326,17 @@ impl Sharder {
         // we need to shard the records inside `m` by their key,
         let mut m = m.take().unwrap();
         for record in m.take_data() {
+            if self.broadcast {
+                for shard in 0..self.txs.len() {
+                    let p = self
+                        .sharded
+                        .entry(shard)
+                        .or_insert_with(|| Box::new(m.clone_data()));
+                    p.map_data(|rs| rs.push(record.clone()));
+                }
+                continue;
+            }
+
             let shard = self.to_shard(&record);
             let p = self
                 .sharded
@@ -118,6 +382,10 @@ impl Sharder {
             }
         } else {
             assert!(is_last_sharder_for_tag.is_none());
+            if m.is_tracked() {
+                // a client is waiting for this batch, so every shard must learn of it
+                dest = Destination::All;
+            }
         }
 
         match dest {
@@ -155,6 +423,7 @@ impl Sharder {
             if let Some(mut shard) = self.sharded.remove(i) {
                 shard.link_mut().src = index;
                 shard.link_mut().dst = dst;
+                shard.mark_sent();
                 output.send(addr, shard);
             }
         }
@@ -171,21 +440,22 @@ impl Sharder {
     ) {
         assert!(!is_sharded);
 
-        if key_columns.len() == 1 && key_columns[0] == self.shard_by {
+        if !self.broadcast && key_columns == &self.shard_by[..] {
             // Send only to the shards that must evict something.
             for key in keys {
-                let shard = self.shard(&key[0]);
-                let dst = self.txs[shard].0;
-                let p = self.sharded.entry(shard).or_insert_with(|| {
-                    Box::new(Packet::EvictKeys {
-                        link: Link { src, dst },
-                        keys: Vec::new(),
-                        tag,
-                    })
-                });
-                match **p {
-                    Packet::EvictKeys { ref mut keys, .. } => keys.push(key.to_vec()),
-                    _ => unreachable!(),
+                for shard in self.shards_of(key) {
+                    let dst = self.txs[shard].0;
+                    let p = self.sharded.entry(shard).or_insert_with(|| {
+                        Box::new(Packet::EvictKeys {
+                            link: Link { src, dst },
+                            keys: Vec::new(),
+                            tag,
+                        })
+                    });
+                    match **p {
+                        Packet::EvictKeys { ref mut keys, .. } => keys.push(key.to_vec()),
+                        _ => unreachable!(),
+                    }
                 }
             }
 
@@ -196,7 +466,14 @@ impl Sharder {
             }
         } else {
             assert_eq!(!key_columns.len(), 0);
-            assert!(!key_columns.contains(&self.shard_by));
+            // a compound sharding key may be evicted by in some other column order, in which
+            // case sending to every shard is merely wasteful. every shard of a broadcast holds
+            // every key, so they must all evict it.
+            assert!(
+                self.broadcast
+                    || self.shard_by.len() > 1
+                    || !key_columns.contains(&self.shard_by[0])
+            );
 
             // send to all shards
             for &mut (dst, addr) in self.txs.iter_mut() {
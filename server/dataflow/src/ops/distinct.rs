@@ -148,6 +148,13 @@ impl Ingredient for Distinct {
         vec![(self.src.as_global(), Some(column))]
     }
 
+    fn used_parent_columns(&self, parent: NodeIndex) -> Vec<usize> {
+        if parent != self.src.as_global() {
+            return Vec::new();
+        }
+        self.group_by.clone()
+    }
+
     fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
         Some(vec![(self.src.as_global(), col)])
     }
@@ -1,8 +1,10 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Display};
+use std::iter;
 use std::sync;
 
+use super::udf::Udf;
 use crate::prelude::*;
 pub use nom_sql::Operator;
 
@@ -34,10 +36,148 @@ impl Display for Value {
     }
 }
 
+/// Lists with more than this many values are hashed when checking `IN` membership; shorter ones
+/// are faster to scan linearly.
+const HASHED_LIST_THRESHOLD: usize = 8;
+
+/// The literal values on the right-hand side of an `IN` or `NOT IN` condition.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "Vec<DataType>", into = "Vec<DataType>")]
+pub struct ValueList {
+    values: Vec<DataType>,
+    set: Option<HashSet<DataType>>,
+}
+
+impl ValueList {
+    pub fn contains(&self, d: &DataType) -> bool {
+        match self.set {
+            Some(ref set) => set.contains(d),
+            None => self.values.contains(d),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &DataType> {
+        self.values.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl From<Vec<DataType>> for ValueList {
+    fn from(values: Vec<DataType>) -> Self {
+        let set = if values.len() > HASHED_LIST_THRESHOLD {
+            Some(values.iter().cloned().collect())
+        } else {
+            None
+        };
+        ValueList { values, set }
+    }
+}
+
+impl Into<Vec<DataType>> for ValueList {
+    fn into(self) -> Vec<DataType> {
+        self.values
+    }
+}
+
+impl PartialEq for ValueList {
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values
+    }
+}
+
+impl Display for ValueList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let vs = self
+            .values
+            .iter()
+            .map(|d| format!("{}", d))
+            .collect::<Vec<_>>();
+        write!(f, "{}", vs.join(", "))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum FilterCondition {
     Comparison(Operator, Value),
-    In(Vec<DataType>),
+    In(ValueList),
+    NotIn(ValueList),
+    /// A call to a user-defined function, with the value as its first argument, that must return
+    /// true.
+    Udf(sync::Arc<Udf>, Vec<Value>),
+}
+
+impl FilterCondition {
+    /// Check whether the value `d` taken from record `r` satisfies this condition.
+    ///
+    /// As in SQL, a comparison involving `NULL` is unknown, and is not satisfied. The exception is
+    /// a comparison with a `NULL` constant, which is how `IS NULL` and `IS NOT NULL` are expressed.
+    pub fn matches(&self, d: &DataType, r: &[DataType]) -> bool {
+        match *self {
+            FilterCondition::Comparison(ref op, Value::Constant(DataType::None)) => match *op {
+                Operator::Equal => d.is_none(),
+                Operator::NotEqual => !d.is_none(),
+                _ => false,
+            },
+            FilterCondition::Comparison(ref op, ref f) => {
+                let v = match *f {
+                    Value::Constant(ref dt) => dt,
+                    Value::Column(c) => &r[c],
+                };
+                if d.is_none() || v.is_none() {
+                    return false;
+                }
+
+                // string literals compared with dates and timestamps are read as date literals
+                let coerced;
+                let v = match *d {
+                    DataType::Timestamp(_) | DataType::Date(_) if v.is_string() => {
+                        let parsed = if d.is_date() {
+                            DataType::parse_date(v.into())
+                        } else {
+                            DataType::parse_datetime(v.into())
+                        };
+                        match parsed {
+                            Ok(parsed) => {
+                                coerced = parsed;
+                                &coerced
+                            }
+                            Err(_) => v,
+                        }
+                    }
+                    _ => v,
+                };
+
+                match *op {
+                    Operator::Equal => d == v,
+                    Operator::NotEqual => d != v,
+                    Operator::Greater => d > v,
+                    Operator::GreaterOrEqual => d >= v,
+                    Operator::Less => d < v,
+                    Operator::LessOrEqual => d <= v,
+                    Operator::In => unreachable!(),
+                    _ => unimplemented!(),
+                }
+            }
+            FilterCondition::In(ref fs) => !d.is_none() && fs.contains(d),
+            FilterCondition::NotIn(ref fs) => !d.is_none() && !fs.contains(d),
+            FilterCondition::Udf(ref f, ref rest) => {
+                let mut args = Vec::with_capacity(rest.len() + 1);
+                args.push(d);
+                args.extend(rest.iter().map(|v| match *v {
+                    Value::Constant(ref dt) => dt,
+                    Value::Column(c) => &r[c],
+                }));
+                f.call(&args).is_truthy()
+            }
+        }
+    }
 }
 
 impl Filter {
@@ -72,6 +212,16 @@ impl Ingredient for Filter {
         self.src.remap(remap);
     }
 
+    fn udfs_mut(&mut self) -> Vec<&mut sync::Arc<Udf>> {
+        sync::Arc::make_mut(&mut self.filter)
+            .iter_mut()
+            .filter_map(|&mut (_, ref mut cond)| match *cond {
+                FilterCondition::Udf(ref mut f, _) => Some(f),
+                _ => None,
+            })
+            .collect()
+    }
+
     fn on_input(
         &mut self,
         _: &mut dyn Executor,
@@ -81,31 +231,7 @@ impl Ingredient for Filter {
         _: &DomainNodes,
         _: &StateMap,
     ) -> ProcessingResult {
-        rs.retain(|r| {
-            self.filter.iter().all(|(i, cond)| {
-                // check if this filter matches
-                let d = &r[*i];
-                match cond {
-                    FilterCondition::Comparison(ref op, ref f) => {
-                        let v = match *f {
-                            Value::Constant(ref dt) => dt,
-                            Value::Column(c) => &r[c],
-                        };
-                        match *op {
-                            Operator::Equal => d == v,
-                            Operator::NotEqual => d != v,
-                            Operator::Greater => d > v,
-                            Operator::GreaterOrEqual => d >= v,
-                            Operator::Less => d < v,
-                            Operator::LessOrEqual => d <= v,
-                            Operator::In => unreachable!(),
-                            _ => unimplemented!(),
-                        }
-                    }
-                    FilterCondition::In(ref fs) => fs.contains(d),
-                }
-            })
-        });
+        rs.retain(|r| self.filter.iter().all(|(i, cond)| cond.matches(&r[*i], r)));
 
         ProcessingResult {
             results: rs,
@@ -142,14 +268,14 @@ impl Ingredient for Filter {
                     FilterCondition::Comparison(ref op, ref x) => {
                         Some(format!("f{} {} {}", i, escape(&format!("{}", op)), x))
                     }
-                    FilterCondition::In(ref xs) => Some(format!(
-                        "f{} IN ({})",
-                        i,
-                        xs.iter()
-                            .map(|d| format!("{}", d))
-                            .collect::<Vec<_>>()
-                            .join(", ")
-                    )),
+                    FilterCondition::In(ref xs) => Some(format!("f{} IN ({})", i, xs)),
+                    FilterCondition::NotIn(ref xs) => Some(format!("f{} NOT IN ({})", i, xs)),
+                    FilterCondition::Udf(ref func, ref rest) => {
+                        let args: Vec<_> = iter::once(format!("f{}", i))
+                            .chain(rest.iter().map(ToString::to_string))
+                            .collect();
+                        Some(format!("{}({})", func, args.join(", ")))
+                    }
                 })
                 .collect::<Vec<_>>()
                 .as_slice()
@@ -172,30 +298,8 @@ impl Ingredient for Filter {
         self.lookup(*self.src, columns, key, nodes, states)
             .and_then(|result| {
                 let f = self.filter.clone();
-                let filter = move |r: &[DataType]| {
-                    f.iter().all(|(i, ref cond)| {
-                        // check if this filter matches
-                        let d = &r[*i];
-                        match *cond {
-                            FilterCondition::Comparison(ref op, ref f) => {
-                                let v = match *f {
-                                    Value::Constant(ref dt) => dt,
-                                    Value::Column(c) => &r[c],
-                                };
-                                match *op {
-                                    Operator::Equal => d == v,
-                                    Operator::NotEqual => d != v,
-                                    Operator::Greater => d > v,
-                                    Operator::GreaterOrEqual => d >= v,
-                                    Operator::Less => d < v,
-                                    Operator::LessOrEqual => d <= v,
-                                    _ => unimplemented!(),
-                                }
-                            }
-                            FilterCondition::In(ref fs) => fs.contains(d),
-                        }
-                    })
-                };
+                let filter =
+                    move |r: &[DataType]| f.iter().all(|(i, cond)| cond.matches(&r[*i], r));
 
                 match result {
                     Some(rs) => {
@@ -211,6 +315,25 @@ impl Ingredient for Filter {
         vec![(self.src.as_global(), Some(column))]
     }
 
+    fn used_parent_columns(&self, parent: NodeIndex) -> Vec<usize> {
+        if parent != self.src.as_global() {
+            return Vec::new();
+        }
+        self.filter
+            .iter()
+            .flat_map(|&(col, ref cond)| match *cond {
+                FilterCondition::Comparison(_, Value::Column(other)) => vec![col, other],
+                FilterCondition::Udf(_, ref rest) => iter::once(col)
+                    .chain(rest.iter().filter_map(|v| match *v {
+                        Value::Column(c) => Some(c),
+                        Value::Constant(_) => None,
+                    }))
+                    .collect(),
+                _ => vec![col],
+            })
+            .collect()
+    }
+
     fn is_selective(&self) -> bool {
         true
     }
@@ -375,6 +498,34 @@ mod tests {
         assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
     }
 
+    #[test]
+    fn it_compares_dates_with_date_literals() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                1,
+                FilterCondition::Comparison(
+                    Operator::GreaterOrEqual,
+                    Value::Constant("2020-02-01".into()),
+                ),
+            )]),
+        );
+
+        let ts = |s| DataType::parse_datetime(s).unwrap();
+        let date = |s| DataType::parse_date(s).unwrap();
+        let mut left: Vec<DataType>;
+
+        // the literal is compared as the start of that day, not as a string
+        left = vec![1.into(), ts("2020-10-01 00:00:00")];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+        left = vec![1.into(), ts("2020-01-31 23:59:59")];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+        left = vec![1.into(), date("2020-02-01")];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+        left = vec![1.into(), date("2020-01-31")];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
+
     #[test]
     fn it_works_with_columns() {
         let mut g = setup(
@@ -392,13 +543,73 @@ mod tests {
         assert_eq!(g.narrow_one_row(left.clone(), false), Records::default());
     }
 
+    #[test]
+    fn it_treats_comparisons_with_null_as_unknown() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Comparison(Operator::Less, Value::Column(1)),
+            )]),
+        );
+        let left = vec![1.into(), 2.into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+        for left in vec![
+            vec![DataType::None, 2.into()],
+            vec![1.into(), DataType::None],
+            vec![DataType::None, DataType::None],
+        ] {
+            assert_eq!(g.narrow_one_row(left, false), Records::default());
+        }
+
+        let mut g = setup(
+            false,
+            Some(&[(
+                1,
+                FilterCondition::Comparison(Operator::NotEqual, Value::Constant(4.into())),
+            )]),
+        );
+        let left = vec![1.into(), DataType::None];
+        assert_eq!(g.narrow_one_row(left, false), Records::default());
+
+        let mut g = setup(
+            false,
+            Some(&[(1, FilterCondition::NotIn(vec![4.into()].into()))]),
+        );
+        let left = vec![1.into(), DataType::None];
+        assert_eq!(g.narrow_one_row(left, false), Records::default());
+    }
+
+    #[test]
+    fn it_works_with_is_null() {
+        let is_null = [(
+            1,
+            FilterCondition::Comparison(Operator::Equal, Value::Constant(DataType::None)),
+        )];
+        let mut g = setup(false, Some(&is_null));
+        let left = vec![1.into(), DataType::None];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+        let left = vec![1.into(), 2.into()];
+        assert_eq!(g.narrow_one_row(left, false), Records::default());
+
+        let is_not_null = [(
+            1,
+            FilterCondition::Comparison(Operator::NotEqual, Value::Constant(DataType::None)),
+        )];
+        let mut g = setup(false, Some(&is_not_null));
+        let left = vec![1.into(), 2.into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+        let left = vec![1.into(), DataType::None];
+        assert_eq!(g.narrow_one_row(left, false), Records::default());
+    }
+
     #[test]
     fn it_works_with_in_list() {
         let mut g = setup(
             false,
             Some(&[
-                (0, FilterCondition::In(vec![2.into(), 42.into()])),
-                (1, FilterCondition::In(vec!["b".into()])),
+                (0, FilterCondition::In(vec![2.into(), 42.into()].into())),
+                (1, FilterCondition::In(vec!["b".into()].into())),
             ]),
         );
 
@@ -420,4 +631,89 @@ mod tests {
         left = vec![42.into(), "b".into()];
         assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
     }
+
+    #[test]
+    fn it_works_with_not_in_list() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                1,
+                FilterCondition::NotIn(vec!["a".into(), "c".into()].into()),
+            )]),
+        );
+
+        let mut left: Vec<DataType>;
+
+        left = vec![1.into(), "b".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        left = vec![1.into(), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        left = vec![2.into(), "c".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
+
+    #[test]
+    fn it_works_with_long_in_list() {
+        let list: Vec<DataType> = (0..100).map(|i: i32| (i * 2).into()).collect();
+        let mut g = setup(false, Some(&[(0, FilterCondition::In(list.into()))]));
+
+        let mut left: Vec<DataType>;
+
+        left = vec![42.into(), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        left = vec![43.into(), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        left = vec![200.into(), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "wasm")]
+    fn it_works_with_udfs() {
+        use crate::ops::udf::UdfType;
+
+        let wat = r#"
+            (module
+              (func (export "divides") (param i64 i64) (result i32)
+                local.get 1
+                local.get 0
+                i64.rem_s
+                i64.eqz))
+        "#;
+        let divides = Udf::new(
+            "divides",
+            wat.as_bytes().to_vec(),
+            "divides",
+            vec![UdfType::Int, UdfType::Int],
+            UdfType::Bool,
+        )
+        .unwrap();
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Udf(sync::Arc::new(divides), vec![Value::Constant(12.into())]),
+            )]),
+        );
+        assert_eq!(g.node().description(true), "σ[divides(f0, 12)]");
+
+        let mut left: Vec<DataType>;
+
+        left = vec![3.into(), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        left = vec![5.into(), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        // dividing by zero traps
+        left = vec![0.into(), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        left = vec![DataType::None, "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
 }
@@ -1,14 +1,20 @@
 use crate::ops::grouped::GroupedOperation;
 use crate::ops::grouped::GroupedOperator;
+use crate::ops::grouped::PartialAggregation;
 
 use crate::prelude::*;
 
 /// Supported aggregation operators.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
 pub enum Aggregation {
-    /// Count the number of records for each group. The value for the `over` column is ignored.
+    /// Count the number of records for each group whose `over` column is not `NULL`.
     COUNT,
-    /// Sum the value of the `over` column for all records of each group.
+    /// Count every record of each group, like `COUNT(*)`. The value for the `over` column is
+    /// ignored.
+    COUNT_STAR,
+    /// Sum the value of the `over` column for all records of each group. `NULL` values are
+    /// skipped.
     SUM,
 }
 
@@ -17,7 +23,8 @@ impl Aggregation {
     ///
     /// The aggregation will aggregate the value in column number `over` from its inputs (i.e.,
     /// from the `src` node in the graph), and use the columns in the `group_by` array as a group
-    /// identifier. The `over` column should not be in the `group_by` array.
+    /// identifier. The `over` column should not be in the `group_by` array, unless every record is
+    /// counted, in which case its value is ignored.
     pub fn over(
         self,
         src: NodeIndex,
@@ -25,7 +32,7 @@ impl Aggregation {
         group_by: &[usize],
     ) -> GroupedOperator<Aggregator> {
         assert!(
-            !group_by.iter().any(|&i| i == over),
+            self == Aggregation::COUNT_STAR || !group_by.iter().any(|&i| i == over),
             "cannot group by aggregation column"
         );
         GroupedOperator::new(
@@ -59,8 +66,43 @@ pub struct Aggregator {
     group: Vec<usize>,
 }
 
+impl GroupedOperator<Aggregator> {
+    /// Compute this count or sum from counts or sums over parts of the same records, grouped the
+    /// same way.
+    pub fn partial(&self) -> PartialAggregation {
+        let mut group_by = self.inner.group.clone();
+        group_by.sort();
+        PartialAggregation {
+            op: self
+                .inner
+                .op
+                .clone()
+                .over(self.src.as_global(), self.inner.over, &self.inner.group)
+                .into(),
+            group_by,
+            only: Vec::new(),
+            additive: true,
+        }
+    }
+
+    /// The aggregation that combines the partial results read from `partials` into the result of
+    /// this one. Partial counts and partial sums are both combined by summing them.
+    pub fn combining(&self, partials: NodeIndex) -> GroupedOperator<Aggregator> {
+        let n = self.inner.group.len();
+        let group: Vec<_> = (0..n).collect();
+        Aggregation::SUM.over(partials, n, &group)
+    }
+}
+
+/// Rescale a decimal mantissa to a larger scale.
+fn rescale(m: i128, from: u8, to: u8) -> i128 {
+    m * 10i128.pow(u32::from(to - from))
+}
+
 impl GroupedOperation for Aggregator {
-    type Diff = i128;
+    /// A change in the aggregated value, as a mantissa and a scale. The scale is only non-zero
+    /// when summing over decimals, which are summed exactly.
+    type Diff = (i128, u8);
 
     fn setup(&mut self, parent: &Node) {
         assert!(
@@ -75,21 +117,26 @@ impl GroupedOperation for Aggregator {
 
     fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
         match self.op {
-            Aggregation::COUNT if pos => 1,
-            Aggregation::COUNT => -1,
+            Aggregation::COUNT if r[self.over].is_none() => (0, 0),
+            Aggregation::COUNT | Aggregation::COUNT_STAR if pos => (1, 0),
+            Aggregation::COUNT | Aggregation::COUNT_STAR => (-1, 0),
             Aggregation::SUM => {
-                let v = match r[self.over] {
-                    DataType::Int(n) => i128::from(n),
-                    DataType::UnsignedInt(n) => i128::from(n),
-                    DataType::BigInt(n) => i128::from(n),
-                    DataType::UnsignedBigInt(n) => i128::from(n),
-                    DataType::None => 0,
+                let (v, scale) = match r[self.over] {
+                    // summing a boolean counts the rows where it is true
+                    DataType::Bool(b) => (i128::from(b), 0),
+                    DataType::Int(n) => (i128::from(n), 0),
+                    DataType::UnsignedInt(n) => (i128::from(n), 0),
+                    DataType::BigInt(n) => (i128::from(n), 0),
+                    DataType::UnsignedBigInt(n) => (i128::from(n), 0),
+                    DataType::HugeInt(ref n) => (**n, 0),
+                    DataType::Decimal(m, scale) => (i128::from(m), scale),
+                    DataType::None => (0, 0),
                     ref x => unreachable!("tried to aggregate over {:?} on {:?}", x, r),
                 };
                 if pos {
-                    v
+                    (v, scale)
                 } else {
-                    0i128 - v
+                    (0i128 - v, scale)
                 }
             }
         }
@@ -100,27 +147,45 @@ impl GroupedOperation for Aggregator {
         current: Option<&DataType>,
         diffs: &mut dyn Iterator<Item = Self::Diff>,
     ) -> DataType {
-        let n = match current {
-            Some(&DataType::Int(n)) => i128::from(n),
-            Some(&DataType::UnsignedInt(n)) => i128::from(n),
-            Some(&DataType::BigInt(n)) => i128::from(n),
-            Some(&DataType::UnsignedBigInt(n)) => i128::from(n),
-            None => 0,
+        let (n, scale, decimal) = match current {
+            Some(&DataType::Int(n)) => (i128::from(n), 0, false),
+            Some(&DataType::UnsignedInt(n)) => (i128::from(n), 0, false),
+            Some(&DataType::BigInt(n)) => (i128::from(n), 0, false),
+            Some(&DataType::UnsignedBigInt(n)) => (i128::from(n), 0, false),
+            Some(&DataType::HugeInt(ref n)) => (**n, 0, false),
+            Some(&DataType::Decimal(m, scale)) => (i128::from(m), scale, true),
+            None => (0, 0, false),
             _ => unreachable!(),
         };
-        diffs.fold(n, |n, d| n + d).into()
+        let (n, scale, decimal) =
+            diffs.fold((n, scale, decimal), |(n, scale, decimal), (d, ds)| {
+                if ds > scale {
+                    (rescale(n, scale, ds) + d, ds, true)
+                } else {
+                    (n + rescale(d, ds, scale), scale, decimal || ds > 0)
+                }
+            });
+        if decimal {
+            // sums of decimals saturate rather than overflow
+            DataType::decimal_saturating(n, scale)
+        } else {
+            // integer sums are exact, and widen to a `HugeInt` when they do not fit in 64 bits, so
+            // retracting a record always undoes adding it.
+            n.into()
+        }
     }
 
     fn description(&self, detailed: bool) -> String {
         if !detailed {
             return String::from(match self.op {
-                Aggregation::COUNT => "+",
+                Aggregation::COUNT | Aggregation::COUNT_STAR => "+",
                 Aggregation::SUM => "𝛴",
             });
         }
 
         let op_string = match self.op {
-            Aggregation::COUNT => "|*|".into(),
+            Aggregation::COUNT => format!("|{}|", self.over),
+            Aggregation::COUNT_STAR => "|*|".into(),
             Aggregation::SUM => format!("𝛴({})", self.over),
         };
         let group_cols = self
@@ -172,6 +237,9 @@ mod tests {
         let s = 0.into();
 
         let c = Aggregation::COUNT.over(s, 1, &[0, 2]);
+        assert_eq!(c.description(true), "|1| γ[0, 2]");
+
+        let c = Aggregation::COUNT_STAR.over(s, 1, &[0, 2]);
         assert_eq!(c.description(true), "|*| γ[0, 2]");
 
         let s = Aggregation::SUM.over(s, 1, &[2, 0]);
@@ -301,6 +369,114 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn it_counts_nulls_only_for_count_star() {
+        let mut c = ops::test::MockGraph::new();
+        let s = c.add_base("source", &["x", "y"]);
+        c.set_op(
+            "count",
+            &["x", "ys"],
+            Aggregation::COUNT.over(s.as_global(), 1, &[0]),
+            true,
+        );
+        let mut cs = ops::test::MockGraph::new();
+        let s = cs.add_base("source", &["x", "y"]);
+        cs.set_op(
+            "count_star",
+            &["x", "rows"],
+            Aggregation::COUNT_STAR.over(s.as_global(), 1, &[0]),
+            true,
+        );
+
+        let u = || {
+            vec![
+                (vec![1.into(), 1.into()], true),
+                (vec![1.into(), DataType::None], true),
+                (vec![1.into(), 2.into()], true),
+            ]
+        };
+        let expected: Records = vec![vec![1.into(), 2.into()]].into();
+        assert_eq!(c.narrow_one(u(), true), expected);
+        let expected: Records = vec![vec![1.into(), 3.into()]].into();
+        assert_eq!(cs.narrow_one(u(), true), expected);
+
+        // a group of only NULLs has a count of zero
+        let u = vec![(vec![2.into(), DataType::None], true)];
+        let expected: Records = vec![vec![2.into(), 0.into()]].into();
+        assert_eq!(c.narrow_one(u, true), expected);
+    }
+
+    #[test]
+    fn it_sums_decimals_exactly() {
+        let mut c = ops::test::MockGraph::new();
+        let s = c.add_base("source", &["x", "y"]);
+        c.set_op(
+            "sum",
+            &["x", "ys"],
+            Aggregation::SUM.over(s.as_global(), 1, &[0]),
+            true,
+        );
+
+        let sum_of = |rs: Records| match rs.into_iter().last().unwrap() {
+            Record::Positive(r) => r[1].clone(),
+            _ => unreachable!(),
+        };
+
+        let u = vec![
+            (vec![1.into(), DataType::decimal(1, 1)], true),
+            (vec![1.into(), DataType::decimal(1, 1)], true),
+            (vec![1.into(), DataType::decimal(1, 1)], true),
+            (vec![1.into(), DataType::decimal(5, 2)], true),
+        ];
+        assert_eq!(sum_of(c.narrow_one(u, true)), DataType::decimal(35, 2));
+
+        // integers mix in exactly, and removing rows subtracts exactly
+        let u = vec![
+            (vec![1.into(), 2.into()], true),
+            (vec![1.into(), DataType::decimal(1, 1)], false),
+        ];
+        assert_eq!(sum_of(c.narrow_one(u, true)), DataType::decimal(225, 2));
+
+        // sums that do not fit saturate
+        let u = vec![
+            (vec![1.into(), DataType::decimal(std::i64::MAX, 0)], true),
+            (vec![1.into(), DataType::decimal(std::i64::MAX, 0)], true),
+        ];
+        assert_eq!(
+            sum_of(c.narrow_one(u, true)),
+            DataType::decimal(std::i64::MAX, 2)
+        );
+    }
+
+    #[test]
+    fn it_widens_integer_sums_that_overflow() {
+        let mut c = ops::test::MockGraph::new();
+        let s = c.add_base("source", &["x", "y"]);
+        c.set_op(
+            "sum",
+            &["x", "ys"],
+            Aggregation::SUM.over(s.as_global(), 1, &[0]),
+            true,
+        );
+
+        let sum_of = |rs: Records| match rs.into_iter().last().unwrap() {
+            Record::Positive(r) => r[1].clone(),
+            _ => unreachable!(),
+        };
+
+        let max = DataType::from(std::i64::MAX);
+        let u = vec![
+            (vec![1.into(), max.clone()], true),
+            (vec![1.into(), max.clone()], true),
+        ];
+        let wide = i128::from(std::i64::MAX) * 2;
+        assert_eq!(sum_of(c.narrow_one(u, true)), DataType::from(wide));
+
+        // retracting a row narrows the sum back down exactly
+        let u = vec![(vec![1.into(), max.clone()], false)];
+        assert_eq!(sum_of(c.narrow_one(u, true)), max);
+    }
+
     #[test]
     #[allow(clippy::cognitive_complexity)]
     fn it_groups_by_multiple_columns() {
@@ -89,16 +89,22 @@ impl GroupConcat {
                     s.push_str(l);
                 }
                 TextComponent::Column(ref i) => match rec[*i] {
-                    DataType::Text(..) | DataType::TinyText(..) => {
+                    DataType::Text(..) | DataType::TinyText(..) | DataType::CiText(..) => {
                         let text: &str = (&rec[*i]).into();
                         s.push_str(text);
                     }
+                    DataType::Bool(b) => s.push_str(if b { "1" } else { "0" }),
                     DataType::Int(ref n) => s.push_str(&n.to_string()),
                     DataType::UnsignedInt(ref n) => s.push_str(&n.to_string()),
                     DataType::BigInt(ref n) => s.push_str(&n.to_string()),
                     DataType::UnsignedBigInt(ref n) => s.push_str(&n.to_string()),
-                    DataType::Real(..) => s.push_str(&rec[*i].to_string()),
+                    DataType::HugeInt(ref n) => s.push_str(&n.to_string()),
+                    DataType::Real(..) | DataType::Decimal(..) => s.push_str(&rec[*i].to_string()),
                     DataType::Timestamp(ref ts) => s.push_str(&ts.format("%+").to_string()),
+                    DataType::Date(..)
+                    | DataType::Uuid(..)
+                    | DataType::Json(..)
+                    | DataType::Bytes(..) => s.push_str(&rec[*i].to_string()),
                     DataType::None => unreachable!(),
                 },
             }
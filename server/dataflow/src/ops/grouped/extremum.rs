@@ -1,5 +1,8 @@
+use crate::ops::filter::{FilterCondition, Operator, Value};
+use crate::ops::grouped::aggregate::Aggregation;
 use crate::ops::grouped::GroupedOperation;
 use crate::ops::grouped::GroupedOperator;
+use crate::ops::grouped::PartialAggregation;
 
 use crate::prelude::*;
 
@@ -58,9 +61,51 @@ pub struct ExtremumOperator {
     group: Vec<usize>,
 }
 
+impl GroupedOperator<ExtremumOperator> {
+    /// Compute this extremum from the number of records that have each value in each group.
+    ///
+    /// An extremum cannot yet recover when the record holding its extreme value is removed, and
+    /// an extremum over each shard would need to do so far more often than one over all records.
+    /// Counting records by value stays exact under removals instead, and the values whose count
+    /// drops to zero are left out of the combined result.
+    pub fn partial(&self) -> PartialAggregation {
+        let mut group_by = self.inner.group.clone();
+        group_by.push(self.inner.over);
+        group_by.sort();
+        let count = group_by.len();
+        PartialAggregation {
+            op: Aggregation::COUNT_STAR
+                .over(self.src.as_global(), self.inner.over, &group_by)
+                .into(),
+            group_by,
+            only: vec![(
+                count,
+                FilterCondition::Comparison(Operator::Greater, Value::Constant(0.into())),
+            )],
+            additive: false,
+        }
+    }
+
+    /// The extremum of the values of the partial results read from `partials`.
+    pub fn combining(&self, partials: NodeIndex) -> GroupedOperator<ExtremumOperator> {
+        let over = self
+            .inner
+            .group
+            .iter()
+            .filter(|&&c| c < self.inner.over)
+            .count();
+        let group: Vec<_> = (0..=self.inner.group.len())
+            .filter(|&c| c != over)
+            .collect();
+        self.inner.op.clone().over(partials, over, &group)
+    }
+}
+
 pub enum DiffType {
     Insert(i128),
     Remove(i128),
+    /// A `NULL` value was inserted or removed. These do not take part in the extremum.
+    Null,
 }
 
 impl GroupedOperation for ExtremumOperator {
@@ -83,6 +128,7 @@ impl GroupedOperation for ExtremumOperator {
             DataType::UnsignedInt(n) => i128::from(n),
             DataType::BigInt(n) => i128::from(n),
             DataType::UnsignedBigInt(n) => i128::from(n),
+            DataType::None => return DiffType::Null,
             _ => {
                 // the column we're aggregating over is non-numerical (or rather, this value is).
                 // if you've removed a column, chances are the  default value has the wrong type.
@@ -109,6 +155,9 @@ impl GroupedOperation for ExtremumOperator {
         current: Option<&DataType>,
         diffs: &mut dyn Iterator<Item = Self::Diff>,
     ) -> DataType {
+        // a group whose values are all NULL has a NULL extremum
+        let current = current.filter(|data| !data.is_none());
+
         // Extreme values are those that are at least as extreme as the current min/max (if any).
         // let mut is_extreme_value : Box<dyn Fn(i64) -> bool> = Box::new(|_|true);
         let mut extreme_values: Vec<i128> = vec![];
@@ -160,6 +209,10 @@ impl GroupedOperation for ExtremumOperator {
         if let Some(extreme) = extreme {
             return extreme.into();
         }
+        if current.is_none() {
+            // we've only seen NULLs
+            return DataType::None;
+        }
 
         // TODO: handle this case by querying into the parent.
         unimplemented!();
@@ -323,6 +376,29 @@ mod tests {
         assert!(out.is_empty());
     }
 
+    #[test]
+    fn it_ignores_nulls() {
+        let mut c = setup(Extremum::MIN, true);
+
+        // a group with only NULLs has a NULL minimum
+        let out = c.narrow_one_row(vec![1.into(), DataType::None], true);
+        let expected: Records = vec![vec![1.into(), DataType::None]].into();
+        assert_eq!(out, expected);
+
+        // until it gets a value
+        let out = c.narrow_one_row(vec![1.into(), 4.into()], true);
+        let expected: Records = vec![
+            Record::Negative(vec![1.into(), DataType::None]),
+            Record::Positive(vec![1.into(), 4.into()]),
+        ]
+        .into();
+        assert_eq!(out, expected);
+
+        // and NULLs never become the minimum
+        let out = c.narrow_one_row(vec![1.into(), DataType::None], true);
+        assert!(out.is_empty());
+    }
+
     #[test]
     fn it_suggests_indices() {
         let me = 1.into();
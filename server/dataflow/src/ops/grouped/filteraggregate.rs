@@ -1,6 +1,6 @@
 use std::sync;
 
-use crate::ops::filter::{FilterCondition, Value};
+use crate::ops::filter::FilterCondition;
 use crate::ops::grouped::GroupedOperation;
 use crate::ops::grouped::GroupedOperator;
 pub use nom_sql::{Literal, Operator};
@@ -9,9 +9,12 @@ use crate::prelude::*;
 
 /// Supported aggregation operators.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
 pub enum FilterAggregation {
-    /// Count the number of records for each filtered group. The value for the `over` column is ignored.
+    /// Count the number of records for each filtered group whose `over` column is not `NULL`.
     COUNT,
+    /// Count every record of each filtered group. The value for the `over` column is ignored.
+    COUNT_STAR,
     /// Sum the value of the `over` column for all records of each filtered group.
     SUM,
 }
@@ -86,37 +89,17 @@ impl GroupedOperation for FilterAggregator {
     }
 
     fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
-        let passes_filter = self.filter.iter().all(|(i, cond)| {
-            // check if this filter matches
-            let d = &r[*i];
-            match *cond {
-                FilterCondition::Comparison(ref op, ref f) => {
-                    let v = match *f {
-                        Value::Constant(ref dt) => dt,
-                        Value::Column(c) => &r[c],
-                    };
-                    match *op {
-                        Operator::Equal => d == v,
-                        Operator::NotEqual => d != v,
-                        Operator::Greater => d > v,
-                        Operator::GreaterOrEqual => d >= v,
-                        Operator::Less => d < v,
-                        Operator::LessOrEqual => d <= v,
-                        Operator::In => unreachable!(),
-                        _ => unimplemented!(),
-                    }
-                }
-                FilterCondition::In(ref fs) => fs.contains(d),
-            }
-        });
+        let passes_filter = self.filter.iter().all(|(i, cond)| cond.matches(&r[*i], r));
         let v = if passes_filter {
             match self.op {
-                FilterAggregation::COUNT => 1,
+                FilterAggregation::COUNT if r[self.over].is_none() => 0,
+                FilterAggregation::COUNT | FilterAggregation::COUNT_STAR => 1,
                 FilterAggregation::SUM => match r[self.over] {
                     DataType::Int(n) => i128::from(n),
                     DataType::UnsignedInt(n) => i128::from(n),
                     DataType::BigInt(n) => i128::from(n),
                     DataType::UnsignedBigInt(n) => i128::from(n),
+                    DataType::HugeInt(ref n) => **n,
                     DataType::None => 0,
                     ref x => unreachable!("tried to aggregate over {:?} on {:?}", x, r),
                 },
@@ -125,7 +108,7 @@ impl GroupedOperation for FilterAggregator {
             // the filter returned false, so check whether we have an else case
             match self.over_else.clone() {
                 Some(over_else) => match self.op {
-                    FilterAggregation::COUNT => 1,
+                    FilterAggregation::COUNT | FilterAggregation::COUNT_STAR => 1,
                     FilterAggregation::SUM => match over_else {
                         Literal::Integer(n) => i128::from(n),
                         Literal::UnsignedInteger(n) => i128::from(n),
@@ -153,16 +136,18 @@ impl GroupedOperation for FilterAggregator {
             Some(&DataType::UnsignedInt(n)) => i128::from(n),
             Some(&DataType::BigInt(n)) => i128::from(n),
             Some(&DataType::UnsignedBigInt(n)) => i128::from(n),
+            Some(&DataType::HugeInt(ref n)) => **n,
             None => 0,
             _ => unreachable!(),
         };
+        // sums that do not fit in 64 bits widen to a `HugeInt`
         diffs.fold(n, |n, d| n + d).into()
     }
 
     fn description(&self, detailed: bool) -> String {
         if !detailed {
             return String::from(match self.op {
-                FilterAggregation::COUNT => "+σ",
+                FilterAggregation::COUNT | FilterAggregation::COUNT_STAR => "+σ",
                 FilterAggregation::SUM => "𝛴σ",
             });
         }
@@ -170,6 +155,7 @@ impl GroupedOperation for FilterAggregator {
         // TODO could include information about filter condition
         let op_string = match self.op {
             FilterAggregation::COUNT => format!("|σ({})|", self.over),
+            FilterAggregation::COUNT_STAR => "|σ(*)|".into(),
             FilterAggregation::SUM => format!("𝛴(σ({}))", self.over),
         };
 
@@ -192,6 +178,7 @@ mod tests {
     use super::*;
 
     use crate::ops;
+    use crate::ops::filter::Value;
 
     fn setup(mat: bool) -> ops::test::MockGraph {
         let mut g = ops::test::MockGraph::new();
@@ -3,6 +3,7 @@ use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt;
 
+use crate::ops::filter::FilterCondition;
 use crate::prelude::*;
 
 // pub mod latest;
@@ -63,6 +64,24 @@ pub trait GroupedOperation: fmt::Debug + Clone {
     fn over_columns(&self) -> Vec<usize>;
 }
 
+/// A partial aggregation that a decomposable aggregation can be computed from.
+///
+/// When the input of such an aggregation is sharded, each shard computes the partial aggregation
+/// over its own records. Only the partial results that satisfy `only` are then merged, and the
+/// aggregation returned by `NodeOperator::combining_aggregation` combines them into the result.
+pub struct PartialAggregation {
+    /// The partial aggregation, which reads from the aggregation's parent.
+    pub op: NodeOperator,
+    /// The columns of the parent that partial results are grouped by, in the order in which they
+    /// appear in the output. The partial result itself is in the last column.
+    pub group_by: Vec<usize>,
+    /// Conditions that a partial result must satisfy to take part in the combined result.
+    pub only: Vec<(usize, FilterCondition)>,
+    /// Whether partial results still combine correctly when a record is removed from a different
+    /// part of the input than the one it was added to, as partial counts and sums do.
+    pub additive: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GroupedOperator<T: GroupedOperation> {
     src: IndexPair,
@@ -309,6 +328,15 @@ where
         vec![(self.src.as_global(), Some(self.colfix[column]))]
     }
 
+    fn used_parent_columns(&self, parent: NodeIndex) -> Vec<usize> {
+        if parent != self.src.as_global() {
+            return Vec::new();
+        }
+        let mut used = self.inner.group_by().to_vec();
+        used.extend(self.inner.over_columns());
+        used
+    }
+
     fn is_selective(&self) -> bool {
         true
     }
@@ -124,6 +124,16 @@ impl Join {
         }
     }
 
+    /// Whether every shard of this join may hold all of `parent`'s records, while its other
+    /// parent stays sharded however it already is.
+    ///
+    /// Each shard then joins its part of the other parent against all of `parent`. That is only
+    /// correct if no shard emits rows for records of `parent` that match nothing on that shard,
+    /// which a left join does for the records of its left parent.
+    pub fn may_broadcast(&self, parent: NodeIndex) -> bool {
+        self.kind == JoinType::Inner || parent == self.right.as_global()
+    }
+
     fn generate_row(
         &self,
         left: &[DataType],
@@ -590,6 +600,17 @@ impl Ingredient for Join {
         )
     }
 
+    fn used_parent_columns(&self, parent: NodeIndex) -> Vec<usize> {
+        let mut used = Vec::new();
+        if parent == self.left.as_global() {
+            used.push(self.on.0);
+        }
+        if parent == self.right.as_global() {
+            used.push(self.on.1);
+        }
+        used
+    }
+
     fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
         let pcol = self.emit[col];
         if (pcol.0 && pcol.1 == self.on.0) || (!pcol.0 && pcol.1 == self.on.1) {
@@ -142,6 +142,13 @@ impl Ingredient for Latest {
     fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
         vec![(self.src.as_global(), Some(column))]
     }
+
+    fn used_parent_columns(&self, parent: NodeIndex) -> Vec<usize> {
+        if parent != self.src.as_global() {
+            return Vec::new();
+        }
+        vec![self.key]
+    }
 }
 
 #[cfg(test)]
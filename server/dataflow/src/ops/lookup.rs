@@ -0,0 +1,537 @@
+//! Joins records with rows that are looked up in an external service.
+//!
+//! The service is reached through a [`LookupClient`] that is registered in each process under a
+//! name, which is what the operator refers to it by, since the client itself cannot be part of
+//! the graph.
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures_util::future;
+use lazy_static::lazy_static;
+
+use super::join::JoinType;
+use crate::prelude::*;
+
+/// How long looked up rows are used for by default.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// How long to wait before the first retry of failed lookups.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+
+/// The rows that a lookup of a key produces.
+pub type LookupFuture = Pin<Box<dyn Future<Output = Result<Vec<Vec<DataType>>, String>> + Send>>;
+
+/// A client for an external service that maps keys to rows.
+pub trait LookupClient: Send + Sync {
+    /// Look up the rows for `key`.
+    ///
+    /// An empty result means that the service has no rows for the key, while an error means that
+    /// it could not be asked, which is handled according to the operator's [`OnFailure`].
+    fn lookup(&self, key: &DataType) -> LookupFuture;
+}
+
+lazy_static! {
+    static ref CLIENTS: RwLock<HashMap<String, Arc<dyn LookupClient>>> = Default::default();
+}
+
+/// Let the external lookups that run in this process reach their service through `client` if
+/// they use `name`.
+///
+/// Every worker whose domains may run such a lookup must register a client under its name.
+pub fn register_client(name: &str, client: Arc<dyn LookupClient>) {
+    CLIENTS.write().unwrap().insert(name.to_owned(), client);
+}
+
+/// What an external lookup does with records whose keys the service could not be asked about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OnFailure {
+    /// Ask again until the service answers, waiting twice as long after each failure, up to
+    /// `max_backoff`. The domain processes nothing else in the meantime, so whatever comes after
+    /// is held back upstream.
+    Retry { max_backoff: Duration },
+    /// Join the records with `NULL`s, as a left join does with records that have no match.
+    Null,
+}
+
+type Rows = Arc<Vec<Vec<DataType>>>;
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    rows: Rows,
+    fetched: Instant,
+}
+
+/// Joins each record with the rows that an external service has for the value of one of its
+/// columns.
+///
+/// The rows for each key are cached for a time to live, after which the next record with that
+/// key looks them up again. A record is retracted by retracting the rows it was joined with when
+/// it arrived, even if the service has other rows for its key by then. Replays go through the same
+/// cache, and records that have been joined before are replayed with the rows they were joined
+/// with, so that replays agree with what the operator has already emitted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalLookup {
+    src: IndexPair,
+    key: usize,
+    client: String,
+    columns: usize,
+    kind: JoinType,
+    ttl: Duration,
+    on_failure: OnFailure,
+    cols: usize,
+
+    #[serde(skip)]
+    cache: HashMap<DataType, CacheEntry>,
+    /// The rows that each record that has not been retracted was joined with.
+    #[serde(skip)]
+    joined: HashMap<Vec<DataType>, Vec<Rows>>,
+    #[serde(skip)]
+    swept: Option<Instant>,
+    #[serde(skip)]
+    failures: usize,
+}
+
+impl ExternalLookup {
+    /// Construct a new external lookup operator.
+    ///
+    /// Records from `src` are joined with the rows, of `columns` columns each, that the client
+    /// registered as `client` has for the value of their `key` column. The output has the columns
+    /// of `src` followed by those of the rows. As with joins, records without any rows are
+    /// dropped by an inner join, and joined with `NULL`s by a left join.
+    pub fn new(src: NodeIndex, key: usize, client: &str, columns: usize, kind: JoinType) -> Self {
+        ExternalLookup {
+            src: src.into(),
+            key,
+            client: client.to_owned(),
+            columns,
+            kind,
+            ttl: DEFAULT_TTL,
+            on_failure: OnFailure::Null,
+            cols: 0,
+            cache: HashMap::new(),
+            joined: HashMap::new(),
+            swept: None,
+            failures: 0,
+        }
+    }
+
+    /// Use looked up rows for `ttl`, rather than for `DEFAULT_TTL`.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Handle failed lookups according to `policy`, rather than by joining with `NULL`s.
+    pub fn on_failure(mut self, policy: OnFailure) -> Self {
+        self.on_failure = policy;
+        self
+    }
+
+    fn nulls(&self) -> Rows {
+        Arc::new(vec![vec![DataType::None; self.columns]])
+    }
+
+    fn is_fresh(&self, key: &DataType, now: Instant) -> bool {
+        self.cache
+            .get(key)
+            .map_or(false, |e| now.duration_since(e.fetched) < self.ttl)
+    }
+
+    /// Forget rows that have outlived their time to live, at most once per time to live.
+    fn sweep(&mut self, now: Instant) {
+        if self
+            .swept
+            .map_or(false, |t| now.duration_since(t) < self.ttl)
+        {
+            return;
+        }
+        let ttl = self.ttl;
+        self.cache
+            .retain(|_, e| now.duration_since(e.fetched) < ttl);
+        self.swept = Some(now);
+    }
+
+    /// Look up all of `keys` at once, and cache the results. Returns the keys whose lookups
+    /// failed, which only happens when failed records are joined with `NULL`s.
+    fn fetch(&mut self, mut keys: Vec<DataType>) -> HashSet<DataType> {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let client = CLIENTS.read().unwrap().get(&self.client).cloned();
+            let results = match client {
+                Some(client) => {
+                    let lookups = future::join_all(keys.iter().map(|k| client.lookup(k)));
+                    // the domain cannot go on without the rows, so it waits for them much like it
+                    // waits for the disk
+                    tokio::task::block_in_place(|| futures_executor::block_on(lookups))
+                }
+                None => keys
+                    .iter()
+                    .map(|_| Err(format!("no lookup client {} is registered", self.client)))
+                    .collect(),
+            };
+
+            let now = Instant::now();
+            let mut failed = Vec::new();
+            for (key, result) in keys.into_iter().zip(results) {
+                match result {
+                    Ok(ref rows) if rows.iter().any(|r| r.len() != self.columns) => {
+                        failed.push(key)
+                    }
+                    Ok(rows) => {
+                        let rows = Arc::new(rows);
+                        self.cache.insert(key, CacheEntry { rows, fetched: now });
+                    }
+                    Err(_) => failed.push(key),
+                }
+            }
+            self.failures += failed.len();
+
+            match self.on_failure {
+                _ if failed.is_empty() => return HashSet::new(),
+                OnFailure::Null => return failed.into_iter().collect(),
+                OnFailure::Retry { max_backoff } => {
+                    tokio::task::block_in_place(|| thread::sleep(backoff));
+                    backoff = std::cmp::min(backoff * 2, max_backoff);
+                    keys = failed;
+                }
+            }
+        }
+    }
+
+    /// The rows to join a new record with `key` with.
+    fn rows_for(&self, key: &DataType, failed: &HashSet<DataType>) -> Rows {
+        if failed.contains(key) {
+            return self.nulls();
+        }
+        match self.cache.get(key) {
+            Some(e) if !e.rows.is_empty() => e.rows.clone(),
+            _ if self.kind == JoinType::Left => self.nulls(),
+            _ => Arc::new(Vec::new()),
+        }
+    }
+}
+
+impl Ingredient for ExternalLookup {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        vec![self.src.as_global()]
+    }
+
+    fn on_connected(&mut self, g: &Graph) {
+        self.cols = g[self.src.as_global()].fields().len();
+    }
+
+    fn on_commit(&mut self, _: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        self.src.remap(remap);
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        replay_key_cols: Option<&[usize]>,
+        _: &DomainNodes,
+        _: &StateMap,
+    ) -> ProcessingResult {
+        debug_assert_eq!(from, *self.src);
+        let replay = replay_key_cols.is_some();
+
+        // look up the keys of the new records that need it all at once
+        let now = Instant::now();
+        self.sweep(now);
+        let mut keys: Vec<_> = rs
+            .iter()
+            .filter(|r| r.is_positive() && !(replay && self.joined.contains_key(r.rec())))
+            .map(|r| &r[self.key])
+            .filter(|k| !k.is_none() && !self.is_fresh(k, now))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.dedup();
+        let failed = if keys.is_empty() {
+            HashSet::new()
+        } else {
+            self.fetch(keys)
+        };
+
+        let mut out = Vec::with_capacity(rs.len());
+        for r in rs {
+            let (r, positive) = r.extract();
+            let rows = if positive {
+                let previous = if replay {
+                    self.joined.get(&r).and_then(|rows| rows.last()).cloned()
+                } else {
+                    None
+                };
+                match previous {
+                    Some(rows) => rows,
+                    None => {
+                        let rows = self.rows_for(&r[self.key], &failed);
+                        self.joined
+                            .entry(r.clone())
+                            .or_insert_with(Vec::new)
+                            .push(rows.clone());
+                        rows
+                    }
+                }
+            } else {
+                let rows = match self.joined.get_mut(&r) {
+                    Some(joined) => {
+                        let rows = joined.pop();
+                        if joined.is_empty() {
+                            self.joined.remove(&r);
+                        }
+                        rows
+                    }
+                    None => None,
+                };
+                match rows {
+                    Some(rows) => rows,
+                    // the record was never joined, so nothing was emitted for it
+                    None => continue,
+                }
+            };
+
+            for row in rows.iter() {
+                let mut joined = r.clone();
+                joined.extend(row.iter().cloned());
+                out.push(if positive {
+                    Record::Positive(joined)
+                } else {
+                    Record::Negative(joined)
+                });
+            }
+        }
+
+        ProcessingResult {
+            results: out.into(),
+            ..Default::default()
+        }
+    }
+
+    fn suggest_indexes(&self, _: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        HashMap::new()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        if col < self.cols {
+            Some(vec![(self.src.as_global(), col)])
+        } else {
+            None
+        }
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        let op = match self.kind {
+            JoinType::Left => "⋉",
+            JoinType::Inner => "⋈",
+        };
+        if !detailed {
+            return String::from(op);
+        }
+        format!("{} {}[{}]", op, self.client, self.key)
+    }
+
+    fn probe(&self) -> HashMap<String, String> {
+        let mut hm = HashMap::new();
+        hm.insert("cached keys".into(), format!("{}", self.cache.len()));
+        hm.insert("joined records".into(), format!("{}", self.joined.len()));
+        hm.insert("failed lookups".into(), format!("{}", self.failures));
+        hm
+    }
+
+    fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        let col = if column < self.cols {
+            Some(column)
+        } else {
+            None
+        };
+        vec![(self.src.as_global(), col)]
+    }
+
+    fn used_parent_columns(&self, parent: NodeIndex) -> Vec<usize> {
+        if parent != self.src.as_global() {
+            return Vec::new();
+        }
+        vec![self.key]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Service {
+        rows: Mutex<HashMap<DataType, Vec<Vec<DataType>>>>,
+        down: AtomicBool,
+        lookups: AtomicUsize,
+    }
+
+    impl Service {
+        fn set(&self, key: DataType, rows: Vec<Vec<DataType>>) {
+            self.rows.lock().unwrap().insert(key, rows);
+        }
+    }
+
+    impl LookupClient for Service {
+        fn lookup(&self, key: &DataType) -> LookupFuture {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            let result = if self.down.load(Ordering::SeqCst) {
+                Err(String::from("down"))
+            } else {
+                Ok(self
+                    .rows
+                    .lock()
+                    .unwrap()
+                    .get(key)
+                    .cloned()
+                    .unwrap_or_default())
+            };
+            Box::pin(future::ready(result))
+        }
+    }
+
+    fn setup<F>(name: &str, kind: JoinType, configure: F) -> (ops::test::MockGraph, Arc<Service>)
+    where
+        F: FnOnce(ExternalLookup) -> ExternalLookup,
+    {
+        let service = Arc::new(Service::default());
+        service.set(1.into(), vec![vec!["one".into()]]);
+        service.set(2.into(), vec![vec!["two".into()], vec!["deux".into()]]);
+        register_client(name, service.clone());
+
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        let op = configure(ExternalLookup::new(s.as_global(), 0, name, 1, kind));
+        g.set_op("lookup", &["x", "y", "z"], op, false);
+        (g, service)
+    }
+
+    #[test]
+    fn it_joins() {
+        let (mut g, service) = setup("it_joins", JoinType::Inner, |op| op);
+        assert_eq!(g.node().description(true), "⋈ it_joins[0]");
+
+        let rs = g.narrow_one_row(vec![1.into(), "a".into()], false);
+        assert_eq!(rs, vec![vec![1.into(), "a".into(), "one".into()]].into());
+
+        let rs = g.narrow_one_row(vec![2.into(), "b".into()], false);
+        assert_eq!(
+            rs,
+            vec![
+                vec![2.into(), "b".into(), "two".into()],
+                vec![2.into(), "b".into(), "deux".into()],
+            ]
+            .into()
+        );
+
+        // no rows
+        assert!(g
+            .narrow_one_row(vec![3.into(), "c".into()], false)
+            .is_empty());
+        // NULLs are never looked up
+        assert!(g
+            .narrow_one_row(vec![DataType::None, "c".into()], false)
+            .is_empty());
+
+        // the rows for 1 are cached
+        g.narrow_one_row(vec![1.into(), "d".into()], false);
+        assert_eq!(service.lookups.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn it_left_joins() {
+        let (mut g, _) = setup("it_left_joins", JoinType::Left, |op| op);
+        let rs = g.narrow_one_row(vec![3.into(), "c".into()], false);
+        assert_eq!(rs, vec![vec![3.into(), "c".into(), DataType::None]].into());
+    }
+
+    #[test]
+    fn it_retracts_what_it_joined() {
+        let (mut g, service) = setup("it_retracts_what_it_joined", JoinType::Inner, |op| {
+            op.with_ttl(Duration::from_secs(0))
+        });
+
+        g.narrow_one_row(vec![1.into(), "a".into()], false);
+        service.set(1.into(), vec![vec!["uno".into()]]);
+
+        // the rows have expired, so new records see the new rows
+        let rs = g.narrow_one_row(vec![1.into(), "b".into()], false);
+        assert_eq!(rs, vec![vec![1.into(), "b".into(), "uno".into()]].into());
+
+        // but retractions retract what was emitted
+        let rs = g.narrow_one_row(Record::Negative(vec![1.into(), "a".into()]), false);
+        assert_eq!(
+            rs,
+            vec![Record::Negative(vec![1.into(), "a".into(), "one".into()])].into()
+        );
+        let rs = g.narrow_one_row(Record::Negative(vec![1.into(), "b".into()]), false);
+        assert_eq!(
+            rs,
+            vec![Record::Negative(vec![1.into(), "b".into(), "uno".into()])].into()
+        );
+
+        // records that were never joined have nothing to retract
+        assert!(g
+            .narrow_one_row(Record::Negative(vec![1.into(), "a".into()]), false)
+            .is_empty());
+    }
+
+    #[test]
+    fn it_joins_failures_with_nulls() {
+        let (mut g, service) = setup("it_joins_failures_with_nulls", JoinType::Inner, |op| op);
+        service.down.store(true, Ordering::SeqCst);
+
+        let rs = g.narrow_one_row(vec![1.into(), "a".into()], false);
+        assert_eq!(rs, vec![vec![1.into(), "a".into(), DataType::None]].into());
+        assert_eq!(g.node().probe()["failed lookups"], "1");
+
+        // failures are not cached
+        service.down.store(false, Ordering::SeqCst);
+        let rs = g.narrow_one_row(vec![1.into(), "b".into()], false);
+        assert_eq!(rs, vec![vec![1.into(), "b".into(), "one".into()]].into());
+
+        // and the failed record is retracted as it was emitted
+        let rs = g.narrow_one_row(Record::Negative(vec![1.into(), "a".into()]), false);
+        assert_eq!(
+            rs,
+            vec![Record::Negative(vec![1.into(), "a".into(), DataType::None])].into()
+        );
+    }
+
+    #[test]
+    fn it_retries_failures() {
+        let (mut g, service) = setup("it_retries_failures", JoinType::Inner, |op| {
+            op.on_failure(OnFailure::Retry {
+                max_backoff: Duration::from_millis(20),
+            })
+        });
+        service.down.store(true, Ordering::SeqCst);
+
+        let up = {
+            let service = service.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                service.down.store(false, Ordering::SeqCst);
+            })
+        };
+        let rs = g.narrow_one_row(vec![1.into(), "a".into()], false);
+        assert_eq!(rs, vec![vec![1.into(), "a".into(), "one".into()]].into());
+        assert!(service.lookups.load(Ordering::SeqCst) > 1);
+        up.join().unwrap();
+    }
+}
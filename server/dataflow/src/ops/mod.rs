@@ -1,6 +1,7 @@
 use slog::Logger;
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 use crate::prelude::*;
 
@@ -10,10 +11,12 @@ pub mod grouped;
 pub mod identity;
 pub mod join;
 pub mod latest;
+pub mod lookup;
 pub mod project;
 pub mod rewrite;
 pub mod topk;
 pub mod trigger;
+pub mod udf;
 pub mod union;
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -33,6 +36,7 @@ pub enum NodeOperator {
     Trigger(trigger::Trigger),
     Rewrite(rewrite::Rewrite),
     Distinct(distinct::Distinct),
+    ExternalLookup(lookup::ExternalLookup),
 }
 
 macro_rules! nodeop_from_impl {
@@ -71,6 +75,42 @@ nodeop_from_impl!(NodeOperator::TopK, topk::TopK);
 nodeop_from_impl!(NodeOperator::Trigger, trigger::Trigger);
 nodeop_from_impl!(NodeOperator::Rewrite, rewrite::Rewrite);
 nodeop_from_impl!(NodeOperator::Distinct, distinct::Distinct);
+nodeop_from_impl!(NodeOperator::ExternalLookup, lookup::ExternalLookup);
+
+impl NodeOperator {
+    /// The partial aggregation that this operator can be computed from when its input is sharded,
+    /// if it is an aggregation that can be decomposed that way.
+    ///
+    /// Counts, sums, minimums, and maximums can. Other aggregations, such as `GROUP_CONCAT` and
+    /// filtered aggregations, are not decomposed, and must see all their input records.
+    pub fn partial_aggregation(&self) -> Option<grouped::PartialAggregation> {
+        match *self {
+            NodeOperator::Sum(ref a) => Some(a.partial()),
+            NodeOperator::Extremum(ref e) => Some(e.partial()),
+            _ => None,
+        }
+    }
+
+    /// The aggregation that combines the results of this operator's `partial_aggregation`, read
+    /// from `partials`, into the result of this operator.
+    pub fn combining_aggregation(&self, partials: NodeIndex) -> Option<NodeOperator> {
+        match *self {
+            NodeOperator::Sum(ref a) => Some(a.combining(partials).into()),
+            NodeOperator::Extremum(ref e) => Some(e.combining(partials).into()),
+            _ => None,
+        }
+    }
+
+    /// Whether every shard of this operator may hold all of `parent`'s records.
+    ///
+    /// Only joins can do with such a copy of one of their parents.
+    pub fn may_broadcast(&self, parent: NodeIndex) -> bool {
+        match *self {
+            NodeOperator::Join(ref j) => j.may_broadcast(parent),
+            _ => false,
+        }
+    }
+}
 
 macro_rules! impl_ingredient_fn_mut {
     ($self:ident, $fn:ident, $( $arg:ident ),* ) => {
@@ -89,6 +129,7 @@ macro_rules! impl_ingredient_fn_mut {
             NodeOperator::Trigger(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Rewrite(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Distinct(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::ExternalLookup(ref mut i) => i.$fn($($arg),*),
         }
     }
 }
@@ -110,6 +151,7 @@ macro_rules! impl_ingredient_fn_ref {
             NodeOperator::Trigger(ref i) => i.$fn($($arg),*),
             NodeOperator::Rewrite(ref i) => i.$fn($($arg),*),
             NodeOperator::Distinct(ref i) => i.$fn($($arg),*),
+            NodeOperator::ExternalLookup(ref i) => i.$fn($($arg),*),
         }
     }
 }
@@ -145,6 +187,9 @@ impl Ingredient for NodeOperator {
     fn on_commit(&mut self, you: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
         impl_ingredient_fn_mut!(self, on_commit, you, remap)
     }
+    fn udfs_mut(&mut self) -> Vec<&mut Arc<udf::Udf>> {
+        impl_ingredient_fn_mut!(self, udfs_mut,)
+    }
     fn on_input(
         &mut self,
         ex: &mut dyn Executor,
@@ -217,6 +262,9 @@ impl Ingredient for NodeOperator {
     fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
         impl_ingredient_fn_ref!(self, parent_columns, column)
     }
+    fn used_parent_columns(&self, parent: NodeIndex) -> Vec<usize> {
+        impl_ingredient_fn_ref!(self, used_parent_columns, parent)
+    }
     fn is_selective(&self) -> bool {
         impl_ingredient_fn_ref!(self, is_selective,)
     }
@@ -419,9 +467,15 @@ pub mod test {
             struct Ex;
 
             impl Executor for Ex {
-                fn ack(&mut self, _: SourceChannelIdentifier) {}
+                fn ack(&mut self, _: SourceChannelIdentifier, _: WriteAck) {}
                 fn create_universe(&mut self, _: HashMap<String, DataType>) {}
                 fn send(&mut self, _: ReplicaAddr, _: Box<Packet>) {}
+                fn queued(&self, _: ReplicaAddr) -> usize {
+                    0
+                }
+                fn queued_records(&self) -> (usize, usize) {
+                    (0, 0)
+                }
             }
 
             let mut u = {
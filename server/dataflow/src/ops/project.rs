@@ -1,9 +1,12 @@
-use nom_sql::ArithmeticOperator;
+use chrono::Datelike;
+use nom_sql::{ArithmeticOperator, Operator};
 
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::Arc;
 
+use super::udf::Udf;
 use crate::prelude::*;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,11 +15,172 @@ pub enum ProjectExpressionBase {
     Literal(DataType),
 }
 
+/// Functions that extract part of a date or timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DateFunction {
+    /// `YEAR()`: the year, as an integer.
+    Year,
+    /// `MONTH()`: the month of the year, from 1 to 12.
+    Month,
+    /// `DATE()`: the date, without its time of day.
+    Date,
+}
+
+impl DateFunction {
+    /// Apply the function to a value. Strings are read as date literals, and `NULL` is returned
+    /// for anything that is not a date.
+    pub fn apply(self, value: &DataType) -> DataType {
+        let date = match *value {
+            DataType::Timestamp(ts) => ts.date(),
+            DataType::Date(d) => d,
+            ref v if v.is_string() => match DataType::parse_date(v.into()) {
+                Ok(DataType::Date(d)) => d,
+                _ => return DataType::None,
+            },
+            _ => return DataType::None,
+        };
+        match self {
+            DateFunction::Year => date.year().into(),
+            DateFunction::Month => (date.month() as i32).into(),
+            DateFunction::Date => date.into(),
+        }
+    }
+}
+
+impl fmt::Display for DateFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DateFunction::Year => write!(f, "YEAR"),
+            DateFunction::Month => write!(f, "MONTH"),
+            DateFunction::Date => write!(f, "DATE"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum JsonPathStep {
+    Key(String),
+    Index(usize),
+}
+
+/// A path to a value inside a JSON document, such as `$.a.b[0]`.
+///
+/// Only object keys (`.key`) and array indices (`[n]`) are supported.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JsonPath(Vec<JsonPathStep>);
+
+impl JsonPath {
+    /// Parse a path of the form `$.a.b[0]`.
+    pub fn parse(path: &str) -> Result<JsonPath, String> {
+        if !path.starts_with('$') {
+            return Err(format!("JSON path {:?} does not start with $", path));
+        }
+
+        let mut steps = Vec::new();
+        let mut rest = &path[1..];
+        while !rest.is_empty() {
+            if rest.starts_with('.') {
+                let end = rest[1..]
+                    .find(|c| c == '.' || c == '[')
+                    .map(|i| i + 1)
+                    .unwrap_or_else(|| rest.len());
+                if end == 1 {
+                    return Err(format!("JSON path {:?} has an empty key", path));
+                }
+                steps.push(JsonPathStep::Key(rest[1..end].to_owned()));
+                rest = &rest[end..];
+            } else if rest.starts_with('[') {
+                let end = rest
+                    .find(']')
+                    .ok_or_else(|| format!("JSON path {:?} has an unclosed [", path))?;
+                let i = rest[1..end]
+                    .parse()
+                    .map_err(|_| format!("JSON path {:?} has a bad array index", path))?;
+                steps.push(JsonPathStep::Index(i));
+                rest = &rest[end + 1..];
+            } else {
+                return Err(format!("JSON path {:?} is malformed at {:?}", path, rest));
+            }
+        }
+        Ok(JsonPath(steps))
+    }
+
+    /// Extract the value at this path from a JSON document. Strings are read as JSON documents.
+    ///
+    /// Scalars become the corresponding `DataType`, while objects and arrays stay JSON. `NULL` is
+    /// returned if the path does not exist, or if the value is not a JSON document.
+    pub fn extract(&self, value: &DataType) -> DataType {
+        if !value.is_json() && !value.is_string() {
+            return DataType::None;
+        }
+        let doc: serde_json::Value = match serde_json::from_str(value.into()) {
+            Ok(doc) => doc,
+            Err(_) => return DataType::None,
+        };
+
+        let mut at = &doc;
+        for step in &self.0 {
+            let next = match *step {
+                JsonPathStep::Key(ref k) => at.get(k),
+                JsonPathStep::Index(i) => at.get(i),
+            };
+            at = match next {
+                Some(v) => v,
+                None => return DataType::None,
+            };
+        }
+
+        match *at {
+            serde_json::Value::Null => DataType::None,
+            serde_json::Value::Bool(b) => DataType::from(b),
+            serde_json::Value::Number(ref n) => {
+                if let Some(i) = n.as_i64() {
+                    i.into()
+                } else if let Some(u) = n.as_u64() {
+                    u.into()
+                } else {
+                    n.as_f64().map(DataType::from).unwrap_or(DataType::None)
+                }
+            }
+            serde_json::Value::String(ref s) => s.as_str().into(),
+            ref v @ serde_json::Value::Array(_) | ref v @ serde_json::Value::Object(_) => {
+                DataType::parse_json(&v.to_string()).unwrap()
+            }
+        }
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "$")?;
+        for step in &self.0 {
+            match *step {
+                JsonPathStep::Key(ref k) => write!(f, ".{}", k)?,
+                JsonPathStep::Index(i) => write!(f, "[{}]", i)?,
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ProjectExpression {
-    op: ArithmeticOperator,
-    left: ProjectExpressionBase,
-    right: ProjectExpressionBase,
+pub enum ProjectExpression {
+    Arithmetic {
+        op: ArithmeticOperator,
+        left: ProjectExpressionBase,
+        right: ProjectExpressionBase,
+    },
+    Date(DateFunction, ProjectExpressionBase),
+    /// `JSON_EXTRACT(doc, path)`
+    JsonExtract(ProjectExpressionBase, JsonPath),
+    /// A comparison, which produces a boolean, or `NULL` if either side is `NULL`.
+    Comparison {
+        op: Operator,
+        left: ProjectExpressionBase,
+        right: ProjectExpressionBase,
+    },
+    /// A call to a user-defined function.
+    Udf(Arc<Udf>, Vec<ProjectExpressionBase>),
 }
 
 impl ProjectExpression {
@@ -25,7 +189,62 @@ impl ProjectExpression {
         left: ProjectExpressionBase,
         right: ProjectExpressionBase,
     ) -> ProjectExpression {
-        ProjectExpression { op, left, right }
+        ProjectExpression::Arithmetic { op, left, right }
+    }
+
+    pub fn date(f: DateFunction, arg: ProjectExpressionBase) -> ProjectExpression {
+        ProjectExpression::Date(f, arg)
+    }
+
+    pub fn json_extract(doc: ProjectExpressionBase, path: JsonPath) -> ProjectExpression {
+        ProjectExpression::JsonExtract(doc, path)
+    }
+
+    /// A comparison of `left` with `right`, if `op` is one that compares two values.
+    pub fn comparison(
+        op: Operator,
+        left: ProjectExpressionBase,
+        right: ProjectExpressionBase,
+    ) -> Result<ProjectExpression, String> {
+        match op {
+            Operator::Equal
+            | Operator::NotEqual
+            | Operator::Greater
+            | Operator::GreaterOrEqual
+            | Operator::Less
+            | Operator::LessOrEqual => Ok(ProjectExpression::Comparison { op, left, right }),
+            op => Err(format!("can't project comparison with {}", op)),
+        }
+    }
+
+    pub fn udf(f: Arc<Udf>, args: Vec<ProjectExpressionBase>) -> ProjectExpression {
+        assert_eq!(
+            args.len(),
+            f.params().len(),
+            "{} takes {} arguments",
+            f,
+            f.params().len()
+        );
+        ProjectExpression::Udf(f, args)
+    }
+
+    /// The values the expression is computed from.
+    fn operands(&self) -> Vec<&ProjectExpressionBase> {
+        match *self {
+            ProjectExpression::Arithmetic {
+                ref left,
+                ref right,
+                ..
+            }
+            | ProjectExpression::Comparison {
+                ref left,
+                ref right,
+                ..
+            } => vec![left, right],
+            ProjectExpression::Date(_, ref arg) => vec![arg],
+            ProjectExpression::JsonExtract(ref doc, _) => vec![doc],
+            ProjectExpression::Udf(_, ref args) => args.iter().collect(),
+        }
     }
 }
 
@@ -40,14 +259,34 @@ impl fmt::Display for ProjectExpressionBase {
 
 impl fmt::Display for ProjectExpression {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let op = match self.op {
+        let (op, left, right) = match *self {
+            ProjectExpression::Arithmetic {
+                ref op,
+                ref left,
+                ref right,
+            } => (op, left, right),
+            ProjectExpression::Date(func, ref arg) => return write!(f, "{}({})", func, arg),
+            ProjectExpression::JsonExtract(ref doc, ref path) => {
+                return write!(f, "JSON_EXTRACT({}, {})", doc, path)
+            }
+            ProjectExpression::Udf(ref func, ref args) => {
+                let args: Vec<_> = args.iter().map(ToString::to_string).collect();
+                return write!(f, "{}({})", func, args.join(", "));
+            }
+            ProjectExpression::Comparison {
+                ref op,
+                ref left,
+                ref right,
+            } => return write!(f, "{} {} {}", left, op, right),
+        };
+        let op = match *op {
             ArithmeticOperator::Add => "+",
             ArithmeticOperator::Subtract => "-",
             ArithmeticOperator::Divide => "/",
             ArithmeticOperator::Multiply => "*",
         };
 
-        write!(f, "{} {} {}", self.left, op, self.right)
+        write!(f, "{} {} {}", left, op, right)
     }
 }
 
@@ -100,18 +339,36 @@ impl Project {
     }
 }
 
-fn eval_expression(expression: &ProjectExpression, record: &[DataType]) -> DataType {
-    let left = match expression.left {
+fn eval_base<'a>(base: &'a ProjectExpressionBase, record: &'a [DataType]) -> &'a DataType {
+    match *base {
         ProjectExpressionBase::Column(i) => &record[i],
         ProjectExpressionBase::Literal(ref data) => data,
-    };
+    }
+}
 
-    let right = match expression.right {
-        ProjectExpressionBase::Column(i) => &record[i],
-        ProjectExpressionBase::Literal(ref data) => data,
+fn eval_expression(expression: &ProjectExpression, record: &[DataType]) -> DataType {
+    let (op, left, right) = match *expression {
+        ProjectExpression::Arithmetic {
+            ref op,
+            ref left,
+            ref right,
+        } => (op, eval_base(left, record), eval_base(right, record)),
+        ProjectExpression::Date(func, ref arg) => return func.apply(eval_base(arg, record)),
+        ProjectExpression::JsonExtract(ref doc, ref path) => {
+            return path.extract(eval_base(doc, record))
+        }
+        ProjectExpression::Udf(ref f, ref args) => {
+            let args: Vec<_> = args.iter().map(|a| eval_base(a, record)).collect();
+            return f.call(&args);
+        }
+        ProjectExpression::Comparison {
+            ref op,
+            ref left,
+            ref right,
+        } => return eval_comparison(op, eval_base(left, record), eval_base(right, record)),
     };
 
-    match expression.op {
+    match *op {
         ArithmeticOperator::Add => left + right,
         ArithmeticOperator::Subtract => left - right,
         ArithmeticOperator::Multiply => left * right,
@@ -119,6 +376,23 @@ fn eval_expression(expression: &ProjectExpression, record: &[DataType]) -> DataT
     }
 }
 
+fn eval_comparison(op: &Operator, left: &DataType, right: &DataType) -> DataType {
+    if left.is_none() || right.is_none() {
+        return DataType::None;
+    }
+    let b = match *op {
+        Operator::Equal => left == right,
+        Operator::NotEqual => left != right,
+        Operator::Greater => left > right,
+        Operator::GreaterOrEqual => left >= right,
+        Operator::Less => left < right,
+        Operator::LessOrEqual => left <= right,
+        // ProjectExpression::comparison rejects the other operators
+        _ => return DataType::None,
+    };
+    b.into()
+}
+
 impl Ingredient for Project {
     fn take(&mut self) -> NodeOperator {
         Clone::clone(self).into()
@@ -220,6 +494,17 @@ impl Ingredient for Project {
         });
     }
 
+    fn udfs_mut(&mut self) -> Vec<&mut Arc<Udf>> {
+        self.expressions
+            .iter_mut()
+            .flatten()
+            .filter_map(|e| match *e {
+                ProjectExpression::Udf(ref mut f, _) => Some(f),
+                _ => None,
+            })
+            .collect()
+    }
+
     fn on_input(
         &mut self,
         _: &mut dyn Executor,
@@ -296,6 +581,21 @@ impl Ingredient for Project {
         format!("π[{}]", emit_cols.join(", "))
     }
 
+    fn used_parent_columns(&self, parent: NodeIndex) -> Vec<usize> {
+        if parent != self.src.as_global() {
+            return Vec::new();
+        }
+        self.expressions
+            .iter()
+            .flatten()
+            .flat_map(|e| e.operands())
+            .filter_map(|b| match *b {
+                ProjectExpressionBase::Column(c) => Some(c),
+                ProjectExpressionBase::Literal(_) => None,
+            })
+            .collect()
+    }
+
     fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
         let result = if self.emit.is_some() && column >= self.emit.as_ref().unwrap().len() {
             None
@@ -351,7 +651,7 @@ mod tests {
     }
 
     fn setup_column_arithmetic(op: ArithmeticOperator) -> ops::test::MockGraph {
-        let expression = ProjectExpression {
+        let expression = ProjectExpression::Arithmetic {
             left: ProjectExpressionBase::Column(0),
             right: ProjectExpressionBase::Column(1),
             op,
@@ -440,6 +740,229 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_forwards_date_functions() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        let date = |f| ProjectExpression::date(f, ProjectExpressionBase::Column(1));
+        g.set_op(
+            "dates",
+            &["x", "year", "month", "date"],
+            Project::new(
+                s.as_global(),
+                &[0],
+                None,
+                Some(vec![
+                    date(DateFunction::Year),
+                    date(DateFunction::Month),
+                    date(DateFunction::Date),
+                ]),
+            ),
+            false,
+        );
+        assert_eq!(
+            g.node().description(true),
+            "π[0, YEAR(1), MONTH(1), DATE(1)]"
+        );
+
+        let ts = DataType::parse_datetime("2020-02-29 12:34:56").unwrap();
+        let day = DataType::parse_date("2020-02-29").unwrap();
+        assert_eq!(
+            g.narrow_one_row(vec![1.into(), ts], false),
+            vec![vec![1.into(), 2020.into(), 2.into(), day.clone()]].into()
+        );
+        assert_eq!(
+            g.narrow_one_row(vec![2.into(), "2021-03-01".into()], false),
+            vec![vec![
+                2.into(),
+                2021.into(),
+                3.into(),
+                DataType::parse_date("2021-03-01").unwrap()
+            ]]
+            .into()
+        );
+        assert_eq!(
+            g.narrow_one_row(vec![3.into(), DataType::None], false),
+            vec![vec![
+                3.into(),
+                DataType::None,
+                DataType::None,
+                DataType::None
+            ]]
+            .into()
+        );
+    }
+
+    #[test]
+    fn it_extracts_json_fields() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "doc"]);
+        let extract = |path| {
+            ProjectExpression::json_extract(
+                ProjectExpressionBase::Column(1),
+                JsonPath::parse(path).unwrap(),
+            )
+        };
+        g.set_op(
+            "fields",
+            &["x", "name", "first", "tags", "missing"],
+            Project::new(
+                s.as_global(),
+                &[0],
+                None,
+                Some(vec![
+                    extract("$.user.name"),
+                    extract("$.items[0]"),
+                    extract("$.tags"),
+                    extract("$.user.age"),
+                ]),
+            ),
+            false,
+        );
+        assert_eq!(
+            g.node().description(true),
+            "π[0, JSON_EXTRACT(1, $.user.name), JSON_EXTRACT(1, $.items[0]), \
+             JSON_EXTRACT(1, $.tags), JSON_EXTRACT(1, $.user.age)]"
+        );
+
+        let doc =
+            DataType::parse_json(r#"{"user": {"name": "bob"}, "items": [7, 8], "tags": ["a"]}"#)
+                .unwrap();
+        assert_eq!(
+            g.narrow_one_row(vec![1.into(), doc], false),
+            vec![vec![
+                1.into(),
+                "bob".into(),
+                7.into(),
+                DataType::parse_json(r#"["a"]"#).unwrap(),
+                DataType::None,
+            ]]
+            .into()
+        );
+        assert_eq!(
+            g.narrow_one_row(vec![2.into(), DataType::None], false),
+            vec![vec![
+                2.into(),
+                DataType::None,
+                DataType::None,
+                DataType::None,
+                DataType::None,
+            ]]
+            .into()
+        );
+    }
+
+    #[test]
+    fn it_forwards_comparisons() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op(
+            "compare",
+            &["x", "y", "big"],
+            Project::new(
+                s.as_global(),
+                &[0, 1],
+                None,
+                Some(vec![ProjectExpression::comparison(
+                    Operator::Greater,
+                    ProjectExpressionBase::Column(1),
+                    ProjectExpressionBase::Literal(10.into()),
+                )
+                .unwrap()]),
+            ),
+            false,
+        );
+        assert_eq!(g.node().description(true), "π[0, 1, 1 > (lit: 10)]");
+
+        assert_eq!(
+            g.narrow_one_row(vec![1.into(), 20.into()], false),
+            vec![vec![1.into(), 20.into(), true.into()]].into()
+        );
+        assert_eq!(
+            g.narrow_one_row(vec![2.into(), 5.into()], false),
+            vec![vec![2.into(), 5.into(), false.into()]].into()
+        );
+        assert_eq!(
+            g.narrow_one_row(vec![3.into(), DataType::None], false),
+            vec![vec![3.into(), DataType::None, DataType::None]].into()
+        );
+
+        // operators that don't compare two values are turned away up front
+        assert!(ProjectExpression::comparison(
+            Operator::Like,
+            ProjectExpressionBase::Column(1),
+            ProjectExpressionBase::Literal("a%".into()),
+        )
+        .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "wasm")]
+    fn it_forwards_udfs() {
+        use crate::ops::udf::UdfType;
+
+        let wat = r#"
+            (module
+              (func (export "score") (param i64 i64) (result i64)
+                local.get 0
+                i64.const 10
+                i64.mul
+                local.get 1
+                i64.add))
+        "#;
+        let score = Udf::new(
+            "score",
+            wat.as_bytes().to_vec(),
+            "score",
+            vec![UdfType::Int, UdfType::Int],
+            UdfType::Int,
+        )
+        .unwrap();
+
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op(
+            "score",
+            &["x", "y", "score"],
+            Project::new(
+                s.as_global(),
+                &[0, 1],
+                None,
+                Some(vec![ProjectExpression::udf(
+                    Arc::new(score),
+                    vec![
+                        ProjectExpressionBase::Column(0),
+                        ProjectExpressionBase::Column(1),
+                    ],
+                )]),
+            ),
+            false,
+        );
+        assert_eq!(g.node().description(true), "π[0, 1, score(0, 1)]");
+
+        assert_eq!(
+            g.narrow_one_row(vec![4.into(), 2.into()], false),
+            vec![vec![4.into(), 2.into(), 42.into()]].into()
+        );
+        assert_eq!(
+            g.narrow_one_row(vec![4.into(), DataType::None], false),
+            vec![vec![4.into(), DataType::None, DataType::None]].into()
+        );
+    }
+
+    #[test]
+    fn it_parses_json_paths() {
+        assert_eq!(
+            JsonPath::parse("$.a.b[10].c").unwrap().to_string(),
+            "$.a.b[10].c"
+        );
+        assert_eq!(JsonPath::parse("$").unwrap().to_string(), "$");
+        assert!(JsonPath::parse("a.b").is_err());
+        assert!(JsonPath::parse("$..a").is_err());
+        assert!(JsonPath::parse("$.a[x]").is_err());
+        assert!(JsonPath::parse("$.a[1").is_err());
+    }
+
     #[test]
     fn it_forwards_subtraction_arithmetic() {
         let mut p = setup_column_arithmetic(ArithmeticOperator::Subtract);
@@ -473,7 +996,7 @@ mod tests {
     #[test]
     fn it_forwards_arithmetic_w_literals() {
         let number: DataType = 40.into();
-        let expression = ProjectExpression {
+        let expression = ProjectExpression::Arithmetic {
             left: ProjectExpressionBase::Column(0),
             right: ProjectExpressionBase::Literal(number),
             op: ArithmeticOperator::Multiply,
@@ -491,7 +1014,7 @@ mod tests {
     fn it_forwards_arithmetic_w_only_literals() {
         let a: DataType = 80.into();
         let b: DataType = 40.into();
-        let expression = ProjectExpression {
+        let expression = ProjectExpression::Arithmetic {
             left: ProjectExpressionBase::Literal(a),
             right: ProjectExpressionBase::Literal(b),
             op: ArithmeticOperator::Divide,
@@ -617,7 +1140,7 @@ mod tests {
     #[test]
     fn it_queries_through_w_arithmetic_and_literals() {
         let additional = Some(vec![DataType::Int(42)]);
-        let expressions = Some(vec![ProjectExpression {
+        let expressions = Some(vec![ProjectExpression::Arithmetic {
             left: ProjectExpressionBase::Column(0),
             right: ProjectExpressionBase::Column(1),
             op: ArithmeticOperator::Add,
@@ -632,7 +1155,7 @@ mod tests {
     #[test]
     fn it_queries_through_w_arithmetic_and_literals_persistent() {
         let additional = Some(vec![DataType::Int(42)]);
-        let expressions = Some(vec![ProjectExpression {
+        let expressions = Some(vec![ProjectExpression::Arithmetic {
             left: ProjectExpressionBase::Column(0),
             right: ProjectExpressionBase::Column(1),
             op: ArithmeticOperator::Add,
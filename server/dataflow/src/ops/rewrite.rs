@@ -205,6 +205,17 @@ impl Ingredient for Rewrite {
     fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
         vec![(self.src.as_global(), Some(column))]
     }
+
+    fn used_parent_columns(&self, parent: NodeIndex) -> Vec<usize> {
+        let mut used = Vec::new();
+        if parent == self.src.as_global() {
+            used.push(self.rw_col);
+        }
+        if parent == self.signal.as_global() {
+            used.push(self.signal_key);
+        }
+        used
+    }
 }
 
 #[cfg(test)]
@@ -175,20 +175,10 @@ impl Ingredient for TopK {
                     // FIXME: if all the elements with the smallest value in the new topk are new,
                     // then it *could* be that there exists some value that is greater than all
                     // those values, and <= the smallest old value. we would only discover that by
-                    // querying. unfortunately, the check below isn't *quite* right because it does
-                    // not consider old rows that were removed in this batch (which should still be
-                    // counted for this condition).
-                    if false {
-                        let all_new_bottom = $current[start..]
-                            .iter()
-                            .take_while(|(ref r, _)| {
-                                $order.cmp(r, &$current[start].0) == Ordering::Equal
-                            })
-                            .all(|&(_, is_new)| is_new);
-                        if all_new_bottom {
-                            eprintln!("topk is guesstimating bottom row");
-                        }
-                    }
+                    // querying. unfortunately, checking whether the rows tied for the bottom spot
+                    // are all new isn't *quite* right because it does not consider old rows that
+                    // were removed in this batch (which should still be counted for this
+                    // condition), so for now we don't detect this case at all.
                 }
 
                 // optimization: if we don't *have to* remove something, we don't
@@ -315,6 +305,15 @@ impl Ingredient for TopK {
     fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
         vec![(self.src.as_global(), Some(col))]
     }
+
+    fn used_parent_columns(&self, parent: NodeIndex) -> Vec<usize> {
+        if parent != self.src.as_global() {
+            return Vec::new();
+        }
+        let mut used = self.group_by.clone();
+        used.extend(self.order.0.iter().map(|&(c, _)| c));
+        used
+    }
 }
 
 #[cfg(test)]
@@ -142,6 +142,13 @@ impl Ingredient for Trigger {
         vec![(self.src.as_global(), Some(column))]
     }
 
+    fn used_parent_columns(&self, parent: NodeIndex) -> Vec<usize> {
+        if parent != self.src.as_global() {
+            return Vec::new();
+        }
+        vec![self.key]
+    }
+
     // Trigger nodes require full materialization because we want group universes
     // to be long lived and to exist even if no user makes use of it.
     // We do this for two reasons: 1) to make user universe creation faster and
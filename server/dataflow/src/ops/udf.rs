@@ -0,0 +1,689 @@
+//! User-defined functions, compiled to WebAssembly, that projections and filters can call.
+//!
+//! A function is a WebAssembly module, the name of the function it exports, and the types of that
+//! function's arguments and result. The module is part of the function, and so it is serialized
+//! along with the operators that call it: every replica and every recovered domain runs exactly
+//! the same code.
+//!
+//! Values are passed to and from the exported function as follows:
+//!
+//!  - `Int` is an `i64`. Integers of any width are accepted, as long as they fit.
+//!  - `Double` is an `f64`. Integers, reals and decimals are accepted.
+//!  - `Bool` is an `i32` that is 0 or 1, and any non-zero result is true.
+//!  - `Text` is two `i32`s, a pointer to the UTF-8 bytes of the string in the module's memory and
+//!    their length. The bytes are copied into a buffer that the module allocates by exporting
+//!    `alloc(len: i32) -> i32`. A function that returns text returns an `i64` that holds the
+//!    pointer in its high 32 bits and the length in its low 32 bits. Modules that take or return
+//!    text must export their memory as `memory`.
+//!
+//! As with built-in functions, a call with a `NULL` argument, or with an argument that is not of
+//! the declared type, returns `NULL` without running the function.
+//!
+//! Functions cannot affect anything outside of the domain that calls them. Modules may not import
+//! anything, which in particular means that they get no access to WASI. Each domain keeps one
+//! instance of every function its operators call, and all of its calls run in that instance.
+//! Which calls a domain makes, and in what order, differs between replicas and after recovery, so
+//! a function must not depend on anything an earlier call left behind in memory or in globals. In
+//! particular, a module that takes text should reuse the buffers that `alloc` hands out.
+//!
+//! Each call is given the same fixed amount of fuel, which it uses up as it executes instructions.
+//! A call that runs out of fuel, or that traps, returns `NULL`, and since it may have left the
+//! instance in any state, the next call runs in a new instance. Unlike a time limit, fuel runs out
+//! at the same point on every replica.
+//!
+//! A recipe registers a function with a statement such as
+//! `FUNCTION score(INT, TEXT) RETURNS INT AS score IN X'0061736d...' FUEL 10000;`, which gives the
+//! module as hex. Queries can only call built-in functions, so migrations look the function up by
+//! name to place it in operators. Running functions requires noria to be built with the `wasm`
+//! feature.
+
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::prelude::*;
+
+/// The fuel that a call is given if the function does not set a limit.
+pub const DEFAULT_FUEL: u64 = 1_000_000;
+
+/// The type of an argument to, or the result of, a user-defined function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UdfType {
+    /// A signed 64-bit integer.
+    Int,
+    /// A 64-bit float.
+    Double,
+    /// A boolean.
+    Bool,
+    /// A UTF-8 string.
+    Text,
+}
+
+impl UdfType {
+    /// Read a value as an argument of this type, if it is one.
+    fn argument(self, value: &DataType) -> Option<Arg> {
+        match (self, value) {
+            (_, &DataType::None) => None,
+            (UdfType::Int, &DataType::UnsignedBigInt(u)) => {
+                if u <= i64::max_value() as u64 {
+                    Some(Arg::Int(u as i64))
+                } else {
+                    None
+                }
+            }
+            (UdfType::Int, &DataType::HugeInt(ref i)) => {
+                if **i >= i128::from(i64::min_value()) && **i <= i128::from(i64::max_value()) {
+                    Some(Arg::Int(**i as i64))
+                } else {
+                    None
+                }
+            }
+            (UdfType::Int, v) if v.is_integer() || v.is_bool() => Some(Arg::Int(v.into())),
+            (UdfType::Int, &DataType::UnsignedInt(u)) => Some(Arg::Int(i64::from(u))),
+            (UdfType::Double, &DataType::UnsignedInt(u)) => Some(Arg::Double(f64::from(u))),
+            (UdfType::Double, &DataType::UnsignedBigInt(u)) => Some(Arg::Double(u as f64)),
+            (UdfType::Double, v) if v.is_integer() || v.is_real() || v.is_decimal() => {
+                Some(Arg::Double(v.into()))
+            }
+            (UdfType::Double, &DataType::HugeInt(ref i)) => Some(Arg::Double(**i as f64)),
+            (UdfType::Bool, &DataType::Bool(b)) => Some(Arg::Bool(b)),
+            (UdfType::Bool, v) if v.is_integer() => Some(Arg::Bool(v.is_truthy())),
+            (UdfType::Text, v) if v.is_string() || v.is_json() => Some(Arg::Text(v.into())),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for UdfType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UdfType::Int => write!(f, "INT"),
+            UdfType::Double => write!(f, "DOUBLE"),
+            UdfType::Bool => write!(f, "BOOL"),
+            UdfType::Text => write!(f, "TEXT"),
+        }
+    }
+}
+
+/// An argument to a call, already read as the type the function takes.
+#[derive(Debug, Clone, Copy)]
+enum Arg<'a> {
+    Int(i64),
+    Double(f64),
+    Bool(bool),
+    Text(&'a str),
+}
+
+/// A user-defined function.
+#[derive(Serialize, Deserialize)]
+pub struct Udf {
+    name: String,
+    module: Vec<u8>,
+    export: String,
+    params: Vec<UdfType>,
+    returns: UdfType,
+    fuel: u64,
+
+    /// The compiled module. Domains compile it again when they first call the function.
+    #[serde(skip)]
+    compiled: Mutex<Option<runtime::Compiled>>,
+    /// The instance that calls run in, from the first call until one traps.
+    #[serde(skip)]
+    instance: Mutex<Option<runtime::Instance>>,
+}
+
+impl Udf {
+    /// Construct a function that calls `export` in the WebAssembly module `module`, which must
+    /// take arguments of types `params` and return a `returns`.
+    ///
+    /// Fails if the module does not compile, if it imports anything, or if it does not export a
+    /// function with that signature, along with whatever else passing text requires.
+    pub fn new(
+        name: &str,
+        module: Vec<u8>,
+        export: &str,
+        params: Vec<UdfType>,
+        returns: UdfType,
+    ) -> Result<Udf, String> {
+        let compiled = runtime::compile(&module, export, &params, returns)
+            .map_err(|e| format!("function {}: {}", name, e))?;
+        Ok(Udf {
+            name: name.to_owned(),
+            module,
+            export: export.to_owned(),
+            params,
+            returns,
+            fuel: DEFAULT_FUEL,
+            compiled: Mutex::new(Some(compiled)),
+            instance: Mutex::new(None),
+        })
+    }
+
+    /// Limit each call to `fuel` units of fuel, rather than `DEFAULT_FUEL`.
+    pub fn with_fuel(mut self, fuel: u64) -> Udf {
+        self.fuel = fuel;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn params(&self) -> &[UdfType] {
+        &self.params
+    }
+
+    pub fn returns(&self) -> UdfType {
+        self.returns
+    }
+
+    /// Call the function with `args`.
+    pub fn call(&self, args: &[&DataType]) -> DataType {
+        assert_eq!(
+            args.len(),
+            self.params.len(),
+            "{} takes {} arguments",
+            self.name,
+            self.params.len()
+        );
+
+        let mut marshalled = Vec::with_capacity(args.len());
+        for (&t, v) in self.params.iter().zip(args) {
+            match t.argument(v) {
+                Some(arg) => marshalled.push(arg),
+                None => return DataType::None,
+            }
+        }
+
+        let mut instance = self.instance.lock().unwrap();
+        if instance.is_none() {
+            match runtime::instantiate(&self.compiled(), self.fuel) {
+                Ok(i) => *instance = Some(i),
+                Err(_) => return DataType::None,
+            }
+        }
+
+        match runtime::call(
+            instance.as_mut().unwrap(),
+            &self.export,
+            &marshalled,
+            self.returns,
+        ) {
+            Ok(v) => v,
+            Err(_) => {
+                // the call may have stopped half way through changing the instance's memory
+                *instance = None;
+                DataType::None
+            }
+        }
+    }
+
+    /// The compiled module, compiling it first if this copy of the function has not yet.
+    fn compiled(&self) -> runtime::Compiled {
+        let mut compiled = self.compiled.lock().unwrap();
+        if compiled.is_none() {
+            *compiled = Some(
+                runtime::compile(&self.module, &self.export, &self.params, self.returns)
+                    .unwrap_or_else(|e| panic!("could not compile {}: {}", self.name, e)),
+            );
+        }
+        compiled.clone().unwrap()
+    }
+}
+
+impl Clone for Udf {
+    /// A copy of the function that shares its compiled module, but makes its own instance.
+    fn clone(&self) -> Udf {
+        Udf {
+            name: self.name.clone(),
+            module: self.module.clone(),
+            export: self.export.clone(),
+            params: self.params.clone(),
+            returns: self.returns,
+            fuel: self.fuel,
+            compiled: Mutex::new(self.compiled.lock().unwrap().clone()),
+            instance: Mutex::new(None),
+        }
+    }
+}
+
+impl fmt::Debug for Udf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Udf")
+            .field("name", &self.name)
+            .field("module", &format_args!("<{} bytes>", self.module.len()))
+            .field("export", &self.export)
+            .field("params", &self.params)
+            .field("returns", &self.returns)
+            .field("fuel", &self.fuel)
+            .finish()
+    }
+}
+
+impl fmt::Display for Udf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl PartialEq for Udf {
+    fn eq(&self, other: &Udf) -> bool {
+        self.name == other.name
+            && self.module == other.module
+            && self.export == other.export
+            && self.params == other.params
+            && self.returns == other.returns
+            && self.fuel == other.fuel
+    }
+}
+
+#[cfg(feature = "wasm")]
+mod runtime {
+    use super::{Arg, UdfType};
+    use crate::prelude::*;
+    use std::iter;
+    use wasmtime::{Config, Engine, ExternType, Memory, Module, Store, Val, ValType};
+
+    /// The function that modules which take text export to allocate buffers for it.
+    const ALLOC: &str = "alloc";
+    /// The name that modules which take or return text export their memory under.
+    const MEMORY: &str = "memory";
+
+    #[derive(Clone)]
+    pub(super) struct Compiled {
+        engine: Engine,
+        module: Module,
+    }
+
+    pub(super) struct Instance {
+        store: Store,
+        instance: wasmtime::Instance,
+        memory: Option<Memory>,
+        /// The fuel that the store had used up when the instance last stopped running.
+        consumed: u64,
+    }
+
+    // a `Store` keeps its state behind an `Rc`, which the instance and its memory hold clones of.
+    // they are only ever moved together, and only used while the function's lock is held.
+    unsafe impl Send for Instance {}
+
+    fn param_types(t: UdfType) -> Vec<ValType> {
+        match t {
+            UdfType::Int => vec![ValType::I64],
+            UdfType::Double => vec![ValType::F64],
+            UdfType::Bool => vec![ValType::I32],
+            UdfType::Text => vec![ValType::I32, ValType::I32],
+        }
+    }
+
+    fn result_type(t: UdfType) -> ValType {
+        match t {
+            UdfType::Int | UdfType::Text => ValType::I64,
+            UdfType::Double => ValType::F64,
+            UdfType::Bool => ValType::I32,
+        }
+    }
+
+    fn exports_func(module: &Module, name: &str, params: &[ValType], result: ValType) -> bool {
+        module.exports().any(|e| {
+            e.name() == name
+                && match e.ty() {
+                    ExternType::Func(ref f) => {
+                        f.params().eq(params.iter().cloned()) && f.results().eq(iter::once(result))
+                    }
+                    _ => false,
+                }
+        })
+    }
+
+    pub(super) fn compile(
+        bytes: &[u8],
+        export: &str,
+        params: &[UdfType],
+        returns: UdfType,
+    ) -> Result<Compiled, String> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        // NaNs have many representations, and which one an operation produces can depend on the
+        // hardware it runs on
+        config.cranelift_nan_canonicalization(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, bytes).map_err(|e| format!("invalid module: {}", e))?;
+
+        if let Some(import) = module.imports().next() {
+            return Err(format!(
+                "module imports from {:?}, but may not import anything",
+                import.module()
+            ));
+        }
+
+        let wasm_params: Vec<_> = params.iter().flat_map(|&t| param_types(t)).collect();
+        if !exports_func(&module, export, &wasm_params, result_type(returns)) {
+            let params: Vec<_> = params.iter().map(ToString::to_string).collect();
+            return Err(format!(
+                "module does not export {}({}) -> {}",
+                export,
+                params.join(", "),
+                returns
+            ));
+        }
+
+        let takes_text = params.contains(&UdfType::Text);
+        if (takes_text || returns == UdfType::Text)
+            && !module
+                .exports()
+                .any(|e| e.name() == MEMORY && e.ty().memory().is_some())
+        {
+            return Err(format!(
+                "module passes text, but does not export {}",
+                MEMORY
+            ));
+        }
+        if takes_text && !exports_func(&module, ALLOC, &[ValType::I32], ValType::I32) {
+            return Err(format!(
+                "module takes text, but does not export {}(i32) -> i32",
+                ALLOC
+            ));
+        }
+
+        Ok(Compiled { engine, module })
+    }
+
+    fn write(memory: &Memory, ptr: usize, bytes: &[u8]) -> Result<(), String> {
+        // safe because no WebAssembly runs while the memory is borrowed
+        let data = unsafe { memory.data_unchecked_mut() };
+        data.get_mut(ptr..ptr + bytes.len())
+            .ok_or_else(|| String::from("buffer is out of bounds"))?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn read(memory: &Memory, ptr: usize, len: usize) -> Result<String, String> {
+        // safe because no WebAssembly runs while the memory is borrowed
+        let data = unsafe { memory.data_unchecked() };
+        let bytes = data
+            .get(ptr..ptr + len)
+            .ok_or_else(|| String::from("result is out of bounds"))?;
+        std::str::from_utf8(bytes)
+            .map(String::from)
+            .map_err(|_| String::from("result is not UTF-8"))
+    }
+
+    pub(super) fn instantiate(compiled: &Compiled, fuel: u64) -> Result<Instance, String> {
+        let store = Store::new(&compiled.engine);
+        store.add_fuel(fuel).map_err(|e| e.to_string())?;
+        let instance =
+            wasmtime::Instance::new(&store, &compiled.module, &[]).map_err(|e| e.to_string())?;
+        let memory = instance.get_memory(MEMORY);
+
+        // the start function, if there is one, does not use up any of the first call's fuel
+        let consumed = store.fuel_consumed().unwrap();
+        store.add_fuel(consumed).map_err(|e| e.to_string())?;
+        Ok(Instance {
+            store,
+            instance,
+            memory,
+            consumed,
+        })
+    }
+
+    pub(super) fn call(
+        instance: &mut Instance,
+        export: &str,
+        args: &[Arg],
+        returns: UdfType,
+    ) -> Result<DataType, String> {
+        let Instance {
+            ref store,
+            ref instance,
+            ref memory,
+            ref mut consumed,
+        } = *instance;
+
+        let mut vals = Vec::with_capacity(args.len());
+        for arg in args {
+            match *arg {
+                Arg::Int(i) => vals.push(Val::I64(i)),
+                Arg::Double(f) => vals.push(Val::F64(f.to_bits())),
+                Arg::Bool(b) => vals.push(Val::I32(b as i32)),
+                Arg::Text(s) => {
+                    if s.len() > i32::max_value() as usize {
+                        return Err(String::from("argument is too long"));
+                    }
+                    let alloc = instance.get_func(ALLOC).unwrap();
+                    let ptr = match alloc
+                        .call(&[Val::I32(s.len() as i32)])
+                        .map_err(|e| e.to_string())?[0]
+                    {
+                        Val::I32(ptr) => ptr as u32 as usize,
+                        _ => unreachable!(),
+                    };
+                    write(memory.as_ref().unwrap(), ptr, s.as_bytes())?;
+                    vals.push(Val::I32(ptr as i32));
+                    vals.push(Val::I32(s.len() as i32));
+                }
+            }
+        }
+
+        let result = instance
+            .get_func(export)
+            .unwrap()
+            .call(&vals)
+            .map_err(|e| e.to_string())?;
+
+        // give the next call the same fuel as this one had
+        let now = store.fuel_consumed().unwrap();
+        store.add_fuel(now - *consumed).map_err(|e| e.to_string())?;
+        *consumed = now;
+
+        Ok(match (returns, &result[0]) {
+            (UdfType::Int, &Val::I64(i)) => i.into(),
+            (UdfType::Double, &Val::F64(bits)) => {
+                let f = f64::from_bits(bits);
+                if f.is_finite() {
+                    f.into()
+                } else {
+                    DataType::None
+                }
+            }
+            (UdfType::Bool, &Val::I32(b)) => DataType::Bool(b != 0),
+            (UdfType::Text, &Val::I64(packed)) => {
+                let ptr = (packed as u64 >> 32) as usize;
+                let len = (packed as u64 & 0xffff_ffff) as usize;
+                read(memory.as_ref().unwrap(), ptr, len)?.into()
+            }
+            _ => unreachable!(),
+        })
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+mod runtime {
+    use super::{Arg, UdfType};
+    use crate::prelude::*;
+
+    #[derive(Clone)]
+    pub(super) enum Compiled {}
+
+    pub(super) enum Instance {}
+
+    pub(super) fn compile(
+        _: &[u8],
+        _: &str,
+        _: &[UdfType],
+        _: UdfType,
+    ) -> Result<Compiled, String> {
+        Err(String::from("noria was built without the wasm feature"))
+    }
+
+    pub(super) fn instantiate(compiled: &Compiled, _: u64) -> Result<Instance, String> {
+        match *compiled {}
+    }
+
+    pub(super) fn call(
+        instance: &mut Instance,
+        _: &str,
+        _: &[Arg],
+        _: UdfType,
+    ) -> Result<DataType, String> {
+        match *instance {}
+    }
+}
+
+#[cfg(all(test, feature = "wasm"))]
+mod tests {
+    use super::*;
+
+    const ADD: &str = r#"
+        (module
+          (func (export "add") (param i64 i64) (result i64)
+            local.get 0
+            local.get 1
+            i64.add))
+    "#;
+
+    const SPIN: &str = r#"
+        (module
+          (func (export "spin") (param i64) (result i32)
+            (loop br 0)
+            i32.const 0))
+    "#;
+
+    // counts up from 1, and traps if its argument is negative
+    const COUNTER: &str = r#"
+        (module
+          (global $n (mut i64) (i64.const 0))
+          (func (export "next") (param i64) (result i64)
+            global.get $n
+            i64.const 1
+            i64.add
+            global.set $n
+            local.get 0
+            i64.const 0
+            i64.lt_s
+            if
+              unreachable
+            end
+            global.get $n))
+    "#;
+
+    // counts up to its argument, and returns it
+    const COUNT: &str = r#"
+        (module
+          (func (export "count") (param i64) (result i64)
+            (local i64)
+            (block
+              (loop
+                local.get 1
+                local.get 0
+                i64.ge_s
+                br_if 1
+                local.get 1
+                i64.const 1
+                i64.add
+                local.set 1
+                br 0))
+            local.get 1))
+    "#;
+
+    // returns its argument with the first byte removed
+    const TAIL: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32)
+            i32.const 16)
+          (func (export "tail") (param i32 i32) (result i64)
+            local.get 0
+            i32.const 1
+            i32.add
+            i64.extend_i32_u
+            i64.const 32
+            i64.shl
+            local.get 1
+            i32.const 1
+            i32.sub
+            i64.extend_i32_u
+            i64.or))
+    "#;
+
+    const IMPORTS: &str = r#"
+        (module
+          (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+          (func (export "f") (param i64) (result i64)
+            local.get 0))
+    "#;
+
+    fn udf(wat: &str, export: &str, params: Vec<UdfType>, returns: UdfType) -> Udf {
+        Udf::new("f", wat.as_bytes().to_vec(), export, params, returns).unwrap()
+    }
+
+    #[test]
+    fn it_calls_functions() {
+        let add = udf(ADD, "add", vec![UdfType::Int, UdfType::Int], UdfType::Int);
+        assert_eq!(add.call(&[&1.into(), &2.into()]), 3.into());
+        assert_eq!(add.call(&[&DataType::BigInt(40), &2.into()]), 42.into());
+    }
+
+    #[test]
+    fn it_passes_text() {
+        let tail = udf(TAIL, "tail", vec![UdfType::Text], UdfType::Text);
+        assert_eq!(tail.call(&[&"noria".into()]), "oria".into());
+    }
+
+    #[test]
+    fn it_returns_null_for_null_or_mistyped_arguments() {
+        let add = udf(ADD, "add", vec![UdfType::Int, UdfType::Int], UdfType::Int);
+        assert_eq!(add.call(&[&DataType::None, &2.into()]), DataType::None);
+        assert_eq!(add.call(&[&"a".into(), &2.into()]), DataType::None);
+    }
+
+    #[test]
+    fn it_stops_calls_that_run_out_of_fuel() {
+        let spin = udf(SPIN, "spin", vec![UdfType::Int], UdfType::Bool).with_fuel(1_000);
+        assert_eq!(spin.call(&[&1.into()]), DataType::None);
+    }
+
+    #[test]
+    fn it_gives_every_call_the_same_fuel() {
+        let count = udf(COUNT, "count", vec![UdfType::Int], UdfType::Int).with_fuel(1_000);
+        for _ in 0..100 {
+            assert_eq!(count.call(&[&50.into()]), 50.into());
+        }
+        assert_eq!(count.call(&[&1_000.into()]), DataType::None);
+        assert_eq!(count.call(&[&50.into()]), 50.into());
+    }
+
+    #[test]
+    fn it_reuses_instances_until_a_call_traps() {
+        let next = udf(COUNTER, "next", vec![UdfType::Int], UdfType::Int);
+        assert_eq!(next.call(&[&0.into()]), 1.into());
+        assert_eq!(next.call(&[&0.into()]), 2.into());
+        assert_eq!(next.call(&[&(-1).into()]), DataType::None);
+        assert_eq!(next.call(&[&0.into()]), 1.into());
+
+        // a copy has an instance of its own
+        let copy = next.clone();
+        assert_eq!(copy.call(&[&0.into()]), 1.into());
+        assert_eq!(next.call(&[&0.into()]), 2.into());
+    }
+
+    #[test]
+    fn it_rejects_bad_modules() {
+        let f = |wat: &str, export, params, returns| {
+            Udf::new("f", wat.as_bytes().to_vec(), export, params, returns)
+        };
+        assert!(f(IMPORTS, "f", vec![UdfType::Int], UdfType::Int).is_err());
+        assert!(f(ADD, "sub", vec![UdfType::Int, UdfType::Int], UdfType::Int).is_err());
+        assert!(f(ADD, "add", vec![UdfType::Int], UdfType::Int).is_err());
+        assert!(f(ADD, "add", vec![UdfType::Text], UdfType::Int).is_err());
+        assert!(f("not wasm", "add", vec![], UdfType::Int).is_err());
+    }
+
+    #[test]
+    fn it_survives_serialization() {
+        let add = udf(ADD, "add", vec![UdfType::Int, UdfType::Int], UdfType::Int);
+        let add: Udf = bincode::deserialize(&bincode::serialize(&add).unwrap()).unwrap();
+        assert!(add.compiled.lock().unwrap().is_none());
+        assert!(add.instance.lock().unwrap().is_none());
+        assert_eq!(add.call(&[&1.into(), &2.into()]), 3.into());
+    }
+}
@@ -4,11 +4,13 @@ use serde::{Deserialize, Serialize};
 use crate::domain;
 use crate::prelude::*;
 use noria;
+use noria::debug::trace::TraceContext;
 use noria::internal::LocalOrNot;
 
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::net::SocketAddr;
+use std::time;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ReplayPathSegment {
@@ -54,6 +56,7 @@ pub enum InitialState {
         gid: petgraph::graph::NodeIndex,
         cols: usize,
         key: Vec<usize>,
+        ordered: bool,
     },
 }
 
@@ -77,6 +80,55 @@ pub struct SourceChannelIdentifier {
     pub tag: u32,
 }
 
+/// Identifies the batch of writes to a base table shard that a regular update stems from.
+///
+/// Each base table shard numbers the batches it processes, starting at 1, so readers can tell
+/// which writes they reflect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Label {
+    pub base: petgraph::graph::NodeIndex,
+    pub shard: usize,
+    pub seq: u64,
+    /// A client is waiting for this batch to reach readers, so updates carrying it must be
+    /// forwarded even if they end up empty.
+    pub tracked: bool,
+    /// The batch includes a traced write, and the events of updates carrying it are recorded
+    /// under this tag.
+    pub trace: Option<u64>,
+    /// The batch includes a write sampled for measuring propagation latency, and this is when the
+    /// earliest such write arrived, in nanoseconds since the Unix epoch.
+    pub origin: Option<u64>,
+    /// The commit timestamp of the transaction the batch is part of, if any.
+    #[serde(default)]
+    pub txn: Option<u64>,
+    /// When the base table shard applied the batch, in nanoseconds since the Unix epoch.
+    #[serde(default)]
+    pub committed: u64,
+    /// The span of a distributed trace that updates carrying this batch are part of, if the
+    /// batch includes a write that is.
+    #[serde(default)]
+    pub context: Option<TraceContext>,
+    /// When an update carrying the batch was sent to the domain it is in, in nanoseconds since the
+    /// Unix epoch, if the batch is part of a distributed trace.
+    #[serde(default)]
+    pub sent: u64,
+    /// Which of the paths through the graph from the base the update took, where there are
+    /// several. Each node that combines the updates of several parents mixes in which of them the
+    /// update came from.
+    #[serde(default)]
+    pub path: u64,
+}
+
+impl Label {
+    /// Record that the update reached `node`, which has several parents, from `parent`.
+    pub(crate) fn via(&mut self, node: petgraph::graph::NodeIndex, parent: LocalNodeIndex) {
+        let hop = ((node.index() as u64) << 32) | parent.id() as u64;
+        self.path = (self.path ^ hop)
+            .wrapping_mul(0x0100_0000_01b3)
+            .rotate_left(29);
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[allow(clippy::large_enum_variant)]
 pub enum Packet {
@@ -85,13 +137,16 @@ pub enum Packet {
     Input {
         inner: LocalOrNot<Input>,
         src: Option<SourceChannelIdentifier>,
-        senders: Vec<SourceChannelIdentifier>,
+        /// The clients whose writes were merged into this packet, along with how many of the
+        /// packet's operations came from each.
+        senders: Vec<(SourceChannelIdentifier, usize)>,
     },
 
     /// Regular data-flow update.
     Message {
         link: Link,
         data: Records,
+        label: Label,
     },
 
     /// Update that is part of a tagged data-flow replay path.
@@ -102,6 +157,12 @@ pub enum Packet {
         context: ReplayPieceContext,
     },
 
+    /// Several packets for the same domain shard that an egress sent together. They are handled
+    /// in order.
+    Batch {
+        packets: Vec<Box<Packet>>,
+    },
+
     /// Trigger an eviction from the target node.
     Evict {
         node: Option<LocalNodeIndex>,
@@ -147,6 +208,85 @@ pub enum Packet {
         column: usize,
     },
 
+    /// Make a `Reader` hold back the batches of a transaction until it has received `parts` of
+    /// them from distinct base shards, and then expose them all at once.
+    ///
+    /// With no `parts`, the reader stops waiting for the transaction, and exposes what it has.
+    ExpectTransaction {
+        node: LocalNodeIndex,
+        txn: u64,
+        parts: usize,
+    },
+
+    /// Let a base accept its part of the transaction with commit timestamp `txn`.
+    OpenTransaction {
+        node: LocalNodeIndex,
+        txn: u64,
+    },
+
+    /// Apply a base's staged part of a transaction, or with no `commit`, drop it, and then apply
+    /// the writes to the base that were held back meanwhile.
+    DecideTransaction {
+        node: LocalNodeIndex,
+        txn: u64,
+        commit: bool,
+    },
+
+    /// Decide the part of a transaction that a base staged before its domain was lost, applying
+    /// it if the transaction is one of `committed`.
+    RecoverTransaction {
+        node: LocalNodeIndex,
+        committed: Vec<u64>,
+    },
+
+    /// Stop a `Reader` from exposing a column that derives from a dropped base column.
+    HideReaderColumn {
+        node: LocalNodeIndex,
+        column: usize,
+        default: DataType,
+    },
+
+    /// Hold back normal writes to a base, and apply rows bulk loaded into it without sending them
+    /// downstream until the load is finished.
+    StartBulkLoad {
+        node: LocalNodeIndex,
+    },
+
+    /// A frame of rows to bulk load into a base.
+    BulkLoad {
+        node: LocalNodeIndex,
+        rows: Vec<Vec<DataType>>,
+    },
+
+    /// Send everything that was bulk loaded into a base downstream, and apply any writes that were
+    /// held back in the meantime.
+    FinishBulkLoad {
+        node: LocalNodeIndex,
+    },
+
+    /// Hold back normal writes to a base until `ResumeWrites`, so that the base's state can be
+    /// snapshotted at the same cut as that of other bases.
+    PauseWrites {
+        node: LocalNodeIndex,
+    },
+
+    /// Reply with the rows of a base, and the label of the latest batch of writes they reflect.
+    SnapshotBase {
+        node: LocalNodeIndex,
+    },
+
+    /// Apply the writes to a base that were held back since `PauseWrites`.
+    ResumeWrites {
+        node: LocalNodeIndex,
+    },
+
+    /// Retract the rows of a range-sharded base that belong to other shards once it is split
+    /// along the given boundaries, and reply with them so they can be loaded where they belong.
+    Resplit {
+        node: LocalNodeIndex,
+        boundaries: Vec<DataType>,
+    },
+
     /// Update Egress node.
     UpdateEgress {
         node: LocalNodeIndex,
@@ -154,6 +294,12 @@ pub enum Packet {
         new_tag: Option<(Tag, NodeIndex)>,
     },
 
+    /// Stop sending updates from an Egress node to the given ingress node, which has been removed.
+    RemoveEgressTx {
+        node: LocalNodeIndex,
+        ingress: NodeIndex,
+    },
+
     /// Add a shard to a Sharder node.
     ///
     /// Note that this *must* be done *before* the sharder starts being used!
@@ -220,12 +366,48 @@ pub enum Packet {
     /// A packet used solely to drive the event loop forward.
     Spin,
 
+    /// Asks the event loop of a domain to show its worker that it is running, by answering the
+    /// ping with the given id. Only ever sent by the domain's own worker.
+    Ping(u64),
+
     /// Request that a domain send usage statistics on the control reply channel.
     /// Argument specifies if we wish to get the full state size or just the partial nodes.
     GetStatistics,
 
+    /// Request that a domain send the events it has recorded for the given trace tag on the
+    /// control reply channel.
+    GetTrace {
+        tag: u64,
+    },
+
+    /// Request that a domain send what it keeps track of about the updates flowing through each
+    /// of its nodes on the control reply channel.
+    GetBookkeeping,
+
     /// Ask domain to log its state size
     UpdateStateSize,
+
+    /// Turn logging of every packet the domain handles on or off.
+    SetPacketLogging {
+        enabled: bool,
+    },
+
+    /// Change how long a node may take to process a packet, and a partial replay may take to
+    /// complete, before the domain logs a warning about it.
+    SetSlowThresholds {
+        packet: Option<time::Duration>,
+        replay: Option<time::Duration>,
+    },
+
+    /// Change the share of client writes that are stamped for measuring propagation latency.
+    SetPropagationSampling {
+        rate: f64,
+    },
+
+    /// Change how fast the domain replays the state of a node in full.
+    SetReplayRate {
+        rate: noria::ReplayRate,
+    },
 }
 
 impl Packet {
@@ -267,6 +449,39 @@ impl Packet {
         }
     }
 
+    /// The number of records or client writes this packet carries.
+    pub fn records(&self) -> usize {
+        match *self {
+            Packet::Input { ref inner, .. } => unsafe { inner.deref() }.data.len(),
+            Packet::Message { ref data, .. } | Packet::ReplayPiece { ref data, .. } => data.len(),
+            Packet::Batch { ref packets } => packets.iter().map(|p| p.records()).sum(),
+            _ => 0,
+        }
+    }
+
+    /// Roughly how many bytes the records or client writes this packet carries take up.
+    pub fn data_size(&self) -> usize {
+        match *self {
+            Packet::Input { ref inner, .. } => unsafe { inner.deref() }
+                .data
+                .iter()
+                .map(|op| match *op {
+                    noria::TableOperation::Insert(ref row)
+                    | noria::TableOperation::Upsert(ref row)
+                    | noria::TableOperation::InsertOrUpdate { ref row, .. } => {
+                        row.deep_size_of() as usize
+                    }
+                    _ => std::mem::size_of::<noria::TableOperation>(),
+                })
+                .sum(),
+            Packet::Message { ref data, .. } | Packet::ReplayPiece { ref data, .. } => {
+                data.iter().map(|r| r.deep_size_of() as usize).sum()
+            }
+            Packet::Batch { ref packets } => packets.iter().map(|p| p.data_size()).sum(),
+            _ => 0,
+        }
+    }
+
     pub(crate) fn map_data<F>(&mut self, map: F)
     where
         F: FnOnce(&mut Records),
@@ -281,6 +496,78 @@ impl Packet {
         }
     }
 
+    /// Returns true if this is a regular update whose batch a client is waiting for.
+    pub(crate) fn is_tracked(&self) -> bool {
+        match *self {
+            Packet::Message { label, .. } => label.tracked,
+            _ => false,
+        }
+    }
+
+    /// The transaction that a client write or a regular update is part of, if any.
+    pub(crate) fn transaction(&self) -> Option<u64> {
+        match *self {
+            Packet::Input { ref inner, .. } => unsafe { inner.deref() }.txn,
+            Packet::Message { label, .. } => label.txn,
+            _ => None,
+        }
+    }
+
+    /// The id that the client gave a write so that it is only applied once, if any.
+    pub(crate) fn operation(&self) -> Option<u64> {
+        match *self {
+            Packet::Input { ref inner, .. } => unsafe { inner.deref() }.op,
+            _ => None,
+        }
+    }
+
+    /// The trace tag of a client write or a regular update, if it is being traced.
+    pub(crate) fn trace(&self) -> Option<u64> {
+        match *self {
+            Packet::Input { ref inner, .. } => unsafe { inner.deref() }.trace,
+            Packet::Message { label, .. } => label.trace,
+            _ => None,
+        }
+    }
+
+    /// The span of a distributed trace that a client write or a regular update is part of, if
+    /// any, along with when it was sent to the domain it is in.
+    pub(crate) fn context(&self) -> Option<(TraceContext, u64)> {
+        match *self {
+            Packet::Input { ref inner, .. } => {
+                let input = unsafe { inner.deref() };
+                input.context.map(|context| (context, input.sent))
+            }
+            Packet::Message { label, .. } => label.context.map(|context| (context, label.sent)),
+            _ => None,
+        }
+    }
+
+    /// Make a regular update that is part of a distributed trace part of the given span instead.
+    pub(crate) fn set_context(&mut self, context: TraceContext) {
+        if let Packet::Message { ref mut label, .. } = *self {
+            label.context = Some(context);
+        }
+    }
+
+    /// Note that a regular update that is part of a distributed trace is being sent to another
+    /// domain now.
+    pub(crate) fn mark_sent(&mut self) {
+        if let Packet::Message { ref mut label, .. } = *self {
+            if label.context.is_some() {
+                label.sent = crate::domain::unix_nanos();
+            }
+        }
+    }
+
+    /// When the sampled write that a regular update stems from arrived at its base, if any.
+    pub(crate) fn origin(&self) -> Option<u64> {
+        match *self {
+            Packet::Message { label, .. } => label.origin,
+            _ => None,
+        }
+    }
+
     pub(crate) fn is_regular(&self) -> bool {
         match *self {
             Packet::Message { .. } => true,
@@ -308,9 +595,14 @@ impl Packet {
 
     pub(crate) fn clone_data(&self) -> Self {
         match *self {
-            Packet::Message { link, ref data } => Packet::Message {
+            Packet::Message {
+                link,
+                ref data,
+                label,
+            } => Packet::Message {
                 link,
                 data: data.clone(),
+                label,
             },
             Packet::ReplayPiece {
                 link,
@@ -333,6 +625,7 @@ impl fmt::Debug for Packet {
         match *self {
             Packet::Input { .. } => write!(f, "Packet::Input"),
             Packet::Message { ref link, .. } => write!(f, "Packet::Message({:?})", link),
+            Packet::Batch { ref packets } => write!(f, "Packet::Batch({:?})", packets),
             Packet::RequestReaderReplay { ref keys, .. } => {
                 write!(f, "Packet::RequestReaderReplay({:?})", keys)
             }
@@ -369,6 +662,16 @@ pub enum ControlReplyPacket {
         HashMap<petgraph::graph::NodeIndex, noria::debug::stats::NodeStats>,
     ),
     Booted(usize, SocketAddr),
+    /// Rows that were moved off a shard of a re-split base.
+    Rows(Vec<Vec<DataType>>),
+    Trace(Vec<noria::debug::trace::TraceEvent>),
+    Bookkeeping(noria::debug::bookkeeping::DomainBookkeeping),
+    /// The rows of a shard of a base, and the label of the latest batch of writes they reflect.
+    BaseSnapshot {
+        shard: usize,
+        label: u64,
+        rows: Vec<Vec<DataType>>,
+    },
 }
 
 impl ControlReplyPacket {
@@ -16,7 +16,7 @@ pub(crate) type Edge = ();
 
 // dataflow types
 pub(crate) use crate::payload::{ReplayPathSegment, SourceChannelIdentifier};
-pub(crate) use noria::Input;
+pub(crate) use noria::{Input, WriteAck};
 
 // domain local state
 pub(crate) use crate::state::{
@@ -41,7 +41,12 @@ pub use crate::PersistenceParameters;
 /// Channel coordinator type specialized for domains
 pub type ChannelCoordinator = noria::channel::ChannelCoordinator<(DomainIndex, usize), Box<Packet>>;
 pub trait Executor {
-    fn ack(&mut self, tag: SourceChannelIdentifier);
+    fn ack(&mut self, tag: SourceChannelIdentifier, ack: WriteAck);
     fn create_universe(&mut self, req: HashMap<String, DataType>);
     fn send(&mut self, dest: ReplicaAddr, m: Box<Packet>);
+    /// The number of packets sent to `dest` that have not yet left this domain.
+    fn queued(&self, dest: ReplicaAddr) -> usize;
+    /// How many records the packets that have not yet left this domain carry in total, and
+    /// roughly how many bytes they take up.
+    fn queued_records(&self) -> (usize, usize);
 }
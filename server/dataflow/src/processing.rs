@@ -1,8 +1,10 @@
 use slog::Logger;
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 use crate::ops;
+use crate::ops::udf::Udf;
 use crate::prelude::*;
 
 // TODO: make a Key type that is an ArrayVec<DataType>
@@ -176,6 +178,14 @@ where
     /// The provided arguments give mappings from global to local addresses.
     fn on_commit(&mut self, you: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>);
 
+    /// The user-defined functions that this operator calls.
+    ///
+    /// The domain that the operator runs in swaps these for its own copies, so that all of its
+    /// calls to a function run in the same instance.
+    fn udfs_mut(&mut self) -> Vec<&mut Arc<Udf>> {
+        Vec::new()
+    }
+
     /// Process a single incoming message, optionally producing an update to be propagated to
     /// children.
     #[allow(clippy::too_many_arguments)]
@@ -270,6 +280,12 @@ where
     /// materialization, and returns results even for computed columns.
     fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)>;
 
+    /// The columns of the given parent that this operator computes on, as opposed to merely
+    /// forwarding them to its output.
+    fn used_parent_columns(&self, _parent: NodeIndex) -> Vec<usize> {
+        Vec::new()
+    }
+
     /// Performance hint: should return true if this operator reduces the size of its input
     fn is_selective(&self) -> bool {
         false
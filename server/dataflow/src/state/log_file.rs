@@ -0,0 +1,166 @@
+use std::fmt::Write;
+
+/// The files that hold the durable state of one shard of a base table, named after the deployment
+/// and the table they belong to.
+///
+/// Names look like `{deployment}-log-{table}-{shard}`, where the deployment and table names have
+/// every character that is not alphanumeric or `_` escaped as `%XX`, so that the `-`s only
+/// separate the parts and two different tables never end up with the same name. Deployments from
+/// before this scheme named them `{deployment}-{table}-{shard}`, which is still recognized.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFile {
+    /// The deployment the base table belongs to, as given by its log prefix.
+    pub deployment: String,
+    /// The name of the base table.
+    pub base: String,
+    /// Which shard of the base table the state is of, or 0 if it is not sharded.
+    pub shard: usize,
+}
+
+impl LogFile {
+    /// The name of these files.
+    pub fn name(&self) -> String {
+        format!(
+            "{}-log-{}-{}",
+            escape(&self.deployment),
+            escape(&self.base),
+            self.shard
+        )
+    }
+
+    /// What these files were named before the names were escaped.
+    pub(crate) fn legacy_name(&self) -> String {
+        format!("{}-{}-{}", self.deployment, self.base, self.shard)
+    }
+
+    /// Recover what a file name refers to, if it is named either way that the durable state of
+    /// `deployment` is.
+    ///
+    /// The `.db` suffix of a RocksDB directory may be left on.
+    pub fn parse(name: &str, deployment: &str) -> Option<Self> {
+        let name = name.trim_end_matches(".db");
+        let parts: Vec<_> = name.split('-').collect();
+        if parts.len() == 4 && parts[1] == "log" {
+            if let (Some(d), Some(base), Ok(shard)) =
+                (unescape(parts[0]), unescape(parts[2]), parts[3].parse())
+            {
+                if d != deployment {
+                    return None;
+                }
+                return Some(LogFile {
+                    deployment: d,
+                    base,
+                    shard,
+                });
+            }
+        }
+
+        // legacy names don't escape anything, so both the deployment and the table may contain
+        // `-`, and only a known deployment tells where the table starts
+        let rest = name.strip_prefix(deployment)?.strip_prefix('-')?;
+        let (base, shard) = rest.split_at(rest.rfind('-')?);
+        if base.is_empty() {
+            return None;
+        }
+        Some(LogFile {
+            deployment: deployment.to_owned(),
+            base: base.to_owned(),
+            shard: shard[1..].parse().ok()?,
+        })
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            escaped.push(c);
+        } else {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                write!(escaped, "%{:02X}", b).unwrap();
+            }
+        }
+    }
+    escaped
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            if tail.len() < 2 {
+                return None;
+            }
+            let hex = std::str::from_utf8(&tail[..2]).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else if b.is_ascii_alphanumeric() || b == b'_' {
+            bytes.push(b);
+            rest = tail;
+        } else {
+            return None;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(deployment: &str, base: &str, shard: usize) -> LogFile {
+        LogFile {
+            deployment: deployment.to_owned(),
+            base: base.to_owned(),
+            shard,
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        let f = file("vote-dbtoaster", "Article Votes", 3);
+        assert_eq!(f.name(), "vote%2Ddbtoaster-log-Article%20Votes-3");
+        assert_eq!(LogFile::parse(&f.name(), "vote-dbtoaster"), Some(f.clone()));
+        assert_eq!(
+            LogFile::parse(&format!("{}.db", f.name()), "vote-dbtoaster"),
+            Some(f.clone())
+        );
+        assert_eq!(LogFile::parse(&f.name(), "vote"), None);
+
+        // names that only differ in characters that are escaped stay apart
+        assert_ne!(file("soup", "a.b", 0).name(), file("soup", "a_b", 0).name());
+        let f = file("soup", "müll-1", 0);
+        assert_eq!(LogFile::parse(&f.name(), "soup"), Some(f));
+    }
+
+    #[test]
+    fn parses_legacy_names() {
+        assert_eq!(
+            LogFile::parse("soup-Article-0.db", "soup"),
+            Some(file("soup", "Article", 0))
+        );
+        assert_eq!(
+            LogFile::parse("soup-user-votes-2", "soup"),
+            Some(file("soup", "user-votes", 2))
+        );
+        assert_eq!(
+            LogFile::parse("vote-dbtoaster-Article-0", "vote-dbtoaster"),
+            Some(file("vote-dbtoaster", "Article", 0))
+        );
+        assert_eq!(LogFile::parse("vote-dbtoaster-Article-0", "soup"), None);
+        assert_eq!(
+            file("soup", "user-votes", 2).legacy_name(),
+            "soup-user-votes-2"
+        );
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert_eq!(LogFile::parse("Cargo.toml", "soup"), None);
+        assert_eq!(LogFile::parse("soup-Article", "soup"), None);
+        assert_eq!(LogFile::parse("soup-Article-x.db", "soup"), None);
+        assert_eq!(LogFile::parse("soup--0", "soup"), None);
+    }
+}
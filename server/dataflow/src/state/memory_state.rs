@@ -153,6 +153,21 @@ impl State for MemoryState {
         self.state[0].values().flat_map(fix).collect()
     }
 
+    fn find_records(
+        &self,
+        matches: &dyn Fn(&[DataType]) -> bool,
+        limit: usize,
+    ) -> Vec<Vec<DataType>> {
+        assert!(!self.state[0].partial());
+        self.state[0]
+            .values()
+            .flat_map(|rs| rs.iter())
+            .filter(|r| matches(&r[..]))
+            .take(limit)
+            .map(|r| Vec::clone(&**r))
+            .collect()
+    }
+
     fn evict_random_keys(&mut self, count: usize) -> (&[usize], Vec<Vec<DataType>>, u64) {
         let mut rng = rand::thread_rng();
         let index = rng.gen_range(0, self.state.len());
@@ -172,6 +187,31 @@ impl State for MemoryState {
         })
     }
 
+    fn add_column(&mut self, column: usize, default: &DataType) {
+        // rows are shared between indices, and should remain so once padded. the original rows
+        // are kept alive until we're done, so their addresses can't be reused for other rows.
+        let mut padded: HashMap<*const Vec<DataType>, (Row, Row)> = HashMap::new();
+        let mut added = 0;
+        for state in &mut self.state {
+            state.map_rows(|r| {
+                if r.len() != column {
+                    return r.clone();
+                }
+                padded
+                    .entry(Rc::as_ptr(&r.0))
+                    .or_insert_with(|| {
+                        added += default.deep_size_of();
+                        let mut row = Vec::clone(r);
+                        row.push(default.clone());
+                        (r.clone(), Row::from(Rc::new(row)))
+                    })
+                    .1
+                    .clone()
+            });
+        }
+        self.mem_size += added;
+    }
+
     fn clear(&mut self) {
         for state in &mut self.state {
             state.clear();
@@ -237,6 +277,46 @@ mod tests {
         state.process_records(&mut record.into(), None);
     }
 
+    #[test]
+    fn memory_state_add_column() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        insert(&mut state, vec![1.into(), "A".into()]);
+        let size = state.deep_size_of();
+
+        state.add_column(2, &3.into());
+        assert!(state.deep_size_of() > size);
+
+        // both indices should see the padded row, and it should still be shared between them
+        let by_key = match state.lookup(&[0], &KeyType::Single(&1.into())) {
+            LookupResult::Some(RecordResult::Borrowed(rows)) => {
+                rows.iter().next().unwrap().0.clone()
+            }
+            _ => unreachable!(),
+        };
+        let by_value = match state.lookup(&[1], &KeyType::Single(&"A".into())) {
+            LookupResult::Some(RecordResult::Borrowed(rows)) => {
+                rows.iter().next().unwrap().0.clone()
+            }
+            _ => unreachable!(),
+        };
+        let padded: Vec<DataType> = vec![1.into(), "A".into(), 3.into()];
+        assert_eq!(*by_key, padded);
+        assert!(Rc::ptr_eq(&by_key, &by_value));
+
+        // rows that already have the column are left alone
+        let full: Vec<DataType> = vec![2.into(), "B".into(), 4.into()];
+        insert(&mut state, full.clone());
+        state.add_column(2, &3.into());
+        match state.lookup(&[0], &KeyType::Single(&2.into())) {
+            LookupResult::Some(RecordResult::Borrowed(rows)) => {
+                assert_eq!(&**rows.iter().next().unwrap(), &full)
+            }
+            _ => unreachable!(),
+        };
+    }
+
     #[test]
     fn memory_state_process_records() {
         let mut state = MemoryState::default();
@@ -269,6 +349,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn memory_state_find_records() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        for k in 0..10 {
+            insert(&mut state, vec![k.into(), (k % 2).into()]);
+        }
+
+        let odd = |r: &[DataType]| r[1] == 1.into();
+        let mut found = state.find_records(&odd, 10);
+        found.sort();
+        let expected: Vec<Vec<DataType>> = (0..10)
+            .filter(|k| k % 2 == 1)
+            .map(|k| vec![k.into(), 1.into()])
+            .collect();
+        assert_eq!(found, expected);
+
+        // the scan stops once it has found enough
+        assert_eq!(state.find_records(&odd, 2).len(), 2);
+        let none = |r: &[DataType]| r[1] == 2.into();
+        assert!(state.find_records(&none, 10).is_empty());
+    }
+
     #[test]
     fn memory_state_old_records_new_index() {
         let mut state = MemoryState::default();
@@ -284,4 +387,31 @@ mod tests {
             _ => unreachable!(),
         };
     }
+
+    #[test]
+    fn memory_state_row_counts() {
+        let tag = Tag::new(0);
+        let mut state = MemoryState::default();
+        state.add_key(&[0], Some(vec![tag]));
+        for k in 0..3 {
+            state.mark_filled(vec![k.into()], tag);
+            insert(&mut state, vec![k.into(), "A".into()]);
+            insert(&mut state, vec![k.into(), "B".into()]);
+        }
+        assert_eq!(state.rows(), 6);
+
+        let mut delete: Records = vec![(vec![0.into(), "A".into()], false)].into();
+        state.process_records(&mut delete, None);
+        assert_eq!(state.rows(), 5);
+
+        state.mark_hole(&[0.into()], tag);
+        assert_eq!(state.rows(), 4);
+
+        state.evict_keys(tag, &[vec![1.into()]]);
+        assert_eq!(state.rows(), 2);
+
+        let (_, keys, _) = state.evict_random_keys(1);
+        assert_eq!(keys, vec![vec![DataType::from(2)]]);
+        assert_eq!(state.rows(), 0);
+    }
 }
@@ -1,4 +1,5 @@
 mod keyed_state;
+mod log_file;
 mod memory_state;
 mod mk_key;
 mod persistent_state;
@@ -14,6 +15,7 @@ use ahash::RandomState;
 use common::SizeOf;
 use hashbag::HashBag;
 
+pub use self::log_file::LogFile;
 pub(crate) use self::memory_state::MemoryState;
 pub(crate) use self::persistent_state::PersistentState;
 
@@ -44,6 +46,14 @@ pub(crate) trait State: SizeOf + Send {
     /// Return a copy of all records. Panics if the state is only partially materialized.
     fn cloned_records(&self) -> Vec<Vec<DataType>>;
 
+    /// Return a copy of at most `limit` records for which `matches` is true, without copying any
+    /// of the others. Panics if the state is only partially materialized.
+    fn find_records(
+        &self,
+        matches: &dyn Fn(&[DataType]) -> bool,
+        limit: usize,
+    ) -> Vec<Vec<DataType>>;
+
     /// Evict `count` randomly selected keys, returning key colunms of the index chosen to evict
     /// from along with the keys evicted and the number of bytes evicted.
     fn evict_random_keys(&mut self, count: usize) -> (&[usize], Vec<Vec<DataType>>, u64);
@@ -52,6 +62,35 @@ pub(crate) trait State: SizeOf + Send {
     /// of the index that was evicted from and the number of bytes evicted.
     fn evict_keys(&mut self, tag: Tag, keys: &[Vec<DataType>]) -> Option<(&[usize], u64)>;
 
+    /// Append `default` to every stored row that has exactly `column` columns, so that rows
+    /// written before a column was added to their base table look like those written after.
+    fn add_column(&mut self, column: usize, default: &DataType);
+
+    /// Remember, together with the next records that are processed, that the client operation
+    /// `op.0` was applied at `op.1`, and forget the operations in `forgotten`. Only state that
+    /// outlives its domain has to keep these.
+    fn record_applied_op(&mut self, _op: (u64, u64), _forgotten: &[u64]) {}
+
+    /// The client operations recorded with `record_applied_op` that have not been forgotten.
+    fn applied_ops(&self) -> Vec<(u64, u64)> {
+        Vec::new()
+    }
+
+    /// Keep the records of a base's part of the transaction with commit timestamp `txn` until
+    /// `unstage_transaction`, so that the transaction can still be decided if the domain is lost
+    /// before then. Only state that outlives its domain has to keep these.
+    fn stage_transaction(&mut self, _txn: u64, _records: &Records) {}
+
+    /// Forget the part of a transaction staged with `stage_transaction` together with the next
+    /// records that are processed.
+    fn unstage_transaction(&mut self) {}
+
+    /// The part of a transaction staged with `stage_transaction` that has not been unstaged, if
+    /// any, along with its commit timestamp.
+    fn staged_transaction(&self) -> Option<(u64, Records)> {
+        None
+    }
+
     fn clear(&mut self);
 }
 
@@ -21,6 +21,12 @@ const META_KEY: &[u8] = b"meta";
 // The indices themselves are stored in a column family each, with their position in
 // PersistentState::indices as name.
 const DEFAULT_CF: &str = "default";
+// The ids of applied client operations are kept in the default column family as well, each under
+// this prefix followed by the id.
+const APPLIED_OP_PREFIX: &[u8] = b"applied_op";
+// The records of a base's part of a transaction that has not been decided yet are kept in the
+// default column family under this key, along with the transaction's commit timestamp.
+const STAGED_KEY: &[u8] = b"staged_transaction";
 
 // Maximum rows per WriteBatch when building new indices for existing rows.
 const INDEX_BATCH_SIZE: usize = 100_000;
@@ -53,6 +59,11 @@ pub struct PersistentState {
     seq: IndexSeq,
     epoch: IndexEpoch,
     has_unique_index: bool,
+    // Applied client operations to remember and to forget with the next records written.
+    pending_ops: Vec<(u64, u64)>,
+    forgotten_ops: Vec<u64>,
+    // Whether to delete the staged part of a transaction with the next records written.
+    unstaging: bool,
     // With DurabilityMode::DeleteOnExit,
     // RocksDB files are stored in a temporary directory.
     _directory: Option<TempDir>,
@@ -61,11 +72,25 @@ pub struct PersistentState {
 impl State for PersistentState {
     fn process_records(&mut self, records: &mut Records, partial_tag: Option<Tag>) {
         assert!(partial_tag.is_none(), "PersistentState can't be partial");
-        if records.len() == 0 {
+        if records.len() == 0
+            && self.pending_ops.is_empty()
+            && self.forgotten_ops.is_empty()
+            && !self.unstaging
+        {
             return;
         }
 
         let mut batch = WriteBatch::default();
+        if self.unstaging {
+            batch.delete(STAGED_KEY);
+            self.unstaging = false;
+        }
+        for (id, at) in self.pending_ops.drain(..) {
+            batch.put(&applied_op_key(id), &bincode::serialize(&at).unwrap());
+        }
+        for id in self.forgotten_ops.drain(..) {
+            batch.delete(&applied_op_key(id));
+        }
         for r in records.iter() {
             match *r {
                 Record::Positive(ref r) => {
@@ -161,6 +186,55 @@ impl State for PersistentState {
         });
     }
 
+    fn record_applied_op(&mut self, op: (u64, u64), forgotten: &[u64]) {
+        self.pending_ops.push(op);
+        self.forgotten_ops.extend_from_slice(forgotten);
+    }
+
+    fn applied_ops(&self) -> Vec<(u64, u64)> {
+        let db = self.db.as_ref().unwrap();
+        let from = rocksdb::IteratorMode::From(APPLIED_OP_PREFIX, rocksdb::Direction::Forward);
+        let mut ops: Vec<_> = tokio::task::block_in_place(|| {
+            db.full_iterator(from)
+                .take_while(|(key, _)| key.starts_with(APPLIED_OP_PREFIX))
+                .map(|(key, value)| {
+                    let mut id = [0; 8];
+                    id.copy_from_slice(&key[APPLIED_OP_PREFIX.len()..]);
+                    (
+                        u64::from_be_bytes(id),
+                        bincode::deserialize(&value).unwrap(),
+                    )
+                })
+                .collect()
+        });
+        ops.extend(self.pending_ops.iter().cloned());
+        ops
+    }
+
+    fn stage_transaction(&mut self, txn: u64, records: &Records) {
+        let mut opts = rocksdb::WriteOptions::default();
+        opts.set_sync(true);
+        let value = bincode::serialize(&(txn, records)).unwrap();
+        tokio::task::block_in_place(|| {
+            self.db.as_ref().unwrap().put_opt(STAGED_KEY, &value, &opts)
+        })
+        .unwrap();
+    }
+
+    fn unstage_transaction(&mut self) {
+        self.unstaging = true;
+    }
+
+    fn staged_transaction(&self) -> Option<(u64, Records)> {
+        if self.unstaging {
+            return None;
+        }
+        let db = self.db.as_ref().unwrap();
+        tokio::task::block_in_place(|| db.get(STAGED_KEY))
+            .unwrap()
+            .map(|value| bincode::deserialize(&*value).unwrap())
+    }
+
     fn keys(&self) -> Vec<Vec<usize>> {
         self.indices
             .iter()
@@ -174,6 +248,52 @@ impl State for PersistentState {
             .collect()
     }
 
+    fn find_records(
+        &self,
+        matches: &dyn Fn(&[DataType]) -> bool,
+        limit: usize,
+    ) -> Vec<Vec<DataType>> {
+        self.all_rows()
+            .map(|(_, ref value)| bincode::deserialize::<Vec<DataType>>(&value).unwrap())
+            .filter(|r| matches(&r[..]))
+            .take(limit)
+            .collect()
+    }
+
+    fn add_column(&mut self, column: usize, default: &DataType) {
+        if self.indices.is_empty() {
+            return;
+        }
+
+        tokio::task::block_in_place(|| {
+            let db = self.db.as_ref().unwrap();
+            let first_cf = db.cf_handle(&self.indices[0].column_family).unwrap();
+            let iter = db.full_iterator_cf(first_cf, rocksdb::IteratorMode::Start);
+            for chunk in iter.chunks(INDEX_BATCH_SIZE).into_iter() {
+                let mut batch = WriteBatch::default();
+                for (ref pk, ref value) in chunk {
+                    let mut row: Vec<DataType> = bincode::deserialize(&value).unwrap();
+                    if row.len() != column {
+                        continue;
+                    }
+                    row.push(default.clone());
+
+                    // the new column isn't indexed, so every row keeps its keys
+                    let value = bincode::serialize(&row).unwrap();
+                    batch.put_cf(first_cf, pk, &value);
+                    for index in self.indices[1..].iter() {
+                        let key = Self::build_key(&row, &index.columns);
+                        let key = Self::serialize_secondary(&key, pk);
+                        let cf = db.cf_handle(&index.column_family).unwrap();
+                        batch.put_cf(cf, &key, &value);
+                    }
+                }
+
+                db.write(batch).unwrap();
+            }
+        })
+    }
+
     // Returns a row count estimate from RocksDB.
     fn rows(&self) -> usize {
         tokio::task::block_in_place(|| {
@@ -284,6 +404,9 @@ impl PersistentState {
                 seq: 0,
                 indices,
                 has_unique_index: primary_key.is_some(),
+                pending_ops: Vec::new(),
+                forgotten_ops: Vec::new(),
+                unstaging: false,
                 epoch: meta.epoch,
                 db_opts: opts,
                 db: Some(db),
@@ -570,7 +693,7 @@ fn prefix_transform<'a>(key: &'a [u8]) -> &'a [u8] {
     // We'll have to make sure this isn't the META_KEY even when we're filtering it out
     // in Self::in_domain_fn, as the SliceTransform is used to make hashed keys for our
     // HashLinkedList memtable factory.
-    if key == META_KEY {
+    if is_meta(key) {
         return key;
     }
 
@@ -584,7 +707,19 @@ fn prefix_transform<'a>(key: &'a [u8]) -> &'a [u8] {
 
 // Decides which keys the prefix transform should apply to.
 fn in_domain(key: &[u8]) -> bool {
-    key != META_KEY
+    !is_meta(key)
+}
+
+// Whether the key holds information about the state rather than a row. No row key starts with the
+// prefix of applied operations, since it would have to encode the size of an enormous key.
+fn is_meta(key: &[u8]) -> bool {
+    key == META_KEY || key == STAGED_KEY || key.starts_with(APPLIED_OP_PREFIX)
+}
+
+fn applied_op_key(id: u64) -> Vec<u8> {
+    let mut key = APPLIED_OP_PREFIX.to_vec();
+    key.extend_from_slice(&id.to_be_bytes());
+    key
 }
 
 impl SizeOf for PersistentState {
@@ -912,6 +1047,57 @@ mod tests {
         }
     }
 
+    #[test]
+    fn persistent_state_recover_applied_ops() {
+        let (_dir, name) = get_tmp_path();
+        let mut params = PersistenceParameters::default();
+        params.mode = DurabilityMode::Permanent;
+        {
+            let mut state = PersistentState::new(name.clone(), Some(&[0]), &params);
+            state.add_key(&[0], None);
+            state.record_applied_op((7, 100), &[]);
+            insert(&mut state, vec![10.into(), "Cat".into()]);
+            // an operation that changed no rows is still remembered
+            state.record_applied_op((8, 200), &[7]);
+            state.process_records(&mut Records::default(), None);
+            state.record_applied_op((9, 300), &[]);
+        }
+
+        // the last operation was never written along with any records
+        let state = PersistentState::new(name, Some(&[0]), &params);
+        assert_eq!(state.applied_ops(), vec![(8, 200)]);
+        assert_eq!(state.rows(), 1);
+    }
+
+    #[test]
+    fn persistent_state_recover_staged_transaction() {
+        let (_dir, name) = get_tmp_path();
+        let mut params = PersistenceParameters::default();
+        params.mode = DurabilityMode::Permanent;
+        let staged: Records = vec![Record::Positive(vec![10.into(), "Cat".into()])].into();
+        {
+            let mut state = PersistentState::new(name.clone(), Some(&[0]), &params);
+            state.add_key(&[0], None);
+            state.stage_transaction(7, &staged);
+        }
+
+        // the staged part survives, but is not applied
+        let mut state = PersistentState::new(name.clone(), Some(&[0]), &params);
+        assert_eq!(state.staged_transaction(), Some((7, staged.clone())));
+        assert_eq!(state.rows(), 0);
+
+        // and is forgotten together with the records it is applied as
+        state.unstage_transaction();
+        assert_eq!(state.staged_transaction(), None);
+        let mut rs = staged.clone();
+        state.process_records(&mut rs, None);
+        drop(state);
+
+        let state = PersistentState::new(name, Some(&[0]), &params);
+        assert_eq!(state.staged_transaction(), None);
+        assert_eq!(state.rows(), 1);
+    }
+
     #[test]
     fn persistent_state_remove() {
         let mut state = setup_persistent("persistent_state_remove");
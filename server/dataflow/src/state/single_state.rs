@@ -200,8 +200,9 @@ impl SingleState {
             }
         };
         // mark_hole should only be called on keys we called mark_filled on
+        let removed = removed.unwrap();
+        self.rows = self.rows.checked_sub(removed.len()).unwrap();
         removed
-            .unwrap()
             .iter()
             .filter(|r| Rc::strong_count(&r.0) == 1)
             .map(SizeOf::deep_size_of)
@@ -230,8 +231,9 @@ impl SingleState {
         let mut bytes_freed = 0;
         let mut keys = Vec::with_capacity(count);
         for _ in 0..count {
-            if let Some((n, key)) = self.state.evict_with_seed(rng.gen()) {
+            if let Some((n, rows, key)) = self.state.evict_with_seed(rng.gen()) {
                 bytes_freed += n;
+                self.rows = self.rows.checked_sub(rows).unwrap();
                 keys.push(key);
             } else {
                 break;
@@ -242,7 +244,36 @@ impl SingleState {
 
     /// Evicts a specified key from this state, returning the number of bytes freed.
     pub(super) fn evict_keys(&mut self, keys: &[Vec<DataType>]) -> u64 {
-        keys.iter().map(|k| self.state.evict(k)).sum()
+        let mut bytes_freed = 0;
+        for key in keys {
+            let (bytes, rows) = self.state.evict(key);
+            bytes_freed += bytes;
+            self.rows = self.rows.checked_sub(rows).unwrap();
+        }
+        bytes_freed
+    }
+
+    /// Replace every row with the one `f` maps it to.
+    pub(super) fn map_rows<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&Row) -> Row,
+    {
+        macro_rules! map_rows {
+            ($map:ident) => {
+                for rs in $map.values_mut() {
+                    *rs = rs.iter().map(&mut f).collect();
+                }
+            };
+        }
+
+        match self.state {
+            KeyedState::Single(ref mut map) => map_rows!(map),
+            KeyedState::Double(ref mut map) => map_rows!(map),
+            KeyedState::Tri(ref mut map) => map_rows!(map),
+            KeyedState::Quad(ref mut map) => map_rows!(map),
+            KeyedState::Quin(ref mut map) => map_rows!(map),
+            KeyedState::Sex(ref mut map) => map_rows!(map),
+        }
     }
 
     pub(super) fn values<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Rows> + 'a> {
@@ -0,0 +1,106 @@
+//! Spans of the distributed traces that clients propagate into Noria, exported over OTLP.
+
+use noria::debug::trace::TraceContext;
+use opentelemetry::sdk::{trace as sdktrace, Resource};
+use opentelemetry::trace::{
+    Link, Span, SpanContext, SpanId, SpanKind, TraceContextExt, TraceError, TraceId, TraceState,
+    Tracer, TRACE_FLAG_SAMPLED,
+};
+use opentelemetry::{Context, KeyValue};
+use std::sync::Arc;
+use std::time;
+
+struct Exporter {
+    tracer: sdktrace::Tracer,
+    /// Exports the spans that are still buffered once the last handle goes away.
+    _uninstall: opentelemetry_otlp::Uninstall,
+}
+
+/// Where a worker sends the spans of the reads and writes that pass through it.
+///
+/// Spans are only recorded for operations whose client propagated a [`TraceContext`], which
+/// clients only do for sampled traces, so unsampled traffic causes no spans. The default
+/// `Telemetry` records none at all, and just passes contexts on.
+#[derive(Clone, Default)]
+pub struct Telemetry(Option<Arc<Exporter>>);
+
+impl Telemetry {
+    /// Export spans over OTLP to the collector at `endpoint`, such as `http://localhost:4317`.
+    pub fn otlp(endpoint: &str) -> Result<Self, TraceError> {
+        let (tracer, uninstall) = opentelemetry_otlp::new_pipeline()
+            .with_endpoint(endpoint)
+            .with_trace_config(
+                sdktrace::config()
+                    .with_resource(Resource::new(vec![KeyValue::new("service.name", "noria")])),
+            )
+            .install()?;
+        Ok(Telemetry(Some(Arc::new(Exporter {
+            tracer,
+            _uninstall: uninstall,
+        }))))
+    }
+
+    /// Whether spans are exported anywhere.
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Record a span that is a child of `parent`, and that started and ended at the given times
+    /// in nanoseconds since the Unix epoch. The span is linked to each of `links`.
+    ///
+    /// Returns the context that the rest of the operation is propagated with, which is the new
+    /// span's, or `parent` if spans are not exported.
+    pub fn span(
+        &self,
+        name: &'static str,
+        parent: TraceContext,
+        start: u64,
+        end: u64,
+        links: &[TraceContext],
+        attributes: Vec<KeyValue>,
+    ) -> TraceContext {
+        let exporter = match self.0 {
+            Some(ref exporter) => exporter,
+            None => return parent,
+        };
+
+        let mut span = exporter
+            .tracer
+            .span_builder(name)
+            .with_parent_context(Context::new().with_remote_span_context(remote(parent)))
+            .with_kind(SpanKind::Internal)
+            .with_start_time(at(start))
+            .with_links(
+                links
+                    .iter()
+                    .map(|&link| Link::new(remote(link), Vec::new()))
+                    .collect(),
+            )
+            .with_attributes(attributes)
+            .start(&exporter.tracer);
+        let context = span.span_context();
+        let context = TraceContext {
+            trace_id: context.trace_id().to_u128(),
+            span_id: context.span_id().to_u64(),
+        };
+        // a span with a start after its end is rejected by some collectors, which can happen when
+        // the clocks of the client and the worker disagree
+        span.end_with_timestamp(at(std::cmp::max(start, end)));
+        context
+    }
+}
+
+/// The span context of a span that was recorded elsewhere.
+fn remote(context: TraceContext) -> SpanContext {
+    SpanContext::new(
+        TraceId::from_u128(context.trace_id),
+        SpanId::from_u64(context.span_id),
+        TRACE_FLAG_SAMPLED,
+        true,
+        TraceState::default(),
+    )
+}
+
+fn at(nanos: u64) -> time::SystemTime {
+    time::UNIX_EPOCH + time::Duration::from_nanos(nanos)
+}
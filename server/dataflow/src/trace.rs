@@ -0,0 +1,79 @@
+use noria::debug::trace::{PacketEvent, TraceEvent};
+use noria::internal::DomainIndex;
+use petgraph::graph::NodeIndex;
+use std::collections::{HashMap, VecDeque};
+use std::time;
+
+/// The most traces a domain keeps events for. Once there are more, the oldest is forgotten.
+const MAX_TRACES: usize = 1024;
+
+/// The events that traced writes have caused in a domain, keyed by trace tag.
+///
+/// The controller asks every domain for the events of a tag and puts them together, so a domain
+/// does not know whether a trace is complete. It instead only remembers the most recent traces.
+pub(crate) struct TraceCollector {
+    domain: DomainIndex,
+    shard: usize,
+    traces: HashMap<u64, Vec<TraceEvent>>,
+    order: VecDeque<u64>,
+}
+
+impl TraceCollector {
+    pub(crate) fn new(domain: DomainIndex, shard: usize) -> Self {
+        TraceCollector {
+            domain,
+            shard,
+            traces: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Record that `event` just happened at `node` to the update traced under `tag`.
+    pub(crate) fn record(&mut self, tag: u64, node: NodeIndex, event: PacketEvent) {
+        let time = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+
+        if !self.traces.contains_key(&tag) {
+            if self.order.len() >= MAX_TRACES {
+                let oldest = self.order.pop_front().unwrap();
+                self.traces.remove(&oldest);
+            }
+            self.order.push_back(tag);
+        }
+        self.traces.entry(tag).or_default().push(TraceEvent {
+            node,
+            domain: self.domain,
+            shard: self.shard,
+            event,
+            time,
+        });
+    }
+
+    /// The events recorded under `tag`, in the order they happened.
+    pub(crate) fn events(&self, tag: u64) -> Vec<TraceEvent> {
+        self.traces.get(&tag).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_oldest() {
+        let mut traces = TraceCollector::new(0.into(), 0);
+        for tag in 0..=MAX_TRACES as u64 {
+            traces.record(tag, NodeIndex::new(1), PacketEvent::Processed);
+        }
+        traces.record(1, NodeIndex::new(2), PacketEvent::Reached);
+
+        assert!(traces.events(0).is_empty());
+        let events = traces.events(1);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].node, NodeIndex::new(2));
+        assert_eq!(events[1].event, PacketEvent::Reached);
+        assert!(events[0].time <= events[1].time);
+    }
+}
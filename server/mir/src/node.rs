@@ -2,13 +2,14 @@ use nom_sql::{ArithmeticExpression, ColumnSpecification, Literal, OrderType};
 use petgraph::graph::NodeIndex;
 use std::cell::RefCell;
 use std::fmt::{Debug, Display, Error, Formatter};
+use std::iter;
 use std::rc::Rc;
 
 use crate::column::Column;
 use crate::{FlowNode, MirNodeRef};
 use common::DataType;
 use dataflow::ops;
-use dataflow::ops::filter::FilterCondition;
+use dataflow::ops::filter::{FilterCondition, Value};
 use dataflow::ops::grouped::aggregate::Aggregation as AggregationKind;
 use dataflow::ops::grouped::extremum::Extremum as ExtremumKind;
 use dataflow::ops::grouped::filteraggregate::FilterAggregation as FilterAggregationKind;
@@ -22,6 +23,7 @@ pub enum GroupedNodeType {
     GroupConcat(String),
 }
 
+#[derive(Clone)]
 pub struct MirNode {
     pub name: String,
     pub from_version: usize,
@@ -196,6 +198,62 @@ impl MirNode {
         self.inner.add_column(c);
     }
 
+    /// The other MIR nodes that this node refers to.
+    fn referenced(&self) -> impl Iterator<Item = &MirNodeRef> {
+        let inner = match self.inner {
+            MirNodeType::Reuse { ref node } | MirNodeType::Leaf { ref node, .. } => Some(node),
+            MirNodeType::Base {
+                adapted_over: Some(ref a),
+                ..
+            } => Some(&a.over),
+            _ => None,
+        };
+        self.ancestors
+            .iter()
+            .chain(self.children.iter())
+            .chain(inner)
+    }
+
+    /// Make the column positions in this node's filter conditions, which refer to the columns
+    /// `from` of its parent, refer to where those columns are in `to` instead.
+    ///
+    /// This is needed when the parent is swapped for a node that has the same columns, or more, in
+    /// a different order.
+    pub fn remap_parent_columns(&mut self, from: &[Column], to: &[Column]) {
+        let position = |i: usize| {
+            to.iter()
+                .position(|c| *c == from[i])
+                .unwrap_or_else(|| panic!("new parent lacks column {:?}", from[i]))
+        };
+        if let MirNodeType::Filter { .. } = self.inner {
+            // filters emit their parent's columns as they are
+            if self.columns[..] == *from {
+                self.columns = to.to_vec();
+            }
+        }
+        let conditions = match self.inner {
+            MirNodeType::Filter { ref mut conditions }
+            | MirNodeType::FilterAggregation {
+                ref mut conditions, ..
+            } => conditions,
+            _ => return,
+        };
+        for (col, cond) in conditions.iter_mut() {
+            *col = position(*col);
+            match *cond {
+                FilterCondition::Comparison(_, Value::Column(ref mut c)) => *c = position(*c),
+                FilterCondition::Udf(_, ref mut args) => {
+                    for arg in args {
+                        if let Value::Column(ref mut c) = *arg {
+                            *c = position(*c);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
     pub fn ancestors(&self) -> &[MirNodeRef] {
         self.ancestors.as_slice()
     }
@@ -388,14 +446,74 @@ impl MirNode {
     }
 }
 
+/// Whether two joins join on the same pairs of columns, in whichever order.
+fn same_join_keys(
+    our_left: &[Column],
+    our_right: &[Column],
+    left: &[Column],
+    right: &[Column],
+) -> bool {
+    our_left.len() == left.len()
+        && left.iter().zip(right).all(|(l, r)| {
+            our_left
+                .iter()
+                .zip(our_right)
+                .any(|(ol, or)| ol == l && or == r)
+        })
+}
+
+/// Copy the given MIR nodes, along with every node they are connected to, such that changing the
+/// copies leaves the originals as they are.
+///
+/// The returned map takes the address of each original node to its copy.
+pub fn duplicate<'a, I>(nodes: I) -> HashMap<*const RefCell<MirNode>, MirNodeRef>
+where
+    I: IntoIterator<Item = &'a MirNodeRef>,
+{
+    let mut copies: HashMap<*const RefCell<MirNode>, MirNodeRef> = HashMap::new();
+    let mut frontier: Vec<MirNodeRef> = nodes.into_iter().cloned().collect();
+    while let Some(n) = frontier.pop() {
+        let addr: *const RefCell<MirNode> = &*n;
+        if copies.contains_key(&addr) {
+            continue;
+        }
+        let copy = n.borrow().clone();
+        frontier.extend(copy.referenced().cloned());
+        copies.insert(addr, Rc::new(RefCell::new(copy)));
+    }
+
+    // the copies still refer to the original nodes, so point them at each other instead
+    let copy_of = |n: &MirNodeRef| copies[&(&**n as *const RefCell<MirNode>)].clone();
+    for copy in copies.values() {
+        let mut copy = copy.borrow_mut();
+        copy.ancestors = copy.ancestors.iter().map(copy_of).collect();
+        copy.children = copy.children.iter().map(copy_of).collect();
+        match copy.inner {
+            MirNodeType::Reuse { ref mut node } | MirNodeType::Leaf { ref mut node, .. } => {
+                *node = copy_of(node);
+            }
+            MirNodeType::Base {
+                adapted_over: Some(ref mut a),
+                ..
+            } => {
+                a.over = copy_of(&a.over);
+            }
+            _ => {}
+        }
+    }
+    copies
+}
+
 /// Specifies the adapatation of an existing base node by column addition/removal.
 /// `over` is a `MirNode` of type `Base`.
+#[derive(Clone)]
 pub struct BaseNodeAdaptation {
     pub over: MirNodeRef,
     pub columns_added: Vec<ColumnSpecification>,
     pub columns_removed: Vec<ColumnSpecification>,
 }
 
+#[derive(Clone)]
 pub enum MirNodeType {
     /// over column, group_by columns
     Aggregation {
@@ -477,10 +595,13 @@ pub enum MirNodeType {
     Reuse {
         node: MirNodeRef,
     },
-    /// leaf (reader) node, keys
+    /// leaf (reader) node, keys, whether the last key column is looked up by range, and the order
+    /// in which the rows for each key are returned
     Leaf {
         node: MirNodeRef,
         keys: Vec<Column>,
+        ordered: bool,
+        row_order: Option<Vec<(Column, OrderType)>>,
     },
     /// Rewrite node
     Rewrite {
@@ -667,9 +788,11 @@ impl MirNodeType {
                         ref on_right,
                         ref project,
                     } => {
-                        // TODO(malte): column order does not actually need to match, but this only
-                        // succeeds if it does.
-                        our_on_left == on_left && our_on_right == on_right && our_project == project
+                        // the sides of an inner join can be swapped, and the operators below a
+                        // join look its columns up by name, so they need not be in the same order
+                        (same_join_keys(our_on_left, our_on_right, on_left, on_right)
+                            || same_join_keys(our_on_left, our_on_right, on_right, on_left))
+                            && project.iter().all(|c| our_project.contains(c))
                     }
                     _ => false,
                 }
@@ -685,9 +808,9 @@ impl MirNodeType {
                         ref on_right,
                         ref project,
                     } => {
-                        // TODO(malte): column order does not actually need to match, but this only
-                        // succeeds if it does.
-                        our_on_left == on_left && our_on_right == on_right && our_project == project
+                        // unlike for inner joins, the sides matter here
+                        same_join_keys(our_on_left, our_on_right, on_left, on_right)
+                            && project.iter().all(|c| our_project.contains(c))
                     }
                     _ => false,
                 }
@@ -742,9 +865,17 @@ impl MirNodeType {
                 _ => false,
             },
             MirNodeType::Leaf {
-                keys: ref our_keys, ..
+                keys: ref our_keys,
+                ordered: our_ordered,
+                row_order: ref our_row_order,
+                ..
             } => match *other {
-                MirNodeType::Leaf { ref keys, .. } => keys == our_keys,
+                MirNodeType::Leaf {
+                    ref keys,
+                    ordered,
+                    ref row_order,
+                    ..
+                } => keys == our_keys && ordered == our_ordered && row_order == our_row_order,
                 _ => false,
             },
             MirNodeType::Union { emit: ref our_emit } => match *other {
@@ -806,6 +937,7 @@ impl Debug for MirNodeType {
             } => {
                 let op_string = match *kind {
                     AggregationKind::COUNT => format!("|*|({})", on.name.as_str()),
+                    AggregationKind::COUNT_STAR => "|*|".to_owned(),
                     AggregationKind::SUM => format!("𝛴({})", on.name.as_str()),
                 };
                 let group_cols = group_by
@@ -866,14 +998,16 @@ impl Debug for MirNodeType {
                             FilterCondition::Comparison(ref op, ref x) => {
                                 Some(format!("f{} {} {:?}", i, escape(&format!("{}", op)), x))
                             }
-                            FilterCondition::In(ref xs) => Some(format!(
-                                "f{} IN ({})",
-                                i,
-                                xs.iter()
-                                    .map(|d| format!("{}", d))
-                                    .collect::<Vec<_>>()
-                                    .join(", ")
-                            )),
+                            FilterCondition::In(ref xs) => Some(format!("f{} IN ({})", i, xs)),
+                            FilterCondition::NotIn(ref xs) => {
+                                Some(format!("f{} NOT IN ({})", i, xs))
+                            }
+                            FilterCondition::Udf(ref func, ref args) => {
+                                let args: Vec<_> = iter::once(format!("f{}", i))
+                                    .chain(args.iter().map(|a| format!("{:?}", a)))
+                                    .collect();
+                                Some(format!("{}({})", func, args.join(", ")))
+                            }
                         })
                         .collect::<Vec<_>>()
                         .as_slice()
@@ -889,6 +1023,7 @@ impl Debug for MirNodeType {
             } => {
                 let op_string = match *kind {
                     FilterAggregationKind::COUNT => format!("|*|(filter {})", on.name.as_str()),
+                    FilterAggregationKind::COUNT_STAR => "|*|(filter)".to_owned(),
                     FilterAggregationKind::SUM => format!("𝛴(filter {})", on.name.as_str()),
                 };
                 let group_cols = group_by
@@ -925,13 +1060,30 @@ impl Debug for MirNodeType {
                     jc
                 )
             }
-            MirNodeType::Leaf { ref keys, .. } => {
+            MirNodeType::Leaf {
+                ref keys,
+                ordered,
+                ref row_order,
+                ..
+            } => {
                 let key_cols = keys
                     .iter()
                     .map(|k| k.name.clone())
                     .collect::<Vec<_>>()
                     .join(", ");
-                write!(f, "Leaf [⚷: {}]", key_cols)
+                write!(f, "Leaf [⚷: {}", key_cols)?;
+                if ordered {
+                    write!(f, ", ordered")?;
+                }
+                if let Some(ref row_order) = *row_order {
+                    let order_cols = row_order
+                        .iter()
+                        .map(|(c, o)| format!("{} {}", c.name, o))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    write!(f, ", sorted by {}", order_cols)?;
+                }
+                write!(f, "]")
             }
             MirNodeType::LeftJoin {
                 ref on_left,
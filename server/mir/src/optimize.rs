@@ -171,6 +171,7 @@ fn find_and_merge_filter_aggregates(q: &mut MirQuery) -> Vec<MirNodeRef> {
                     group_by.to_vec(),
                     match kind {
                         Aggregation::COUNT => FilterAggregation::COUNT,
+                        Aggregation::COUNT_STAR => FilterAggregation::COUNT_STAR,
                         Aggregation::SUM => FilterAggregation::SUM,
                     },
                 )
@@ -32,11 +32,28 @@ pub fn rewind_until_columns_found(leaf: MirNodeRef, columns: &[Column]) -> Optio
     }
 }
 
+/// Whether `new` and `old` have the same number of ancestors, and every ancestor of `new` can be
+/// stood in for by one of `old`'s.
+fn same_ancestors(old: &MirNode, new: &MirNode) -> bool {
+    old.ancestors().len() == new.ancestors().len()
+        && new.ancestors().iter().all(|na| {
+            old.ancestors()
+                .iter()
+                .any(|oa| oa.borrow().can_reuse_as(&*na.borrow()))
+        })
+}
+
+/// Splice `new_query` onto the nodes of `old_query` that it can reuse.
+///
+/// `may_reuse` is asked about every pair of an old node and the new node it would stand in for,
+/// and lets the caller veto reuse that the MIR alone cannot rule out, for example because the
+/// old node is not materialized the way the new query needs it.
 #[allow(clippy::cognitive_complexity)]
 pub fn merge_mir_for_queries(
     log: &slog::Logger,
     new_query: &MirQuery,
     old_query: &MirQuery,
+    may_reuse: &dyn Fn(&MirNode, &MirNode) -> bool,
 ) -> (MirQuery, usize) {
     use std::cell::RefCell;
     use std::collections::{HashMap, HashSet, VecDeque};
@@ -46,7 +63,9 @@ pub fn merge_mir_for_queries(
     for old_base in &old_query.roots {
         let mut found = false;
         for new_base in &new_query.roots {
-            if old_base.borrow().can_reuse_as(&*new_base.borrow()) {
+            if old_base.borrow().can_reuse_as(&*new_base.borrow())
+                && may_reuse(&*old_base.borrow(), &*new_base.borrow())
+            {
                 found = true;
                 trace!(log, "tracing from reusable base {:?}", old_base);
                 trace_nodes.push_back((old_base.clone(), new_base.clone()));
@@ -112,7 +131,8 @@ pub fn merge_mir_for_queries(
 
             let mut found = false;
             for old_child in old.borrow().children() {
-                if old_child.borrow().can_reuse_as(&*new_child.borrow()) {
+                let (o, n) = (old_child.borrow(), new_child.borrow());
+                if o.can_reuse_as(&*n) && same_ancestors(&*o, &*n) && may_reuse(&*o, &*n) {
                     if reused.contains(&old_child.borrow().versioned_name()) {
                         continue;
                     }
@@ -178,7 +198,21 @@ pub fn merge_mir_for_queries(
             .collect();
 
         let real_n = match reuse.get(&n.borrow().versioned_name()) {
-            None => n.clone(),
+            None => {
+                // a reused parent may have its columns in a different order, or have more of
+                // them, so column positions taken from the original parent must be updated
+                let orig = n.borrow().ancestors().to_vec();
+                if orig.len() == 1 {
+                    if let Some(reused) = reuse.get(&orig[0].borrow().versioned_name()) {
+                        let from = orig[0].borrow().columns().to_vec();
+                        let to = reused.borrow().columns().to_vec();
+                        if from != to {
+                            n.borrow_mut().remap_parent_columns(&from, &to);
+                        }
+                    }
+                }
+                n.clone()
+            }
             Some(reused) => reused.clone(),
         };
 
@@ -276,6 +310,8 @@ mod tests {
             MirNodeType::Leaf {
                 node: c.clone(),
                 keys: vec![Column::from("ba")],
+                ordered: false,
+                row_order: None,
             },
             vec![],
             vec![],
@@ -307,7 +343,7 @@ mod tests {
         };
 
         // when merging with ourselves, the result should consist entirely of reuse nodes
-        let (merged_reflexive, _) = merge_mir_for_queries(&log, &mq1, &mq1);
+        let (merged_reflexive, _) = merge_mir_for_queries(&log, &mq1, &mq1, &|_, _| true);
         assert!(merged_reflexive
             .topo_nodes()
             .iter()
@@ -341,7 +377,7 @@ mod tests {
             roots: vec![a, b],
             leaf: d,
         };
-        let (merged_extension, _) = merge_mir_for_queries(&log, &mq2, &mq1);
+        let (merged_extension, _) = merge_mir_for_queries(&log, &mq2, &mq1, &|_, _| true);
         for n in merged_extension.topo_nodes() {
             match n.borrow().name() {
                 // first three nodes (2x base, 1x join) should have been reused
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::fmt::{self, Write};
+use std::iter;
 
 use crate::node::{MirNode, MirNodeType};
 use crate::query::MirQuery;
@@ -106,6 +107,7 @@ impl GraphViz for MirNodeType {
             } => {
                 let op_string = match *kind {
                     AggregationKind::COUNT => format!("\\|*\\|({})", print_col(on)),
+                    AggregationKind::COUNT_STAR => "\\|*\\|".to_owned(),
                     AggregationKind::SUM => format!("𝛴({})", print_col(on)),
                 };
                 let group_cols = group_by
@@ -158,6 +160,7 @@ impl GraphViz for MirNodeType {
             } => {
                 let op_string = match *kind {
                     FilterAggregationKind::COUNT => format!("\\|*\\|(filter {})", print_col(on)),
+                    FilterAggregationKind::COUNT_STAR => "\\|*\\|(filter)".to_owned(),
                     FilterAggregationKind::SUM => format!("𝛴(filter {})", print_col(on)),
                 };
                 let group_cols = group_by
@@ -185,14 +188,16 @@ impl GraphViz for MirNodeType {
                             FilterCondition::Comparison(ref op, ref x) => {
                                 Some(format!("f{} {} {}", i, escape(&format!("{}", op)), x))
                             }
-                            FilterCondition::In(ref xs) => Some(format!(
-                                "f{} IN ({})",
-                                i,
-                                xs.iter()
-                                    .map(|d| format!("{}", d))
-                                    .collect::<Vec<_>>()
-                                    .join(", ")
-                            )),
+                            FilterCondition::In(ref xs) => Some(format!("f{} IN ({})", i, xs)),
+                            FilterCondition::NotIn(ref xs) => {
+                                Some(format!("f{} NOT IN ({})", i, xs))
+                            }
+                            FilterCondition::Udf(ref func, ref args) => {
+                                let args: Vec<_> = iter::once(format!("f{}", i))
+                                    .chain(args.iter().map(ToString::to_string))
+                                    .collect();
+                                Some(format!("{}({})", func, args.join(", ")))
+                            }
                         })
                         .collect::<Vec<_>>()
                         .as_slice()
@@ -0,0 +1,75 @@
+use dataflow::{replay_capture, CaptureFile};
+use std::collections::BTreeMap;
+use std::process;
+
+fn main() {
+    use clap::{App, Arg};
+    let matches = App::new("noria-replay")
+        .version("0.0.1")
+        .about(
+            "Replays the events that a domain captured against a copy of the domain, and prints \
+             the state the copy ends up with as JSON.",
+        )
+        .arg(
+            Arg::with_name("capture")
+                .required(true)
+                .help("Capture written by the domain to its capture directory."),
+        )
+        .arg(
+            Arg::with_name("realtime")
+                .long("realtime")
+                .help("Process each event no sooner than it was processed when captured."),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help("Log what the domain does."),
+        )
+        .get_matches();
+
+    let path = matches.value_of("capture").unwrap();
+    let capture = match CaptureFile::open(path) {
+        Ok(capture) => capture,
+        Err(e) => {
+            eprintln!("cannot read capture {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+    let realtime = matches.is_present("realtime");
+    let log = if matches.is_present("verbose") {
+        noria_server::logger_pls()
+    } else {
+        slog::Logger::root(slog::Discard, slog::o!())
+    };
+
+    let mut rt = tokio::runtime::Builder::new();
+    rt.enable_all();
+    rt.threaded_scheduler();
+    let mut rt = rt.build().unwrap();
+    let replayed = rt
+        .block_on(tokio::spawn(async move {
+            replay_capture(capture, realtime, log)
+        }))
+        .unwrap();
+    let replayed = match replayed {
+        Ok(replayed) => replayed,
+        Err(e) => {
+            eprintln!("cannot replay capture {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    for why in &replayed.nondeterminism {
+        eprintln!("warning: replay may differ from capture: {}", why);
+    }
+    let mut sent: Vec<_> = replayed.sent.iter().collect();
+    sent.sort();
+    for (&(domain, shard), n) in sent {
+        eprintln!("sent {} packets to domain {}.{}", n, domain.index(), shard);
+    }
+    eprintln!("acknowledged {} writes", replayed.acks);
+
+    let state: BTreeMap<_, _> = replayed.state.into_iter().collect();
+    println!("{}", serde_json::to_string_pretty(&state).unwrap());
+}
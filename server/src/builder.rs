@@ -1,11 +1,19 @@
 use crate::handle::Handle;
+use crate::worker::{Faults, KafkaSource};
 use crate::Config;
+use crate::CpuAffinity;
 use crate::FrontierStrategy;
 use crate::ReuseConfigType;
-use dataflow::PersistenceParameters;
+use dataflow::node::special::{DedupWindow, EgressBatching, HotKeySalting};
+use dataflow::ops::lookup::{self, LookupClient};
+use dataflow::{EvictionPolicy, NegativeCaching, OverloadPolicy, PersistenceParameters};
+use noria::auth::Principals;
+use noria::channel::TlsConfig;
 use noria::consensus::{Authority, LocalAuthority};
+use noria::ReplayRate;
 use std::future::Future;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time;
 
@@ -14,6 +22,20 @@ pub struct Builder {
     config: Config,
     memory_limit: Option<usize>,
     memory_check_frequency: Option<time::Duration>,
+    domain_memory_budget: Option<usize>,
+    cpu_affinity: Option<CpuAffinity>,
+    faults: Option<Faults>,
+    metrics_addr: Option<SocketAddr>,
+    view_http_addr: Option<SocketAddr>,
+    grpc_addr: Option<SocketAddr>,
+    otlp_endpoint: Option<String>,
+    kafka_sources: Vec<KafkaSource>,
+    lookup_clients: Vec<(String, Arc<dyn LookupClient>)>,
+    tls: Option<TlsConfig>,
+    principals: Option<Principals>,
+    drain_timeout: time::Duration,
+    labels: Vec<String>,
+    local_transport: bool,
     listen_addr: IpAddr,
     log: slog::Logger,
 }
@@ -25,6 +47,20 @@ impl Default for Builder {
             log: slog::Logger::root(slog::Discard, o!()),
             memory_limit: None,
             memory_check_frequency: None,
+            domain_memory_budget: None,
+            cpu_affinity: None,
+            faults: None,
+            metrics_addr: None,
+            view_http_addr: None,
+            grpc_addr: None,
+            otlp_endpoint: None,
+            kafka_sources: Vec::new(),
+            lookup_clients: Vec::new(),
+            tls: None,
+            principals: None,
+            drain_timeout: time::Duration::from_secs(10),
+            labels: Vec::new(),
+            local_transport: false,
         }
     }
 }
@@ -32,6 +68,9 @@ impl Builder {
     /// Set the maximum number of concurrent partial replay requests a domain can have outstanding
     /// at any given time.
     ///
+    /// Further requests are queued in the order they were made, and are sent as earlier replays
+    /// complete. How many had to wait, and for how long, is reported in the domain's statistics.
+    ///
     /// Note that this number *must* be greater than the width (in terms of number of ancestors) of
     /// the widest union in the graph, otherwise a deadlock will occur.
     pub fn set_max_concurrent_replay(&mut self, n: usize) {
@@ -43,6 +82,166 @@ impl Builder {
         self.config.domain_config.replay_batch_timeout = t;
     }
 
+    /// Set the most keys a partial replay response is held back to collect before it is sent
+    /// without waiting for the batch timeout.
+    pub fn set_partial_replay_batch_size(&mut self, keys: usize) {
+        assert_ne!(keys, 0);
+        self.config.domain_config.replay_batch_size = keys;
+    }
+
+    /// Set how many threads a domain uses to split up the state it replays in full when a
+    /// migration adds a view over it.
+    ///
+    /// The replayed records are still processed by each domain along the replay path one at a
+    /// time.
+    pub fn set_replay_parallelism(&mut self, threads: usize) {
+        assert_ne!(threads, 0);
+        self.config.domain_config.replay_parallelism = threads;
+    }
+
+    /// Limit how fast a domain replays the state of a node in full, such as when a new view is
+    /// filled from a large table, so that the replay does not crowd out regular updates.
+    ///
+    /// This can also be changed while Noria is running, with
+    /// [`ControllerHandle::set_replay_rate`](noria::ControllerHandle::set_replay_rate).
+    pub fn set_replay_rate(&mut self, rate: ReplayRate) {
+        assert_ne!(rate, ReplayRate::RecordsPerSecond(0));
+        assert_ne!(rate, ReplayRate::PacketsPerSecond(0));
+        self.config.domain_config.replay_rate = rate;
+    }
+
+    /// Have replays of a node's full state hold off for as long as the domain has more than
+    /// `packets` packets waiting to be sent to other domains, on top of their replay rate.
+    ///
+    /// A replay holds off for at most a second before each packet it sends.
+    pub fn set_replay_backoff(&mut self, packets: usize) {
+        self.config.domain_config.replay_backoff_depth = packets;
+    }
+
+    /// Have each domain write every event it processes to a file in `dir`, named after the domain
+    /// and shard, so that what it did can be replayed elsewhere with `noria-replay`.
+    ///
+    /// Only domains that start after this is set are captured, from the moment they start.
+    pub fn set_capture_directory<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.config.domain_config.capture_dir = Some(dir.into());
+    }
+
+    /// Set how many packets a domain may have waiting for any one downstream domain shard before it
+    /// stops taking in new work until that shard catches up.
+    pub fn set_output_high_water(&mut self, packets: usize) {
+        self.config.domain_config.output_high_water = packets;
+    }
+
+    /// Set whether bases block client writes or reject them with [`TableError::Overloaded`] while
+    /// a downstream domain is falling behind.
+    ///
+    /// [`TableError::Overloaded`]: noria::error::TableError::Overloaded
+    pub fn set_overload_policy(&mut self, policy: OverloadPolicy) {
+        self.config.domain_config.overload_policy = policy;
+    }
+
+    /// Have egresses hold back regular updates for up to `max_delay`, and send those for the same
+    /// domain shard together once they add up to `max_records` records or about `max_bytes`
+    /// bytes.
+    ///
+    /// Replays, evictions, and updates that a client is waiting for are never held back.
+    pub fn set_egress_batching(
+        &mut self,
+        max_delay: time::Duration,
+        max_records: usize,
+        max_bytes: usize,
+    ) {
+        self.config.domain_config.egress_batching = Some(EgressBatching {
+            max_delay,
+            max_records,
+            max_bytes,
+        });
+    }
+
+    /// Have partially materialized readers write the keys they evict to files in `dir`, so that
+    /// a later read of such a key can be answered from disk instead of with a replay.
+    ///
+    /// Spilled keys are forgotten as soon as they are written to, and the files are removed when
+    /// their readers go away.
+    pub fn set_spill_directory<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.config.domain_config.spill_dir = Some(dir.into());
+    }
+
+    /// Have partially materialized readers remember up to `capacity` keys that they evicted while
+    /// those held no rows, for up to `ttl` each, and fill misses on them without a replay.
+    ///
+    /// A remembered key is forgotten as soon as a write for it reaches the reader, or it is
+    /// evicted further upstream.
+    pub fn set_negative_caching(&mut self, capacity: usize, ttl: time::Duration) {
+        self.config.domain_config.negative_caching = Some(NegativeCaching { capacity, ttl });
+    }
+
+    /// Log a warning whenever a node takes longer than `packet` to process a single packet, or a
+    /// partial replay takes longer than `replay` from the miss that caused it until the missing
+    /// state is filled. `None` turns the respective warning off, which is the default.
+    ///
+    /// This can also be changed while Noria is running, with
+    /// [`ControllerHandle::set_slow_thresholds`](noria::ControllerHandle::set_slow_thresholds).
+    pub fn set_slow_thresholds(
+        &mut self,
+        packet: Option<time::Duration>,
+        replay: Option<time::Duration>,
+    ) {
+        self.config.domain_config.slow_packet_threshold = packet;
+        self.config.domain_config.slow_replay_threshold = replay;
+    }
+
+    /// Have bases that go `interval` without writes let the readers downstream of them know that
+    /// they have applied nothing newer.
+    ///
+    /// Reads bounded in staleness, made with [`View::lookup_fresh`](noria::View::lookup_fresh),
+    /// otherwise wait for the next write to every base table the view reads from. Each such
+    /// update is sent straight through egress batching, so a short interval limits how long
+    /// updates are batched for.
+    pub fn set_freshness_interval(&mut self, interval: time::Duration) {
+        self.config.domain_config.freshness_interval = Some(interval);
+    }
+
+    /// Have every base table shard remember the ids of up to `capacity` of the writes made with
+    /// [`Table::perform_all_once`](noria::Table::perform_all_once), for up to `ttl` each, and drop
+    /// any write with an id it remembers.
+    ///
+    /// The ids are forgotten oldest first, and persisted along with the rows of durable base
+    /// tables. By default, 100000 ids are remembered for ten minutes.
+    pub fn set_dedup_window(&mut self, capacity: usize, ttl: time::Duration) {
+        self.config.domain_config.dedup_window = DedupWindow { capacity, ttl };
+    }
+
+    /// Set the share of client writes whose latency from base table to reader is measured.
+    ///
+    /// This can also be changed while Noria is running, with
+    /// [`ControllerHandle::set_propagation_sampling`](noria::ControllerHandle::set_propagation_sampling).
+    pub fn set_propagation_sampling(&mut self, rate: f64) {
+        self.config.domain_config.propagation_sampling = rate;
+    }
+
+    /// Set the most transactions that may be open at once, and how long one may stay open before
+    /// the controller gives up on its client and aborts it. Clients that go away without
+    /// finishing their transactions hold a place until then.
+    ///
+    /// By default, 65536 transactions may be open, each for ten heartbeats.
+    pub fn set_transaction_limits(&mut self, max_open: usize, timeout: time::Duration) {
+        self.config.max_open_transactions = max_open;
+        self.config.transaction_timeout = Some(timeout);
+    }
+
+    /// Set how many entries the controller's event log keeps. Older entries are dropped.
+    pub fn set_event_log_retention(&mut self, events: usize) {
+        self.config.event_log_retention = events;
+    }
+
+    /// Move up to `max_moves` domains from the workers with the most load onto each worker that
+    /// joins once the deployment is running. With `dry_run`, the moves are only recorded in the
+    /// event log.
+    pub fn set_rebalance_on_join(&mut self, max_moves: usize, dry_run: bool) {
+        self.config.rebalance = Some(noria::Rebalance { max_moves, dry_run });
+    }
+
     /// Set the persistence parameters used by the system.
     pub fn set_persistence(&mut self, p: PersistenceParameters) {
         self.config.persistence = p;
@@ -58,11 +257,49 @@ impl Builder {
         self.config.frontier_strategy = f;
     }
 
+    /// How partially materialized views evict keys, unless their migration chose otherwise.
+    pub fn set_eviction_policy(&mut self, policy: EvictionPolicy) {
+        self.config.eviction_policy = policy;
+    }
+
+    /// Keep the readers of views with several parameters ordered, so that they can serve prefix
+    /// lookups. Such readers are always fully materialized.
+    pub fn enable_prefix_lookups(&mut self) {
+        self.config.prefix_lookups = true;
+    }
+
     /// Set sharding policy for all subsequent migrations; `None` disables
     pub fn set_sharding(&mut self, shards: Option<usize>) {
         self.config.sharding = shards;
     }
 
+    /// Spread keys that make up more than `share` of the records sent to a sharded count or sum
+    /// over `fanout` shards, whose partial counts or sums are then combined.
+    ///
+    /// Only counts and sums that are created after this is set have their hot keys spread.
+    pub fn set_hot_key_salting(&mut self, share: f64, fanout: usize) {
+        assert!(share > 0.0 && share < 1.0);
+        assert!(fanout > 1);
+        self.config.hot_key_salting = Some(HotKeySalting { share, fanout });
+    }
+
+    /// Copy join inputs computed from at most `rows` base rows to every shard of the join, rather
+    /// than shuffle the join's other input by the join key.
+    ///
+    /// The size of an input is estimated when a join is added, from the rows its bases hold at
+    /// that time, so inputs whose bases are added in the same migration are never copied.
+    pub fn set_broadcast_threshold(&mut self, rows: u64) {
+        self.config.broadcast_threshold = Some(rows);
+    }
+
+    /// Set the hash that keys are sharded by.
+    ///
+    /// The hash is fixed when a deployment is first started, since changing it would move keys to
+    /// other shards; starting an existing deployment with a different hash fails.
+    pub fn set_shard_hasher(&mut self, hasher: noria::ShardHasher) {
+        self.config.domain_config.shard_hasher = hasher;
+    }
+
     /// Set how many workers this worker should wait for before becoming a controller. More workers
     /// can join later, but they won't be assigned any of the initial domains.
     pub fn set_quorum(&mut self, quorum: usize) {
@@ -78,6 +315,145 @@ impl Builder {
         self.memory_check_frequency = Some(check_freq);
     }
 
+    /// Cap the materialized state of each of this worker's domains at `bytes`.
+    ///
+    /// Domains that go over evict keys from their largest partial states. Full state cannot be
+    /// evicted, so a domain whose full state alone exceeds the budget logs a warning with the size
+    /// of each of its nodes' state instead.
+    pub fn set_domain_memory_budget(&mut self, bytes: usize) {
+        assert_ne!(bytes, 0);
+        self.domain_memory_budget = Some(bytes);
+    }
+
+    /// Run each of this worker's domains on a thread of its own, pinned to cores according to
+    /// `policy`.
+    ///
+    /// The cores each domain ended up on are reported in its statistics. On platforms where
+    /// threads cannot be pinned, a warning is logged and the domains run as if this was not set.
+    pub fn set_cpu_affinity(&mut self, policy: CpuAffinity) {
+        self.cpu_affinity = Some(policy);
+    }
+
+    /// Inject `faults` into the packets that this worker's domains send each other.
+    ///
+    /// Only available with the `fault_injection` feature, and meant for tests.
+    #[cfg(feature = "fault_injection")]
+    pub fn set_faults(&mut self, faults: Faults) {
+        self.faults = Some(faults);
+    }
+
+    /// Serve metrics about this worker's domains in the Prometheus text format at `/metrics` on
+    /// `addr`.
+    ///
+    /// The address the metrics are served on is available from [`Handle::metrics_address`].
+    pub fn set_metrics_address(&mut self, addr: SocketAddr) {
+        self.metrics_addr = Some(addr);
+    }
+
+    /// Export spans of the reads and writes that clients propagate a distributed trace into, over
+    /// OTLP to the collector at `endpoint`.
+    ///
+    /// Spans cover sending the operation to the worker, group commit, processing at the base,
+    /// each hop between domains, and applying it at readers. Operations without a
+    /// [`TraceContext`](noria::debug::trace::TraceContext) cause none.
+    pub fn set_otlp_endpoint(&mut self, endpoint: &str) {
+        self.otlp_endpoint = Some(endpoint.to_owned());
+    }
+
+    /// Write the messages of a set of partitions of a Kafka topic to a base table, as described
+    /// by `source`.
+    ///
+    /// The source starts once the worker has, and waits for its table to be created. Keep the
+    /// [`SourceStats`](crate::kafka::SourceStats) from [`KafkaSource::stats`] to see how far it
+    /// has come. Only available with the `kafka` feature.
+    #[cfg(feature = "kafka")]
+    pub fn add_kafka_source(&mut self, source: KafkaSource) {
+        self.kafka_sources.push(source);
+    }
+
+    /// Let the external lookups whose domains run on this worker reach their service through
+    /// `client` if they use `name`.
+    ///
+    /// Clients are registered for the whole process when the worker starts, so every worker that
+    /// may run such a lookup must be given one.
+    pub fn add_lookup_client(&mut self, name: &str, client: Arc<dyn LookupClient>) {
+        self.lookup_clients.push((name.to_owned(), client));
+    }
+
+    /// Serve the contents of views as JSON over HTTP on `addr`.
+    ///
+    /// `GET /view/{name}/{key}` looks up a key of a single column, and `POST /view/{name}` a key
+    /// given as a JSON array in the request body. The query parameters `block`, `timeout_ms`,
+    /// `limit` and `page` control the lookup as they do for [`noria::View`]. Unknown views are
+    /// answered with `404`, keys that are still being backfilled with `503`, and reads that take
+    /// too long with `504`.
+    ///
+    /// The address the views are served on is available from [`Handle::view_http_address`].
+    pub fn set_view_http_address(&mut self, addr: SocketAddr) {
+        self.view_http_addr = Some(addr);
+    }
+
+    /// Serve the gRPC interface defined in `proto/noria.proto` on `addr`.
+    ///
+    /// It offers writes to tables and reads from views to clients in languages that cannot use
+    /// the Rust client. Requests are routed to the right shards, so they can be sent to any worker.
+    ///
+    /// The address the interface is served on is available from [`Handle::grpc_address`].
+    pub fn set_grpc_address(&mut self, addr: SocketAddr) {
+        self.grpc_addr = Some(addr);
+    }
+
+    /// Use TLS for the connections this worker makes and, if `tls` has a certificate, for the
+    /// connections made to it.
+    ///
+    /// A worker with a certificate accepts only TLS connections from other workers, from the
+    /// controller, and from clients. Workers without one keep accepting plain text connections,
+    /// so a deployment can move to TLS one worker at a time. The gRPC, HTTP and metrics endpoints
+    /// are not affected.
+    pub fn set_tls(&mut self, tls: TlsConfig) {
+        self.tls = Some(tls);
+    }
+
+    /// Only let clients that present the token of one of `principals` obtain tables and views.
+    ///
+    /// Each principal may only write to the tables, and read from the views, on its allowlists.
+    /// Every worker should be given the same principals, since any of them may become the
+    /// controller, and each of them checks the tokens that clients connect to it with. Clients
+    /// give their token with `ControllerHandle::with_token`.
+    pub fn set_principals(&mut self, principals: Principals) {
+        self.principals = Some(principals);
+    }
+
+    /// Set how long `Handle::drain` waits for the worker's domains to finish the work they have
+    /// been given.
+    ///
+    /// Whatever the domains have not finished by then is dropped, and the worker logs what that
+    /// was. The default is 10 seconds.
+    pub fn set_drain_timeout(&mut self, timeout: time::Duration) {
+        self.drain_timeout = timeout;
+    }
+
+    /// Give this worker `label`, so that the domains of tables and views whose placement is
+    /// constrained to that label may run on it.
+    ///
+    /// Constraints are set with `ControllerHandle::set_placement`. A worker can have any number
+    /// of labels, and domains that are not constrained may run on any worker.
+    pub fn add_label(&mut self, label: &str) {
+        self.labels.push(label.to_owned());
+    }
+
+    /// Have the parts of a deployment that run in this process reach this instance in memory
+    /// rather than over TCP.
+    ///
+    /// The controller, the workers and the domains of instances in the same process that have
+    /// this set send each other messages over in-memory queues, and the tables that clients in
+    /// the process obtain hand their writes to domains in this process without serializing them.
+    /// Which connections go in memory is worked out as they are made, so peers in other processes
+    /// are still reached over TCP. Views are still read over TCP.
+    pub fn set_local_transport(&mut self, local: bool) {
+        self.local_transport = local;
+    }
+
     /// Set the IP address that the worker should use for listening.
     pub fn set_listen_addr(&mut self, listen_addr: IpAddr) {
         self.listen_addr = listen_addr;
@@ -113,11 +489,28 @@ impl Builder {
             ref config,
             memory_limit,
             memory_check_frequency,
+            domain_memory_budget,
+            ref cpu_affinity,
+            ref faults,
+            metrics_addr,
+            view_http_addr,
+            grpc_addr,
+            ref otlp_endpoint,
+            ref kafka_sources,
+            ref lookup_clients,
+            ref tls,
+            ref principals,
+            drain_timeout,
+            ref labels,
+            local_transport,
             ref log,
         } = *self;
 
         let config = config.clone();
         let log = log.clone();
+        for (name, client) in lookup_clients {
+            lookup::register_client(name, client.clone());
+        }
 
         crate::startup::start_instance(
             authority,
@@ -125,6 +518,19 @@ impl Builder {
             config,
             memory_limit,
             memory_check_frequency,
+            domain_memory_budget,
+            cpu_affinity.clone(),
+            faults.clone(),
+            metrics_addr,
+            view_http_addr,
+            grpc_addr,
+            otlp_endpoint.clone(),
+            kafka_sources.clone(),
+            tls.clone(),
+            principals.clone(),
+            drain_timeout,
+            labels.clone(),
+            local_transport,
             log,
         )
     }
@@ -0,0 +1,106 @@
+//! The on-disk layout of a backup of a deployment.
+//!
+//! A backup is a directory that holds a file with the rows of each shard of each base table, and
+//! a manifest that lists those files along with the recipe history. The manifest is written last,
+//! so a directory without one holds no usable backup.
+
+use dataflow::prelude::DataType;
+use noria::RecipeVersion;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+const MANIFEST: &str = "manifest.json";
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct Manifest {
+    pub(super) version: u32,
+    pub(super) recipe: Vec<RecipeVersion>,
+    pub(super) tables: Vec<TableBackup>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct TableBackup {
+    pub(super) name: String,
+    pub(super) shards: Vec<ShardBackup>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct ShardBackup {
+    pub(super) file: String,
+    pub(super) rows: usize,
+    /// The label of the latest batch of writes to the shard that the rows reflect.
+    pub(super) label: u64,
+}
+
+impl Manifest {
+    pub(super) fn new(recipe: Vec<RecipeVersion>) -> Self {
+        Manifest {
+            version: FORMAT_VERSION,
+            recipe,
+            tables: Vec::new(),
+        }
+    }
+}
+
+/// Create the directory a backup is written to, which must not exist or be empty.
+pub(super) fn create_dir(dir: &Path) -> io::Result<()> {
+    if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} is not empty", dir.display()),
+        ));
+    }
+    fs::create_dir_all(dir)
+}
+
+/// Write the rows of one shard of a table into the backup in `dir`.
+pub(super) fn write_rows(dir: &Path, file: &str, rows: &[Vec<DataType>]) -> io::Result<()> {
+    let mut w = BufWriter::new(fs::File::create(dir.join(file))?);
+    bincode::serialize_into(&mut w, rows).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    w.flush()?;
+    w.get_ref().sync_all()
+}
+
+/// Write the manifest of the backup in `dir`, which makes the backup complete.
+pub(super) fn write_manifest(dir: &Path, manifest: &Manifest) -> io::Result<()> {
+    let tmp = dir.join(format!("{}.tmp", MANIFEST));
+    {
+        let mut w = BufWriter::new(fs::File::create(&tmp)?);
+        serde_json::to_writer_pretty(&mut w, manifest)?;
+        w.flush()?;
+        w.get_ref().sync_all()?;
+    }
+    fs::rename(tmp, dir.join(MANIFEST))
+}
+
+pub(super) fn read_manifest(dir: &Path) -> io::Result<Manifest> {
+    let r = BufReader::new(fs::File::open(dir.join(MANIFEST))?);
+    let manifest: Manifest = serde_json::from_reader(r)?;
+    if manifest.version != FORMAT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown backup format version {}", manifest.version),
+        ));
+    }
+    Ok(manifest)
+}
+
+pub(super) fn read_rows(dir: &Path, shard: &ShardBackup) -> io::Result<Vec<Vec<DataType>>> {
+    let r = BufReader::new(fs::File::open(dir.join(&shard.file))?);
+    let rows: Vec<Vec<DataType>> =
+        bincode::deserialize_from(r).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if rows.len() != shard.rows {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} holds {} rows, but the manifest lists {}",
+                shard.file,
+                rows.len(),
+                shard.rows
+            ),
+        ));
+    }
+    Ok(rows)
+}
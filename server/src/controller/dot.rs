@@ -0,0 +1,239 @@
+use crate::controller::domain_handle::DomainHandle;
+use crate::controller::migrate::materialization::Materializations;
+use dataflow::prelude::*;
+use noria::debug::stats::GraphStats;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Render the dataflow graph in the DOT language, with the nodes of each domain drawn in a
+/// cluster of their own, and every node drawn once per shard of its domain.
+///
+/// Each node is labeled with its name, its operator, its fields, the columns its state is keyed
+/// by and whether that state is full or partial. If `stats` is given, the labels also include the
+/// size of each shard's state.
+pub(super) fn annotated_graphviz(
+    graph: &Graph,
+    materializations: &Materializations,
+    domains: &HashMap<DomainIndex, DomainHandle>,
+    stats: Option<&GraphStats>,
+) -> String {
+    let shards_of = |n: &Node| domains.get(&n.domain()).map(|d| d.shards()).unwrap_or(1);
+    let drawn = |n: &Node| !n.is_source() && !n.is_dropped() && n.has_domain();
+
+    let mut by_domain: HashMap<DomainIndex, Vec<NodeIndex>> = HashMap::new();
+    for ni in graph.node_indices() {
+        if drawn(&graph[ni]) {
+            by_domain.entry(graph[ni].domain()).or_default().push(ni);
+        }
+    }
+    let mut by_domain: Vec<_> = by_domain.into_iter().collect();
+    by_domain.sort_by_key(|&(d, _)| d.index());
+
+    let mut s = String::new();
+    writeln!(s, "digraph dataflow {{").unwrap();
+    writeln!(s, "    node [shape=box, fontsize=10]").unwrap();
+    writeln!(s, "    edge [fontsize=9]").unwrap();
+
+    for (domain, nodes) in by_domain {
+        let handle = domains.get(&domain);
+        let shards = handle.map(|d| d.shards()).unwrap_or(1);
+        writeln!(s, "    subgraph cluster_d{} {{", domain.index()).unwrap();
+        writeln!(
+            s,
+            "        label=\"domain {}\"; style=rounded;",
+            domain.index()
+        )
+        .unwrap();
+        for shard in 0..shards {
+            let worker = handle
+                .map(|d| d.assignment(shard).to_string())
+                .unwrap_or_else(|| "unassigned".to_owned());
+            writeln!(
+                s,
+                "        subgraph cluster_d{}_s{} {{",
+                domain.index(),
+                shard
+            )
+            .unwrap();
+            writeln!(
+                s,
+                "            label=\"shard {} on {}\"; style=dashed;",
+                shard,
+                escape(&worker)
+            )
+            .unwrap();
+            for &ni in &nodes {
+                let n = &graph[ni];
+                let mut label = describe(ni, n, materializations);
+                if let Some(stats) = stats {
+                    let node_stats = stats
+                        .domains
+                        .get(&(domain, shard))
+                        .and_then(|(_, nodes)| nodes.get(&ni));
+                    match node_stats {
+                        Some(ns) => match ns.materialized {
+                            MaterializationStatus::Not => {}
+                            _ => {
+                                write!(label, "\n{} rows, {} bytes", ns.rows, ns.mem_size).unwrap()
+                            }
+                        },
+                        None => {}
+                    }
+                }
+                writeln!(
+                    s,
+                    "            n{}_s{} [label=\"{}\"{}]",
+                    ni.index(),
+                    shard,
+                    escape(&label),
+                    style(n)
+                )
+                .unwrap();
+            }
+            writeln!(s, "        }}").unwrap();
+        }
+        writeln!(s, "    }}").unwrap();
+    }
+
+    for edge in graph.raw_edges() {
+        let (src, dst) = (edge.source(), edge.target());
+        let (a, b) = (&graph[src], &graph[dst]);
+        if !drawn(a) || !drawn(b) {
+            continue;
+        }
+
+        let (from, to) = (shards_of(a), shards_of(b));
+        let attrs = if a.domain() == b.domain() {
+            String::new()
+        } else if let Some(by) = a.with_sharder(|sh| {
+            if sh.is_broadcast() {
+                "broadcast".to_owned()
+            } else {
+                format!("shard by {}", column_names(a, sh.sharded_by()))
+            }
+        }) {
+            format!("style=dashed, label=\"{}\"", escape(&by))
+        } else {
+            format!(
+                "style=dashed, label=\"{}\"",
+                escape(&sharding(b, b.sharded_by()))
+            )
+        };
+
+        // within a domain, and between domains that are sharded alike and not joined by a
+        // sharder, each shard only talks to the same shard.
+        let aligned = from == to && (a.domain() == b.domain() || !a.is_sharder());
+        for i in 0..from {
+            for j in 0..to {
+                if aligned && i != j {
+                    continue;
+                }
+                writeln!(
+                    s,
+                    "    n{}_s{} -> n{}_s{} [{}]",
+                    src.index(),
+                    i,
+                    dst.index(),
+                    j,
+                    attrs
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    writeln!(s, "}}").unwrap();
+    s
+}
+
+/// The label of a node, before escaping.
+fn describe(ni: NodeIndex, n: &Node, materializations: &Materializations) -> String {
+    let operator = if n.is_base() {
+        "base table".to_owned()
+    } else if n.is_reader() {
+        "reader".to_owned()
+    } else if n.is_ingress() {
+        "ingress".to_owned()
+    } else if n.is_egress() {
+        "egress".to_owned()
+    } else if n.is_sharder() {
+        "sharder".to_owned()
+    } else {
+        n.description(true)
+    };
+
+    let mut keys: Vec<_> = materializations
+        .indices_of(ni)
+        .map(|cols| format!("[{}]", column_names(n, cols)))
+        .collect();
+    if let Ok(Some(key)) = n.with_reader(|r| r.key()) {
+        keys.push(format!("[{}]", column_names(n, key)));
+    }
+    keys.sort();
+    keys.dedup();
+
+    let state = match materializations.get_status(ni, n) {
+        MaterializationStatus::Not => "none",
+        MaterializationStatus::Full => "full",
+        MaterializationStatus::Partial {
+            beyond_materialization_frontier: false,
+        } => "partial",
+        MaterializationStatus::Partial {
+            beyond_materialization_frontier: true,
+        } => "partial, beyond frontier",
+    };
+
+    let mut label = format!(
+        "{} ({})\n{}\nfields: {}\nsharding: {}",
+        n.name(),
+        ni.index(),
+        operator,
+        n.fields().join(", "),
+        sharding(n, n.sharded_by())
+    );
+    if !keys.is_empty() {
+        write!(label, "\nkeys: {}", keys.join(" ")).unwrap();
+    }
+    write!(label, "\nstate: {}", state).unwrap();
+    label
+}
+
+fn style(n: &Node) -> &'static str {
+    if n.is_base() {
+        ", shape=tab, style=bold"
+    } else if n.is_reader() {
+        ", shape=box3d, style=bold"
+    } else if n.is_ingress() || n.is_egress() || n.is_sharder() {
+        ", style=dotted"
+    } else {
+        ""
+    }
+}
+
+fn sharding(n: &Node, by: Sharding) -> String {
+    match by {
+        Sharding::ByColumn(k, w) => format!("{} / {}-way", column_names(n, &[k]), w),
+        Sharding::ByColumns(ref ks, w) => format!("{} / {}-way", column_names(n, ks), w),
+        Sharding::ByRange(k, ref bs) => {
+            format!("{} / {} ranges", column_names(n, &[k]), bs.len() + 1)
+        }
+        Sharding::Random(w) => format!("random / {}-way", w),
+        Sharding::Broadcast(w) => format!("broadcast / {}-way", w),
+        Sharding::None => "none".to_owned(),
+        Sharding::ForcedNone => "none (forced)".to_owned(),
+    }
+}
+
+fn column_names(n: &Node, cols: &[usize]) -> String {
+    cols.iter()
+        .map(|&c| n.fields().get(c).cloned().unwrap_or_else(|| c.to_string()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Escape a string for use inside a quoted DOT identifier.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,100 @@
+use noria::debug::events::{Event, EventKind, EventPage};
+use std::collections::VecDeque;
+use std::time;
+
+/// The controller's log of what it has done, kept in memory.
+///
+/// Only the latest `retention` events are kept. Events are numbered as they are recorded, so a
+/// reader that falls behind can tell how many it missed.
+pub(super) struct EventLog {
+    events: VecDeque<Event>,
+    next: u64,
+    retention: usize,
+}
+
+impl EventLog {
+    pub(super) fn new(retention: usize) -> Self {
+        EventLog {
+            events: VecDeque::new(),
+            next: 0,
+            retention,
+        }
+    }
+
+    /// Record that `kind` just happened.
+    pub(super) fn record(&mut self, kind: EventKind) {
+        if self.retention == 0 {
+            self.next += 1;
+            return;
+        }
+        if self.events.len() >= self.retention {
+            self.events.pop_front();
+        }
+
+        let time = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        self.events.push_back(Event {
+            seq: self.next,
+            time,
+            kind,
+        });
+        self.next += 1;
+    }
+
+    /// At most `limit` events, starting with the one numbered `cursor`.
+    pub(super) fn page(&self, cursor: u64, limit: usize) -> EventPage {
+        let first = self.next - self.events.len() as u64;
+        // a cursor from before a controller restart may be ahead of the log
+        let start = cursor.max(first).min(self.next);
+        let events: Vec<_> = self
+            .events
+            .iter()
+            .skip((start - first) as usize)
+            .take(limit)
+            .cloned()
+            .collect();
+        EventPage {
+            next: start + events.len() as u64,
+            dropped: first.saturating_sub(cursor),
+            events,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages() {
+        let mut log = EventLog::new(3);
+        for shards in 0..5 {
+            log.record(EventKind::ReshardStarted { shards });
+        }
+
+        // the first two have been dropped
+        let page = log.page(0, 2);
+        assert_eq!(page.dropped, 2);
+        assert_eq!(page.events.len(), 2);
+        assert_eq!(page.events[0].seq, 2);
+        assert_eq!(page.events[0].kind, EventKind::ReshardStarted { shards: 2 });
+        assert_eq!(page.next, 4);
+
+        let page = log.page(page.next, 2);
+        assert_eq!(page.dropped, 0);
+        assert_eq!(page.events.len(), 1);
+        assert_eq!(page.events[0].seq, 4);
+        assert_eq!(page.next, 5);
+
+        // nothing new yet
+        let page = log.page(page.next, 2);
+        assert!(page.events.is_empty());
+        assert_eq!(page.next, 5);
+
+        log.record(EventKind::ReshardAborted);
+        let page = log.page(page.next, 2);
+        assert_eq!(page.events[0].kind, EventKind::ReshardAborted);
+    }
+}
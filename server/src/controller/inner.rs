@@ -1,29 +1,76 @@
+use crate::controller::backup::{self, Manifest, ShardBackup, TableBackup};
 use crate::controller::domain_handle::{DomainHandle, DomainShardHandle};
+use crate::controller::dot;
+use crate::controller::events::EventLog;
 use crate::controller::migrate::materialization::Materializations;
+use crate::controller::migrate::progress::Progress;
 use crate::controller::recipe::Schema;
+use crate::controller::recovery::Recovery;
 use crate::controller::schema;
 use crate::controller::{ControllerState, Migration, Recipe};
 use crate::controller::{Worker, WorkerIdentifier};
 use crate::coordination::{CoordinationMessage, CoordinationPayload, DomainDescriptor};
+use dataflow::ops::udf::Udf;
 use dataflow::prelude::*;
-use dataflow::{node, payload::ControlReplyPacket, prelude::Packet, DomainBuilder, DomainConfig};
+use dataflow::{
+    node, payload::ControlReplyPacket, prelude::Packet, DomainBuilder, DomainConfig, EvictionPolicy,
+};
 use futures_util::stream::StreamExt;
 use hyper::{self, Method, StatusCode};
 use nom_sql::ColumnSpecification;
+use noria::auth::{AuthError, Principals, Scope};
 use noria::builders::*;
-use noria::channel::tcp::{SendError, TcpSender};
+use noria::channel::tcp::SendError;
+use noria::channel::tls::{self, Tls};
+use noria::channel::{local, MaybeLocalSender};
 use noria::consensus::{Authority, Epoch, STATE_KEY};
-use noria::debug::stats::{DomainStats, GraphStats, NodeStats};
-use noria::ActivationResult;
+use noria::debug::bookkeeping::{DomainBookkeeping, EdgeFrontier};
+use noria::debug::events::{EventKind, EventPage};
+use noria::debug::stats::{DomainStats, GraphStats, MemoryBreakdown, NodeStats, ViewStats};
+use noria::debug::trace::{PacketEvent, Trace, TraceEvent};
+use noria::namespace;
+use noria::{
+    ActivationResult, BackupSummary, BeginTransaction, DecommissionStatus, DomainMove,
+    MigrationPlan, Rebalance, RecipeChange, RecipeDiff, RecipeVersion, ReplayRate,
+    TransactionBegun, TransactionDecided, TRANSACTION_PROTOCOL,
+};
 use petgraph::visit::Bfs;
 use slog::Logger;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::mem;
 use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::{cell, io, time};
 
+/// A transaction that has begun, and that not every base it writes to has applied or dropped
+/// its part of yet.
+struct OpenTransaction {
+    began: Instant,
+    /// The bases the transaction writes to.
+    bases: Vec<NodeIndex>,
+    /// The readers that hold back the transaction's writes until all of them have arrived.
+    readers: Vec<(DomainIndex, LocalNodeIndex)>,
+    /// Whether the transaction has been committed, and is only kept until the bases that could not
+    /// be told so apply their part once they are recovered.
+    committed: bool,
+}
+
+/// How many rows of a backed up table are bulk loaded at a time when it is restored.
+const RESTORE_FRAME: usize = 10_000;
+
+/// A change of the number of shards, from `ControllerInner::start_reshard` until it is finished
+/// or aborted.
+struct Resharding {
+    /// The sharding to go back to if the change is aborted.
+    old_sharding: Option<usize>,
+    /// Nodes with an index below this existed before the change, and the rest were added by it.
+    first_new: usize,
+    /// The node that takes over from each existing node once the change is finished.
+    copies: HashMap<NodeIndex, NodeIndex>,
+}
+
 /// `Controller` is the core component of the alternate Soup implementation.
 ///
 /// It keeps track of the structure of the underlying data flow graph and its domains. `Controller`
@@ -35,19 +82,35 @@ pub(super) struct ControllerInner {
     pub(super) source: NodeIndex,
     pub(super) ndomains: usize,
     pub(super) sharding: Option<usize>,
+    /// When the sharders in front of sharded counts and sums spread hot keys over several shards.
+    pub(super) hot_key_salting: Option<node::special::HotKeySalting>,
+    /// How many rows an input of a sharded join may be computed from for it to be copied to every
+    /// shard of the join.
+    pub(super) broadcast_threshold: Option<u64>,
 
     pub(super) domain_config: DomainConfig,
 
     /// Parameters for persistence code.
     pub(super) persistence: PersistenceParameters,
     pub(super) materializations: Materializations,
+    /// How new partially materialized readers evict keys by default.
+    pub(super) eviction_policy: EvictionPolicy,
+    /// Whether views with several parameters get ordered readers.
+    prefix_lookups: bool,
 
     /// Current recipe
     recipe: Recipe,
 
     pub(super) domains: HashMap<DomainIndex, DomainHandle>,
+    /// The cores that the threads running pinned domain shards may run on.
+    pub(super) domain_cores: HashMap<(DomainIndex, usize), Vec<usize>>,
     pub(in crate::controller) domain_nodes: HashMap<DomainIndex, Vec<NodeIndex>>,
     pub(super) channel_coordinator: Arc<ChannelCoordinator>,
+    /// The address that workers send messages to the controller at, which its own messages to
+    /// workers come from.
+    worker_addr: SocketAddr,
+    /// Who may obtain tables and views, if clients must authenticate.
+    principals: Option<Arc<Principals>>,
 
     /// Map from worker address to the address the worker is listening on for reads.
     read_addrs: HashMap<WorkerIdentifier, SocketAddr>,
@@ -58,7 +121,36 @@ pub(super) struct ControllerInner {
 
     pub(super) epoch: Epoch,
 
-    pending_recovery: Option<(Vec<String>, usize)>,
+    /// Every change made to the recipe, oldest first.
+    recipe_history: Vec<RecipeVersion>,
+    pending_recovery: Option<usize>,
+    /// The label of the workers that the domains of each constrained table and view must go on.
+    pub(super) placement: HashMap<String, String>,
+    /// The addresses that the workers being decommissioned listen on.
+    decommissioning: HashSet<SocketAddr>,
+    /// When a domain was last moved off a worker that is being decommissioned.
+    last_decommission_step: Instant,
+    /// How to spread domains onto workers that join once the deployment is running, if at all.
+    rebalance_on_join: Option<Rebalance>,
+    /// The worker that new domains go on while domains are being moved onto it.
+    preferred_worker: Option<WorkerIdentifier>,
+
+    /// Bases that are being bulk loaded, and how many rows have been loaded into each so far.
+    bulk_loads: HashMap<NodeIndex, usize>,
+    /// A change of the number of shards that has been started but not yet finished or aborted.
+    resharding: Option<Resharding>,
+    /// Transactions that have begun but not finished, by commit timestamp.
+    transactions: HashMap<u64, OpenTransaction>,
+    /// Committed transactions that some bases may not have applied their part of yet, as
+    /// persisted in the controller state.
+    committed_transactions: HashSet<u64>,
+    /// The commit timestamp of the most recent transaction.
+    last_transaction: u64,
+    /// The most transactions that may be open at once. Clients that go away without finishing
+    /// theirs hold a place until the controller gives up on them.
+    max_open_transactions: usize,
+    /// How long a transaction may stay open before the controller gives up on it.
+    transaction_timeout: Duration,
 
     quorum: usize,
     heartbeat_every: Duration,
@@ -66,6 +158,12 @@ pub(super) struct ControllerInner {
     last_checked_workers: Instant,
 
     log: slog::Logger,
+    /// What the controller has done, for operators to look back on.
+    events: EventLog,
+    /// How far along the running or most recent migration is.
+    pub(super) progress: Progress,
+    /// How far the controller is with restoring the dataflow it took over, if it took one over.
+    recovery: Recovery,
 
     pub(in crate::controller) replies: DomainReplies,
 }
@@ -110,14 +208,72 @@ impl DomainReplies {
         }
         stats
     }
+
+    async fn wait_for_trace(&mut self, d: &DomainHandle) -> Vec<TraceEvent> {
+        let mut events = Vec::new();
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::Trace(es) => events.extend(es),
+                r => unreachable!("got unexpected non-trace control reply: {:?}", r),
+            }
+        }
+        events
+    }
+
+    async fn wait_for_bookkeeping(&mut self, d: &DomainHandle) -> Vec<DomainBookkeeping> {
+        let mut shards = Vec::new();
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::Bookkeeping(b) => shards.push(b),
+                r => unreachable!("got unexpected non-bookkeeping control reply: {:?}", r),
+            }
+        }
+        shards.sort_by_key(|b| b.shard);
+        shards
+    }
+
+    async fn wait_for_snapshot(&mut self, d: &DomainHandle) -> Vec<(u64, Vec<Vec<DataType>>)> {
+        let mut shards = vec![None; d.shards()];
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::BaseSnapshot { shard, label, rows } => {
+                    shards[shard] = Some((label, rows))
+                }
+                r => unreachable!("got unexpected non-snapshot control reply: {:?}", r),
+            }
+        }
+        shards.into_iter().map(Option::unwrap).collect()
+    }
+
+    async fn wait_for_rows(&mut self, d: &DomainHandle) -> Vec<Vec<DataType>> {
+        let mut rows = Vec::new();
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::Rows(rs) => rows.extend(rs),
+                r => unreachable!("got unexpected non-rows control reply: {:?}", r),
+            }
+        }
+        rows
+    }
 }
 
 pub(super) fn graphviz(
     graph: &Graph,
     detailed: bool,
     materializations: &Materializations,
+) -> String {
+    graphviz_of(graph, detailed, materializations, None)
+}
+
+/// Like `graphviz`, but only describes the nodes in `only`, if given.
+fn graphviz_of(
+    graph: &Graph,
+    detailed: bool,
+    materializations: &Materializations,
+    only: Option<&HashSet<NodeIndex>>,
 ) -> String {
     let mut s = String::new();
+    let shown = |ni: NodeIndex| only.map_or(true, |only| only.contains(&ni));
 
     let indentln = |s: &mut String| s.push_str("    ");
 
@@ -135,7 +291,7 @@ pub(super) fn graphviz(
     }
 
     // node descriptions.
-    for index in graph.node_indices() {
+    for index in graph.node_indices().filter(|&ni| shown(ni)) {
         let node = &graph[index];
         let materialization_status = materializations.get_status(index, node);
         indentln(&mut s);
@@ -144,7 +300,11 @@ pub(super) fn graphviz(
     }
 
     // edges.
-    for (_, edge) in graph.raw_edges().iter().enumerate() {
+    for edge in graph
+        .raw_edges()
+        .iter()
+        .filter(|e| shown(e.source()) && shown(e.target()))
+    {
         indentln(&mut s);
         s.push_str(&format!(
             "n{} -> n{} [ {} ]",
@@ -167,6 +327,23 @@ pub(super) fn graphviz(
     s
 }
 
+/// The entries of `nodes` that are in `namespace`, by their names within it, or all of them if no
+/// namespace is given.
+fn in_namespace(
+    nodes: BTreeMap<String, NodeIndex>,
+    namespace: Option<&str>,
+) -> BTreeMap<String, NodeIndex> {
+    match namespace {
+        None => nodes,
+        Some(_) => nodes
+            .into_iter()
+            .filter_map(|(name, ni)| {
+                namespace::strip(namespace, &name).map(|name| (name.to_owned(), ni))
+            })
+            .collect(),
+    }
+}
+
 impl ControllerInner {
     pub(in crate::controller) fn topo_order(&self, new: &HashSet<NodeIndex>) -> Vec<NodeIndex> {
         let mut topo_list = Vec::with_capacity(new.len());
@@ -196,20 +373,24 @@ impl ControllerInner {
     ) -> Result<Result<String, String>, StatusCode> {
         use serde_json as json;
 
+        // these can be limited to a namespace, but all work without a body
+        let namespace = json::from_slice::<Option<String>>(&body).unwrap_or(None);
+        let ns = namespace.as_deref();
+
         match (&method, path.as_ref()) {
-            (&Method::GET, "/simple_graph") => return Ok(Ok(self.graphviz(false))),
+            (&Method::GET, "/simple_graph") => return Ok(Ok(self.graphviz(false, ns))),
             (&Method::POST, "/simple_graphviz") => {
-                return Ok(Ok(json::to_string(&self.graphviz(false)).unwrap()));
+                return Ok(Ok(json::to_string(&self.graphviz(false, ns)).unwrap()));
             }
-            (&Method::GET, "/graph") => return Ok(Ok(self.graphviz(true))),
+            (&Method::GET, "/graph") => return Ok(Ok(self.graphviz(true, ns))),
             (&Method::POST, "/graphviz") => {
-                return Ok(Ok(json::to_string(&self.graphviz(true)).unwrap()));
+                return Ok(Ok(json::to_string(&self.graphviz(true, ns)).unwrap()));
             }
             (&Method::GET, "/get_statistics") => {
-                return Ok(Ok(json::to_string(&self.get_statistics()).unwrap()));
+                return Ok(Ok(json::to_string(&self.get_statistics(ns)).unwrap()));
             }
             (&Method::POST, "/get_statistics") => {
-                return Ok(Ok(json::to_string(&self.get_statistics()).unwrap()));
+                return Ok(Ok(json::to_string(&self.get_statistics(ns)).unwrap()));
             }
             _ => {}
         }
@@ -222,8 +403,14 @@ impl ControllerInner {
             (Method::GET, "/flush_partial") => {
                 Ok(Ok(json::to_string(&self.flush_partial()).unwrap()))
             }
-            (Method::POST, "/inputs") => Ok(Ok(json::to_string(&self.inputs()).unwrap())),
-            (Method::POST, "/outputs") => Ok(Ok(json::to_string(&self.outputs()).unwrap())),
+            (Method::POST, "/inputs") => Ok(Ok(
+                json::to_string(&in_namespace(self.inputs(), ns)).unwrap()
+            )),
+            (Method::POST, "/outputs") => {
+                Ok(Ok(
+                    json::to_string(&in_namespace(self.outputs(), ns)).unwrap()
+                ))
+            }
             (Method::GET, "/instances") => Ok(Ok(json::to_string(&self.get_instances()).unwrap())),
             (Method::GET, "/nodes") => {
                 // TODO(malte): this is a pretty yucky hack, but hyper doesn't provide easy access
@@ -261,10 +448,31 @@ impl ControllerInner {
             }
             (Method::POST, "/table_builder") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
-                .map(|args| Ok(json::to_string(&self.table_builder(args)).unwrap())),
+                .map(
+                    |(name, token, ns): (String, Option<String>, Option<String>)| {
+                        let tb =
+                            self.authorized_table_builder(&name, token.as_deref(), ns.as_deref());
+                        Ok(json::to_string(&tb).unwrap())
+                    },
+                ),
             (Method::POST, "/view_builder") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
-                .map(|args| Ok(json::to_string(&self.view_builder(args)).unwrap())),
+                .map(
+                    |(name, token, ns): (String, Option<String>, Option<String>)| {
+                        let vb =
+                            self.authorized_view_builder(&name, token.as_deref(), ns.as_deref());
+                        Ok(json::to_string(&vb).unwrap())
+                    },
+                ),
+            (Method::POST, "/change_feed") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(
+                    |(name, token, ns): (String, Option<String>, Option<String>)| {
+                        let vb =
+                            self.authorized_change_feed(&name, token.as_deref(), ns.as_deref());
+                        Ok(json::to_string(&vb).unwrap())
+                    },
+                ),
             (Method::POST, "/extend_recipe") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
@@ -277,6 +485,36 @@ impl ControllerInner {
                     self.install_recipe(authority, args)
                         .map(|r| json::to_string(&r).unwrap())
                 }),
+            (Method::POST, "/rollback_recipe") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.rollback_recipe(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/recipe_diff") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| self.recipe_diff(args).map(|r| json::to_string(&r).unwrap())),
+            (Method::POST, "/plan_recipe") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| self.plan_recipe(args).map(|r| json::to_string(&r).unwrap())),
+            (Method::POST, "/recipe_history") => {
+                Ok(Ok(json::to_string(&self.recipe_history).unwrap()))
+            }
+            (Method::POST, "/set_placement") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.set_placement(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/placements") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|namespace| Ok(json::to_string(&self.placements(namespace)).unwrap())),
+            (Method::POST, "/remove_view") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.remove_view(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
             (Method::POST, "/set_security_config") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
@@ -289,6 +527,131 @@ impl ControllerInner {
                     self.create_universe(args)
                         .map(|r| json::to_string(&r).unwrap())
                 }),
+            (Method::POST, "/start_bulk_load") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.start_bulk_load(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/bulk_load") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| self.bulk_load(args).map(|r| json::to_string(&r).unwrap())),
+            (Method::POST, "/finish_bulk_load") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.finish_bulk_load(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/bulk_load_progress") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args: String| Ok(json::to_string(&self.bulk_load_progress(&args)).unwrap())),
+            (Method::POST, "/backup") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| self.backup(args).map(|r| json::to_string(&r).unwrap())),
+            (Method::POST, "/restore") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.restore(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/begin_transaction") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.begin_transaction(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/commit_transaction") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.commit_transaction(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/abort_transaction") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.abort_transaction(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/resplit") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| self.resplit(args).map(|r| json::to_string(&r).unwrap())),
+            (Method::POST, "/start_reshard") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.start_reshard(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/finish_reshard") => Ok(self
+                .finish_reshard(authority)
+                .map(|r| json::to_string(&r).unwrap())),
+            (Method::POST, "/workers") => {
+                let mut workers: Vec<_> = self.workers.keys().collect();
+                workers.sort();
+                Ok(Ok(json::to_string(&workers).unwrap()))
+            }
+            (Method::POST, "/decommission") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|worker| {
+                    self.decommission(authority, worker)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/abort_decommission") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|worker| {
+                    self.abort_decommission(authority, worker)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/decommission_status") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|worker| {
+                    self.decommission_status(worker)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/rebalance") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| self.rebalance(args).map(|r| json::to_string(&r).unwrap())),
+            (Method::POST, "/abort_reshard") => {
+                Ok(self.abort_reshard().map(|r| json::to_string(&r).unwrap()))
+            }
+            (Method::POST, "/annotated_graphviz") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|stats| Ok(json::to_string(&self.annotated_graphviz(stats)).unwrap())),
+            (Method::POST, "/set_packet_logging") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.set_packet_logging(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/set_slow_thresholds") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.set_slow_thresholds(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/set_replay_rate") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|rate| {
+                    self.set_replay_rate(rate)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/set_propagation_sampling") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|rate| {
+                    self.set_propagation_sampling(rate)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/domain_bookkeeping") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|domain| {
+                    self.domain_bookkeeping(domain)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/events") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| Ok(json::to_string(&self.events(args)).unwrap())),
+            (Method::POST, "/trace") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|tag| Ok(json::to_string(&self.trace(tag)).unwrap())),
             (Method::POST, "/remove_node") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
@@ -300,41 +663,99 @@ impl ControllerInner {
     }
 
     pub(super) fn handle_register(&mut self, msg: CoordinationMessage) -> Result<(), io::Error> {
-        let (remote, read_listen_addr) = if let CoordinationPayload::Register {
-            addr: remote,
-            read_listen_addr,
-            ..
-        } = msg.payload
-        {
-            (remote, read_listen_addr)
-        } else {
-            unreachable!();
-        };
+        let (remote, read_listen_addr, expects_tls, labels) =
+            if let CoordinationPayload::Register {
+                addr: remote,
+                read_listen_addr,
+                tls,
+                labels,
+                ..
+            } = msg.payload
+            {
+                (remote, read_listen_addr, tls, labels)
+            } else {
+                unreachable!();
+            };
 
         info!(
             self.log,
-            "new worker registered from {:?}, which listens on {:?}", msg.source, remote
+            "new worker registered from {:?}, which listens on {:?}", msg.source, remote;
+            "labels" => ?labels,
         );
 
-        let sender = TcpSender::connect(&remote)?;
-        let ws = Worker::new(sender);
+        let tls = tls::for_endpoint(self.channel_coordinator.tls(), remote, expects_tls)?;
+        let sender = MaybeLocalSender::connect(&remote, tls)?;
+        let mut ws = Worker::new(remote, sender, expects_tls, labels.into_iter().collect());
+        // a worker that was being decommissioned when the previous controller went away still is
+        ws.decommissioning = self.decommissioning.contains(&remote);
         self.workers.insert(msg.source, ws);
         self.read_addrs.insert(msg.source, read_listen_addr);
+        self.events
+            .record(EventKind::WorkerJoined { worker: msg.source });
 
         if self.workers.len() >= self.quorum {
-            if let Some((recipes, recipe_version)) = self.pending_recovery.take() {
-                assert_eq!(self.workers.len(), self.quorum);
+            if let Some(recipe_version) = self.pending_recovery {
                 assert_eq!(self.recipe.version(), 0);
-                assert!(recipe_version + 1 >= recipes.len());
+                let latest = self.recipe_history.last().unwrap().version;
+
+                // wait for a worker that each constrained table and view may be placed on
+                let restored = Recipe::at_version(&self.recipe_history, latest, None).unwrap();
+                if let Err(e) = self.check_placement(restored.names().iter().map(|n| &**n)) {
+                    warn!(self.log, "not restoring graph configuration yet: {}", e);
+                    return Ok(());
+                }
+                self.pending_recovery = None;
+                // the controller serves nothing else until the dataflow is restored, so no client
+                // reads or writes before the views have caught up with the restored tables
+                let tables = restored
+                    .expressions()
+                    .into_iter()
+                    .filter_map(|(_, q)| match *q {
+                        nom_sql::SqlQuery::CreateTable(ref ctq) => Some(ctq.table.name.clone()),
+                        _ => None,
+                    });
+                self.recovery.start(tables);
+
+                let changes: Vec<_> = Recipe::changes_at(&self.recipe_history, latest)
+                    .unwrap()
+                    .into_iter()
+                    .cloned()
+                    .collect();
+                assert!(recipe_version + 1 >= changes.len());
 
                 info!(self.log, "Restoring graph configuration");
                 self.recipe = Recipe::with_version(
-                    recipe_version + 1 - recipes.len(),
+                    recipe_version + 1 - changes.len(),
                     Some(self.log.clone()),
                 );
-                for r in recipes {
-                    self.apply_recipe(self.recipe.clone().extend(&r).unwrap())
+                if self.prefix_lookups {
+                    self.recipe.enable_prefix_lookups();
+                }
+                for change in changes {
+                    let r = self
+                        .recipe
+                        .clone()
+                        .apply(&change, Some(self.log.clone()))
                         .unwrap();
+                    self.apply_recipe(r).unwrap();
+                    self.report_restored_bases();
+                }
+                // the parts of transactions that bases staged before the deployment went down are
+                // applied or dropped before anything reads from the restored tables
+                self.recover_transactions();
+                // each migration fills the full materializations it adds from the restored tables
+                // before it returns, and partial ones are filled from them on demand, so nothing
+                // is left to settle.
+                self.recovery.finish();
+                info!(self.log, "Restored graph configuration");
+            } else if let Some(rebalance) = self.rebalance_on_join {
+                // once the deployment is running, a new worker would otherwise sit idle until a
+                // migration happens to place something on it
+                if self.domains.is_empty() {
+                    return Ok(());
+                }
+                if let Err(e) = self.rebalance((msg.source, rebalance)) {
+                    warn!(self.log, "not rebalancing domains onto new worker: {}", e);
                 }
             }
         }
@@ -342,6 +763,34 @@ impl ControllerInner {
         Ok(())
     }
 
+    /// Record the rows of every base table that has been restored since this was last called.
+    fn report_restored_bases(&mut self) {
+        let inputs = self.inputs();
+        let restored: Vec<_> = match self.recovery.report() {
+            Some(status) => status
+                .bases
+                .into_iter()
+                .filter(|&(_, ref rows)| rows.is_none())
+                .filter_map(|(base, _)| inputs.get(&base).map(|&ni| (base, ni)))
+                .collect(),
+            None => return,
+        };
+        if restored.is_empty() {
+            return;
+        }
+
+        let stats = self.get_statistics(None);
+        for (base, ni) in restored {
+            let rows = stats
+                .domains
+                .values()
+                .filter_map(|(_, nodes)| nodes.get(&ni))
+                .map(|n| n.rows)
+                .sum();
+            self.recovery.restored(&base, rows);
+        }
+    }
+
     fn check_worker_liveness(&mut self) {
         let mut any_failed = false;
 
@@ -365,6 +814,7 @@ impl ControllerInner {
                     error!(self.log, "worker at {:?} has failed!", addr);
                     ws.healthy = false;
                     failed.push(addr.clone());
+                    self.events.record(EventKind::WorkerLost { worker: *addr });
                 }
             }
             self.handle_failed_workers(failed);
@@ -372,16 +822,30 @@ impl ControllerInner {
     }
 
     fn handle_failed_workers(&mut self, failed: Vec<WorkerIdentifier>) {
+        // bases that are recovered no longer know which transactions they accept parts of, so
+        // those in flight are aborted, and any parts the bases staged are decided once they are
+        // back
+        let open: Vec<_> = self.transactions.keys().cloned().collect();
+        for txn in open {
+            if let Err(e) = self.abort_transaction(txn) {
+                warn!(self.log, "failed to abort transaction: {}", e; "timestamp" => txn);
+            }
+        }
+
         // first, translate from the affected workers to affected data-flow nodes
         let mut affected_nodes = Vec::new();
-        for wi in failed {
+        for wi in &failed {
             info!(self.log, "handling failure of worker {:?}", wi);
-            affected_nodes.extend(self.get_failed_nodes(&wi));
+            affected_nodes.extend(self.get_failed_nodes(wi));
         }
 
         // then, figure out which queries are affected (and thus must be removed and added again in
         // a migration)
         let affected_queries = self.recipe.queries_for_nodes(affected_nodes);
+        self.events.record(EventKind::RecoveryStarted {
+            workers: failed.clone(),
+            queries: affected_queries.clone(),
+        });
         let (recovery, mut original) = self.recipe.make_recovery(affected_queries);
 
         // activate recipe
@@ -396,8 +860,17 @@ impl ControllerInner {
         original.set_sql_inc(tmp.sql_inc().clone());
 
         // back to original recipe, which should add the query again
-        self.apply_recipe(original)
-            .expect("failed to activate original recipe");
+        if let Err(e) = self.apply_recipe(original) {
+            // the queries' domains may only go on workers that are all gone; they stay down
+            crit!(
+                self.log,
+                "failed to bring back queries after worker failure: {}",
+                e
+            );
+        }
+        self.recover_transactions();
+        self.events
+            .record(EventKind::RecoveryFinished { workers: failed });
     }
 
     pub(super) fn handle_heartbeat(&mut self, msg: CoordinationMessage) -> Result<(), io::Error> {
@@ -413,14 +886,50 @@ impl ControllerInner {
         }
 
         self.check_worker_liveness();
+        self.continue_decommission();
+        self.expire_transactions();
         Ok(())
     }
 
+    /// A domain shard on a worker that is shutting down has finished its work, and will send
+    /// nothing more along the edges that leave it than what `frontiers` says it has sent.
+    pub(super) fn handle_quiescent(
+        &mut self,
+        domain: DomainIndex,
+        shard: usize,
+        frontiers: Vec<EdgeFrontier>,
+    ) {
+        info!(self.log, "domain drained";
+              "domain" => domain.index(), "shard" => shard, "edges" => frontiers.len());
+        self.events.record(EventKind::DomainDrained {
+            domain,
+            shard,
+            frontiers,
+        });
+    }
+
+    /// A domain shard refused a connection from `peer`, a sender in the dataflow of an earlier
+    /// controller. The sender stands down on its own once it finds the connection closed.
+    pub(super) fn handle_fenced(&mut self, domain: DomainIndex, shard: usize, peer: SocketAddr) {
+        warn!(self.log, "domain fenced off a sender from an earlier epoch";
+              "domain" => domain.index(), "shard" => shard, "peer" => ?peer);
+        self.events.record(EventKind::SenderFenced {
+            domain,
+            shard,
+            peer,
+        });
+    }
+
     /// Construct `ControllerInner` with a specified listening interface
     pub(super) fn new(
         log: slog::Logger,
         state: ControllerState,
+        worker_addr: SocketAddr,
         drx: tokio::sync::mpsc::UnboundedReceiver<ControlReplyPacket>,
+        tls: Option<Tls>,
+        principals: Option<Arc<Principals>>,
+        progress: Progress,
+        recovery: Recovery,
     ) -> Self {
         let mut g = petgraph::Graph::new();
         let source = g.add_node(node::Node::new(
@@ -435,17 +944,21 @@ impl ControllerInner {
         }
         materializations.set_frontier_strategy(state.config.frontier_strategy);
 
-        let cc = Arc::new(ChannelCoordinator::new());
+        let cc = Arc::new(ChannelCoordinator::with_tls(tls));
+        cc.set_epoch(state.epoch);
         assert_ne!(state.config.quorum, 0);
 
-        let pending_recovery = if !state.recipes.is_empty() {
-            Some((state.recipes, state.recipe_version))
+        let pending_recovery = if !state.recipe_history.is_empty() {
+            Some(state.recipe_version)
         } else {
             None
         };
 
         let mut recipe = Recipe::blank(Some(log.clone()));
         recipe.enable_reuse(state.config.reuse);
+        if state.config.prefix_lookups {
+            recipe.enable_prefix_lookups();
+        }
 
         ControllerInner {
             ingredients: g,
@@ -453,7 +966,11 @@ impl ControllerInner {
             ndomains: 0,
 
             materializations,
+            eviction_policy: state.config.eviction_policy,
+            prefix_lookups: state.config.prefix_lookups,
             sharding: state.config.sharding,
+            hot_key_salting: state.config.hot_key_salting,
+            broadcast_threshold: state.config.broadcast_threshold,
             domain_config: state.config.domain_config,
             persistence: state.config.persistence,
             heartbeat_every: state.config.heartbeat_every,
@@ -463,8 +980,11 @@ impl ControllerInner {
             log,
 
             domains: Default::default(),
+            domain_cores: Default::default(),
             domain_nodes: Default::default(),
             channel_coordinator: cc,
+            worker_addr,
+            principals,
             epoch: state.epoch,
 
             remap: HashMap::default(),
@@ -472,8 +992,27 @@ impl ControllerInner {
             read_addrs: HashMap::default(),
             workers: HashMap::default(),
 
+            recipe_history: state.recipe_history,
             pending_recovery,
+            placement: state.placement,
+            decommissioning: state.decommissioning,
+            last_decommission_step: Instant::now(),
+            rebalance_on_join: state.config.rebalance,
+            preferred_worker: None,
+            bulk_loads: HashMap::default(),
+            resharding: None,
+            transactions: HashMap::default(),
+            committed_transactions: state.committed_transactions,
+            last_transaction: 0,
+            max_open_transactions: state.config.max_open_transactions,
+            transaction_timeout: state
+                .config
+                .transaction_timeout
+                .unwrap_or(state.config.heartbeat_every * 10),
             last_checked_workers: Instant::now(),
+            events: EventLog::new(state.config.event_log_retention),
+            progress,
+            recovery,
 
             replies: DomainReplies(drx),
         }
@@ -509,6 +1048,14 @@ impl ControllerInner {
     ) -> DomainHandle {
         // TODO: can we just redirect all domain traffic through the worker's connection?
         let mut assignments = Vec::new();
+        let node_indices = nodes.iter().map(|&(ni, _)| ni).collect();
+        let label = self.placement_label(nodes.iter().map(|&(ni, _)| ni));
+        if let Err(e) = self.check_placement(label.iter().map(|(name, _)| &**name)) {
+            // recipes are checked before they are activated, so only a hand-built migration gets
+            // this far
+            panic!("{}", e);
+        }
+        let label = label.map(|(_, label)| label);
         let mut nodes = Some(
             nodes
                 .into_iter()
@@ -520,7 +1067,15 @@ impl ControllerInner {
                 .collect(),
         );
 
-        // TODO(malte): simple round-robin placement for the moment
+        // TODO(malte): simple round-robin placement for the moment, among the workers that are
+        // not being decommissioned and that the domain's placement constraint allows. While
+        // domains are being moved onto a worker, every shard goes there if it can.
+        let allowed = |w: &Worker| {
+            w.accepts_domains() && label.as_ref().map_or(true, |l| w.labels.contains(l))
+        };
+        let preferred = self
+            .preferred_worker
+            .filter(|pw| self.workers.get(pw).map_or(false, |w| allowed(w)));
         let mut wi = self.workers.iter_mut();
 
         // Send `AssignDomain` to each shard of the given domain
@@ -542,7 +1097,7 @@ impl ControllerInner {
 
             let (identifier, w) = loop {
                 if let Some((i, w)) = wi.next() {
-                    if w.healthy {
+                    if preferred.map_or_else(|| allowed(w), |pw| *i == pw) {
                         break (*i, w);
                     }
                 } else {
@@ -558,11 +1113,10 @@ impl ControllerInner {
                 domain.shard.unwrap_or(0),
                 w.sender.peer_addr()
             );
-            let src = w.sender.local_addr().unwrap();
             w.sender
                 .send(CoordinationMessage {
                     epoch: self.epoch,
-                    source: src,
+                    source: self.worker_addr,
                     payload: CoordinationPayload::AssignDomain(domain),
                 })
                 .unwrap();
@@ -578,13 +1132,21 @@ impl ControllerInner {
         for r in replies {
             match r {
                 ControlReplyPacket::Booted(shard, addr) => {
-                    self.channel_coordinator.insert_remote((idx, shard), addr);
-                    announce.push(DomainDescriptor::new(idx, shard, addr));
+                    let tls = self.workers[&assignments[shard]].tls;
+                    self.channel_coordinator
+                        .insert_remote((idx, shard), addr, tls);
+                    // a domain in this process is sent its packets in memory
+                    match local::connect(&addr) {
+                        Some(chan) => self.channel_coordinator.insert_local((idx, shard), chan),
+                        None => self.channel_coordinator.remove_local(&(idx, shard)),
+                    }
+                    announce.push(DomainDescriptor::new(idx, shard, addr, tls));
                     txs.insert(
                         shard,
                         self.channel_coordinator
                             .builder_for(&(idx, shard))
                             .unwrap()
+                            .prioritized()
                             .build_sync()
                             .unwrap(),
                     );
@@ -614,13 +1176,19 @@ impl ControllerInner {
                     .sender
                     .send(CoordinationMessage {
                         epoch: self.epoch,
-                        source: endpoint.sender.local_addr().unwrap(),
+                        source: self.worker_addr,
                         payload: CoordinationPayload::DomainBooted(dd),
                     })
                     .unwrap();
             }
         }
 
+        self.events.record(EventKind::DomainPlaced {
+            domain: idx,
+            workers: assignments.clone(),
+            nodes: node_indices,
+        });
+
         let shards = assignments
             .into_iter()
             .enumerate()
@@ -653,6 +1221,7 @@ impl ControllerInner {
         F: FnOnce(&mut Migration) -> T,
     {
         info!(self.log, "starting migration: new soup universe");
+        self.progress.start();
         let miglog = self.log.new(o!());
         let mut m = Migration {
             mainline: self,
@@ -669,12 +1238,16 @@ impl ControllerInner {
     }
 
     /// Perform a new query schema migration.
+    ///
+    /// Its progress is reported through `progress`, where the migration stays in its last phase
+    /// until whoever asked for it calls `Progress::end`.
     // crate viz for tests
     pub(crate) fn migrate<F, T>(&mut self, f: F) -> T
     where
         F: FnOnce(&mut Migration) -> T,
     {
         info!(self.log, "starting migration");
+        self.progress.start();
         let miglog = self.log.new(o!());
         let mut m = Migration {
             mainline: self,
@@ -690,6 +1263,84 @@ impl ControllerInner {
         r
     }
 
+    /// Perform a migration like `migrate`, but only commit it if `f` succeeds.
+    ///
+    /// Nothing reaches the domains until a migration is committed, so if `f` fails, taking the
+    /// nodes it added back out of the controller's copy of the graph leaves the dataflow as it was.
+    fn migrate_or_roll_back<F, T>(&mut self, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut Migration) -> Result<T, String>,
+    {
+        info!(self.log, "starting migration");
+        self.progress.start();
+        let ingredients = self.ingredients.clone();
+        let ndomains = self.ndomains;
+        let remap = self.remap.clone();
+
+        let miglog = self.log.new(o!());
+        let mut m = Migration {
+            mainline: self,
+            added: Default::default(),
+            columns: Default::default(),
+            readers: Default::default(),
+            context: Default::default(),
+            start: time::Instant::now(),
+            log: miglog,
+        };
+        match f(&mut m) {
+            Ok(r) => {
+                m.commit();
+                Ok(r)
+            }
+            Err(e) => {
+                warn!(self.log, "rolling back migration: {}", e);
+                self.ingredients = ingredients;
+                self.ndomains = ndomains;
+                self.remap = remap;
+                self.progress.end(Err(e.clone()));
+                Err(e)
+            }
+        }
+    }
+
+    /// Work out what the migration that `f` sets up would do, without committing it.
+    ///
+    /// The controller's copy of the graph is put back the way it was afterwards, and nothing is
+    /// sent to the domains, so the dataflow is left as it is.
+    fn plan_migration<F>(&mut self, f: F) -> Result<MigrationPlan, String>
+    where
+        F: FnOnce(&mut Migration) -> Result<ActivationResult, String>,
+    {
+        info!(self.log, "starting migration plan");
+        let base_rows = self.base_rows();
+        let ingredients = self.ingredients.clone();
+        let ndomains = self.ndomains;
+        let remap = self.remap.clone();
+
+        let miglog = self.log.new(o!());
+        let mut m = Migration {
+            mainline: self,
+            added: Default::default(),
+            columns: Default::default(),
+            readers: Default::default(),
+            context: Default::default(),
+            start: time::Instant::now(),
+            log: miglog,
+        };
+        let r = f(&mut m).map(|ra| {
+            let mut plan = m.plan(&base_rows);
+            plan.new_nodes = ra.new_nodes;
+            plan.removed_leaves = ra.removed_leaves;
+            plan.shared = ra.reused_nodes;
+            plan
+        });
+
+        self.ingredients = ingredients;
+        self.ndomains = ndomains;
+        self.remap = remap;
+        r
+    }
+
     #[cfg(test)]
     pub(crate) fn graph(&self) -> &Graph {
         &self.ingredients
@@ -749,6 +1400,64 @@ impl ControllerInner {
         None
     }
 
+    /// Check that the principal whose `token` was given may change the tables and views in
+    /// `namespace`, if clients must authenticate.
+    fn authorize_namespace(
+        &self,
+        token: Option<&str>,
+        namespace: Option<&str>,
+    ) -> Result<(), String> {
+        match self.principals {
+            Some(ref principals) => principals
+                .authorize_namespace(token, namespace)
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    /// Obtain a `ViewBuilder` for the view `name` in `namespace`, if the principal whose `token`
+    /// was given may read from it.
+    fn authorized_view_builder(
+        &self,
+        name: &str,
+        token: Option<&str>,
+        namespace: Option<&str>,
+    ) -> Result<Option<ViewBuilder>, AuthError> {
+        if let Some(ref principals) = self.principals {
+            principals.authorize_view(token, namespace, name)?;
+        }
+        Ok(self.view_builder(&namespace::qualify(namespace, name)))
+    }
+
+    /// Obtain a `ViewBuilder` for the change feed of the base table `base` in `namespace`, if the
+    /// principal whose `token` was given may read from it.
+    fn authorized_change_feed(
+        &mut self,
+        base: &str,
+        token: Option<&str>,
+        namespace: Option<&str>,
+    ) -> Result<Option<ViewBuilder>, AuthError> {
+        if let Some(ref principals) = self.principals {
+            principals.authorize_view(token, namespace, base)?;
+        }
+        Ok(self.change_feed(&namespace::qualify(namespace, base)))
+    }
+
+    /// Obtain a `ViewBuilder` for a reader straight over the base table `base`, whose change log
+    /// clients follow the table's changes in. The reader is added on first use.
+    fn change_feed(&mut self, base: &str) -> Option<ViewBuilder> {
+        let ni = self.find_base(base).ok()?;
+        let name = format!("{}$changes", base);
+        if self.find_view_for(ni, &name).is_none() {
+            info!(self.log, "adding change feed"; "table" => base);
+            // a partial reader would only log the changes to the keys it happens to hold, while
+            // an ordered reader is always fully materialized
+            self.migrate(|mig| mig.maintain_ordered(name.clone(), ni, &[0]));
+        }
+        self.view_builder(&name)
+    }
+
     /// Obtain a `ViewBuilder` that can be sent to a client and then used to query a given
     /// (already maintained) reader node called `name`.
     fn view_builder(&self, name: &str) -> Option<ViewBuilder> {
@@ -774,16 +1483,59 @@ impl ControllerInner {
             let shards = (0..self.domains[&domain].shards())
                 .map(|i| self.read_addrs[&self.domains[&domain].assignment(i)])
                 .collect();
+            let shards_tls = (0..self.domains[&domain].shards())
+                .map(|i| {
+                    self.workers
+                        .get(&self.domains[&domain].assignment(i))
+                        .map_or(false, |w| w.tls)
+                })
+                .collect();
 
             ViewBuilder {
                 node: r,
                 columns,
                 schema,
+                key: self.ingredients[r]
+                    .with_reader(|r| r.key().map(Vec::from))
+                    .ok()
+                    .and_then(|k| k)
+                    .unwrap_or_default(),
                 shards,
+                shards_tls,
+                bases: self.bases_upstream_of(r),
+                ordered: self.ingredients[r]
+                    .with_reader(|r| r.is_ordered())
+                    .unwrap_or(false),
+                shard_ranges: match self.ingredients[r].sharded_by() {
+                    Sharding::ByRange(_, boundaries) => Some(boundaries),
+                    _ => None,
+                },
+                shard_hasher: self.domain_config.shard_hasher,
             }
         })
     }
 
+    /// The base tables whose writes flow into the given node.
+    fn bases_upstream_of(&self, ni: NodeIndex) -> Vec<NodeIndex> {
+        let mut bases = Vec::new();
+        let mut visited = HashSet::new();
+        let mut nodes = vec![ni];
+        while let Some(node) = nodes.pop() {
+            if !visited.insert(node) {
+                continue;
+            }
+            if self.ingredients[node].is_base() {
+                bases.push(node);
+                continue;
+            }
+            nodes.extend(
+                self.ingredients
+                    .neighbors_directed(node, petgraph::EdgeDirection::Incoming),
+            );
+        }
+        bases
+    }
+
     fn view_schema(&self, view_ni: NodeIndex) -> Option<Vec<ColumnSpecification>> {
         let n = &self.ingredients[view_ni];
         let schema: Vec<_> = (0..n.fields().len())
@@ -797,25 +1549,1469 @@ impl ControllerInner {
         }
     }
 
-    /// Obtain a TableBuild that can be used to construct a Table to perform writes and deletes
-    /// from the given named base node.
-    fn table_builder(&self, base: &str) -> Option<TableBuilder> {
+    /// The user-defined function `name` that the recipe registers, if any.
+    pub(super) fn udf(&self, name: &str) -> Option<Arc<Udf>> {
+        self.recipe.udf(name)
+    }
+
+    /// Find the base node with the given name.
+    fn find_base(&self, base: &str) -> Result<NodeIndex, String> {
         let ni = match self.recipe.node_addr_for(base) {
             Ok(ni) => ni,
-            Err(_) => *self.inputs().get(base)?,
+            Err(_) => *self
+                .inputs()
+                .get(base)
+                .ok_or_else(|| format!("no table named {}", base))?,
         };
-        let node = &self.ingredients[ni];
+        if !self.ingredients[ni].is_base() {
+            return Err(format!("{} is not a base table", base));
+        }
+        Ok(ni)
+    }
 
-        trace!(self.log, "creating table"; "for" => base);
+    /// Put the given base into bulk loading mode.
+    ///
+    /// Until the load is finished, rows loaded into the base go straight into its state without
+    /// being sent downstream, and normal writes to the base are held back.
+    fn start_bulk_load(&mut self, base: String) -> Result<(), String> {
+        let ni = self.find_base(&base)?;
+        if self.bulk_loads.contains_key(&ni) {
+            return Err(format!("{} is already being bulk loaded", base));
+        }
+        if self.resharding.is_some() {
+            return Err("cannot bulk load while the dataflow is being resharded".to_owned());
+        }
+        if self.transactions.values().any(|t| t.bases.contains(&ni)) {
+            return Err(format!("{} is being written to by a transaction", base));
+        }
 
-        let mut key = self.ingredients[ni]
+        let node = &self.ingredients[ni];
+        let domain = self.domains.get_mut(&node.domain()).unwrap();
+        domain
+            .send_to_healthy(
+                Box::new(Packet::StartBulkLoad {
+                    node: node.local_addr(),
+                }),
+                &self.workers,
+            )
+            .map_err(|e| e.to_string())?;
+        futures_executor::block_on(self.replies.wait_for_acks(&domain));
+
+        info!(self.log, "started bulk load"; "base" => base);
+        self.bulk_loads.insert(ni, 0);
+        Ok(())
+    }
+
+    /// Turn logging of every packet that the shards of a domain handle on or off.
+    fn set_packet_logging(&mut self, (domain, enabled): (DomainIndex, bool)) -> Result<(), String> {
+        let handle = self
+            .domains
+            .get_mut(&domain)
+            .ok_or_else(|| format!("no domain {}", domain.index()))?;
+        handle
+            .send_to_healthy(
+                Box::new(Packet::SetPacketLogging { enabled }),
+                &self.workers,
+            )
+            .map_err(|e| e.to_string())?;
+        futures_executor::block_on(self.replies.wait_for_acks(&handle));
+
+        info!(self.log, "set packet logging"; "domain" => domain.index(), "enabled" => enabled);
+        Ok(())
+    }
+
+    /// What every shard of a domain keeps track of about the updates flowing through its nodes.
+    ///
+    /// The request is sent on the domain's priority lane, so it is answered in between packets
+    /// even while the domain is busy.
+    fn domain_bookkeeping(
+        &mut self,
+        domain: DomainIndex,
+    ) -> Result<Vec<DomainBookkeeping>, String> {
+        let handle = self
+            .domains
+            .get_mut(&domain)
+            .ok_or_else(|| format!("no domain {}", domain.index()))?;
+        handle
+            .send_to_healthy(Box::new(Packet::GetBookkeeping), &self.workers)
+            .map_err(|e| e.to_string())?;
+        Ok(futures_executor::block_on(
+            self.replies.wait_for_bookkeeping(&handle),
+        ))
+    }
+
+    /// Change how long a node may take to process a packet, and a partial replay may take to
+    /// complete, before the domain logs a warning. Domains added later use the new thresholds too.
+    fn set_slow_thresholds(
+        &mut self,
+        (packet, replay): (Option<Duration>, Option<Duration>),
+    ) -> Result<(), String> {
+        self.domain_config.slow_packet_threshold = packet;
+        self.domain_config.slow_replay_threshold = replay;
+        for handle in self.domains.values_mut() {
+            handle
+                .send_to_healthy(
+                    Box::new(Packet::SetSlowThresholds { packet, replay }),
+                    &self.workers,
+                )
+                .map_err(|e| e.to_string())?;
+            futures_executor::block_on(self.replies.wait_for_acks(&handle));
+        }
+
+        info!(self.log, "set slow thresholds"; "packet" => ?packet, "replay" => ?replay);
+        Ok(())
+    }
+
+    /// Change the share of client writes that base domains stamp for measuring how long writes
+    /// take to reach readers. Domains added later use the new rate too.
+    fn set_propagation_sampling(&mut self, rate: f64) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(format!("sampling rate {} is not between 0 and 1", rate));
+        }
+
+        self.domain_config.propagation_sampling = rate;
+        for handle in self.domains.values_mut() {
+            handle
+                .send_to_healthy(
+                    Box::new(Packet::SetPropagationSampling { rate }),
+                    &self.workers,
+                )
+                .map_err(|e| e.to_string())?;
+            futures_executor::block_on(self.replies.wait_for_acks(&handle));
+        }
+
+        info!(self.log, "set propagation sampling"; "rate" => rate);
+        Ok(())
+    }
+
+    /// Change how fast domains replay the state of a node in full, including in replays that are
+    /// underway. Domains added later use the new rate too.
+    fn set_replay_rate(&mut self, rate: ReplayRate) -> Result<(), String> {
+        match rate {
+            ReplayRate::RecordsPerSecond(0) | ReplayRate::PacketsPerSecond(0) => {
+                return Err("the replay rate must be above zero".to_owned());
+            }
+            _ => {}
+        }
+
+        self.domain_config.replay_rate = rate;
+        for handle in self.domains.values_mut() {
+            handle
+                .send_to_healthy(Box::new(Packet::SetReplayRate { rate }), &self.workers)
+                .map_err(|e| e.to_string())?;
+            futures_executor::block_on(self.replies.wait_for_acks(&handle));
+        }
+
+        info!(self.log, "set replay rate"; "rate" => ?rate);
+        Ok(())
+    }
+
+    /// Load a frame of rows into a base that is being bulk loaded.
+    fn bulk_load(&mut self, (base, rows): (String, Vec<Vec<DataType>>)) -> Result<(), String> {
+        let ni = self.find_base(&base)?;
+        if !self.bulk_loads.contains_key(&ni) {
+            return Err(format!("{} is not being bulk loaded", base));
+        }
+
+        let node = &self.ingredients[ni];
+        let ncols = node.fields().len();
+        if let Some(row) = rows.iter().find(|row| row.len() != ncols) {
+            return Err(format!(
+                "{} has {} columns, but was given a row with {}",
+                base,
+                ncols,
+                row.len()
+            ));
+        }
+
+        let domain = self.domains.get_mut(&node.domain()).unwrap();
+        let nrows = rows.len();
+        let mut frames = vec![Vec::new(); domain.shards()];
+        if frames.len() == 1 {
+            frames[0] = rows;
+        } else {
+            match node.sharded_by() {
+                Sharding::ByColumn(col, _) => {
+                    for row in rows {
+                        let hasher = &self.domain_config.shard_hasher;
+                        frames[noria::shard_by(hasher, &row[col], domain.shards())].push(row);
+                    }
+                }
+                Sharding::ByRange(col, ref boundaries) => {
+                    for row in rows {
+                        frames[noria::shard_by_range(&row[col], boundaries)].push(row);
+                    }
+                }
+                s => unreachable!("sharded base is sharded by {:?}", s),
+            }
+        }
+
+        // every shard gets a frame, even if it is empty, so that every shard acks
+        for (shard, rows) in frames.into_iter().enumerate() {
+            let m = Box::new(Packet::BulkLoad {
+                node: node.local_addr(),
+                rows,
+            });
+            domain
+                .send_to_healthy_shard(shard, m, &self.workers)
+                .map_err(|e| e.to_string())?;
+        }
+        futures_executor::block_on(self.replies.wait_for_acks(&domain));
+
+        *self.bulk_loads.get_mut(&ni).unwrap() += nrows;
+        Ok(())
+    }
+
+    /// Finish bulk loading the given base, and return how many rows were loaded.
+    ///
+    /// All the loaded rows are sent downstream in one pass, after which any writes that were
+    /// held back are applied.
+    fn finish_bulk_load(&mut self, base: String) -> Result<usize, String> {
+        let ni = self.find_base(&base)?;
+        if !self.bulk_loads.contains_key(&ni) {
+            return Err(format!("{} is not being bulk loaded", base));
+        }
+
+        let node = &self.ingredients[ni];
+        let domain = self.domains.get_mut(&node.domain()).unwrap();
+        domain
+            .send_to_healthy(
+                Box::new(Packet::FinishBulkLoad {
+                    node: node.local_addr(),
+                }),
+                &self.workers,
+            )
+            .map_err(|e| e.to_string())?;
+        futures_executor::block_on(self.replies.wait_for_acks(&domain));
+
+        let loaded = self.bulk_loads.remove(&ni).unwrap();
+        info!(self.log, "finished bulk load"; "base" => base, "rows" => loaded);
+        Ok(loaded)
+    }
+
+    /// How many rows have been loaded into the given base, if it is being bulk loaded.
+    fn bulk_load_progress(&self, base: &str) -> Option<usize> {
+        let ni = self.find_base(base).ok()?;
+        self.bulk_loads.get(&ni).cloned()
+    }
+
+    /// Back up every base, along with the recipe history, into the directory `dir`.
+    ///
+    /// Writes to all the bases are paused before any of them is snapshotted, so the snapshots
+    /// reflect the same cut of the writes: a write that a client only issued once an earlier
+    /// write was acknowledged is never backed up without that earlier write.
+    fn backup(&mut self, dir: String) -> Result<BackupSummary, String> {
+        if self.resharding.is_some() {
+            return Err("cannot back up while the dataflow is being resharded".to_owned());
+        }
+        if !self.bulk_loads.is_empty() {
+            return Err("cannot back up while a table is being bulk loaded".to_owned());
+        }
+        self.expire_transactions();
+        if !self.transactions.is_empty() {
+            return Err("cannot back up while a transaction is open".to_owned());
+        }
+
+        let dir = Path::new(&dir);
+        backup::create_dir(dir)
+            .map_err(|e| format!("cannot back up into {}: {}", dir.display(), e))?;
+
+        let bases = self.inputs();
+        let mut paused = Vec::with_capacity(bases.len());
+        let snapshots = self.snapshot_bases(&bases, &mut paused);
+        // writes must resume even if the snapshot failed part of the way
+        let resumed = paused
+            .into_iter()
+            .map(|ni| self.pause_writes(ni, false))
+            .fold(Ok(()), Result::and);
+        let snapshots = snapshots.and_then(|s| resumed.map(|()| s))?;
+
+        let mut manifest = Manifest::new(self.recipe_history.clone());
+        let mut summary = BackupSummary {
+            recipe_version: self.recipe.version(),
+            ..Default::default()
+        };
+        let written = snapshots
+            .into_iter()
+            .enumerate()
+            .try_for_each(|(i, (name, shards))| -> io::Result<()> {
+                let mut table = TableBackup {
+                    name,
+                    shards: Vec::with_capacity(shards.len()),
+                };
+                for (shard, (label, rows)) in shards.into_iter().enumerate() {
+                    let file = format!("{}-{}.rows", i, shard);
+                    backup::write_rows(dir, &file, &rows)?;
+                    table.shards.push(ShardBackup {
+                        file,
+                        rows: rows.len(),
+                        label,
+                    });
+                }
+                summary.tables.insert(
+                    table.name.clone(),
+                    table.shards.iter().map(|s| s.rows).sum(),
+                );
+                manifest.tables.push(table);
+                Ok(())
+            })
+            .and_then(|()| backup::write_manifest(dir, &manifest));
+        if let Err(e) = written {
+            // don't leave a partial backup behind that looks like it may be restored
+            let _ = std::fs::remove_dir_all(dir);
+            return Err(format!(
+                "failed to write backup to {}: {}",
+                dir.display(),
+                e
+            ));
+        }
+
+        info!(self.log, "backed up deployment";
+              "dir" => %dir.display(),
+              "tables" => summary.tables.len(),
+              "rows" => summary.tables.values().sum::<usize>());
+        Ok(summary)
+    }
+
+    /// Pause the writes to each of `bases`, recording the ones that were paused in `paused`, and
+    /// then snapshot them all.
+    fn snapshot_bases(
+        &mut self,
+        bases: &BTreeMap<String, NodeIndex>,
+        paused: &mut Vec<NodeIndex>,
+    ) -> Result<Vec<(String, Vec<(u64, Vec<Vec<DataType>>)>)>, String> {
+        for &ni in bases.values() {
+            self.pause_writes(ni, true)?;
+            paused.push(ni);
+        }
+        bases
+            .iter()
+            .map(|(name, &ni)| Ok((name.clone(), self.snapshot_base(ni)?)))
+            .collect()
+    }
+
+    /// Hold back writes to the given base, or apply the writes that were held back.
+    fn pause_writes(&mut self, ni: NodeIndex, pause: bool) -> Result<(), String> {
+        let node = &self.ingredients[ni];
+        let m = if pause {
+            Packet::PauseWrites {
+                node: node.local_addr(),
+            }
+        } else {
+            Packet::ResumeWrites {
+                node: node.local_addr(),
+            }
+        };
+        let domain = self.domains.get_mut(&node.domain()).unwrap();
+        domain
+            .send_to_healthy(Box::new(m), &self.workers)
+            .map_err(|e| e.to_string())?;
+        futures_executor::block_on(self.replies.wait_for_acks(&domain));
+        Ok(())
+    }
+
+    /// The label of the latest batch of writes to each shard of the given base, and its rows.
+    fn snapshot_base(&mut self, ni: NodeIndex) -> Result<Vec<(u64, Vec<Vec<DataType>>)>, String> {
+        let node = &self.ingredients[ni];
+        let domain = self.domains.get_mut(&node.domain()).unwrap();
+        domain
+            .send_to_healthy(
+                Box::new(Packet::SnapshotBase {
+                    node: node.local_addr(),
+                }),
+                &self.workers,
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(futures_executor::block_on(
+            self.replies.wait_for_snapshot(&domain),
+        ))
+    }
+
+    /// Restore the backup in the directory `dir` into this deployment, which must have no tables.
+    ///
+    /// The backed up recipe is installed, and the rows of each table are then bulk loaded into
+    /// it, which fills the views as any bulk load does.
+    fn restore<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        dir: String,
+    ) -> Result<BackupSummary, String> {
+        if !self.inputs().is_empty() || !self.recipe_history.is_empty() {
+            return Err("can only restore a backup into a deployment without tables".to_owned());
+        }
+
+        let dir = Path::new(&dir);
+        let manifest = backup::read_manifest(dir)
+            .map_err(|e| format!("no backup to restore in {}: {}", dir.display(), e))?;
+
+        if let Some(latest) = manifest.recipe.last().map(|v| v.version) {
+            let changes: Vec<_> = Recipe::changes_at(&manifest.recipe, latest)?
+                .into_iter()
+                .cloned()
+                .collect();
+            for change in changes {
+                let r = self.recipe.clone().apply(&change, Some(self.log.clone()))?;
+                self.apply_recipe(r)?;
+                if self
+                    .record_recipe_change(authority, change.namespace, change.change)
+                    .is_err()
+                {
+                    return Err("failed to persist restored recipe".to_owned());
+                }
+            }
+        }
+
+        let mut summary = BackupSummary {
+            recipe_version: self.recipe.version(),
+            ..Default::default()
+        };
+        for table in manifest.tables {
+            let ni = self.find_base(&table.name)?;
+            self.start_bulk_load(table.name.clone())?;
+            for shard in &table.shards {
+                let mut rows = backup::read_rows(dir, shard)
+                    .map_err(|e| format!("failed to read backup of {}: {}", table.name, e))?
+                    .into_iter();
+                loop {
+                    let frame: Vec<_> = rows.by_ref().take(RESTORE_FRAME).collect();
+                    if frame.is_empty() {
+                        break;
+                    }
+                    self.bulk_load((table.name.clone(), frame))?;
+                }
+            }
+            self.finish_bulk_load(table.name.clone())?;
+
+            let expected: usize = table.shards.iter().map(|s| s.rows).sum();
+            let restored: usize = self
+                .snapshot_base(ni)?
+                .iter()
+                .map(|(_, rows)| rows.len())
+                .sum();
+            if restored != expected {
+                return Err(format!(
+                    "restored {} rows into {}, but the backup holds {}",
+                    restored, table.name, expected
+                ));
+            }
+            summary.tables.insert(table.name, restored);
+        }
+
+        info!(self.log, "restored deployment";
+              "dir" => %dir.display(),
+              "tables" => summary.tables.len(),
+              "rows" => summary.tables.values().sum::<usize>());
+        Ok(summary)
+    }
+
+    /// Begin a transaction that writes to the given bases, and return its commit timestamp.
+    ///
+    /// Every reader that receives the transaction's writes from more than one base shard is told
+    /// to hold them back until all of them have arrived, and every base is told to accept its
+    /// part, before the clients send any of them.
+    fn begin_transaction(&mut self, begin: BeginTransaction) -> Result<TransactionBegun, String> {
+        if begin.version != TRANSACTION_PROTOCOL {
+            return Ok(TransactionBegun::Rejected(format!(
+                "client speaks transaction protocol {}, but the controller speaks {}",
+                begin.version, TRANSACTION_PROTOCOL
+            )));
+        }
+        let mut bases = begin.bases;
+        bases.sort();
+        bases.dedup();
+        for &ni in &bases {
+            match self.ingredients.node_weight(ni) {
+                Some(n) if n.is_base() && !n.is_dropped() => {}
+                _ => {
+                    return Ok(TransactionBegun::Rejected(format!(
+                        "node {} is not a base table",
+                        ni.index()
+                    )))
+                }
+            }
+            if self.bulk_loads.contains_key(&ni) {
+                return Ok(TransactionBegun::Conflict(format!(
+                    "{} is being bulk loaded",
+                    self.ingredients[ni].name()
+                )));
+            }
+        }
+        if self.resharding.is_some() {
+            return Ok(TransactionBegun::Conflict(
+                "the dataflow is being resharded".to_owned(),
+            ));
+        }
+        // clients that go away without finishing their transactions must not be able to make the
+        // controller remember an unbounded number of them
+        self.expire_transactions();
+        if self.transactions.len() >= self.max_open_transactions {
+            return Ok(TransactionBegun::Conflict(format!(
+                "{} transactions are already open",
+                self.transactions.len()
+            )));
+        }
+
+        // how many base shards each reader hears about the transaction from
+        let mut parts: HashMap<NodeIndex, usize> = HashMap::new();
+        for &base in &bases {
+            let shards = self.domains[&self.ingredients[base].domain()].shards();
+            // a reader that is only reached through a sharder or a shard merger hears from every
+            // shard of the base, and one whose shards line up with the base's from one each
+            let mut reached = HashMap::new();
+            let mut stack = vec![(base, false)];
+            let mut visited = HashSet::new();
+            while let Some((ni, crossed)) = stack.pop() {
+                if !visited.insert((ni, crossed)) {
+                    continue;
+                }
+                let n = &self.ingredients[ni];
+                if n.is_reader() && !n.is_dropped() {
+                    let r = reached.entry(ni).or_insert(false);
+                    *r = *r || crossed;
+                }
+                let crossed = crossed || n.is_sharder() || n.is_shard_merger();
+                for child in self
+                    .ingredients
+                    .neighbors_directed(ni, petgraph::EdgeDirection::Outgoing)
+                {
+                    stack.push((child, crossed));
+                }
+            }
+            for (reader, crossed) in reached {
+                *parts.entry(reader).or_insert(0) += if crossed { shards } else { 1 };
+            }
+        }
+
+        let timestamp = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0)
+            .max(self.last_transaction + 1);
+
+        let mut readers = Vec::new();
+        let mut result = Ok(TransactionBegun::Begun(timestamp));
+        for (reader, parts) in parts {
+            if parts < 2 {
+                // a single batch is exposed all at once anyway
+                continue;
+            }
+            let n = &self.ingredients[reader];
+            let domain = n.domain();
+            let node = n.local_addr();
+            let dh = self.domains.get_mut(&domain).unwrap();
+            // the readers told so far are released when the client aborts, or the transaction
+            // expires
+            readers.push((domain, node));
+            if let Err(e) = dh.send_to_healthy(
+                Box::new(Packet::ExpectTransaction {
+                    node,
+                    txn: timestamp,
+                    parts,
+                }),
+                &self.workers,
+            ) {
+                result = Err(e.to_string());
+                break;
+            }
+            futures_executor::block_on(self.replies.wait_for_acks(&dh));
+        }
+        if result.is_ok() {
+            for &base in &bases {
+                let n = &self.ingredients[base];
+                let node = n.local_addr();
+                let dh = self.domains.get_mut(&n.domain()).unwrap();
+                if let Err(e) = dh.send_to_healthy(
+                    Box::new(Packet::OpenTransaction {
+                        node,
+                        txn: timestamp,
+                    }),
+                    &self.workers,
+                ) {
+                    result = Err(e.to_string());
+                    break;
+                }
+                futures_executor::block_on(self.replies.wait_for_acks(&dh));
+            }
+        }
+
+        self.last_transaction = timestamp;
+        self.transactions.insert(
+            timestamp,
+            OpenTransaction {
+                began: Instant::now(),
+                bases,
+                readers,
+                committed: false,
+            },
+        );
+        result
+    }
+
+    /// Commit a transaction whose part every base it writes to has checked and staged, and have
+    /// the bases apply their parts.
+    ///
+    /// The commit is persisted before any base is told of it, so that a base that cannot be told
+    /// now applies its part once it is recovered.
+    fn commit_transaction<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        txn: u64,
+    ) -> Result<TransactionDecided, String> {
+        match self.transactions.get(&txn) {
+            None => {
+                return Ok(TransactionDecided::Aborted(
+                    "the transaction was aborted or has expired".to_owned(),
+                ))
+            }
+            Some(t) if t.committed => return Ok(TransactionDecided::Committed),
+            Some(_) => {}
+        }
+        if let Err(e) = self.persist_commit(authority, txn) {
+            if let Err(e) = self.abort_transaction(txn) {
+                crit!(self.log, "failed to abort transaction: {}", e; "timestamp" => txn);
+            }
+            return Ok(TransactionDecided::Aborted(e));
+        }
+
+        let t = self.transactions.get_mut(&txn).unwrap();
+        t.committed = true;
+        let bases = t.bases.clone();
+        let mut applied = true;
+        for base in bases {
+            let n = &self.ingredients[base];
+            let node = n.local_addr();
+            let dh = match self.domains.get_mut(&n.domain()) {
+                Some(dh) => dh,
+                None => continue,
+            };
+            if let Err(e) = dh.send_to_healthy(
+                Box::new(Packet::DecideTransaction {
+                    node,
+                    txn,
+                    commit: true,
+                }),
+                &self.workers,
+            ) {
+                // the base applies its part once it is recovered
+                warn!(self.log, "failed to commit part of transaction: {}", e;
+                      "timestamp" => txn, "node" => base.index());
+                applied = false;
+                continue;
+            }
+            futures_executor::block_on(self.replies.wait_for_acks(&dh));
+        }
+        if applied {
+            self.transactions.remove(&txn);
+            self.committed_transactions.remove(&txn);
+        }
+        Ok(TransactionDecided::Committed)
+    }
+
+    /// Have the bases a transaction writes to drop the parts they staged, and the readers that
+    /// hold back its writes stop waiting for them.
+    ///
+    /// A transaction that has been committed is left alone.
+    fn abort_transaction(&mut self, txn: u64) -> Result<(), String> {
+        match self.transactions.get(&txn) {
+            Some(t) if !t.committed => {}
+            _ => return Ok(()),
+        }
+        let t = self.transactions.remove(&txn).unwrap();
+
+        // a base that cannot be told drops its part once it is recovered, so the rest are still
+        // told
+        let mut result = Ok(());
+        for base in t.bases {
+            let n = &self.ingredients[base];
+            let node = n.local_addr();
+            let dh = match self.domains.get_mut(&n.domain()) {
+                Some(dh) => dh,
+                None => continue,
+            };
+            match dh.send_to_healthy(
+                Box::new(Packet::DecideTransaction {
+                    node,
+                    txn,
+                    commit: false,
+                }),
+                &self.workers,
+            ) {
+                Ok(()) => futures_executor::block_on(self.replies.wait_for_acks(&dh)),
+                Err(e) => result = Err(e.to_string()),
+            }
+        }
+        for (domain, node) in t.readers {
+            let dh = match self.domains.get_mut(&domain) {
+                Some(dh) => dh,
+                None => continue,
+            };
+            match dh.send_to_healthy(
+                Box::new(Packet::ExpectTransaction {
+                    node,
+                    txn,
+                    parts: 0,
+                }),
+                &self.workers,
+            ) {
+                Ok(()) => futures_executor::block_on(self.replies.wait_for_acks(&dh)),
+                Err(e) => result = Err(e.to_string()),
+            }
+        }
+        result
+    }
+
+    /// Decide the parts of transactions that bases staged before their domains were lost, once
+    /// the domains have been recovered.
+    ///
+    /// Every transaction that has not been committed must have been aborted before, so that
+    /// nothing else can have staged parts.
+    fn recover_transactions(&mut self) {
+        let mut committed: Vec<_> = self.committed_transactions.iter().cloned().collect();
+        committed.extend(
+            self.transactions
+                .iter()
+                .filter(|&(_, t)| t.committed)
+                .map(|(&txn, _)| txn),
+        );
+        committed.sort();
+        committed.dedup();
+
+        let bases: Vec<_> = self
+            .ingredients
+            .node_indices()
+            .filter(|&ni| {
+                let n = &self.ingredients[ni];
+                n.is_base() && !n.is_dropped()
+            })
+            .collect();
+        let mut recovered = true;
+        for base in bases {
+            let n = &self.ingredients[base];
+            let node = n.local_addr();
+            let dh = match self.domains.get_mut(&n.domain()) {
+                Some(dh) => dh,
+                None => continue,
+            };
+            match dh.send_to_healthy(
+                Box::new(Packet::RecoverTransaction {
+                    node,
+                    committed: committed.clone(),
+                }),
+                &self.workers,
+            ) {
+                Ok(()) => futures_executor::block_on(self.replies.wait_for_acks(&dh)),
+                Err(e) => {
+                    crit!(self.log, "failed to recover transactions: {}", e;
+                          "node" => base.index());
+                    recovered = false;
+                }
+            }
+        }
+        if recovered {
+            // every base has applied its part of these by now
+            self.transactions.retain(|_, t| !t.committed);
+            self.committed_transactions.clear();
+        }
+    }
+
+    /// Persist that transaction `txn` is committed, along with the other committed transactions
+    /// that some bases may not have applied their part of yet.
+    fn persist_commit<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        txn: u64,
+    ) -> Result<(), String> {
+        let mut committed = self.committed_transactions.clone();
+        committed.insert(txn);
+        match authority.read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+            None => unreachable!(),
+            Some(ref state) if state.epoch > self.epoch => Err(()),
+            Some(mut state) => {
+                state.committed_transactions = committed.clone();
+                Ok(state)
+            }
+        }) {
+            Ok(Ok(_)) => {
+                self.committed_transactions = committed;
+                Ok(())
+            }
+            _ => Err("failed to persist the commit of the transaction".to_owned()),
+        }
+    }
+
+    /// Abort the transactions whose clients have not finished them in a long while, so that a
+    /// client that went away does not keep readers from exposing new writes forever, or bases
+    /// from applying them.
+    fn expire_transactions(&mut self) {
+        let timeout = self.transaction_timeout;
+        let expired: Vec<_> = self
+            .transactions
+            .iter()
+            .filter(|&(_, t)| !t.committed)
+            .map(|(&txn, t)| (txn, t.began.elapsed()))
+            .filter(|&(_, open_for)| open_for > timeout)
+            .collect();
+        for (txn, open_for) in expired {
+            warn!(self.log, "aborting abandoned transaction"; "timestamp" => txn);
+            if let Err(e) = self.abort_transaction(txn) {
+                crit!(self.log, "failed to abort transaction: {}", e; "timestamp" => txn);
+            }
+            self.events.record(EventKind::TransactionExpired {
+                timestamp: txn,
+                open_for,
+            });
+        }
+    }
+
+    /// Move the boundaries between the shards of a range-sharded base.
+    ///
+    /// Rows that now belong to a different shard are retracted from their old shard and bulk
+    /// loaded into the new one, so they are briefly absent downstream, and writes to the base are
+    /// held back meanwhile. Table and view handles built before the re-split still route by the
+    /// old boundaries.
+    fn resplit(&mut self, (base, boundaries): (String, Vec<DataType>)) -> Result<(), String> {
+        let ni = self.find_base(&base)?;
+        let old = match self.ingredients[ni].sharded_by() {
+            Sharding::ByRange(_, old) => old,
+            _ => return Err(format!("{} is not range sharded", base)),
+        };
+        if boundaries.len() != old.len() {
+            return Err(format!(
+                "{} must be split at exactly {} boundaries",
+                base,
+                old.len()
+            ));
+        }
+        if !boundaries.windows(2).all(|w| w[0] < w[1]) {
+            return Err("range boundaries must be strictly increasing".to_owned());
+        }
+        if self.ingredients[ni].get_base().unwrap().key().is_none() {
+            return Err(format!(
+                "{} has no key, so its rows are not kept where they could be moved from",
+                base
+            ));
+        }
+        if self.bulk_loads.contains_key(&ni) {
+            return Err(format!("{} is being bulk loaded", base));
+        }
+        if self.transactions.values().any(|t| t.bases.contains(&ni)) {
+            return Err(format!("{} is being written to by a transaction", base));
+        }
+
+        // find everything downstream that is split along the same ranges
+        let mut split = Vec::new();
+        let mut dfs = petgraph::visit::Dfs::new(&self.ingredients, ni);
+        while let Some(n) = dfs.next(&self.ingredients) {
+            if self.ingredients[n]
+                .with_sharder(|s| s.ranges().is_some())
+                .unwrap_or(false)
+            {
+                return Err(format!(
+                    "a view over {} is re-sharded by its ranges, so it cannot be re-split",
+                    base
+                ));
+            }
+            match self.ingredients[n].sharded_by() {
+                Sharding::ByRange(col, ref b) if *b == old => split.push((n, col)),
+                _ => {}
+            }
+        }
+
+        self.start_bulk_load(base.clone())?;
+        let node = &self.ingredients[ni];
+        let domain = self.domains.get_mut(&node.domain()).unwrap();
+        domain
+            .send_to_healthy(
+                Box::new(Packet::Resplit {
+                    node: node.local_addr(),
+                    boundaries: boundaries.clone(),
+                }),
+                &self.workers,
+            )
+            .map_err(|e| e.to_string())?;
+        let moved = futures_executor::block_on(self.replies.wait_for_rows(&domain));
+
+        for (n, col) in split {
+            self.ingredients[n].shard_by(Sharding::ByRange(col, boundaries.clone()));
+        }
+        self.ingredients[ni]
+            .get_base_mut()
+            .unwrap()
+            .resplit(boundaries);
+
+        // the moved rows are routed to their new shards by the new boundaries
+        self.bulk_load((base.clone(), moved))?;
+        let moved = self.finish_bulk_load(base.clone())?;
+        info!(self.log, "re-split base"; "base" => base, "moved" => moved);
+        self.events
+            .record(EventKind::BaseResplit { base: ni, moved });
+        Ok(())
+    }
+
+    /// Start changing the number of shards that the dataflow is split into.
+    ///
+    /// Every node other than the bases is copied under the new number of shards, with a shuffle
+    /// after each base, and the copies are filled from the bases' state while the existing nodes
+    /// keep serving reads. Writes reach both the existing nodes and their copies until the change
+    /// is finished with `finish_reshard`, or undone with `abort_reshard`.
+    ///
+    /// Bases, and their state, keep the shards they have. Moving them would mean moving their
+    /// persisted rows between shards and re-routing every client's writes, so only nodes added
+    /// after the change are sharded the new way.
+    fn start_reshard(&mut self, shards: usize) -> Result<(), String> {
+        if self.resharding.is_some() {
+            return Err("the dataflow is already being resharded".to_owned());
+        }
+        if !self.bulk_loads.is_empty() {
+            return Err("cannot reshard while tables are being bulk loaded".to_owned());
+        }
+        if !self.transactions.is_empty() {
+            return Err("cannot reshard while a transaction is open".to_owned());
+        }
+        let sharding = if shards > 1 { Some(shards) } else { None };
+        if sharding == self.sharding {
+            return Err(format!("the dataflow already has {} shards", shards));
+        }
+        if sharding.is_none() {
+            // migrations only plan shard merges when sharding is on, and copies of nodes below
+            // sharded bases would need them
+            return Err("a sharded dataflow cannot be resharded into a single shard".to_owned());
+        }
+
+        let everything = self
+            .ingredients
+            .node_indices()
+            .filter(|&ni| ni != self.source && !self.ingredients[ni].is_dropped())
+            .collect();
+
+        let kept = nodes
+            .iter()
+            .filter(|&&ni| self.ingredients[ni].is_base())
+            .count();
+        info!(self.log, "starting resharding"; "shards" => shards, "bases_kept" => kept);
+        self.events.record(EventKind::ReshardStarted { shards });
+        let first_new = self.ingredients.node_count();
+        let old_sharding = mem::replace(&mut self.sharding, sharding);
+        let copies = self.copy_nodes(&everything);
+
+        self.resharding = Some(Resharding {
+            old_sharding,
+            first_new,
+            copies,
+        });
+        Ok(())
+    }
+
+    /// Copy the nodes in `copied` in a migration, with each copy reading from the copies of its
+    /// parents. `copied` must include everything downstream of the nodes in it, and no bases.
+    ///
+    /// Returns what each node is replaced by: its copy if it was copied, and itself otherwise.
+    /// Ingress, egress, and sharder nodes are not copied as such, and are replaced by whatever
+    /// replaces the node they forward.
+    fn copy_nodes(&mut self, copied: &HashSet<NodeIndex>) -> HashMap<NodeIndex, NodeIndex> {
+        // parents must be copied before their children
+        let mut nodes = Vec::new();
+        let mut topo = petgraph::visit::Topo::new(&self.ingredients);
+        while let Some(ni) = topo.next(&self.ingredients) {
+            if ni != self.source && !self.ingredients[ni].is_dropped() {
+                nodes.push(ni);
+            }
+        }
+
+        self.migrate(|mig| {
+            let mut copies = HashMap::new();
+            for ni in nodes {
+                let n = &mig.mainline.ingredients[ni];
+                let copy = if n.is_base() {
+                    ni
+                } else if n.is_reader() {
+                    if copied.contains(&ni) {
+                        let parent = copies[&n.with_reader(|r| r.is_for()).unwrap()];
+                        mig.add_reader_copy(ni, parent)
+                    } else {
+                        ni
+                    }
+                } else if n.is_internal() && !n.is_shard_merger() {
+                    if copied.contains(&ni) {
+                        mig.add_copy(ni, &copies)
+                    } else {
+                        ni
+                    }
+                } else {
+                    // ingress, egress, and sharder nodes, and the unions that merge shards, are
+                    // added for the copies wherever the new domains and sharding need them. they
+                    // stand in for the node they forward.
+                    let parent = mig
+                        .mainline
+                        .ingredients
+                        .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                        .next()
+                        .unwrap();
+                    copies[&parent]
+                };
+                copies.insert(ni, copy);
+            }
+            copies
+        })
+    }
+
+    /// Switch over to the copies made by `start_reshard`, and remove the nodes they replace.
+    ///
+    /// Views obtained from now on read from the copies. `View` handles obtained before are told
+    /// that the readers they point to were removed, and ask for the copies instead.
+    fn finish_reshard<A: Authority + 'static>(&mut self, authority: &Arc<A>) -> Result<(), String> {
+        let resharding = self
+            .resharding
+            .take()
+            .ok_or_else(|| "the dataflow is not being resharded".to_owned())?;
+
+        if authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.config.sharding = self.sharding;
+                    Ok(state)
+                }
+            })
+            .is_err()
+        {
+            self.resharding = Some(resharding);
+            return Err("Failed to persist the new sharding".to_owned());
+        }
+
+        // from here on, every query resolves to its copy
+        self.recipe.remap_nodes(&resharding.copies);
+        let replaced = resharding
+            .copies
+            .iter()
+            .filter(|&(old, copy)| old != copy)
+            .map(|(&old, _)| old)
+            .collect();
+        self.remove_subgraph(replaced)?;
+        info!(self.log, "finished resharding"; "shards" => self.sharding.unwrap_or(1));
+        self.events.record(EventKind::ReshardFinished {
+            shards: self.sharding.unwrap_or(1),
+        });
+        Ok(())
+    }
+
+    /// Remove the copies made by `start_reshard`, leaving the dataflow as it was.
+    fn abort_reshard(&mut self) -> Result<(), String> {
+        let resharding = self
+            .resharding
+            .take()
+            .ok_or_else(|| "the dataflow is not being resharded".to_owned())?;
+
+        self.sharding = resharding.old_sharding;
+        let added = (resharding.first_new..self.ingredients.node_count())
+            .map(NodeIndex::new)
+            .filter(|&ni| !self.ingredients[ni].is_dropped())
+            .collect();
+        self.remove_subgraph(added)?;
+        info!(self.log, "aborted resharding");
+        self.events.record(EventKind::ReshardAborted);
+        Ok(())
+    }
+
+    /// Start moving the domains of `worker` to other workers, so that it can be stopped.
+    ///
+    /// The domains are moved one at a time by `continue_decommission`.
+    fn decommission<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        worker: WorkerIdentifier,
+    ) -> Result<(), String> {
+        let addr = match self.workers.get(&worker) {
+            None => return Err(format!("no worker {:?} has registered", worker)),
+            Some(w) if w.decommissioning => return Ok(()),
+            Some(w) => w.addr,
+        };
+        if !self
+            .workers
+            .iter()
+            .any(|(&wi, w)| wi != worker && w.accepts_domains())
+        {
+            return Err("there is no other worker to move the domains to".to_owned());
+        }
+
+        let mut decommissioning = self.decommissioning.clone();
+        decommissioning.insert(addr);
+        self.persist_decommissioning(authority, decommissioning)?;
+        self.workers.get_mut(&worker).unwrap().decommissioning = true;
+        info!(self.log, "decommissioning worker"; "worker" => ?worker);
+        self.events
+            .record(EventKind::DecommissionStarted { worker });
+        Ok(())
+    }
+
+    /// Stop moving the domains of `worker` elsewhere, and let new domains be placed on it again.
+    fn abort_decommission<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        worker: WorkerIdentifier,
+    ) -> Result<(), String> {
+        let addr = match self.workers.get(&worker) {
+            None => return Err(format!("no worker {:?} has registered", worker)),
+            Some(w) if !w.decommissioning => {
+                return Err(format!("worker {:?} is not being decommissioned", worker))
+            }
+            Some(w) => w.addr,
+        };
+
+        let mut decommissioning = self.decommissioning.clone();
+        decommissioning.remove(&addr);
+        self.persist_decommissioning(authority, decommissioning)?;
+        self.workers.get_mut(&worker).unwrap().decommissioning = false;
+        info!(self.log, "aborted decommissioning worker"; "worker" => ?worker);
+        self.events
+            .record(EventKind::DecommissionAborted { worker });
+        Ok(())
+    }
+
+    fn persist_decommissioning<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        decommissioning: HashSet<SocketAddr>,
+    ) -> Result<(), String> {
+        match authority.read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+            None => unreachable!(),
+            Some(ref state) if state.epoch > self.epoch => Err(()),
+            Some(mut state) => {
+                state.decommissioning = decommissioning.clone();
+                Ok(state)
+            }
+        }) {
+            Ok(Ok(_)) => {
+                self.decommissioning = decommissioning;
+                Ok(())
+            }
+            _ => Err("failed to persist the workers being decommissioned".to_owned()),
+        }
+    }
+
+    /// The domains that have a shard on a worker that is being decommissioned, which new nodes
+    /// must not be added to.
+    pub(in crate::controller) fn closed_domains(&self) -> HashSet<DomainIndex> {
+        self.domains
+            .values()
+            .filter(|dh| {
+                (0..dh.shards()).any(|shard| {
+                    self.workers
+                        .get(&dh.assignment(shard))
+                        .map_or(false, |w| w.decommissioning)
+                })
+            })
+            .map(|dh| dh.index())
+            .collect()
+    }
+
+    /// How far along moving the domains off `worker` is.
+    fn decommission_status(&self, worker: WorkerIdentifier) -> Result<DecommissionStatus, String> {
+        let w = self
+            .workers
+            .get(&worker)
+            .ok_or_else(|| format!("no worker {:?} has registered", worker))?;
+        let mut status = DecommissionStatus {
+            decommissioning: w.decommissioning,
+            ..Default::default()
+        };
+        for dh in self.domains.values() {
+            if !dh.assigned_to_worker(&worker) {
+                continue;
+            }
+            let tables: Vec<_> = self.domain_nodes[&dh.index()]
+                .iter()
+                .map(|&ni| &self.ingredients[ni])
+                .filter(|n| n.is_base() && !n.is_dropped())
+                .map(|n| n.name().to_owned())
+                .collect();
+            if tables.is_empty() {
+                status.remaining.push(dh.index());
+            } else {
+                status.tables.extend(tables);
+            }
+        }
+        status.remaining.sort();
+        status.tables.sort();
+        status.empty = status.remaining.is_empty() && status.tables.is_empty();
+        Ok(status)
+    }
+
+    /// Move one domain off a worker that is being decommissioned, if there is one that can be
+    /// moved, and the dataflow is not in the middle of another change.
+    ///
+    /// This is called as heartbeats come in, and moves at most one domain per heartbeat interval,
+    /// so that decommissioning can be called off between any two moves.
+    fn continue_decommission(&mut self) {
+        if self.last_decommission_step.elapsed() < self.heartbeat_every
+            || self.pending_recovery.is_some()
+            || self.resharding.is_some()
+            || !self.bulk_loads.is_empty()
+        {
+            return;
+        }
+        self.last_decommission_step = Instant::now();
+
+        let next = self
+            .workers
+            .iter()
+            .filter(|(_, w)| w.healthy && w.decommissioning)
+            .filter_map(|(&wi, _)| {
+                let status = self.decommission_status(wi).ok()?;
+                status.remaining.first().map(|&di| (wi, di))
+            })
+            .min_by_key(|&(_, di)| di);
+
+        if let Some((worker, domain)) = next {
+            info!(self.log, "moving domain off worker that is being decommissioned";
+                  "domain" => domain.index(),
+                  "worker" => ?worker);
+            let r = self.move_domain(domain);
+            self.progress.end(r.clone());
+            match r {
+                Ok(()) => self
+                    .events
+                    .record(EventKind::DomainMoved { domain, worker }),
+                Err(e) => {
+                    crit!(self.log, "failed to move domain: {}", e; "domain" => domain.index())
+                }
+            }
+        }
+    }
+
+    /// Replace `domain`, and everything downstream of it, with copies in new domains.
+    ///
+    /// The copies are placed on workers that take new domains, and are filled while the existing
+    /// nodes keep serving reads. Writes reach both until the existing nodes are removed. The
+    /// domain must not hold any bases.
+    fn move_domain(&mut self, domain: DomainIndex) -> Result<(), String> {
+        let mut moved = HashSet::new();
+        let mut frontier: Vec<_> = self.domain_nodes[&domain]
+            .iter()
+            .cloned()
+            .filter(|&ni| !self.ingredients[ni].is_dropped())
+            .collect();
+        while let Some(ni) = frontier.pop() {
+            if moved.insert(ni) {
+                frontier.extend(
+                    self.ingredients
+                        .neighbors_directed(ni, petgraph::EdgeDirection::Outgoing),
+                );
+            }
+        }
+
+        // the copies of readers that are constrained to a label need a worker with it
+        let views: Vec<_> = moved
+            .iter()
+            .map(|&ni| &self.ingredients[ni])
+            .filter(|n| n.is_reader())
+            .map(|n| n.name().to_owned())
+            .collect();
+        self.check_placement(views.iter().map(|v| &**v))?;
+
+        let copies: HashMap<_, _> = self
+            .copy_nodes(&moved)
+            .into_iter()
+            .filter(|(old, _)| moved.contains(old))
+            .collect();
+
+        // from here on, every query resolves to its copy
+        self.recipe.remap_nodes(&copies);
+        self.remove_subgraph(moved)
+    }
+
+    /// Move domains from the workers with the most load onto `worker`, one at a time, or for a
+    /// dry run only work out which would be moved.
+    ///
+    /// If a move fails, the domains moved before it stay on `worker`, and the rest stay put.
+    fn rebalance(
+        &mut self,
+        (worker, rebalance): (WorkerIdentifier, Rebalance),
+    ) -> Result<Vec<DomainMove>, String> {
+        match self.workers.get(&worker) {
+            None => return Err(format!("no worker {:?} has registered", worker)),
+            Some(w) if !w.accepts_domains() => {
+                return Err(format!("worker {:?} cannot take new domains", worker))
+            }
+            Some(_) => {}
+        }
+        if self.pending_recovery.is_some()
+            || self.resharding.is_some()
+            || !self.bulk_loads.is_empty()
+        {
+            return Err("cannot move domains while the dataflow is being changed".to_owned());
+        }
+
+        let planned = self.plan_rebalance(worker, rebalance.max_moves);
+        info!(self.log, "rebalancing domains onto worker";
+              "worker" => ?worker,
+              "moves" => planned.len(),
+              "dry_run" => rebalance.dry_run);
+        if rebalance.dry_run {
+            self.events.record(EventKind::Rebalanced {
+                worker,
+                moves: planned.clone(),
+                dry_run: true,
+            });
+            return Ok(planned);
+        }
+
+        let mut moves = Vec::new();
+        let mut result = Ok(());
+        for m in planned {
+            self.preferred_worker = Some(worker);
+            let r = self.move_domain(m.domain);
+            self.preferred_worker = None;
+            self.progress.end(r.clone());
+            if let Err(e) = r {
+                crit!(self.log, "failed to move domain: {}", e; "domain" => m.domain.index());
+                result = Err(e);
+                break;
+            }
+            moves.push(m);
+        }
+        self.events.record(EventKind::Rebalanced {
+            worker,
+            moves: moves.clone(),
+            dry_run: false,
+        });
+        result.map(|_| moves)
+    }
+
+    /// Pick up to `max_moves` domains to move onto `worker` so that the load on the workers that
+    /// take new domains evens out, largest first.
+    ///
+    /// The load of a domain shard is the mean of the share of all domain shards that it is, of the
+    /// state that they hold, and of the time that they have spent processing packets. Only domains
+    /// without bases, with nothing downstream of them in other domains, and with all their shards
+    /// on one other healthy worker are moved, so that each move is of just that domain.
+    fn plan_rebalance(&mut self, worker: WorkerIdentifier, max_moves: usize) -> Vec<DomainMove> {
+        let stats = self.get_statistics(None);
+        let nshards = stats.domains.len() as f64;
+        let total_mem: u64 = stats.domains.values().map(|(s, _)| s.mem_size).sum();
+        let total_ptime: u64 = stats.domains.values().map(|(s, _)| s.total_ptime).sum();
+        let share = |x: u64, total: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                x as f64 / total as f64
+            }
+        };
+
+        let mut worker_load: HashMap<WorkerIdentifier, f64> = self
+            .workers
+            .iter()
+            .filter(|(_, w)| w.accepts_domains())
+            .map(|(&wi, _)| (wi, 0.0))
+            .collect();
+        let mut domain_load: HashMap<DomainIndex, f64> = HashMap::new();
+        for (&(di, shard), (s, _)) in &stats.domains {
+            let load =
+                (1.0 / nshards + share(s.mem_size, total_mem) + share(s.total_ptime, total_ptime))
+                    / 3.0;
+            if let Some(l) = worker_load.get_mut(&self.domains[&di].assignment(shard)) {
+                *l += load;
+            }
+            *domain_load.entry(di).or_insert(0.0) += load;
+        }
+        let even = worker_load.values().sum::<f64>() / worker_load.len() as f64;
+
+        let mut candidates: Vec<_> = domain_load
+            .into_iter()
+            .filter_map(|(di, load)| Some((di, self.movable_from(di, worker)?, load)))
+            .collect();
+        candidates.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap().then(a.0.cmp(&b.0)));
+
+        let mut moves = Vec::new();
+        for (domain, from, load) in candidates {
+            if moves.len() == max_moves {
+                break;
+            }
+            // only take load off workers that have more than their share, and don't give the
+            // new worker more than its share
+            let from_load = worker_load.get(&from).cloned().unwrap_or(0.0);
+            if from_load <= even || worker_load[&worker] + load > even + 1e-9 {
+                continue;
+            }
+            worker_load.insert(from, from_load - load);
+            *worker_load.get_mut(&worker).unwrap() += load;
+            moves.push(DomainMove {
+                domain,
+                from,
+                to: worker,
+            });
+        }
+        moves
+    }
+
+    /// The worker that runs all of `domain`, if it could be moved onto `to` on its own.
+    fn movable_from(&self, domain: DomainIndex, to: WorkerIdentifier) -> Option<WorkerIdentifier> {
+        let dh = &self.domains[&domain];
+        let from = dh.assignment(0);
+        if from == to
+            || (1..dh.shards()).any(|shard| dh.assignment(shard) != from)
+            || !self.workers.get(&from).map_or(false, |w| w.healthy)
+        {
+            return None;
+        }
+
+        let nodes: Vec<_> = self.domain_nodes[&domain]
+            .iter()
+            .cloned()
+            .filter(|&ni| !self.ingredients[ni].is_dropped())
+            .collect();
+        let self_contained = nodes.iter().all(|&ni| {
+            !self.ingredients[ni].is_base()
+                && self
+                    .ingredients
+                    .neighbors_directed(ni, petgraph::EdgeDirection::Outgoing)
+                    .all(|child| self.ingredients[child].domain() == domain)
+        });
+        if nodes.is_empty() || !self_contained {
+            return None;
+        }
+
+        match self.placement_label(nodes) {
+            Some((_, label)) if !self.workers[&to].labels.contains(&label) => None,
+            _ => Some(from),
+        }
+    }
+
+    /// Obtain a `TableBuilder` for the table `base` in `namespace`, if the principal whose `token`
+    /// was given may write to it.
+    fn authorized_table_builder(
+        &self,
+        base: &str,
+        token: Option<&str>,
+        namespace: Option<&str>,
+    ) -> Result<Option<TableBuilder>, AuthError> {
+        if let Some(ref principals) = self.principals {
+            principals.authorize_table(token, namespace, base)?;
+        }
+        Ok(self.table_builder(&namespace::qualify(namespace, base)))
+    }
+
+    /// Obtain a TableBuild that can be used to construct a Table to perform writes and deletes
+    /// from the given named base node.
+    fn table_builder(&self, base: &str) -> Option<TableBuilder> {
+        let ni = match self.recipe.node_addr_for(base) {
+            Ok(ni) => ni,
+            Err(_) => *self.inputs().get(base)?,
+        };
+        let node = &self.ingredients[ni];
+
+        trace!(self.log, "creating table"; "for" => base);
+
+        let mut key = self.ingredients[ni]
             .suggest_indexes(ni)
             .remove(&ni)
             .unwrap_or_else(Vec::new);
         let mut is_primary = false;
         if key.is_empty() {
-            if let Sharding::ByColumn(col, _) = self.ingredients[ni].sharded_by() {
-                key = vec![col];
+            match self.ingredients[ni].sharded_by() {
+                Sharding::ByColumn(col, _) | Sharding::ByRange(col, _) => key = vec![col],
+                _ => {}
             }
         } else {
             is_primary = true;
@@ -828,6 +3024,9 @@ impl ControllerInner {
                     .unwrap()
             })
             .collect();
+        let txs_tls = (0..self.domains[&node.domain()].shards())
+            .map(|i| self.channel_coordinator.expects_tls(&(node.domain(), i)))
+            .collect();
 
         let base_operator = node
             .get_base()
@@ -850,27 +3049,44 @@ impl ControllerInner {
 
         Some(TableBuilder {
             txs,
+            txs_tls,
             ni: node.global_addr(),
             addr: node.local_addr(),
             key,
             key_is_primary: is_primary,
             dropped: base_operator.get_dropped(),
+            auto_increment: base_operator.is_auto_increment(),
+            shard_ranges: match node.sharded_by() {
+                Sharding::ByRange(_, boundaries) => Some(boundaries),
+                _ => None,
+            },
+            shard_hasher: self.domain_config.shard_hasher,
             table_name: node.name().to_owned(),
             columns,
             schema,
         })
     }
 
-    /// Get statistics about the time spent processing different parts of the graph.
-    fn get_statistics(&mut self) -> GraphStats {
+    /// Get statistics about the time spent processing different parts of the graph, or only the
+    /// part of it in `namespace`, if given.
+    fn get_statistics(&mut self, namespace: Option<&str>) -> GraphStats {
         trace!(self.log, "asked to get statistics");
+        let only = namespace.map(|ns| self.namespace_nodes(ns));
+        let only_domains: Option<HashSet<_>> = only.as_ref().map(|only| {
+            only.iter()
+                .map(|&ni| self.ingredients[ni].domain())
+                .collect()
+        });
         let log = &self.log;
         let workers = &self.workers;
         let replies = &mut self.replies;
+        let cores = &self.domain_cores;
+        let only_ref = only.as_ref();
         // TODO: request stats from domains in parallel.
         let domains = self
             .domains
             .iter_mut()
+            .filter(|&(di, _)| only_domains.as_ref().map_or(true, |only| only.contains(di)))
             .flat_map(|(&di, s)| {
                 trace!(log, "requesting stats from domain"; "di" => di.index());
                 s.send_to_healthy(Box::new(Packet::GetStatistics), workers)
@@ -878,11 +3094,155 @@ impl ControllerInner {
                 futures_executor::block_on(replies.wait_for_statistics(&s))
                     .into_iter()
                     .enumerate()
-                    .map(move |(i, s)| ((di, i), s))
+                    .map(move |(i, (mut domain, mut nodes))| {
+                        // only the worker running the domain knows where its thread ended up
+                        domain.cores = cores.get(&(di, i)).cloned();
+                        if let Some(only) = only_ref {
+                            nodes.retain(|ni, _| only.contains(ni));
+                        }
+                        ((di, i), (domain, nodes))
+                    })
+            })
+            .collect::<HashMap<_, _>>();
+
+        // sum each reader's state over its shards, which all share the reader's node index
+        let views = self
+            .ingredients
+            .externals(petgraph::EdgeDirection::Outgoing)
+            .filter(|&n| self.ingredients[n].is_reader())
+            .filter_map(|n| {
+                let name = self.ingredients[n].name();
+                let name = match only {
+                    None => name,
+                    Some(ref only) if only.contains(&n) => {
+                        namespace::strip(namespace, name).unwrap_or(name)
+                    }
+                    Some(_) => return None,
+                };
+                let mut stats = ViewStats::default();
+                for n in domains.values().filter_map(|(_, nodes)| nodes.get(&n)) {
+                    stats.mem_size += n.mem_size;
+                    stats.rows += n.rows;
+                    if let Some(ref propagation) = n.propagation {
+                        stats.propagation.merge(propagation);
+                    }
+                    // the view is only as fresh as its stalest shard
+                    if let Some(applied) = n.applied {
+                        stats.applied = Some(stats.applied.map_or(applied, |a| a.min(applied)));
+                    }
+                }
+                Some((name.to_owned(), stats))
+            })
+            .collect();
+
+        let mut workers: HashMap<String, MemoryBreakdown> = HashMap::new();
+        for (&(di, shard), (domain, _)) in &domains {
+            workers
+                .entry(self.domains[&di].assignment(shard).to_string())
+                .or_default()
+                .merge(&domain.memory);
+        }
+
+        GraphStats {
+            domains,
+            views,
+            workers,
+        }
+    }
+
+    /// The events every domain has recorded under the trace tag `tag`.
+    fn trace_events(&mut self, tag: u64) -> Vec<TraceEvent> {
+        let workers = &self.workers;
+        let replies = &mut self.replies;
+        self.domains
+            .values_mut()
+            .flat_map(|s| {
+                s.send_to_healthy(Box::new(Packet::GetTrace { tag }), workers)
+                    .unwrap();
+                futures_executor::block_on(replies.wait_for_trace(&s))
+            })
+            .collect()
+    }
+
+    /// At most `limit` entries of the event log, starting with the one numbered `cursor`.
+    fn events(&self, (cursor, limit): (u64, usize)) -> EventPage {
+        self.events.page(cursor, limit)
+    }
+
+    /// Put together the trace of the write traced under `tag`.
+    fn trace(&mut self, tag: u64) -> Trace {
+        trace!(self.log, "asked for trace"; "tag" => tag);
+        let mut events = self.trace_events(tag);
+
+        // if group commit merged the write with another traced write, the update they became
+        // carries the other write's tag.
+        let carriers: HashSet<_> = events
+            .iter()
+            .filter_map(|e| match e.event {
+                PacketEvent::Merged { into, .. } if into != tag => Some(into),
+                _ => None,
+            })
+            .collect();
+        for into in carriers {
+            events.extend(
+                self.trace_events(into)
+                    .into_iter()
+                    .filter(|e| match e.event {
+                        PacketEvent::Merged { .. } => false,
+                        _ => true,
+                    }),
+            );
+        }
+        events.sort_by_key(|e| e.time);
+
+        // the write is through once every shard of every reader below its bases has applied it
+        let reached: HashSet<_> = events
+            .iter()
+            .filter(|e| e.event == PacketEvent::Reached)
+            .map(|e| (e.node, e.shard))
+            .collect();
+        let bases: HashSet<_> = events
+            .iter()
+            .filter_map(|e| match e.event {
+                PacketEvent::Merged { .. } => Some(e.node),
+                _ => None,
             })
             .collect();
+        let mut complete = !bases.is_empty();
+        for base in bases {
+            let mut bfs = Bfs::new(&self.ingredients, base);
+            while let Some(ni) = bfs.next(&self.ingredients) {
+                let n = &self.ingredients[ni];
+                if !n.is_reader() || n.is_dropped() {
+                    continue;
+                }
+                let shards = self.domains[&n.domain()].shards();
+                if (0..shards).any(|shard| !reached.contains(&(ni, shard))) {
+                    complete = false;
+                }
+            }
+        }
+
+        Trace { events, complete }
+    }
 
-        GraphStats { domains }
+    /// The number of rows in each base's state, summed over its shards.
+    ///
+    /// Bases whose state is not materialized are left out, since their size is unknown.
+    pub(super) fn base_rows(&mut self) -> HashMap<NodeIndex, u64> {
+        let stats = self.get_statistics(None);
+        let mut rows = HashMap::new();
+        for (_, nodes) in stats.domains.values() {
+            for (&ni, s) in nodes {
+                if let MaterializationStatus::Not = s.materialized {
+                    continue;
+                }
+                if self.ingredients[ni].is_base() {
+                    *rows.entry(ni).or_insert(0) += s.rows;
+                }
+            }
+        }
+        rows
     }
 
     fn get_instances(&self) -> Vec<(WorkerIdentifier, bool, Duration)> {
@@ -971,7 +3331,16 @@ impl ControllerInner {
                 let rgb: Option<ViewBuilder> = self.view_builder(&g);
                 // TODO: using block_on here _only_ works because View::lookup just waits on a
                 // channel, which doesn't use anything except the pure executor
-                let mut view = rgb.map(|rgb| rgb.build(x.clone()).unwrap()).unwrap();
+                // TODO: the controller has no token of its own, so this fails if clients must
+                // authenticate
+                let tls = self.channel_coordinator.tls().cloned();
+                let scope = Scope::View {
+                    namespace: None,
+                    name: g.clone(),
+                };
+                let mut view = rgb
+                    .map(|rgb| rgb.build(x.clone(), tls, None, scope).unwrap())
+                    .unwrap();
                 let my_groups: Vec<DataType> = futures_executor::block_on(view.lookup(uid, true))
                     .unwrap()
                     .iter()
@@ -1007,7 +3376,17 @@ impl ControllerInner {
     }
 
     fn apply_recipe(&mut self, mut new: Recipe) -> Result<ActivationResult, String> {
-        let r = self.migrate(|mig| {
+        // fail before anything is added to the graph if a new table or view can't be placed
+        if let Err(e) = self.check_placement(new.added_names().iter().map(|n| &**n)) {
+            crit!(self.log, "failed to apply recipe: {}", e);
+            self.events
+                .record(EventKind::RecipeFailed { error: e.clone() });
+            self.recipe = new.abandon();
+            return Err(e);
+        }
+
+        let checkpoint = new.checkpoint();
+        let r = self.migrate_or_roll_back(|mig| {
             new.activate(mig)
                 .map_err(|e| format!("failed to activate recipe: {}", e))
         });
@@ -1054,13 +3433,26 @@ impl ControllerInner {
                     self.remove_nodes(vec![base].as_slice()).unwrap();
                 }
 
+                let mut added: Vec<_> = ra
+                    .new_nodes
+                    .iter()
+                    .map(|(name, &ni)| (name.clone(), ni))
+                    .collect();
+                added.sort();
+                self.events.record(EventKind::RecipeActivated {
+                    version: new.version(),
+                    added,
+                    removed: ra.removed_leaves.clone(),
+                });
                 self.recipe = new;
             }
             Err(ref e) => {
                 crit!(self.log, "failed to apply recipe: {}", e);
-                // TODO(malte): a little yucky, since we don't really need the blank recipe
-                let recipe = mem::replace(&mut self.recipe, Recipe::blank(None));
-                self.recipe = recipe.revert();
+                self.events
+                    .record(EventKind::RecipeFailed { error: e.clone() });
+                // none of the recipe's changes made it into the graph, so the incorporator goes
+                // back to what it knew before too
+                self.recipe = new.roll_back(checkpoint);
             }
         }
 
@@ -1070,29 +3462,27 @@ impl ControllerInner {
     fn extend_recipe<A: Authority + 'static>(
         &mut self,
         authority: &Arc<A>,
-        add_txt: String,
+        (add_txt, namespace, token): (String, Option<String>, Option<String>),
     ) -> Result<ActivationResult, String> {
+        self.authorize_namespace(token.as_deref(), namespace.as_deref())?;
+        if self.resharding.is_some() {
+            return Err(
+                "cannot change the recipe while the dataflow is being resharded".to_owned(),
+            );
+        }
         // needed because self.apply_recipe needs to mutate self.recipe, so can't have it borrowed
         let new = mem::replace(&mut self.recipe, Recipe::blank(None));
-        match new.extend(&add_txt) {
+        match new.extend_in(&add_txt, namespace.as_deref()) {
             Ok(new) => {
-                let activation_result = self.apply_recipe(new);
-                if authority
-                    .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
-                        None => unreachable!(),
-                        Some(ref state) if state.epoch > self.epoch => Err(()),
-                        Some(mut state) => {
-                            state.recipe_version = self.recipe.version();
-                            state.recipes.push(add_txt.clone());
-                            Ok(state)
-                        }
-                    })
+                let activation_result = self.apply_recipe(new)?;
+                if self
+                    .record_recipe_change(authority, namespace, RecipeChange::Extend(add_txt))
                     .is_err()
                 {
                     return Err("Failed to persist recipe extension".to_owned());
                 }
 
-                activation_result
+                Ok(activation_result)
             }
             Err((old, e)) => {
                 // need to restore the old recipe
@@ -1103,31 +3493,63 @@ impl ControllerInner {
         }
     }
 
+    /// Remove the view `name` in `namespace`, along with the dataflow nodes no other query needs.
+    fn remove_view<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (name, namespace, token): (String, Option<String>, Option<String>),
+    ) -> Result<ActivationResult, String> {
+        self.authorize_namespace(token.as_deref(), namespace.as_deref())?;
+        if self.resharding.is_some() {
+            return Err(
+                "cannot change the recipe while the dataflow is being resharded".to_owned(),
+            );
+        }
+        let old = mem::replace(&mut self.recipe, Recipe::blank(None));
+        match old.remove_view(&namespace::qualify(namespace.as_deref(), &name)) {
+            Ok(new) => {
+                let activation_result = self.apply_recipe(new)?;
+                if self
+                    .record_recipe_change(authority, namespace, RecipeChange::RemoveView(name))
+                    .is_err()
+                {
+                    return Err("Failed to persist view removal".to_owned());
+                }
+
+                Ok(activation_result)
+            }
+            Err((old, e)) => {
+                self.recipe = old;
+                Err(e)
+            }
+        }
+    }
+
+    /// Replace the tables and views in `namespace` with those in the recipe `r_txt`.
     fn install_recipe<A: Authority + 'static>(
         &mut self,
         authority: &Arc<A>,
-        r_txt: String,
+        (r_txt, namespace, token): (String, Option<String>, Option<String>),
     ) -> Result<ActivationResult, String> {
-        match Recipe::from_str(&r_txt, Some(self.log.clone())) {
+        self.authorize_namespace(token.as_deref(), namespace.as_deref())?;
+        if self.resharding.is_some() {
+            return Err(
+                "cannot change the recipe while the dataflow is being resharded".to_owned(),
+            );
+        }
+        let ns = namespace.as_deref();
+        match Recipe::from_str_in(&r_txt, ns, Some(self.log.clone())) {
             Ok(r) => {
                 let old = mem::replace(&mut self.recipe, Recipe::blank(None));
-                let new = old.replace(r).unwrap();
-                let activation_result = self.apply_recipe(new);
-                if authority
-                    .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
-                        None => unreachable!(),
-                        Some(ref state) if state.epoch > self.epoch => Err(()),
-                        Some(mut state) => {
-                            state.recipe_version = self.recipe.version();
-                            state.recipes = vec![r_txt.clone()];
-                            Ok(state)
-                        }
-                    })
+                let new = old.replace_in(r, ns).unwrap();
+                let activation_result = self.apply_recipe(new)?;
+                if self
+                    .record_recipe_change(authority, namespace, RecipeChange::Install(r_txt))
                     .is_err()
                 {
                     return Err("Failed to persist recipe installation".to_owned());
                 }
-                activation_result
+                Ok(activation_result)
             }
             Err(e) => {
                 crit!(self.log, "failed to parse recipe: {:?}", e);
@@ -1136,8 +3558,238 @@ impl ControllerInner {
         }
     }
 
-    fn graphviz(&self, detailed: bool) -> String {
-        graphviz(&self.ingredients, detailed, &self.materializations)
+    /// Roll the tables and views in `namespace` back to what they were at version `to` of the
+    /// recipe, provided that the recipe is still at version `from`.
+    fn rollback_recipe<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (from, to, namespace, token): (usize, usize, Option<String>, Option<String>),
+    ) -> Result<ActivationResult, String> {
+        self.authorize_namespace(token.as_deref(), namespace.as_deref())?;
+        if self.resharding.is_some() {
+            return Err(
+                "cannot change the recipe while the dataflow is being resharded".to_owned(),
+            );
+        }
+        if self.recipe.version() != from {
+            return Err(format!(
+                "conflict: the recipe is at version {}, not {}",
+                self.recipe.version(),
+                from
+            ));
+        }
+        let ns = namespace.as_deref();
+        let target = Recipe::at_version_in(&self.recipe_history, to, ns, Some(self.log.clone()))?;
+        let old = mem::replace(&mut self.recipe, Recipe::blank(None));
+        let new = old.replace_in(target, ns).unwrap();
+        let activation_result = self.apply_recipe(new)?;
+        if self
+            .record_recipe_change(authority, namespace, RecipeChange::Rollback(to))
+            .is_err()
+        {
+            return Err("Failed to persist recipe rollback".to_owned());
+        }
+        Ok(activation_result)
+    }
+
+    /// What installing the recipe `r_txt` into `namespace` would change, without installing it.
+    fn recipe_diff(
+        &self,
+        (r_txt, namespace): (String, Option<String>),
+    ) -> Result<RecipeDiff, String> {
+        let candidate = Recipe::from_str_in(&r_txt, namespace.as_deref(), None)?;
+        Ok(self.recipe.diff(&candidate, namespace.as_deref()))
+    }
+
+    /// What installing the recipe `r_txt` into `namespace` would do to the dataflow, without
+    /// installing it.
+    ///
+    /// The plan is made by activating a copy of the recipe, so the recipe is left as it is too.
+    fn plan_recipe(
+        &mut self,
+        (r_txt, namespace, token): (String, Option<String>, Option<String>),
+    ) -> Result<MigrationPlan, String> {
+        self.authorize_namespace(token.as_deref(), namespace.as_deref())?;
+        if self.resharding.is_some() {
+            return Err(
+                "cannot plan a recipe change while the dataflow is being resharded".to_owned(),
+            );
+        }
+        let ns = namespace.as_deref();
+        let candidate = Recipe::from_str_in(&r_txt, ns, Some(self.log.clone()))?;
+        let mut new = self.recipe.duplicate().replace_in(candidate, ns)?;
+        let version = self.recipe.version();
+        let mut plan = self.plan_migration(|mig| {
+            new.activate(mig)
+                .map_err(|e| format!("failed to activate recipe: {}", e))
+        })?;
+        plan.version = version;
+        Ok(plan)
+    }
+
+    /// Constrain the domains of the table or view `name` in `namespace` to workers with `label`,
+    /// or lift the constraint if `label` is `None`.
+    fn set_placement<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (name, label, namespace, token): (String, Option<String>, Option<String>, Option<String>),
+    ) -> Result<(), String> {
+        self.authorize_namespace(token.as_deref(), namespace.as_deref())?;
+        let name = namespace::qualify(namespace.as_deref(), &name);
+        let mut placement = self.placement.clone();
+        match label {
+            Some(label) => {
+                info!(self.log, "constraining placement"; "name" => &name, "label" => &label);
+                placement.insert(name, label);
+            }
+            None => {
+                placement.remove(&name);
+            }
+        }
+
+        match authority.read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+            None => unreachable!(),
+            Some(ref state) if state.epoch > self.epoch => Err(()),
+            Some(mut state) => {
+                state.placement = placement.clone();
+                Ok(state)
+            }
+        }) {
+            Ok(Ok(_)) => {
+                self.placement = placement;
+                Ok(())
+            }
+            _ => Err("failed to persist placement constraint".to_owned()),
+        }
+    }
+
+    /// The placement constraints on the tables and views in `namespace`, by their names within it.
+    fn placements(&self, namespace: Option<String>) -> HashMap<String, String> {
+        self.placement
+            .iter()
+            .filter_map(|(name, label)| {
+                namespace::strip(namespace.as_deref(), name)
+                    .map(|name| (name.to_owned(), label.clone()))
+            })
+            .collect()
+    }
+
+    /// The constrained table or view that the given nodes of a domain belong to, if any, and the
+    /// label of the workers that the domain must go on.
+    ///
+    /// Only bases and readers are constrained. Domain assignment never puts bases with different
+    /// constraints together, and readers are always in domains of their own.
+    fn placement_label<I>(&self, nodes: I) -> Option<(String, String)>
+    where
+        I: IntoIterator<Item = NodeIndex>,
+    {
+        nodes.into_iter().find_map(|ni| {
+            let n = &self.ingredients[ni];
+            if !n.is_base() && !n.is_reader() {
+                return None;
+            }
+            self.placement
+                .get(n.name())
+                .map(|label| (n.name().to_owned(), label.clone()))
+        })
+    }
+
+    /// Check that, for each table or view in `names` that is constrained to a label, a worker with
+    /// that label can take new domains.
+    fn check_placement<'a, I>(&self, names: I) -> Result<(), String>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        for name in names {
+            if let Some(label) = self.placement.get(name) {
+                if !self
+                    .workers
+                    .values()
+                    .any(|w| w.accepts_domains() && w.labels.contains(label))
+                {
+                    return Err(format!(
+                        "cannot place the domains of {}: no worker labeled {} can take them",
+                        name, label
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the change that led to the current recipe version, and the namespace it was made in, to
+    /// the history, and persist it.
+    fn record_recipe_change<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        namespace: Option<String>,
+        change: RecipeChange,
+    ) -> Result<(), ()> {
+        let entry = RecipeVersion {
+            version: self.recipe.version(),
+            change,
+            namespace,
+        };
+        match authority.read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+            None => unreachable!(),
+            Some(ref state) if state.epoch > self.epoch => Err(()),
+            Some(mut state) => {
+                state.recipe_version = entry.version;
+                state.recipe_history.push(entry.clone());
+                Ok(state)
+            }
+        }) {
+            Ok(Ok(_)) => {
+                self.recipe_history.push(entry);
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+
+    fn graphviz(&self, detailed: bool, namespace: Option<&str>) -> String {
+        let only = namespace.map(|ns| self.namespace_nodes(ns));
+        graphviz_of(
+            &self.ingredients,
+            detailed,
+            &self.materializations,
+            only.as_ref(),
+        )
+    }
+
+    /// The nodes that make up the tables and views in `namespace`. Namespaces share no nodes, so
+    /// these are the namespace's base tables and everything downstream of them.
+    fn namespace_nodes(&self, namespace: &str) -> HashSet<NodeIndex> {
+        let mut nodes = HashSet::new();
+        let mut frontier: Vec<_> = self
+            .inputs()
+            .into_iter()
+            .filter(|(name, _)| namespace::strip(Some(namespace), name).is_some())
+            .map(|(_, ni)| ni)
+            .collect();
+        while let Some(ni) = frontier.pop() {
+            if nodes.insert(ni) {
+                frontier.extend(
+                    self.ingredients
+                        .neighbors_directed(ni, petgraph::EdgeDirection::Outgoing),
+                );
+            }
+        }
+        nodes
+    }
+
+    fn annotated_graphviz(&mut self, stats: bool) -> String {
+        let stats = if stats {
+            Some(self.get_statistics(None))
+        } else {
+            None
+        };
+        dot::annotated_graphviz(
+            &self.ingredients,
+            &self.materializations,
+            &self.domains,
+            stats.as_ref(),
+        )
     }
 
     fn remove_leaf(&mut self, mut leaf: NodeIndex) -> Result<(), String> {
@@ -1198,6 +3850,7 @@ impl ControllerInner {
             0
         );
 
+        let mut detached = Vec::new();
         let mut nodes = vec![leaf];
         while let Some(node) = nodes.pop() {
             let mut parents = self
@@ -1207,6 +3860,9 @@ impl ControllerInner {
             while let Some(parent) = parents.next_node(&self.ingredients) {
                 let edge = self.ingredients.find_edge(parent, node).unwrap();
                 self.ingredients.remove_edge(edge);
+                if self.ingredients[parent].is_egress() {
+                    detached.push((parent, node));
+                }
 
                 if !self.ingredients[parent].is_source()
                     && !self.ingredients[parent].is_base()
@@ -1224,9 +3880,80 @@ impl ControllerInner {
             removals.push(node);
         }
 
+        self.detach_egresses(&detached, &removals)?;
+        self.remove_nodes(removals.as_slice())
+    }
+
+    /// Remove the given nodes, children before parents.
+    ///
+    /// A node that still has children that are not being removed is kept.
+    fn remove_subgraph(&mut self, nodes: HashSet<NodeIndex>) -> Result<(), String> {
+        let mut order = Vec::with_capacity(nodes.len());
+        let mut topo = petgraph::visit::Topo::new(&self.ingredients);
+        while let Some(node) = topo.next(&self.ingredients) {
+            if nodes.contains(&node) {
+                order.push(node);
+            }
+        }
+
+        let mut detached = Vec::new();
+        let mut removals = Vec::with_capacity(order.len());
+        for node in order.into_iter().rev() {
+            if self
+                .ingredients
+                .neighbors_directed(node, petgraph::EdgeDirection::Outgoing)
+                .count()
+                > 0
+            {
+                continue;
+            }
+
+            let mut parents = self
+                .ingredients
+                .neighbors_directed(node, petgraph::EdgeDirection::Incoming)
+                .detach();
+            while let Some(parent) = parents.next_node(&self.ingredients) {
+                let edge = self.ingredients.find_edge(parent, node).unwrap();
+                self.ingredients.remove_edge(edge);
+                if self.ingredients[parent].is_egress() {
+                    detached.push((parent, node));
+                }
+            }
+            removals.push(node);
+        }
+
+        self.detach_egresses(&detached, &removals)?;
         self.remove_nodes(removals.as_slice())
     }
 
+    /// Tell the egress nodes that stay around to stop sending to the ingress nodes that are about
+    /// to be removed, so that nothing is sent to a domain after it has been shut down.
+    fn detach_egresses(
+        &mut self,
+        detached: &[(NodeIndex, NodeIndex)],
+        removals: &[NodeIndex],
+    ) -> Result<(), String> {
+        for &(egress, ingress) in detached {
+            if removals.contains(&egress) {
+                continue;
+            }
+
+            let node = &self.ingredients[egress];
+            let domain = self.domains.get_mut(&node.domain()).unwrap();
+            domain
+                .send_to_healthy(
+                    Box::new(Packet::RemoveEgressTx {
+                        node: node.local_addr(),
+                        ingress,
+                    }),
+                    &self.workers,
+                )
+                .map_err(|e| e.to_string())?;
+            futures_executor::block_on(self.replies.wait_for_acks(&domain));
+        }
+        Ok(())
+    }
+
     fn remove_nodes(&mut self, removals: &[NodeIndex]) -> Result<(), String> {
         // Remove node from controller local state
         let mut domain_removals: HashMap<DomainIndex, Vec<LocalNodeIndex>> = HashMap::default();
@@ -1271,9 +3998,34 @@ impl ControllerInner {
             }
         }
 
+        self.free_empty_domains();
         Ok(())
     }
 
+    /// Shut down the domains whose nodes have all been removed.
+    fn free_empty_domains(&mut self) {
+        let ingredients = &self.ingredients;
+        let empty: Vec<_> = self
+            .domain_nodes
+            .iter()
+            .filter(|(_, nodes)| nodes.iter().all(|&ni| ingredients[ni].is_dropped()))
+            .map(|(&di, _)| di)
+            .collect();
+
+        for di in empty {
+            info!(self.log, "shutting down empty domain"; "domain" => di.index());
+            self.domain_nodes.remove(&di);
+            self.remap.remove(&di);
+            if let Some(mut domain) = self.domains.remove(&di) {
+                for shard in 0..domain.shards() {
+                    self.domain_cores.remove(&(di, shard));
+                }
+                // don't unwrap, because the domain may be on a worker that has failed
+                drop(domain.send_to_healthy(Box::new(Packet::Quit), &self.workers));
+            }
+        }
+    }
+
     fn get_failed_nodes(&self, lost_worker: &WorkerIdentifier) -> Vec<NodeIndex> {
         // Find nodes directly impacted by worker failure.
         let mut nodes: Vec<NodeIndex> = self.nodes_on_worker(Some(lost_worker));
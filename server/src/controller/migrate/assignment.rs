@@ -3,14 +3,24 @@
 use dataflow::prelude::*;
 use petgraph;
 use slog::Logger;
-
-pub fn assign(log: &Logger, graph: &mut Graph, topo_list: &[NodeIndex], ndomains: &mut usize) {
+use std::collections::{HashMap, HashSet};
+
+pub fn assign(
+    log: &Logger,
+    graph: &mut Graph,
+    topo_list: &[NodeIndex],
+    ndomains: &mut usize,
+    placement: &HashMap<String, String>,
+    closed: &HashSet<DomainIndex>,
+) {
     // we need to walk the data flow graph and assign domains to all new nodes.
     // we generally want as few domains as possible, but in *some* cases we must make new ones.
     // specifically:
     //
     //  - the child of a Sharder is always in a different domain from the sharder
     //  - shard merge nodes are never in the same domain as their sharded ancestors
+    //  - bases whose placement is constrained to different labels are never in the same domain
+    //  - nothing new joins the `closed` domains, which are on workers that are being emptied
 
     let mut next_domain = || {
         *ndomains += 1;
@@ -79,7 +89,11 @@ pub fn assign(log: &Logger, graph: &mut Graph, topo_list: &[NodeIndex], ndomains
                         let p = &graph[pni];
                         if p.is_source() || p.is_sharder() || p.is_shard_merger() {
                         } else if p.is_base() {
-                            if p.has_domain() {
+                            // a domain can only be placed on workers with one label
+                            if p.has_domain()
+                                && !closed.contains(&p.domain())
+                                && placement.get(p.name()) == placement.get(n.name())
+                            {
                                 friendly_base = Some(p);
                                 break 'search;
                             }
@@ -140,7 +154,7 @@ pub fn assign(log: &Logger, graph: &mut Graph, topo_list: &[NodeIndex], ndomains
                     // the key may move to a different column, so we can't actually check for
                     // ByColumn equality. this'll do for now.
                     assert_eq!(p.sharded_by().is_none(), n.sharded_by().is_none());
-                    if p.has_domain() {
+                    if p.has_domain() && !closed.contains(&p.domain()) {
                         assignment = Some(p.domain().index())
                     }
                 }
@@ -166,7 +180,7 @@ pub fn assign(log: &Logger, graph: &mut Graph, topo_list: &[NodeIndex], ndomains
                         .neighbors_directed(pni, petgraph::EdgeDirection::Outgoing)
                         .map(|ni| &graph[ni]);
                     for s in siblings {
-                        if !s.has_domain() {
+                        if !s.has_domain() || closed.contains(&s.domain()) {
                             continue;
                         }
                         if s.sharded_by().is_none() != n.sharded_by().is_none() {
@@ -6,6 +6,7 @@
 //! module).
 
 use crate::controller::domain_handle::DomainHandle;
+use crate::controller::migrate::progress::Progress;
 use crate::controller::{
     inner::{graphviz, DomainReplies},
     keys,
@@ -46,6 +47,22 @@ impl Default for FrontierStrategy {
     }
 }
 
+/// Whether `ni` receives its records from a sharder for which `f` is true.
+fn fed_by_sharder<F>(graph: &Graph, ni: NodeIndex, f: &F) -> bool
+where
+    F: Fn(&dataflow::node::special::Sharder) -> bool,
+{
+    graph
+        .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+        .any(|p| {
+            if graph[p].is_ingress() {
+                fed_by_sharder(graph, p, f)
+            } else {
+                graph[p].with_sharder(f).unwrap_or(false)
+            }
+        })
+}
+
 pub(in crate::controller) struct Materializations {
     log: Logger,
 
@@ -335,6 +352,25 @@ impl Materializations {
                 able = false;
             }
 
+            // replays through a salting sharder are salted by whichever keys are hot at the time,
+            // not by those that the node's records were salted by when they were first sent
+            if fed_by_sharder(graph, ni, &|s| s.is_salting()) {
+                warn!(self.log, "full because input salts hot keys"; "node" => ni.index());
+                able = false;
+            }
+
+            // every shard of a broadcast input is meant to hold all of its records
+            if fed_by_sharder(graph, ni, &|s| s.is_broadcast()) {
+                warn!(self.log, "full because input is broadcast"; "node" => ni.index());
+                able = false;
+            }
+
+            // a miss on a range can't be expressed as a replay of individual keys
+            if let Ok(true) = graph[ni].with_reader(|r| r.is_ordered()) {
+                warn!(self.log, "full because reader serves range lookups"; "node" => ni.index());
+                able = false;
+            }
+
             // we are already fully materialized, so can't be made partial
             if !new.contains(&ni)
                 && self.added.get(&ni).map(|i| i.len()).unwrap_or(0)
@@ -452,6 +488,14 @@ impl Materializations {
         assert!(replay_obligations.is_empty());
     }
 
+    /// The column sets that the state of the given node is indexed by.
+    pub(in crate::controller) fn indices_of(
+        &self,
+        index: NodeIndex,
+    ) -> impl Iterator<Item = &Vec<usize>> {
+        self.have.get(&index).into_iter().flatten()
+    }
+
     /// Retrieves the materialization status of a given node, or None
     /// if the node isn't materialized.
     pub(in crate::controller) fn get_status(
@@ -473,10 +517,35 @@ impl Materializations {
         }
     }
 
+    /// Decide on the materializations for the given new nodes without committing to them.
+    ///
+    /// Returns the indices that would be added to each node, and which nodes would be partial.
+    pub(super) fn plan(
+        &self,
+        graph: &Graph,
+        new: &HashSet<NodeIndex>,
+    ) -> (HashMap<NodeIndex, HashSet<Vec<usize>>>, HashSet<NodeIndex>) {
+        let mut planned = Materializations {
+            log: self.log.clone(),
+
+            have: self.have.clone(),
+            added: self.added.clone(),
+
+            partial: self.partial.clone(),
+            partial_enabled: self.partial_enabled,
+            frontier_strategy: self.frontier_strategy.clone(),
+
+            tag_generator: AtomicUsize::new(self.tag_generator.load(Ordering::SeqCst)),
+        };
+        planned.extend(graph, new);
+        (planned.added, planned.partial)
+    }
+
     /// Commit to all materialization decisions since the last time `commit` was called.
     ///
     /// This includes setting up replay paths, adding new indices to existing materializations, and
-    /// populating new materializations.
+    /// populating new materializations. As each new node is readied, the rows that filling it was
+    /// estimated to replay are counted towards the migration's `progress`.
     #[allow(clippy::cognitive_complexity)]
     pub(super) fn commit(
         &mut self,
@@ -485,6 +554,8 @@ impl Materializations {
         domains: &mut HashMap<DomainIndex, DomainHandle>,
         workers: &HashMap<WorkerIdentifier, Worker>,
         replies: &mut DomainReplies,
+        replay_estimates: &HashMap<NodeIndex, u64>,
+        progress: &Progress,
     ) {
         self.extend(graph, new);
 
@@ -639,9 +710,10 @@ impl Materializations {
                     .expect("shard mergers must have a parent");
                 let psharding = graph[parent].sharded_by();
 
-                if let Sharding::ByColumn(col, _) = psharding {
-                    // we want to resolve col all the way to its nearest materialized ancestor.
-                    // and then check whether any other cols of the parent alias that source column
+                if let Some(shard_cols) = psharding.columns() {
+                    // we want to resolve each sharding column all the way to its nearest
+                    // materialized ancestor, and then check whether any other cols of the parent
+                    // alias that source column
                     let columns: Vec<_> = (0..n.fields().len()).collect();
                     for path in keys::provenance_of(graph, parent, &columns[..], |_, _, _| None) {
                         let (mat_anc, cols) = path
@@ -652,26 +724,28 @@ impl Materializations {
                                 "since bases are materialized, \
                                  every path must eventually have a materialized node",
                             );
-                        let src = cols[col];
-                        if src.is_none() {
-                            continue;
-                        }
+                        for &col in shard_cols {
+                            let src = cols[col];
+                            if src.is_none() {
+                                continue;
+                            }
 
-                        if let Some((c, res)) = cols
-                            .iter()
-                            .enumerate()
-                            .find(|&(c, res)| c != col && res == &src)
-                        {
-                            // another column in the merger's parent resolved to the source column!
-                            //println!("{}", graphviz(graph, &self));
-                            crit!(self.log, "attempting to merge sharding by aliased column";
-                                      "parent" => mat_anc.index(),
-                                      "aliased" => res,
-                                      "sharded" => parent.index(),
-                                      "alias" => c,
-                                      "shard" => col,
-                            );
-                            unimplemented!();
+                            if let Some((c, res)) = cols
+                                .iter()
+                                .enumerate()
+                                .find(|&(c, res)| c != col && res == &src)
+                            {
+                                // another column in the merger's parent resolved to the source
+                                // column!
+                                crit!(self.log, "attempting to merge sharding by aliased column";
+                                          "parent" => mat_anc.index(),
+                                          "aliased" => res,
+                                          "sharded" => parent.index(),
+                                          "alias" => c,
+                                          "shard" => col,
+                                );
+                                unimplemented!();
+                            }
                         }
                     }
                 }
@@ -860,6 +934,7 @@ impl Materializations {
                 .unwrap();
             futures_executor::block_on(replies.wait_for_acks(&domain));
             trace!(self.log, "node ready"; "node" => ni.index());
+            progress.advance(replay_estimates.get(&ni).cloned().unwrap_or(0));
 
             if reconstructed {
                 info!(self.log, "reconstruction completed";
@@ -360,6 +360,9 @@ impl<'a> Plan<'a> {
                             let shards = src_sharding.shards().unwrap_or(1);
                             let lookup_key_to_shard = match src_sharding {
                                 Sharding::Random(..) => None,
+                                // every shard of a broadcast source holds all its records, so
+                                // asking our own shard is enough.
+                                Sharding::Broadcast(..) => None,
                                 Sharding::ByColumn(c, _) => {
                                     let lookup_key =
                                         nodes.iter().next().unwrap().1.as_ref().unwrap();
@@ -381,8 +384,15 @@ impl<'a> Plan<'a> {
                                         lookup_key.iter().position(|&kc| kc == c)
                                     }
                                 }
-                                s if s.is_none() => None,
-                                s => unreachable!("unhandled new sharding pattern {:?}", s),
+                                Sharding::ByColumns(..) | Sharding::ByRange(..) => {
+                                    // a compound sharding key can't be pinned to a single lookup
+                                    // key column, and a range-sharded source isn't found by
+                                    // hashing the key. if we share the source's sharding we still
+                                    // only ask our own shard; otherwise we ask all of them.
+                                    None
+                                }
+                                ref s if s.is_none() => None,
+                                ref s => unreachable!("unhandled new sharding pattern {:?}", s),
                             };
 
                             let selection = if let Some(i) = lookup_key_to_shard {
@@ -529,6 +539,7 @@ impl<'a> Plan<'a> {
                     InitialState::Global {
                         cols: self.graph[self.node].fields().len(),
                         key: Vec::from(r.key().unwrap()),
+                        ordered: r.is_ordered(),
                         gid: self.node,
                     }
                 }
@@ -21,9 +21,13 @@
 //! Beware, Here be dragons™
 
 use crate::controller::ControllerInner;
+use dataflow::ops::udf::Udf;
 use dataflow::prelude::*;
-use dataflow::{node, prelude::Packet};
+use dataflow::{node, prelude::Packet, EvictionPolicy};
+use nom_sql::OrderType;
+use noria::{MigrationPhase, MigrationPlan, PlannedMaterialization, PlannedNode};
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use std::time::Instant;
 
 use petgraph;
@@ -32,6 +36,7 @@ use slog;
 mod assignment;
 mod augmentation;
 pub(crate) mod materialization;
+pub(crate) mod progress;
 mod routing;
 mod sharding;
 
@@ -39,6 +44,7 @@ mod sharding;
 pub(super) enum ColumnChange {
     Add(String, DataType),
     Drop(usize),
+    Hide(usize, DataType),
 }
 
 /// A `Migration` encapsulates a number of changes to the Soup data flow graph.
@@ -73,7 +79,10 @@ impl<'a> Migration<'a> {
         FS: IntoIterator<Item = S2>,
         I: Into<NodeOperator>,
     {
-        let mut i = node::Node::new(name.to_string(), fields, i.into());
+        self.add_node(node::Node::new(name.to_string(), fields, i.into()))
+    }
+
+    fn add_node(&mut self, mut i: node::Node) -> NodeIndex {
         i.on_connected(&self.mainline.ingredients);
         let parents = i.ancestors();
         assert!(!parents.is_empty());
@@ -96,6 +105,29 @@ impl<'a> Migration<'a> {
         ni
     }
 
+    /// Add a copy of the existing internal node `n` whose parents are replaced by the nodes they
+    /// map to in `parents`.
+    pub(super) fn add_copy(
+        &mut self,
+        n: NodeIndex,
+        parents: &HashMap<NodeIndex, NodeIndex>,
+    ) -> NodeIndex {
+        let graph = &self.mainline.ingredients;
+        let remap = graph[n]
+            .ancestors()
+            .into_iter()
+            .map(|p| {
+                // the copy is committed again once it is placed, so the local index is only a
+                // placeholder until then
+                let mut ip = IndexPair::from(parents[&p]);
+                ip.set_local(graph[p].local_addr());
+                (p, ip)
+            })
+            .collect();
+        let copy = graph[n].copy_onto(&remap);
+        self.add_node(copy)
+    }
+
     /// Add the given `Base` to the Soup.
     ///
     /// The returned identifier can later be used to refer to the added ingredient.
@@ -153,6 +185,23 @@ impl<'a> Migration<'a> {
         }
     }
 
+    /// The node this migration added most recently, if it has added any.
+    pub(super) fn last_added(&self) -> Option<NodeIndex> {
+        self.added.iter().max().cloned()
+    }
+
+    /// Describe the node `ni` that this migration added, for error messages.
+    pub(super) fn describe_added(&self, ni: NodeIndex) -> String {
+        let n = &self.mainline.ingredients[ni];
+        format!("{} \"{}\" ({})", ni.index(), n.name(), n.description(true))
+    }
+
+    /// The user-defined function `name` that a `FUNCTION` statement in the recipe registers, if
+    /// any, for the operators this migration adds to call.
+    pub fn udf(&self, name: &str) -> Option<Arc<Udf>> {
+        self.mainline.udf(name)
+    }
+
     /// Returns the context of this migration
     pub(super) fn context(&self) -> &HashMap<String, DataType> {
         &self.context
@@ -174,6 +223,23 @@ impl<'a> Migration<'a> {
         (id, group)
     }
 
+    /// The columns of the existing node `ni` that its state is keyed on: those it is sharded by
+    /// and, if it is partially materialized, those of its indices.
+    ///
+    /// A query can only share the node if it has all of these columns.
+    pub(super) fn key_columns(&self, ni: NodeIndex) -> Vec<usize> {
+        let n = &self.mainline.ingredients[ni];
+        let mut cols: Vec<usize> = n.sharded_by().columns().unwrap_or(&[]).to_vec();
+        if let MaterializationStatus::Partial { .. } =
+            self.mainline.materializations.get_status(ni, n)
+        {
+            cols.extend(self.mainline.materializations.indices_of(ni).flatten());
+        }
+        cols.sort();
+        cols.dedup();
+        cols
+    }
+
     /// Add a new column to a base node.
     ///
     /// Note that a default value must be provided such that old writes can be converted into this
@@ -192,6 +258,20 @@ impl<'a> Migration<'a> {
         let base = &mut self.mainline.ingredients[node];
         assert!(base.is_base());
 
+        // existing rows have already been given the default of a live column, so we can't hand
+        // out a different default for it after the fact.
+        if let Some(existing) = base
+            .fields()
+            .iter()
+            .enumerate()
+            .position(|(i, f)| *f == field && !base.get_base().unwrap().is_dropped(i))
+        {
+            panic!(
+                "column {} ({}) already exists; changing its default is not supported",
+                field, existing
+            );
+        }
+
         // we need to tell the base about its new column and its default, so that old writes that
         // do not have it get the additional value added to them.
         let col_i1 = base.add_column(&field);
@@ -208,21 +288,143 @@ impl<'a> Migration<'a> {
     }
 
     /// Drop a column from a base node.
+    ///
+    /// The drop is refused if any view is keyed on the column or any operator computes on it.
+    /// Views that merely carry the column along show its default value from then on.
     // crate viz for tests
-    pub fn drop_column(&mut self, node: NodeIndex, column: usize) {
-        // not allowed to drop columns from new nodes
-        assert!(!self.added.contains(&node));
-
-        let base = &mut self.mainline.ingredients[node];
-        assert!(base.is_base());
+    pub fn drop_column(&mut self, node: NodeIndex, column: usize) -> Result<(), String> {
+        let readers = self.check_drop_column(node, column)?;
 
         // we need to tell the base about the dropped column, so that old writes that contain that
         // column will have it filled in with default values (this is done in Mutator).
         // we can't rely on DerefMut, since it disallows mutating Taken nodes
+        let base = &mut self.mainline.ingredients[node];
         base.get_base_mut().unwrap().drop_column(column);
+        let default = base.get_base().unwrap().get_dropped()[column].clone();
 
         // also eventually propagate to domain clone
         self.columns.push((node, ColumnChange::Drop(column)));
+
+        // rows that are already materialized in readers still hold the old value
+        for (reader, columns) in readers {
+            for column in columns {
+                self.columns
+                    .push((reader, ColumnChange::Hide(column, default.clone())));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether the given column can be dropped from a base node.
+    ///
+    /// On success, returns the existing readers that expose the column, along with the columns
+    /// of each reader that it ends up in. On failure, the error lists every dependent.
+    pub(crate) fn check_drop_column(
+        &self,
+        node: NodeIndex,
+        column: usize,
+    ) -> Result<Vec<(NodeIndex, Vec<usize>)>, String> {
+        // not allowed to drop columns from new nodes
+        assert!(!self.added.contains(&node));
+
+        let graph = &self.mainline.ingredients;
+        let base = &graph[node];
+        assert!(base.is_base());
+        if column >= base.fields().len() || base.get_base().unwrap().is_dropped(column) {
+            return Err(format!(
+                "column {} of {} does not exist or was already dropped",
+                column,
+                base.name()
+            ));
+        }
+
+        // columns added in this migration have not yet been pushed to the domains, so dropping
+        // them again would leave the domain's copy of the base with a column it never saw added.
+        let added = self
+            .columns
+            .iter()
+            .filter(|&&(ni, ref change)| ni == node && matches!(change, ColumnChange::Add(..)))
+            .count();
+        if column >= base.fields().len() - added {
+            return Err(format!(
+                "cannot drop column {} of {} in the same migration that added it",
+                column,
+                base.name()
+            ));
+        }
+
+        // follow the column through the graph, keeping track of which output columns of each
+        // node it ends up in.
+        let mut carried: HashMap<NodeIndex, Vec<usize>> = HashMap::new();
+        carried.insert(node, vec![column]);
+        let mut readers = Vec::new();
+        let mut dependents = Vec::new();
+        let mut topo = petgraph::visit::Topo::new(graph);
+        while let Some(ni) = topo.next(graph) {
+            let n = &graph[ni];
+            if ni == node || n.is_dropped() || self.added.contains(&ni) {
+                continue;
+            }
+            let parents: Vec<_> = graph
+                .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                .filter(|p| carried.contains_key(p))
+                .collect();
+            if parents.is_empty() {
+                continue;
+            }
+
+            if n.is_reader() {
+                let columns = &carried[&parents[0]];
+                let keyed = n
+                    .with_reader(|r| {
+                        r.key()
+                            .map(|key| key.iter().any(|c| columns.contains(c)))
+                            .unwrap_or(false)
+                    })
+                    .unwrap();
+                if keyed {
+                    dependents.push(format!("view {} is keyed on it", n.name()));
+                } else {
+                    readers.push((ni, columns.clone()));
+                }
+            } else if !n.is_internal() {
+                // ingress, egress, and sharder nodes forward rows unchanged
+                let columns = carried[&parents[0]].clone();
+                carried.insert(ni, columns);
+            } else {
+                if parents.iter().any(|p| {
+                    n.used_parent_columns(*p)
+                        .iter()
+                        .any(|c| carried[p].contains(c))
+                }) {
+                    dependents.push(format!("{} ({}) uses it", n.name(), n.description(false)));
+                }
+
+                let columns: Vec<_> = (0..n.fields().len())
+                    .filter(|&c| {
+                        n.parent_columns(c).into_iter().any(|(p, pc)| match pc {
+                            Some(pc) => carried.get(&p).map(|cs| cs.contains(&pc)) == Some(true),
+                            None => false,
+                        })
+                    })
+                    .collect();
+                if !columns.is_empty() {
+                    carried.insert(ni, columns);
+                }
+            }
+        }
+
+        if dependents.is_empty() {
+            Ok(readers)
+        } else {
+            Err(format!(
+                "cannot drop column {} ({}) of {}: {}",
+                column,
+                base.fields()[column],
+                base.name(),
+                dependents.join(", ")
+            ))
+        }
     }
 
     #[cfg(test)]
@@ -230,11 +432,19 @@ impl<'a> Migration<'a> {
         self.mainline.graph()
     }
 
+    #[cfg(test)]
+    pub(crate) fn domain_addr(&self, ni: NodeIndex, shard: usize) -> Option<std::net::SocketAddr> {
+        let domain = self.mainline.ingredients[ni].domain();
+        self.mainline.channel_coordinator.get_addr(&(domain, shard))
+    }
+
     fn ensure_reader_for(&mut self, n: NodeIndex, name: Option<String>) {
         use std::collections::hash_map::Entry;
         if let Entry::Vacant(e) = self.readers.entry(n) {
             // make a reader
-            let r = node::special::Reader::new(n);
+            let mut r = node::special::Reader::new(n);
+            r.set_eviction_policy(self.mainline.eviction_policy);
+            r.set_paths(paths_from_bases(&self.mainline.ingredients, n));
             let mut r = if let Some(name) = name {
                 self.mainline.ingredients[n].named_mirror(r, name)
             } else {
@@ -243,6 +453,16 @@ impl<'a> Migration<'a> {
             if r.name().starts_with("SHALLOW_") {
                 r.purge = true;
             }
+            // a reader straight over a base must not expose the stale values of dropped columns
+            if let Some(base) = self.mainline.ingredients[n].get_base() {
+                let dropped = base.get_dropped();
+                r.with_reader_mut(|r| {
+                    for (column, default) in dropped {
+                        r.hide_column(column, default);
+                    }
+                })
+                .unwrap();
+            }
             let r = self.mainline.ingredients.add_node(r);
             self.mainline.ingredients.add_edge(n, r, ());
             self.added.insert(r);
@@ -250,6 +470,21 @@ impl<'a> Migration<'a> {
         }
     }
 
+    /// Add a copy of the existing reader `r` that reads from `n` instead.
+    pub(super) fn add_reader_copy(&mut self, r: NodeIndex, n: NodeIndex) -> NodeIndex {
+        let old = &self.mainline.ingredients[r];
+        let mut reader = old.with_reader(|r| r.mirror_for(n)).unwrap();
+        reader.set_paths(paths_from_bases(&self.mainline.ingredients, n));
+        let mut copy = self.mainline.ingredients[n].named_mirror(reader, old.name().to_owned());
+        copy.purge = old.purge;
+
+        let ri = self.mainline.ingredients.add_node(copy);
+        self.mainline.ingredients.add_edge(n, ri, ());
+        self.added.insert(ri);
+        self.readers.insert(n, ri);
+        ri
+    }
+
     /// Set up the given node such that its output can be efficiently queried.
     ///
     /// To query into the maintained state, use `ControllerInner::get_getter`.
@@ -277,6 +512,41 @@ impl<'a> Migration<'a> {
             .unwrap();
     }
 
+    /// Set up the given node such that its output can be queried by ranges over the last column
+    /// in `key`.
+    ///
+    /// Ordered readers are always fully materialized.
+    pub fn maintain_ordered(&mut self, name: String, n: NodeIndex, key: &[usize]) {
+        self.maintain(name, n, key);
+
+        let ri = self.readers[&n];
+        self.mainline.ingredients[ri]
+            .with_reader_mut(|r| r.set_ordered())
+            .unwrap();
+    }
+
+    /// Have the reader for the given node return the rows for each key sorted by the given
+    /// columns, as for a query with an `ORDER BY` but no `LIMIT`.
+    ///
+    /// The node must already be maintained with [`Migration::maintain`].
+    pub fn maintain_row_order(&mut self, n: NodeIndex, order: Vec<(usize, OrderType)>) {
+        let ri = self.readers[&n];
+        self.mainline.ingredients[ri]
+            .with_reader_mut(|r| r.set_row_order(order))
+            .unwrap();
+    }
+
+    /// Have the reader for the given node evict keys according to `policy` when it is partially
+    /// materialized, instead of the controller's default policy.
+    ///
+    /// The node must already be maintained with [`Migration::maintain`].
+    pub fn set_eviction_policy(&mut self, n: NodeIndex, policy: EvictionPolicy) {
+        let ri = self.readers[&n];
+        self.mainline.ingredients[ri]
+            .with_reader_mut(|r| r.set_eviction_policy(policy))
+            .unwrap();
+    }
+
     /// Commit the changes introduced by this `Migration` to the master `Soup`.
     ///
     /// This will spin up an execution thread for each new thread domain, and hook those new
@@ -289,166 +559,15 @@ impl<'a> Migration<'a> {
         let log = self.log;
         let start = self.start;
         let mut mainline = self.mainline;
-        let mut new = self.added;
-        let mut topo = mainline.topo_order(&new);
+        let readers = self.readers.len() as u64;
+        mainline.progress.advance(self.added.len() as u64);
 
-        // Shard the graph as desired
-        let mut swapped0 = if let Some(shards) = mainline.sharding {
-            let (t, swapped) =
-                sharding::shard(&log, &mut mainline.ingredients, &mut new, &topo, shards);
-            topo = t;
+        mainline.progress.enter(MigrationPhase::DomainAssignment, 0);
+        let (new, changed_domains) = finalize(&log, mainline, self.added);
+        mainline.progress.advance(changed_domains.len() as u64);
 
-            swapped
-        } else {
-            HashMap::default()
-        };
-
-        // Assign domains
-        assignment::assign(
-            &log,
-            &mut mainline.ingredients,
-            &topo,
-            &mut mainline.ndomains,
-        );
-
-        // Set up ingress and egress nodes
-        let swapped1 = routing::add(
-            &log,
-            &mut mainline.ingredients,
-            mainline.source,
-            &mut new,
-            &topo,
-        );
-        topo = mainline.topo_order(&new);
-
-        // Merge the swap lists
-        for ((dst, src), instead) in swapped1 {
-            use std::collections::hash_map::Entry;
-            match swapped0.entry((dst, src)) {
-                Entry::Occupied(mut instead0) => {
-                    if &instead != instead0.get() {
-                        // This can happen if sharding decides to add a Sharder *under* a node,
-                        // and routing decides to add an ingress/egress pair between that node
-                        // and the Sharder. It's perfectly okay, but we should prefer the
-                        // "bottommost" swap to take place (i.e., the node that is *now*
-                        // closest to the dst node). This *should* be the sharding node, unless
-                        // routing added an ingress *under* the Sharder. We resolve the
-                        // collision by looking at which translation currently has an adge from
-                        // `src`, and then picking the *other*, since that must then be node
-                        // below.
-                        if mainline.ingredients.find_edge(src, instead).is_some() {
-                            // src -> instead -> instead0 -> [children]
-                            // from [children]'s perspective, we should use instead0 for from, so
-                            // we can just ignore the `instead` swap.
-                        } else {
-                            // src -> instead0 -> instead -> [children]
-                            // from [children]'s perspective, we should use instead for src, so we
-                            // need to prefer the `instead` swap.
-                            *instead0.get_mut() = instead;
-                        }
-                    }
-                }
-                Entry::Vacant(hole) => {
-                    hole.insert(instead);
-                }
-            }
-
-            // we may also already have swapped the parents of some node *to* `src`. in
-            // swapped0. we want to change that mapping as well, since lookups in swapped
-            // aren't recursive.
-            for (_, instead0) in swapped0.iter_mut() {
-                if *instead0 == src {
-                    *instead0 = instead;
-                }
-            }
-        }
-        let swapped = swapped0;
-        let mut sorted_new = new.iter().collect::<Vec<_>>();
-        sorted_new.sort();
-
-        // Find all nodes for domains that have changed
-        let changed_domains: HashSet<DomainIndex> = sorted_new
-            .iter()
-            .filter(|&&&ni| !mainline.ingredients[ni].is_dropped())
-            .map(|&&ni| mainline.ingredients[ni].domain())
-            .collect();
-
-        let mut domain_new_nodes = sorted_new
-            .iter()
-            .filter(|&&&ni| ni != mainline.source)
-            .filter(|&&&ni| !mainline.ingredients[ni].is_dropped())
-            .map(|&&ni| (mainline.ingredients[ni].domain(), ni))
-            .fold(HashMap::new(), |mut dns, (d, ni)| {
-                dns.entry(d).or_insert_with(Vec::new).push(ni);
-                dns
-            });
-
-        // Assign local addresses to all new nodes, and initialize them
-        for (domain, nodes) in &mut domain_new_nodes {
-            // Number of pre-existing nodes
-            let mut nnodes = mainline.remap.get(domain).map(HashMap::len).unwrap_or(0);
-
-            if nodes.is_empty() {
-                // Nothing to do here
-                continue;
-            }
-
-            let log = log.new(o!("domain" => domain.index()));
-
-            // Give local addresses to every (new) node
-            for &ni in nodes.iter() {
-                debug!(log,
-                       "assigning local index";
-                       "type" => format!("{:?}", mainline.ingredients[ni]),
-                       "node" => ni.index(),
-                       "local" => nnodes
-                );
-
-                let mut ip: IndexPair = ni.into();
-                ip.set_local(unsafe { LocalNodeIndex::make(nnodes as u32) });
-                mainline.ingredients[ni].set_finalized_addr(ip);
-                mainline
-                    .remap
-                    .entry(*domain)
-                    .or_insert_with(HashMap::new)
-                    .insert(ni, ip);
-                nnodes += 1;
-            }
-
-            // Initialize each new node
-            for &ni in nodes.iter() {
-                if mainline.ingredients[ni].is_internal() {
-                    // Figure out all the remappings that have happened
-                    // NOTE: this has to be *per node*, since a shared parent may be remapped
-                    // differently to different children (due to sharding for example). we just
-                    // allocate it once though.
-                    let mut remap = mainline.remap[domain].clone();
-
-                    // Parents in other domains have been swapped for ingress nodes.
-                    // Those ingress nodes' indices are now local.
-                    for (&(dst, src), &instead) in &swapped {
-                        if dst != ni {
-                            // ignore mappings for other nodes
-                            continue;
-                        }
-
-                        let old = remap.insert(src, mainline.remap[domain][&instead]);
-                        assert_eq!(old, None);
-                    }
-
-                    trace!(log, "initializing new node"; "node" => ni.index());
-                    mainline
-                        .ingredients
-                        .node_weight_mut(ni)
-                        .unwrap()
-                        .on_commit(&remap);
-                }
-            }
-        }
-
-        if let Some(shards) = mainline.sharding {
-            sharding::validate(&log, &mainline.ingredients, &topo, shards)
-        };
+        // the bases have to be measured before new domains that can't answer yet are added
+        let estimates = replay_estimates(mainline, &new);
 
         // at this point, we've hooked up the graph such that, for any given domain, the graph
         // looks like this:
@@ -496,6 +615,9 @@ impl<'a> Migration<'a> {
 
         // Boot up new domains (they'll ignore all updates for now)
         debug!(log, "booting new domains");
+        mainline
+            .progress
+            .enter(MigrationPhase::DomainBoot, changed_domains.len() as u64);
         for domain in changed_domains {
             if mainline.domains.contains_key(&domain) {
                 // this is not a new domain
@@ -510,11 +632,14 @@ impl<'a> Migration<'a> {
                 nodes,
             );
             mainline.domains.insert(domain, d);
+            mainline.progress.advance(1);
         }
 
         // Add any new nodes to existing domains (they'll also ignore all updates for now)
         debug!(log, "mutating existing domains");
+        let informed = uninformed_domain_nodes.len() as u64;
         augmentation::inform(&log, &mut mainline, uninformed_domain_nodes);
+        mainline.progress.advance(informed);
 
         // Tell all base nodes and base ingress children about newly added columns
         for (ni, change) in self.columns {
@@ -539,6 +664,21 @@ impl<'a> Migration<'a> {
             };
             inform.push(ni);
 
+            if let ColumnChange::Add(..) = change {
+                // readers that mirror the base's rows must also have their existing rows padded
+                let readers: Vec<_> = inform
+                    .iter()
+                    .flat_map(|&ni| {
+                        mainline
+                            .ingredients
+                            .neighbors_directed(ni, petgraph::EdgeDirection::Outgoing)
+                    })
+                    .filter(|&rni| mainline.ingredients[rni].is_reader())
+                    .filter(|rni| !new.contains(rni))
+                    .collect();
+                inform.extend(readers);
+            }
+
             for ni in inform {
                 let n = &mainline.ingredients[ni];
                 let m = match change.clone() {
@@ -551,6 +691,11 @@ impl<'a> Migration<'a> {
                         node: n.local_addr(),
                         column,
                     }),
+                    ColumnChange::Hide(column, default) => Box::new(Packet::HideReaderColumn {
+                        node: n.local_addr(),
+                        column,
+                        default,
+                    }),
                 };
 
                 let domain = mainline.domains.get_mut(&n.domain()).unwrap();
@@ -573,14 +718,389 @@ impl<'a> Migration<'a> {
 
         // And now, the last piece of the puzzle -- set up materializations
         info!(log, "initializing new materializations");
+        mainline
+            .progress
+            .enter(MigrationPhase::StateReplay, estimates.values().sum());
         mainline.materializations.commit(
             &mut mainline.ingredients,
             &new,
             &mut mainline.domains,
             &mainline.workers,
             &mut mainline.replies,
+            &estimates,
+            &mainline.progress,
         );
 
+        // the new readers are ready, but clients only find them once the request is answered
+        mainline
+            .progress
+            .enter(MigrationPhase::ReaderPublication, readers);
         warn!(log, "migration completed"; "ms" => start.elapsed().as_millis());
     }
+
+    /// Work out what committing the migration would do to the dataflow, without sending anything
+    /// to the domains.
+    ///
+    /// This still changes the controller's copy of the graph, its number of domains and its local
+    /// addresses, which the caller must put back afterwards. The rows that new materializations
+    /// would replay are estimated from `base_rows`, the number of rows in each base.
+    pub(super) fn plan(self, base_rows: &HashMap<NodeIndex, u64>) -> MigrationPlan {
+        info!(self.log, "planning migration"; "#nodes" => self.added.len());
+
+        let mainline = self.mainline;
+        let mut reused: Vec<_> = self
+            .added
+            .iter()
+            .flat_map(|&ni| {
+                mainline
+                    .ingredients
+                    .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+            })
+            .filter(|ni| *ni != mainline.source && !self.added.contains(ni))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        reused.sort();
+
+        let (new, _) = finalize(&self.log, mainline, self.added);
+        let (mut indices, partial) = mainline.materializations.plan(&mainline.ingredients, &new);
+
+        let graph = &mainline.ingredients;
+
+        let mut plan = MigrationPlan {
+            reused,
+            ..MigrationPlan::default()
+        };
+        for ni in mainline.topo_order(&new) {
+            let n = &graph[ni];
+            let mut keys: Vec<_> = if n.is_reader() {
+                n.with_reader(|r| r.key().map(Vec::from))
+                    .unwrap()
+                    .into_iter()
+                    .collect()
+            } else {
+                indices
+                    .remove(&ni)
+                    .map(|idx| idx.into_iter().collect())
+                    .unwrap_or_default()
+            };
+            keys.sort();
+
+            let materialization = if keys.is_empty() {
+                PlannedMaterialization::Not
+            } else if partial.contains(&ni) {
+                PlannedMaterialization::Partial
+            } else {
+                PlannedMaterialization::Full
+            };
+            let replay_rows = match materialization {
+                PlannedMaterialization::Full if !n.is_base() => replay_rows(graph, ni, base_rows),
+                _ => 0,
+            };
+            plan.replay_rows += replay_rows;
+
+            let sharding = n.sharded_by();
+            plan.added.push(PlannedNode {
+                node: ni,
+                name: n.name().to_owned(),
+                operator: format!("{:?}", n),
+                domain: n.domain().index(),
+                shards: sharding.shards(),
+                shard_key: sharding.columns().map(Vec::from),
+                materialization,
+                keys,
+                replay_rows,
+            });
+        }
+
+        // whatever is left are existing materializations
+        plan.reindexed = indices
+            .into_iter()
+            .map(|(ni, idx)| {
+                let mut idx: Vec<_> = idx.into_iter().collect();
+                idx.sort();
+                (ni, idx)
+            })
+            .collect();
+        plan.reindexed.sort();
+        plan
+    }
+}
+
+/// How many rows filling the full materialization of `ni` replays, given the number of rows in
+/// each base.
+fn replay_rows(graph: &Graph, ni: NodeIndex, base_rows: &HashMap<NodeIndex, u64>) -> u64 {
+    // full materializations are filled with everything in the bases they are computed from
+    let mut bases = HashSet::new();
+    let mut stack = vec![ni];
+    while let Some(ni) = stack.pop() {
+        if graph[ni].is_base() {
+            bases.insert(ni);
+        } else {
+            stack.extend(graph.neighbors_directed(ni, petgraph::EdgeDirection::Incoming));
+        }
+    }
+    bases.into_iter().filter_map(|b| base_rows.get(&b)).sum()
+}
+
+/// The number of paths through the graph from each base to `ni`, for the bases that have several.
+fn paths_from_bases(graph: &Graph, ni: NodeIndex) -> Vec<(NodeIndex, usize)> {
+    fn count(
+        graph: &Graph,
+        ni: NodeIndex,
+        counted: &mut HashMap<NodeIndex, HashMap<NodeIndex, usize>>,
+    ) -> HashMap<NodeIndex, usize> {
+        if let Some(paths) = counted.get(&ni) {
+            return paths.clone();
+        }
+        let mut paths = HashMap::new();
+        if graph[ni].is_base() {
+            paths.insert(ni, 1);
+        } else {
+            for parent in graph.neighbors_directed(ni, petgraph::EdgeDirection::Incoming) {
+                for (base, n) in count(graph, parent, counted) {
+                    *paths.entry(base).or_insert(0) += n;
+                }
+            }
+        }
+        counted.insert(ni, paths.clone());
+        paths
+    }
+
+    let mut paths: Vec<_> = count(graph, ni, &mut HashMap::new())
+        .into_iter()
+        .filter(|&(_, n)| n > 1)
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Estimate how many rows filling each new full materialization will replay.
+///
+/// This asks the domains for the sizes of the bases, which it only does if there is something to
+/// replay.
+fn replay_estimates(
+    mainline: &mut ControllerInner,
+    new: &HashSet<NodeIndex>,
+) -> HashMap<NodeIndex, u64> {
+    let (indices, partial) = mainline.materializations.plan(&mainline.ingredients, new);
+    let full: Vec<_> = new
+        .iter()
+        .cloned()
+        .filter(|ni| {
+            let n = &mainline.ingredients[*ni];
+            !n.is_base()
+                && !partial.contains(ni)
+                && (indices.contains_key(ni)
+                    || n.with_reader(|r| r.is_materialized()).unwrap_or(false))
+        })
+        .collect();
+    if full.is_empty() {
+        return HashMap::new();
+    }
+
+    let base_rows = mainline.base_rows();
+    full.into_iter()
+        .map(|ni| (ni, replay_rows(&mainline.ingredients, ni, &base_rows)))
+        .collect()
+}
+
+/// Shard the new nodes, assign them to domains, set up ingress and egress nodes for them, and
+/// give them local addresses.
+///
+/// This only changes the controller's view of the graph; nothing is sent to the domains. Returns
+/// the new nodes, now including any ingress, egress and sharder nodes, and the domains they are
+/// in.
+#[allow(clippy::cognitive_complexity)]
+fn finalize(
+    log: &slog::Logger,
+    mainline: &mut ControllerInner,
+    mut new: HashSet<NodeIndex>,
+) -> (HashSet<NodeIndex>, HashSet<DomainIndex>) {
+    let mut topo = mainline.topo_order(&new);
+
+    // Shard the graph as desired
+    let mut swapped0 = if let Some(shards) = mainline.sharding {
+        // joins copy inputs computed from small enough bases to every shard
+        let base_rows = if mainline.broadcast_threshold.is_some() {
+            mainline.base_rows()
+        } else {
+            HashMap::default()
+        };
+        let (t, swapped) = sharding::shard(
+            log,
+            &mut mainline.ingredients,
+            &mut new,
+            &topo,
+            shards,
+            mainline.hot_key_salting,
+            mainline.broadcast_threshold,
+            &base_rows,
+        );
+        topo = t;
+
+        // sharders pick shards by the deployment's hash
+        let hasher = mainline.domain_config.shard_hasher;
+        for &ni in &new {
+            if mainline.ingredients[ni].is_sharder() {
+                mainline.ingredients[ni].with_sharder_mut(|s| s.set_hasher(hasher));
+            }
+        }
+
+        swapped
+    } else {
+        HashMap::default()
+    };
+
+    // Assign domains
+    let closed = mainline.closed_domains();
+    assignment::assign(
+        log,
+        &mut mainline.ingredients,
+        &topo,
+        &mut mainline.ndomains,
+        &mainline.placement,
+        &closed,
+    );
+
+    // Set up ingress and egress nodes
+    let swapped1 = routing::add(
+        log,
+        &mut mainline.ingredients,
+        mainline.source,
+        &mut new,
+        &topo,
+    );
+    topo = mainline.topo_order(&new);
+
+    // Merge the swap lists
+    for ((dst, src), instead) in swapped1 {
+        use std::collections::hash_map::Entry;
+        match swapped0.entry((dst, src)) {
+            Entry::Occupied(mut instead0) => {
+                if &instead != instead0.get() {
+                    // This can happen if sharding decides to add a Sharder *under* a node,
+                    // and routing decides to add an ingress/egress pair between that node
+                    // and the Sharder. It's perfectly okay, but we should prefer the
+                    // "bottommost" swap to take place (i.e., the node that is *now*
+                    // closest to the dst node). This *should* be the sharding node, unless
+                    // routing added an ingress *under* the Sharder. We resolve the
+                    // collision by looking at which translation currently has an adge from
+                    // `src`, and then picking the *other*, since that must then be node
+                    // below.
+                    if mainline.ingredients.find_edge(src, instead).is_some() {
+                        // src -> instead -> instead0 -> [children]
+                        // from [children]'s perspective, we should use instead0 for from, so
+                        // we can just ignore the `instead` swap.
+                    } else {
+                        // src -> instead0 -> instead -> [children]
+                        // from [children]'s perspective, we should use instead for src, so we
+                        // need to prefer the `instead` swap.
+                        *instead0.get_mut() = instead;
+                    }
+                }
+            }
+            Entry::Vacant(hole) => {
+                hole.insert(instead);
+            }
+        }
+
+        // we may also already have swapped the parents of some node *to* `src`. in
+        // swapped0. we want to change that mapping as well, since lookups in swapped
+        // aren't recursive.
+        for (_, instead0) in swapped0.iter_mut() {
+            if *instead0 == src {
+                *instead0 = instead;
+            }
+        }
+    }
+    let swapped = swapped0;
+    let mut sorted_new = new.iter().collect::<Vec<_>>();
+    sorted_new.sort();
+
+    // Find all nodes for domains that have changed
+    let changed_domains: HashSet<DomainIndex> = sorted_new
+        .iter()
+        .filter(|&&&ni| !mainline.ingredients[ni].is_dropped())
+        .map(|&&ni| mainline.ingredients[ni].domain())
+        .collect();
+
+    let mut domain_new_nodes = sorted_new
+        .iter()
+        .filter(|&&&ni| ni != mainline.source)
+        .filter(|&&&ni| !mainline.ingredients[ni].is_dropped())
+        .map(|&&ni| (mainline.ingredients[ni].domain(), ni))
+        .fold(HashMap::new(), |mut dns, (d, ni)| {
+            dns.entry(d).or_insert_with(Vec::new).push(ni);
+            dns
+        });
+
+    // Assign local addresses to all new nodes, and initialize them
+    for (domain, nodes) in &mut domain_new_nodes {
+        // Number of pre-existing nodes
+        let mut nnodes = mainline.remap.get(domain).map(HashMap::len).unwrap_or(0);
+
+        if nodes.is_empty() {
+            // Nothing to do here
+            continue;
+        }
+
+        let log = log.new(o!("domain" => domain.index()));
+
+        // Give local addresses to every (new) node
+        for &ni in nodes.iter() {
+            debug!(log,
+                   "assigning local index";
+                   "type" => format!("{:?}", mainline.ingredients[ni]),
+                   "node" => ni.index(),
+                   "local" => nnodes
+            );
+
+            let mut ip: IndexPair = ni.into();
+            ip.set_local(unsafe { LocalNodeIndex::make(nnodes as u32) });
+            mainline.ingredients[ni].set_finalized_addr(ip);
+            mainline
+                .remap
+                .entry(*domain)
+                .or_insert_with(HashMap::new)
+                .insert(ni, ip);
+            nnodes += 1;
+        }
+
+        // Initialize each new node
+        for &ni in nodes.iter() {
+            if mainline.ingredients[ni].is_internal() {
+                // Figure out all the remappings that have happened
+                // NOTE: this has to be *per node*, since a shared parent may be remapped
+                // differently to different children (due to sharding for example). we just
+                // allocate it once though.
+                let mut remap = mainline.remap[domain].clone();
+
+                // Parents in other domains have been swapped for ingress nodes.
+                // Those ingress nodes' indices are now local.
+                for (&(dst, src), &instead) in &swapped {
+                    if dst != ni {
+                        // ignore mappings for other nodes
+                        continue;
+                    }
+
+                    let old = remap.insert(src, mainline.remap[domain][&instead]);
+                    assert_eq!(old, None);
+                }
+
+                trace!(log, "initializing new node"; "node" => ni.index());
+                mainline
+                    .ingredients
+                    .node_weight_mut(ni)
+                    .unwrap()
+                    .on_commit(&remap);
+            }
+        }
+    }
+
+    if let Some(shards) = mainline.sharding {
+        sharding::validate(log, &mainline.ingredients, &topo, shards)
+    };
+
+    (new, changed_domains)
 }
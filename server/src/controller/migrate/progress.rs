@@ -0,0 +1,163 @@
+use noria::{MigrationPhase, MigrationProgress, MigrationStatus, PhaseProgress};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+struct Phase {
+    phase: MigrationPhase,
+    started: Instant,
+    ended: Option<Instant>,
+    done: u64,
+    total: u64,
+}
+
+struct State {
+    id: u64,
+    started: Instant,
+    ended: Option<Instant>,
+    failure: Option<String>,
+    phases: Vec<Phase>,
+}
+
+/// Where the migration that the controller is running, or last ran, is at.
+///
+/// The controller handles one request at a time, and a migration keeps it busy until it is done.
+/// This is therefore shared with the handler of external requests, which answers polls for it
+/// without going through the controller.
+#[derive(Clone, Default)]
+pub(crate) struct Progress(Arc<Mutex<Option<State>>>);
+
+impl Progress {
+    /// Start keeping track of a new migration, which begins by constructing its part of the graph.
+    pub(crate) fn start(&self) {
+        let mut state = self.0.lock().unwrap();
+        let now = Instant::now();
+        *state = Some(State {
+            id: state.as_ref().map_or(0, |s| s.id + 1),
+            started: now,
+            ended: None,
+            failure: None,
+            phases: vec![Phase {
+                phase: MigrationPhase::GraphConstruction,
+                started: now,
+                ended: None,
+                done: 0,
+                total: 0,
+            }],
+        });
+    }
+
+    fn with_running<F: FnOnce(&mut State)>(&self, f: F) {
+        let mut state = self.0.lock().unwrap();
+        if let Some(ref mut state) = *state {
+            if state.ended.is_none() {
+                f(state);
+            }
+        }
+    }
+
+    /// Move the running migration on to `phase`, which is expected to do `total` units of work.
+    pub(crate) fn enter(&self, phase: MigrationPhase, total: u64) {
+        self.with_running(|state| {
+            let now = Instant::now();
+            if let Some(current) = state.phases.last_mut() {
+                current.ended = Some(now);
+            }
+            state.phases.push(Phase {
+                phase,
+                started: now,
+                ended: None,
+                done: 0,
+                total,
+            });
+        })
+    }
+
+    /// Record that the current phase of the running migration has done `n` more units of work.
+    ///
+    /// Totals are only estimates, so a phase that does more than expected raises its total.
+    pub(crate) fn advance(&self, n: u64) {
+        self.with_running(|state| {
+            if let Some(current) = state.phases.last_mut() {
+                current.done += n;
+                current.total = current.total.max(current.done);
+            }
+        })
+    }
+
+    /// End the running migration, successfully if `result` is `Ok`.
+    ///
+    /// Does nothing if no migration is running, so it can be called after any request.
+    pub(crate) fn end(&self, result: Result<(), String>) {
+        self.with_running(|state| {
+            let now = Instant::now();
+            state.ended = Some(now);
+            if let Some(current) = state.phases.last_mut() {
+                current.ended = Some(now);
+                if result.is_ok() {
+                    current.done = current.total;
+                }
+            }
+            state.failure = result.err();
+        })
+    }
+
+    /// Fail the running migration if the controller panics before `end` is called.
+    pub(crate) fn fail_on_panic(&self) -> FailOnPanic {
+        FailOnPanic(self.clone())
+    }
+
+    /// How far along the running or most recent migration is, if there has been one.
+    pub(crate) fn report(&self) -> Option<MigrationProgress> {
+        let state = self.0.lock().unwrap();
+        let state = state.as_ref()?;
+        let now = Instant::now();
+
+        let phases: Vec<_> = state
+            .phases
+            .iter()
+            .map(|p| PhaseProgress {
+                phase: p.phase,
+                elapsed: p.ended.unwrap_or(now) - p.started,
+                done: p.done,
+                total: p.total,
+            })
+            .collect();
+        let current = phases.last().unwrap();
+
+        let status = match (state.ended, &state.failure) {
+            (None, _) => MigrationStatus::Running(current.phase),
+            (Some(_), None) => MigrationStatus::Done,
+            (Some(_), Some(e)) => MigrationStatus::Failed(e.clone()),
+        };
+
+        // assume that the rest of the phase's work goes as fast as what it has done so far
+        let eta = if state.ended.is_none() && current.done != 0 && current.total > current.done {
+            let left = (current.total - current.done) as f64 / current.done as f64;
+            Some(current.elapsed.mul_f64(left))
+        } else {
+            None
+        };
+
+        Some(MigrationProgress {
+            id: state.id,
+            status,
+            elapsed: state.ended.unwrap_or(now) - state.started,
+            eta,
+            phases,
+        })
+    }
+}
+
+/// Fails the running migration if it is dropped during a panic.
+pub(crate) struct FailOnPanic(Progress);
+
+impl Drop for FailOnPanic {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            // the lock is never held across anything that can panic
+            self.0
+                .end(Err("the controller crashed during the migration".to_owned()));
+        }
+    }
+}
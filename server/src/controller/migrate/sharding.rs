@@ -13,6 +13,9 @@ pub fn shard(
     new: &mut HashSet<NodeIndex>,
     topo_list: &[NodeIndex],
     sharding_factor: usize,
+    salting: Option<node::special::HotKeySalting>,
+    broadcast_threshold: Option<u64>,
+    base_rows: &HashMap<NodeIndex, u64>,
 ) -> (Vec<NodeIndex>, HashMap<(NodeIndex, NodeIndex), NodeIndex>) {
     // we must keep track of changes we make to the parent of a node, since this remapping must be
     // communicated to the nodes so they know the true identifier of their parent in the graph.
@@ -37,16 +40,31 @@ pub fn shard(
                 continue;
             }
 
+            // readers that keep their keys in order serve range and prefix lookups over the
+            // leading key columns, which hashing the whole compound key would scatter. if the
+            // leading column comes from a range-sharded base, we can shard by its ranges instead.
+            // such readers are fully materialized, so misses never need to be routed by range.
+            let ordered = graph[node].with_reader(|r| r.is_ordered()).unwrap();
             let s = graph[node]
-                .with_reader(|r| r.key())
+                .with_reader(|r| r.key().map(<[usize]>::to_vec))
                 .unwrap()
                 .and_then(|c| {
-                    if c.len() == 1 {
-                        if graph[node].fields()[c[0]] == "bogokey" {
-                            Some(Sharding::ForcedNone)
-                        } else {
-                            Some(Sharding::ByColumn(c[0], sharding_factor))
+                    if c.iter().any(|&c| graph[node].fields()[c] == "bogokey") {
+                        return Some(Sharding::ForcedNone);
+                    }
+                    if !ordered {
+                        return Some(Sharding::by_columns(c, sharding_factor));
+                    }
+                    match input_shardings[&ni] {
+                        Sharding::ByRange(rc, _) if rc == c[0] => {
+                            return Some(input_shardings[&ni].clone());
                         }
+                        _ => {}
+                    }
+                    if let Some(b) = upstream_range_split(graph, ni, c[0]) {
+                        Some(Sharding::ByRange(c[0], b))
+                    } else if c.len() == 1 {
+                        Some(Sharding::ByColumn(c[0], sharding_factor))
                     } else {
                         None
                     }
@@ -63,7 +81,7 @@ pub fn shard(
 
             if s != input_shardings[&ni] {
                 // input is sharded by different key -- need shuffle
-                reshard(log, new, &mut swaps, graph, ni, node, s);
+                reshard(log, new, &mut swaps, graph, ni, node, s.clone());
             }
             graph.node_weight_mut(node).unwrap().shard_by(s);
             continue;
@@ -73,35 +91,59 @@ pub fn shard(
             // non-internal nodes are always pass-through
             HashMap::new()
         };
+
+        // bases that asked to be split by value ranges are sharded that way regardless of their
+        // key, so that ordered readers downstream can be split along the same ranges.
+        if let Some((col, boundaries)) = graph[node]
+            .get_base()
+            .and_then(|b| b.range_sharding(sharding_factor))
+        {
+            if boundaries.len() + 1 == sharding_factor {
+                warn!(log, "range sharding base node"; "node" => ?node, "column" => col);
+                graph
+                    .node_weight_mut(node)
+                    .unwrap()
+                    .shard_by(Sharding::ByRange(col, boundaries));
+                continue;
+            }
+            error!(log, "range split does not match sharding factor; hashing instead";
+                   "node" => ?node,
+                   "ranges" => boundaries.len() + 1,
+                   "shards" => sharding_factor);
+        }
+
         if need_sharding.is_empty()
-            && (input_shardings.len() == 1 || input_shardings.iter().all(|(_, &s)| s.is_none()))
+            && (input_shardings.len() == 1 || input_shardings.values().all(Sharding::is_none))
         {
-            let mut s = if input_shardings
-                .iter()
-                .any(|(_, &s)| s == Sharding::ForcedNone)
-            {
+            let mut s = if input_shardings.values().any(|s| *s == Sharding::ForcedNone) {
                 Sharding::ForcedNone
             } else {
-                input_shardings.iter().map(|(_, &s)| s).next().unwrap()
+                input_shardings.values().next().cloned().unwrap()
             };
             info!(log, "preserving sharding of pass-through node";
                   "node" => ?node,
                   "sharding" => ?s);
 
             if graph[node].is_internal() || graph[node].is_base() {
-                if let Sharding::ByColumn(c, shards) = s {
-                    // remap c according to node's semantics
+                if let Some(cs) = s.columns().map(<[usize]>::to_vec) {
+                    let shards = s.shards().unwrap();
+                    // remap each sharding column according to node's semantics
                     let n = &graph[node];
-                    let src = (0..n.fields().len()).find(|&col| {
-                        if let Some(src) = n.parent_columns(col)[0].1 {
-                            src == c
-                        } else {
-                            false
-                        }
-                    });
+                    let srcs: Option<Vec<_>> = cs
+                        .into_iter()
+                        .map(|c| {
+                            (0..n.fields().len()).find(|&col| {
+                                if let Some(src) = n.parent_columns(col)[0].1 {
+                                    src == c
+                                } else {
+                                    false
+                                }
+                            })
+                        })
+                        .collect();
 
-                    if let Some(src) = src {
-                        s = Sharding::ByColumn(src, shards);
+                    if let Some(srcs) = srcs {
+                        s = s.with_columns(srcs);
                     } else {
                         // sharding column is not emitted by this node!
                         // at this point, sharding is effectively random.
@@ -113,22 +155,9 @@ pub fn shard(
             continue;
         }
 
-        let mut complex = false;
-        for lookup_col in need_sharding.values() {
-            if lookup_col.len() != 1 {
-                complex = true;
-            }
-        }
-        if complex {
-            if !graph[node].is_base() {
-                // not supported yet -- force no sharding
-                // TODO: if we're sharding by a two-part key and need sharding by the *first* part
-                // of that key, we can probably re-use the existing sharding?
-                error!(log, "de-sharding for lack of multi-key sharding support"; "node" => ?node);
-                for &ni in input_shardings.keys() {
-                    reshard(log, new, &mut swaps, graph, ni, node, Sharding::ForcedNone);
-                }
-            }
+        if graph[node].is_base() && need_sharding.values().any(|cols| cols.len() != 1) {
+            // we can't shard compound bases (yet), since writes are routed to base shards by a
+            // single column.
             continue;
         }
 
@@ -136,20 +165,20 @@ pub fn shard(
         // not at all). this *also* means that its inputs must be sharded by the column(s) that the
         // output column resolves to.
         if let Some(want_sharding) = need_sharding.remove(&node) {
-            assert_eq!(want_sharding.len(), 1);
-            let want_sharding = want_sharding[0];
-
-            if graph[node].fields()[want_sharding] == "bogokey" {
+            if want_sharding
+                .iter()
+                .any(|&c| graph[node].fields()[c] == "bogokey")
+            {
                 info!(log, "de-sharding node that operates on bogokey"; "node" => ?node);
                 for (ni, s) in input_shardings.iter_mut() {
-                    reshard(log, new, &mut swaps, graph, *ni, node, Sharding::ForcedNone);
+                    deshard_aggregation(log, new, &mut swaps, graph, *ni, node);
                     *s = Sharding::ForcedNone;
                 }
                 continue;
             }
 
             let resolved = if graph[node].is_internal() {
-                graph[node].resolve(want_sharding)
+                resolve_key(&graph[node], &want_sharding)
             } else if graph[node].is_base() {
                 // nothing resolves through a base
                 None
@@ -159,7 +188,7 @@ pub fn shard(
                 Some(
                     graph
                         .neighbors_directed(node, petgraph::EdgeDirection::Incoming)
-                        .map(|ni| (ni, want_sharding))
+                        .map(|ni| (ni, want_sharding.clone()))
                         .collect(),
                 )
             };
@@ -178,32 +207,26 @@ pub fn shard(
                 }
                 None => {
                     // base nodes -- what do we shard them by?
-                    warn!(log, "sharding base node"; "node" => ?node, "column" => want_sharding);
+                    warn!(log, "sharding base node"; "node" => ?node, "column" => ?want_sharding);
                     graph
                         .node_weight_mut(node)
                         .unwrap()
-                        .shard_by(Sharding::ByColumn(want_sharding, sharding_factor));
+                        .shard_by(Sharding::by_columns(want_sharding, sharding_factor));
                     continue;
                 }
                 Some(want_sharding_input) => {
-                    let want_sharding_input: HashMap<_, _> =
-                        want_sharding_input.into_iter().collect();
-
-                    // we can shard by the ouput column `want_sharding` *only* if we don't do
+                    // we can shard by the ouput column(s) `want_sharding` *only* if we don't do
                     // lookups based on any *other* columns in any ancestor. if we do, we must
                     // force no sharding :(
                     let mut ok = true;
                     for (ni, lookup_col) in &need_sharding {
-                        assert_eq!(lookup_col.len(), 1);
-                        let lookup_col = lookup_col[0];
-
-                        if let Some(&in_shard_col) = want_sharding_input.get(ni) {
+                        if let Some(in_shard_col) = want_sharding_input.get(ni) {
                             if in_shard_col != lookup_col {
                                 // we do lookups on this input on a different column than the one
                                 // that produces the output shard column.
                                 warn!(log, "not sharding self-lookup node; lookup conflict";
                                       "node" => ?node,
-                                      "wants" => want_sharding,
+                                      "wants" => ?want_sharding,
                                       "lookup" => ?(ni, lookup_col));
                                 ok = false;
                             }
@@ -212,24 +235,50 @@ pub fn shard(
                             // sharding output on -- no unambigous sharding.
                             warn!(log, "not sharding self-lookup node; also looks up by other";
                                   "node" => ?node,
-                                  "wants" => want_sharding,
+                                  "wants" => ?want_sharding,
                                   "lookup" => ?(ni, lookup_col));
                             ok = false;
                         }
                     }
 
                     if ok {
-                        // we can shard ourselves and our inputs by a single column!
-                        let s = Sharding::ByColumn(want_sharding, sharding_factor);
+                        // we can shard ourselves and our inputs by the same key!
+                        let s = Sharding::by_columns(want_sharding, sharding_factor);
                         info!(log, "sharding node doing self-lookup";
                               "node" => ?node,
                               "sharding" => ?s);
 
-                        for (ni, col) in want_sharding_input {
-                            let need_sharding = Sharding::ByColumn(col, sharding_factor);
+                        let ninputs = input_shardings.len();
+                        for (ni, cols) in want_sharding_input {
+                            let need_sharding = Sharding::by_columns(cols, sharding_factor);
                             if input_shardings[&ni] != need_sharding {
+                                if let Some(salting) = salting.filter(|_| ninputs == 1) {
+                                    // spread the hot keys of counts and sums over several shards
+                                    if salt_aggregation(
+                                        log,
+                                        new,
+                                        &mut swaps,
+                                        graph,
+                                        ni,
+                                        node,
+                                        salting,
+                                        sharding_factor,
+                                    ) {
+                                        input_shardings.insert(ni, need_sharding);
+                                        continue;
+                                    }
+                                }
+
                                 // input is sharded by different key -- need shuffle
-                                reshard(log, new, &mut swaps, graph, ni, node, need_sharding);
+                                reshard(
+                                    log,
+                                    new,
+                                    &mut swaps,
+                                    graph,
+                                    ni,
+                                    node,
+                                    need_sharding.clone(),
+                                );
                                 input_shardings.insert(ni, need_sharding);
                             }
                         }
@@ -251,6 +300,50 @@ pub fn shard(
             // with that of our inputs.
             debug!(log, "testing for harmonious sharding"; "node" => ?node);
 
+            // a join of a large, sharded input with one small enough to copy to every shard can
+            // keep the large input's sharding rather than shuffle it by the join key.
+            if let Some((small, big)) = broadcast_threshold.and_then(|threshold| {
+                broadcast_input(graph, node, &input_shardings, threshold, base_rows)
+            }) {
+                let s = follow_sharding(&graph[node], big, input_shardings[&big].clone());
+                let to = Sharding::Broadcast(s.shards().unwrap());
+                info!(log, "broadcasting small join input to every shard";
+                      "node" => ?node,
+                      "input" => ?small,
+                      "sharding" => ?s);
+                if input_shardings[&small] != to {
+                    reshard(log, new, &mut swaps, graph, small, node, to.clone());
+                    input_shardings.insert(small, to);
+                }
+                graph.node_weight_mut(node).unwrap().shard_by(s);
+                continue 'nodes;
+            }
+
+            if need_sharding.values().any(|cols| cols.len() != 1) {
+                // we look up into some ancestor by a compound key (e.g., a join on two columns).
+                // if the output has columns that resolve to that key in *every* ancestor, sharding
+                // by those columns keeps all of our lookups on one shard.
+                let ninputs = input_shardings.len();
+                if let Some(key) = shared_output_key(&graph[node], &need_sharding, ninputs) {
+                    let s = Sharding::by_columns(key, sharding_factor);
+                    info!(log, "sharding node with consistent compound lookup key";
+                          "node" => ?node,
+                          "sharding" => ?s);
+
+                    for (&ni, cols) in &need_sharding {
+                        let need_sharding = Sharding::by_columns(cols.clone(), sharding_factor);
+                        if input_shardings[&ni] != need_sharding {
+                            debug!(log, "resharding input with sharding {:?} to match desired sharding {:?}",
+                               input_shardings[&ni], need_sharding; "node" => ?node, "input" => ?ni);
+                            reshard(log, new, &mut swaps, graph, ni, node, need_sharding.clone());
+                            input_shardings.insert(ni, need_sharding);
+                        }
+                    }
+                    graph.node_weight_mut(node).unwrap().shard_by(s);
+                    continue 'nodes;
+                }
+            }
+
             // you can think of this loop as happening inside each of the ifs below, just hoisted
             // up to share some code.
             'outer: for col in 0..graph[node].fields().len() {
@@ -348,7 +441,7 @@ pub fn shard(
                         if input_shardings[&ni] != need_sharding {
                             debug!(log, "resharding input with sharding {:?} to match desired sharding {:?}",
                                input_shardings[&ni], need_sharding; "node" => ?node, "input" => ?ni);
-                            reshard(log, new, &mut swaps, graph, ni, node, need_sharding);
+                            reshard(log, new, &mut swaps, graph, ni, node, need_sharding.clone());
                             input_shardings.insert(ni, need_sharding);
                         }
                     }
@@ -358,7 +451,30 @@ pub fn shard(
             }
 
             if need_sharding.is_empty() {
-                // if we get here, that means no one column resolves to matching shardings across
+                // no one column resolves to matching shardings across all ancestors, but they may
+                // all be sharded by the same compound key.
+                let keys: Option<HashMap<_, _>> = input_shardings
+                    .iter()
+                    .map(|(&ni, s)| match *s {
+                        Sharding::ByColumns(ref cols, shards) if shards == sharding_factor => {
+                            Some((ni, cols.clone()))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let ninputs = input_shardings.len();
+                if let Some(key) =
+                    keys.and_then(|keys| shared_output_key(&graph[node], &keys, ninputs))
+                {
+                    let s = Sharding::by_columns(key, sharding_factor);
+                    info!(log, "continuing consistent sharding through node";
+                          "node" => ?node,
+                          "sharding" => ?s);
+                    graph.node_weight_mut(node).unwrap().shard_by(s);
+                    continue 'nodes;
+                }
+
+                // if we get here, that means no key resolves to matching shardings across
                 // all ancestors. we have two options here, either force no sharding or force
                 // sharding to the "most common" sharding of our ancestors. the latter is left as
                 // TODO for now.
@@ -376,8 +492,8 @@ pub fn shard(
         for (&ni, in_sharding) in &mut input_shardings {
             if !in_sharding.is_none() {
                 // ancestor must be forced to right sharding
-                reshard(log, new, &mut swaps, graph, ni, node, sharding);
-                *in_sharding = sharding;
+                deshard_aggregation(log, new, &mut swaps, graph, ni, node);
+                *in_sharding = sharding.clone();
             }
         }
     }
@@ -411,8 +527,17 @@ pub fn shard(
             assert!(!graph[p].is_source());
 
             // and that its children must be sharded somehow (otherwise what is the sharder doing?)
-            let col = graph[n].with_sharder(|s| s.sharded_by()).unwrap();
-            let by = Sharding::ByColumn(col, sharding_factor);
+            let by = graph[n]
+                .with_sharder(|s| s.sharding(sharding_factor))
+                .unwrap();
+            let col = match by {
+                Sharding::ByColumn(col, _) => col,
+                _ => {
+                    // we'd need to resolve every column of the key through the parent
+                    trace!(log, "no, sharder does not shard by a single hashed column");
+                    continue;
+                }
+            };
 
             // we can only push sharding above newly created nodes that are not already sharded.
             if !new.contains(&p) || graph[p].sharded_by() != Sharding::None {
@@ -502,13 +627,13 @@ pub fn shard(
             let mut remove = Vec::new();
             for c in graph.neighbors_directed(p, petgraph::EdgeDirection::Outgoing) {
                 // what does c shard by?
-                let col = graph[c].with_sharder(|s| s.sharded_by());
-                if col.is_none() {
+                let csharding = graph[c].with_sharder(|s| s.sharding(sharding_factor));
+                if csharding.is_none() {
                     // lifting n would shard a node that isn't expecting to be sharded
                     // TODO: we *could* insert a de-shard here
                     continue 'sharders;
                 }
-                let csharding = Sharding::ByColumn(col.unwrap(), sharding_factor);
+                let csharding = csharding.unwrap();
 
                 if csharding == by {
                     // sharding by the same key, which is now unnecessary.
@@ -623,6 +748,74 @@ pub fn shard(
     (topo_list, swaps)
 }
 
+/// Resolve each of `key`'s columns in `n` to the ancestors it comes from, keeping only the
+/// ancestors that the *whole* key resolves to. Returns `None` if some column is generated by `n`.
+fn resolve_key(n: &Node, key: &[usize]) -> Option<HashMap<NodeIndex, Vec<usize>>> {
+    let mut resolved: HashMap<NodeIndex, Vec<usize>> = HashMap::new();
+    for &col in key {
+        for (ni, src) in n.resolve(col)? {
+            resolved.entry(ni).or_default().push(src);
+        }
+    }
+    resolved.retain(|_, srcs| srcs.len() == key.len());
+    Some(resolved)
+}
+
+/// Find the output columns of `n` that, position by position, resolve to the given key columns
+/// of *every* one of its `ninputs` ancestors. Sharding `n` by those columns keeps it co-sharded
+/// with ancestors that are each sharded by their key, so no shuffle is needed.
+fn shared_output_key(
+    n: &Node,
+    keys: &HashMap<NodeIndex, Vec<usize>>,
+    ninputs: usize,
+) -> Option<Vec<usize>> {
+    if keys.len() != ninputs {
+        return None;
+    }
+    let width = keys.values().next()?.len();
+    if keys.values().any(|key| key.len() != width) {
+        return None;
+    }
+
+    (0..width)
+        .map(|i| {
+            (0..n.fields().len()).find(|&col| {
+                let srcs = n.parent_columns(col);
+                keys.iter()
+                    .all(|(&ni, key)| srcs.contains(&(ni, Some(key[i]))))
+            })
+        })
+        .collect()
+}
+
+/// If column `col` of `node` carries the column that an upstream base is range sharded by,
+/// return the boundaries between that base's shards.
+fn upstream_range_split(graph: &Graph, node: NodeIndex, col: usize) -> Option<Vec<DataType>> {
+    let n = &graph[node];
+    if n.is_base() {
+        return match n.sharded_by() {
+            Sharding::ByRange(c, boundaries) if c == col => Some(boundaries),
+            _ => None,
+        };
+    }
+
+    let parents: Vec<_> = if n.is_internal() {
+        n.parent_columns(col)
+            .into_iter()
+            .filter_map(|(p, c)| Some((p, c?)))
+            .collect()
+    } else {
+        // non-internal nodes just pass through columns
+        graph
+            .neighbors_directed(node, petgraph::EdgeDirection::Incoming)
+            .map(|p| (p, col))
+            .collect()
+    };
+    parents
+        .into_iter()
+        .find_map(|(p, c)| upstream_range_split(graph, p, c))
+}
+
 /// Modify the graph such that the path between `src` and `dst` shuffles the input such that the
 /// records received by `dst` are sharded by sharding `to`.
 fn reshard(
@@ -650,7 +843,7 @@ fn reshard(
             let n: NodeOperator =
                 ops::union::Union::new_deshard(src, graph[src].sharded_by()).into();
             let mut n = graph[src].mirror(n);
-            n.shard_by(to);
+            n.shard_by(to.clone());
             n
         }
         Sharding::ByColumn(c, _) => {
@@ -658,6 +851,21 @@ fn reshard(
             n.shard_by(graph[src].sharded_by());
             n
         }
+        Sharding::ByColumns(ref cs, _) => {
+            let mut n = graph[src].mirror(node::special::Sharder::by_columns(cs.clone()));
+            n.shard_by(graph[src].sharded_by());
+            n
+        }
+        Sharding::ByRange(c, ref boundaries) => {
+            let mut n = graph[src].mirror(node::special::Sharder::by_range(c, boundaries.clone()));
+            n.shard_by(graph[src].sharded_by());
+            n
+        }
+        Sharding::Broadcast(_) => {
+            let mut n = graph[src].mirror(node::special::Sharder::broadcast());
+            n.shard_by(graph[src].sharded_by());
+            n
+        }
         Sharding::Random(_) => unreachable!(),
     };
     let node = graph.add_node(node);
@@ -686,6 +894,242 @@ fn reshard(
     );
 }
 
+/// Modify the graph such that the records of the sharded `src` reach `dst` unsharded, as
+/// `reshard` does.
+///
+/// If `dst` is an aggregation that can be computed from partial aggregations, each shard of `src`
+/// instead computes the partial aggregation over its own records, so that only partial results
+/// cross the shard merger, and `dst` is replaced by the aggregation that combines them.
+fn deshard_aggregation(
+    log: &Logger,
+    new: &mut HashSet<NodeIndex>,
+    swaps: &mut HashMap<(NodeIndex, NodeIndex), NodeIndex>,
+    graph: &mut Graph,
+    src: NodeIndex,
+    dst: NodeIndex,
+) {
+    let from = graph[src].sharded_by();
+    let partial = if from.is_none() || !graph[dst].is_internal() {
+        None
+    } else {
+        graph[dst].partial_aggregation()
+    };
+    let partial = match partial {
+        Some(partial) => partial,
+        None => {
+            reshard(log, new, swaps, graph, src, dst, Sharding::ForcedNone);
+            return;
+        }
+    };
+
+    // the partial results stay sharded the way their input was, as long as the sharding columns
+    // are among the columns they are grouped by.
+    let shards = from.shards().unwrap();
+    let sharding = match from.columns() {
+        Some(cs) => cs
+            .iter()
+            .map(|c| partial.group_by.iter().position(|g| g == c))
+            .collect::<Option<Vec<_>>>()
+            .map(|cs| from.with_columns(cs))
+            .unwrap_or(Sharding::Random(shards)),
+        None => from.clone(),
+    };
+
+    let (_, partials) = split_aggregation(new, graph, src, dst, partial, sharding.clone());
+    info!(log, "pre-aggregating in each shard";
+          "node" => ?dst,
+          "partial" => ?partials,
+          "sharding" => ?sharding);
+
+    reshard(log, new, swaps, graph, partials, dst, Sharding::ForcedNone);
+}
+
+/// Modify the graph such that the records of `src` reach the sharded count or sum `dst` through a
+/// sharder that spreads hot keys over several shards, as `salting` says.
+///
+/// Since a hot key's records then end up on several shards, those shards compute partial counts
+/// or sums, and `dst` is replaced by the aggregation that combines them, sharded by `dst`'s key.
+/// Returns false, leaving the graph unchanged, if `dst` cannot be computed that way.
+#[allow(clippy::too_many_arguments)]
+fn salt_aggregation(
+    log: &Logger,
+    new: &mut HashSet<NodeIndex>,
+    swaps: &mut HashMap<(NodeIndex, NodeIndex), NodeIndex>,
+    graph: &mut Graph,
+    src: NodeIndex,
+    dst: NodeIndex,
+    salting: node::special::HotKeySalting,
+    sharding_factor: usize,
+) -> bool {
+    let partial = match graph[dst].partial_aggregation() {
+        Some(partial) if partial.additive => partial,
+        _ => return false,
+    };
+
+    let by = partial.group_by.clone();
+    let key: Vec<_> = (0..by.len()).collect();
+    let (p, partials) = split_aggregation(
+        new,
+        graph,
+        src,
+        dst,
+        partial,
+        Sharding::Random(sharding_factor),
+    );
+
+    // shuffle src's records to the partial aggregation, salting hot keys
+    let mut n = graph[src].mirror(node::special::Sharder::salting(by, salting));
+    n.shard_by(graph[src].sharded_by());
+    let sharder = graph.add_node(n);
+    new.insert(sharder);
+    let e = graph.find_edge(src, p).unwrap();
+    graph.remove_edge(e).unwrap();
+    graph.add_edge(src, sharder, ());
+    graph.add_edge(sharder, p, ());
+    swaps.insert((p, src), sharder);
+    info!(log, "salting hot keys of sharded aggregation";
+          "node" => ?dst,
+          "partial" => ?p,
+          "sharder" => ?sharder);
+
+    // and then shuffle the partial results by key to where they are combined
+    let s = Sharding::by_columns(key, sharding_factor);
+    reshard(log, new, swaps, graph, partials, dst, s);
+    true
+}
+
+/// Split the aggregation `dst` over `src` into the `partial` aggregation of `src`'s records,
+/// sharded by `sharding`, and the aggregation in `dst` that combines the partial results.
+///
+/// Returns the partial aggregation, and the node that `dst` now reads partial results from.
+fn split_aggregation(
+    new: &mut HashSet<NodeIndex>,
+    graph: &mut Graph,
+    src: NodeIndex,
+    dst: NodeIndex,
+    partial: ops::grouped::PartialAggregation,
+    sharding: Sharding,
+) -> (NodeIndex, NodeIndex) {
+    let name = format!("{}_partial", graph[dst].name());
+    let mut fields: Vec<_> = partial
+        .group_by
+        .iter()
+        .map(|&c| graph[src].fields()[c].clone())
+        .collect();
+    fields.push(String::from("partial"));
+
+    let mut n = node::Node::new(&*name, &fields, partial.op);
+    n.on_connected(graph);
+    n.shard_by(sharding.clone());
+    let p = graph.add_node(n);
+    graph.add_edge(src, p, ());
+    new.insert(p);
+
+    let mut partials = p;
+    if !partial.only.is_empty() {
+        // leave out partial results that must not take part in the combined result
+        let f: NodeOperator = ops::filter::Filter::new(p, &partial.only).into();
+        let mut n = node::Node::new(&*name, &fields, f);
+        n.on_connected(graph);
+        n.shard_by(sharding);
+        let f = graph.add_node(n);
+        graph.add_edge(p, f, ());
+        new.insert(f);
+        partials = f;
+    }
+
+    // dst now combines the partial results instead of aggregating src's records
+    let combining = graph[dst].combining_aggregation(partials).unwrap();
+    let mut n = graph[dst].mirror(combining);
+    n.on_connected(graph);
+    *graph.node_weight_mut(dst).unwrap() = n;
+    let e = graph.find_edge(src, dst).unwrap();
+    graph.remove_edge(e).unwrap();
+    graph.add_edge(partials, dst, ());
+
+    (p, partials)
+}
+
+/// An estimate of how many records `ni` holds: the number of rows in the bases it is computed
+/// from. There is none if any of those bases is new, or of unknown size.
+fn estimated_rows(
+    graph: &Graph,
+    base_rows: &HashMap<NodeIndex, u64>,
+    ni: NodeIndex,
+) -> Option<u64> {
+    let mut rows = 0;
+    let mut seen = HashSet::new();
+    let mut stack = vec![ni];
+    while let Some(n) = stack.pop() {
+        if !seen.insert(n) || graph[n].is_source() {
+            continue;
+        }
+        if graph[n].is_base() {
+            rows += *base_rows.get(&n)?;
+        } else {
+            stack.extend(graph.neighbors_directed(n, petgraph::EdgeDirection::Incoming));
+        }
+    }
+    Some(rows)
+}
+
+/// The input of join `node` to copy to every shard, and the input whose sharding the join then
+/// follows, if one of them holds at most `threshold` records and the other is sharded.
+///
+/// If both are small enough, the smaller one is copied.
+fn broadcast_input(
+    graph: &Graph,
+    node: NodeIndex,
+    input_shardings: &HashMap<NodeIndex, Sharding>,
+    threshold: u64,
+    base_rows: &HashMap<NodeIndex, u64>,
+) -> Option<(NodeIndex, NodeIndex)> {
+    if !graph[node].is_internal() || input_shardings.len() != 2 {
+        return None;
+    }
+
+    let inputs: Vec<_> = input_shardings.keys().cloned().collect();
+    let mut best: Option<(u64, NodeIndex, NodeIndex)> = None;
+    for &(small, big) in &[(inputs[0], inputs[1]), (inputs[1], inputs[0])] {
+        match input_shardings[&big] {
+            ref s if s.is_none() => continue,
+            Sharding::Broadcast(_) => continue,
+            _ => {}
+        }
+        if !graph[node].may_broadcast(small) {
+            continue;
+        }
+        let rows = match estimated_rows(graph, base_rows, small) {
+            Some(rows) if rows <= threshold => rows,
+            _ => continue,
+        };
+        if best.map(|(r, _, _)| rows < r).unwrap_or(true) {
+            best = Some((rows, small, big));
+        }
+    }
+    best.map(|(_, small, big)| (small, big))
+}
+
+/// The sharding of `n`'s output when its input `parent` is sharded by `ps`, and its other inputs
+/// are copied to every shard.
+fn follow_sharding(n: &Node, parent: NodeIndex, ps: Sharding) -> Sharding {
+    let cs = match ps.columns() {
+        Some(cs) => cs.to_vec(),
+        None => return ps,
+    };
+    let srcs: Option<Vec<_>> = cs
+        .into_iter()
+        .map(|c| {
+            (0..n.fields().len()).find(|&col| n.parent_columns(col).contains(&(parent, Some(c))))
+        })
+        .collect();
+    match srcs {
+        Some(srcs) => ps.with_columns(srcs),
+        // the sharding column is not emitted, so the output is effectively sharded randomly
+        None => Sharding::Random(ps.shards().unwrap()),
+    }
+}
+
 pub fn validate(log: &Logger, graph: &Graph, topo_list: &[NodeIndex], sharding_factor: usize) {
     // ensure that each node matches the sharding of each of its ancestors, unless the ancestor is
     // a sharder or a shard merger
@@ -703,36 +1147,45 @@ pub fn validate(log: &Logger, graph: &Graph, topo_list: &[NodeIndex], sharding_f
 
         let remap = |nd: &Node, pni: NodeIndex, ps: Sharding| -> Sharding {
             if nd.is_internal() || nd.is_base() {
-                if let Sharding::ByColumn(c, shards) = ps {
-                    // remap c according to node's semantics
-                    let src = (0..nd.fields().len()).find(|&col| {
-                        for pc in nd.parent_columns(col) {
-                            if let (p, Some(src)) = pc {
-                                // found column c in parent pni
-                                if p == pni && src == c {
-                                    // extract *child* column ID that we found a match for
-                                    return true;
-                                } else if !graph[pni].is_internal() {
-                                    // need to look transitively for an indirect parent, since
-                                    // `parent_columns`'s return values does not take sharder
-                                    // and desharder nodes previously added into account (as
-                                    // the `src` in the operator is only rewritten to the
-                                    // sharder later, in `on_connected`).
-                                    // NOTE(malte): just checking connectivity here is perhaps a
-                                    // bit too lax (i.e., may miss some incorrect shardings)
-                                    if petgraph::algo::has_path_connecting(graph, p, pni, None)
-                                        && src == c
-                                    {
-                                        return true;
+                if let Some(cs) = ps.columns() {
+                    // remap each sharding column according to node's semantics
+                    let srcs: Option<Vec<_>> = cs
+                        .iter()
+                        .map(|&c| {
+                            (0..nd.fields().len()).find(|&col| {
+                                for pc in nd.parent_columns(col) {
+                                    if let (p, Some(src)) = pc {
+                                        // found column c in parent pni
+                                        if p == pni && src == c {
+                                            // extract *child* column ID that we found a match for
+                                            return true;
+                                        } else if !graph[pni].is_internal() {
+                                            // need to look transitively for an indirect parent,
+                                            // since `parent_columns`'s return values does not
+                                            // take sharder and desharder nodes previously added
+                                            // into account (as the `src` in the operator is only
+                                            // rewritten to the sharder later, in
+                                            // `on_connected`).
+                                            // NOTE(malte): just checking connectivity here is
+                                            // perhaps a bit too lax (i.e., may miss some incorrect
+                                            // shardings)
+                                            if petgraph::algo::has_path_connecting(
+                                                graph, p, pni, None,
+                                            ) && src == c
+                                            {
+                                                return true;
+                                            }
+                                        }
                                     }
                                 }
-                            }
-                        }
-                        false
-                    });
+                                false
+                            })
+                        })
+                        .collect();
 
-                    if let Some(src) = src {
-                        return Sharding::ByColumn(src, shards);
+                    let shards = ps.shards().unwrap();
+                    if let Some(srcs) = srcs {
+                        return ps.with_columns(srcs);
                     } else {
                         return Sharding::Random(shards);
                     }
@@ -747,11 +1200,7 @@ pub fn validate(log: &Logger, graph: &Graph, topo_list: &[NodeIndex], sharding_f
             if in_node.is_sharder() {
                 // ancestor is a sharder, so its output sharding must match ours
                 in_node.with_sharder(|s| {
-                    let in_sharding = remap(
-                        n,
-                        in_ni,
-                        Sharding::ByColumn(s.sharded_by(), sharding_factor),
-                    );
+                    let in_sharding = remap(n, in_ni, s.sharding(sharding_factor));
                     if in_sharding != n.sharded_by() {
                         crit!(
                             log,
@@ -775,6 +1224,9 @@ pub fn validate(log: &Logger, graph: &Graph, topo_list: &[NodeIndex], sharding_f
                         Sharding::ForcedNone | Sharding::None => true,
                         _ => in_sharding == out_sharding,
                     },
+                    // every shard of a broadcast input holds all of its records, however we are
+                    // sharded
+                    Sharding::Broadcast(shards) => out_sharding.shards() == Some(shards),
                     _ => in_sharding == out_sharding,
                 };
 
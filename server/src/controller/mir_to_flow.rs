@@ -71,6 +71,35 @@ pub(super) fn mir_query_to_flow_parts(
     }
 }
 
+/// Check that every base column that `mir_query` removes can actually be dropped, before any of
+/// the query is pushed into the flow graph.
+pub(super) fn check_base_adaptations(mir_query: &MirQuery, mig: &Migration) -> Result<(), String> {
+    for root in &mir_query.roots {
+        let root = root.borrow();
+        if let MirNodeType::Base {
+            adapted_over: Some(ref bna),
+            ..
+        } = root.inner
+        {
+            let over = bna.over.borrow();
+            let na = match over.flow_node {
+                None => panic!("adapted base node must have a flow node already!"),
+                Some(ref flow_node) => flow_node.address(),
+            };
+            for r in &bna.columns_removed {
+                let cid = over
+                    .column_specifications()
+                    .iter()
+                    .find(|&&(ref ecs, _)| ecs == r)
+                    .and_then(|&(_, cid)| cid)
+                    .expect("base column ID must be set to remove column");
+                mig.check_drop_column(na, cid)?;
+            }
+        }
+    }
+    Ok(())
+}
+
 fn mir_node_to_flow_parts(
     mir_node: &mut MirNode,
     mig: &mut Migration,
@@ -212,10 +241,15 @@ fn mir_node_to_flow_parts(
                     let parent = mir_node.ancestors[0].clone();
                     make_latest_node(&name, parent, mir_node.columns.as_slice(), group_by, mig)
                 }
-                MirNodeType::Leaf { ref keys, .. } => {
+                MirNodeType::Leaf {
+                    ref keys,
+                    ordered,
+                    ref row_order,
+                    ..
+                } => {
                     assert_eq!(mir_node.ancestors.len(), 1);
                     let parent = mir_node.ancestors[0].clone();
-                    materialize_leaf_node(&parent, name, keys, mig);
+                    materialize_leaf_node(&parent, name, keys, ordered, row_order, mig);
                     // TODO(malte): below is yucky, but required to satisfy the type system:
                     // each match arm must return a `FlowNode`, so we use the parent's one
                     // here.
@@ -390,7 +424,8 @@ fn adapt_base_node(
         let cid = over_node.column_specifications()[pos]
             .1
             .expect("base column ID must be set to remove column");
-        mig.drop_column(na, cid);
+        mig.drop_column(na, cid)
+            .expect("column removal was checked before adapting the base");
     }
 
     FlowNode::Existing(na)
@@ -442,11 +477,33 @@ fn make_base_node(
                     .unwrap()
             })
             .collect();
-        node::special::Base::new(default_values).with_key(pkey_column_ids)
+        let auto_increment = pkey_columns.len() == 1
+            && column_specs.iter().any(|&(ref cs, _)| {
+                Column::from(&cs.column) == pkey_columns[0]
+                    && cs.constraints.contains(&ColumnConstraint::AutoIncrement)
+            });
+        let base = node::special::Base::new(default_values).with_key(pkey_column_ids);
+        if auto_increment {
+            base.with_auto_increment()
+        } else {
+            base
+        }
     } else {
         node::special::Base::new(default_values)
     };
 
+    let case_insensitive_columns: Vec<_> = column_specs
+        .iter()
+        .enumerate()
+        .filter(|&(_, &(ref cs, _))| noria::is_case_insensitive_column(cs))
+        .map(|(i, _)| i)
+        .collect();
+    let base = if case_insensitive_columns.is_empty() {
+        base
+    } else {
+        base.with_case_insensitive_columns(case_insensitive_columns)
+    };
+
     FlowNode::New(mig.add_base(name, column_names.as_slice(), base))
 }
 
@@ -918,6 +975,8 @@ fn materialize_leaf_node(
     parent: &MirNodeRef,
     name: String,
     key_cols: &[Column],
+    ordered: bool,
+    row_order: &Option<Vec<(Column, OrderType)>>,
     mig: &mut Migration,
 ) {
     let na = parent.borrow().flow_node_addr().unwrap();
@@ -934,9 +993,21 @@ fn materialize_leaf_node(
             .iter()
             .map(|c| parent.borrow().column_id_for_column(c, None))
             .collect();
-        mig.maintain(name, na, &key_cols[..]);
+        if ordered {
+            mig.maintain_ordered(name, na, &key_cols[..]);
+        } else {
+            mig.maintain(name, na, &key_cols[..]);
+        }
     } else {
         // if no key specified, default to the first column
         mig.maintain(name, na, &[0]);
     }
+
+    if let Some(ref row_order) = *row_order {
+        let row_order = row_order
+            .iter()
+            .map(|(c, o)| (parent.borrow().column_id_for_column(c, None), o.clone()))
+            .collect();
+        mig.maintain_row_order(na, row_order);
+    }
 }
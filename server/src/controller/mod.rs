@@ -1,6 +1,8 @@
 use crate::controller::inner::ControllerInner;
+use crate::controller::migrate::progress::Progress;
 use crate::controller::migrate::Migration;
 use crate::controller::recipe::Recipe;
+use crate::controller::recovery::Recovery;
 use crate::coordination::CoordinationMessage;
 use crate::coordination::CoordinationPayload;
 use crate::startup::Event;
@@ -8,15 +10,16 @@ use crate::Config;
 use async_bincode::AsyncBincodeReader;
 use dataflow::payload::ControlReplyPacket;
 use futures_util::{
-    future::FutureExt,
-    future::TryFutureExt,
     sink::SinkExt,
     stream::{StreamExt, TryStreamExt},
 };
 use hyper::{self, StatusCode};
-use noria::channel::TcpSender;
+use noria::auth::Principals;
+use noria::channel::tls::{self, Tls};
+use noria::channel::{local, MaybeLocalSender};
 use noria::consensus::{Authority, Epoch, STATE_KEY};
-use noria::ControllerDescriptor;
+use noria::{ControllerDescriptor, RecipeVersion};
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
@@ -24,12 +27,16 @@ use std::time;
 use stream_cancel::Valve;
 use tokio::sync::mpsc::UnboundedSender;
 
+mod backup;
 mod domain_handle;
+mod dot;
+mod events;
 mod inner;
 mod keys;
 pub(crate) mod migrate; // crate viz for tests
 mod mir_to_flow;
 pub(crate) mod recipe; // crate viz for tests
+pub(crate) mod recovery;
 mod schema;
 mod security;
 pub(crate) mod sql; // crate viz for tests
@@ -40,23 +47,56 @@ pub(crate) struct ControllerState {
     pub(crate) epoch: Epoch,
 
     recipe_version: usize,
-    recipes: Vec<String>,
+    /// Every change made to the recipe, oldest first.
+    recipe_history: Vec<RecipeVersion>,
+    /// The label of the workers that the domains of each constrained table and view must go on.
+    #[serde(default)]
+    placement: HashMap<String, String>,
+    /// The addresses that the workers being decommissioned listen on.
+    #[serde(default)]
+    decommissioning: HashSet<SocketAddr>,
+    /// The commit timestamps of committed transactions that some bases may not have applied
+    /// their part of yet.
+    #[serde(default)]
+    committed_transactions: HashSet<u64>,
 }
 
 struct Worker {
     healthy: bool,
     last_heartbeat: time::Instant,
-    sender: TcpSender<CoordinationMessage>,
+    /// The address the worker listens on. Unlike its identifier, this stays the same when the
+    /// worker registers with a new controller.
+    addr: SocketAddr,
+    sender: MaybeLocalSender<CoordinationMessage>,
+    /// Whether the worker expects its peers and clients to connect over TLS.
+    tls: bool,
+    labels: HashSet<String>,
+    /// Whether the worker is being emptied so that it can be stopped.
+    decommissioning: bool,
 }
 
 impl Worker {
-    fn new(sender: TcpSender<CoordinationMessage>) -> Self {
+    fn new(
+        addr: SocketAddr,
+        sender: MaybeLocalSender<CoordinationMessage>,
+        tls: bool,
+        labels: HashSet<String>,
+    ) -> Self {
         Worker {
             healthy: true,
             last_heartbeat: time::Instant::now(),
+            addr,
             sender,
+            tls,
+            labels,
+            decommissioning: false,
         }
     }
+
+    /// Whether new domains may be placed on the worker.
+    fn accepts_domains(&self) -> bool {
+        self.healthy && !self.decommissioning
+    }
 }
 
 type WorkerIdentifier = SocketAddr;
@@ -68,22 +108,40 @@ pub(super) async fn main<A: Authority + 'static>(
     descriptor: ControllerDescriptor,
     mut ctrl_rx: tokio::sync::mpsc::UnboundedReceiver<Event>,
     cport: tokio::net::TcpListener,
+    tls: Option<Tls>,
+    principals: Option<Arc<Principals>>,
     log: slog::Logger,
     authority: Arc<A>,
     tx: tokio::sync::mpsc::UnboundedSender<Event>,
+    progress: Progress,
+    recovery: Recovery,
+    local_transport: bool,
 ) {
     let (dtx, drx) = tokio::sync::mpsc::unbounded_channel();
 
+    // domains in this process send their replies in memory
+    if local_transport {
+        let (ltx, lrx) = tokio::sync::mpsc::unbounded_channel::<ControlReplyPacket>();
+        tokio::spawn(crate::startup::listen_local(
+            alive.clone(),
+            valve.clone(),
+            local::register(descriptor.domain_addr, ltx),
+            lrx,
+            dtx.clone(),
+        ));
+    }
     tokio::spawn(listen_domain_replies(
         alive.clone(),
         valve.clone(),
         log.clone(),
         dtx,
         cport,
+        tls.clone(),
     ));
 
     // note that we do not start up the data-flow until we find a controller!
 
+    let worker_addr = descriptor.worker_addr;
     let campaign = instance_campaign(tx.clone(), authority.clone(), descriptor, config);
 
     // state that this instance will take if it becomes the controller
@@ -99,7 +157,9 @@ pub(super) async fn main<A: Authority + 'static>(
                 }
                 CoordinationPayload::CreateUniverse(universe) => {
                     if let Some(ref mut ctrl) = controller {
+                        let _fail = progress.fail_on_panic();
                         tokio::task::block_in_place(|| ctrl.create_universe(universe).unwrap());
+                        progress.end(Ok(()));
                     }
                 }
                 CoordinationPayload::Register { .. } => {
@@ -111,6 +171,33 @@ pub(super) async fn main<A: Authority + 'static>(
                         });
                     }
                 }
+                CoordinationPayload::DomainPinned {
+                    domain,
+                    shard,
+                    cores,
+                } => {
+                    if let Some(ref mut ctrl) = controller {
+                        ctrl.domain_cores.insert((domain, shard), cores);
+                    }
+                }
+                CoordinationPayload::DomainQuiescent {
+                    domain,
+                    shard,
+                    frontiers,
+                } => {
+                    if let Some(ref mut ctrl) = controller {
+                        ctrl.handle_quiescent(domain, shard, frontiers);
+                    }
+                }
+                CoordinationPayload::SenderFenced {
+                    domain,
+                    shard,
+                    peer,
+                } => {
+                    if let Some(ref mut ctrl) = controller {
+                        ctrl.handle_fenced(domain, shard, peer);
+                    }
+                }
                 CoordinationPayload::Heartbeat => {
                     if let Some(ref mut ctrl) = controller {
                         tokio::task::block_in_place(|| ctrl.handle_heartbeat(msg).unwrap());
@@ -121,9 +208,16 @@ pub(super) async fn main<A: Authority + 'static>(
             Event::ExternalRequest(method, path, query, body, reply_tx) => {
                 if let Some(ref mut ctrl) = controller {
                     let authority = &authority;
+                    let _fail = progress.fail_on_panic();
                     let reply = tokio::task::block_in_place(|| {
                         ctrl.external_request(method, path, query, body, &authority)
                     });
+                    // a migration ends with the request that made it, and fails if the request does
+                    progress.end(match reply {
+                        Ok(Ok(_)) => Ok(()),
+                        Ok(Err(ref e)) => Err(e.clone()),
+                        Err(status) => Err(status.to_string()),
+                    });
 
                     if reply_tx.send(reply).is_err() {
                         warn!(log, "client hung up");
@@ -135,8 +229,10 @@ pub(super) async fn main<A: Authority + 'static>(
             Event::ManualMigration { f, done } => {
                 if let Some(ref mut ctrl) = controller {
                     if !ctrl.workers.is_empty() {
+                        let _fail = progress.fail_on_panic();
                         tokio::task::block_in_place(|| {
                             ctrl.migrate(move |m| f(m));
+                            progress.end(Ok(()));
                             done.send(()).unwrap();
                         });
                     }
@@ -159,7 +255,16 @@ pub(super) async fn main<A: Authority + 'static>(
                 let c = campaign.take().unwrap();
                 tokio::task::block_in_place(move || c.join().unwrap());
                 let drx = drx.take().unwrap();
-                controller = Some(ControllerInner::new(log.clone(), state, drx));
+                controller = Some(ControllerInner::new(
+                    log.clone(),
+                    state,
+                    worker_addr,
+                    drx,
+                    tls.clone(),
+                    principals.clone(),
+                    progress.clone(),
+                    recovery.clone(),
+                ));
             }
             Event::CampaignError(e) => {
                 panic!("{:?}", e);
@@ -183,6 +288,7 @@ async fn listen_domain_replies(
     log: slog::Logger,
     reply_tx: UnboundedSender<ControlReplyPacket>,
     mut on: tokio::net::TcpListener,
+    tls: Option<Tls>,
 ) {
     let mut incoming = valve.wrap(on.incoming());
     while let Some(sock) = incoming.next().await {
@@ -193,20 +299,31 @@ async fn listen_domain_replies(
             }
             Ok(sock) => {
                 let alive = alive.clone();
-                tokio::spawn(
-                    valve
+                let valve = valve.clone();
+                let log = log.clone();
+                let tls = tls.clone();
+                let reply_tx = reply_tx.clone();
+                tokio::spawn(async move {
+                    let _alive = alive;
+                    let sock = match tls::maybe_accept(tls.as_ref(), sock).await {
+                        Ok(sock) => sock,
+                        Err(e) => {
+                            warn!(log, "domain reply connection failed TLS handshake: {:?}", e);
+                            return;
+                        }
+                    };
+                    let r = valve
                         .wrap(AsyncBincodeReader::from(sock))
                         .map_err(failure::Error::from)
                         .forward(
-                            crate::ImplSinkForSender(reply_tx.clone())
+                            crate::ImplSinkForSender(reply_tx)
                                 .sink_map_err(|_| format_err!("main event loop went away")),
                         )
-                        .map_err(|e| panic!("{:?}", e))
-                        .map(move |_| {
-                            let _ = alive;
-                            ()
-                        }),
-                );
+                        .await;
+                    if let Err(e) = r {
+                        panic!("{:?}", e);
+                    }
+                });
             }
         }
     }
@@ -261,11 +378,20 @@ fn instance_campaign<A: Authority + 'static>(
                         config: config.clone(),
                         epoch,
                         recipe_version: 0,
-                        recipes: vec![],
+                        recipe_history: vec![],
+                        placement: HashMap::new(),
+                        decommissioning: HashSet::new(),
+                        committed_transactions: HashSet::new(),
                     }),
                     Some(ref state) if state.epoch > epoch => Err(()),
                     Some(mut state) => {
                         state.epoch = epoch;
+                        // changing the shard hash would silently split keys across shards
+                        assert_eq!(
+                            state.config.domain_config.shard_hasher,
+                            config.domain_config.shard_hasher,
+                            "Deployment is sharded by a different hash than the requested one!"
+                        );
                         // check that running config is the same that builder requested
                         assert_eq!(
                             state.config, config,
@@ -1,19 +1,23 @@
 use crate::controller::security::SecurityConfig;
-use crate::controller::sql::SqlIncorporator;
+use crate::controller::sql::{NamespaceQualification, SqlIncorporator};
 use crate::controller::Migration;
 use crate::ReuseConfigType;
 use dataflow::ops::trigger::Trigger;
 use dataflow::ops::trigger::TriggerEvent;
+use dataflow::ops::udf::{Udf, UdfType};
 use dataflow::prelude::DataType;
+use mir::query::QueryFlowParts;
 use nom_sql::parser as sql_parser;
 use nom_sql::SqlQuery;
-use noria::ActivationResult;
+use noria::namespace;
+use noria::{ActivationResult, RecipeChange, RecipeDiff, RecipeVersion};
 use petgraph::graph::NodeIndex;
 
 use nom_sql::CreateTableStatement;
 use slog;
 use std::collections::HashMap;
 use std::str;
+use std::sync::Arc;
 use std::vec::Vec;
 
 type QueryID = u64;
@@ -30,6 +34,8 @@ pub(crate) struct Recipe {
     aliases: HashMap<String, QueryID>,
     /// Security configuration
     security_config: Option<SecurityConfig>,
+    /// User-defined functions registered by the recipe, by name.
+    functions: HashMap<String, Arc<Udf>>,
 
     /// Recipe revision.
     version: usize,
@@ -50,6 +56,7 @@ impl PartialEq for Recipe {
         self.expressions == other.expressions
             && self.expression_order == other.expression_order
             && self.aliases == other.aliases
+            && self.functions == other.functions
             && self.version == other.version
             && self.prior == other.prior
     }
@@ -70,6 +77,29 @@ fn hash_query(q: &SqlQuery) -> QueryID {
     h.finish()
 }
 
+/// The namespace of a recipe expression, going by its name, or by the first table it reads from if
+/// it has none.
+fn namespace_of(name: Option<&String>, q: &SqlQuery) -> Option<String> {
+    let name = match (name, q) {
+        (Some(name), _) => name,
+        (None, SqlQuery::CreateTable(ctq)) => &ctq.table.name,
+        (None, SqlQuery::CreateView(cvq)) => &cvq.name,
+        (None, SqlQuery::Select(sq)) => &sq.tables.first()?.name,
+        (None, SqlQuery::CompoundSelect(csq)) => &csq.selects.first()?.1.tables.first()?.name,
+        _ => return None,
+    };
+    namespace::split(name).0.map(String::from)
+}
+
+/// The existing nodes, other than bases, that a newly added query shares with earlier queries.
+fn shared_nodes(mig: &Migration, qfp: &QueryFlowParts) -> Vec<NodeIndex> {
+    qfp.reused_nodes
+        .iter()
+        .cloned()
+        .filter(|&ni| !mig.mainline.ingredients[ni].is_base())
+        .collect()
+}
+
 #[inline]
 fn ident(input: &str) -> nom::IResult<&str, &str> {
     use nom::InputTakeAtPosition;
@@ -119,8 +149,119 @@ fn query_expr(input: &str) -> nom::IResult<&str, (bool, Option<&str>, SqlQuery)>
     ))
 }
 
-fn query_exprs(input: &str) -> nom::IResult<&str, Vec<(bool, Option<&str>, SqlQuery)>> {
-    nom::multi::many1(query_expr)(input)
+/// A user-defined function as a recipe registers it, before its module has been compiled.
+struct FunctionExpr {
+    name: String,
+    params: Vec<UdfType>,
+    returns: UdfType,
+    export: String,
+    module: Vec<u8>,
+    fuel: Option<u64>,
+}
+
+/// A statement in a recipe.
+enum RecipeExpr<'a> {
+    /// A table or query: whether it is public, its name, and the query itself.
+    Query(bool, Option<&'a str>, SqlQuery),
+    /// A user-defined function.
+    Function(FunctionExpr),
+}
+
+fn nonempty_ident(input: &str) -> nom::IResult<&str, &str> {
+    nom::combinator::verify(ident, |s: &str| !s.is_empty())(input)
+}
+
+fn udf_type(input: &str) -> nom::IResult<&str, UdfType> {
+    use nom::branch::alt;
+    use nom::bytes::complete::tag_no_case;
+    use nom::combinator::map;
+    alt((
+        map(tag_no_case("int"), |_| UdfType::Int),
+        map(tag_no_case("double"), |_| UdfType::Double),
+        map(tag_no_case("bool"), |_| UdfType::Bool),
+        map(tag_no_case("text"), |_| UdfType::Text),
+    ))(input)
+}
+
+/// A WebAssembly module written as a hex string literal, `X'0061736d...'`, which may be broken up
+/// by whitespace.
+fn hex_module(input: &str) -> nom::IResult<&str, Vec<u8>> {
+    use nom::bytes::complete::{tag, tag_no_case, take_while};
+    use nom::combinator::map_res;
+    use nom::sequence::{delimited, preceded};
+    map_res(
+        preceded(
+            tag_no_case("x"),
+            delimited(
+                tag("'"),
+                take_while(|c: char| c.is_ascii_hexdigit() || c.is_ascii_whitespace()),
+                tag("'"),
+            ),
+        ),
+        |hex: &str| {
+            let digits: Vec<u8> = hex.bytes().filter(u8::is_ascii_hexdigit).collect();
+            if digits.len() % 2 != 0 {
+                return Err(());
+            }
+            digits
+                .chunks(2)
+                .map(|d| u8::from_str_radix(str::from_utf8(d).unwrap(), 16).map_err(|_| ()))
+                .collect()
+        },
+    )(input)
+}
+
+/// `FUNCTION name(type, ...) RETURNS type AS export IN X'...' [FUEL n];`, which registers a call
+/// to `export` in the given WebAssembly module as the user-defined function `name`.
+fn function_expr(input: &str) -> nom::IResult<&str, FunctionExpr> {
+    use nom::bytes::complete::tag_no_case;
+    use nom::character::complete::{char, digit1, multispace0, multispace1};
+    use nom::combinator::{map_res, opt};
+    use nom::multi::separated_list;
+    use nom::sequence::{delimited, pair, preceded, tuple};
+    let (input, _) = pair(tag_no_case("function"), multispace1)(input)?;
+    let (input, name) = nonempty_ident(input)?;
+    let (input, _) = multispace0(input)?;
+    let (input, params) = delimited(
+        pair(char('('), multispace0),
+        separated_list(tuple((multispace0, char(','), multispace0)), udf_type),
+        pair(multispace0, char(')')),
+    )(input)?;
+    let (input, _) = tuple((multispace0, tag_no_case("returns"), multispace1))(input)?;
+    let (input, returns) = udf_type(input)?;
+    let (input, _) = tuple((multispace1, tag_no_case("as"), multispace1))(input)?;
+    let (input, export) = nonempty_ident(input)?;
+    let (input, _) = tuple((multispace1, tag_no_case("in"), multispace1))(input)?;
+    let (input, module) = hex_module(input)?;
+    let (input, fuel) = opt(preceded(
+        tuple((multispace1, tag_no_case("fuel"), multispace1)),
+        map_res(digit1, |d: &str| d.parse::<u64>()),
+    ))(input)?;
+    let (input, _) = multispace0(input)?;
+    let (input, _) = opt(char(';'))(input)?;
+    let (input, _) = multispace0(input)?;
+    Ok((
+        input,
+        FunctionExpr {
+            name: name.to_owned(),
+            params,
+            returns,
+            export: export.to_owned(),
+            module,
+            fuel,
+        },
+    ))
+}
+
+fn recipe_exprs(input: &str) -> nom::IResult<&str, Vec<RecipeExpr>> {
+    use nom::branch::alt;
+    use nom::combinator::map;
+    nom::multi::many1(alt((
+        map(function_expr, RecipeExpr::Function),
+        map(query_expr, |(public, name, q)| {
+            RecipeExpr::Query(public, name, q)
+        }),
+    )))(input)
 }
 
 #[allow(unused)]
@@ -157,6 +298,7 @@ impl Recipe {
                 Some(log) => log,
             },
             security_config: None,
+            functions: HashMap::default(),
         }
     }
 
@@ -185,6 +327,11 @@ impl Recipe {
         self.inc.as_mut().unwrap().enable_reuse(reuse_type)
     }
 
+    /// Give views with several parameters ordered readers, so they can serve prefix lookups.
+    pub(super) fn enable_prefix_lookups(&mut self) {
+        self.inc.as_mut().unwrap().enable_prefix_lookups()
+    }
+
     pub(in crate::controller) fn resolve_alias(&self, alias: &str) -> Option<&str> {
         self.aliases.get(alias).map(|ref qid| {
             let (ref internal_qn, _, _) = self.expressions[qid];
@@ -192,12 +339,20 @@ impl Recipe {
         })
     }
 
+    fn names_expression(&self, name: &str) -> bool {
+        self.expressions
+            .values()
+            .any(|&(ref n, _, _)| n.as_ref().map(String::as_str) == Some(name))
+    }
+
     /// Obtains the `NodeIndex` for the node corresponding to a named query or a write type.
     pub(in crate::controller) fn node_addr_for(&self, name: &str) -> Result<NodeIndex, String> {
         match self.inc {
             Some(ref inc) => {
                 // `name` might be an alias for another identical query, so resolve if needed
                 let na = match self.resolve_alias(name) {
+                    // a removed view whose query lives on for other views that share it
+                    None if self.names_expression(name) => None,
                     None => inc.get_query_address(name),
                     Some(ref internal_qn) => inc.get_query_address(internal_qn),
                 };
@@ -228,6 +383,11 @@ impl Recipe {
         }
     }
 
+    /// The user-defined function `name` registered by the recipe, if any.
+    pub(in crate::controller) fn udf(&self, name: &str) -> Option<Arc<Udf>> {
+        self.functions.get(name).cloned()
+    }
+
     /// Set recipe's security configuration
     pub(in crate::controller) fn set_security_config(&mut self, config_text: &str) {
         let mut config = SecurityConfig::parse(config_text);
@@ -239,6 +399,15 @@ impl Recipe {
     /// it.
     // crate viz for tests
     pub(crate) fn from_str(recipe_text: &str, log: Option<slog::Logger>) -> Result<Recipe, String> {
+        Recipe::from_str_in(recipe_text, None, log)
+    }
+
+    /// Like `from_str`, but puts the tables and queries in the recipe in `namespace`.
+    pub(super) fn from_str_in(
+        recipe_text: &str,
+        namespace: Option<&str>,
+        log: Option<slog::Logger>,
+    ) -> Result<Recipe, String> {
         // remove comment lines
         let lines: Vec<String> = recipe_text
             .lines()
@@ -249,9 +418,37 @@ impl Recipe {
         let cleaned_recipe_text = lines.join("\n");
 
         // parse and compute differences to current recipe
-        let parsed_queries = Recipe::parse(&cleaned_recipe_text)?;
+        let (mut parsed_queries, parsed_functions) = Recipe::parse(&cleaned_recipe_text)?;
+
+        // qualify all names in the namespace, so that the queries are distinct from identical
+        // ones in other namespaces, and nothing is shared between them.
+        if let Some(ns) = namespace {
+            namespace::validate(ns)?;
+            parsed_queries = parsed_queries
+                .into_iter()
+                .map(|(n, q, is_leaf)| {
+                    let n = n.map(|n| namespace::qualify(Some(ns), &n));
+                    Ok((n, q.qualify_names(ns)?, is_leaf))
+                })
+                .collect::<Result<_, String>>()?;
+        }
+
+        // compile the functions now, so that a recipe with a function that cannot run is rejected
+        let mut functions = HashMap::new();
+        for f in parsed_functions {
+            let name = namespace::qualify(namespace, &f.name);
+            let mut udf = Udf::new(&name, f.module, &f.export, f.params, f.returns)?;
+            if let Some(fuel) = f.fuel {
+                udf = udf.with_fuel(fuel);
+            }
+            if functions.insert(name.clone(), Arc::new(udf)).is_some() {
+                return Err(format!("function {} is registered more than once", name));
+            }
+        }
 
-        Ok(Recipe::from_queries(parsed_queries, log))
+        let mut recipe = Recipe::from_queries(parsed_queries, log);
+        recipe.functions = functions;
+        Ok(recipe)
     }
 
     /// Creates a recipe from a set of pre-parsed `SqlQuery` structures.
@@ -305,6 +502,7 @@ impl Recipe {
             expression_order,
             aliases,
             security_config: None,
+            functions: HashMap::default(),
             version: 0,
             prior: None,
             inc: Some(inc),
@@ -323,6 +521,7 @@ impl Recipe {
         let mut result = ActivationResult {
             new_nodes: HashMap::default(),
             removed_leaves: Vec::default(),
+            reused_nodes: HashMap::default(),
             expressions_added: 0,
             expressions_removed: 0,
         };
@@ -374,6 +573,10 @@ impl Recipe {
                 None => qfp.name.clone(),
             };
 
+            let shared = shared_nodes(mig, &qfp);
+            if !shared.is_empty() {
+                result.reused_nodes.insert(query_name.clone(), shared);
+            }
             result.new_nodes.insert(query_name, qfp.query_leaf);
         }
 
@@ -400,6 +603,7 @@ impl Recipe {
         let mut result = ActivationResult {
             new_nodes: HashMap::default(),
             removed_leaves: Vec::default(),
+            reused_nodes: HashMap::default(),
             expressions_added: added.len(),
             expressions_removed: removed.len(),
         };
@@ -454,12 +658,26 @@ impl Recipe {
         for qid in added {
             let (n, q, is_leaf) = self.expressions[&qid].clone();
 
-            // add the query
+            // add the query, saying which one failed, and how far it got, if it can't be added
+            let described = match n {
+                Some(ref name) => format!("query {}", name),
+                None => format!("query \"{}\"", q),
+            };
+            let built_before = mig.last_added();
             let qfp = self
                 .inc
                 .as_mut()
                 .unwrap()
-                .add_parsed_query(q, n.clone(), is_leaf, mig)?;
+                .add_parsed_query(q, n.clone(), is_leaf, mig)
+                .map_err(|e| match mig.last_added() {
+                    Some(ni) if Some(ni) != built_before => format!(
+                        "{}: {} (after building operator {})",
+                        described,
+                        e,
+                        mig.describe_added(ni)
+                    ),
+                    _ => format!("{}: {}", described, e),
+                })?;
 
             // If the user provided us with a query name, use that.
             // If not, use the name internally used by the QFP.
@@ -468,6 +686,10 @@ impl Recipe {
                 None => qfp.name.clone(),
             };
 
+            let shared = shared_nodes(mig, &qfp);
+            if !shared.is_empty() {
+                result.reused_nodes.insert(query_name.clone(), shared);
+            }
             result.new_nodes.insert(query_name, qfp.query_leaf);
         }
 
@@ -527,6 +749,31 @@ impl Recipe {
         (added_queries, removed_queries)
     }
 
+    /// The names of the tables and views that activating this recipe adds to those of its prior
+    /// recipe.
+    pub(super) fn added_names(&self) -> Vec<String> {
+        let (added, _) = match self.prior {
+            None => self.compute_delta(&Recipe::blank(None)),
+            Some(ref pr) => self.compute_delta(pr),
+        };
+        let mut names = Vec::new();
+        for qid in added {
+            match self.expressions[&qid] {
+                (_, SqlQuery::CreateTable(ref ctq), _) => names.push(ctq.table.name.clone()),
+                (ref name, ..) => names.extend(name.iter().cloned()),
+            }
+            names.extend(
+                self.aliases
+                    .iter()
+                    .filter(|&(_, &q)| q == qid)
+                    .map(|(name, _)| name.clone()),
+            );
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
     /// Returns the query expressions in the recipe.
     // crate viz for tests
     pub(crate) fn expressions(&self) -> Vec<(Option<&String>, &SqlQuery)> {
@@ -541,13 +788,27 @@ impl Recipe {
     /// recipe; use `replace` if removal of unused expressions is desired.
     /// Consumes `self` and returns a replacement recipe.
     // crate viz for tests
-    pub(crate) fn extend(mut self, additions: &str) -> Result<Recipe, (Recipe, String)> {
+    pub(crate) fn extend(self, additions: &str) -> Result<Recipe, (Recipe, String)> {
+        self.extend_in(additions, None)
+    }
+
+    /// Like `extend`, but puts the added queries in `namespace`.
+    pub(super) fn extend_in(
+        mut self,
+        additions: &str,
+        namespace: Option<&str>,
+    ) -> Result<Recipe, (Recipe, String)> {
         // parse and compute differences to current recipe
-        let add_rp = match Recipe::from_str(additions, None) {
+        let add_rp = match Recipe::from_str_in(additions, namespace, None) {
             Ok(rp) => rp,
             Err(e) => return Err((self, e)),
         };
         let (added, _) = add_rp.compute_delta(&self);
+        for (name, f) in &add_rp.functions {
+            if self.functions.get(name).map_or(false, |g| g != f) {
+                return Err((self, format!("function {} exists, but is different", name)));
+            }
+        }
 
         // move the incorporator state from the old recipe to the new one
         let prior_inc = self.inc.take();
@@ -561,9 +822,11 @@ impl Recipe {
             inc: prior_inc,
             log: self.log.clone(),
             security_config: self.security_config.clone(),
+            functions: self.functions.clone(),
             // retain the old recipe for future reference
             prior: Some(Box::new(self)),
         };
+        new.functions.extend(add_rp.functions);
 
         // apply changes
         for qid in added {
@@ -585,6 +848,197 @@ impl Recipe {
         Ok(new)
     }
 
+    /// Remove the view `name` from this recipe. Tables cannot be removed this way.
+    ///
+    /// If other views are defined by the very same query, only the name goes away, and the query
+    /// stays for the others.
+    /// Consumes `self` and returns a replacement recipe.
+    pub(super) fn remove_view(mut self, name: &str) -> Result<Recipe, (Recipe, String)> {
+        let is_table = |q: &SqlQuery| match *q {
+            SqlQuery::CreateTable(ref ctq) => ctq.table.name == name,
+            _ => false,
+        };
+        if self.expressions.values().any(|&(_, ref q, _)| is_table(q)) {
+            return Err((self, format!("{} is a table, not a view", name)));
+        }
+        let qid = match self.aliases.get(name) {
+            Some(&qid) => qid,
+            None => return Err((self, format!("no view named {}", name))),
+        };
+
+        // move the incorporator state from the old recipe to the new one
+        let prior_inc = self.inc.take();
+
+        let mut new = Recipe {
+            expressions: self.expressions.clone(),
+            expression_order: self.expression_order.clone(),
+            aliases: self.aliases.clone(),
+            version: self.version + 1,
+            inc: prior_inc,
+            log: self.log.clone(),
+            security_config: self.security_config.clone(),
+            functions: self.functions.clone(),
+            // retain the old recipe for future reference
+            prior: Some(Box::new(self)),
+        };
+
+        if new.aliases.values().filter(|&&q| q == qid).count() > 1 {
+            new.aliases.remove(name);
+        } else {
+            new.remove_query(name);
+        }
+        Ok(new)
+    }
+
+    /// Computes what replacing the tables and views in `namespace` with those in `candidate` would
+    /// change, without activating anything. The names in the diff are those within the namespace.
+    pub(super) fn diff(&self, candidate: &Recipe, namespace: Option<&str>) -> RecipeDiff {
+        let in_namespace = |queries: HashMap<String, QueryID>| -> HashMap<String, QueryID> {
+            queries
+                .into_iter()
+                .filter_map(|(name, qid)| {
+                    namespace::strip(namespace, &name).map(|name| (name.to_owned(), qid))
+                })
+                .collect()
+        };
+        let ours = in_namespace(self.named_queries());
+        let theirs = in_namespace(candidate.named_queries());
+
+        let mut diff = RecipeDiff {
+            version: self.version,
+            ..Default::default()
+        };
+        for (name, qid) in &theirs {
+            if ours.get(name) == Some(qid) {
+                diff.reused.push(name.clone());
+            } else {
+                diff.added.push(name.clone());
+            }
+        }
+        diff.removed = ours
+            .iter()
+            .filter(|&(name, qid)| theirs.get(name) != Some(qid))
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        diff.added.sort();
+        diff.removed.sort();
+        diff.reused.sort();
+        diff
+    }
+
+    /// The names of the tables and named views in the recipe.
+    pub(super) fn names(&self) -> Vec<String> {
+        self.named_queries()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// The queries behind the tables and named views. Unnamed queries cannot be read from, and
+    /// are left out.
+    fn named_queries(&self) -> HashMap<String, QueryID> {
+        let tables = self
+            .expressions
+            .iter()
+            .filter_map(|(&qid, &(_, ref q, _))| match *q {
+                SqlQuery::CreateTable(ref ctq) => Some((ctq.table.name.clone(), qid)),
+                _ => None,
+            });
+        self.aliases
+            .iter()
+            .map(|(name, &qid)| (name.clone(), qid))
+            .chain(tables)
+            .collect()
+    }
+
+    /// The changes that, made in order to a blank recipe, give the recipe at `version` in
+    /// `history`. None of them are rollbacks.
+    ///
+    /// An install only replaces the tables and views in its own namespace, and a rollback only
+    /// undoes the changes made in its own namespace, so the changes go all the way back to the
+    /// start of the history.
+    pub(super) fn changes_at(
+        history: &[RecipeVersion],
+        version: usize,
+    ) -> Result<Vec<&RecipeVersion>, String> {
+        let end = history
+            .iter()
+            .position(|v| v.version == version)
+            .ok_or_else(|| format!("no recipe version {} in the history", version))?;
+
+        // the latest version whose changes still count, in each namespace that was rolled back
+        let mut rolled_back: HashMap<Option<&str>, usize> = HashMap::new();
+        let mut changes = Vec::new();
+        for entry in history[..=end].iter().rev() {
+            let ns = entry.namespace.as_deref();
+            if rolled_back.get(&ns).map_or(false, |&to| entry.version > to) {
+                continue;
+            }
+            match entry.change {
+                RecipeChange::Rollback(to) => {
+                    if !history.iter().any(|v| v.version == to) {
+                        return Err(format!("no recipe version {} in the history", to));
+                    }
+                    rolled_back.insert(ns, to);
+                }
+                _ => changes.push(entry),
+            }
+        }
+        changes.reverse();
+        Ok(changes)
+    }
+
+    /// Rebuilds the recipe at `version` in `history`, without activating it.
+    pub(super) fn at_version(
+        history: &[RecipeVersion],
+        version: usize,
+        log: Option<slog::Logger>,
+    ) -> Result<Recipe, String> {
+        let mut r = Recipe::blank(log.clone());
+        for change in Recipe::changes_at(history, version)? {
+            r = r.apply(change, log.clone())?;
+        }
+        Ok(r)
+    }
+
+    /// Rebuilds the tables and views in `namespace` as they were at `version` in `history`, without
+    /// activating them.
+    pub(super) fn at_version_in(
+        history: &[RecipeVersion],
+        version: usize,
+        namespace: Option<&str>,
+        log: Option<slog::Logger>,
+    ) -> Result<Recipe, String> {
+        let mut r = Recipe::blank(log.clone());
+        for change in Recipe::changes_at(history, version)? {
+            if change.namespace.as_deref() == namespace {
+                r = r.apply(change, log.clone())?;
+            }
+        }
+        Ok(r)
+    }
+
+    /// Make the given change from the history to this recipe. The change cannot be a rollback.
+    /// Consumes `self` and returns a replacement recipe.
+    pub(super) fn apply(
+        self,
+        change: &RecipeVersion,
+        log: Option<slog::Logger>,
+    ) -> Result<Recipe, String> {
+        let ns = change.namespace.as_deref();
+        match change.change {
+            RecipeChange::Install(ref txt) => {
+                self.replace_in(Recipe::from_str_in(txt, ns, log)?, ns)
+            }
+            RecipeChange::Extend(ref txt) => self.extend_in(txt, ns).map_err(|(_, e)| e),
+            RecipeChange::RemoveView(ref name) => self
+                .remove_view(&namespace::qualify(ns, name))
+                .map_err(|(_, e)| e),
+            RecipeChange::Rollback(_) => unreachable!(),
+        }
+    }
+
     /// Helper method to reparent a recipe. This is needed for the recovery logic to build
     /// recovery and original recipe (see `make_recovery`).
     pub(in crate::controller) fn set_prior(&mut self, new_prior: Recipe) {
@@ -596,12 +1050,20 @@ impl Recipe {
         self.inc.as_ref().unwrap()
     }
 
+    /// Point the recipe's queries at the given replacements for their dataflow nodes.
+    pub(super) fn remap_nodes(&mut self, copies: &HashMap<NodeIndex, NodeIndex>) {
+        self.inc.as_mut().unwrap().remap_nodes(copies);
+    }
+
     /// Helper method to reparent a recipe. This is needed for some of t
     pub(in crate::controller) fn set_sql_inc(&mut self, new_inc: SqlIncorporator) {
         self.inc = Some(new_inc);
     }
 
-    fn parse(recipe_text: &str) -> Result<Vec<(Option<String>, SqlQuery, bool)>, String> {
+    #[allow(clippy::type_complexity)]
+    fn parse(
+        recipe_text: &str,
+    ) -> Result<(Vec<(Option<String>, SqlQuery, bool)>, Vec<FunctionExpr>), String> {
         let lines: Vec<&str> = recipe_text
             .lines()
             .filter(|l| !l.is_empty() && !l.starts_with('#'))
@@ -634,8 +1096,8 @@ impl Recipe {
 
         let parsed_queries = query_strings.iter().fold(
             Vec::new(),
-            |mut acc: Vec<Result<(bool, Option<&str>, SqlQuery), String>>, q| {
-                match query_exprs(q) {
+            |mut acc: Vec<Result<RecipeExpr<'_>, String>>, q| {
+                match recipe_exprs(q) {
                     Result::Err(e) => {
                         // we got a parse error
                         acc.push(Err(format!("Query \"{}\", parse error: {}", q, e)));
@@ -656,13 +1118,17 @@ impl Recipe {
             },
         );
 
-        Ok(parsed_queries
-            .into_iter()
-            .map(|pr| {
-                let pr = pr.unwrap();
-                (pr.1.map(String::from), pr.2, pr.0)
-            })
-            .collect::<Vec<_>>())
+        let mut queries = Vec::new();
+        let mut functions = Vec::new();
+        for pr in parsed_queries {
+            match pr.unwrap() {
+                RecipeExpr::Query(public, name, q) => {
+                    queries.push((name.map(String::from), q, public))
+                }
+                RecipeExpr::Function(f) => functions.push(f),
+            }
+        }
+        Ok((queries, functions))
     }
 
     /// Returns the predecessor from which this `Recipe` was migrated to.
@@ -709,6 +1175,44 @@ impl Recipe {
         Ok(new)
     }
 
+    /// Replace the tables and queries in `namespace` with those in `new`, which must all be in that
+    /// namespace, and keep those in other namespaces as they are.
+    /// Consumes `self` and returns a replacement recipe.
+    pub(super) fn replace_in(
+        self,
+        mut new: Recipe,
+        namespace: Option<&str>,
+    ) -> Result<Recipe, String> {
+        let kept: Vec<QueryID> = self
+            .expression_order
+            .iter()
+            .filter(|qid| {
+                let (ref n, ref q, _) = self.expressions[qid];
+                namespace_of(n.as_ref(), q).as_deref() != namespace
+                    && !new.expressions.contains_key(qid)
+            })
+            .cloned()
+            .collect();
+
+        for qid in &kept {
+            new.expressions.insert(*qid, self.expressions[qid].clone());
+        }
+        for (n, qid) in &self.aliases {
+            if kept.contains(qid) {
+                new.aliases.insert(n.clone(), *qid);
+            }
+        }
+        // the kept queries were all added before any of the new ones
+        new.expression_order = kept.into_iter().chain(new.expression_order).collect();
+        for (name, f) in &self.functions {
+            if namespace::split(name).0 != namespace && !new.functions.contains_key(name) {
+                new.functions.insert(name.clone(), f.clone());
+            }
+        }
+
+        self.replace(new)
+    }
+
     /// Increments the version of a recipe. Returns the new version number.
     pub(super) fn next(&mut self) -> usize {
         self.version += 1;
@@ -721,6 +1225,33 @@ impl Recipe {
         self.version
     }
 
+    /// Go back to the prior version of a recipe that has not been activated, handing the prior
+    /// recipe back the incorporator state it passed on.
+    pub(super) fn abandon(mut self) -> Recipe {
+        let inc = self.inc.take();
+        let mut prior = self.revert();
+        if inc.is_some() {
+            prior.inc = inc;
+        }
+        prior
+    }
+
+    /// A copy of the incorporator state, for `roll_back` to go back to if activating the recipe
+    /// fails part way through.
+    pub(super) fn checkpoint(&self) -> Option<SqlIncorporator> {
+        self.inc.as_ref().map(SqlIncorporator::duplicate)
+    }
+
+    /// Go back to the prior version of a recipe whose activation failed, with the incorporator
+    /// state that `checkpoint` saved before the activation began.
+    pub(super) fn roll_back(self, checkpoint: Option<SqlIncorporator>) -> Recipe {
+        let mut prior = self.revert();
+        if checkpoint.is_some() {
+            prior.inc = checkpoint;
+        }
+        prior
+    }
+
     /// Reverts to prior version of recipe
     pub(super) fn revert(self) -> Recipe {
         if let Some(prior) = self.prior {
@@ -742,6 +1273,14 @@ impl Recipe {
             .collect()
     }
 
+    /// A copy of the recipe that can be activated without changing what this recipe knows about
+    /// the dataflow.
+    pub(super) fn duplicate(&self) -> Recipe {
+        let mut copy = self.clone();
+        copy.inc = self.inc.as_ref().map(SqlIncorporator::duplicate);
+        copy
+    }
+
     pub(super) fn make_recovery(&self, mut affected_queries: Vec<String>) -> (Recipe, Recipe) {
         affected_queries.sort();
         affected_queries.dedup();
@@ -847,6 +1386,182 @@ mod tests {
         assert_eq!(r1.resolve_alias("q_1"), r1.resolve_alias("q_0"));
     }
 
+    #[test]
+    fn it_removes_views() {
+        let r0 = Recipe::blank(None);
+
+        let r1_txt = "CREATE TABLE b (a int, c int, x int);\n\
+                      q_0: SELECT a FROM b;\n\
+                      q_1: SELECT a FROM b;\n\
+                      q_2: SELECT a, c FROM b WHERE x = 42;";
+        let r1_t = Recipe::from_str(r1_txt, None).unwrap();
+        let r1 = r0.replace(r1_t).unwrap();
+        assert_eq!(r1.expressions.len(), 3);
+
+        let r2 = r1.remove_view("q_2").unwrap();
+        assert_eq!(r2.version, 2);
+        assert_eq!(r2.expressions.len(), 2);
+        assert_eq!(r2.resolve_alias("q_2"), None);
+
+        // q_1 is the same query as q_0, which must stay around for it
+        let r3 = r2.remove_view("q_0").unwrap();
+        assert_eq!(r3.expressions.len(), 2);
+        assert_eq!(r3.resolve_alias("q_0"), None);
+        assert!(r3.resolve_alias("q_1").is_some());
+
+        let (r3, e) = r3.remove_view("b").unwrap_err();
+        assert_eq!(e, "b is a table, not a view");
+        let (_, e) = r3.remove_view("q_2").unwrap_err();
+        assert_eq!(e, "no view named q_2");
+    }
+
+    #[test]
+    fn it_diffs_recipes() {
+        let r0 = Recipe::blank(None);
+        let r1_txt = "CREATE TABLE b (a int, c int, x int);\n\
+                      q_0: SELECT a FROM b;\n\
+                      q_1: SELECT a, c FROM b WHERE x = 42;";
+        let r1 = r0.replace(Recipe::from_str(r1_txt, None).unwrap()).unwrap();
+
+        let r2_txt = "CREATE TABLE b (a int, c int, x int);\n\
+                      q_0: SELECT a FROM b;\n\
+                      q_1: SELECT a, c FROM b WHERE x = 7;\n\
+                      q_2: SELECT c FROM b;";
+        let diff = r1.diff(&Recipe::from_str(r2_txt, None).unwrap(), None);
+        assert_eq!(diff.version, 1);
+        assert_eq!(diff.added, vec!["q_1", "q_2"]);
+        assert_eq!(diff.removed, vec!["q_1"]);
+        assert_eq!(diff.reused, vec!["b", "q_0"]);
+    }
+
+    #[test]
+    fn it_keeps_namespaces_apart() {
+        let txt = "CREATE TABLE Article (id int, votes int);\n\
+                   q_0: SELECT id FROM Article WHERE votes > 10;";
+        let in_ns = |txt: &str, ns: &str| Recipe::from_str_in(txt, Some(ns), None).unwrap();
+
+        let r1 = Recipe::blank(None)
+            .replace_in(in_ns(txt, "blog"), Some("blog"))
+            .unwrap();
+        let r2 = r1.replace_in(in_ns(txt, "shop"), Some("shop")).unwrap();
+        assert_eq!(r2.expressions.len(), 4);
+        assert!(r2.resolve_alias("blog:q_0").is_some());
+        assert_ne!(r2.resolve_alias("blog:q_0"), r2.resolve_alias("shop:q_0"));
+        assert_eq!(r2.resolve_alias("q_0"), None);
+
+        // installing into one namespace leaves the others alone
+        let r3 = r2
+            .replace_in(
+                in_ns("CREATE TABLE Article (id int, votes int);", "blog"),
+                Some("blog"),
+            )
+            .unwrap();
+        assert_eq!(r3.expressions.len(), 3);
+        assert_eq!(r3.resolve_alias("blog:q_0"), None);
+        assert!(r3.resolve_alias("shop:q_0").is_some());
+
+        let candidate = in_ns(
+            "CREATE TABLE Article (id int, votes int);\nq_1: SELECT votes FROM Article;",
+            "shop",
+        );
+        let diff = r3.diff(&candidate, Some("shop"));
+        assert_eq!(diff.added, vec!["q_1"]);
+        assert_eq!(diff.removed, vec!["q_0"]);
+        assert_eq!(diff.reused, vec!["Article"]);
+    }
+
+    #[test]
+    fn it_rebuilds_recipes_from_history() {
+        let history = vec![
+            RecipeVersion {
+                version: 1,
+                change: RecipeChange::Install(
+                    "CREATE TABLE b (a int, c int);\nq_0: SELECT a FROM b;".to_owned(),
+                ),
+                namespace: None,
+            },
+            RecipeVersion {
+                version: 2,
+                change: RecipeChange::Extend("q_1: SELECT c FROM b;".to_owned()),
+                namespace: None,
+            },
+            RecipeVersion {
+                version: 3,
+                change: RecipeChange::RemoveView("q_0".to_owned()),
+                namespace: None,
+            },
+            RecipeVersion {
+                version: 4,
+                change: RecipeChange::Rollback(2),
+                namespace: None,
+            },
+            RecipeVersion {
+                version: 5,
+                change: RecipeChange::Extend("q_2: SELECT a, c FROM b;".to_owned()),
+                namespace: None,
+            },
+        ];
+
+        let r3 = Recipe::at_version(&history, 3, None).unwrap();
+        assert_eq!(r3.resolve_alias("q_0"), None);
+        assert!(r3.resolve_alias("q_1").is_some());
+
+        // the rollback brings back q_0, and later changes build on top of it
+        assert_eq!(Recipe::changes_at(&history, 5).unwrap().len(), 3);
+        let r5 = Recipe::at_version(&history, 5, None).unwrap();
+        assert_eq!(r5.expressions.len(), 4);
+        assert!(r5.resolve_alias("q_0").is_some());
+
+        assert!(Recipe::at_version(&history, 6, None).is_err());
+    }
+
+    #[test]
+    fn it_rolls_back_one_namespace() {
+        let change = |version, change, namespace: Option<&str>| RecipeVersion {
+            version,
+            change,
+            namespace: namespace.map(String::from),
+        };
+        let history = vec![
+            change(
+                1,
+                RecipeChange::Install("CREATE TABLE b (a int, c int);".to_owned()),
+                Some("x"),
+            ),
+            change(
+                2,
+                RecipeChange::Install("CREATE TABLE d (e int);".to_owned()),
+                Some("y"),
+            ),
+            change(
+                3,
+                RecipeChange::Extend("q_0: SELECT a FROM b;".to_owned()),
+                Some("x"),
+            ),
+            change(
+                4,
+                RecipeChange::Extend("q_1: SELECT e FROM d;".to_owned()),
+                Some("y"),
+            ),
+            change(5, RecipeChange::Rollback(2), Some("x")),
+        ];
+
+        // only the change made in the rolled back namespace since version 2 is undone
+        let changes: Vec<_> = Recipe::changes_at(&history, 5)
+            .unwrap()
+            .into_iter()
+            .map(|v| v.version)
+            .collect();
+        assert_eq!(changes, vec![1, 2, 4]);
+        let r5 = Recipe::at_version(&history, 5, None).unwrap();
+        assert_eq!(r5.resolve_alias("x:q_0"), None);
+        assert!(r5.resolve_alias("y:q_1").is_some());
+
+        let x = Recipe::at_version_in(&history, 3, Some("x"), None).unwrap();
+        assert_eq!(x.expressions.len(), 2);
+        assert!(x.resolve_alias("x:q_0").is_some());
+    }
+
     #[test]
     #[should_panic(expected = "Query name exists but existing query is different")]
     fn it_avoids_spurious_aliasing() {
@@ -896,4 +1611,27 @@ mod tests {
         let r1 = r0.replace(r1_t).unwrap();
         assert_eq!(r1.expressions.len(), 2);
     }
+
+    #[test]
+    fn it_parses_functions() {
+        let r_txt = "FUNCTION score(INT, text) RETURNS int AS score_v2\n\
+                     IN X'0061 736d' FUEL 500; QUERY q_0: SELECT a FROM b;\n\
+                     function noop() returns BOOL as noop in x'';";
+        let (queries, functions) = Recipe::parse(r_txt).unwrap();
+        assert_eq!(queries.len(), 1);
+        assert_eq!(functions.len(), 2);
+
+        let score = &functions[0];
+        assert_eq!(score.name, "score");
+        assert_eq!(score.params, vec![UdfType::Int, UdfType::Text]);
+        assert_eq!(score.returns, UdfType::Int);
+        assert_eq!(score.export, "score_v2");
+        assert_eq!(score.module, vec![0x00, 0x61, 0x73, 0x6d]);
+        assert_eq!(score.fuel, Some(500));
+
+        let noop = &functions[1];
+        assert!(noop.params.is_empty());
+        assert!(noop.module.is_empty());
+        assert_eq!(noop.fuel, None);
+    }
 }
@@ -0,0 +1,55 @@
+use noria::RecoveryStatus;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+struct State {
+    started: Instant,
+    ended: Option<Instant>,
+    bases: BTreeMap<String, Option<u64>>,
+}
+
+/// How far the controller is with restoring the dataflow over base tables that a previous
+/// controller created, if it took over from one.
+///
+/// Like migration progress, this is shared with the handler of external requests, since the
+/// controller answers nothing else until recovery is done.
+#[derive(Clone, Default)]
+pub(crate) struct Recovery(Arc<Mutex<Option<State>>>);
+
+impl Recovery {
+    /// Start restoring the given base tables.
+    pub(crate) fn start<I: IntoIterator<Item = String>>(&self, bases: I) {
+        *self.0.lock().unwrap() = Some(State {
+            started: Instant::now(),
+            ended: None,
+            bases: bases.into_iter().map(|b| (b, None)).collect(),
+        });
+    }
+
+    /// Record that `base` has been restored with `rows` rows, and that those rows have reached
+    /// every materialization that has been restored so far.
+    pub(crate) fn restored(&self, base: &str, rows: u64) {
+        if let Some(ref mut state) = *self.0.lock().unwrap() {
+            state.bases.insert(base.to_owned(), Some(rows));
+        }
+    }
+
+    /// Record that the whole dataflow has been restored.
+    pub(crate) fn finish(&self) {
+        if let Some(ref mut state) = *self.0.lock().unwrap() {
+            state.ended = Some(Instant::now());
+        }
+    }
+
+    /// How far recovery is, if the controller had anything to recover.
+    pub(crate) fn report(&self) -> Option<RecoveryStatus> {
+        let state = self.0.lock().unwrap();
+        let state = state.as_ref()?;
+        Some(RecoveryStatus {
+            complete: state.ended.is_some(),
+            elapsed: state.ended.unwrap_or_else(Instant::now) - state.started,
+            bases: state.bases.clone(),
+        })
+    }
+}
@@ -1,8 +1,10 @@
 use super::keys::provenance_of;
 use super::recipe::{Recipe, Schema};
 use dataflow::ops;
+use dataflow::ops::project::{DateFunction, ProjectExpression};
+use dataflow::ops::udf::UdfType;
 use dataflow::prelude::*;
-use nom_sql::{Column, ColumnSpecification, SqlType};
+use nom_sql::{Column, ColumnConstraint, ColumnSpecification, SqlType};
 
 use slog;
 
@@ -13,17 +15,26 @@ type Path = std::vec::Vec<(
 
 fn to_sql_type(d: &DataType) -> Option<SqlType> {
     match d {
+        DataType::Bool(_) => Some(SqlType::Bool),
         DataType::Int(_) => Some(SqlType::Int(32)),
         DataType::UnsignedInt(_) => Some(SqlType::UnsignedInt(32)),
         DataType::BigInt(_) => Some(SqlType::Bigint(64)),
         DataType::UnsignedBigInt(_) => Some(SqlType::UnsignedBigint(64)),
+        // there is no wider integer type, but a 38-digit decimal holds any 128-bit integer
+        DataType::HugeInt(_) => Some(SqlType::Decimal(38, 0)),
         DataType::Real(_, _) => Some(SqlType::Real),
-        DataType::Text(_) => Some(SqlType::Text),
+        // an i64 mantissa holds any 18-digit decimal
+        DataType::Decimal(_, scale) => Some(SqlType::Decimal(18, *scale)),
+        DataType::Text(_) | DataType::CiText(_) => Some(SqlType::Text),
         DataType::TinyText(_) => Some(SqlType::Varchar(8)),
         // TODO(malte): There is no SqlType for `NULL` (as it's not a
         // type), so caller must handle appropriately.
         DataType::None => None,
         DataType::Timestamp(_) => Some(SqlType::Timestamp),
+        DataType::Date(_) => Some(SqlType::Date),
+        DataType::Uuid(_) => Some(SqlType::Binary(16)),
+        DataType::Json(_) => Some(SqlType::Text),
+        DataType::Bytes(_) => Some(SqlType::Blob),
     }
 }
 
@@ -43,9 +54,23 @@ fn type_for_internal_column(
             assert!(column_index >= emits.0.len());
             if column_index < emits.0.len() + emits.2.len() {
                 // computed expression
-                // TODO(malte): trace the actual column types, since this could be a
-                // real-valued arithmetic operation
-                Some(SqlType::Bigint(64))
+                match emits.2[column_index - emits.0.len()] {
+                    ProjectExpression::Date(DateFunction::Year, _)
+                    | ProjectExpression::Date(DateFunction::Month, _) => Some(SqlType::Int(32)),
+                    ProjectExpression::Date(DateFunction::Date, _) => Some(SqlType::Date),
+                    // the extracted value's type depends on the document
+                    ProjectExpression::JsonExtract(..) => Some(SqlType::Text),
+                    ProjectExpression::Comparison { .. } => Some(SqlType::Bool),
+                    ProjectExpression::Udf(ref f, _) => Some(match f.returns() {
+                        UdfType::Int => SqlType::Bigint(64),
+                        UdfType::Double => SqlType::Double,
+                        UdfType::Bool => SqlType::Bool,
+                        UdfType::Text => SqlType::Text,
+                    }),
+                    // TODO(malte): trace the actual column types, since this could be a
+                    // real-valued arithmetic operation
+                    ProjectExpression::Arithmetic { .. } => Some(SqlType::Bigint(64)),
+                }
             } else {
                 // literal
                 let off = column_index - (emits.0.len() + emits.2.len());
@@ -149,6 +174,26 @@ fn trace_column_type_on_path(
     }
 }
 
+/// The collation of the base table column that a path traces a column back to, if it has one.
+fn collation_on_path(path: &Path, graph: &Graph, recipe: &Recipe) -> Option<ColumnConstraint> {
+    let (ni, cols) = path
+        .iter()
+        .rev()
+        .find(|e| e.1.iter().any(Option::is_some))?;
+    let source_node = &graph[*ni];
+    if !source_node.is_base() {
+        return None;
+    }
+    match recipe.schema_for(source_node.name())? {
+        Schema::Table(s) => s.fields[cols[0]?]
+            .constraints
+            .iter()
+            .find(|c| matches!(c, ColumnConstraint::Collation(_)))
+            .cloned(),
+        _ => unreachable!(),
+    }
+}
+
 pub(super) fn column_schema(
     graph: &Graph,
     view: NodeIndex,
@@ -166,15 +211,19 @@ pub(super) fn column_schema(
     let vn = &graph[view];
 
     let mut col_type = None;
+    let mut collation = None;
     for p in paths {
         trace!(log, "considering path {:?}", p);
+        // lookup keys must be collated like the values they are looked up in
+        let c = collation_on_path(&p, graph, recipe);
         if let t @ Some(_) = trace_column_type_on_path(p, graph, recipe, log) {
             col_type = t;
+            collation = c;
         }
     }
 
     // found something, so return a ColumnSpecification
-    let cs = ColumnSpecification::new(
+    let mut cs = ColumnSpecification::new(
         Column {
             name: vn.fields()[column_index].to_owned(),
             table: Some(vn.name().to_owned()),
@@ -184,5 +233,6 @@ pub(super) fn column_schema(
         // ? in case we found no schema for this column
         col_type?,
     );
+    cs.constraints.extend(collation);
     Some(cs)
 }
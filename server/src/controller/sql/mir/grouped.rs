@@ -9,6 +9,14 @@ use nom_sql::{
 use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
 
+/// The column a function is computed over. This drops the marker that a rewritten `COUNT(*)`
+/// puts on the column it counts over, which is the only function an argument column carries.
+pub(super) fn over_column(col: &nom_sql::Column) -> Column {
+    let mut col = Column::from(col);
+    col.function = None;
+    col
+}
+
 fn target_columns_from_computed_column(computed_col: &nom_sql::Column) -> Column {
     use nom_sql::FunctionExpression::*;
 
@@ -32,7 +40,7 @@ fn target_columns_from_computed_column(computed_col: &nom_sql::Column) -> Column
             }),
             _,
         )
-        | Sum(FunctionArguments::Column(ref col), _) => Column::from(col),
+        | Sum(FunctionArguments::Column(ref col), _) => over_column(col),
         CountStar => {
             // see comment re COUNT(*) rewriting in make_aggregation_node
             panic!("COUNT(*) should have been rewritten earlier!")
@@ -7,6 +7,7 @@ use petgraph::graph::NodeIndex;
 use dataflow::ops::filter::FilterCondition;
 use dataflow::ops::join::JoinType;
 
+use crate::controller::sql::passes::count_star_rewrite::is_count_star_column;
 use crate::controller::sql::query_graph::{OutputColumn, QueryGraph};
 use crate::controller::sql::query_signature::Signature;
 use nom_sql::{
@@ -49,10 +50,9 @@ fn predicate_columns(ce: &ConditionExpression) -> HashSet<Column> {
         Base(ConditionBase::Field(ref c)) => {
             cols.insert(Column::from(c));
         }
-        Bracketed(ref ce) => {
+        Bracketed(ref ce) | NegationOp(ref ce) => {
             cols.extend(predicate_columns(&ce));
         }
-        NegationOp(_) => unreachable!("negations should have been eliminated"),
         _ => (),
     }
 
@@ -102,6 +102,8 @@ pub(super) struct SqlToMirConverter {
     log: slog::Logger,
     nodes: HashMap<(String, usize), MirNodeRef>,
     schema_version: usize,
+    /// Whether leaves keyed on several columns keep their keys in order for prefix lookups.
+    prefix_lookups: bool,
 
     /// Universe in which the conversion is happening
     universe: Universe,
@@ -115,6 +117,7 @@ impl Default for SqlToMirConverter {
             log: slog::Logger::root(slog::Discard, o!()),
             nodes: HashMap::default(),
             schema_version: 0,
+            prefix_lookups: false,
             universe: Universe::default(),
         }
     }
@@ -136,6 +139,11 @@ impl SqlToMirConverter {
         self.universe = universe;
     }
 
+    /// Make leaves keyed on several columns ordered, so that they can serve prefix lookups.
+    pub(super) fn enable_prefix_lookups(&mut self) {
+        self.prefix_lookups = true;
+    }
+
     /// Set the universe to a policy-free universe
     pub(super) fn clear_universe(&mut self) {
         self.universe = Universe::default();
@@ -176,7 +184,7 @@ impl SqlToMirConverter {
                         self.logical_op_to_conditions(ct2, columns, n)
                     }
                     ConditionExpression::ComparisonOp(ref ct2) => {
-                        self.to_conditions(ct2, columns, n)
+                        self.to_conditions(ct2, columns, n, false)
                     }
                     _ => unimplemented!(),
                 };
@@ -185,7 +193,7 @@ impl SqlToMirConverter {
                         self.logical_op_to_conditions(ct2, columns, n)
                     }
                     ConditionExpression::ComparisonOp(ref ct2) => {
-                        self.to_conditions(ct2, columns, n)
+                        self.to_conditions(ct2, columns, n, false)
                     }
                     _ => unimplemented!(),
                 };
@@ -196,13 +204,32 @@ impl SqlToMirConverter {
         }
     }
 
+    /// Turn a constant compared with column `c` into the form the column holds its values in, if
+    /// `c` is a base table column, such as a UUID given in its text form.
+    fn compared_value(&self, c: &nom_sql::Column, mut v: DataType) -> DataType {
+        let spec = c.table.as_ref().and_then(|table| {
+            let (_, specs) = self.base_schemas.get(table)?.last()?;
+            specs.iter().find(|cs| cs.column.name == c.name)
+        });
+        if let Some(spec) = spec.filter(|_| v.is_string()) {
+            // the incorporator has already made sure that strings fit the column
+            v.coerce_to_column(spec)
+                .expect("constant does not fit the column it is compared with");
+        }
+        v
+    }
+
     /// Converts a condition tree stored in the `ConditionExpr` returned by the SQL parser
     /// and adds its to a vector of conditions.
+    ///
+    /// `negated` is set for membership tests that appeared as `NOT IN`, which are the only
+    /// negations left after negation removal.
     fn to_conditions(
         &self,
         ct: &ConditionTree,
         columns: &mut Vec<Column>,
         n: &MirNodeRef,
+        negated: bool,
     ) -> Vec<(usize, FilterCondition)> {
         use std::cmp::max;
 
@@ -212,6 +239,7 @@ impl SqlToMirConverter {
             _ => unimplemented!(),
         };
         use dataflow::ops::filter;
+        assert!(!negated || ct.operator == Operator::In);
         let f = match *ct.right.as_ref() {
             ConditionExpression::Base(ConditionBase::Literal(Literal::Integer(ref i))) => {
                 FilterCondition::Comparison(
@@ -222,7 +250,7 @@ impl SqlToMirConverter {
             ConditionExpression::Base(ConditionBase::Literal(Literal::String(ref s))) => {
                 FilterCondition::Comparison(
                     ct.operator.clone(),
-                    filter::Value::Constant(DataType::from(s.clone())),
+                    filter::Value::Constant(self.compared_value(&l, DataType::from(s.clone()))),
                 )
             }
             ConditionExpression::Base(ConditionBase::Literal(Literal::Null)) => {
@@ -232,7 +260,15 @@ impl SqlToMirConverter {
                 )
             }
             ConditionExpression::Base(ConditionBase::LiteralList(ref ll)) => {
-                FilterCondition::In(ll.iter().map(|l| DataType::from(l.clone())).collect())
+                let values = ll
+                    .iter()
+                    .map(|v| self.compared_value(&l, DataType::from(v.clone())))
+                    .collect::<Vec<_>>();
+                if negated {
+                    FilterCondition::NotIn(values.into())
+                } else {
+                    FilterCondition::In(values.into())
+                }
             }
             ConditionExpression::Base(ConditionBase::Field(ref f)) => {
                 // NOTE(jon): the uwnrap here is almost certainly wrong given the business
@@ -335,6 +371,8 @@ impl SqlToMirConverter {
             MirNodeType::Leaf {
                 node: parent.clone(),
                 keys: Vec::from(params),
+                ordered: false,
+                row_order: None,
             },
             vec![n],
             vec![],
@@ -419,6 +457,8 @@ impl SqlToMirConverter {
                 MirNodeType::Leaf {
                     node: final_node.clone(),
                     keys: vec![],
+                    ordered: false,
+                    row_order: None,
                 },
                 vec![final_node.clone()],
                 vec![],
@@ -456,6 +496,15 @@ impl SqlToMirConverter {
         }
     }
 
+    /// All MIR nodes the converter has made, across schema versions.
+    pub(super) fn nodes(&self) -> impl Iterator<Item = &MirNodeRef> {
+        self.nodes.values()
+    }
+
+    pub(super) fn nodes_mut(&mut self) -> impl Iterator<Item = &mut MirNodeRef> {
+        self.nodes.values_mut()
+    }
+
     pub(super) fn get_leaf(&self, name: &str) -> Option<NodeIndex> {
         match self.current.get(name) {
             None => None,
@@ -961,10 +1010,16 @@ impl SqlToMirConverter {
         )
     }
 
-    fn make_filter_node(&self, name: &str, parent: MirNodeRef, cond: &ConditionTree) -> MirNodeRef {
+    fn make_filter_node(
+        &self,
+        name: &str,
+        parent: MirNodeRef,
+        cond: &ConditionTree,
+        negated: bool,
+    ) -> MirNodeRef {
         let mut fields = parent.borrow().columns().to_vec();
 
-        let filter = self.to_conditions(cond, &mut fields, &parent);
+        let filter = self.to_conditions(cond, &mut fields, &parent, negated);
         trace!(
             self.log,
             "Added filter node {} with condition {:?}",
@@ -1067,6 +1122,19 @@ impl SqlToMirConverter {
                 false,
                 Some(condition),
             ),
+            Count(FunctionArguments::Column(ref col), distinct) if is_count_star_column(col) => {
+                // there is no "over" column, but our aggregation operators' API requires one to
+                // be specified, so we earlier rewrote it to use some parent column (see
+                // passes/count_star_rewrite.rs). COUNT_STAR ignores its value, so rows where it
+                // is NULL are still counted.
+                mknode(
+                    &grouped::over_column(col),
+                    None,
+                    GroupedNodeType::Aggregation(Aggregation::COUNT_STAR),
+                    distinct,
+                    None,
+                )
+            }
             Count(FunctionArguments::Column(ref col), distinct) => mknode(
                 &Column::from(col),
                 None,
@@ -1074,15 +1142,7 @@ impl SqlToMirConverter {
                 distinct,
                 None,
             ),
-            CountStar => {
-                // XXX(malte): there is no "over" column, but our aggregation operators' API
-                // requires one to be specified, so we earlier rewrote it to use the last parent
-                // column (see passes/count_star_rewrite.rs). However, this isn't *entirely*
-                // faithful to COUNT(*) semantics, because COUNT(*) is supposed to count all
-                // rows including those with NULL values, and we don't have a mechanism to do that
-                // (but we also don't have a NULL value, so maybe we're okay).
-                panic!("COUNT(*) should have been rewritten earlier!")
-            }
+            CountStar => panic!("COUNT(*) should have been rewritten earlier!"),
             Count(
                 FunctionArguments::Conditional(CaseWhenExpression {
                     ref condition,
@@ -1194,9 +1254,16 @@ impl SqlToMirConverter {
                     LogicalOp(ref ct) => {
                         self.logical_op_to_conditions(ct, &mut fields, &parent_node)
                     }
-                    ComparisonOp(ref ct) => self.to_conditions(ct, &mut fields, &parent_node),
+                    ComparisonOp(ref ct) => {
+                        self.to_conditions(ct, &mut fields, &parent_node, false)
+                    }
                     Bracketed(_) => unimplemented!(),
-                    NegationOp(_) => unreachable!("negation should have been removed earlier"),
+                    NegationOp(ref inner) => match **inner {
+                        ComparisonOp(ref ct) => {
+                            self.to_conditions(ct, &mut fields, &parent_node, true)
+                        }
+                        _ => unreachable!("negation should have been removed earlier"),
+                    },
                     Base(_) => unreachable!("dangling base predicate"),
                     Arithmetic(_) => unimplemented!(),
                 };
@@ -1502,14 +1569,21 @@ impl SqlToMirConverter {
             ComparisonOp(ref ct) => {
                 // currently, we only support filter-like
                 // comparison operations, no nested-selections
-                let f = self.make_filter_node(&format!("{}_f{}", name, nc), parent, ct);
+                let f = self.make_filter_node(&format!("{}_f{}", name, nc), parent, ct, false);
 
                 pred_nodes.push(f);
             }
             Bracketed(ref inner) => {
                 pred_nodes.extend(self.make_predicate_nodes(name, parent, &*inner, nc));
             }
-            NegationOp(_) => unreachable!("negation should have been removed earlier"),
+            NegationOp(ref inner) => match **inner {
+                // only `NOT IN` survives negation removal
+                ComparisonOp(ref ct) => {
+                    let f = self.make_filter_node(&format!("{}_f{}", name, nc), parent, ct, true);
+                    pred_nodes.push(f);
+                }
+                _ => unreachable!("negation should have been removed earlier"),
+            },
             Base(_) => unreachable!("dangling base predicate"),
             Arithmetic(_) => unimplemented!(),
         }
@@ -2074,6 +2148,30 @@ impl SqlToMirConverter {
                     qg.parameters().into_iter().map(Column::from).collect()
                 };
 
+                let row_order = match qg.row_order {
+                    Some(ref order) => {
+                        let order: Vec<_> = order
+                            .iter()
+                            .map(|(c, o)| (Column::from(c), o.clone()))
+                            .collect();
+                        let projected = leaf_project_node.borrow();
+                        if let Some((c, _)) =
+                            order.iter().find(|(c, _)| !projected.columns().contains(c))
+                        {
+                            return Err(format!(
+                                "ORDER BY column {} must be projected by query {}",
+                                c.name, name
+                            ));
+                        }
+                        Some(order)
+                    }
+                    None => None,
+                };
+
+                // range lookups and prefix lookups both need the reader's keys in order
+                let ordered =
+                    qg.range_parameter.is_some() || (self.prefix_lookups && query_params.len() > 1);
+
                 let leaf_node = MirNode::new(
                     name,
                     self.schema_version,
@@ -2081,6 +2179,8 @@ impl SqlToMirConverter {
                     MirNodeType::Leaf {
                         node: leaf_project_node.clone(),
                         keys: query_params,
+                        ordered,
+                        row_order,
                     },
                     vec![leaf_project_node.clone()],
                     vec![],
@@ -7,16 +7,18 @@ mod reuse;
 pub(super) mod security;
 
 use self::mir::SqlToMirConverter;
+pub(in crate::controller) use self::passes::namespaces::NamespaceQualification;
 use self::query_graph::{to_query_graph, QueryGraph};
 use self::query_signature::Signature;
 use self::reuse::ReuseConfig;
-use super::mir_to_flow::mir_query_to_flow_parts;
+use super::mir_to_flow::{check_base_adaptations, mir_query_to_flow_parts};
 use crate::controller::Migration;
 use crate::ReuseConfigType;
+use ::mir::node::MirNode;
 use ::mir::query::{MirQuery, QueryFlowParts};
 use ::mir::reuse as mir_reuse;
-use ::mir::Column;
 use ::mir::MirNodeRef;
+use ::mir::{Column, FlowNode};
 use dataflow::prelude::DataType;
 use nom_sql::parser as sql_parser;
 use nom_sql::{ArithmeticBase, CreateTableStatement, SqlQuery};
@@ -24,6 +26,7 @@ use nom_sql::{CompoundSelectOperator, CompoundSelectStatement, SelectStatement};
 use petgraph::graph::NodeIndex;
 
 use slog;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::str;
 use std::vec::Vec;
@@ -114,6 +117,11 @@ impl SqlIncorporator {
         self.reuse_type = reuse_type;
     }
 
+    /// Give views with several parameters ordered readers in future migrations.
+    pub(super) fn enable_prefix_lookups(&mut self) {
+        self.mir_converter.enable_prefix_lookups();
+    }
+
     /// Incorporates a single query into via the flow graph migration in `mig`. The `query`
     /// argument is a string that holds a parameterized SQL query, and the `name` argument supplies
     /// an optional name for the query. If no `name` is specified, the table name is used in the
@@ -178,6 +186,56 @@ impl SqlIncorporator {
         self.leaf_addresses.values().any(|nn| *nn == ni)
     }
 
+    /// Point the queries at the given replacements for their dataflow nodes.
+    ///
+    /// Nodes that are not in `copies` are left as they are.
+    pub(super) fn remap_nodes(&mut self, copies: &HashMap<NodeIndex, NodeIndex>) {
+        for ni in self.leaf_addresses.values_mut() {
+            if let Some(&copy) = copies.get(ni) {
+                *ni = copy;
+            }
+        }
+
+        let remap = |n: &MirNodeRef| match n.borrow_mut().flow_node {
+            Some(FlowNode::New(ref mut ni)) | Some(FlowNode::Existing(ref mut ni)) => {
+                if let Some(&copy) = copies.get(ni) {
+                    *ni = copy;
+                }
+            }
+            None => {}
+        };
+        for mq in self.mir_queries.values() {
+            mq.topo_nodes().iter().for_each(remap);
+        }
+        self.mir_converter.nodes().for_each(remap);
+    }
+
+    /// A copy of the incorporator whose MIR graph is separate from this one's, so that queries can
+    /// be added to the copy without changing what this incorporator knows about.
+    pub(super) fn duplicate(&self) -> Self {
+        let copies = ::mir::node::duplicate(
+            self.mir_converter.nodes().chain(
+                self.base_mir_queries
+                    .values()
+                    .chain(self.mir_queries.values())
+                    .flat_map(|mq| mq.roots.iter().chain(Some(&mq.leaf))),
+            ),
+        );
+        let copy_of = |n: &mut MirNodeRef| *n = copies[&(&**n as *const RefCell<MirNode>)].clone();
+
+        let mut copy = self.clone();
+        copy.mir_converter.nodes_mut().for_each(copy_of);
+        for mq in copy
+            .base_mir_queries
+            .values_mut()
+            .chain(copy.mir_queries.values_mut())
+        {
+            mq.roots.iter_mut().for_each(copy_of);
+            copy_of(&mut mq.leaf);
+        }
+        copy
+    }
+
     pub(super) fn get_queries_for_node(&self, ni: NodeIndex) -> Vec<String> {
         self.leaf_addresses
             .iter()
@@ -241,6 +299,9 @@ impl SqlIncorporator {
                     return (qg, QueryGraphReuse::ExactMatch(mir_query.leaf.clone()));
                 } else if existing_qg.signature() == qg.signature()
                     && existing_qg.parameters() != qg.parameters()
+                    // range lookups and sorted reads need a fresh reader of their own
+                    && qg.range_parameter.is_none()
+                    && qg.row_order.is_none()
                 {
                     use self::query_graph::OutputColumn;
 
@@ -413,7 +474,7 @@ impl SqlIncorporator {
         query_name: &str,
         query: &SqlQuery,
         mut mig: &mut Migration,
-    ) -> QueryFlowParts {
+    ) -> Result<QueryFlowParts, String> {
         // first, compute the MIR representation of the SQL query
         let mut mir = self.mir_converter.named_base_to_mir(query_name, query);
 
@@ -421,6 +482,9 @@ impl SqlIncorporator {
 
         // no optimization, because standalone base nodes can't be optimized
 
+        // refuse to drop columns that existing queries still depend on
+        check_base_adaptations(&mir, mig)?;
+
         // push it into the flow graph using the migration in `mig`, and obtain `QueryFlowParts`
         let qfp = mir_query_to_flow_parts(&mut mir, &mut mig, None);
 
@@ -436,7 +500,7 @@ impl SqlIncorporator {
 
         self.register_query(query_name, None, &mir, mig.universe());
 
-        qfp
+        Ok(qfp)
     }
 
     fn add_compound_query(
@@ -712,7 +776,9 @@ impl SqlIncorporator {
                 continue;
             }
             let mq = &self.mir_queries[&m];
-            let res = merge_mir_for_queries(&self.log, &reused_mir, &mq);
+            let res = merge_mir_for_queries(&self.log, &reused_mir, &mq, &|old, new| {
+                may_splice(&*mig, old, new)
+            });
             reused_mir = res.0;
             if res.1 > num_reused_nodes {
                 num_reused_nodes = res.1;
@@ -775,6 +841,7 @@ impl SqlIncorporator {
         // TODO: make this not take &mut self
 
         use passes::alias_removal::AliasRemoval;
+        use passes::bool_predicates::BoolPredicates;
         use passes::count_star_rewrite::CountStarRewrite;
         use passes::implied_tables::ImpliedTableExpansion;
         use passes::key_def_coalescing::KeyDefinitionCoalescing;
@@ -853,13 +920,160 @@ impl SqlIncorporator {
 
         // Run some standard rewrite passes on the query. This makes the later work easier,
         // as we no longer have to consider complications like aliases.
-        Ok(fq
+        let fq = fq
             .expand_table_aliases(mig.context())
+            .rewrite_bool_predicates()
             .remove_negation()
             .coalesce_key_definitions()
             .expand_stars(&self.view_schemas)
             .expand_implied_tables(&self.view_schemas)
-            .rewrite_count_star(&self.view_schemas))
+            .rewrite_count_star(&self.view_schemas);
+
+        self.check_blob_comparisons(&fq)?;
+        self.check_compared_values(&fq)?;
+        Ok(fq)
+    }
+
+    /// Constants compared with a column take the form the column holds its values in, such as
+    /// UUIDs given in their text form, so make sure that they can.
+    fn check_compared_values(&self, q: &SqlQuery) -> Result<(), String> {
+        use nom_sql::{ColumnSpecification, ConditionBase, ConditionExpression, Literal};
+
+        let spec = |c: &nom_sql::Column| {
+            let table = c.table.as_ref()?;
+            self.base_schemas
+                .get(table)?
+                .fields
+                .iter()
+                .find(|cs| cs.column.name == c.name)
+        };
+
+        fn check<'s>(
+            ce: &ConditionExpression,
+            spec: &dyn Fn(&nom_sql::Column) -> Option<&'s ColumnSpecification>,
+        ) -> Result<(), String> {
+            match *ce {
+                ConditionExpression::LogicalOp(ref ct) => {
+                    check(&ct.left, spec)?;
+                    check(&ct.right, spec)
+                }
+                ConditionExpression::ComparisonOp(ref ct) => {
+                    let (column, values) = match (&*ct.left, &*ct.right) {
+                        (
+                            ConditionExpression::Base(ConditionBase::Field(ref c)),
+                            ConditionExpression::Base(ConditionBase::Literal(ref l)),
+                        )
+                        | (
+                            ConditionExpression::Base(ConditionBase::Literal(ref l)),
+                            ConditionExpression::Base(ConditionBase::Field(ref c)),
+                        ) => (c, vec![l]),
+                        (
+                            ConditionExpression::Base(ConditionBase::Field(ref c)),
+                            ConditionExpression::Base(ConditionBase::LiteralList(ref ll)),
+                        ) => (c, ll.iter().collect()),
+                        _ => return Ok(()),
+                    };
+                    let spec = match spec(column) {
+                        Some(spec) => spec,
+                        None => return Ok(()),
+                    };
+                    for l in values {
+                        if let Literal::String(_) = *l {
+                            DataType::from(l).coerce_to_column(spec).map_err(|e| {
+                                format!("{} compared with {}: {}", l.to_string(), column, e)
+                            })?;
+                        }
+                    }
+                    Ok(())
+                }
+                ConditionExpression::NegationOp(ref inner)
+                | ConditionExpression::Bracketed(ref inner) => check(inner, spec),
+                ConditionExpression::Base(_) | ConditionExpression::Arithmetic(_) => Ok(()),
+            }
+        }
+
+        let check_select = |st: &SelectStatement| match st.where_clause {
+            Some(ref ce) => check(ce, &spec),
+            None => Ok(()),
+        };
+        match *q {
+            SqlQuery::Select(ref st) => check_select(st),
+            SqlQuery::CompoundSelect(ref csq) => {
+                csq.selects.iter().map(|(_, st)| check_select(st)).collect()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Blobs have no meaningful order, so the only predicates we allow on blob columns are
+    /// (in)equality and membership tests, which also cover NULL checks.
+    fn check_blob_comparisons(&self, q: &SqlQuery) -> Result<(), String> {
+        use nom_sql::{ConditionBase, ConditionExpression, Operator, SqlType};
+
+        let is_blob = |c: &nom_sql::Column| {
+            let table = match c.table {
+                Some(ref t) => t,
+                None => return false,
+            };
+            self.base_schemas.get(table).map_or(false, |cts| {
+                cts.fields.iter().any(|cs| {
+                    cs.column.name == c.name
+                        && match cs.sql_type {
+                            SqlType::Blob
+                            | SqlType::Tinyblob
+                            | SqlType::Mediumblob
+                            | SqlType::Longblob
+                            | SqlType::Binary(_)
+                            | SqlType::Varbinary(_) => true,
+                            _ => false,
+                        }
+                })
+            })
+        };
+
+        fn check(
+            ce: &ConditionExpression,
+            is_blob: &dyn Fn(&nom_sql::Column) -> bool,
+        ) -> Result<(), String> {
+            match *ce {
+                ConditionExpression::LogicalOp(ref ct) => {
+                    check(&ct.left, is_blob)?;
+                    check(&ct.right, is_blob)
+                }
+                ConditionExpression::ComparisonOp(ref ct) => match ct.operator {
+                    Operator::Equal | Operator::NotEqual | Operator::In => Ok(()),
+                    ref op => {
+                        for side in &[&ct.left, &ct.right] {
+                            if let ConditionExpression::Base(ConditionBase::Field(ref c)) = ***side
+                            {
+                                if is_blob(c) {
+                                    return Err(format!(
+                                        "cannot compare blob column {} with {}",
+                                        c, op
+                                    ));
+                                }
+                            }
+                        }
+                        Ok(())
+                    }
+                },
+                ConditionExpression::NegationOp(ref inner)
+                | ConditionExpression::Bracketed(ref inner) => check(inner, is_blob),
+                ConditionExpression::Base(_) | ConditionExpression::Arithmetic(_) => Ok(()),
+            }
+        }
+
+        let check_select = |st: &SelectStatement| match st.where_clause {
+            Some(ref ce) => check(ce, &is_blob),
+            None => Ok(()),
+        };
+        match *q {
+            SqlQuery::Select(ref st) => check_select(st),
+            SqlQuery::CompoundSelect(ref csq) => {
+                csq.selects.iter().map(|(_, st)| check_select(st)).collect()
+            }
+            _ => Ok(()),
+        }
     }
 
     fn nodes_for_named_query(
@@ -903,7 +1117,7 @@ impl SqlIncorporator {
                     .unwrap()
             }
             SqlQuery::Select(sq) => self.add_select_query(&query_name, &sq, is_leaf, mig)?.0,
-            ref q @ SqlQuery::CreateTable { .. } => self.add_base_via_mir(&query_name, &q, mig),
+            ref q @ SqlQuery::CreateTable { .. } => self.add_base_via_mir(&query_name, &q, mig)?,
             q => panic!("unhandled query type in recipe: {:?}", q),
         };
 
@@ -927,6 +1141,22 @@ impl SqlIncorporator {
     }
 }
 
+/// Whether a new query can have its MIR node `new` spliced onto the existing node `old`.
+///
+/// The existing node's state is sharded and (if partial) indexed by some of its columns, and the
+/// new query only gets to share that state if it keeps all of those columns.
+fn may_splice(mig: &Migration, old: &MirNode, new: &MirNode) -> bool {
+    let ni = match old.flow_node {
+        Some(ref flow_node) => flow_node.address(),
+        None => return true,
+    };
+    mig.key_columns(ni).into_iter().all(|c| {
+        old.columns
+            .get(c)
+            .map_or(false, |c| new.columns.contains(c))
+    })
+}
+
 /// Enables incorporation of a textual SQL query into a Soup graph.
 trait ToFlowParts {
     /// Turn a SQL query into a set of nodes inserted into the Soup graph managed by
@@ -1196,7 +1426,7 @@ mod tests {
             );
             let agg_view = get_node(&inc, mig, &format!("q_{:x}_n0", qid));
             assert_eq!(agg_view.fields(), &["aid", "votes"]);
-            assert_eq!(agg_view.description(true), "|*| γ[0]");
+            assert_eq!(agg_view.description(true), "|1| γ[0]");
             // check edge view
             let edge_view = get_node(&inc, mig, &res.unwrap().name);
             assert_eq!(edge_view.fields(), &["votes", "bogokey"]);
@@ -1476,7 +1706,7 @@ mod tests {
             // check aggregation view
             let agg_view = get_node(&inc, mig, &format!("q_{:x}_n0", qid));
             assert_eq!(agg_view.fields(), &["grp", "count"]);
-            assert_eq!(agg_view.description(true), "|*| γ[1]");
+            assert_eq!(agg_view.description(true), "|0| γ[1]");
             // check edge view -- note that it's not actually currently possible to read from
             // this for a lack of key (the value would be the key). Hence, the view also has a
             // bogokey column.
@@ -1513,7 +1743,9 @@ mod tests {
             assert_eq!(mig.graph().node_count(), 5);
             // check aggregation view
             let f = Box::new(FunctionExpression::Count(
-                FunctionArguments::Column(Column::from("votes.aid")),
+                FunctionArguments::Column(super::passes::count_star_rewrite::count_star_column(
+                    "votes", "aid",
+                )),
                 false,
             ));
             let qid = query_id_hash(
@@ -2292,6 +2524,33 @@ mod tests {
         .await;
     }
 
+    #[tokio::test(threaded_scheduler)]
+    async fn it_rejects_ordered_blob_predicates() {
+        // set up graph
+        let mut g = integration::start_simple("it_rejects_ordered_blob_predicates").await;
+        g.migrate(|mig| {
+            let mut inc = SqlIncorporator::default();
+            assert!(inc
+                .add_query("CREATE TABLE thumbs (id int, data blob);", None, mig)
+                .is_ok());
+
+            // equality on a blob is fine
+            let res = inc.add_query("SELECT id FROM thumbs WHERE thumbs.data = ?;", None, mig);
+            assert!(res.is_ok());
+
+            // but blobs have no order
+            let res = inc.add_query("SELECT id FROM thumbs WHERE thumbs.data > ?;", None, mig);
+            assert!(res.is_err());
+            let res = inc.add_query(
+                "SELECT id FROM thumbs WHERE thumbs.id = 1 AND thumbs.data LIKE 'x%';",
+                None,
+                mig,
+            );
+            assert!(res.is_err());
+        })
+        .await;
+    }
+
     #[tokio::test(threaded_scheduler)]
     #[ignore]
     async fn it_queries_over_aliased_view() {
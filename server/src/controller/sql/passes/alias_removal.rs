@@ -62,6 +62,9 @@ fn rewrite_conditional(
             };
             ConditionExpression::LogicalOp(rewritten_ct)
         }
+        ConditionExpression::NegationOp(inner) => {
+            ConditionExpression::NegationOp(Box::new(rewrite_conditional(table_aliases, *inner)))
+        }
         x => x,
     }
 }
@@ -0,0 +1,104 @@
+use nom_sql::{
+    Column, ConditionBase, ConditionExpression, ConditionTree, JoinConstraint, Literal, Operator,
+    SqlQuery,
+};
+
+pub trait BoolPredicates {
+    fn rewrite_bool_predicates(self) -> SqlQuery;
+}
+
+/// The SQL parser has no boolean literals, so `TRUE` and `FALSE` come out as references to
+/// columns of that name. Booleans are equal to the integers 1 and 0, so we use those instead.
+fn bool_literal(c: &Column) -> Option<Literal> {
+    if c.table.is_some() || c.function.is_some() {
+        None
+    } else if c.name.eq_ignore_ascii_case("true") {
+        Some(Literal::Integer(1))
+    } else if c.name.eq_ignore_ascii_case("false") {
+        Some(Literal::Integer(0))
+    } else {
+        None
+    }
+}
+
+fn rewrite_operand(ce: &mut ConditionExpression) {
+    let literal = match *ce {
+        ConditionExpression::Base(ConditionBase::Field(ref c)) => bool_literal(c),
+        _ => None,
+    };
+    if let Some(l) = literal {
+        *ce = ConditionExpression::Base(ConditionBase::Literal(l));
+    }
+}
+
+/// Turn bare columns used as predicates (`WHERE active`) into comparisons (`WHERE active != 0`),
+/// which hold for `TRUE` and for the non-zero integers that used to stand in for it.
+fn rewrite_predicate(ce: &mut ConditionExpression) {
+    match *ce {
+        ConditionExpression::LogicalOp(ConditionTree {
+            ref mut left,
+            ref mut right,
+            ..
+        }) => {
+            rewrite_predicate(left);
+            rewrite_predicate(right);
+        }
+        ConditionExpression::ComparisonOp(ConditionTree {
+            ref mut left,
+            ref mut right,
+            ..
+        }) => {
+            rewrite_operand(left);
+            rewrite_operand(right);
+        }
+        ConditionExpression::NegationOp(ref mut inner)
+        | ConditionExpression::Bracketed(ref mut inner) => rewrite_predicate(inner),
+        ConditionExpression::Base(ConditionBase::Field(ref c)) if bool_literal(c).is_none() => {
+            *ce = ConditionExpression::ComparisonOp(ConditionTree {
+                operator: Operator::NotEqual,
+                left: Box::new(ConditionExpression::Base(ConditionBase::Field(c.clone()))),
+                right: Box::new(ConditionExpression::Base(ConditionBase::Literal(
+                    Literal::Integer(0),
+                ))),
+            });
+        }
+        ConditionExpression::Base(_) | ConditionExpression::Arithmetic(_) => {}
+    }
+}
+
+impl BoolPredicates for SqlQuery {
+    fn rewrite_bool_predicates(mut self) -> SqlQuery {
+        if let SqlQuery::Select(ref mut s) = self {
+            if let Some(ref mut w) = s.where_clause {
+                rewrite_predicate(w);
+            }
+
+            for j in s.join.iter_mut() {
+                if let JoinConstraint::On(ref mut ce) = j.constraint {
+                    rewrite_predicate(ce);
+                }
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nom_sql::parse_query;
+
+    #[test]
+    fn it_rewrites_bare_columns_and_literals() {
+        let q = parse_query("SELECT id FROM users WHERE active AND NOT (admin = true);").unwrap();
+        let expected =
+            parse_query("SELECT id FROM users WHERE active != 0 AND NOT (admin = 1);").unwrap();
+        assert_eq!(q.rewrite_bool_predicates(), expected);
+    }
+
+    #[test]
+    fn it_leaves_qualified_columns_named_true() {
+        let q = parse_query("SELECT id FROM users WHERE users.flag = users.true;").unwrap();
+        assert_eq!(q.clone().rewrite_bool_predicates(), q);
+    }
+}
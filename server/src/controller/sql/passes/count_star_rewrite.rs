@@ -1,10 +1,31 @@
 use nom_sql::{
     Column, ConditionBase, ConditionExpression, ConditionTree, FieldDefinitionExpression,
-    FunctionArguments, SqlQuery, Table,
+    FunctionArguments, FunctionExpression, SqlQuery, Table,
 };
 
 use std::collections::HashMap;
 
+/// The column that a rewritten `COUNT(*)` counts over.
+///
+/// The column is marked with `COUNT(*)` as its function, so that the aggregation still counts
+/// every row rather than only those where the column is not `NULL`.
+pub(in crate::controller::sql) fn count_star_column(table: &str, name: &str) -> Column {
+    Column {
+        name: name.to_owned(),
+        alias: None,
+        table: Some(table.to_owned()),
+        function: Some(Box::new(FunctionExpression::CountStar)),
+    }
+}
+
+/// Returns true if `col` is the column that a rewritten `COUNT(*)` counts over.
+pub(in crate::controller::sql) fn is_count_star_column(col: &Column) -> bool {
+    col.function
+        .as_ref()
+        .map(|f| **f == FunctionExpression::CountStar)
+        .unwrap_or(false)
+}
+
 pub trait CountStarRewrite {
     fn rewrite_count_star(self, write_schemas: &HashMap<String, Vec<String>>) -> SqlQuery;
 }
@@ -63,12 +84,7 @@ impl CountStarRewrite for SqlQuery {
                     }
 
                     c.function = Some(Box::new(Count(
-                        FunctionArguments::Column(Column {
-                            name: bogo_column.clone(),
-                            alias: None,
-                            table: Some(bogo_table.name.clone()),
-                            function: None,
-                        }),
+                        FunctionArguments::Column(count_star_column(&bogo_table.name, bogo_column)),
                         false,
                     )));
                 }
@@ -107,7 +123,7 @@ impl CountStarRewrite for SqlQuery {
 
 #[cfg(test)]
 mod tests {
-    use super::CountStarRewrite;
+    use super::{count_star_column, CountStarRewrite};
     use nom_sql::{Column, FieldDefinitionExpression, SqlQuery};
     use std::collections::HashMap;
 
@@ -118,7 +134,7 @@ mod tests {
 
         // SELECT COUNT(*) FROM users;
         // -->
-        // SELECT COUNT(users.id) FROM users;
+        // SELECT COUNT(users.id) FROM users; (counting NULL ids too)
         let q = parse_query("SELECT COUNT(*) FROM users;").unwrap();
         let mut schema = HashMap::new();
         schema.insert(
@@ -136,7 +152,7 @@ mod tests {
                         alias: None,
                         table: None,
                         function: Some(Box::new(FunctionExpression::Count(
-                            FunctionArguments::Column(Column::from("users.id")),
+                            FunctionArguments::Column(count_star_column("users", "id")),
                             false,
                         ))),
                     })]
@@ -154,7 +170,7 @@ mod tests {
 
         // SELECT COUNT(*) FROM users GROUP BY id;
         // -->
-        // SELECT COUNT(users.name) FROM users GROUP BY id;
+        // SELECT COUNT(users.name) FROM users GROUP BY id; (counting NULL names too)
         let q = parse_query("SELECT COUNT(*) FROM users GROUP BY id;").unwrap();
         let mut schema = HashMap::new();
         schema.insert(
@@ -172,7 +188,7 @@ mod tests {
                         alias: None,
                         table: None,
                         function: Some(Box::new(FunctionExpression::Count(
-                            FunctionArguments::Column(Column::from("users.name")),
+                            FunctionArguments::Column(count_star_column("users", "name")),
                             false,
                         ))),
                     })]
@@ -50,6 +50,11 @@ where
             left: Box::new(rewrite_conditional(expand_columns, *left, avail_tables)),
             right: Box::new(rewrite_conditional(expand_columns, *right, avail_tables)),
         }),
+        NegationOp(inner) => NegationOp(Box::new(rewrite_conditional(
+            expand_columns,
+            *inner,
+            avail_tables,
+        ))),
         x => x,
     }
 }
@@ -0,0 +1,327 @@
+use nom_sql::{
+    ArithmeticBase, ArithmeticExpression, CaseWhenExpression, Column, ColumnOrLiteral,
+    ConditionBase, ConditionExpression, FieldDefinitionExpression, FieldValueExpression,
+    FunctionArguments, FunctionExpression, JoinConstraint, JoinRightSide, SelectSpecification,
+    SelectStatement, SqlQuery, Table, TableKey,
+};
+use noria::namespace;
+
+use std::collections::HashSet;
+
+pub trait NamespaceQualification {
+    /// Qualify the names of all tables and views the query defines or refers to with
+    /// `namespace`, so that they cannot clash with those in other namespaces.
+    fn qualify_names(self, namespace: &str) -> Result<SqlQuery, String>;
+}
+
+fn qualify(ns: &str, name: &mut String) {
+    *name = namespace::qualify(Some(ns), name);
+}
+
+fn qualify_table(ns: &str, t: &mut Table) {
+    qualify(ns, &mut t.name);
+}
+
+// Columns refer either to a table by its name, which needs qualifying, or to one of the query's
+// table aliases, which does not.
+fn qualify_column(ns: &str, aliases: &HashSet<String>, c: &mut Column) -> Result<(), String> {
+    if let Some(ref mut t) = c.table {
+        if !aliases.contains(t) {
+            qualify(ns, t);
+        }
+    }
+    if let Some(ref mut f) = c.function {
+        use nom_sql::FunctionExpression::*;
+        match **f {
+            Avg(ref mut args, _)
+            | Count(ref mut args, _)
+            | Sum(ref mut args, _)
+            | Min(ref mut args)
+            | Max(ref mut args)
+            | GroupConcat(ref mut args, _) => qualify_arguments(ns, aliases, args)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn qualify_arguments(
+    ns: &str,
+    aliases: &HashSet<String>,
+    args: &mut FunctionArguments,
+) -> Result<(), String> {
+    match *args {
+        FunctionArguments::Column(ref mut c) => qualify_column(ns, aliases, c),
+        FunctionArguments::Conditional(CaseWhenExpression {
+            ref mut condition,
+            ref mut then_expr,
+            ..
+        }) => {
+            qualify_condition(ns, aliases, condition)?;
+            if let ColumnOrLiteral::Column(ref mut c) = *then_expr {
+                qualify_column(ns, aliases, c)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn qualify_arithmetic(
+    ns: &str,
+    aliases: &HashSet<String>,
+    ae: &mut ArithmeticExpression,
+) -> Result<(), String> {
+    if let ArithmeticBase::Column(ref mut c) = ae.left {
+        qualify_column(ns, aliases, c)?;
+    }
+    if let ArithmeticBase::Column(ref mut c) = ae.right {
+        qualify_column(ns, aliases, c)?;
+    }
+    Ok(())
+}
+
+fn qualify_condition(
+    ns: &str,
+    aliases: &HashSet<String>,
+    ce: &mut ConditionExpression,
+) -> Result<(), String> {
+    match *ce {
+        ConditionExpression::LogicalOp(ref mut ct)
+        | ConditionExpression::ComparisonOp(ref mut ct) => {
+            qualify_condition(ns, aliases, &mut ct.left)?;
+            qualify_condition(ns, aliases, &mut ct.right)
+        }
+        ConditionExpression::NegationOp(ref mut inner)
+        | ConditionExpression::Bracketed(ref mut inner) => qualify_condition(ns, aliases, inner),
+        ConditionExpression::Arithmetic(ref mut ae) => qualify_arithmetic(ns, aliases, ae),
+        ConditionExpression::Base(ConditionBase::Field(ref mut c)) => {
+            qualify_column(ns, aliases, c)
+        }
+        ConditionExpression::Base(ConditionBase::NestedSelect(ref mut sq)) => {
+            qualify_selection(ns, sq)
+        }
+        ConditionExpression::Base(_) => Ok(()),
+    }
+}
+
+fn qualify_selection(ns: &str, sq: &mut SelectStatement) -> Result<(), String> {
+    // collect the aliases before rewriting anything, since they can be used anywhere
+    let mut aliases = HashSet::new();
+    {
+        let mut add_alias = |t: &Table| {
+            if let Some(ref a) = t.alias {
+                aliases.insert(a.clone());
+            }
+        };
+        sq.tables.iter().for_each(&mut add_alias);
+        for jc in &sq.join {
+            match jc.right {
+                JoinRightSide::Table(ref t) => add_alias(t),
+                JoinRightSide::Tables(ref ts) => ts.iter().for_each(&mut add_alias),
+                // a subquery in a join becomes a view named by its alias, so the alias is qualified
+                // like any other view name
+                JoinRightSide::NestedSelect(..) => (),
+                JoinRightSide::NestedJoin(_) => {
+                    return Err("nested joins cannot be put in a namespace".to_owned());
+                }
+            }
+        }
+    }
+
+    for t in &mut sq.tables {
+        qualify_table(ns, t);
+    }
+    for field in &mut sq.fields {
+        match *field {
+            FieldDefinitionExpression::All => (),
+            FieldDefinitionExpression::AllInTable(ref mut t) => {
+                if !aliases.contains(t) {
+                    qualify(ns, t);
+                }
+            }
+            FieldDefinitionExpression::Col(ref mut c) => qualify_column(ns, &aliases, c)?,
+            FieldDefinitionExpression::Value(FieldValueExpression::Arithmetic(ref mut ae)) => {
+                qualify_arithmetic(ns, &aliases, ae)?
+            }
+            FieldDefinitionExpression::Value(FieldValueExpression::Literal(_)) => (),
+        }
+    }
+    for jc in &mut sq.join {
+        match jc.right {
+            JoinRightSide::Table(ref mut t) => qualify_table(ns, t),
+            JoinRightSide::Tables(ref mut ts) => {
+                for t in ts {
+                    qualify_table(ns, t);
+                }
+            }
+            JoinRightSide::NestedSelect(ref mut nsq, ref mut alias) => {
+                qualify_selection(ns, nsq)?;
+                if let Some(ref mut a) = *alias {
+                    qualify(ns, a);
+                }
+            }
+            JoinRightSide::NestedJoin(_) => unreachable!(),
+        }
+        match jc.constraint {
+            JoinConstraint::On(ref mut ce) => qualify_condition(ns, &aliases, ce)?,
+            JoinConstraint::Using(ref mut cols) => {
+                for c in cols {
+                    qualify_column(ns, &aliases, c)?;
+                }
+            }
+        }
+    }
+    if let Some(ref mut wc) = sq.where_clause {
+        qualify_condition(ns, &aliases, wc)?;
+    }
+    if let Some(ref mut gbc) = sq.group_by {
+        for c in &mut gbc.columns {
+            qualify_column(ns, &aliases, c)?;
+        }
+        if let Some(ref mut hc) = gbc.having {
+            qualify_condition(ns, &aliases, hc)?;
+        }
+    }
+    if let Some(ref mut oc) = sq.order {
+        for &mut (ref mut c, _) in &mut oc.columns {
+            qualify_column(ns, &aliases, c)?;
+        }
+    }
+    Ok(())
+}
+
+impl NamespaceQualification for SqlQuery {
+    fn qualify_names(self, ns: &str) -> Result<SqlQuery, String> {
+        // the name of a table is qualified wherever it is written out in full
+        let qualify_key_column = |c: &mut Column| {
+            if let Some(ref mut t) = c.table {
+                qualify(ns, t);
+            }
+        };
+
+        match self {
+            SqlQuery::CreateTable(mut ctq) => {
+                qualify_table(ns, &mut ctq.table);
+                for cs in &mut ctq.fields {
+                    qualify_key_column(&mut cs.column);
+                }
+                if let Some(ref mut keys) = ctq.keys {
+                    for key in keys {
+                        match *key {
+                            TableKey::PrimaryKey(ref mut cols)
+                            | TableKey::UniqueKey(_, ref mut cols)
+                            | TableKey::FulltextKey(_, ref mut cols)
+                            | TableKey::Key(_, ref mut cols) => {
+                                for c in cols {
+                                    qualify_key_column(c);
+                                }
+                            }
+                        }
+                    }
+                }
+                Ok(SqlQuery::CreateTable(ctq))
+            }
+            SqlQuery::CreateView(mut cvq) => {
+                qualify(ns, &mut cvq.name);
+                match *cvq.definition {
+                    SelectSpecification::Simple(ref mut sq) => qualify_selection(ns, sq)?,
+                    SelectSpecification::Compound(ref mut csq) => {
+                        for &mut (_, ref mut sq) in &mut csq.selects {
+                            qualify_selection(ns, sq)?;
+                        }
+                    }
+                }
+                Ok(SqlQuery::CreateView(cvq))
+            }
+            SqlQuery::Select(mut sq) => {
+                qualify_selection(ns, &mut sq)?;
+                Ok(SqlQuery::Select(sq))
+            }
+            SqlQuery::CompoundSelect(mut csq) => {
+                for &mut (_, ref mut sq) in &mut csq.selects {
+                    qualify_selection(ns, sq)?;
+                }
+                Ok(SqlQuery::CompoundSelect(csq))
+            }
+            q => Err(format!(
+                "only tables and queries can be put in a namespace, not {}",
+                q
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NamespaceQualification;
+    use nom_sql::parser::parse_query;
+    use nom_sql::{Column, FieldDefinitionExpression, SqlQuery, Table};
+
+    #[test]
+    fn it_qualifies_tables() {
+        let q = parse_query("CREATE TABLE Article (id int, title varchar(255), PRIMARY KEY(id));")
+            .unwrap();
+        match q.qualify_names("blog").unwrap() {
+            SqlQuery::CreateTable(ctq) => {
+                assert_eq!(ctq.table, Table::from("blog:Article"));
+                for cs in &ctq.fields {
+                    assert!(cs
+                        .column
+                        .table
+                        .as_ref()
+                        .map_or(true, |t| *t == ctq.table.name));
+                }
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn it_qualifies_selections() {
+        let q = parse_query(
+            "SELECT Article.title, count(Vote.user) AS votes FROM Article, Vote \
+             WHERE Article.id = Vote.aid GROUP BY Article.title ORDER BY Article.title;",
+        )
+        .unwrap();
+        match q.qualify_names("blog").unwrap() {
+            SqlQuery::Select(sq) => {
+                assert_eq!(
+                    sq.tables,
+                    vec![Table::from("blog:Article"), Table::from("blog:Vote")]
+                );
+                assert_eq!(
+                    sq.fields[0],
+                    FieldDefinitionExpression::Col(Column::from("blog:Article.title"))
+                );
+                let gbc = sq.group_by.unwrap();
+                assert_eq!(gbc.columns, vec![Column::from("blog:Article.title")]);
+                let oc = sq.order.unwrap();
+                assert_eq!(oc.columns[0].0, Column::from("blog:Article.title"));
+                assert!(format!("{}", sq.where_clause.unwrap()).contains("blog:Vote"));
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn it_keeps_table_aliases() {
+        let q = parse_query("SELECT a.title FROM Article AS a WHERE a.id = ?;").unwrap();
+        match q.qualify_names("blog").unwrap() {
+            SqlQuery::Select(sq) => {
+                assert_eq!(sq.tables[0].name, "blog:Article");
+                assert_eq!(sq.tables[0].alias, Some("a".to_owned()));
+                assert_eq!(
+                    sq.fields,
+                    vec![FieldDefinitionExpression::Col(Column::from("a.title"))]
+                );
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn it_rejects_writes() {
+        let q = parse_query("INSERT INTO Article (id) VALUES (1);").unwrap();
+        assert!(q.qualify_names("blog").is_err());
+    }
+}
@@ -10,6 +10,17 @@ pub trait NegationRemoval {
 
 fn normalize_condition_expr(ce: &mut ConditionExpression, negate: bool) {
     match *ce {
+        ConditionExpression::ComparisonOp(ConditionTree {
+            operator: Operator::In,
+            ..
+        }) if negate => {
+            // there is no `NOT IN` operator, so a negated membership test keeps its negation
+            let inner = mem::replace(
+                ce,
+                ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)),
+            );
+            *ce = ConditionExpression::NegationOp(Box::new(inner));
+        }
         ConditionExpression::LogicalOp(ConditionTree {
             ref mut operator,
             ref mut left,
@@ -122,4 +133,29 @@ mod tests {
         normalize_condition_expr(&mut expr, false);
         assert_eq!(expr, target);
     }
+
+    #[test]
+    fn it_keeps_negated_in() {
+        let in_list = ConditionExpression::ComparisonOp(ConditionTree {
+            operator: Operator::In,
+            left: Box::new(ConditionExpression::Base(ConditionBase::Field("a".into()))),
+            right: Box::new(ConditionExpression::Base(ConditionBase::LiteralList(vec![
+                1.into(),
+                2.into(),
+            ]))),
+        });
+
+        let mut expr = ConditionExpression::NegationOp(Box::new(in_list.clone()));
+        normalize_condition_expr(&mut expr, false);
+        assert_eq!(
+            expr,
+            ConditionExpression::NegationOp(Box::new(in_list.clone()))
+        );
+
+        let mut expr = ConditionExpression::NegationOp(Box::new(ConditionExpression::NegationOp(
+            Box::new(in_list.clone()),
+        )));
+        normalize_condition_expr(&mut expr, false);
+        assert_eq!(expr, in_list);
+    }
 }
@@ -2,7 +2,7 @@ use nom_sql::SelectStatement;
 use nom_sql::{
     ArithmeticBase, ArithmeticExpression, Column, ConditionBase, ConditionExpression,
     ConditionTree, FieldDefinitionExpression, FieldValueExpression, JoinConstraint, JoinOperator,
-    JoinRightSide, Literal, Operator, Table,
+    JoinRightSide, Literal, Operator, OrderType, Table,
 };
 
 use std::cmp::Ordering;
@@ -164,6 +164,13 @@ pub struct QueryGraph {
     pub join_order: Vec<JoinRef>,
     /// Global predicates (not associated with a particular relation)
     pub global_predicates: Vec<ConditionExpression>,
+    /// A parameter compared with an inequality (e.g., `WHERE created_at > ?`). Views with a range
+    /// parameter are looked up by exact values for all other parameters and a range of values for
+    /// this one.
+    pub range_parameter: Option<Column>,
+    /// The order in which the rows for each key are returned, for queries with an `ORDER BY` but
+    /// no `LIMIT` (those with a `LIMIT` use a TopK operator instead).
+    pub row_order: Option<Vec<(Column, OrderType)>>,
 }
 
 impl QueryGraph {
@@ -174,18 +181,23 @@ impl QueryGraph {
             columns: Vec::new(),
             join_order: Vec::new(),
             global_predicates: Vec::new(),
+            range_parameter: None,
+            row_order: None,
         }
     }
 
     /// Returns the set of columns on which this query is parameterized. They can come from
-    /// multiple tables involved in the query.
+    /// multiple tables involved in the query. The range parameter, if any, always comes last.
     pub fn parameters<'a>(&'a self) -> Vec<&'a Column> {
-        self.relations
-            .values()
-            .fold(Vec::new(), |mut acc: Vec<&'a Column>, qgn| {
-                acc.extend(qgn.parameters.iter());
-                acc
-            })
+        let mut params =
+            self.relations
+                .values()
+                .fold(Vec::new(), |mut acc: Vec<&'a Column>, qgn| {
+                    acc.extend(qgn.parameters.iter());
+                    acc
+                });
+        params.extend(self.range_parameter.iter());
+        params
     }
 
     pub fn exact_hash(&self) -> u64 {
@@ -215,6 +227,8 @@ impl Hash for QueryGraph {
         self.columns.hash(state);
         self.join_order.hash(state);
         self.global_predicates.hash(state);
+        self.range_parameter.hash(state);
+        self.row_order.hash(state);
     }
 }
 
@@ -255,7 +269,7 @@ fn classify_conditionals(
     local: &mut HashMap<String, Vec<ConditionExpression>>,
     join: &mut Vec<ConditionTree>,
     global: &mut Vec<ConditionExpression>,
-    params: &mut Vec<Column>,
+    params: &mut Vec<(Column, Operator)>,
 ) {
     // Handling OR and AND expressions requires some care as there are some corner cases.
     //    a) we don't support OR expressions with predicates with placeholder parameters,
@@ -404,11 +418,12 @@ fn classify_conditionals(
                         // right-hand side is a placeholder, so this must be a query parameter
                         ConditionBase::Literal(Literal::Placeholder) => {
                             if let ConditionBase::Field(ref lf) = *l {
-                                params.push(lf.clone());
+                                params.push((lf.clone(), ct.operator.clone()));
                             }
                         }
-                        // right-hand side is a non-placeholder literal, so this is a predicate
-                        ConditionBase::Literal(_) => {
+                        // right-hand side is a non-placeholder literal or a list of literals, so
+                        // this is a predicate
+                        ConditionBase::Literal(_) | ConditionBase::LiteralList(_) => {
                             if let ConditionBase::Field(ref lf) = *l {
                                 // we assume that implied table names have previously been expanded
                                 // and thus all non-computed columns carry table names
@@ -422,7 +437,6 @@ fn classify_conditionals(
                                 }
                             }
                         }
-                        ConditionBase::LiteralList(_) => (),
                         ConditionBase::NestedSelect(_) => unimplemented!(),
                     }
                 };
@@ -448,9 +462,31 @@ fn classify_conditionals(
             // parent selection predicate
             panic!("encountered unexpected standalone base of condition expression");
         }
-        ConditionExpression::NegationOp(_) => {
-            panic!("negation should have been removed earlier");
-        }
+        ConditionExpression::NegationOp(ref inner) => match **inner {
+            // negated membership tests survive negation removal; they classify like the test
+            // itself, but we must keep the negation on the resulting predicates
+            ConditionExpression::ComparisonOp(ref ct) if ct.operator == Operator::In => {
+                let mut new_local = HashMap::new();
+                let mut new_global = Vec::new();
+                classify_conditionals(
+                    inner.as_ref(),
+                    tables,
+                    &mut new_local,
+                    join,
+                    &mut new_global,
+                    params,
+                );
+                let negate = |p| ConditionExpression::NegationOp(Box::new(p));
+                for (t, ps) in new_local {
+                    local
+                        .entry(t)
+                        .or_default()
+                        .extend(ps.into_iter().map(negate));
+                }
+                global.extend(new_global.into_iter().map(negate));
+            }
+            _ => panic!("negation should have been removed earlier"),
+        },
         ConditionExpression::Arithmetic(_) => unimplemented!(),
     }
 }
@@ -692,7 +728,7 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
         //    node for this query. Such columns will be carried all the way through the operators
         //    implementing the query (unlike in a traditional query plan, where the predicates on
         //    parameters might be evaluated sooner).
-        for column in query_parameters.into_iter() {
+        for (column, operator) in query_parameters.into_iter() {
             match column.table {
                 None => panic!("each parameter's column must have an associated table!"),
                 Some(ref table) => {
@@ -703,7 +739,26 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                     // the parameter column is included in the projected columns of the output, but
                     // we also separately register it as a parameter so that we can set keys
                     // correctly on the leaf view
-                    rel.parameters.push(column.clone());
+                    match operator {
+                        Operator::Equal | Operator::In => rel.parameters.push(column.clone()),
+                        Operator::Greater
+                        | Operator::GreaterOrEqual
+                        | Operator::Less
+                        | Operator::LessOrEqual => match qg.range_parameter {
+                            // both ends of a range (`x > ? AND x < ?`) share the same parameter
+                            Some(ref rp) if *rp == column => (),
+                            Some(ref rp) => {
+                                return Err(format!(
+                                    "cannot have range parameters on both {} and {}",
+                                    rp.name, column.name
+                                ));
+                            }
+                            None => qg.range_parameter = Some(column.clone()),
+                        },
+                        ref op => {
+                            return Err(format!("unsupported operator {} for parameter", op));
+                        }
+                    }
                 }
             }
         }
@@ -828,5 +883,11 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
         }
     }
 
+    // without a LIMIT, an ORDER BY only determines the order in which the reader returns the rows
+    // for each key
+    if st.limit.is_none() {
+        qg.row_order = st.order.as_ref().map(|o| o.columns.clone());
+    }
+
     Ok(qg)
 }
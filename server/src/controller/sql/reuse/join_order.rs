@@ -121,6 +121,25 @@ fn from_join_ref<'a>(jref: &JoinRef, qg: &'a QueryGraph) -> &'a ConditionTree {
     }
 }
 
+fn is_left_join(jref: &JoinRef, qg: &QueryGraph) -> bool {
+    match qg.edges[&(jref.src.clone(), jref.dst.clone())] {
+        QueryGraphEdge::LeftJoin(_) => true,
+        _ => false,
+    }
+}
+
+/// Whether `qg` and `eqg` have at least one join in common, in which case the new query may be
+/// able to share that join with the existing one.
+pub(super) fn shares_joins(qg: &QueryGraph, eqg: &QueryGraph) -> bool {
+    qg.join_order.iter().any(|new_jref| {
+        let njp = from_join_ref(new_jref, qg);
+        eqg.join_order.iter().any(|existing_jref| {
+            is_left_join(new_jref, qg) == is_left_join(existing_jref, eqg)
+                && predicate_is_equivalent(njp, from_join_ref(existing_jref, eqg))
+        })
+    })
+}
+
 pub(super) fn reorder_joins(
     qg: &mut QueryGraph,
     reuse_candidates: &[(ReuseType, (u64, &QueryGraph))],
@@ -1,5 +1,5 @@
 use crate::controller::sql::query_graph::QueryGraph;
-use crate::controller::sql::reuse::join_order::reorder_joins;
+use crate::controller::sql::reuse::join_order::{reorder_joins, shares_joins};
 use crate::controller::sql::UniverseId;
 use crate::ReuseConfigType;
 use dataflow::prelude::DataType;
@@ -31,7 +31,7 @@ impl ReuseConfig {
         qg: &mut QueryGraph,
         query_graphs: &'a HashMap<u64, QueryGraph>,
     ) -> Vec<(ReuseType, (u64, &'a QueryGraph))> {
-        let reuse_candidates = match self.config {
+        let mut reuse_candidates = match self.config {
             ReuseConfigType::Finkelstein => {
                 finkelstein::Finkelstein::reuse_candidates(qg, query_graphs)
             }
@@ -39,6 +39,19 @@ impl ReuseConfig {
             ReuseConfigType::Full => full::Full::reuse_candidates(qg, query_graphs),
             _ => unreachable!(),
         };
+
+        // queries that join the same tables on the same columns can share those joins even if
+        // nothing else about them matches, so consider them as well
+        let mut sharing: Vec<_> = query_graphs
+            .iter()
+            .filter(|&(sig, eqg)| {
+                !reuse_candidates.iter().any(|&(_, (s, _))| s == *sig) && shares_joins(qg, eqg)
+            })
+            .map(|(sig, eqg)| (ReuseType::PrefixReuse, (*sig, eqg)))
+            .collect();
+        sharing.sort_by_key(|&(_, (sig, _))| sig);
+        reuse_candidates.extend(sharing);
+
         self.reorder_joins(qg, &reuse_candidates);
 
         reuse_candidates
@@ -1,6 +1,7 @@
 use dataflow::prelude::*;
 use dataflow::DomainBuilder;
 use noria::consensus::Epoch;
+use noria::debug::bookkeeping::EdgeFrontier;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 
@@ -26,6 +27,10 @@ pub enum CoordinationPayload {
         read_listen_addr: SocketAddr,
         /// Which log files are stored locally on the worker.
         log_files: Vec<String>,
+        /// Whether the worker expects its peers and clients to connect over TLS.
+        tls: bool,
+        /// The labels the worker was started with, which placement constraints refer to.
+        labels: Vec<String>,
     },
     /// Worker going offline.
     Deregister,
@@ -37,6 +42,34 @@ pub enum CoordinationPayload {
     RemoveDomain,
     /// Domain connectivity gossip.
     DomainBooted(DomainDescriptor),
+    /// The thread running a domain has been pinned to the given cores.
+    DomainPinned {
+        /// The domain.
+        domain: DomainIndex,
+        /// The shard of the domain.
+        shard: usize,
+        /// The cores the domain's thread may run on.
+        cores: Vec<usize>,
+    },
+    /// A domain on a worker that is shutting down has finished the work it had been given.
+    DomainQuiescent {
+        /// The domain.
+        domain: DomainIndex,
+        /// The shard of the domain.
+        shard: usize,
+        /// The latest batch from each base table shard that the domain sent along each of the
+        /// edges that leave it.
+        frontiers: Vec<EdgeFrontier>,
+    },
+    /// A domain refused a connection from a sender in the dataflow of an earlier controller.
+    SenderFenced {
+        /// The domain.
+        domain: DomainIndex,
+        /// The shard of the domain.
+        shard: usize,
+        /// Where the connection came from.
+        peer: SocketAddr,
+    },
     /// Create a new security universe.
     CreateUniverse(HashMap<String, DataType>),
 }
@@ -46,11 +79,17 @@ pub struct DomainDescriptor {
     id: DomainIndex,
     shard: usize,
     addr: SocketAddr,
+    tls: bool,
 }
 
 impl DomainDescriptor {
-    pub fn new(id: DomainIndex, shard: usize, addr: SocketAddr) -> Self {
-        DomainDescriptor { id, shard, addr }
+    pub fn new(id: DomainIndex, shard: usize, addr: SocketAddr, tls: bool) -> Self {
+        DomainDescriptor {
+            id,
+            shard,
+            addr,
+            tls,
+        }
     }
 
     pub fn domain(&self) -> DomainIndex {
@@ -64,4 +103,9 @@ impl DomainDescriptor {
     pub fn addr(&self) -> SocketAddr {
         self.addr
     }
+
+    /// Whether the domain expects other domains to connect to it over TLS.
+    pub fn tls(&self) -> bool {
+        self.tls
+    }
 }
@@ -1,11 +1,16 @@
 use crate::controller::migrate::Migration;
 use crate::startup::Event;
+use crate::worker::health::Liveness;
 use dataflow::prelude::*;
+use noria::channel::Tls;
 use noria::consensus::Authority;
+use noria::debug::health::HealthReport;
 use noria::prelude::*;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
+use std::time::Duration;
 use stream_cancel::Trigger;
 
 /// A handle to a controller that is running in the same process as this one.
@@ -14,6 +19,10 @@ pub struct Handle<A: Authority + 'static> {
     #[allow(dead_code)]
     event_tx: Option<tokio::sync::mpsc::UnboundedSender<Event>>,
     kill: Option<Trigger>,
+    liveness: Arc<Liveness>,
+    metrics_addr: Option<SocketAddr>,
+    view_http_addr: Option<SocketAddr>,
+    grpc_addr: Option<SocketAddr>,
 }
 
 impl<A: Authority> Deref for Handle<A> {
@@ -32,17 +41,55 @@ impl<A: Authority> DerefMut for Handle<A> {
 impl<A: Authority + 'static> Handle<A> {
     pub(super) async fn new(
         authority: Arc<A>,
+        tls: Option<Tls>,
         event_tx: tokio::sync::mpsc::UnboundedSender<Event>,
         kill: Trigger,
+        liveness: Arc<Liveness>,
+        metrics_addr: Option<SocketAddr>,
+        view_http_addr: Option<SocketAddr>,
+        grpc_addr: Option<SocketAddr>,
     ) -> Result<Self, failure::Error> {
         let c = ControllerHandle::make(authority).await?;
+        let c = match tls {
+            Some(tls) => c.with_tls(tls),
+            None => c,
+        };
         Ok(Handle {
             c: Some(c),
             event_tx: Some(event_tx),
             kill: Some(kill),
+            liveness,
+            metrics_addr,
+            view_http_addr,
+            grpc_addr,
         })
     }
 
+    /// Check that the event loop of each domain shard on this worker is running, by waiting up to
+    /// `deadline` for each to answer a ping sent down the channel it reads from.
+    ///
+    /// This is also served as JSON at `/health` on the worker's external address, with the
+    /// deadline in milliseconds given as `?deadline_ms=`, and a 503 status if the worker is
+    /// unhealthy.
+    pub async fn health(&self, deadline: Duration) -> HealthReport {
+        self.liveness.check(deadline).await
+    }
+
+    /// The address this worker serves metrics on, if it does.
+    pub fn metrics_address(&self) -> Option<SocketAddr> {
+        self.metrics_addr
+    }
+
+    /// The address this worker serves the contents of views on over HTTP, if it does.
+    pub fn view_http_address(&self) -> Option<SocketAddr> {
+        self.view_http_addr
+    }
+
+    /// The address this worker serves the gRPC interface on, if it does.
+    pub fn grpc_address(&self) -> Option<SocketAddr> {
+        self.grpc_addr
+    }
+
     #[cfg(test)]
     pub(super) async fn backend_ready(&mut self) {
         use std::time;
@@ -131,6 +178,23 @@ impl<A: Authority + 'static> Handle<A> {
             .map_err(|e| format_err!("failed to make table: {:?}", e))
     }
 
+    /// Let the local worker's domains finish the work they have been given, so that the instance
+    /// can then be shut down without losing any of it.
+    ///
+    /// The worker stops taking in client writes, commits the writes that are waiting for group
+    /// commit, and sends on every update its domains are holding back. Each domain then tells the
+    /// controller which updates it has sent, so that whatever replaces it knows where to pick up.
+    /// This returns once every domain has done so, or once the drain timeout has passed, in which
+    /// case the worker logs what was left undone.
+    pub async fn drain(&mut self) {
+        if let Some(ref event_tx) = self.event_tx {
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            if event_tx.send(Event::Drain(tx)).is_ok() {
+                let _ = rx.await;
+            }
+        }
+    }
+
     /// Inform the local instance that it should exit.
     pub fn shutdown(&mut self) {
         if let Some(kill) = self.kill.take() {
@@ -378,8 +378,11 @@ pub enum ReuseConfigType {
 
 pub use crate::builder::Builder;
 pub use crate::handle::Handle;
+pub use crate::worker::CpuAffinity;
+#[cfg(feature = "fault_injection")]
+pub use crate::worker::{Faults, Injected};
 pub use controller::migrate::materialization::FrontierStrategy;
-pub use dataflow::{DurabilityMode, PersistenceParameters};
+pub use dataflow::{DurabilityMode, EvictionPolicy, OverloadPolicy, PersistenceParameters};
 pub use noria::consensus::LocalAuthority;
 pub use noria::*;
 pub use petgraph::graph::NodeIndex;
@@ -387,18 +390,37 @@ pub use petgraph::graph::NodeIndex;
 #[doc(hidden)]
 pub mod manual {
     pub use crate::controller::migrate::Migration;
-    pub use dataflow::node::special::Base;
+    pub use dataflow::node::special::{Base, DuplicateKeyPolicy, Ttl};
     pub use dataflow::ops;
 }
 
+/// Sources that feed base tables from Kafka topics.
+///
+/// Only available with the `kafka` feature.
+#[cfg(feature = "kafka")]
+pub mod kafka {
+    pub use crate::worker::kafka::{Decoder, JsonDecoder, KafkaSource, PoisonPolicy, SourceStats};
+}
+
+/// Clients for the external services that external lookups join records with.
+pub mod lookup {
+    pub use dataflow::ops::lookup::{LookupClient, LookupFuture};
+}
+
 use dataflow::DomainConfig;
 use std::time;
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
 pub(crate) struct Config {
     pub(crate) sharding: Option<usize>,
+    #[serde(default)]
+    pub(crate) hot_key_salting: Option<dataflow::node::special::HotKeySalting>,
+    #[serde(default)]
+    pub(crate) broadcast_threshold: Option<u64>,
     pub(crate) partial_enabled: bool,
     pub(crate) frontier_strategy: FrontierStrategy,
+    pub(crate) eviction_policy: EvictionPolicy,
+    pub(crate) prefix_lookups: bool,
     pub(crate) domain_config: DomainConfig,
     pub(crate) persistence: PersistenceParameters,
     pub(crate) heartbeat_every: time::Duration,
@@ -406,7 +428,28 @@ pub(crate) struct Config {
     pub(crate) quorum: usize,
     pub(crate) reuse: ReuseConfigType,
     pub(crate) threads: Option<usize>,
+    /// How many entries the controller's event log keeps.
+    #[serde(default = "default_event_log_retention")]
+    pub(crate) event_log_retention: usize,
+    /// How to spread domains onto workers that join a running deployment, if at all.
+    #[serde(default)]
+    pub(crate) rebalance: Option<noria::Rebalance>,
+    /// The most transactions that may be open at once.
+    #[serde(default = "default_max_open_transactions")]
+    pub(crate) max_open_transactions: usize,
+    /// How long a transaction may stay open before it is aborted, if not for ten heartbeats.
+    #[serde(default)]
+    pub(crate) transaction_timeout: Option<time::Duration>,
 }
+
+fn default_event_log_retention() -> usize {
+    10_000
+}
+
+fn default_max_open_transactions() -> usize {
+    1 << 16
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -414,11 +457,31 @@ impl Default for Config {
             sharding: Some(2),
             #[cfg(not(test))]
             sharding: None,
+            hot_key_salting: None,
+            broadcast_threshold: None,
             partial_enabled: true,
             frontier_strategy: Default::default(),
+            eviction_policy: Default::default(),
+            prefix_lookups: false,
             domain_config: DomainConfig {
                 concurrent_replays: 512,
                 replay_batch_timeout: time::Duration::new(0, 100_000),
+                replay_batch_size: 1024,
+                replay_parallelism: 1,
+                shard_hasher: Default::default(),
+                output_high_water: 4096,
+                overload_policy: Default::default(),
+                egress_batching: None,
+                spill_dir: None,
+                negative_caching: None,
+                propagation_sampling: 0.0,
+                slow_packet_threshold: None,
+                slow_replay_threshold: None,
+                freshness_interval: None,
+                dedup_window: Default::default(),
+                replay_rate: Default::default(),
+                replay_backoff_depth: 0,
+                capture_dir: None,
             },
             persistence: Default::default(),
             heartbeat_every: time::Duration::from_secs(1),
@@ -429,6 +492,10 @@ impl Default for Config {
             threads: Some(2),
             #[cfg(not(any(debug_assertions, test)))]
             threads: None,
+            event_log_retention: default_event_log_retention(),
+            rebalance: None,
+            max_open_transactions: default_max_open_transactions(),
+            transaction_timeout: None,
         }
     }
 }
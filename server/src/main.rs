@@ -1,8 +1,11 @@
 use clap::value_t_or_exit;
-use noria_server::{Builder, ReuseConfigType, ZookeeperAuthority};
+use noria_server::auth::Principals;
+use noria_server::channel::TlsConfig;
+use noria_server::{Builder, CpuAffinity, ReuseConfigType, ZookeeperAuthority};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
 
 fn main() {
     use clap::{App, Arg};
@@ -45,6 +48,20 @@ fn main() {
                 .default_value("100000")
                 .help("Time to wait before processing a merged packet, in nanoseconds."),
         )
+        .arg(
+            Arg::with_name("max-group-records")
+                .long("max-group-records")
+                .takes_value(true)
+                .default_value("10000")
+                .help("Most table operations to merge into one packet."),
+        )
+        .arg(
+            Arg::with_name("max-group-bytes")
+                .long("max-group-bytes")
+                .takes_value(true)
+                .default_value("4194304")
+                .help("Most bytes of table operations to merge into one packet."),
+        )
         .arg(
             Arg::with_name("log-dir")
                 .long("log-dir")
@@ -75,6 +92,13 @@ fn main() {
                 .requires("memory")
                 .help("Frequency at which to check the state size against the memory limit [in seconds]."),
         )
+        .arg(
+            Arg::with_name("domain_memory")
+                .long("domain-memory")
+                .takes_value(true)
+                .default_value("0")
+                .help("Memory, in bytes, available for the materialized state of each domain [0 = unlimited]."),
+        )
         .arg(
             Arg::with_name("noreuse")
                 .long("no-reuse")
@@ -100,6 +124,75 @@ fn main() {
                 .default_value("0")
                 .help("Shard the graph this many ways (0 = disable sharding)."),
         )
+        .arg(
+            Arg::with_name("pin-domains")
+                .long("pin-domains")
+                .help("Run each domain on a thread of its own, pinned to a core round-robin."),
+        )
+        .arg(
+            Arg::with_name("metrics-address")
+                .long("metrics-address")
+                .takes_value(true)
+                .help("Serve Prometheus metrics about this worker's domains at /metrics on this address."),
+        )
+        .arg(
+            Arg::with_name("view-http-address")
+                .long("view-http-address")
+                .takes_value(true)
+                .help("Serve the contents of views as JSON at /view/<name>/<key> on this address."),
+        )
+        .arg(
+            Arg::with_name("otlp-endpoint")
+                .long("otlp-endpoint")
+                .takes_value(true)
+                .help("Export spans of traced reads and writes over OTLP to this collector."),
+        )
+        .arg(
+            Arg::with_name("grpc-address")
+                .long("grpc-address")
+                .takes_value(true)
+                .help("Serve the gRPC interface for table writes and view reads on this address."),
+        )
+        .arg(
+            Arg::with_name("tls-cert")
+                .long("tls-cert")
+                .takes_value(true)
+                .requires_all(&["tls-key", "tls-ca"])
+                .help("PEM certificate chain to accept TLS connections with."),
+        )
+        .arg(
+            Arg::with_name("tls-key")
+                .long("tls-key")
+                .takes_value(true)
+                .requires("tls-cert")
+                .help("PEM private key for --tls-cert."),
+        )
+        .arg(
+            Arg::with_name("tls-ca")
+                .long("tls-ca")
+                .takes_value(true)
+                .help("PEM bundle of CAs to verify peers against. Enables TLS to peers that expect it."),
+        )
+        .arg(
+            Arg::with_name("tls-server-name")
+                .long("tls-server-name")
+                .takes_value(true)
+                .default_value("noria")
+                .help("Name that worker certificates are issued for."),
+        )
+        .arg(
+            Arg::with_name("principals")
+                .long("principals")
+                .takes_value(true)
+                .help("JSON file listing the principals that clients must present the token of."),
+        )
+        .arg(
+            Arg::with_name("drain-timeout")
+                .long("drain-timeout")
+                .takes_value(true)
+                .default_value("10")
+                .help("Seconds to let domains finish their work for on SIGTERM before exiting."),
+        )
         .arg(
             Arg::with_name("verbose")
                 .short("v")
@@ -116,9 +209,13 @@ fn main() {
     let zookeeper_addr = matches.value_of("zookeeper").unwrap();
     let memory = value_t_or_exit!(matches, "memory", usize);
     let memory_check_freq = value_t_or_exit!(matches, "memory_check_freq", u64);
+    let domain_memory = value_t_or_exit!(matches, "domain_memory", usize);
     let quorum = value_t_or_exit!(matches, "quorum", usize);
     let persistence_threads = value_t_or_exit!(matches, "persistence-threads", i32);
     let flush_ns = value_t_or_exit!(matches, "flush-timeout", u32);
+    let max_group_records = value_t_or_exit!(matches, "max-group-records", usize);
+    let max_group_bytes = value_t_or_exit!(matches, "max-group-bytes", usize);
+    let drain_timeout = value_t_or_exit!(matches, "drain-timeout", u64);
     let sharding = match value_t_or_exit!(matches, "shards", usize) {
         0 => None,
         x => Some(x),
@@ -133,6 +230,9 @@ fn main() {
     if memory > 0 {
         builder.set_memory_limit(memory, Duration::from_secs(memory_check_freq));
     }
+    if domain_memory > 0 {
+        builder.set_domain_memory_budget(domain_memory);
+    }
     builder.set_sharding(sharding);
     builder.set_quorum(quorum);
     if matches.is_present("nopartial") {
@@ -141,6 +241,32 @@ fn main() {
     if matches.is_present("noreuse") {
         builder.set_reuse(ReuseConfigType::NoReuse);
     }
+    if matches.is_present("pin-domains") {
+        builder.set_cpu_affinity(CpuAffinity::RoundRobin);
+    }
+    if let Some(addr) = matches.value_of("metrics-address") {
+        builder.set_metrics_address(addr.parse().unwrap());
+    }
+    if let Some(addr) = matches.value_of("view-http-address") {
+        builder.set_view_http_address(addr.parse().unwrap());
+    }
+    if let Some(endpoint) = matches.value_of("otlp-endpoint") {
+        builder.set_otlp_endpoint(endpoint);
+    }
+    if let Some(addr) = matches.value_of("grpc-address") {
+        builder.set_grpc_address(addr.parse().unwrap());
+    }
+    if let Some(ca) = matches.value_of("tls-ca") {
+        let tls = match (matches.value_of("tls-cert"), matches.value_of("tls-key")) {
+            (Some(cert), Some(key)) => TlsConfig::new(cert, key, ca),
+            _ => TlsConfig::client(ca),
+        };
+        builder.set_tls(tls.with_server_name(matches.value_of("tls-server-name").unwrap()));
+    }
+    if let Some(path) = matches.value_of("principals") {
+        builder.set_principals(Principals::from_file(path).unwrap());
+    }
+    builder.set_drain_timeout(Duration::from_secs(drain_timeout));
 
     let mut persistence_params = noria_server::PersistenceParameters::new(
         match durability {
@@ -156,6 +282,8 @@ fn main() {
     persistence_params.log_dir = matches
         .value_of("log-dir")
         .and_then(|p| Some(PathBuf::from(p)));
+    persistence_params.max_group_records = max_group_records;
+    persistence_params.max_group_bytes = max_group_bytes;
     builder.set_persistence(persistence_params);
 
     if verbose {
@@ -171,7 +299,18 @@ fn main() {
         rt.core_threads(threads);
     }
     let mut rt = rt.build().unwrap();
-    let (_server, done) = rt.block_on(builder.start(Arc::new(authority))).unwrap();
-    rt.block_on(done);
+    let (mut server, mut done) = rt.block_on(builder.start(Arc::new(authority))).unwrap();
+    rt.block_on(async move {
+        let mut terminate = signal(SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = &mut done => {}
+            _ = terminate.recv() => {
+                // finish the work we've been given before we go
+                server.drain().await;
+                server.shutdown();
+                done.await;
+            }
+        }
+    });
     drop(rt);
 }
@@ -1,6 +1,9 @@
+use crate::controller::migrate::progress::Progress;
+use crate::controller::recovery::Recovery;
 use crate::controller::ControllerState;
 use crate::coordination::{CoordinationMessage, CoordinationPayload};
 use async_bincode::AsyncBincodeReader;
+use dataflow::Telemetry;
 use futures_util::{
     future::FutureExt,
     future::TryFutureExt,
@@ -8,7 +11,11 @@ use futures_util::{
     stream::{StreamExt, TryStreamExt},
 };
 use hyper::{self, header::CONTENT_TYPE, Method, StatusCode};
+use noria::auth::Principals;
+use noria::channel::local;
+use noria::channel::tls::{self, Tls, TlsConfig};
 use noria::consensus::Authority;
+use noria::debug::health::HealthStatus;
 use noria::ControllerDescriptor;
 use std::io;
 use std::net::{IpAddr, SocketAddr};
@@ -23,7 +30,13 @@ use stream_cancel::Valve;
 use tokio::sync::mpsc::UnboundedSender;
 
 use crate::handle::Handle;
+use crate::worker::grpc::serve as serve_grpc;
+use crate::worker::health::{self, Liveness};
+use crate::worker::http::serve as serve_views;
+use crate::worker::metrics::{serve as serve_metrics, Registry};
+use crate::worker::{Faults, KafkaSource};
 use crate::Config;
+use crate::CpuAffinity;
 
 #[allow(clippy::large_enum_variant)]
 pub(crate) enum Event {
@@ -40,6 +53,7 @@ pub(crate) enum Event {
     CampaignError(failure::Error),
     #[cfg(test)]
     IsReady(tokio::sync::oneshot::Sender<bool>),
+    Drain(tokio::sync::oneshot::Sender<()>),
     ManualMigration {
         f: Box<dyn FnOnce(&mut crate::controller::migrate::Migration) + Send + 'static>,
         done: tokio::sync::oneshot::Sender<()>,
@@ -57,6 +71,7 @@ impl fmt::Debug for Event {
             Event::CampaignError(ref e) => write!(f, "CampaignError({:?})", e),
             #[cfg(test)]
             Event::IsReady(..) => write!(f, "IsReady"),
+            Event::Drain(..) => write!(f, "Drain"),
             Event::ManualMigration { .. } => write!(f, "ManualMigration{{..}}"),
         }
     }
@@ -70,8 +85,28 @@ pub(super) async fn start_instance<A: Authority + 'static>(
     config: Config,
     memory_limit: Option<usize>,
     memory_check_frequency: Option<time::Duration>,
+    domain_memory_budget: Option<usize>,
+    cpu_affinity: Option<CpuAffinity>,
+    faults: Option<Faults>,
+    metrics_addr: Option<SocketAddr>,
+    view_http_addr: Option<SocketAddr>,
+    grpc_addr: Option<SocketAddr>,
+    otlp_endpoint: Option<String>,
+    kafka_sources: Vec<KafkaSource>,
+    tls: Option<TlsConfig>,
+    principals: Option<Principals>,
+    drain_timeout: time::Duration,
+    labels: Vec<String>,
+    local_transport: bool,
     log: slog::Logger,
 ) -> Result<(Handle<A>, impl Future<Output = ()> + Unpin + Send), failure::Error> {
+    let tls = match tls {
+        Some(tls) => Some(tls.load()?),
+        None => None,
+    };
+    let expects_tls = tls.as_ref().map_or(false, Tls::accepts);
+    let principals = principals.map(Arc::new);
+
     let (trigger, valve) = Valve::new();
     let (alive, done) = tokio::sync::mpsc::channel(1);
     let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
@@ -91,12 +126,50 @@ pub(super) async fn start_instance<A: Authority + 'static>(
     // give it its own channel.
     let cport = tokio::net::TcpListener::bind(SocketAddr::new(listen_addr, 0)).await?;
     let caddr = cport.local_addr()?;
+    // and, if asked to, we serve metrics for scraping
+    let metrics = match metrics_addr {
+        Some(addr) => {
+            let mport = tokio::net::TcpListener::bind(addr).await?;
+            Some((mport.local_addr()?, mport, Registry::default()))
+        }
+        None => None,
+    };
+    // and, if asked to, we serve the contents of views over http
+    let view_http = match view_http_addr {
+        Some(addr) => Some(tokio::net::TcpListener::bind(addr).await?),
+        None => None,
+    };
+    let view_http_addr = match view_http {
+        Some(ref vport) => Some(vport.local_addr()?),
+        None => None,
+    };
+    // and the grpc interface for clients that don't speak our native protocol
+    let grpc = match grpc_addr {
+        Some(addr) => Some(tokio::net::TcpListener::bind(addr).await?),
+        None => None,
+    };
+    let grpc_addr = match grpc {
+        Some(ref gport) => Some(gport.local_addr()?),
+        None => None,
+    };
+    // and we export the spans of traced reads and writes
+    let telemetry = match otlp_endpoint {
+        Some(ref endpoint) => Telemetry::otlp(endpoint)
+            .map_err(|e| format_err!("cannot export spans to {}: {}", endpoint, e))?,
+        None => Telemetry::default(),
+    };
 
     // set up different loops for the controller "part" and the worker "part" of us. this is
     // necessary because sometimes the two need to communicate (e.g., for migrations), and if they
     // were in a single loop, that could deadlock.
     let (ctrl_tx, ctrl_rx) = tokio::sync::mpsc::unbounded_channel();
     let (worker_tx, worker_rx) = tokio::sync::mpsc::unbounded_channel();
+    // the controller is busy while it migrates or recovers, so how far it is with either is
+    // answered for without it
+    let progress = Progress::default();
+    let recovery = Recovery::default();
+    // and so is checking on the domains on this worker
+    let liveness = Arc::new(Liveness::default());
 
     // spawn all of those
     tokio::spawn(listen_internal(
@@ -105,7 +178,19 @@ pub(super) async fn start_instance<A: Authority + 'static>(
         log.clone(),
         tx.clone(),
         wport,
+        tls.clone(),
     ));
+    // the controller and workers in this process send each other messages in memory
+    if local_transport {
+        let (ltx, lrx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(listen_local(
+            alive.clone(),
+            valve.clone(),
+            local::register(waddr, ltx),
+            lrx.map(Event::InternalMessage),
+            tx.clone(),
+        ));
+    }
     let ext_log = log.clone();
     tokio::spawn(
         listen_external(
@@ -114,6 +199,9 @@ pub(super) async fn start_instance<A: Authority + 'static>(
             tx.clone(),
             xport,
             authority.clone(),
+            progress.clone(),
+            recovery.clone(),
+            liveness.clone(),
         )
         .map_err(move |e| {
             warn!(ext_log, "external request failed: {:?}", e);
@@ -121,6 +209,56 @@ pub(super) async fn start_instance<A: Authority + 'static>(
         .map(|_| ()),
     );
 
+    let metrics_addr = metrics.as_ref().map(|&(addr, ..)| addr);
+    let metrics = metrics.map(|(_, mut mport, registry)| {
+        let metrics_log = log.clone();
+        let incoming = valve.clone();
+        let r = registry.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_metrics(incoming.wrap(mport.incoming()), r).await {
+                warn!(metrics_log, "metrics endpoint failed: {:?}", e);
+            }
+        });
+        registry
+    });
+
+    if let Some(mut vport) = view_http {
+        let controller = client(authority.clone(), tls.clone()).await?;
+        let view_http_log = log.clone();
+        let incoming = valve.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_views(incoming.wrap(vport.incoming()), controller).await {
+                warn!(view_http_log, "view http endpoint failed: {:?}", e);
+            }
+        });
+    }
+
+    if let Some(mut gport) = grpc {
+        let controller = client(authority.clone(), tls.clone()).await?;
+        let grpc_log = log.clone();
+        let incoming = valve.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_grpc(incoming.wrap(gport.incoming()), controller).await {
+                warn!(grpc_log, "grpc endpoint failed: {:?}", e);
+            }
+        });
+    }
+
+    // and we feed base tables from kafka. without the feature, there are no sources to run.
+    #[cfg(feature = "kafka")]
+    for source in kafka_sources {
+        let controller = client(authority.clone(), tls.clone()).await?;
+        let kafka_log = log.new(o!("source" => source.topic().to_owned()));
+        tokio::spawn(crate::worker::kafka::run(
+            source,
+            controller,
+            valve.clone(),
+            kafka_log,
+        ));
+    }
+    #[cfg(not(feature = "kafka"))]
+    drop(kafka_sources);
+
     // first, a loop that just forwards to the appropriate place
     let a = alive.clone();
     tokio::spawn(async move {
@@ -134,6 +272,9 @@ pub(super) async fn start_instance<A: Authority + 'static>(
                     CoordinationPayload::RemoveDomain => wtx.send(e),
                     CoordinationPayload::AssignDomain(..) => wtx.send(e),
                     CoordinationPayload::DomainBooted(..) => wtx.send(e),
+                    CoordinationPayload::DomainPinned { .. } => ctx.send(e),
+                    CoordinationPayload::DomainQuiescent { .. } => ctx.send(e),
+                    CoordinationPayload::SenderFenced { .. } => ctx.send(e),
                     CoordinationPayload::Register { .. } => ctx.send(e),
                     CoordinationPayload::Heartbeat => ctx.send(e),
                     CoordinationPayload::CreateUniverse(..) => ctx.send(e),
@@ -141,6 +282,7 @@ pub(super) async fn start_instance<A: Authority + 'static>(
                 Event::ExternalRequest(..) => ctx.send(e),
                 Event::ManualMigration { .. } => ctx.send(e),
                 Event::LeaderChange(..) => wtx.send(e),
+                Event::Drain(..) => wtx.send(e),
                 Event::WonLeaderElection(..) => ctx.send(e),
                 Event::CampaignError(..) => ctx.send(e),
                 #[cfg(test)]
@@ -156,6 +298,7 @@ pub(super) async fn start_instance<A: Authority + 'static>(
         worker_addr: waddr,
         domain_addr: caddr,
         nonce: rand::random(),
+        tls: expects_tls,
     };
     tokio::spawn(crate::controller::main(
         alive.clone(),
@@ -164,9 +307,14 @@ pub(super) async fn start_instance<A: Authority + 'static>(
         descriptor,
         ctrl_rx,
         cport,
+        tls.clone(),
+        principals.clone(),
         log.clone(),
         authority.clone(),
         tx.clone(),
+        progress,
+        recovery,
+        local_transport,
     ));
     tokio::spawn(crate::worker::main(
         alive.clone(),
@@ -175,19 +323,53 @@ pub(super) async fn start_instance<A: Authority + 'static>(
         waddr,
         memory_limit,
         memory_check_frequency,
+        domain_memory_budget,
+        cpu_affinity,
+        faults,
+        liveness.clone(),
+        telemetry,
+        metrics,
+        tls.clone(),
+        principals,
+        drain_timeout,
+        labels,
+        local_transport,
         log.clone(),
     ));
 
-    let h = Handle::new(authority, tx, trigger).await?;
+    let h = Handle::new(
+        authority,
+        tls,
+        tx,
+        trigger,
+        liveness,
+        metrics_addr,
+        view_http_addr,
+        grpc_addr,
+    )
+    .await?;
     Ok((h, done.into_future().map(|_| {})))
 }
 
+/// A client for the instance's own use, which connects to workers that expect TLS over TLS.
+async fn client<A: Authority + 'static>(
+    authority: Arc<A>,
+    tls: Option<Tls>,
+) -> Result<noria::ControllerHandle<A>, failure::Error> {
+    let c = noria::ControllerHandle::make(authority).await?;
+    Ok(match tls {
+        Some(tls) => c.with_tls(tls),
+        None => c,
+    })
+}
+
 async fn listen_internal(
     alive: tokio::sync::mpsc::Sender<()>,
     valve: Valve,
     log: slog::Logger,
     event_tx: UnboundedSender<Event>,
     mut on: tokio::net::TcpListener,
+    tls: Option<Tls>,
 ) {
     let mut rx = valve.wrap(on.incoming());
     while let Some(r) = rx.next().await {
@@ -198,30 +380,65 @@ async fn listen_internal(
             }
             Ok(sock) => {
                 let alive = alive.clone();
-                tokio::spawn(
-                    valve
+                let valve = valve.clone();
+                let log = log.clone();
+                let tls = tls.clone();
+                let event_tx = event_tx.clone();
+                tokio::spawn(async move {
+                    let _alive = alive;
+                    let sock = match tls::maybe_accept(tls.as_ref(), sock).await {
+                        Ok(sock) => sock,
+                        Err(e) => {
+                            warn!(log, "internal connection failed TLS handshake: {:?}", e);
+                            return;
+                        }
+                    };
+                    let r = valve
                         .wrap(AsyncBincodeReader::from(sock))
                         .map_ok(Event::InternalMessage)
                         .map_err(failure::Error::from)
                         .forward(
-                            crate::ImplSinkForSender(event_tx.clone())
+                            crate::ImplSinkForSender(event_tx)
                                 .sink_map_err(|_| format_err!("main event loop went away")),
                         )
-                        .map_err(|e| panic!("{:?}", e))
-                        .map(move |_| {
-                            let _ = alive;
-                            ()
-                        }),
-                );
+                        .await;
+                    if let Err(e) = r {
+                        panic!("{:?}", e);
+                    }
+                });
             }
         }
     }
 }
 
+/// Pass on what is sent to an endpoint in memory to `tx`, until the instance shuts down, and the
+/// endpoint can no longer be reached in memory.
+pub(crate) async fn listen_local<S, T>(
+    alive: tokio::sync::mpsc::Sender<()>,
+    valve: Valve,
+    registration: local::Registration,
+    incoming: S,
+    tx: UnboundedSender<T>,
+) where
+    S: futures_util::stream::Stream<Item = T> + Unpin,
+{
+    let _alive = alive;
+    let _registration = registration;
+    let mut incoming = valve.wrap(incoming);
+    while let Some(t) = incoming.next().await {
+        if tx.send(t).is_err() {
+            break;
+        }
+    }
+}
+
 struct ExternalServer<A: Authority>(
     tokio::sync::mpsc::Sender<()>,
     UnboundedSender<Event>,
     Arc<A>,
+    Progress,
+    Recovery,
+    Arc<Liveness>,
 );
 
 async fn listen_external<A: Authority + 'static>(
@@ -230,6 +447,9 @@ async fn listen_external<A: Authority + 'static>(
     event_tx: UnboundedSender<Event>,
     mut on: tokio::net::TcpListener,
     authority: Arc<A>,
+    progress: Progress,
+    recovery: Recovery,
+    liveness: Arc<Liveness>,
 ) -> Result<(), hyper::Error> {
     let on = valve.wrap(on.incoming());
     use hyper::{service::make_service_fn, Body, Request, Response};
@@ -237,7 +457,14 @@ async fn listen_external<A: Authority + 'static>(
     impl<A: Authority> Clone for ExternalServer<A> {
         // Needed due to #26925
         fn clone(&self) -> Self {
-            ExternalServer(self.0.clone(), self.1.clone(), self.2.clone())
+            ExternalServer(
+                self.0.clone(),
+                self.1.clone(),
+                self.2.clone(),
+                self.3.clone(),
+                self.4.clone(),
+                self.5.clone(),
+            )
         }
     }
 
@@ -262,6 +489,31 @@ async fn listen_external<A: Authority + 'static>(
                             .body(hyper::Body::from(include_str!("graph.html")));
                         return Box::pin(async move { Ok(res.unwrap()) });
                     }
+                    "/health" => {
+                        // orchestration probes must not hang on a stuck domain, so each check
+                        // waits for the domains for no longer than its deadline
+                        let deadline = req
+                            .uri()
+                            .query()
+                            .and_then(|q| q.split('&').find(|kv| kv.starts_with("deadline_ms=")))
+                            .and_then(|kv| kv["deadline_ms=".len()..].parse().ok())
+                            .map(time::Duration::from_millis)
+                            .unwrap_or(health::DEFAULT_HEALTH_DEADLINE);
+                        let liveness = self.5.clone();
+                        return Box::pin(async move {
+                            let report = liveness.check(deadline).await;
+                            let res = match report.status {
+                                HealthStatus::Unhealthy => {
+                                    res.status(StatusCode::SERVICE_UNAVAILABLE)
+                                }
+                                HealthStatus::Healthy | HealthStatus::Degraded => res,
+                            };
+                            let res = res
+                                .header(CONTENT_TYPE, "application/json; charset=utf-8")
+                                .body(hyper::Body::from(serde_json::to_string(&report).unwrap()));
+                            Ok(res.unwrap())
+                        });
+                    }
                     path if path.starts_with("/zookeeper/") => {
                         let res = match self.2.try_read(&format!("/{}", &path[11..])) {
                             Ok(Some(data)) => res
@@ -274,6 +526,19 @@ async fn listen_external<A: Authority + 'static>(
                     _ => {}
                 }
             }
+            if let Method::POST = *req.method() {
+                let report = match req.uri().path() {
+                    "/migration_progress" => Some(serde_json::to_string(&self.3.report())),
+                    "/recovery_status" => Some(serde_json::to_string(&self.4.report())),
+                    _ => None,
+                };
+                if let Some(report) = report {
+                    let res = res
+                        .header(CONTENT_TYPE, "application/json; charset=utf-8")
+                        .body(hyper::Body::from(report.unwrap()));
+                    return Box::pin(async move { Ok(res.unwrap()) });
+                }
+            }
 
             let method = req.method().clone();
             let path = req.uri().path().to_string();
@@ -315,7 +580,7 @@ async fn listen_external<A: Authority + 'static>(
         }
     }
 
-    let service = ExternalServer(alive, event_tx, authority);
+    let service = ExternalServer(alive, event_tx, authority, progress, recovery, liveness);
     hyper::server::Server::builder(hyper::server::accept::from_stream(on))
         .serve(make_service_fn(move |_| {
             let s = service.clone();
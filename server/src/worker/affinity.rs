@@ -0,0 +1,145 @@
+use noria::internal::DomainIndex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+
+/// How a worker places the threads that run its domains on the machine's cores.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CpuAffinity {
+    /// Pin each domain shard's thread to a single core, taking the cores this process may run on
+    /// in turn.
+    RoundRobin,
+    /// Pin the thread of each listed domain shard, given as a domain index and a shard, to the
+    /// listed cores. Domain shards that are not listed are placed round-robin.
+    Explicit(HashMap<(usize, usize), Vec<usize>>),
+}
+
+impl Default for CpuAffinity {
+    fn default() -> Self {
+        CpuAffinity::RoundRobin
+    }
+}
+
+/// Picks the cores for each domain shard a worker starts.
+pub(super) struct Placement {
+    policy: CpuAffinity,
+    cores: Vec<usize>,
+    next: usize,
+}
+
+impl Placement {
+    /// Place domain threads according to `policy`, or return `None` if threads cannot be pinned
+    /// on this platform.
+    pub(super) fn new(policy: CpuAffinity) -> Option<Self> {
+        let cores = allowed_cores().ok()?;
+        if cores.is_empty() {
+            return None;
+        }
+        Some(Placement {
+            policy,
+            cores,
+            next: 0,
+        })
+    }
+
+    pub(super) fn cores_for(&mut self, domain: DomainIndex, shard: usize) -> Vec<usize> {
+        if let CpuAffinity::Explicit(ref map) = self.policy {
+            if let Some(cores) = map.get(&(domain.index(), shard)) {
+                return cores.clone();
+            }
+        }
+
+        let core = self.cores[self.next % self.cores.len()];
+        self.next += 1;
+        vec![core]
+    }
+}
+
+/// Run `fut` on a thread of its own that is pinned to `cores`.
+///
+/// The thread runs a single-threaded runtime, and any thread that runtime starts to run blocking
+/// work on is pinned to the same cores. If the thread cannot be pinned, a warning is logged and it
+/// runs wherever the OS puts it.
+pub(super) fn spawn_pinned<F>(
+    name: String,
+    cores: Vec<usize>,
+    log: slog::Logger,
+    fut: F,
+) -> io::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let pin_log = log.clone();
+    let pin_cores = cores.clone();
+    let mut rt = tokio::runtime::Builder::new()
+        .threaded_scheduler()
+        .core_threads(1)
+        .enable_all()
+        .thread_name(name.clone())
+        .on_thread_start(move || {
+            if let Err(e) = pin_current_thread(&pin_cores) {
+                warn!(pin_log, "failed to pin domain thread"; "cores" => ?pin_cores, "err" => %e);
+            }
+        })
+        .build()?;
+
+    let task = rt.spawn(fut);
+    std::thread::Builder::new().name(name).spawn(move || {
+        if let Err(e) = rt.block_on(task) {
+            crit!(log, "pinned domain thread failed: {:?}", e; "cores" => ?cores);
+        }
+    })?;
+    Ok(())
+}
+
+/// The cores the current thread may run on.
+#[cfg(target_os = "linux")]
+pub(super) fn allowed_cores() -> io::Result<Vec<usize>> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|&core| libc::CPU_ISSET(core, &set))
+            .collect())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(super) fn allowed_cores() -> io::Result<Vec<usize>> {
+    Err(unsupported())
+}
+
+#[cfg(target_os = "linux")]
+fn pin_current_thread(cores: &[usize]) -> io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &core in cores {
+            if core >= libc::CPU_SETSIZE as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("no core {}", core),
+                ));
+            }
+            libc::CPU_SET(core, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_current_thread(_: &[usize]) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        "thread affinity is not supported on this platform",
+    )
+}
@@ -0,0 +1,347 @@
+// without the feature, nothing outside of tests can make faults to inject
+#![cfg_attr(not(feature = "fault_injection"), allow(dead_code))]
+
+use super::replica::ReplicaAddr;
+use dataflow::Packet;
+use noria::internal::DomainIndex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// The faults to inject into the packets sent along one edge between two domains.
+struct Edge {
+    rng: StdRng,
+    drop_next: usize,
+    drop_rate: f64,
+    delay: Duration,
+    jitter: Duration,
+    duplicate_next: usize,
+    /// How many times the edge has been severed.
+    severs: u64,
+    injected: Injected,
+}
+
+/// How many faults have been injected into the packets sent along an edge between two domains.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Injected {
+    /// Packets that were dropped.
+    pub dropped: u64,
+    /// Packets that were held back.
+    pub delayed: u64,
+    /// Packets that were sent twice.
+    pub duplicated: u64,
+    /// Packets that were lost because the edge was severed before they were sent.
+    pub severed: u64,
+}
+
+struct Inner {
+    seed: u64,
+    edges: HashMap<(DomainIndex, DomainIndex), Edge>,
+}
+
+impl Inner {
+    fn edge(&mut self, from: DomainIndex, to: DomainIndex) -> &mut Edge {
+        let seed = self.seed ^ ((from.index() as u64) << 32 | to.index() as u64);
+        self.edges.entry((from, to)).or_insert_with(|| Edge {
+            rng: StdRng::seed_from_u64(seed),
+            drop_next: 0,
+            drop_rate: 0.0,
+            delay: Duration::from_secs(0),
+            jitter: Duration::from_secs(0),
+            duplicate_next: 0,
+            severs: 0,
+            injected: Injected::default(),
+        })
+    }
+}
+
+/// Faults to inject into the packets that domains send each other, so that tests can see how
+/// Noria copes with packets that are lost, late, out of order, or repeated.
+///
+/// Faults are set up per edge between two domains, and apply to every shard of either. Random
+/// choices are made with a generator per edge that is seeded from `seed` and the edge, so a test
+/// that sends the same packets along an edge sees the same faults every time it runs.
+///
+/// Only available with the `fault_injection` feature.
+#[derive(Clone)]
+pub struct Faults(Arc<Mutex<Inner>>);
+
+impl Faults {
+    /// Make faults whose random choices are seeded with `seed`. No faults are injected until they
+    /// are set up for an edge.
+    pub fn new(seed: u64) -> Self {
+        Faults(Arc::new(Mutex::new(Inner {
+            seed,
+            edges: HashMap::new(),
+        })))
+    }
+
+    /// Drop the next `n` packets that `from` sends to `to`.
+    pub fn drop_next(&self, from: DomainIndex, to: DomainIndex, n: usize) {
+        self.0.lock().unwrap().edge(from, to).drop_next += n;
+    }
+
+    /// Drop each packet that `from` sends to `to` with probability `p`.
+    pub fn drop_randomly(&self, from: DomainIndex, to: DomainIndex, p: f64) {
+        assert!((0.0..=1.0).contains(&p));
+        self.0.lock().unwrap().edge(from, to).drop_rate = p;
+    }
+
+    /// Hold each packet that `from` sends to `to` back for `delay`, plus a random amount up to
+    /// `jitter`. Packets that are held back for less can overtake those held back for longer.
+    pub fn delay(&self, from: DomainIndex, to: DomainIndex, delay: Duration, jitter: Duration) {
+        let mut inner = self.0.lock().unwrap();
+        let edge = inner.edge(from, to);
+        edge.delay = delay;
+        edge.jitter = jitter;
+    }
+
+    /// Send the next `n` packets that `from` sends to `to` twice.
+    pub fn duplicate_next(&self, from: DomainIndex, to: DomainIndex, n: usize) {
+        self.0.lock().unwrap().edge(from, to).duplicate_next += n;
+    }
+
+    /// Close the connections from `from` to `to`, and lose the packets that are waiting to be
+    /// sent on them. The next packet opens a new connection.
+    pub fn sever(&self, from: DomainIndex, to: DomainIndex) {
+        self.0.lock().unwrap().edge(from, to).severs += 1;
+    }
+
+    /// Stop injecting faults into the packets that `from` sends to `to`. Packets that are being
+    /// held back are still sent late.
+    pub fn heal(&self, from: DomainIndex, to: DomainIndex) {
+        let mut inner = self.0.lock().unwrap();
+        let edge = inner.edge(from, to);
+        edge.drop_next = 0;
+        edge.drop_rate = 0.0;
+        edge.delay = Duration::from_secs(0);
+        edge.jitter = Duration::from_secs(0);
+        edge.duplicate_next = 0;
+    }
+
+    /// How many faults have been injected into the packets that `from` sent to `to`.
+    pub fn injected(&self, from: DomainIndex, to: DomainIndex) -> Injected {
+        self.0
+            .lock()
+            .unwrap()
+            .edges
+            .get(&(from, to))
+            .map(|e| e.injected)
+            .unwrap_or_default()
+    }
+
+    /// The faults as seen by one domain shard that sends packets.
+    pub(super) fn for_sender(&self, from: ReplicaAddr) -> SenderFaults {
+        SenderFaults {
+            faults: self.clone(),
+            from,
+            held: Vec::new(),
+            timer: None,
+            severs: HashMap::new(),
+        }
+    }
+}
+
+/// The faults to inject into the packets that one domain shard sends.
+pub(super) struct SenderFaults {
+    faults: Faults,
+    from: ReplicaAddr,
+    /// Packets that are being held back, with when to send them.
+    held: Vec<(Instant, ReplicaAddr, Box<Packet>)>,
+    /// Wakes the domain up when the next held back packet is due.
+    timer: Option<tokio::time::Delay>,
+    /// How many times each edge had been severed when we last looked.
+    severs: HashMap<DomainIndex, u64>,
+}
+
+impl SenderFaults {
+    /// Inject faults into a packet about to be sent to `to`, and return the packets to send now.
+    pub(super) fn on_send(&mut self, to: ReplicaAddr, m: Box<Packet>) -> Vec<Box<Packet>> {
+        let mut inner = self.faults.0.lock().unwrap();
+        let edge = inner.edge(self.from.0, to.0);
+
+        if edge.drop_next > 0 {
+            edge.drop_next -= 1;
+            edge.injected.dropped += 1;
+            return vec![];
+        }
+        if edge.drop_rate > 0.0 && edge.rng.gen_bool(edge.drop_rate) {
+            edge.injected.dropped += 1;
+            return vec![];
+        }
+
+        let mut out = vec![m];
+        if edge.duplicate_next > 0 {
+            edge.duplicate_next -= 1;
+            edge.injected.duplicated += 1;
+            let copy = out[0].clone();
+            out.push(copy);
+        }
+
+        if edge.delay > Duration::from_secs(0) || edge.jitter > Duration::from_secs(0) {
+            let jitter = if edge.jitter > Duration::from_secs(0) {
+                edge.jitter.mul_f64(edge.rng.gen::<f64>())
+            } else {
+                Duration::from_secs(0)
+            };
+            let at = Instant::now() + edge.delay + jitter;
+            edge.injected.delayed += out.len() as u64;
+            self.held.extend(out.into_iter().map(|m| (at, to, m)));
+            return vec![];
+        }
+        out
+    }
+
+    /// The held back packets that are due to be sent, and when the next of the rest is.
+    pub(super) fn release(&mut self) -> (Vec<(ReplicaAddr, Box<Packet>)>, Option<Instant>) {
+        let now = Instant::now();
+        let (due, held): (Vec<_>, Vec<_>) = self.held.drain(..).partition(|&(at, _, _)| at <= now);
+        self.held = held;
+        let next = self.held.iter().map(|&(at, _, _)| at).min();
+        (due.into_iter().map(|(_, to, m)| (to, m)).collect(), next)
+    }
+
+    /// The held back packets that are due to be sent, making sure that the domain is woken up
+    /// when the next of the rest is.
+    pub(super) fn poll_release(&mut self, cx: &mut Context<'_>) -> Vec<(ReplicaAddr, Box<Packet>)> {
+        let (due, next) = self.release();
+        match next {
+            Some(at) => {
+                let at = tokio::time::Instant::from_std(at);
+                match self.timer {
+                    Some(ref mut timer) if timer.deadline() != at => timer.reset(at),
+                    Some(_) => {}
+                    None => self.timer = Some(tokio::time::delay_until(at)),
+                }
+                if let Some(ref mut timer) = self.timer {
+                    if let Poll::Ready(()) = Pin::new(timer).poll(cx) {
+                        // the next packet became due just now, so go around again
+                        cx.waker().wake_by_ref();
+                    }
+                }
+            }
+            None => self.timer = None,
+        }
+        due
+    }
+
+    /// The domains whose connections from us have been severed since we last looked.
+    pub(super) fn severed(&mut self) -> Vec<DomainIndex> {
+        let inner = self.faults.0.lock().unwrap();
+        let from = self.from.0;
+        let severs = &mut self.severs;
+        inner
+            .edges
+            .iter()
+            .filter(|&(&(f, _), _)| f == from)
+            .filter_map(|(&(_, to), edge)| {
+                let seen = severs.entry(to).or_insert(0);
+                if edge.severs > *seen {
+                    *seen = edge.severs;
+                    Some(to)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Record that `n` packets were lost because the edge to `to` was severed.
+    pub(super) fn lost(&self, to: DomainIndex, n: usize) {
+        self.faults
+            .0
+            .lock()
+            .unwrap()
+            .edge(self.from.0, to)
+            .injected
+            .severed += n as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sends(faults: &Faults, n: usize) -> Vec<bool> {
+        let mut sender = faults.for_sender((DomainIndex::from(0), 0));
+        (0..n)
+            .map(|_| {
+                !sender
+                    .on_send((DomainIndex::from(1), 0), Box::new(Packet::Spin))
+                    .is_empty()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deterministic_given_seed() {
+        let faults = Faults::new(42);
+        faults.drop_randomly(0.into(), 1.into(), 0.5);
+        let first = sends(&faults, 100);
+        assert!(first.iter().any(|&sent| sent));
+        assert!(first.iter().any(|&sent| !sent));
+
+        let faults = Faults::new(42);
+        faults.drop_randomly(0.into(), 1.into(), 0.5);
+        assert_eq!(sends(&faults, 100), first);
+    }
+
+    #[test]
+    fn drops_and_duplicates() {
+        let faults = Faults::new(0);
+        faults.drop_next(0.into(), 1.into(), 2);
+        faults.duplicate_next(0.into(), 1.into(), 1);
+        let mut sender = faults.for_sender((0.into(), 0));
+        let counts: Vec<_> = (0..4)
+            .map(|_| sender.on_send((1.into(), 0), Box::new(Packet::Spin)).len())
+            .collect();
+        assert_eq!(counts, vec![0, 0, 2, 1]);
+        // other edges are left alone
+        assert_eq!(
+            sender.on_send((2.into(), 0), Box::new(Packet::Spin)).len(),
+            1
+        );
+
+        let injected = faults.injected(0.into(), 1.into());
+        assert_eq!(injected.dropped, 2);
+        assert_eq!(injected.duplicated, 1);
+    }
+
+    #[test]
+    fn delays() {
+        let faults = Faults::new(0);
+        faults.delay(
+            0.into(),
+            1.into(),
+            Duration::from_millis(10),
+            Duration::from_secs(0),
+        );
+        let mut sender = faults.for_sender((0.into(), 0));
+        assert!(sender
+            .on_send((1.into(), 0), Box::new(Packet::Spin))
+            .is_empty());
+        let (due, next) = sender.release();
+        assert!(due.is_empty());
+        assert!(next.is_some());
+
+        std::thread::sleep(Duration::from_millis(20));
+        let (due, next) = sender.release();
+        assert_eq!(due.len(), 1);
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn severs() {
+        let faults = Faults::new(0);
+        let mut sender = faults.for_sender((0.into(), 0));
+        assert!(sender.severed().is_empty());
+        faults.sever(0.into(), 1.into());
+        assert_eq!(sender.severed(), vec![DomainIndex::from(1)]);
+        assert!(sender.severed().is_empty());
+    }
+}
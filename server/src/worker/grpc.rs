@@ -0,0 +1,366 @@
+use futures_util::stream::{Stream, StreamExt};
+use noria::consensus::Authority;
+use noria::error::{TableError, ViewError};
+use noria::subscription::{Change, SubscriptionOptions, Update};
+use noria::{ControllerHandle, DataType, Table, TableOperation, View};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tonic::{Request, Response, Status};
+
+/// The types and service definitions generated from `proto/noria.proto`.
+pub(crate) mod proto {
+    tonic::include_proto!("noria");
+}
+
+use self::proto::value::Kind;
+
+/// Serve the `Noria` gRPC service on `incoming`.
+///
+/// Writes and reads go through the same `Table` and `View` handles that Rust clients use, so they
+/// are routed to the right shards, and follow them when they move.
+pub(crate) async fn serve<S, A>(
+    incoming: S,
+    controller: ControllerHandle<A>,
+) -> Result<(), tonic::transport::Error>
+where
+    S: Stream<Item = io::Result<tokio::net::TcpStream>> + Send,
+    A: Authority + 'static,
+{
+    tonic::transport::Server::builder()
+        .add_service(proto::noria_server::NoriaServer::new(Service {
+            controller,
+            tables: Default::default(),
+            views: Default::default(),
+        }))
+        .serve_with_incoming(incoming)
+        .await
+}
+
+struct Service<A: Authority + 'static> {
+    controller: ControllerHandle<A>,
+    // handles are cached, since fetching them from the controller for every request would be slow
+    tables: Arc<Mutex<HashMap<String, Table>>>,
+    views: Arc<Mutex<HashMap<String, View>>>,
+}
+
+impl<A: Authority + 'static> Service<A> {
+    async fn table(&self, name: &str) -> Result<Table, Status> {
+        let cached = self.tables.lock().unwrap().get(name).cloned();
+        if let Some(table) = cached {
+            return Ok(table);
+        }
+
+        let mut controller = self.controller.clone();
+        controller
+            .ready()
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        let table = controller
+            .table(name)
+            .await
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        self.tables
+            .lock()
+            .unwrap()
+            .insert(name.to_owned(), table.clone());
+        Ok(table)
+    }
+
+    async fn view(&self, name: &str) -> Result<View, Status> {
+        let cached = self.views.lock().unwrap().get(name).cloned();
+        if let Some(view) = cached {
+            return Ok(view);
+        }
+
+        let mut controller = self.controller.clone();
+        controller
+            .ready()
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        let view = controller
+            .view(name)
+            .await
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        self.views
+            .lock()
+            .unwrap()
+            .insert(name.to_owned(), view.clone());
+        Ok(view)
+    }
+
+    /// Perform `ops` on the table called `name`.
+    async fn write(&self, name: &str, ops: Vec<TableOperation>) -> Result<(), Status> {
+        let mut table = self.table(name).await?;
+        match table.perform_all(ops).await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                if let TableError::TransportError(_) = e {
+                    // the table may have been removed or replaced, so fetch it again next time
+                    self.tables.lock().unwrap().remove(name);
+                }
+                Err(table_error(e))
+            }
+        }
+    }
+
+    /// Look up `keys` in the view called `name`.
+    async fn read(
+        &self,
+        name: &str,
+        keys: Vec<Vec<DataType>>,
+        block: bool,
+    ) -> Result<(Vec<String>, Vec<Vec<proto::Row>>), Status> {
+        let mut view = self.view(name).await?;
+        let results = match view.multi_lookup(keys, block).await {
+            Ok(results) => results,
+            Err(e) => {
+                if let ViewError::Redirected | ViewError::TransportError(_) = e {
+                    self.views.lock().unwrap().remove(name);
+                }
+                return Err(view_error(e));
+            }
+        };
+        if results.iter().any(|rs| rs.is_pending()) {
+            return Err(Status::unavailable(
+                "the key is still being backfilled; retry later",
+            ));
+        }
+
+        let columns = view.columns().to_vec();
+        let results = results
+            .into_iter()
+            .map(|rs| {
+                let rows: Vec<Vec<DataType>> = rs.into();
+                rows.into_iter()
+                    .map(|mut row| {
+                        // readers may keep columns beyond those of the view
+                        row.truncate(columns.len());
+                        to_row(&row)
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok((columns, results))
+    }
+}
+
+#[tonic::async_trait]
+impl<A: Authority + 'static> proto::noria_server::Noria for Service<A> {
+    async fn insert_many(
+        &self,
+        request: Request<proto::InsertManyRequest>,
+    ) -> Result<Response<proto::WriteReply>, Status> {
+        let request = request.into_inner();
+        let ops = request
+            .rows
+            .into_iter()
+            .map(|r| from_row(r).map(TableOperation::Insert))
+            .collect::<Result<_, _>>()?;
+        self.write(&request.table, ops).await?;
+        Ok(Response::new(proto::WriteReply {}))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<proto::DeleteRequest>,
+    ) -> Result<Response<proto::WriteReply>, Status> {
+        let request = request.into_inner();
+        let ops = request
+            .keys
+            .into_iter()
+            .map(|k| from_row(k).map(|key| TableOperation::Delete { key }))
+            .collect::<Result<_, _>>()?;
+        self.write(&request.table, ops).await?;
+        Ok(Response::new(proto::WriteReply {}))
+    }
+
+    async fn upsert(
+        &self,
+        request: Request<proto::UpsertRequest>,
+    ) -> Result<Response<proto::WriteReply>, Status> {
+        let request = request.into_inner();
+        if !self.table(&request.table).await?.has_primary_key() {
+            return Err(Status::failed_precondition(
+                "upserts require a table with a primary key",
+            ));
+        }
+        let ops = request
+            .rows
+            .into_iter()
+            .map(|r| from_row(r).map(TableOperation::Upsert))
+            .collect::<Result<_, _>>()?;
+        self.write(&request.table, ops).await?;
+        Ok(Response::new(proto::WriteReply {}))
+    }
+
+    async fn lookup(
+        &self,
+        request: Request<proto::LookupRequest>,
+    ) -> Result<Response<proto::LookupReply>, Status> {
+        let request = request.into_inner();
+        let key = from_row(request.key.unwrap_or_default())?;
+        let (columns, mut results) = self.read(&request.view, vec![key], request.block).await?;
+        Ok(Response::new(proto::LookupReply {
+            columns,
+            rows: results.pop().unwrap_or_default(),
+        }))
+    }
+
+    async fn multi_lookup(
+        &self,
+        request: Request<proto::MultiLookupRequest>,
+    ) -> Result<Response<proto::MultiLookupReply>, Status> {
+        let request = request.into_inner();
+        let keys = request
+            .keys
+            .into_iter()
+            .map(from_row)
+            .collect::<Result<_, _>>()?;
+        let (columns, results) = self.read(&request.view, keys, request.block).await?;
+        Ok(Response::new(proto::MultiLookupReply {
+            columns,
+            results: results
+                .into_iter()
+                .map(|rows| proto::RowSet { rows })
+                .collect(),
+        }))
+    }
+
+    type SubscribeStream = tokio::sync::mpsc::Receiver<Result<proto::StreamUpdate, Status>>;
+
+    async fn subscribe(
+        &self,
+        request: Request<proto::SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let request = request.into_inner();
+        let key = request.key.map(from_row).transpose()?;
+        let mut view = self.view(&request.view).await?;
+        let mut subscription = view
+            .subscribe(key, SubscriptionOptions::default())
+            .await
+            .map_err(view_error)?;
+
+        let (mut tx, rx) = tokio::sync::mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Some(update) = subscription.next().await {
+                let update = match update {
+                    Ok(Update::Batch { changes, .. }) => Ok(proto::StreamUpdate {
+                        changes: changes
+                            .into_iter()
+                            .map(|c| match c {
+                                Change::Positive(row) => proto::Change {
+                                    positive: true,
+                                    row: Some(to_row(&row)),
+                                },
+                                Change::Negative(row) => proto::Change {
+                                    positive: false,
+                                    row: Some(to_row(&row)),
+                                },
+                            })
+                            .collect(),
+                        lagged: false,
+                    }),
+                    Ok(Update::Lagged) => Ok(proto::StreamUpdate {
+                        changes: Vec::new(),
+                        lagged: true,
+                    }),
+                    Err(e) => Err(view_error(e)),
+                };
+                let failed = update.is_err();
+                if tx.send(update).await.is_err() || failed {
+                    // the client went away, or the subscription ended
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(rx))
+    }
+}
+
+fn table_error(e: TableError) -> Status {
+    match e {
+        TableError::WrongColumnCount(..)
+        | TableError::WrongKeyColumnCount(..)
+        | TableError::InvalidValue(..) => Status::invalid_argument(e.to_string()),
+        TableError::DuplicateKey(..) => Status::already_exists(e.to_string()),
+        TableError::Overloaded(..) => Status::resource_exhausted(e.to_string()),
+        TableError::TransportError(..) => Status::unavailable(e.to_string()),
+    }
+}
+
+fn view_error(e: ViewError) -> Status {
+    match e {
+        ViewError::NotYetAvailable | ViewError::StillWarming { .. } => {
+            Status::unavailable(e.to_string())
+        }
+        ViewError::WriteTimeout => Status::deadline_exceeded(e.to_string()),
+        ViewError::PrefixNotSupported => Status::failed_precondition(e.to_string()),
+        ViewError::SubscriptionLagged => Status::aborted(e.to_string()),
+        ViewError::Unauthorized(..) => Status::permission_denied(e.to_string()),
+        ViewError::Redirected | ViewError::TransportError(..) => Status::unavailable(e.to_string()),
+    }
+}
+
+fn to_row(row: &[DataType]) -> proto::Row {
+    proto::Row {
+        values: row.iter().map(to_value).collect(),
+    }
+}
+
+fn from_row(row: proto::Row) -> Result<Vec<DataType>, Status> {
+    row.values.into_iter().map(from_value).collect()
+}
+
+/// A value in its protobuf form. `NULL` is a value with no kind.
+fn to_value(d: &DataType) -> proto::Value {
+    let kind = match *d {
+        DataType::None => None,
+        DataType::Bool(b) => Some(Kind::Bool(b)),
+        DataType::Int(n) => Some(Kind::Int(n.into())),
+        DataType::BigInt(n) => Some(Kind::Int(n)),
+        DataType::UnsignedInt(n) => Some(Kind::Uint(n.into())),
+        DataType::UnsignedBigInt(n) => Some(Kind::Uint(n)),
+        DataType::HugeInt(ref n) => Some(Kind::HugeInt(n.to_string())),
+        DataType::Real(..) => Some(Kind::Real(f64::from(d))),
+        DataType::Decimal(..) => Some(Kind::Decimal(d.to_string())),
+        DataType::Text(..) | DataType::TinyText(..) | DataType::CiText(..) => {
+            Some(Kind::Text(<&str>::from(d).to_owned()))
+        }
+        DataType::Json(..) => Some(Kind::Json(<&str>::from(d).to_owned())),
+        DataType::Bytes(ref b) => Some(Kind::Bytes(b.to_vec())),
+        DataType::Uuid(ref b) => Some(Kind::Uuid(b.to_vec())),
+        DataType::Timestamp(ts) => Some(Kind::Timestamp(
+            ts.format("%Y-%m-%d %H:%M:%S%.f").to_string(),
+        )),
+        DataType::Date(date) => Some(Kind::Date(date.format("%Y-%m-%d").to_string())),
+    };
+    proto::Value { kind }
+}
+
+fn from_value(v: proto::Value) -> Result<DataType, Status> {
+    let invalid = |what: &str| Status::invalid_argument(format!("invalid {} value", what));
+    Ok(match v.kind {
+        None => DataType::None,
+        Some(Kind::Bool(b)) => DataType::from(b),
+        Some(Kind::Int(n)) => DataType::from(n),
+        Some(Kind::Uint(n)) => DataType::from(n),
+        Some(Kind::HugeInt(s)) => {
+            DataType::from(s.parse::<i128>().map_err(|_| invalid("huge_int"))?)
+        }
+        Some(Kind::Real(f)) if f.is_finite() => DataType::from(f),
+        Some(Kind::Real(_)) => return Err(invalid("real")),
+        Some(Kind::Decimal(s)) => DataType::parse_decimal(&s).map_err(|_| invalid("decimal"))?,
+        Some(Kind::Text(s)) => DataType::from(s),
+        Some(Kind::Bytes(b)) => DataType::from(b),
+        Some(Kind::Timestamp(s)) => {
+            DataType::parse_datetime(&s).map_err(|_| invalid("timestamp"))?
+        }
+        Some(Kind::Date(s)) => DataType::parse_date(&s).map_err(|_| invalid("date"))?,
+        Some(Kind::Uuid(b)) => {
+            DataType::from(<[u8; 16]>::try_from(&b[..]).map_err(|_| invalid("uuid"))?)
+        }
+        Some(Kind::Json(s)) => DataType::parse_json(&s).map_err(|_| invalid("json"))?,
+    })
+}
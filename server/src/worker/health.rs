@@ -0,0 +1,102 @@
+use super::replica::ReplicaAddr;
+use dataflow::Packet;
+use futures_util::future;
+use noria::debug::health::{DomainHealth, HealthReport, HealthStatus};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+/// How long a worker waits for its domains to answer a health check unless asked otherwise.
+pub(crate) const DEFAULT_HEALTH_DEADLINE: Duration = Duration::from_secs(1);
+
+/// The domain shards running on a worker, and the pings they have yet to answer.
+///
+/// A ping is sent down the same local channel as the packets that other domains on the worker
+/// send a domain shard, and is answered by the shard's event loop once it gets to it. So a domain
+/// shard that is stuck, or too far behind to get to it in time, does not answer.
+#[derive(Default)]
+pub(crate) struct Liveness {
+    domains: Mutex<HashMap<ReplicaAddr, mpsc::UnboundedSender<Box<Packet>>>>,
+    pings: Mutex<HashMap<u64, oneshot::Sender<()>>>,
+    next_ping: AtomicU64,
+}
+
+impl Liveness {
+    /// Start checking on a domain shard, which reads what is sent on `tx`.
+    pub(super) fn insert(&self, domain: ReplicaAddr, tx: mpsc::UnboundedSender<Box<Packet>>) {
+        self.domains.lock().unwrap().insert(domain, tx);
+    }
+
+    /// Stop checking on a domain shard that has gone away.
+    pub(super) fn remove(&self, domain: ReplicaAddr) {
+        self.domains.lock().unwrap().remove(&domain);
+    }
+
+    /// Answer a ping.
+    pub(super) fn pong(&self, ping: u64) {
+        if let Some(tx) = self.pings.lock().unwrap().remove(&ping) {
+            // whoever sent it may have given up on it already
+            let _ = tx.send(());
+        }
+    }
+
+    /// Ping every domain shard on the worker, and wait up to `deadline` for them to answer.
+    pub(crate) async fn check(&self, deadline: Duration) -> HealthReport {
+        let domains: Vec<_> = self
+            .domains
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&domain, tx)| (domain, tx.clone()))
+            .collect();
+
+        let mut sent = Vec::with_capacity(domains.len());
+        let mut answers = Vec::with_capacity(domains.len());
+        for ((domain, shard), tx) in domains {
+            let ping = self.next_ping.fetch_add(1, Ordering::Relaxed);
+            let (pong_tx, pong_rx) = oneshot::channel();
+            self.pings.lock().unwrap().insert(ping, pong_tx);
+            sent.push(ping);
+
+            let start = Instant::now();
+            // a domain shard that has just gone away does not answer
+            let _ = tx.send(Box::new(Packet::Ping(ping)));
+            answers.push(async move {
+                let answered = tokio::time::timeout(deadline, pong_rx).await;
+                DomainHealth {
+                    domain,
+                    shard,
+                    round_trip: match answered {
+                        Ok(Ok(())) => Some(start.elapsed()),
+                        _ => None,
+                    },
+                }
+            });
+        }
+        let mut domains = future::join_all(answers).await;
+
+        // forget the pings that were not answered in time
+        let mut pings = self.pings.lock().unwrap();
+        for ping in sent {
+            pings.remove(&ping);
+        }
+        drop(pings);
+
+        domains.sort_by_key(|d| (d.domain, d.shard));
+        let answered = domains.iter().filter(|d| d.round_trip.is_some()).count();
+        let status = if answered == domains.len() {
+            HealthStatus::Healthy
+        } else if answered == 0 {
+            HealthStatus::Unhealthy
+        } else {
+            HealthStatus::Degraded
+        };
+        HealthReport {
+            status,
+            deadline,
+            domains,
+        }
+    }
+}
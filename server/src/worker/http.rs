@@ -0,0 +1,355 @@
+use futures_util::stream::Stream;
+use hyper::{header::CONTENT_TYPE, Body, Method, Request, Response, StatusCode};
+use nom_sql::SqlType;
+use noria::consensus::Authority;
+use noria::{ControllerHandle, DataType, PageToken, View, ViewError};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The longest a read may take before it fails with `504 Gateway Timeout`.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A failed request, along with the status code and message to answer it with.
+type Error = (StatusCode, String);
+
+/// Serve the contents of views as JSON.
+///
+/// `GET /view/{name}/{key}` looks up a single-column key, and `POST /view/{name}` looks up the
+/// key given as a JSON array in the request body. Keys are parsed according to the types of the
+/// view's key columns. The query string may hold:
+///
+///  - `block=false` to answer a miss in partial state with `503 Service Unavailable` rather than
+///    wait for the key to be backfilled.
+///  - `timeout_ms=N` to wait at most `N` milliseconds for the backfill instead.
+///  - `limit=N` to return at most `N` rows, and `page=T` to continue from where the page that
+///    returned the token `T` left off.
+///
+/// Results are returned as `{"columns": [..], "rows": [[..], ..]}`, along with the token of the
+/// next page as `next` for paginated lookups.
+pub(crate) async fn serve<S, A>(
+    incoming: S,
+    controller: ControllerHandle<A>,
+) -> Result<(), hyper::Error>
+where
+    S: Stream<Item = io::Result<tokio::net::TcpStream>> + Send,
+    A: Authority + 'static,
+{
+    use hyper::service::{make_service_fn, service_fn};
+
+    // views are cached, since fetching a view from the controller for every read would be slow
+    let views: Arc<Mutex<HashMap<String, View>>> = Default::default();
+    hyper::server::Server::builder(hyper::server::accept::from_stream(incoming))
+        .serve(make_service_fn(move |_| {
+            let controller = controller.clone();
+            let views = views.clone();
+            async move {
+                io::Result::Ok(service_fn(move |req: Request<Body>| {
+                    let controller = controller.clone();
+                    let views = views.clone();
+                    async move {
+                        let res = match handle(controller, views, req).await {
+                            Ok(body) => Response::builder()
+                                .header(CONTENT_TYPE, "application/json")
+                                .body(Body::from(body.to_string())),
+                            Err((status, error)) => Response::builder()
+                                .status(status)
+                                .header(CONTENT_TYPE, "application/json")
+                                .body(Body::from(json!({ "error": error }).to_string())),
+                        };
+                        Ok::<_, hyper::Error>(res.unwrap())
+                    }
+                }))
+            }
+        }))
+        .await
+}
+
+async fn handle<A: Authority + 'static>(
+    mut controller: ControllerHandle<A>,
+    views: Arc<Mutex<HashMap<String, View>>>,
+    req: Request<Body>,
+) -> Result<Value, Error> {
+    let bad_request = |e: String| (StatusCode::BAD_REQUEST, e);
+
+    let path: Vec<_> = req.uri().path().trim_matches('/').split('/').collect();
+    let (name, key) = match (req.method(), &path[..]) {
+        (&Method::GET, &["view", name, key]) => (name, Some(key)),
+        (&Method::POST, &["view", name]) => (name, None),
+        _ => return Err((StatusCode::NOT_FOUND, "no such endpoint".to_owned())),
+    };
+    let name = decode(name).ok_or_else(|| bad_request("malformed view name".to_owned()))?;
+    let key = match key {
+        Some(key) => Some(vec![Value::String(
+            decode(key).ok_or_else(|| bad_request("malformed key".to_owned()))?,
+        )]),
+        None => None,
+    };
+
+    let params: HashMap<_, _> = req
+        .uri()
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let mut kv = p.splitn(2, '=');
+            let k = kv.next().unwrap();
+            let v = kv.next().unwrap_or("");
+            match (decode(&k.replace('+', " ")), decode(&v.replace('+', " "))) {
+                (Some(k), Some(v)) => Ok((k, v)),
+                _ => Err(bad_request("malformed query string".to_owned())),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    let param = |name: &str| -> Result<Option<u64>, Error> {
+        params
+            .get(name)
+            .map(|v| {
+                v.parse()
+                    .map_err(|_| bad_request(format!("invalid {}", name)))
+            })
+            .transpose()
+    };
+    let block = match params.get("block").map(String::as_str) {
+        None | Some("true") | Some("1") => true,
+        Some("false") | Some("0") => false,
+        Some(_) => return Err(bad_request("invalid block".to_owned())),
+    };
+    let timeout = param("timeout_ms")?.map(Duration::from_millis);
+    let limit = param("limit")?;
+    let page = params
+        .get("page")
+        .map(|t| {
+            serde_json::from_str::<PageToken>(t).map_err(|_| bad_request("invalid page".to_owned()))
+        })
+        .transpose()?;
+    if limit == Some(0) {
+        return Err(bad_request("limit must be at least 1".to_owned()));
+    }
+    if page.is_some() && limit.is_none() {
+        return Err(bad_request("page requires a limit".to_owned()));
+    }
+    if timeout.is_some() && (limit.is_some() || !block) {
+        return Err(bad_request(
+            "timeout_ms cannot be combined with limit or block=false".to_owned(),
+        ));
+    }
+
+    let key = match key {
+        Some(key) => key,
+        None => {
+            let body = hyper::body::to_bytes(req.into_body())
+                .await
+                .map_err(|e| bad_request(e.to_string()))?;
+            serde_json::from_slice::<Vec<Value>>(&body).map_err(|_| {
+                bad_request("the body must be a JSON array of key values".to_owned())
+            })?
+        }
+    };
+
+    let cached = views.lock().unwrap().get(&name).cloned();
+    let mut view = match cached {
+        Some(view) => view,
+        None => {
+            controller
+                .ready()
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            match controller.view(&name).await {
+                Ok(view) => view,
+                Err(e) => {
+                    let exists = match controller.ready().await {
+                        Ok(()) => controller
+                            .outputs()
+                            .await
+                            .map(|outputs| outputs.contains_key(&name))
+                            .unwrap_or(true),
+                        Err(_) => true,
+                    };
+                    return Err(if exists {
+                        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+                    } else {
+                        (StatusCode::NOT_FOUND, format!("no view named {}", name))
+                    });
+                }
+            }
+        }
+    };
+    let key = parse_key(&view, key).map_err(bad_request)?;
+
+    let columns = view.columns().to_vec();
+    let read = async {
+        match (limit, timeout) {
+            (Some(limit), _) => view
+                .lookup_paginated(&key, limit as usize, page, block)
+                .await
+                .map(|(rows, next)| (rows, Some(next))),
+            (None, Some(timeout)) => view
+                .lookup_with_deadline(&key, timeout)
+                .await
+                .map(|rows| (rows, None)),
+            (None, None) => view.lookup(&key, block).await.map(|rows| (rows, None)),
+        }
+    };
+    let res = tokio::time::timeout(READ_TIMEOUT, read).await;
+
+    let (rows, next) = match res {
+        Ok(Ok((rows, _))) if rows.is_pending() => {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "the key is still being backfilled".to_owned(),
+            ))
+        }
+        Ok(Ok(read)) => read,
+        Ok(Err(ViewError::StillWarming { .. })) => {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "the key is still being backfilled".to_owned(),
+            ))
+        }
+        Ok(Err(ViewError::NotYetAvailable)) => {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "the view is not yet available".to_owned(),
+            ))
+        }
+        Ok(Err(ViewError::WriteTimeout)) | Err(_) => {
+            return Err((
+                StatusCode::GATEWAY_TIMEOUT,
+                "timed out waiting for the read".to_owned(),
+            ))
+        }
+        Ok(Err(e)) => {
+            // the view may have been removed or replaced, so fetch it again next time
+            views.lock().unwrap().remove(&name);
+            return Err((StatusCode::BAD_GATEWAY, e.to_string()));
+        }
+    };
+    views.lock().unwrap().insert(name, view);
+
+    let rows: Vec<Vec<DataType>> = rows.into();
+    let rows: Vec<Value> = rows
+        .iter()
+        .map(|row| Value::Array(row.iter().take(columns.len()).map(to_json).collect()))
+        .collect();
+    let mut body = json!({ "columns": columns, "rows": rows });
+    if let Some(next) = next {
+        body["next"] = serde_json::to_value(next).unwrap();
+    }
+    Ok(body)
+}
+
+/// Parse the values of a key according to the types of the view's key columns.
+///
+/// Values may be given as JSON strings, numbers or booleans. If the view does not report its
+/// schema, values that look like integers are taken to be integers, and all others strings.
+fn parse_key(view: &View, key: Vec<Value>) -> Result<Vec<DataType>, String> {
+    let key_columns = view.key_columns();
+    if !key_columns.is_empty() && key.len() != key_columns.len() {
+        return Err(format!(
+            "the view is keyed by {} columns, but {} values were given",
+            key_columns.len(),
+            key.len()
+        ));
+    }
+
+    key.into_iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let s = match v {
+                Value::String(s) => s,
+                Value::Number(n) => n.to_string(),
+                Value::Bool(b) => b.to_string(),
+                Value::Null => return Ok(DataType::None),
+                _ => return Err(format!("{} is not a valid key value", v)),
+            };
+            let ty = key_columns
+                .get(i)
+                .and_then(|&c| view.schema().and_then(|s| s.get(c)))
+                .map(|c| &c.sql_type);
+            parse_value(&s, ty)
+        })
+        .collect()
+}
+
+/// Parse a value given as text into a value of type `ty`.
+fn parse_value(s: &str, ty: Option<&SqlType>) -> Result<DataType, String> {
+    let invalid = |what: &str| format!("{:?} is not a valid {}", s, what);
+    match ty {
+        Some(SqlType::Bool) => match s {
+            "true" | "TRUE" | "1" => Ok(DataType::from(true)),
+            "false" | "FALSE" | "0" => Ok(DataType::from(false)),
+            _ => Err(invalid("boolean")),
+        },
+        Some(SqlType::Int(_)) | Some(SqlType::Bigint(_)) | Some(SqlType::Tinyint(_)) => s
+            .parse::<i64>()
+            .map(DataType::from)
+            .map_err(|_| invalid("integer")),
+        Some(SqlType::UnsignedInt(_))
+        | Some(SqlType::UnsignedBigint(_))
+        | Some(SqlType::UnsignedTinyint(_)) => s
+            .parse::<u64>()
+            .map(DataType::from)
+            .map_err(|_| invalid("unsigned integer")),
+        Some(SqlType::Double) | Some(SqlType::Float) | Some(SqlType::Real) => s
+            .parse::<f64>()
+            .map(DataType::from)
+            .map_err(|_| invalid("number")),
+        Some(SqlType::Decimal(_, scale)) => DataType::parse_decimal(s)
+            .and_then(|d| d.to_decimal(*scale))
+            .map_err(|_| invalid("decimal")),
+        Some(SqlType::DateTime(_)) | Some(SqlType::Timestamp) => {
+            DataType::parse_datetime(s).map_err(|_| invalid("datetime"))
+        }
+        Some(SqlType::Date) => DataType::parse_date(s).map_err(|_| invalid("date")),
+        Some(_) => Ok(DataType::from(s)),
+        None => Ok(s
+            .parse::<i64>()
+            .map(DataType::from)
+            .unwrap_or_else(|_| DataType::from(s))),
+    }
+}
+
+/// A value as JSON. Values that JSON has no type for are given as strings.
+fn to_json(d: &DataType) -> Value {
+    match *d {
+        DataType::None => Value::Null,
+        DataType::Bool(b) => Value::Bool(b),
+        DataType::Int(n) => n.into(),
+        DataType::UnsignedInt(n) => n.into(),
+        DataType::BigInt(n) => n.into(),
+        DataType::UnsignedBigInt(n) => n.into(),
+        DataType::Real(..) | DataType::Decimal(..) => f64::from(d).into(),
+        DataType::Text(..) | DataType::TinyText(..) | DataType::CiText(..) => {
+            let s: &str = d.into();
+            s.into()
+        }
+        DataType::Json(..) => {
+            let s: &str = d.into();
+            serde_json::from_str(s).unwrap_or_else(|_| s.into())
+        }
+        DataType::Timestamp(ts) => ts.format("%Y-%m-%d %H:%M:%S%.f").to_string().into(),
+        DataType::Date(date) => date.format("%Y-%m-%d").to_string().into(),
+        _ => d.to_string().into(),
+    }
+}
+
+/// Undo the percent-encoding of a part of a URI.
+fn decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
@@ -0,0 +1,612 @@
+// without the feature, sources can be configured but there is nothing to run them
+#![cfg_attr(not(feature = "kafka"), allow(dead_code))]
+
+use noria::{DataType, TableOperation};
+use serde_json::Value;
+use siphasher::sip::SipHasher13;
+use std::fmt;
+use std::hash::Hasher;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "kafka")]
+use {
+    futures_util::stream::StreamExt,
+    noria::consensus::Authority,
+    noria::error::TableError,
+    noria::{ControllerHandle, Table},
+    rdkafka::config::ClientConfig,
+    rdkafka::consumer::{CommitMode, Consumer, StreamConsumer},
+    rdkafka::{Message, Offset, TopicPartitionList},
+    stream_cancel::Valve,
+};
+
+/// Turns the payload of a Kafka message into the operations to perform on a base table.
+///
+/// A payload that cannot be decoded is a poison message, and is handled according to the
+/// source's [`PoisonPolicy`]. Decoding must be deterministic, since a batch that was written but
+/// not committed before a restart is decoded again, and must produce the same operations for its
+/// write to be recognized as a retry.
+pub trait Decoder: Send + Sync {
+    /// Decode one message's payload.
+    fn decode(&self, payload: &[u8]) -> Result<Vec<TableOperation>, String>;
+}
+
+/// Decodes each message as a JSON object that is inserted as one row.
+#[derive(Clone, Debug)]
+pub struct JsonDecoder {
+    columns: Vec<String>,
+    required: bool,
+}
+
+impl JsonDecoder {
+    /// Insert a row whose columns are taken from the given fields of each message, in the order
+    /// of the table's columns.
+    ///
+    /// Fields are JSON pointers, such as `/user/id`, so they can reach into nested objects. A
+    /// plain name like `id` is taken to be a top-level field.
+    pub fn new<I, S>(columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        JsonDecoder {
+            columns: columns
+                .into_iter()
+                .map(Into::into)
+                .map(|c| {
+                    if c.starts_with('/') {
+                        c
+                    } else {
+                        format!("/{}", c)
+                    }
+                })
+                .collect(),
+            required: false,
+        }
+    }
+
+    /// Treat messages that lack one of the fields as poison, rather than giving the column a
+    /// `NULL` value.
+    pub fn require_all(mut self) -> Self {
+        self.required = true;
+        self
+    }
+}
+
+impl Decoder for JsonDecoder {
+    fn decode(&self, payload: &[u8]) -> Result<Vec<TableOperation>, String> {
+        let message: Value =
+            serde_json::from_slice(payload).map_err(|e| format!("invalid JSON: {}", e))?;
+        if !message.is_object() {
+            return Err(format!("expected a JSON object, got {}", message));
+        }
+
+        let mut row = Vec::with_capacity(self.columns.len());
+        for column in &self.columns {
+            row.push(match message.pointer(column) {
+                Some(value) => from_json(value),
+                None if self.required => return Err(format!("missing field {}", column)),
+                None => DataType::None,
+            });
+        }
+        Ok(vec![TableOperation::Insert(row)])
+    }
+}
+
+/// A JSON value as the value of a column. Objects and arrays are kept as JSON documents.
+fn from_json(value: &Value) -> DataType {
+    match *value {
+        Value::Null => DataType::None,
+        Value::Bool(b) => DataType::from(b),
+        Value::Number(ref n) => {
+            if let Some(n) = n.as_i64() {
+                DataType::from(n)
+            } else if let Some(n) = n.as_u64() {
+                DataType::from(n)
+            } else {
+                // JSON numbers are always finite
+                DataType::from(n.as_f64().unwrap())
+            }
+        }
+        Value::String(ref s) => DataType::from(&**s),
+        Value::Array(..) | Value::Object(..) => DataType::parse_json(&value.to_string()).unwrap(),
+    }
+}
+
+/// What a Kafka source does with a message that cannot be decoded, or whose operations the table
+/// rejects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PoisonPolicy {
+    /// Log the message, count it in [`SourceStats::skipped`], and carry on past it.
+    Skip,
+    /// Log the message, and stop consuming the partition it is in without committing it, so that
+    /// it is consumed again once the source is restarted.
+    Halt,
+}
+
+#[derive(Default)]
+struct Counters {
+    consumed: AtomicU64,
+    written: AtomicU64,
+    skipped: AtomicU64,
+    halted: AtomicBool,
+}
+
+/// How far a Kafka source has come. Shared by every clone.
+#[derive(Clone, Default)]
+pub struct SourceStats(Arc<Counters>);
+
+impl SourceStats {
+    /// Messages read from Kafka, including ones that were later consumed again after a restart.
+    pub fn consumed(&self) -> u64 {
+        self.0.consumed.load(Ordering::Relaxed)
+    }
+
+    /// Operations the table acknowledged.
+    pub fn written(&self) -> u64 {
+        self.0.written.load(Ordering::Relaxed)
+    }
+
+    /// Poison messages that were skipped.
+    pub fn skipped(&self) -> u64 {
+        self.0.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Whether a partition stopped at a poison message.
+    pub fn halted(&self) -> bool {
+        self.0.halted.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for SourceStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SourceStats")
+            .field("consumed", &self.consumed())
+            .field("written", &self.written())
+            .field("skipped", &self.skipped())
+            .field("halted", &self.halted())
+            .finish()
+    }
+}
+
+/// A set of partitions of a Kafka topic whose messages are written to a base table.
+///
+/// Each partition is consumed by a task of its own that writes the partition's messages in
+/// offset order, in batches. Each batch is written with
+/// [`Table::perform_all_once`](noria::Table::perform_all_once), and its offsets are committed to
+/// the consumer group only once the table has acknowledged the write, and so persisted it. A
+/// source that restarts resumes from the committed offsets, and a batch that was written but not
+/// committed is recognized by the table and not applied twice, as long as the restart is within
+/// the table's deduplication window.
+#[derive(Clone)]
+pub struct KafkaSource {
+    brokers: String,
+    group: String,
+    topic: String,
+    partitions: Vec<i32>,
+    table: String,
+    decoder: Arc<dyn Decoder>,
+    poison: PoisonPolicy,
+    batch_size: usize,
+    batch_timeout: Duration,
+    stats: SourceStats,
+}
+
+impl KafkaSource {
+    /// Write the messages in `partitions` of `topic` to the base table `table`, decoding them
+    /// with `decoder`.
+    ///
+    /// `brokers` is a comma-separated list of `host:port` pairs. Offsets are committed to the
+    /// consumer group `group`. Only the given partitions are consumed, so workers that consume
+    /// disjoint sets of partitions can share a topic between them. A partition with no committed
+    /// offset is consumed from its start.
+    pub fn new<D: Decoder + 'static>(
+        brokers: &str,
+        group: &str,
+        topic: &str,
+        partitions: Vec<i32>,
+        table: &str,
+        decoder: D,
+    ) -> Self {
+        assert!(!partitions.is_empty(), "a Kafka source needs partitions");
+        KafkaSource {
+            brokers: brokers.to_owned(),
+            group: group.to_owned(),
+            topic: topic.to_owned(),
+            partitions,
+            table: table.to_owned(),
+            decoder: Arc::new(decoder),
+            poison: PoisonPolicy::Skip,
+            batch_size: 1024,
+            batch_timeout: Duration::from_millis(10),
+            stats: SourceStats::default(),
+        }
+    }
+
+    /// Set what to do with messages that cannot be decoded or are rejected. Defaults to
+    /// [`PoisonPolicy::Skip`].
+    pub fn set_poison_policy(&mut self, policy: PoisonPolicy) {
+        self.poison = policy;
+    }
+
+    /// Write each partition's messages in batches of at most `size`, waiting at most `timeout`
+    /// after the first message of a batch for the rest to arrive.
+    pub fn set_batching(&mut self, size: usize, timeout: Duration) {
+        assert_ne!(size, 0);
+        self.batch_size = size;
+        self.batch_timeout = timeout;
+    }
+
+    /// The topic this source consumes.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// How far this source has come.
+    pub fn stats(&self) -> SourceStats {
+        self.stats.clone()
+    }
+}
+
+impl fmt::Debug for KafkaSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KafkaSource")
+            .field("brokers", &self.brokers)
+            .field("group", &self.group)
+            .field("topic", &self.topic)
+            .field("partitions", &self.partitions)
+            .field("table", &self.table)
+            .field("poison", &self.poison)
+            .finish()
+    }
+}
+
+/// Consume each of `source`'s partitions into its table until `valve` is closed.
+#[cfg(feature = "kafka")]
+pub(crate) async fn run<A: Authority + 'static>(
+    source: KafkaSource,
+    mut controller: ControllerHandle<A>,
+    valve: Valve,
+    log: slog::Logger,
+) {
+    let table = loop {
+        if let Err(e) = controller.ready().await {
+            warn!(log, "kafka source cannot reach the controller"; "err" => ?e);
+            return;
+        }
+        match controller.table(&source.table).await {
+            Ok(table) => break table,
+            Err(e) => {
+                // the table may not have been created yet
+                debug!(log, "kafka source waiting for its table"; "table" => &source.table, "err" => ?e);
+                tokio::time::delay_for(Duration::from_secs(1)).await;
+            }
+        }
+    };
+
+    let source = Arc::new(source);
+    for &partition in &source.partitions {
+        let source = source.clone();
+        let table = table.clone();
+        let valve = valve.clone();
+        let log = log.new(o!("topic" => source.topic.clone(), "partition" => partition));
+        tokio::spawn(async move {
+            match consume(&source, partition, table, valve, &log).await {
+                Ok(()) => debug!(log, "kafka source partition stopped"),
+                Err(e) => error!(log, "kafka source partition failed"; "err" => ?e),
+            }
+        });
+    }
+}
+
+/// A message that has been read, and what it decoded to.
+#[cfg(feature = "kafka")]
+struct Decoded {
+    offset: i64,
+    ops: Result<Vec<TableOperation>, String>,
+}
+
+/// Consume one partition into `table`.
+///
+/// Before a batch is written, the offset it starts at is committed along with the offset it ends
+/// at as metadata. Once the write is acknowledged, the offset after the batch is committed. A
+/// restart that finds an unfinished batch in the metadata consumes exactly that batch again, so
+/// that it is written with the same id and the same operations, and the table drops it if the
+/// earlier write was applied.
+#[cfg(feature = "kafka")]
+async fn consume(
+    source: &KafkaSource,
+    partition: i32,
+    mut table: Table,
+    valve: Valve,
+    log: &slog::Logger,
+) -> Result<(), failure::Error> {
+    let consumer: Arc<StreamConsumer> = Arc::new(
+        ClientConfig::new()
+            .set("bootstrap.servers", &source.brokers)
+            .set("group.id", &source.group)
+            .set("enable.auto.commit", "false")
+            .set("enable.auto.offset.store", "false")
+            .create()?,
+    );
+
+    // find where we left off
+    let (start, mut replay) = {
+        let mut tpl = TopicPartitionList::new();
+        tpl.add_partition(&source.topic, partition);
+        let c = consumer.clone();
+        let committed =
+            tokio::task::spawn_blocking(move || c.committed_offsets(tpl, Duration::from_secs(10)))
+                .await??;
+        match committed.find_partition(&source.topic, partition) {
+            Some(elem) => match elem.offset() {
+                Offset::Offset(offset) => (Offset::Offset(offset), elem.metadata().parse().ok()),
+                _ => (Offset::Beginning, None),
+            },
+            None => (Offset::Beginning, None),
+        }
+    };
+    if let Some(end) = replay {
+        info!(log, "kafka source consuming an unfinished batch again"; "start" => ?start, "end" => end);
+    }
+
+    let mut tpl = TopicPartitionList::new();
+    tpl.add_partition_offset(&source.topic, partition, start)?;
+    consumer.assign(&tpl)?;
+
+    let columns = table.columns().len();
+    let mut messages = valve.wrap(consumer.start());
+    let mut batch: Vec<Decoded> = Vec::with_capacity(source.batch_size);
+    loop {
+        // collect a batch. a batch that is consumed again must end where it ended before.
+        batch.clear();
+        let mut deadline = None;
+        while replay.is_some() || batch.len() < source.batch_size {
+            let next = match deadline {
+                Some(deadline) if replay.is_none() => {
+                    match tokio::time::timeout_at(deadline, messages.next()).await {
+                        Ok(next) => next,
+                        Err(_) => break,
+                    }
+                }
+                _ => messages.next().await,
+            };
+            let message = match next {
+                Some(message) => message?,
+                None => return Ok(()),
+            };
+            let offset = message.offset();
+            let ops = match message.payload() {
+                Some(payload) => source.decoder.decode(payload),
+                None => Err(String::from("message has no payload")),
+            }
+            .and_then(|ops| {
+                for op in &ops {
+                    if let TableOperation::Insert(ref row) = *op {
+                        if row.len() != columns {
+                            return Err(format!(
+                                "decoded {} columns, but the table has {}",
+                                row.len(),
+                                columns
+                            ));
+                        }
+                    }
+                }
+                Ok(ops)
+            });
+            source.stats.0.consumed.fetch_add(1, Ordering::Relaxed);
+            batch.push(Decoded { offset, ops });
+
+            if deadline.is_none() {
+                deadline = Some(tokio::time::Instant::now() + source.batch_timeout);
+            }
+            if replay.map_or(false, |end| offset >= end) {
+                replay = None;
+                break;
+            }
+        }
+        if batch.is_empty() {
+            continue;
+        }
+
+        // set aside the poison messages. a partition that halts still writes what came before.
+        let mut halt = None;
+        let mut ops = Vec::new();
+        let mut origin = Vec::new();
+        for (i, message) in batch.iter_mut().enumerate() {
+            match message.ops {
+                Ok(ref mut decoded) => {
+                    origin.extend(std::iter::repeat(i).take(decoded.len()));
+                    ops.append(decoded);
+                }
+                Err(ref e) => {
+                    warn!(log, "kafka source found a poison message"; "offset" => message.offset, "err" => e);
+                    if source.poison == PoisonPolicy::Halt {
+                        halt = Some(i);
+                        break;
+                    }
+                    source.stats.0.skipped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        if let Some(i) = halt {
+            batch.truncate(i);
+        }
+
+        if !batch.is_empty() {
+            let first = batch[0].offset;
+            let last = batch[batch.len() - 1].offset;
+            commit(&consumer, source, partition, first, &last.to_string()).await?;
+
+            let op = batch_op(&source.group, &source.topic, partition, first);
+            let written = ops.len();
+            let rejected = if ops.is_empty() {
+                Vec::new()
+            } else {
+                write(&mut table, op, ops, log).await?
+            };
+
+            source
+                .stats
+                .0
+                .written
+                .fetch_add((written - rejected.len()) as u64, Ordering::Relaxed);
+            let mut rejected_messages: Vec<usize> =
+                rejected.into_iter().map(|p| origin[p]).collect();
+            rejected_messages.dedup();
+            if !rejected_messages.is_empty() {
+                for &i in &rejected_messages {
+                    warn!(log, "table rejected a message"; "offset" => batch[i].offset);
+                }
+                if source.poison == PoisonPolicy::Halt {
+                    // the rest of the batch was applied, but it is left uncommitted so that it is
+                    // consumed again, and dropped as a retry, once the source restarts
+                    source.stats.0.halted.store(true, Ordering::Relaxed);
+                    error!(log, "kafka source partition halted at a rejected message");
+                    return Ok(());
+                }
+                source
+                    .stats
+                    .0
+                    .skipped
+                    .fetch_add(rejected_messages.len() as u64, Ordering::Relaxed);
+            }
+
+            commit(&consumer, source, partition, last + 1, "").await?;
+        }
+
+        if halt.is_some() {
+            source.stats.0.halted.store(true, Ordering::Relaxed);
+            error!(log, "kafka source partition halted at a poison message");
+            return Ok(());
+        }
+    }
+}
+
+/// The keys of the hash that batch ids are made with. They must never change, or batches that
+/// are consumed again after an upgrade are no longer recognized as retries.
+const BATCH_OP_KEYS: (u64, u64) = (0x6b61_666b_615f_6f70, 0x6e6f_7269_615f_6f70);
+
+/// The id that the batch of messages starting at offset `first` of `partition` is written with
+/// by the consumer group `group`.
+///
+/// The id has to be the same in every process and every release, so it is not made with
+/// `DefaultHasher`, whose algorithm may change.
+pub(crate) fn batch_op(group: &str, topic: &str, partition: i32, first: i64) -> u64 {
+    let mut hasher = SipHasher13::new_with_keys(BATCH_OP_KEYS.0, BATCH_OP_KEYS.1);
+    hasher.write(group.as_bytes());
+    hasher.write(&[0xff]);
+    hasher.write(topic.as_bytes());
+    hasher.write(&[0xff]);
+    hasher.write(&partition.to_le_bytes());
+    hasher.write(&first.to_le_bytes());
+    hasher.finish()
+}
+
+/// Commit `offset` for `partition`, along with `metadata`, and wait for Kafka to store it.
+#[cfg(feature = "kafka")]
+async fn commit(
+    consumer: &Arc<StreamConsumer>,
+    source: &KafkaSource,
+    partition: i32,
+    offset: i64,
+    metadata: &str,
+) -> Result<(), failure::Error> {
+    let mut tpl = TopicPartitionList::new();
+    tpl.add_partition_offset(&source.topic, partition, Offset::Offset(offset))?;
+    if let Some(mut elem) = tpl.find_partition(&source.topic, partition) {
+        elem.set_metadata(metadata);
+    }
+    let consumer = consumer.clone();
+    tokio::task::spawn_blocking(move || consumer.commit(&tpl, CommitMode::Sync)).await??;
+    Ok(())
+}
+
+/// Write `ops` to `table` once, retrying until the write is acknowledged. Returns the positions
+/// of operations that the table rejected.
+#[cfg(feature = "kafka")]
+async fn write(
+    table: &mut Table,
+    op: u64,
+    ops: Vec<TableOperation>,
+    log: &slog::Logger,
+) -> Result<Vec<usize>, TableError> {
+    let mut backoff = Duration::from_millis(10);
+    loop {
+        match table.perform_all_once(op, ops.clone()).await {
+            Ok(()) => return Ok(Vec::new()),
+            Err(TableError::InvalidValue(positions)) | Err(TableError::DuplicateKey(positions)) => {
+                return Ok(positions)
+            }
+            Err(e @ TableError::Overloaded(..)) | Err(e @ TableError::TransportError(..)) => {
+                // writing the same batch with the same id is safe whether or not it took effect
+                debug!(log, "kafka source retrying a write"; "err" => ?e);
+                tokio::time::delay_for(backoff).await;
+                backoff = std::cmp::min(backoff * 2, Duration::from_secs(1));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(decoder: &JsonDecoder, payload: &str) -> Result<Vec<DataType>, String> {
+        let mut ops = decoder.decode(payload.as_bytes())?;
+        assert_eq!(ops.len(), 1);
+        match ops.swap_remove(0) {
+            TableOperation::Insert(row) => Ok(row),
+            op => panic!("expected an insert, got {:?}", op),
+        }
+    }
+
+    #[test]
+    fn json_columns_follow_the_mapping() {
+        let decoder = JsonDecoder::new(vec!["id", "/user/name", "score", "tags"]);
+        let row = decode(
+            &decoder,
+            r#"{"score": 1.5, "id": 7, "user": {"name": "a"}, "tags": ["x"]}"#,
+        )
+        .unwrap();
+        assert_eq!(row[0], DataType::from(7));
+        assert_eq!(row[1], DataType::from("a"));
+        assert_eq!(row[2], DataType::from(1.5));
+        assert_eq!(row[3], DataType::parse_json(r#"["x"]"#).unwrap());
+    }
+
+    #[test]
+    fn json_missing_fields() {
+        let decoder = JsonDecoder::new(vec!["id", "name"]);
+        let row = decode(&decoder, r#"{"id": 7}"#).unwrap();
+        assert_eq!(row, vec![DataType::from(7), DataType::None]);
+
+        let decoder = decoder.require_all();
+        assert!(decode(&decoder, r#"{"id": 7}"#).is_err());
+    }
+
+    #[test]
+    fn batch_ops_do_not_change() {
+        // a batch consumed again by a newer release must get the id it was written with
+        assert_eq!(batch_op("group", "topic", 3, 42), 3_175_640_886_645_614_069);
+        assert_ne!(
+            batch_op("group", "topic", 3, 42),
+            batch_op("group", "topic", 3, 43)
+        );
+        assert_ne!(
+            batch_op("group", "topic", 3, 42),
+            batch_op("grouptopic", "", 3, 42)
+        );
+    }
+
+    #[test]
+    fn json_poison() {
+        let decoder = JsonDecoder::new(vec!["id"]);
+        assert!(decode(&decoder, "{").is_err());
+        assert!(decode(&decoder, "[7]").is_err());
+    }
+}
@@ -0,0 +1,275 @@
+use dataflow::{DomainMetrics, LabelMetric};
+use futures_util::stream::Stream;
+use hyper::{header::CONTENT_TYPE, Body, Method, Request, Response, StatusCode};
+use noria::debug::stats::LatencyHistogram;
+use noria::internal::DomainIndex;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// The latest metrics of each of a worker's domain shards.
+///
+/// Each domain's replica replaces its entry every time it refreshes its state sizes, so reading
+/// the metrics never waits for a domain.
+pub(crate) type Registry = Arc<Mutex<HashMap<(DomainIndex, usize), Arc<Mutex<DomainMetrics>>>>>;
+
+/// Serve the metrics in `registry` in the Prometheus text format at `/metrics`.
+pub(crate) async fn serve<S>(incoming: S, registry: Registry) -> Result<(), hyper::Error>
+where
+    S: Stream<Item = io::Result<tokio::net::TcpStream>> + Send,
+{
+    use hyper::service::{make_service_fn, service_fn};
+
+    hyper::server::Server::builder(hyper::server::accept::from_stream(incoming))
+        .serve(make_service_fn(move |_| {
+            let registry = registry.clone();
+            async move {
+                io::Result::Ok(service_fn(move |req: Request<Body>| {
+                    let res = if req.method() == Method::GET && req.uri().path() == "/metrics" {
+                        Response::builder()
+                            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                            .body(Body::from(render(&registry)))
+                    } else {
+                        Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::empty())
+                    };
+                    async move { Ok::<_, hyper::Error>(res.unwrap()) }
+                }))
+            }
+        }))
+        .await
+}
+
+/// The metrics of every domain shard in `registry`, in the Prometheus text format.
+///
+/// The label lag of a reader is only known for base shards on this worker. For the others, it is
+/// the difference between the base's `noria_base_label` and the reader's `noria_reader_label`.
+pub(crate) fn render(registry: &Registry) -> String {
+    let mut domains: Vec<_> = {
+        let registry = registry.lock().unwrap();
+        registry
+            .iter()
+            .map(|(&(domain, shard), m)| ((domain.index(), shard), m.lock().unwrap().clone()))
+            .collect()
+    };
+    domains.sort_by_key(|&(id, _)| id);
+
+    let mut bases = HashMap::new();
+    for (_, m) in &domains {
+        for l in &m.base_labels {
+            bases.insert((l.base, l.base_shard), (l.node.clone(), l.seq));
+        }
+    }
+    let base_name = |l: &LabelMetric| {
+        bases
+            .get(&(l.base, l.base_shard))
+            .map(|(name, _)| name.clone())
+            .unwrap_or_else(|| l.base.index().to_string())
+    };
+
+    let mut out = String::new();
+    macro_rules! family {
+        ($name:expr, $kind:expr, $help:expr, |$m:ident| $value:expr) => {{
+            writeln!(out, "# HELP {} {}", $name, $help).unwrap();
+            writeln!(out, "# TYPE {} {}", $name, $kind).unwrap();
+            for &((domain, shard), ref $m) in &domains {
+                writeln!(
+                    out,
+                    "{}{{domain=\"{}\",shard=\"{}\"}} {}",
+                    $name, domain, shard, $value
+                )
+                .unwrap();
+            }
+        }};
+    }
+
+    family!(
+        "noria_domain_packets_total",
+        "counter",
+        "Packets handled by the domain.",
+        |m| m.packets
+    );
+    family!(
+        "noria_domain_output_queue",
+        "gauge",
+        "Packets waiting to be sent to other domains.",
+        |m| m.output_queue
+    );
+    family!(
+        "noria_domain_group_commits_total",
+        "counter",
+        "Groups of client writes committed to the domain's bases together.",
+        |m| m.group_commits
+    );
+    family!(
+        "noria_domain_reader_replays_total",
+        "counter",
+        "Keys the domain's partial readers have issued replays for.",
+        |m| m.reader_replays
+    );
+    family!(
+        "noria_domain_replay_seconds_total",
+        "counter",
+        "Time spent handling replays.",
+        |m| m.replay_time.as_secs_f64()
+    );
+    family!(
+        "noria_domain_queued_replays_total",
+        "counter",
+        "Partial replay requests that waited for a free slot.",
+        |m| m.queued_replays
+    );
+    family!(
+        "noria_domain_replay_queue_seconds_total",
+        "counter",
+        "Time partial replay requests spent waiting for a free slot.",
+        |m| m.replay_queue_time.as_secs_f64()
+    );
+    family!(
+        "noria_domain_replay_queue",
+        "gauge",
+        "Partial replay requests waiting for a free slot.",
+        |m| m.replay_queue_len
+    );
+
+    writeln!(
+        out,
+        "# HELP noria_node_state_bytes Size of the node's materialized state."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE noria_node_state_bytes gauge").unwrap();
+    for &((domain, shard), ref m) in &domains {
+        for &(ref node, size) in &m.state_sizes {
+            writeln!(
+                out,
+                "noria_node_state_bytes{{domain=\"{}\",shard=\"{}\",node=\"{}\"}} {}",
+                domain,
+                shard,
+                escape(node),
+                size
+            )
+            .unwrap();
+        }
+    }
+
+    writeln!(
+        out,
+        "# HELP noria_base_label Sequence number of the latest batch of writes the base processed."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE noria_base_label gauge").unwrap();
+    for &((domain, shard), ref m) in &domains {
+        for l in &m.base_labels {
+            writeln!(
+                out,
+                "noria_base_label{{domain=\"{}\",shard=\"{}\",node=\"{}\"}} {}",
+                domain,
+                shard,
+                escape(&l.node),
+                l.seq
+            )
+            .unwrap();
+        }
+    }
+
+    for (name, help, lag) in &[
+        (
+            "noria_reader_label",
+            "Sequence number of the latest batch from the base shard that the reader reflects.",
+            false,
+        ),
+        (
+            "noria_reader_label_lag",
+            "Batches the base shard has processed that the reader does not reflect yet.",
+            true,
+        ),
+    ] {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} gauge", name).unwrap();
+        for &((domain, shard), ref m) in &domains {
+            for l in &m.reader_labels {
+                let value = if *lag {
+                    match bases.get(&(l.base, l.base_shard)) {
+                        Some(&(_, seq)) => seq.saturating_sub(l.seq),
+                        None => continue,
+                    }
+                } else {
+                    l.seq
+                };
+                writeln!(
+                    out,
+                    "{}{{domain=\"{}\",shard=\"{}\",node=\"{}\",base=\"{}\",base_shard=\"{}\"}} {}",
+                    name,
+                    domain,
+                    shard,
+                    escape(&l.node),
+                    escape(&base_name(l)),
+                    l.base_shard,
+                    value
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    writeln!(
+        out,
+        "# HELP noria_view_propagation_seconds Time sampled writes took from arriving at their base \
+         to being applied at the reader."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE noria_view_propagation_seconds histogram").unwrap();
+    for &((domain, shard), ref m) in &domains {
+        for &(ref node, ref h) in &m.reader_propagation {
+            let labels = format!(
+                "domain=\"{}\",shard=\"{}\",node=\"{}\"",
+                domain,
+                shard,
+                escape(node)
+            );
+            // the last bucket also holds everything longer, so it is only counted by +Inf
+            let mut cumulative = 0;
+            let bounded = h.buckets.len().saturating_sub(1);
+            for (i, &n) in h.buckets.iter().enumerate().take(bounded) {
+                cumulative += n;
+                let le = LatencyHistogram::bound(i) as f64 / 1e9;
+                writeln!(
+                    out,
+                    "noria_view_propagation_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, le, cumulative
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "noria_view_propagation_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, h.count
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "noria_view_propagation_seconds_sum{{{}}} {}",
+                labels,
+                h.sum as f64 / 1e9
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "noria_view_propagation_seconds_count{{{}}} {}",
+                labels, h.count
+            )
+            .unwrap();
+        }
+    }
+
+    out
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
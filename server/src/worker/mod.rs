@@ -2,18 +2,20 @@ use crate::controller::ControllerState;
 use crate::coordination::{CoordinationMessage, CoordinationPayload, DomainDescriptor};
 use crate::startup::Event;
 use async_bincode::AsyncBincodeWriter;
-use dataflow::{DomainBuilder, Packet};
+use dataflow::{DomainBuilder, DomainMetrics, LogFile, Packet, Telemetry};
 use futures_util::{future::FutureExt, future::TryFutureExt, sink::SinkExt, stream::StreamExt};
-use noria::channel;
+use noria::auth::Principals;
+use noria::channel::{self, tls, Tls};
 use noria::consensus::Epoch;
 use noria::internal::DomainIndex;
 use noria::ControllerDescriptor;
-use replica::ReplicaAddr;
+use replica::{Draining, ReplicaAddr};
 use slog;
 use std::collections::HashMap;
 use std::fs;
 use std::io;
 use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
 use std::sync::{
     atomic::{AtomicUsize, Ordering},
     Arc, Mutex,
@@ -23,16 +25,33 @@ use stream_cancel::{Trigger, Valve};
 use tokio;
 use tokio::sync::mpsc::UnboundedSender;
 
+mod affinity;
+mod faults;
+pub(crate) mod grpc;
+pub(crate) mod health;
+pub(crate) mod http;
+pub(crate) mod kafka;
+pub(crate) mod metrics;
 mod readers;
 mod replica;
 
+pub use affinity::CpuAffinity;
+pub use faults::{Faults, Injected};
+pub use kafka::KafkaSource;
+
 type ChannelCoordinator = channel::ChannelCoordinator<ReplicaAddr, Box<Packet>>;
 
+type CoordinationSink =
+    Box<dyn futures_util::sink::Sink<CoordinationMessage, Error = failure::Error> + Send + Unpin>;
+
 enum InstanceState {
     Pining,
     Active {
         epoch: Epoch,
         trigger: Trigger,
+        // tells the domains to finish what they have been given, but take in nothing new
+        drain: Option<Trigger>,
+        draining: Draining,
         add_domain: UnboundedSender<DomainBuilder>,
     },
 }
@@ -49,10 +68,21 @@ pub(super) async fn main(
     waddr: SocketAddr,
     memory_limit: Option<usize>,
     memory_check_frequency: Option<time::Duration>,
+    domain_memory_budget: Option<usize>,
+    cpu_affinity: Option<CpuAffinity>,
+    faults: Option<Faults>,
+    liveness: Arc<health::Liveness>,
+    telemetry: Telemetry,
+    metrics: Option<metrics::Registry>,
+    tls: Option<Tls>,
+    principals: Option<Arc<Principals>>,
+    drain_timeout: Duration,
+    labels: Vec<String>,
+    local_transport: bool,
     log: slog::Logger,
 ) {
     // shared df state
-    let coord = Arc::new(ChannelCoordinator::new());
+    let coord = Arc::new(ChannelCoordinator::with_tls(tls));
 
     let mut worker_state = InstanceState::Pining;
     let log = log.clone();
@@ -89,14 +119,30 @@ pub(super) async fn main(
                                 "found that domain {}.{} is at {:?}",
                                 domain.index(),
                                 shard,
-                                addr
+                                addr;
+                                "tls" => dd.tls()
                             );
-                            coord.insert_remote((domain, shard), addr);
+                            coord.insert_remote((domain, shard), addr, dd.tls());
                         }
                     }
                 }
                 _ => unreachable!(),
             },
+            Event::Drain(done) => {
+                if let InstanceState::Active {
+                    ref mut drain,
+                    ref draining,
+                    ..
+                } = worker_state
+                {
+                    if let Some(drain) = drain.take() {
+                        info!(log, "draining domains"; "timeout" => ?drain_timeout);
+                        drain.cancel();
+                        wait_for_drain(&log, draining, drain_timeout).await;
+                    }
+                }
+                let _ = done.send(());
+            }
             Event::LeaderChange(state, descriptor) => {
                 if let InstanceState::Active {
                     add_domain,
@@ -129,18 +175,31 @@ pub(super) async fn main(
                 // we need to make a new valve that we can use to shut down *just* the
                 // worker in the case of controller failover.
                 let (trigger, valve) = Valve::new();
+                let (drain, drain_valve) = Valve::new();
+                let draining = Draining::default();
 
                 // TODO: memory stuff should probably also be in config?
                 let (rep_tx, rep_rx) = tokio::sync::mpsc::unbounded_channel();
                 let ctrl = listen_df(
                     alive.clone(),
                     valve,
+                    drain_valve,
+                    draining.clone(),
                     log.clone(),
                     (memory_limit, memory_check_frequency),
+                    domain_memory_budget,
+                    cpu_affinity.clone(),
+                    faults.clone(),
+                    liveness.clone(),
+                    telemetry.clone(),
+                    metrics.clone(),
                     &state,
                     &descriptor,
                     waddr,
                     coord.clone(),
+                    principals.clone(),
+                    labels.clone(),
+                    local_transport,
                     listen_addr,
                     rep_rx,
                 )
@@ -155,6 +214,8 @@ pub(super) async fn main(
                         epoch: state.epoch,
                         add_domain: rep_tx,
                         trigger,
+                        drain: Some(drain),
+                        draining,
                     };
                     warn!(log, "Connected to new leader");
                 }
@@ -171,35 +232,105 @@ pub(super) async fn main(
     // TODO: maybe flush things or something?
 }
 
+/// Wait for every domain to have drained, or for `timeout` to pass, whichever comes first.
+async fn wait_for_drain(log: &slog::Logger, draining: &Draining, timeout: Duration) {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let left: Vec<_> = draining
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, s)| !s.quiescent)
+            .map(|(&addr, &s)| (addr, s))
+            .collect();
+        if left.is_empty() {
+            info!(log, "all domains drained");
+            return;
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            for ((domain, shard), s) in left {
+                warn!(log, "domain did not drain in time; dropping its remaining work";
+                      "domain" => domain.index(),
+                      "shard" => shard,
+                      "queued_packets" => s.queued,
+                      "queued_records" => s.records,
+                      "unacked_writes" => s.unacked);
+            }
+            return;
+        }
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+    }
+}
+
 async fn listen_df<'a>(
     alive: tokio::sync::mpsc::Sender<()>,
     valve: Valve,
+    drain: Valve,
+    draining: Draining,
     log: slog::Logger,
     (memory_limit, evict_every): (Option<usize>, Option<Duration>),
+    domain_memory_budget: Option<usize>,
+    cpu_affinity: Option<CpuAffinity>,
+    faults: Option<Faults>,
+    liveness: Arc<health::Liveness>,
+    telemetry: Telemetry,
+    metrics: Option<metrics::Registry>,
     state: &'a ControllerState,
     desc: &'a ControllerDescriptor,
     waddr: SocketAddr,
     coord: Arc<ChannelCoordinator>,
+    principals: Option<Arc<Principals>>,
+    labels: Vec<String>,
+    local_transport: bool,
     on: IpAddr,
     mut replicas: tokio::sync::mpsc::UnboundedReceiver<DomainBuilder>,
 ) -> Result<(), failure::Error> {
-    // first, try to connect to controller
-    let ctrl = tokio::net::TcpStream::connect(&desc.worker_addr).await?;
-    let ctrl_addr = ctrl.local_addr()?;
-    info!(log, "connected to controller"; "src" => ?ctrl_addr);
-
-    let log_prefix = state.config.persistence.log_prefix.clone();
-    let prefix = format!("{}-log-", log_prefix);
-    let log_files: Vec<String> = fs::read_dir(".")
-        .unwrap()
+    // the endpoints of this worker expect TLS if it has a certificate
+    let expects_tls = coord.tls().map_or(false, Tls::accepts);
+
+    // first, try to connect to controller, in memory if it is in this process. the controller
+    // then knows the worker by the address of its instance rather than that of the connection.
+    let (ctrl_addr, mut ctrl): (SocketAddr, CoordinationSink) =
+        if let Some(tx) = channel::local::connect(&desc.worker_addr) {
+            info!(log, "connected to controller in memory");
+            let ctrl =
+                crate::ImplSinkForSender(tx).sink_map_err(|_| format_err!("controller went away"));
+            (waddr, Box::new(ctrl) as CoordinationSink)
+        } else {
+            let ctrl = tokio::net::TcpStream::connect(&desc.worker_addr).await?;
+            let ctrl_addr = ctrl.local_addr()?;
+            let ctrl_tls = tls::for_endpoint(coord.tls(), desc.worker_addr, desc.tls)?;
+            let ctrl = tls::maybe_connect(ctrl_tls, ctrl, desc.worker_addr).await?;
+            info!(log, "connected to controller"; "src" => ?ctrl_addr, "tls" => ctrl.is_tls());
+            let ctrl = AsyncBincodeWriter::from(ctrl)
+                .for_async()
+                .sink_map_err(failure::Error::from);
+            (ctrl_addr, Box::new(ctrl) as CoordinationSink)
+        };
+
+    // the durable state of base tables, under either the current or the legacy naming
+    let (log_dir, deployment) = state.config.persistence.log_location();
+    let log_dir = if log_dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        log_dir
+    };
+    let log_files: Vec<String> = fs::read_dir(log_dir)
+        .into_iter()
+        .flatten()
         .filter_map(Result::ok)
-        .filter(|e| e.file_type().ok().map(|t| t.is_file()).unwrap_or(false))
-        .map(|e| e.path().to_string_lossy().into_owned())
-        .filter(|path| path.starts_with(&prefix))
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|name| LogFile::parse(name, &deployment).is_some())
         .collect();
+    if !log_files.is_empty() {
+        info!(log, "found durable base state"; "files" => ?log_files);
+    }
 
     // extract important things from state config
     let epoch = state.epoch;
+    // domains only take packets from the dataflow of this controller
+    coord.set_epoch(epoch);
     let heartbeat_every = state.config.heartbeat_every;
 
     let (ctrl_tx, mut ctrl_rx) = tokio::sync::mpsc::unbounded_channel();
@@ -211,7 +342,6 @@ async fn listen_df<'a>(
     info!(log, "listening for reads"; "on" => ?raddr);
 
     // start controller message handler
-    let mut ctrl = AsyncBincodeWriter::from(ctrl).for_async();
     let a = alive.clone();
     tokio::spawn(async move {
         let _alive = a;
@@ -237,6 +367,9 @@ async fn listen_df<'a>(
         valve.clone(),
         rport,
         readers.clone(),
+        coord.tls().cloned(),
+        principals.clone(),
+        telemetry.clone(),
     ));
 
     // and tell the controller about us
@@ -252,6 +385,8 @@ async fn listen_df<'a>(
             addr: waddr,
             read_listen_addr: raddr,
             log_files,
+            tls: expects_tls,
+            labels,
         });
 
         // start sending heartbeats
@@ -263,6 +398,11 @@ async fn listen_df<'a>(
         }
     });
 
+    // the domains of the previous leader, if any, are gone
+    if let Some(ref metrics) = metrics {
+        metrics.lock().unwrap().clear();
+    }
+
     let state_sizes = Arc::new(Mutex::new(HashMap::new()));
     if let Some(evict_every) = evict_every {
         let log = log.clone();
@@ -289,8 +429,20 @@ async fn listen_df<'a>(
         });
     }
 
+    let mut placement = cpu_affinity.and_then(|policy| {
+        let placement = affinity::Placement::new(policy);
+        if placement.is_none() {
+            warn!(
+                log,
+                "cannot pin domain threads to cores on this platform; running them unpinned"
+            );
+        }
+        placement
+    });
+
     // Now we're ready to accept new domains.
     let dcaddr = desc.domain_addr;
+    let dctls = desc.tls;
     tokio::spawn(
         async move {
             let alive = alive;
@@ -304,44 +456,113 @@ async fn listen_df<'a>(
                 let state_size = Arc::new(AtomicUsize::new(0));
                 let d = tokio::task::block_in_place(|| {
                     d.build(
-                        log.clone(),
+                        log.new(o!("worker" => waddr.to_string())),
                         readers.clone(),
                         coord.clone(),
                         dcaddr,
+                        dctls,
                         &valve,
                         state_size.clone(),
+                        telemetry.clone(),
                     )
                 });
 
-                let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+                // updates from local domains wait in a bounded channel, so that a domain that
+                // falls behind makes the domains that feed it stop taking in work too.
+                let (tx, rx) = tokio::sync::mpsc::channel(d.output_high_water().max(1));
+                let (priority_tx, priority_rx) = tokio::sync::mpsc::unbounded_channel();
 
                 // need to register the domain with the local channel coordinator.
                 // local first to ensure that we don't unnecessarily give away remote for a
                 // local thing if there's a race
-                coord.insert_local((idx, shard), tx);
-                coord.insert_remote((idx, shard), addr);
+                liveness.insert((idx, shard), priority_tx.clone());
+                coord.insert_local(
+                    (idx, shard),
+                    channel::LocalChannels {
+                        updates: tx,
+                        priority: priority_tx,
+                    },
+                );
+                coord.insert_remote((idx, shard), addr, expects_tls);
+                // and the controller and clients in this process find those channels by its address
+                let registration = if local_transport {
+                    Some(channel::local::register(
+                        addr,
+                        coord.local_channels(&(idx, shard)).unwrap(),
+                    ))
+                } else {
+                    None
+                };
 
                 tokio::task::block_in_place(|| {
                     state_sizes.lock().unwrap().insert((idx, shard), state_size)
                 });
+                draining
+                    .lock()
+                    .unwrap()
+                    .insert((idx, shard), Default::default());
+                let domain_metrics = metrics.as_ref().map(|metrics| {
+                    let m = Arc::new(Mutex::new(DomainMetrics::default()));
+                    tokio::task::block_in_place(|| {
+                        metrics.lock().unwrap().insert((idx, shard), m.clone())
+                    });
+                    m
+                });
 
                 let replica = replica::Replica::new(
                     &valve,
+                    &drain,
+                    draining.clone(),
                     d,
                     on,
                     rx,
+                    priority_rx,
+                    domain_memory_budget,
+                    domain_metrics,
                     ctrl_tx.clone(),
                     log.clone(),
                     coord.clone(),
+                    principals.clone(),
+                    epoch,
+                    faults.as_ref().map(|f| f.for_sender((idx, shard))),
+                    liveness.clone(),
                 );
                 let a = alive.clone();
-                tokio::spawn(async move {
+                let draining = draining.clone();
+                let liveness = liveness.clone();
+                let run = async move {
                     let _alive = a;
+                    let _registration = registration;
                     let log = replica.log.clone();
                     if let Err(e) = replica.await {
                         crit!(log, "replica failure: {:?}", e);
                     }
-                });
+                    // a domain that is gone has nothing left to drain, nor is it checked on
+                    draining.lock().unwrap().remove(&(idx, shard));
+                    liveness.remove((idx, shard));
+                };
+                if let Some(ref mut placement) = placement {
+                    let cores = placement.cores_for(idx, shard);
+                    let ctx = ctrl_tx.clone();
+                    affinity::spawn_pinned(
+                        format!("domain-{}.{}", idx.index(), shard),
+                        cores,
+                        log.clone(),
+                        async move {
+                            // report where the thread actually ended up
+                            if let Ok(cores) = affinity::allowed_cores() {
+                                let _ = ctx.send(CoordinationPayload::DomainPinned {
+                                    domain: idx,
+                                    shard,
+                                    cores,
+                                });
+                            }
+                            run.await
+                        },
+                    )?;
+                } else {
+                    tokio::spawn(run);
+                }
 
                 info!(
                     log,
@@ -353,7 +574,10 @@ async fn listen_df<'a>(
 
                 ctrl_tx
                     .send(CoordinationPayload::DomainBooted(DomainDescriptor::new(
-                        idx, shard, addr,
+                        idx,
+                        shard,
+                        addr,
+                        expects_tls,
                     )))
                     .map_err(|_| {
                         // controller went away -- exit?
@@ -456,7 +680,12 @@ async fn do_eviction(
 
                     let tx = domain_senders.entry(target).or_insert_with(|| {
                         tokio::task::block_in_place(|| {
-                            coord.builder_for(&target).unwrap().build_async().unwrap()
+                            coord
+                                .builder_for(&target)
+                                .unwrap()
+                                .prioritized()
+                                .build_async()
+                                .unwrap()
                         })
                     });
                     let r = tx
@@ -2,18 +2,23 @@ use async_bincode::AsyncBincodeStream;
 use dataflow::prelude::DataType;
 use dataflow::prelude::*;
 use dataflow::Readers;
-use dataflow::SingleReadHandle;
+use dataflow::Telemetry;
+use dataflow::{KeyRows, SingleReadHandle};
 use futures_util::{
     future,
     future::Either,
     future::{FutureExt, TryFutureExt},
     stream::{StreamExt, TryStreamExt},
 };
-use noria::{ReadQuery, ReadReply, Tagged};
+use noria::auth::{self, Principals, Scope};
+use noria::channel::tls::{self, Tls};
+use noria::{ChangeCursor, ReadQuery, ReadReply, Tagged};
+use opentelemetry::KeyValue;
 use pin_project::pin_project;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::mem;
+use std::sync::Arc;
 use std::time;
 use std::{future::Future, task::Poll};
 use stream_cancel::Valve;
@@ -60,6 +65,9 @@ pub(super) async fn listen(
     valve: Valve,
     mut on: tokio::net::TcpListener,
     readers: Readers,
+    tls: Option<Tls>,
+    principals: Option<Arc<Principals>>,
+    telemetry: Telemetry,
 ) {
     let mut stream = valve.wrap(on.incoming()).into_stream();
     while let Some(stream) = stream.next().await {
@@ -115,13 +123,44 @@ pub(super) async fn listen(
         });
         tokio::spawn(retries);
 
-        let server = READERS.scope(
-            Default::default(),
+        let tls = tls.clone();
+        let principals = principals.clone();
+        let telemetry = telemetry.clone();
+        let server = READERS.scope(Default::default(), async move {
+            let mut stream = match tls::maybe_accept(tls.as_ref(), stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("!!! reader client failed the TLS handshake: {:?}", e);
+                    return Ok(());
+                }
+            };
+            // a client whose token was checked may only read from the view it said it is for
+            let only = match auth::check(&mut stream, principals.as_deref()).await {
+                Ok(None) => None,
+                Ok(Some(Scope::Table { .. })) => {
+                    eprintln!("!!! rejected reader client that is for a table");
+                    return Ok(());
+                }
+                Ok(Some(scope)) => Some(scope.node_name()),
+                Err(e) => {
+                    eprintln!("!!! rejected reader client: {:?}", e);
+                    return Ok(());
+                }
+            };
             server::Server::new(
                 AsyncBincodeStream::from(stream).for_async(),
-                service_fn(move |req| handle_message(req, &readers, &mut tx)),
-            ),
-        );
+                service_fn(move |req: Tagged<ReadQuery>| {
+                    if let Some(ref only) = only {
+                        if !may_read(&readers, req.v.target(), only) {
+                            eprintln!("!!! dropped reader client that read from another view");
+                            return Either::Left(future::err(()));
+                        }
+                    }
+                    Either::Right(handle_message(req, &readers, &mut tx, &telemetry))
+                }),
+            )
+            .await
+        });
         tokio::spawn(
             server
                 .map_err(|e| {
@@ -176,13 +215,285 @@ where
     SerializedReadReplyBatch(v)
 }
 
+/// Serialize the rows of one page, and compute the offset at which the next page starts, if any.
+fn serialize_page<'a, I>(
+    rs: I,
+    offset: usize,
+    limit: usize,
+) -> (SerializedReadReplyBatch, Option<usize>)
+where
+    I: IntoIterator<Item = &'a Vec<DataType>>,
+    I::IntoIter: ExactSizeIterator,
+{
+    let it = rs.into_iter();
+    let end = offset.saturating_add(limit);
+    let next = if it.len() > end { Some(end) } else { None };
+    (serialize(it.skip(offset).take(limit)), next)
+}
+
+/// Serialize the rows for a key, which the reader gives in its row order if it has one. If `page`
+/// is given as `(offset, limit)`, only that page of the rows is included, along with the offset of
+/// the next.
+fn serialize_rows(
+    rs: KeyRows<'_>,
+    page: Option<(usize, usize)>,
+) -> (SerializedReadReplyBatch, Option<usize>) {
+    match page {
+        None => (serialize(rs), None),
+        Some((offset, limit)) => serialize_page(rs, offset, limit),
+    }
+}
+
+/// Resolve the reply to a blocking read, treating a dropped read as an error.
+fn flatten_ack(
+    r: Result<
+        Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>,
+        tokio::sync::oneshot::error::RecvError,
+    >,
+) -> Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()> {
+    match r {
+        Err(_) => Err(()),
+        Ok(r) => r,
+    }
+}
+
+fn unix_nanos() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// Whether the reader that `target` names may be read from by a client that may only read from
+/// the reader named `only`.
+///
+/// Readers that are not (or no longer) here may be asked for, so that the client is told to look
+/// for them elsewhere.
+fn may_read(s: &Readers, target: (NodeIndex, usize), only: &str) -> bool {
+    READERS.with(|readers_cache| {
+        use std::collections::hash_map::Entry;
+
+        let mut readers_cache = readers_cache.borrow_mut();
+        let reader = match readers_cache.entry(target) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => match s.lock().unwrap().get(&target) {
+                Some(reader) => e.insert(reader.clone()),
+                None => return true,
+            },
+        };
+        reader.name() == only
+    })
+}
+
 fn handle_message(
     m: Tagged<ReadQuery>,
     s: &Readers,
     wait: &mut tokio::sync::mpsc::UnboundedSender<(BlockingRead, Ack)>,
+    telemetry: &Telemetry,
 ) -> impl Future<Output = Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>> + Send {
     let tag = m.tag;
     match m.v {
+        ReadQuery::Traced {
+            context,
+            sent,
+            query,
+        } => {
+            let received = unix_nanos();
+            let (node, shard) = query.target();
+            let attributes = move || {
+                vec![
+                    KeyValue::new("noria.node", node.index() as i64),
+                    KeyValue::new("noria.shard", shard as i64),
+                ]
+            };
+            let context = telemetry.span(
+                "noria.client_send",
+                context,
+                sent,
+                received,
+                &[],
+                attributes(),
+            );
+            // the read's span lasts until it is answered, including any wait for it to be
+            // possible to answer
+            let telemetry = telemetry.clone();
+            Either::Left(
+                handle_query(Tagged { tag, v: *query }, s, wait).map(move |reply| {
+                    telemetry.span(
+                        "noria.read",
+                        context,
+                        received,
+                        unix_nanos(),
+                        &[],
+                        attributes(),
+                    );
+                    reply
+                }),
+            )
+        }
+        ReadQuery::Changes {
+            target,
+            from,
+            key,
+            limit,
+            wait,
+        } => Either::Right(Either::Left(handle_changes(
+            tag, target, from, key, limit, wait, s,
+        ))),
+        query => Either::Right(Either::Right(handle_query(
+            Tagged { tag, v: query },
+            s,
+            wait,
+        ))),
+    }
+}
+
+/// Read the changes to a reader after `from`, waiting up to `wait` for there to be some.
+fn handle_changes(
+    tag: u32,
+    target: (NodeIndex, usize),
+    from: Option<ChangeCursor>,
+    key: Option<Vec<DataType>>,
+    limit: usize,
+    wait: time::Duration,
+    s: &Readers,
+) -> impl Future<Output = Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>> + Send {
+    let reader = READERS.with(|readers_cache| {
+        use std::collections::hash_map::Entry;
+
+        let mut readers_cache = readers_cache.borrow_mut();
+        match readers_cache.entry(target) {
+            Entry::Occupied(e) if e.get().is_removed() => {
+                e.remove();
+                None
+            }
+            Entry::Occupied(e) => Some(e.get().clone()),
+            Entry::Vacant(e) => s
+                .lock()
+                .unwrap()
+                .get(&target)
+                .map(|reader| e.insert(reader.clone()).clone()),
+        }
+    });
+
+    async move {
+        let reader = match reader {
+            Some(reader) => reader,
+            None => {
+                return Ok(Tagged {
+                    tag,
+                    v: ReadReply::Redirect,
+                })
+            }
+        };
+
+        // the reader is only ever borrowed in between waits, since it can't be shared
+        let deadline = tokio::time::Instant::now() + wait;
+        let mut swaps = reader.change_swaps();
+        loop {
+            let changes = reader.changes_since(from.as_ref(), key.as_deref(), limit);
+            let changes = match changes {
+                Ok(Some(cs)) => {
+                    // a subscription is acknowledged right away, so the client can go ahead
+                    let subscribed = from.as_ref().map_or(true, |from| from.log != cs.next.log);
+                    if subscribed || cs.dropped || !cs.changes.is_empty() {
+                        Ok(cs)
+                    } else {
+                        match tokio::time::timeout_at(deadline, swaps.recv()).await {
+                            Ok(Some(_)) => continue,
+                            _ => Ok(cs),
+                        }
+                    }
+                }
+                Ok(None) => match tokio::time::timeout_at(deadline, swaps.recv()).await {
+                    Ok(Some(_)) => continue,
+                    // the client tries again, as if the reader wasn't ready
+                    _ => Err(()),
+                },
+                Err(()) => Err(()),
+            };
+            return Ok(Tagged {
+                tag,
+                v: ReadReply::Changes(changes),
+            });
+        }
+    }
+}
+
+fn handle_query(
+    m: Tagged<ReadQuery>,
+    s: &Readers,
+    wait: &mut tokio::sync::mpsc::UnboundedSender<(BlockingRead, Ack)>,
+) -> impl Future<Output = Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>> + Send {
+    let tag = m.tag;
+    // a read with a deadline is a blocking read that gives up once the deadline passes
+    let (query, deadline) = match m.v {
+        ReadQuery::Deadline {
+            target,
+            keys,
+            timeout,
+        } => (
+            ReadQuery::Normal {
+                target,
+                keys,
+                block: true,
+            },
+            Some(time::Instant::now() + timeout),
+        ),
+        query => (query, None),
+    };
+    // a read bounded in staleness waits like a read after writes, but for the reader to have
+    // caught up with a point in time rather than with particular writes
+    let (query, since) = match query {
+        ReadQuery::Fresh {
+            target,
+            keys,
+            staleness,
+            timeout,
+        } => (
+            ReadQuery::After {
+                target,
+                keys,
+                token: Vec::new(),
+                timeout,
+            },
+            Some(
+                time::SystemTime::now()
+                    .checked_sub(staleness)
+                    .and_then(|t| t.duration_since(time::UNIX_EPOCH).ok())
+                    .map_or(0, |d| d.as_nanos() as u64),
+            ),
+        ),
+        query => (query, None),
+    };
+
+    // a client with out-of-date information about where the view's shards are may ask for a
+    // reader that has since been removed or moved elsewhere, or ask the wrong shard for its keys
+    let misrouted = READERS.with(|readers_cache| {
+        use std::collections::hash_map::Entry;
+
+        let mut readers_cache = readers_cache.borrow_mut();
+        let target = query.target();
+        let reader = match readers_cache.entry(target) {
+            Entry::Occupied(e) if e.get().is_removed() => {
+                e.remove();
+                return true;
+            }
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => match s.lock().unwrap().get(&target) {
+                Some(reader) => e.insert(reader.clone()),
+                None => return true,
+            },
+        };
+        !query.keys().iter().all(|key| reader.holds(key))
+    });
+    if misrouted {
+        return Either::Right(future::ready(Ok(Tagged {
+            tag,
+            v: ReadReply::Redirect,
+        })));
+    }
+
+    match query {
         ReadQuery::Normal {
             target,
             mut keys,
@@ -207,7 +518,9 @@ fn handle_message(
                         ret.push(SerializedReadReplyBatch::empty());
                         return false;
                     }
-                    let rs = reader.try_find_and(key, |rs| serialize(rs)).map(|r| r.0);
+                    let rs = reader
+                        .try_find_and(key, |rs| serialize_rows(rs, None).0)
+                        .map(|r| r.0);
                     match rs {
                         Ok(Some(rs)) => {
                             // immediate hit!
@@ -245,8 +558,9 @@ fn handle_message(
                     });
                 }
 
-                // trigger backfills for all the keys we missed on
-                reader.trigger(keys.iter().map(Vec::as_slice));
+                // trigger backfills for all the keys we missed on. keys that are already being
+                // backfilled, say for other reads that missed on them, are left alone.
+                reader.trigger_new(keys.iter().map(Vec::as_slice));
 
                 Err((keys, ret, pending))
             });
@@ -255,9 +569,10 @@ fn handle_message(
                 Ok(reply) => Either::Left(Either::Left(future::ready(Ok(reply)))),
                 Err((keys, ret, pending)) => {
                     if !block {
+                        // return the hits right away, and tell the client which keys missed
                         Either::Left(Either::Left(future::ready(Ok(Tagged {
                             tag,
-                            v: ReadReply::Normal(Ok(ret)),
+                            v: ReadReply::Partial(ret, pending),
                         }))))
                     } else {
                         let (tx, rx) = tokio::sync::oneshot::channel();
@@ -270,6 +585,10 @@ fn handle_message(
                                 keys,
                                 pending,
                                 read: ret,
+                                page: None,
+                                next: None,
+                                after: None,
+                                deadline,
                                 truth: s.clone(),
                                 trigger_timeout: trigger,
                                 next_trigger: now,
@@ -281,13 +600,177 @@ fn handle_message(
                             // we're shutting down
                             return Either::Left(Either::Left(future::ready(Err(()))));
                         }
-                        Either::Left(Either::Right(rx.map(|r| match r {
-                            Err(_) => Err(()),
-                            Ok(r) => r,
-                        })))
+                        Either::Left(Either::Right(rx.map(flatten_ack)))
+                    }
+                }
+            }
+        }
+        ReadQuery::Range {
+            target,
+            prefix,
+            range,
+        } => {
+            let rows: Result<_, String> = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                reader.check_ordered_lookup(prefix.len(), true)?;
+                // ordered readers are never partial, so there is nothing to wait for
+                Ok(reader.try_find_range_and(&prefix, &range, |rs| {
+                    rs.iter().cloned().collect::<Vec<_>>()
+                }))
+            });
+
+            let reply = match rows {
+                Ok(Ok(rows)) => {
+                    let rows: Vec<_> = rows.into_iter().flatten().collect();
+                    ReadReply::Normal(Ok(vec![serialize(&rows)]))
+                }
+                Ok(Err(())) => ReadReply::Normal(Err(())),
+                Err(e) => ReadReply::Invalid(e),
+            };
+            Either::Right(future::ready(Ok(Tagged { tag, v: reply })))
+        }
+        ReadQuery::Prefix { target, prefix } => {
+            let rows: Result<_, String> = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                reader.check_ordered_lookup(prefix.len(), false)?;
+                // ordered readers are never partial, so there is nothing to wait for
+                let rows = reader
+                    .try_find_prefix_and(&prefix, |rs| rs.iter().cloned().collect::<Vec<_>>());
+                Ok(rows)
+            });
+
+            let reply = match rows {
+                Ok(Ok(rows)) => {
+                    let rows: Vec<_> = rows.into_iter().flatten().collect();
+                    ReadReply::Normal(Ok(vec![serialize(&rows)]))
+                }
+                Ok(Err(())) => ReadReply::Normal(Err(())),
+                Err(e) => ReadReply::Invalid(e),
+            };
+            Either::Right(future::ready(Ok(Tagged { tag, v: reply })))
+        }
+        ReadQuery::Page {
+            target,
+            key,
+            offset,
+            limit,
+            block,
+        } => {
+            let immediate = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                match reader
+                    .try_find_and(&key, |rs| serialize_rows(rs, Some((offset, limit))))
+                    .map(|r| r.0)
+                {
+                    Ok(Some(page)) => Ok(ReadReply::Page(Ok(page))),
+                    Err(()) => Ok(ReadReply::Page(Err(()))),
+                    Ok(None) => {
+                        reader.trigger_new(std::iter::once(&key[..]));
+                        Err(key)
                     }
                 }
+            });
+
+            match immediate {
+                Ok(v) => Either::Right(future::ready(Ok(Tagged { tag, v }))),
+                Err(_) if !block => {
+                    // a miss looks like an empty page that should be fetched again
+                    Either::Right(future::ready(Ok(Tagged {
+                        tag,
+                        v: ReadReply::Page(Ok((SerializedReadReplyBatch::empty(), Some(offset)))),
+                    })))
+                }
+                Err(key) => {
+                    let (tx, rx) = tokio::sync::oneshot::channel();
+                    let trigger = time::Duration::from_millis(TRIGGER_TIMEOUT_MS);
+                    let now = time::Instant::now();
+                    let r = wait.send((
+                        BlockingRead {
+                            tag,
+                            target,
+                            keys: vec![key],
+                            pending: vec![0],
+                            read: vec![SerializedReadReplyBatch::empty()],
+                            page: Some((offset, limit)),
+                            next: None,
+                            after: None,
+                            deadline: None,
+                            truth: s.clone(),
+                            trigger_timeout: trigger,
+                            next_trigger: now,
+                            first: now,
+                        },
+                        tx,
+                    ));
+                    if r.is_err() {
+                        // we're shutting down
+                        return Either::Right(future::ready(Err(())));
+                    }
+                    Either::Left(Either::Right(rx.map(flatten_ack)))
+                }
+            }
+        }
+        ReadQuery::After {
+            target,
+            keys,
+            token,
+            timeout,
+        } => {
+            // the read has to wait for the writes first, so leave it all to the blocking path
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            let trigger = time::Duration::from_millis(TRIGGER_TIMEOUT_MS);
+            let now = time::Instant::now();
+            let r = wait.send((
+                BlockingRead {
+                    tag,
+                    target,
+                    read: keys
+                        .iter()
+                        .map(|_| SerializedReadReplyBatch::empty())
+                        .collect(),
+                    pending: (0..keys.len()).collect(),
+                    keys,
+                    page: None,
+                    next: None,
+                    after: Some((
+                        since.map_or(Awaited::Writes(token), Awaited::Since),
+                        now + timeout,
+                    )),
+                    deadline: None,
+                    truth: s.clone(),
+                    trigger_timeout: trigger,
+                    next_trigger: now,
+                    first: now,
+                },
+                tx,
+            ));
+            if r.is_err() {
+                // we're shutting down
+                return Either::Right(future::ready(Err(())));
             }
+            Either::Left(Either::Right(rx.map(flatten_ack)))
+        }
+        ReadQuery::Changes { .. } => {
+            // only traced reads get here, and a change feed isn't traced
+            Either::Right(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::Changes(Err(())),
+            })))
         }
         ReadQuery::Size { target } => {
             let size = READERS.with(|readers_cache| {
@@ -305,9 +788,23 @@ fn handle_message(
                 v: ReadReply::Size(size),
             })))
         }
+        ReadQuery::Deadline { .. } | ReadQuery::Fresh { .. } => {
+            unreachable!("turned into other reads above")
+        }
+        ReadQuery::Traced { .. } => unreachable!("traced reads are unwrapped before they get here"),
     }
 }
 
+/// What a reader must reflect before a read from it can go ahead.
+#[derive(Debug)]
+enum Awaited {
+    /// The batches of writes identified by a write token.
+    Writes(Vec<(NodeIndex, usize, u64)>),
+    /// Every batch of writes its base shards applied up to the given time, in nanoseconds since
+    /// the Unix epoch.
+    Since(u64),
+}
+
 #[pin_project]
 struct BlockingRead {
     tag: u32,
@@ -318,6 +815,14 @@ struct BlockingRead {
     keys: Vec<Vec<DataType>>,
     // index in self.read that each entyr in keys corresponds to
     pending: Vec<usize>,
+    // offset and limit if this is a paginated read of a single key
+    page: Option<(usize, usize)>,
+    // offset of the page after the one we read, if any
+    next: Option<usize>,
+    // what the reader must reflect before we read, and when to give up waiting for it
+    after: Option<(Awaited, time::Instant)>,
+    // when to give up waiting for missing keys to be backfilled, if ever
+    deadline: Option<time::Instant>,
     truth: Readers,
 
     trigger_timeout: time::Duration,
@@ -333,6 +838,10 @@ impl std::fmt::Debug for BlockingRead {
             .field("read", &self.read)
             .field("keys", &self.keys)
             .field("pending", &self.pending)
+            .field("page", &self.page)
+            .field("next", &self.next)
+            .field("after", &self.after)
+            .field("deadline", &self.deadline)
             .field("trigger_timeout", &self.trigger_timeout)
             .field("next_trigger", &self.next_trigger)
             .field("first", &self.first)
@@ -342,7 +851,36 @@ impl std::fmt::Debug for BlockingRead {
 
 impl BlockingRead {
     fn check(&mut self) -> Poll<Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>> {
-        READERS.with(|readers_cache| {
+        if let Some((ref token, deadline)) = self.after {
+            let observed = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let s = &self.truth;
+                let target = &self.target;
+                let reader = readers_cache.entry(self.target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(target).unwrap().clone()
+                });
+                match *token {
+                    Awaited::Writes(ref token) => reader.has_observed(token),
+                    Awaited::Since(since) => reader.is_fresh(since),
+                }
+            });
+
+            if !observed {
+                if time::Instant::now() > deadline {
+                    return Poll::Ready(Ok(Tagged {
+                        tag: self.tag,
+                        v: ReadReply::TimedOut,
+                    }));
+                }
+                return Poll::Pending;
+            }
+
+            // the writes are visible, so we can go ahead with the read
+            self.after = None;
+        }
+
+        let warming = READERS.with(|readers_cache| {
             let mut readers_cache = readers_cache.borrow_mut();
             let s = &self.truth;
             let target = &self.target;
@@ -353,6 +891,8 @@ impl BlockingRead {
 
             let now = time::Instant::now();
             let read = &mut self.read;
+            let next = &mut self.next;
+            let page = self.page;
             let next_trigger = self.next_trigger;
 
             // here's the trick we're going to play:
@@ -364,9 +904,13 @@ impl BlockingRead {
 
             while let Some(read_i) = self.pending.pop() {
                 let key = self.keys.pop().expect("pending.len() == keys.len()");
-                match reader.try_find_and(&key, |rs| serialize(rs)).map(|r| r.0) {
-                    Ok(Some(rs)) => {
+                let rs = reader
+                    .try_find_and(&key, |rs| serialize_rows(rs, page))
+                    .map(|r| r.0);
+                match rs {
+                    Ok(Some((rs, next_page))) => {
                         read[read_i] = rs;
+                        *next = next_page;
                     }
                     Err(()) => {
                         // map has been deleted, so server is shutting down
@@ -385,8 +929,9 @@ impl BlockingRead {
             debug_assert_eq!(self.pending.len(), self.keys.len());
 
             if !self.keys.is_empty() && now > next_trigger {
-                // maybe the key got filled, then evicted, and we missed it?
-                if !reader.trigger(self.keys.iter().map(Vec::as_slice)) {
+                // maybe the key got filled, then evicted, and we missed it? or the replay was
+                // lost?
+                if !reader.trigger_new(self.keys.iter().map(Vec::as_slice)) {
                     // server is shutting down and won't do the backfill
                     return Err(());
                 }
@@ -404,16 +949,36 @@ impl BlockingRead {
                         self.keys, waited
                     );
                 }
+
+                if self.deadline.map(|d| now > d).unwrap_or(false) {
+                    // the backfill carries on, and a retry of the read will wait for it
+                    return Ok(Some(
+                        reader.replay_outstanding(self.keys.iter().map(Vec::as_slice)),
+                    ));
+                }
             }
 
-            Ok(())
+            Ok(None)
         })?;
 
-        if self.keys.is_empty() {
-            Poll::Ready(Ok(Tagged {
+        if let Some(outstanding) = warming {
+            return Poll::Ready(Ok(Tagged {
                 tag: self.tag,
-                v: ReadReply::Normal(Ok(mem::take(&mut self.read))),
-            }))
+                v: ReadReply::Warming(outstanding),
+            }));
+        }
+
+        if self.keys.is_empty() {
+            let v = if self.page.is_some() {
+                let rs = self
+                    .read
+                    .pop()
+                    .expect("paginated reads have exactly one key");
+                ReadReply::Page(Ok((rs, self.next)))
+            } else {
+                ReadReply::Normal(Ok(mem::take(&mut self.read)))
+            };
+            Poll::Ready(Ok(Tagged { tag: self.tag, v }))
         } else {
             Poll::Pending
         }
@@ -534,6 +1099,67 @@ mod readreply {
         ));
     }
 
+    #[test]
+    fn rtt_partial() {
+        let got: Tagged<ReadReply> = bincode::deserialize(
+            &bincode::serialize(&Tagged {
+                tag: 32,
+                v: ReadReply::Partial::<SerializedReadReplyBatch>(
+                    vec![
+                        super::serialize(&[vec![DataType::from(1)]]),
+                        SerializedReadReplyBatch::empty(),
+                    ],
+                    vec![1],
+                ),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+
+        match got {
+            Tagged {
+                v: ReadReply::Partial(got, pending),
+                tag: 32,
+            } => {
+                assert_eq!(got.len(), 2);
+                assert_eq!(&*got[0], &[vec![DataType::from(1)]]);
+                assert!(got[1].is_empty());
+                assert_eq!(pending, vec![1]);
+            }
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn rtt_page() {
+        let rows = vec![
+            vec![DataType::from(1)],
+            vec![DataType::from(2)],
+            vec![DataType::from(3)],
+        ];
+        let got: Tagged<ReadReply> = bincode::deserialize(
+            &bincode::serialize(&Tagged {
+                tag: 32,
+                v: ReadReply::Page::<SerializedReadReplyBatch>(Ok(super::serialize_page(
+                    &rows, 1, 1,
+                ))),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+
+        match got {
+            Tagged {
+                v: ReadReply::Page(Ok((got, next))),
+                tag: 32,
+            } => {
+                assert_eq!(&*got, &rows[1..2]);
+                assert_eq!(next, Some(2));
+            }
+            r => panic!("{:?}", r),
+        }
+    }
+
     #[test]
     fn rtt_size() {
         let got: Tagged<ReadReply> = bincode::deserialize(
@@ -553,6 +1179,25 @@ mod readreply {
         ));
     }
 
+    #[test]
+    fn rtt_redirect() {
+        let got: Tagged<ReadReply> = bincode::deserialize(
+            &bincode::serialize(&Tagged {
+                tag: 32,
+                v: ReadReply::Redirect::<SerializedReadReplyBatch>,
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        assert!(matches!(
+            got,
+            Tagged {
+                tag: 32,
+                v: ReadReply::Redirect
+            }
+        ));
+    }
+
     async fn async_bincode_rtt_ok(data: Vec<Vec<Vec<DataType>>>) {
         use futures_util::{SinkExt, StreamExt};
 
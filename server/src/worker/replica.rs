@@ -1,6 +1,12 @@
 /// Only allow processing this many inputs in a domain before we handle timer events, acks, etc.
 const FORCE_INPUT_YIELD_EVERY: usize = 32;
 
+/// While the priority lanes have more for a domain than it processes before yielding, only read
+/// this many regular updates (or writes) in between, so that they are not starved entirely.
+const MIN_UPDATES_PER_YIELD: usize = 4;
+
+use super::faults::SenderFaults;
+use super::health::Liveness;
 use super::ChannelCoordinator;
 use crate::coordination::CoordinationPayload;
 use ahash::{AHashMap, AHashSet};
@@ -10,22 +16,25 @@ use bincode;
 use dataflow::{
     payload::SourceChannelIdentifier,
     prelude::{DataType, Executor},
-    Domain, Packet, PollEvent, ProcessResult,
+    Domain, DomainMetrics, OverloadPolicy, Packet, PollEvent, ProcessResult,
 };
 use failure::{self, Fail, ResultExt};
 use futures_util::{
     sink::Sink,
     stream::{futures_unordered::FuturesUnordered, Stream},
 };
-use noria::channel::{DualTcpStream, CONNECTION_FROM_BASE};
+use noria::auth::{self, Principals, Scope};
+use noria::channel::tls::{self, MaybeTls, Tls};
+use noria::channel::{self, DualTcpStream, CONNECTION_FROM_BASE, CONNECTION_PRIORITY};
+use noria::consensus::Epoch;
 use noria::internal::DomainIndex;
-use noria::internal::LocalOrNot;
-use noria::{Input, Tagged};
+use noria::internal::{LocalNodeIndex, LocalOrNot};
+use noria::{Input, Tagged, WriteAck};
 use pin_project::pin_project;
 use slog;
 use std::collections::{HashMap, VecDeque};
 use std::io;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time;
 use std::{
     future::Future,
@@ -39,16 +48,57 @@ use tokio::io::{AsyncReadExt, BufReader, BufStream, BufWriter};
 
 pub(super) type ReplicaAddr = (DomainIndex, usize);
 
-// https://github.com/rust-lang/rust/issues/64445
-type FirstByte = impl Future<Output = Result<(tokio::net::TcpStream, u8), tokio::io::Error>> + Send;
+/// How far each domain on a worker that is shutting down has come with draining its work.
+pub(super) type Draining = Arc<Mutex<HashMap<ReplicaAddr, DrainStatus>>>;
+
+/// What a domain still has left to do before it has drained.
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct DrainStatus {
+    /// The domain has nothing left to do, and has told the controller so.
+    pub(super) quiescent: bool,
+    /// How many packets are waiting to be sent to other domains.
+    pub(super) queued: usize,
+    /// How many records those packets carry.
+    pub(super) records: usize,
+    /// How many client writes have been taken in, but not yet acknowledged.
+    pub(super) unacked: usize,
+}
+
+type Connection = MaybeTls<tokio::net::TcpStream>;
 
-/// Read the first byte of a stream.
-fn read_first_byte(mut stream: tokio::net::TcpStream) -> FirstByte {
+type InputStream =
+    DualTcpStream<BufStream<Connection>, Box<Packet>, Tagged<LocalOrNot<Input>>, AsyncDestination>;
+
+type OutputSink = Box<dyn Sink<Box<Packet>, Error = bincode::Error> + Send + Unpin>;
+
+// https://github.com/rust-lang/rust/issues/64445
+type FirstByte = impl Future<Output = Result<Accepted, tokio::io::Error>> + Send;
+
+/// A new connection, what kind of connection it is, and what it presented.
+type Accepted = (Connection, u8, Option<Epoch>, Option<Scope>);
+
+/// Start TLS on a new connection if we expect it, and then read the first byte of the stream.
+///
+/// Connections from clients must then present a token of one of the `principals` that may write
+/// to the table the connection is for, which is returned if there are principals. Connections from
+/// other domains must present the epoch of the controller they were made for, if any.
+fn read_first_byte(
+    stream: tokio::net::TcpStream,
+    tls: Option<Tls>,
+    principals: Option<Arc<Principals>>,
+) -> FirstByte {
     async move {
+        let mut stream = tls::maybe_accept(tls.as_ref(), stream).await?;
         let mut byte = [0; 1];
         let n = stream.read_exact(&mut byte[..]).await?;
         assert_eq!(n, 1);
-        Ok((stream, byte[0]))
+        if byte[0] == CONNECTION_FROM_BASE {
+            let scope = auth::check(&mut stream, principals.as_deref()).await?;
+            return Ok((stream, byte[0], None, scope));
+        }
+        let mut fence = [0; channel::FENCE_LEN];
+        stream.read_exact(&mut fence[..]).await?;
+        Ok((stream, byte[0], channel::read_fence(fence), None))
     }
 }
 
@@ -59,6 +109,15 @@ pub(super) struct Replica {
 
     coord: Arc<ChannelCoordinator>,
 
+    // what new connections must use, if anything
+    tls: Option<Tls>,
+
+    // who may write to the domain's bases, if clients must authenticate
+    principals: Option<Arc<Principals>>,
+
+    // the epoch of the controller whose dataflow the domain belongs to
+    controller_epoch: Epoch,
+
     retry: Option<Box<Packet>>,
 
     #[pin]
@@ -67,69 +126,120 @@ pub(super) struct Replica {
     #[pin]
     valve: Valve,
 
+    // closed when the worker is shutting down, and the domain should finish the work it has been
+    // given without taking in any new writes
+    #[pin]
+    drain: Valve,
+    draining: bool,
+    quiescent: bool,
+    drain_status: Draining,
+
     #[pin]
     incoming: Strawpoll<tokio::net::TcpListener>,
 
     #[pin]
     first_byte: FuturesUnordered<FirstByte>,
 
-    locals: tokio::sync::mpsc::UnboundedReceiver<Box<Packet>>,
+    // updates and replayed records from local domains
+    locals: tokio::sync::mpsc::Receiver<Box<Packet>>,
+
+    // the priority lane from local domains and the controller
+    priority_locals: tokio::sync::mpsc::UnboundedReceiver<Box<Packet>>,
 
+    // writes from clients
     #[pin]
-    inputs: StreamUnordered<
-        DualTcpStream<
-            BufStream<tokio::net::TcpStream>,
-            Box<Packet>,
-            Tagged<LocalOrNot<Input>>,
-            AsyncDestination,
-        >,
-    >,
-
-    outputs: AHashMap<
-        ReplicaAddr,
-        (
-            Box<dyn Sink<Box<Packet>, Error = bincode::Error> + Send + Unpin>,
-            bool,
-        ),
-    >,
+    inputs: StreamUnordered<InputStream>,
+
+    // updates and replayed records from remote domains
+    #[pin]
+    upstream: StreamUnordered<InputStream>,
+
+    // the priority lane from remote domains and the controller
+    #[pin]
+    priority: StreamUnordered<InputStream>,
+
+    outputs: AHashMap<ReplicaAddr, (OutputSink, bool)>,
+    priority_outputs: AHashMap<ReplicaAddr, (OutputSink, bool)>,
 
     #[pin]
     timeout: Strawpoll<async_timer::oneshot::Timer>,
     timed_out: bool,
 
+    // whether we've stopped taking in new work until our outboxes drain
+    paused: bool,
+
+    // what to do with client writes while we're paused
+    overload_policy: OverloadPolicy,
+
+    // how much state the domain may keep before it evicts some
+    memory_budget: Option<usize>,
+
+    // where the worker reads the domain's metrics from, if it exports them
+    metrics: Option<Arc<Mutex<DomainMetrics>>>,
+
+    // where we answer the worker's pings
+    liveness: Arc<Liveness>,
+
     out: Outboxes,
 }
 
 impl Replica {
     pub(super) fn new(
         valve: &Valve,
+        drain: &Valve,
+        drain_status: Draining,
         mut domain: Domain,
         on: tokio::net::TcpListener,
-        locals: tokio::sync::mpsc::UnboundedReceiver<Box<Packet>>,
+        locals: tokio::sync::mpsc::Receiver<Box<Packet>>,
+        priority_locals: tokio::sync::mpsc::UnboundedReceiver<Box<Packet>>,
+        memory_budget: Option<usize>,
+        metrics: Option<Arc<Mutex<DomainMetrics>>>,
         ctrl_tx: tokio::sync::mpsc::UnboundedSender<CoordinationPayload>,
         log: slog::Logger,
         cc: Arc<ChannelCoordinator>,
+        principals: Option<Arc<Principals>>,
+        controller_epoch: Epoch,
+        faults: Option<SenderFaults>,
+        liveness: Arc<Liveness>,
     ) -> Self {
         let id = domain.id();
         let id = format!("{}.{}", id.0.index(), id.1);
         domain.booted(on.local_addr().unwrap());
+        let high_water = domain.output_high_water();
+        let overload_policy = domain.overload_policy();
         Replica {
+            tls: cc.tls().cloned(),
+            principals,
+            controller_epoch,
             coord: cc,
             domain,
             retry: None,
             valve: valve.clone(),
+            drain: drain.clone(),
+            draining: false,
+            quiescent: false,
+            drain_status,
             incoming: Strawpoll::from(on),
             first_byte: FuturesUnordered::new(),
             locals,
+            priority_locals,
             log: log.new(o! {"id" => id}),
             inputs: Default::default(),
+            upstream: Default::default(),
+            priority: Default::default(),
             outputs: Default::default(),
-            out: Outboxes::new(ctrl_tx),
+            priority_outputs: Default::default(),
+            out: Outboxes::new(ctrl_tx, high_water, faults),
             timeout: Strawpoll::from(async_timer::oneshot::Timer::new(time::Duration::from_secs(
                 3600,
             ))),
             refresh_sizes: tokio::time::interval(time::Duration::from_millis(500)),
             timed_out: false,
+            paused: false,
+            overload_policy,
+            memory_budget,
+            metrics,
+            liveness,
         }
     }
 
@@ -147,7 +257,7 @@ impl Replica {
             let mut stream = Pin::new(&mut inputs[streami]);
             let mut sent = 0;
 
-            for &tag in &conn.tag_acks {
+            for &(tag, ref ack) in &conn.tag_acks {
                 match stream.as_mut().poll_ready(cx) {
                     Poll::Ready(Ok(())) => {}
                     Poll::Pending => break,
@@ -157,7 +267,10 @@ impl Replica {
                     }
                 }
 
-                if let Err(e) = stream.as_mut().start_send(Tagged { tag, v: () }) {
+                if let Err(e) = stream.as_mut().start_send(Tagged {
+                    tag,
+                    v: ack.clone(),
+                }) {
                     // start_send shouldn't generally error
                     err.push(e.into());
                     break;
@@ -256,81 +369,60 @@ impl Replica {
         Ok(())
     }
 
-    fn try_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Result<(), failure::Error> {
+    /// Lose what was waiting for the domains whose connections from us were severed, and queue up
+    /// the held back packets that are now due.
+    fn try_faults(self: Pin<&mut Self>, cx: &mut Context<'_>) {
         let this = self.project();
-
-        let cc = this.coord;
-        let outputs = this.outputs;
-
-        // just like in try_acks:
-        // first, queue up any additional writes we have to do
-        let mut err = Vec::new();
-        for (&ri, ms) in &mut this.out.domains {
-            if ms.is_empty() {
-                continue;
-            }
-
-            let &mut (ref mut tx, ref mut pending) = outputs.entry(ri).or_insert_with(|| {
-                while !cc.has(&ri) {}
-                let tx = cc.builder_for(&ri).unwrap().build_async().unwrap();
-                (tx, true)
-            });
-
-            let mut tx = Pin::new(tx);
-
-            while !ms.is_empty() {
-                match tx.as_mut().poll_ready(cx) {
-                    Poll::Ready(Ok(())) => {}
-                    Poll::Pending => break,
-                    Poll::Ready(Err(e)) => {
-                        err.push(e);
-                        break;
-                    }
-                }
-
-                let m = ms.pop_front().expect("!is_empty");
-                match tx.as_mut().start_send(m) {
-                    Ok(()) => {
-                        // we queued something, so we'll need to send!
-                        *pending = true;
-                    }
-                    Err(e) => {
-                        err.push(e);
-                        break;
-                    }
+        let out = this.out;
+        let faults = match out.faults {
+            Some(ref mut faults) => faults,
+            None => return,
+        };
+
+        for to in faults.severed() {
+            let mut lost = 0;
+            for (&(d, _), ms) in out.domains.iter_mut().chain(out.priority.iter_mut()) {
+                if d == to {
+                    lost += ms.len();
+                    ms.clear();
                 }
             }
-        }
+            faults.lost(to, lost);
 
-        if !err.is_empty() {
-            return Err(err.swap_remove(0).into());
+            // dropping the sinks closes the connections, and new ones are made on the next send
+            this.outputs.retain(|&(d, _), _| d != to);
+            this.priority_outputs.retain(|&(d, _), _| d != to);
+            warn!(this.log, "severed connections to domain";
+                  "domain" => to.index(), "lost" => lost);
         }
 
-        // then, try to do any sends that are still pending
-        for &mut (ref mut tx, ref mut pending) in outputs.values_mut() {
-            if !*pending {
-                continue;
-            }
-
-            match Pin::new(tx).poll_flush(cx) {
-                Poll::Ready(Ok(())) => {
-                    *pending = false;
-                }
-                Poll::Pending => {}
-                Poll::Ready(Err(e)) => err.push(e),
-            }
+        for (dest, m) in faults.poll_release(cx) {
+            out.enqueue(dest, m);
         }
+    }
 
-        if !err.is_empty() {
-            return Err(err.swap_remove(0).into());
-        }
+    fn try_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Result<(), failure::Error> {
+        let this = self.project();
 
+        let cc = &**this.coord;
+        flush(cc, &mut this.out.priority, this.priority_outputs, true, cx)?;
+        flush(cc, &mut this.out.domains, this.outputs, false, cx)?;
         Ok(())
     }
 
     fn try_new(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Result<bool, failure::Error> {
         let mut this = self.project();
 
+        if !*this.draining {
+            if let Poll::Ready(true) = this.drain.poll_closed(cx) {
+                info!(
+                    this.log,
+                    "draining domain; no longer taking in client writes"
+                );
+                *this.draining = true;
+            }
+        }
+
         if let Poll::Ready(true) = this.valve.poll_closed(cx) {
             return Ok(false);
         } else {
@@ -343,13 +435,17 @@ impl Replica {
                 // we know that any new connection to a domain will first send a one-byte
                 // token to indicate whether the connection is from a base or not.
                 debug!(this.log, "accepted new connection"; "from" => ?stream.peer_addr().unwrap());
-                this.first_byte.push(read_first_byte(stream));
+                this.first_byte.push(read_first_byte(
+                    stream,
+                    this.tls.clone(),
+                    this.principals.clone(),
+                ));
             }
         }
 
         while let Poll::Ready(Some(r)) = this.first_byte.as_mut().poll_next(cx) {
-            let (stream, tag) = match r {
-                Ok((s, t)) => (s, t),
+            let (stream, tag, sender_epoch, scope) = match r {
+                Ok(r) => r,
                 Err(e) => {
                     if let io::ErrorKind::BrokenPipe
                     | io::ErrorKind::NotConnected
@@ -361,16 +457,100 @@ impl Replica {
                         // let's not bother the user with it
                         continue;
                     }
+                    if let io::ErrorKind::InvalidData = e.kind() {
+                        // a peer that cannot speak TLS with us should not take the domain down
+                        warn!(
+                            this.log,
+                            "rejected connection that failed the TLS handshake: {}", e
+                        );
+                        continue;
+                    }
+                    if let io::ErrorKind::PermissionDenied = e.kind() {
+                        warn!(this.log, "rejected client connection: {}", e);
+                        continue;
+                    }
                     Err(e).context("poll_next")?;
                     unreachable!();
                 }
             };
+            match sender_epoch {
+                Some(e) if e < *this.controller_epoch => {
+                    // a sender that was cut off while the controller failed over, and came back.
+                    // closing the connection makes it stand down.
+                    let peer = stream.get_ref().peer_addr().ok();
+                    warn!(this.log, "refused connection from an earlier controller's dataflow";
+                          "from" => ?peer, "epoch" => ?e);
+                    if let Some(peer) = peer {
+                        let (domain, shard) = this.domain.id();
+                        let _ = this.out.ctrl_tx.send(CoordinationPayload::SenderFenced {
+                            domain,
+                            shard,
+                            peer,
+                        });
+                    }
+                    continue;
+                }
+                Some(e) if e > *this.controller_epoch => {
+                    // it's us that has been cut off, and our worker will soon replace us
+                    warn!(this.log, "refused connection from a later controller's dataflow";
+                          "from" => ?stream.get_ref().peer_addr().ok(), "epoch" => ?e);
+                    continue;
+                }
+                _ => {}
+            }
+
             let is_base = tag == CONNECTION_FROM_BASE;
+            if is_base && *this.draining {
+                debug!(this.log, "turned away client connection while draining");
+                continue;
+            }
+
+            // a client whose token was checked may only write to the table it said it is for
+            let base = match scope {
+                Some(Scope::Table { .. }) => {
+                    let name = scope.as_ref().unwrap().node_name();
+                    match this.domain.base_named(&name) {
+                        Some(base) => Some(base),
+                        None => {
+                            warn!(this.log, "refused client connection for a table we do not hold";
+                                  "table" => name);
+                            continue;
+                        }
+                    }
+                }
+                Some(scope) => {
+                    warn!(this.log, "refused client connection that is not for a table";
+                          "scope" => ?scope);
+                    continue;
+                }
+                None => None,
+            };
 
             debug!(this.log, "established new connection"; "base" => ?is_base);
+            if let Err(e) = stream.get_ref().set_nodelay(true) {
+                warn!(this.log,
+                      "failed to set TCP_NODELAY for new connection: {:?}", e;
+                      "from" => ?stream.get_ref().peer_addr().unwrap());
+            }
+            if !is_base {
+                // domains never wait for acks, so we don't need to track their connections
+                let tcp = tokio::io::BufStream::from(BufReader::with_capacity(
+                    2 * 1024 * 1024,
+                    BufWriter::with_capacity(4 * 1024, stream),
+                ))
+                .into();
+                if tag == CONNECTION_PRIORITY {
+                    this.priority.insert(tcp);
+                } else {
+                    this.upstream.insert(tcp);
+                }
+                continue;
+            }
+
             let slot = this.inputs.stream_entry();
             let token = slot.token();
             let epoch = if let Some(e) = this.out.connections.get_mut(token) {
+                e.base = base;
                 e.epoch
             } else {
                 let epoch = 1;
@@ -379,33 +559,21 @@ impl Replica {
                     tag_acks: Vec::new(),
                     epoch,
                     pending_flush: false,
+                    base,
                 });
                 assert_eq!(t, token);
                 epoch
             };
-            if let Err(e) = stream.set_nodelay(true) {
-                warn!(this.log,
-                      "failed to set TCP_NODELAY for new connection: {:?}", e;
-                      "from" => ?stream.peer_addr().unwrap());
-            }
-            let tcp = if is_base {
-                DualTcpStream::upgrade(
-                    tokio::io::BufStream::new(stream),
-                    move |Tagged { v: input, tag }| {
-                        Box::new(Packet::Input {
-                            inner: input,
-                            src: Some(SourceChannelIdentifier { token, tag, epoch }),
-                            senders: Vec::new(),
-                        })
-                    },
-                )
-            } else {
-                tokio::io::BufStream::from(BufReader::with_capacity(
-                    2 * 1024 * 1024,
-                    BufWriter::with_capacity(4 * 1024, stream),
-                ))
-                .into()
-            };
+            let tcp = DualTcpStream::upgrade(
+                tokio::io::BufStream::new(stream),
+                move |Tagged { v: input, tag }| {
+                    Box::new(Packet::Input {
+                        inner: input,
+                        src: Some(SourceChannelIdentifier { token, tag, epoch }),
+                        senders: Vec::new(),
+                    })
+                },
+            );
             slot.insert(tcp);
         }
         Ok(true)
@@ -430,20 +598,160 @@ impl Replica {
 
         processed
     }
+
+    /// Send on everything the domain is holding back, and tell the controller once the domain has
+    /// nothing left to do.
+    ///
+    /// `idle` says whether the domain found nothing to process the last time it looked.
+    fn try_drain(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        idle: bool,
+    ) -> Result<(), failure::Error> {
+        let this = self.as_mut().project();
+        let dirty = std::mem::replace(&mut this.out.dirty, false);
+        let frontiers = this.domain.drain(this.out);
+        let sent = this.out.dirty;
+        this.out.dirty |= dirty;
+        self.as_mut()
+            .try_flush(cx)
+            .context("downstream flush (drain)")?;
+
+        let this = self.project();
+        let queued: usize = this
+            .out
+            .domains
+            .values()
+            .chain(this.out.priority.values())
+            .map(VecDeque::len)
+            .sum();
+        let (records, _) = this.out.queued_records();
+        let unacked: usize = this.out.connections.iter().map(|(_, c)| c.unacked).sum();
+        let quiescent = idle && !sent && queued == 0 && this.retry.is_none();
+        if quiescent && !*this.quiescent {
+            let (domain, shard) = this.domain.id();
+            info!(this.log, "domain drained"; "edges" => frontiers.len());
+            let quiesced = CoordinationPayload::DomainQuiescent {
+                domain,
+                shard,
+                frontiers,
+            };
+            if this.out.ctrl_tx.send(quiesced).is_err() {
+                warn!(this.log, "controller went away before the domain drained");
+            }
+        } else if !quiescent && queued == 0 {
+            // nothing is waiting on the network, so nothing else will wake us up to check again
+            cx.waker().wake_by_ref();
+        }
+        *this.quiescent = quiescent;
+
+        this.drain_status.lock().unwrap().insert(
+            this.domain.id(),
+            DrainStatus {
+                quiescent,
+                queued,
+                records,
+                unacked,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Send what we can of the messages waiting for other domains on one lane, and flush what we sent.
+fn flush(
+    cc: &ChannelCoordinator,
+    outboxes: &mut AHashMap<ReplicaAddr, VecDeque<Box<Packet>>>,
+    outputs: &mut AHashMap<ReplicaAddr, (OutputSink, bool)>,
+    prioritized: bool,
+    cx: &mut Context<'_>,
+) -> Result<(), failure::Error> {
+    // just like in try_acks:
+    // first, queue up any additional writes we have to do
+    let mut err = Vec::new();
+    for (&ri, ms) in outboxes {
+        if ms.is_empty() {
+            continue;
+        }
+
+        let &mut (ref mut tx, ref mut pending) = outputs.entry(ri).or_insert_with(|| {
+            while !cc.has(&ri) {}
+            let builder = cc.builder_for(&ri).unwrap();
+            let builder = if prioritized {
+                builder.prioritized()
+            } else {
+                builder
+            };
+            (builder.build_async().unwrap(), true)
+        });
+
+        let mut tx = Pin::new(tx);
+
+        while !ms.is_empty() {
+            match tx.as_mut().poll_ready(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Pending => break,
+                Poll::Ready(Err(e)) => {
+                    err.push(e);
+                    break;
+                }
+            }
+
+            let m = ms.pop_front().expect("!is_empty");
+            match tx.as_mut().start_send(m) {
+                Ok(()) => {
+                    // we queued something, so we'll need to send!
+                    *pending = true;
+                }
+                Err(e) => {
+                    err.push(e);
+                    break;
+                }
+            }
+        }
+    }
+
+    if !err.is_empty() {
+        return Err(err.swap_remove(0).into());
+    }
+
+    // then, try to do any sends that are still pending
+    for &mut (ref mut tx, ref mut pending) in outputs.values_mut() {
+        if !*pending {
+            continue;
+        }
+
+        match Pin::new(tx).poll_flush(cx) {
+            Poll::Ready(Ok(())) => {
+                *pending = false;
+            }
+            Poll::Pending => {}
+            Poll::Ready(Err(e)) => err.push(e),
+        }
+    }
+
+    if !err.is_empty() {
+        return Err(err.swap_remove(0).into());
+    }
+
+    Ok(())
 }
 
 struct ConnState {
     // number of unacked inputs
     unacked: usize,
 
-    // unsent acks (value is the tag)
-    tag_acks: Vec<u32>,
+    // unsent acks (value is the tag, and the ack for the batch the write was processed in)
+    tag_acks: Vec<(u32, WriteAck)>,
 
     // epoch counter for each stream index (since they're re-used)
     epoch: usize,
 
     // do we have stuff to flush
     pending_flush: bool,
+
+    // the only base that writes over this connection may go to, if its client was authenticated
+    base: Option<LocalNodeIndex>,
 }
 
 struct Outboxes {
@@ -453,6 +761,12 @@ struct Outboxes {
     // messages for other domains
     domains: AHashMap<ReplicaAddr, VecDeque<Box<Packet>>>,
 
+    // messages for the priority lanes of other domains
+    priority: AHashMap<ReplicaAddr, VecDeque<Box<Packet>>>,
+
+    // how many messages may wait for any one domain before we stop taking in new work
+    high_water: usize,
+
     // connection state for each stream
     connections: slab::Slab<ConnState>,
 
@@ -461,10 +775,17 @@ struct Outboxes {
 
     // for sending messages to the controller
     ctrl_tx: tokio::sync::mpsc::UnboundedSender<CoordinationPayload>,
+
+    // faults to inject into the messages for other domains
+    faults: Option<SenderFaults>,
 }
 
 impl Outboxes {
-    fn new(ctrl_tx: tokio::sync::mpsc::UnboundedSender<CoordinationPayload>) -> Self {
+    fn new(
+        ctrl_tx: tokio::sync::mpsc::UnboundedSender<CoordinationPayload>,
+        high_water: usize,
+        faults: Option<SenderFaults>,
+    ) -> Self {
         let mut connections = slab::Slab::new();
 
         // index 0 is reserved
@@ -473,17 +794,64 @@ impl Outboxes {
             tag_acks: Vec::new(),
             epoch: 0,
             pending_flush: false,
+            base: None,
         });
 
         Outboxes {
             domains: Default::default(),
+            priority: Default::default(),
+            high_water,
             connections,
             pending: Default::default(),
             ctrl_tx,
+            faults,
             dirty: false,
         }
     }
 
+    /// Are too many messages waiting for some domain for us to take in any more work?
+    ///
+    /// Each domain's messages are sent independently of the others', so a domain that is slow to
+    /// receive them only holds up its own. But if we kept taking in work while its messages pile
+    /// up, they would do so without bound, which is what happens if it never recovers.
+    fn over_high_water(&self) -> bool {
+        self.domains.values().any(|ms| ms.len() > self.high_water)
+    }
+
+    /// Turn away a client's write without applying it.
+    fn reject(&mut self, packet: Box<Packet>) {
+        if let Packet::Input {
+            inner,
+            src: Some(src),
+            ..
+        } = *packet
+        {
+            let input = unsafe { inner.take() };
+            self.saw_input(src.token, src.epoch);
+            self.ack(
+                src,
+                WriteAck {
+                    overloaded: (0..input.data.len()).collect(),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    /// Queue up a message for another domain on the lane it should go on.
+    fn enqueue(&mut self, dest: ReplicaAddr, m: Box<Packet>) {
+        self.dirty = true;
+        // replay requests and evictions may overtake updates, but replayed records, the keys
+        // evicted along a replay path, and the end of a replay may not.
+        let lane = match *m {
+            Packet::RequestPartialReplay { .. }
+            | Packet::RequestReaderReplay { .. }
+            | Packet::Evict { .. } => &mut self.priority,
+            _ => &mut self.domains,
+        };
+        lane.entry(dest).or_default().push_back(m);
+    }
+
     fn saw_input(&mut self, token: usize, epoch: usize) {
         let mut c = &mut self.connections[token];
         if c.epoch == epoch {
@@ -491,6 +859,26 @@ impl Outboxes {
         }
     }
 
+    /// Whether `packet`, which came in over the client connection `streami`, writes to a base
+    /// that the connection may write to.
+    fn may_write(&self, streami: usize, packet: &Packet) -> bool {
+        match (self.connections[streami].base, packet) {
+            (Some(base), Packet::Input { inner, .. }) => unsafe { inner.deref() }.dst == base,
+            _ => true,
+        }
+    }
+
+    /// Forget the client connection `streami`, which is being closed whether or not we still owe
+    /// it acks.
+    fn forget(&mut self, streami: usize) {
+        let c = &mut self.connections[streami];
+        c.epoch += 1;
+        c.unacked = 0;
+        c.tag_acks.clear();
+        c.pending_flush = false;
+        self.pending.remove(&streami);
+    }
+
     fn try_retire(&mut self, streami: usize) -> bool {
         let mut c = &mut self.connections[streami];
         if c.unacked == 0 && c.tag_acks.is_empty() && !c.pending_flush {
@@ -510,13 +898,13 @@ impl Outboxes {
 }
 
 impl Executor for Outboxes {
-    fn ack(&mut self, id: SourceChannelIdentifier) {
+    fn ack(&mut self, id: SourceChannelIdentifier, ack: WriteAck) {
         self.dirty = true;
         let mut c = &mut self.connections[id.token];
         if id.epoch == c.epoch {
             // if the epoch doesn't match, the stream was closed and a new one has been established
             // note that this only matters for connections that do not wait for all acks!
-            c.tag_acks.push(id.tag);
+            c.tag_acks.push((id.tag, ack));
 
             // NOTE: it's a little sad we can't crash on underflow here.
             // it is because if a send fails, we set c.unacked = 0, and should the domain _then_
@@ -535,8 +923,27 @@ impl Executor for Outboxes {
     }
 
     fn send(&mut self, dest: ReplicaAddr, m: Box<Packet>) {
-        self.dirty = true;
-        self.domains.entry(dest).or_default().push_back(m);
+        if let Some(ref mut faults) = self.faults {
+            for m in faults.on_send(dest, m) {
+                self.enqueue(dest, m);
+            }
+        } else {
+            self.enqueue(dest, m);
+        }
+    }
+
+    fn queued(&self, dest: ReplicaAddr) -> usize {
+        self.domains.get(&dest).map(VecDeque::len).unwrap_or(0)
+    }
+
+    fn queued_records(&self) -> (usize, usize) {
+        self.domains
+            .values()
+            .chain(self.priority.values())
+            .flatten()
+            .fold((0, 0), |(records, bytes), m| {
+                (records + m.records(), bytes + m.data_size())
+            })
     }
 }
 
@@ -560,35 +967,50 @@ impl Future for Replica {
             // we have three logical input sources: receives from local domains, receives from
             // remote domains, and remote mutators. we want to achieve some kind of fairness among
             // these, but bias the data-flow towards finishing work it has accepted (i.e., domain
-            // operations) to accepting new work. however, this is complicated by two facts:
-            //
-            //  - we cannot (currently) differentiate between receives from remote domains and
-            //    receives from mutators. they are all remote tcp channels. we *could*
-            //    differentiate them using `is_base` in `try_new` to store them separately, but
-            //    it's also unclear how that would change the receive heuristic.
-            //  - domain operations are not all "completing starting work". in many cases, traffic
-            //    from domains will be replay-related, in which case favoring domains would favor
-            //    writes over reads. while we do in general want reads to be fast, we don't want
-            //    them to fully starve writes.
+            // operations) to accepting new work. however, this is complicated by the fact that
+            // domain operations are not all "completing starting work". in many cases, traffic
+            // from domains will be replay-related, in which case favoring domains would favor
+            // writes over reads. while we do in general want reads to be fast, we don't want
+            // them to fully starve writes.
             //
             // the current stategy is therefore that we alternate reading once from the local
-            // channel and once from the set of remote channels. this biases slightly in favor of
-            // local sends, without starving either. we also stop alternating once either source is
-            // depleted.
+            // channel and once from the remote channels, where we only read from mutators if no
+            // remote domain has anything for us. this biases slightly in favor of local sends,
+            // without starving either. we also stop alternating once either source is depleted.
+            //
+            // separately from all that, we always first read from the priority lanes. they carry
+            // the replay requests that cold reads are waiting on, so they shouldn't have to wait
+            // behind a deep queue of updates, and the replays they start may be what lets our
+            // outboxes drain. if they have more for us than we'll take in one go, we only read a
+            // handful of updates before we get back to them. note that replayed records
+            // themselves travel with the updates, since they must stay ordered with them.
+            let mut priority_done = false;
             let mut local_done = false;
             let mut remote_done = false;
             let mut check_local = true;
             let mut this = self.as_mut().project();
             let d = this.domain;
             let out = this.out;
+            d.set_output_queued(out.domains.values().map(VecDeque::len).sum());
 
             if let Poll::Ready(Some(_)) = this.refresh_sizes.poll_next(cx) {
                 // TODO: keep the state size up-to-date continuously?
+                if let Some(budget) = *this.memory_budget {
+                    d.enforce_memory_budget(budget, out);
+                }
                 d.update_state_sizes();
+                if let Some(metrics) = this.metrics.as_ref() {
+                    let mut m = d.metrics();
+                    m.output_queue = out.domains.values().map(VecDeque::len).sum();
+                    *metrics.lock().unwrap() = m;
+                }
             }
 
+            // whether we found anything to process, which a draining domain needs to know
+            let mut processed = false;
             macro_rules! process {
                 ($retry:expr, $outbox:expr, $p:expr, $pp:expr) => {{
+                    processed = true;
                     $retry = Some($p);
                     let retry = &mut $retry;
                     if let ProcessResult::StopPolling = {
@@ -616,6 +1038,82 @@ impl Future for Replica {
             }
 
             for _ in 0..FORCE_INPUT_YIELD_EVERY {
+                let mut progress = false;
+                match this.priority_locals.poll_recv(cx) {
+                    Poll::Ready(Some(packet)) => {
+                        progress = true;
+                        if let Packet::Ping(ping) = *packet {
+                            // the worker only wants to know that we got to it
+                            this.liveness.pong(ping);
+                        } else {
+                            process!(*this.retry, out, packet, |p| d
+                                .on_event(out, PollEvent::Process(p),));
+                        }
+                    }
+                    Poll::Ready(None) => {
+                        // local input stream finished
+                        // TODO: should we finish up remaining work?
+                        warn!(this.log, "local input stream ended");
+                        return Poll::Ready(Ok(()));
+                    }
+                    Poll::Pending => {}
+                }
+
+                match this.priority.as_mut().poll_next(cx) {
+                    Poll::Ready(Some((StreamYield::Item(Ok(packet)), _))) => {
+                        progress = true;
+                        process!(*this.retry, out, packet, |p| d
+                            .on_event(out, PollEvent::Process(p),));
+                    }
+                    Poll::Ready(Some((StreamYield::Finished(f), _))) => {
+                        progress = true;
+                        f.remove(this.priority.as_mut());
+                    }
+                    Poll::Ready(Some((StreamYield::Item(Err(e)), streami))) => {
+                        progress = true;
+                        error!(this.log, "priority input stream failed: {:?}", e);
+                        this.priority.as_mut().remove(streami);
+                    }
+                    Poll::Ready(None) | Poll::Pending => {}
+                }
+
+                if !progress {
+                    priority_done = true;
+                    break;
+                }
+            }
+
+            // if some downstream domain isn't keeping up, stop taking in new work until it does.
+            // the sends that fill our outboxes wake us up again once they make progress. this
+            // in turn fills the outboxes of the domains that feed us, until the bases stop
+            // taking in writes.
+            if out.over_high_water() {
+                if !*this.paused {
+                    warn!(
+                        this.log,
+                        "downstream domain is falling behind; pausing intake"
+                    );
+                    *this.paused = true;
+                }
+            } else if *this.paused {
+                info!(this.log, "downstream domains caught up; resuming intake");
+                *this.paused = false;
+            }
+            let paused = *this.paused;
+            let reject = paused && *this.overload_policy == OverloadPolicy::Reject;
+            let draining = *this.draining;
+            if paused {
+                local_done = true;
+                // we still read writes from clients if we're to turn them away
+                remote_done = !reject;
+            }
+
+            let budget = if priority_done {
+                FORCE_INPUT_YIELD_EVERY
+            } else {
+                MIN_UPDATES_PER_YIELD
+            };
+            for _ in 0..budget {
                 if !local_done && (check_local || remote_done) {
                     match this.locals.poll_recv(cx) {
                         Poll::Ready(Some(packet)) => {
@@ -635,38 +1133,73 @@ impl Future for Replica {
                 }
 
                 if !remote_done && (!check_local || local_done) {
-                    match this.inputs.as_mut().poll_next(cx) {
-                        Poll::Ready(Some((StreamYield::Item(Ok(packet)), _))) => {
-                            process!(*this.retry, out, packet, |p| d
-                                .on_event(out, PollEvent::Process(p),));
-                        }
-                        Poll::Ready(Some((StreamYield::Finished(f), streami))) => {
-                            if out.try_retire(streami) {
-                                f.remove(this.inputs.as_mut());
-                            } else {
-                                // We still have responses to send, even though there are no
-                                // more requests. Keep the stream around for now. We'll clean
-                                // it up when the sends have finished.
-                                f.keep();
+                    let mut upstream_done = paused;
+                    if !upstream_done {
+                        match this.upstream.as_mut().poll_next(cx) {
+                            Poll::Ready(Some((StreamYield::Item(Ok(packet)), _))) => {
+                                process!(*this.retry, out, packet, |p| d
+                                    .on_event(out, PollEvent::Process(p),));
+                            }
+                            Poll::Ready(Some((StreamYield::Finished(f), _))) => {
+                                f.remove(this.upstream.as_mut());
+                            }
+                            Poll::Ready(Some((StreamYield::Item(Err(e)), streami))) => {
+                                error!(this.log, "input stream failed: {:?}", e);
+                                this.upstream.as_mut().remove(streami);
+                            }
+                            Poll::Ready(None) | Poll::Pending => {
+                                upstream_done = true;
                             }
                         }
-                        Poll::Ready(None) => {
-                            // we probably haven't booted yet
-                            remote_done = true;
-                        }
-                        Poll::Pending => {
-                            remote_done = true;
-                        }
-                        Poll::Ready(Some((StreamYield::Item(Err(e)), streami))) => {
-                            error!(this.log, "input stream failed: {:?}", e);
-                            // we want to _forcibly_ retire streami
-                            this.inputs.as_mut().remove(streami);
-                            let c = &mut out.connections[streami];
-                            c.epoch += 1;
-                            c.unacked = 0;
-                            c.tag_acks.clear();
-                            c.pending_flush = false;
-                            out.pending.remove(&streami);
+                    }
+
+                    if upstream_done && draining {
+                        // client writes that have not been read yet are left unacknowledged,
+                        // so their clients know to send them again
+                        remote_done = true;
+                    } else if upstream_done {
+                        match this.inputs.as_mut().poll_next(cx) {
+                            Poll::Ready(Some((StreamYield::Item(Ok(packet)), streami)))
+                                if !out.may_write(streami, &packet) =>
+                            {
+                                warn!(
+                                    this.log,
+                                    "dropped client connection that wrote to another table"
+                                );
+                                this.inputs.as_mut().remove(streami);
+                                out.forget(streami);
+                            }
+                            Poll::Ready(Some((StreamYield::Item(Ok(packet)), _))) => {
+                                if reject {
+                                    out.reject(packet);
+                                } else {
+                                    process!(*this.retry, out, packet, |p| d
+                                        .on_event(out, PollEvent::Process(p),));
+                                }
+                            }
+                            Poll::Ready(Some((StreamYield::Finished(f), streami))) => {
+                                if out.try_retire(streami) {
+                                    f.remove(this.inputs.as_mut());
+                                } else {
+                                    // We still have responses to send, even though there are no
+                                    // more requests. Keep the stream around for now. We'll clean
+                                    // it up when the sends have finished.
+                                    f.keep();
+                                }
+                            }
+                            Poll::Ready(None) => {
+                                // we probably haven't booted yet
+                                remote_done = true;
+                            }
+                            Poll::Pending => {
+                                remote_done = true;
+                            }
+                            Poll::Ready(Some((StreamYield::Item(Err(e)), streami))) => {
+                                error!(this.log, "input stream failed: {:?}", e);
+                                // we want to _forcibly_ retire streami
+                                this.inputs.as_mut().remove(streami);
+                                out.forget(streami);
+                            }
                         }
                     }
                 }
@@ -681,6 +1214,7 @@ impl Future for Replica {
 
             // send to downstream
             // TODO: send fail == exiting?
+            self.as_mut().try_faults(cx);
             self.as_mut()
                 .try_flush(cx)
                 .context("downstream flush (after)")?;
@@ -688,9 +1222,18 @@ impl Future for Replica {
             // send acks
             self.as_mut().try_acks(cx)?;
 
-            if !local_done || !remote_done {
-                // we're yielding voluntarily to not block the executor and must ensure we wake
-                // up again
+            if self.draining {
+                let idle = !processed && priority_done && local_done && remote_done;
+                self.as_mut().try_drain(cx, idle)?;
+            }
+
+            if !priority_done
+                || !local_done
+                || !remote_done
+                || (self.paused && !self.out.over_high_water())
+            {
+                // we're yielding voluntarily to not block the executor (or have drained our
+                // outboxes enough to take in work again) and must ensure we wake up again
                 cx.waker().wake_by_ref();
             }
 
@@ -31,6 +31,9 @@ enum Type {
 
 impl Type {
     pub fn make_datatype(&self, value: &str) -> DataType {
+        if value == "NULL" {
+            return DataType::None;
+        }
         match *self {
             Type::Int => i64::from_str(value).unwrap().into(),
             Type::Text => value.into(),
@@ -173,7 +176,17 @@ fn generate_target_results(schemas: &BTreeMap<String, Schema>) {
             );
             let insert = conn.prep(query).unwrap();
             for row in table.data.as_ref().unwrap().iter() {
-                if let Err(msg) = conn.exec_drop(&insert, row.clone()) {
+                let values: Vec<mysql::Value> = row
+                    .iter()
+                    .map(|v| {
+                        if v == "NULL" {
+                            mysql::Value::NULL
+                        } else {
+                            v.into()
+                        }
+                    })
+                    .collect();
+                if let Err(msg) = conn.exec_drop(&insert, values) {
                     println!(
                         "MySQL insert query failed for table: {}, values: {:?}",
                         table_name, row
@@ -305,16 +318,23 @@ async fn check_query(
                 row.into_iter()
                     .map(|v| match v {
                         DataType::None => "NULL".to_owned(),
+                        DataType::Bool(b) => (b as i32).to_string(),
                         DataType::Int(i) => i.to_string(),
                         DataType::UnsignedInt(i) => i.to_string(),
                         DataType::BigInt(i) => i.to_string(),
                         DataType::UnsignedBigInt(i) => i.to_string(),
+                        DataType::HugeInt(ref i) => i.to_string(),
                         DataType::Real(i, f) => ((i as f64) + (f as f64) * 1.0e-9).to_string(),
-                        DataType::Text(_) | DataType::TinyText(_) => {
+                        DataType::Decimal(..) => v.to_string(),
+                        DataType::Text(_) | DataType::TinyText(_) | DataType::CiText(_) => {
                             let s: &str = (&v).into();
                             s.to_string()
                         }
                         DataType::Timestamp(_) => unimplemented!(),
+                        DataType::Date(_)
+                        | DataType::Uuid(_)
+                        | DataType::Json(_)
+                        | DataType::Bytes(_) => v.to_string(),
                     })
                     .collect()
             })